@@ -0,0 +1,167 @@
+#ifndef EMU_CAPI_H
+#define EMU_CAPI_H
+
+/* Generated by cbindgen from crates/emu-capi/src/lib.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Width of the picture in pixels
+ */
+#define NES_FRAME_WIDTH 256
+
+/**
+ * Height of the picture in pixels
+ */
+#define NES_FRAME_HEIGHT 240
+
+/**
+ * Rate of the samples [`nes_audio_read`] returns, in Hz
+ */
+#define NES_AUDIO_SAMPLE_RATE 44100
+
+/**
+ * Result of a call
+ */
+typedef enum NesStatus {
+  NES_STATUS_OK = 0,
+  /**
+   * A required pointer was NULL
+   */
+  NES_STATUS_NULL_POINTER = 1,
+  /**
+   * The ROM could not be loaded
+   */
+  NES_STATUS_INVALID_ROM = 2,
+  /**
+   * The emulated program crashed the CPU
+   */
+  NES_STATUS_EMULATION = 3,
+  /**
+   * An argument was out of range
+   */
+  NES_STATUS_INVALID_ARGUMENT = 4,
+  /**
+   * The core panicked; the handle can only be destroyed
+   */
+  NES_STATUS_PANIC = 5,
+} NesStatus;
+
+/**
+ * Controller buttons for [`nes_set_button`]
+ */
+typedef enum NesButton {
+  NES_BUTTON_A = 0,
+  NES_BUTTON_B = 1,
+  NES_BUTTON_SELECT = 2,
+  NES_BUTTON_START = 3,
+  NES_BUTTON_UP = 4,
+  NES_BUTTON_DOWN = 5,
+  NES_BUTTON_LEFT = 6,
+  NES_BUTTON_RIGHT = 7,
+} NesButton;
+
+/**
+ * An emulated console, opaque to the host
+ */
+typedef struct NesHandle NesHandle;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Load a ROM image (iNES format) into a new console
+ *
+ * On success `*out` receives the handle, which must be released with
+ * [`nes_destroy`]. On failure `*out` is set to NULL. The ROM bytes are
+ * copied, so the buffer may be freed afterwards.
+ *
+ * # Safety
+ *
+ * `rom` must point to `len` readable bytes and `out` to a writable handle pointer.
+ */
+enum NesStatus nes_create(const uint8_t *rom, size_t len, struct NesHandle **out);
+
+/**
+ * Release a handle; NULL is ignored
+ *
+ * # Safety
+ *
+ * `handle` must be NULL or a live handle from [`nes_create`], and is
+ * invalid afterwards.
+ */
+void nes_destroy(struct NesHandle *handle);
+
+/**
+ * Run one frame, collecting its audio
+ *
+ * # Safety
+ *
+ * `handle` must be NULL or a live handle from [`nes_create`].
+ */
+enum NesStatus nes_run_frame(struct NesHandle *handle);
+
+/**
+ * Get the current picture as 24-bit RGB, row by row
+ *
+ * The pixels stay valid until the next call on this handle.
+ *
+ * # Safety
+ *
+ * `handle` must be NULL or a live handle from [`nes_create`]; `pixels`,
+ * `width` and `height` must be writable.
+ */
+enum NesStatus nes_framebuffer(struct NesHandle *handle,
+                               const uint8_t **pixels,
+                               uint32_t *width,
+                               uint32_t *height);
+
+/**
+ * Press or release `button` (a [`NesButton`]) on controller `player` (0 or 1)
+ *
+ * # Safety
+ *
+ * `handle` must be NULL or a live handle from [`nes_create`].
+ */
+enum NesStatus nes_set_button(struct NesHandle *handle,
+                              uint32_t player,
+                              uint32_t button,
+                              bool pressed);
+
+/**
+ * Move up to `max` samples (mono, [-1, 1], [`NES_AUDIO_SAMPLE_RATE`] Hz)
+ * into `out`, oldest first, and return how many were written
+ *
+ * Up to one second is buffered; older samples are dropped. Returns 0 on
+ * failure.
+ *
+ * # Safety
+ *
+ * `handle` must be NULL or a live handle from [`nes_create`], and `out`
+ * must have room for `max` floats.
+ */
+size_t nes_audio_read(struct NesHandle *handle, float *out, size_t max);
+
+/**
+ * Describe the last failed call on this thread, or NULL if none has failed
+ *
+ * Errors are per thread rather than per handle, so this also explains a
+ * failed [`nes_create`]; `handle` may be NULL. The string stays valid until
+ * the next failed call on this thread.
+ *
+ * # Safety
+ *
+ * Always safe to call; the handle is not dereferenced.
+ */
+const char *nes_last_error(const struct NesHandle *handle);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* EMU_CAPI_H */
@@ -0,0 +1,371 @@
+//! C API for embedding the NES core in non-Rust hosts
+//!
+//! Builds as a cdylib and staticlib; the header is `include/emu_capi.h`,
+//! regenerated by cbindgen on every build. A host creates a [`NesHandle`]
+//! from ROM bytes, calls [`nes_run_frame`] once per frame, then reads the
+//! picture with [`nes_framebuffer`] and the sound with [`nes_audio_read`].
+//!
+//! Every function catches panics at the boundary and reports them as
+//! [`NesStatus::Panic`]; a handle that panicked refuses further calls.
+//! When a call fails, [`nes_last_error`] describes why.
+//!
+//! Handles are not thread-safe: use each one from one thread at a time.
+
+use emu_core::Button;
+use emu_nes::{framebuffer_to_rgb, NesSystem};
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// Width of the picture in pixels
+pub const NES_FRAME_WIDTH: u32 = 256;
+
+/// Height of the picture in pixels
+pub const NES_FRAME_HEIGHT: u32 = 240;
+
+/// Rate of the samples [`nes_audio_read`] returns, in Hz
+pub const NES_AUDIO_SAMPLE_RATE: u32 = 44_100;
+
+/// Samples kept for the host before the oldest are dropped (one second)
+const AUDIO_BUFFER_LIMIT: usize = NES_AUDIO_SAMPLE_RATE as usize;
+
+/// Result of a call
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NesStatus {
+    Ok = 0,
+    /// A required pointer was NULL
+    NullPointer = 1,
+    /// The ROM could not be loaded
+    InvalidRom = 2,
+    /// The emulated program crashed the CPU
+    Emulation = 3,
+    /// An argument was out of range
+    InvalidArgument = 4,
+    /// The core panicked; the handle can only be destroyed
+    Panic = 5,
+}
+
+/// Controller buttons for [`nes_set_button`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NesButton {
+    A = 0,
+    B = 1,
+    Select = 2,
+    Start = 3,
+    Up = 4,
+    Down = 5,
+    Left = 6,
+    Right = 7,
+}
+
+/// An emulated console, opaque to the host
+pub struct NesHandle {
+    system: NesSystem,
+    /// RGB copy of the last picture handed out by [`nes_framebuffer`]
+    rgb: Vec<u8>,
+    /// Samples not yet read by the host
+    audio: Vec<f32>,
+    /// A call panicked, so the state may be inconsistent
+    poisoned: bool,
+}
+
+/// Why a call failed
+struct Failure {
+    status: NesStatus,
+    message: String,
+}
+
+impl Failure {
+    fn new(status: NesStatus, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+}
+
+thread_local! {
+    /// Message for the last failed call on this thread
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: &str) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Run `body`, turning failures and panics into a status and a last error
+fn guard(body: impl FnOnce() -> Result<(), Failure>) -> NesStatus {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => NesStatus::Ok,
+        Ok(Err(failure)) => {
+            set_last_error(&failure.message);
+            failure.status
+        }
+        Err(payload) => {
+            set_last_error(&format!("panic: {}", panic_message(payload.as_ref())));
+            NesStatus::Panic
+        }
+    }
+}
+
+/// [`guard`] for calls on a handle, poisoning it if the call panics
+///
+/// # Safety
+///
+/// `handle` must be NULL or a live handle from [`nes_create`].
+unsafe fn with_handle(handle: *mut NesHandle, body: impl FnOnce(&mut NesHandle) -> Result<(), Failure>) -> NesStatus {
+    if handle.is_null() {
+        return guard(|| Err(Failure::new(NesStatus::NullPointer, "handle is NULL")));
+    }
+    if (*handle).poisoned {
+        return guard(|| Err(Failure::new(NesStatus::Panic, "handle is unusable after an earlier panic")));
+    }
+    let status = guard(|| body(&mut *handle));
+    if status == NesStatus::Panic {
+        (*handle).poisoned = true;
+    }
+    status
+}
+
+/// Load a ROM image (iNES format) into a new console
+///
+/// On success `*out` receives the handle, which must be released with
+/// [`nes_destroy`]. On failure `*out` is set to NULL. The ROM bytes are
+/// copied, so the buffer may be freed afterwards.
+///
+/// # Safety
+///
+/// `rom` must point to `len` readable bytes and `out` to a writable handle pointer.
+#[no_mangle]
+pub unsafe extern "C" fn nes_create(rom: *const u8, len: usize, out: *mut *mut NesHandle) -> NesStatus {
+    guard(|| {
+        if out.is_null() {
+            return Err(Failure::new(NesStatus::NullPointer, "out is NULL"));
+        }
+        *out = ptr::null_mut();
+        if rom.is_null() {
+            return Err(Failure::new(NesStatus::NullPointer, "rom is NULL"));
+        }
+        let rom = std::slice::from_raw_parts(rom, len);
+        let system = NesSystem::from_bytes(rom).map_err(|err| Failure::new(NesStatus::InvalidRom, err.to_string()))?;
+        *out = Box::into_raw(Box::new(NesHandle {
+            system,
+            rgb: Vec::new(),
+            audio: Vec::new(),
+            poisoned: false,
+        }));
+        Ok(())
+    })
+}
+
+/// Release a handle; NULL is ignored
+///
+/// # Safety
+///
+/// `handle` must be NULL or a live handle from [`nes_create`], and is
+/// invalid afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn nes_destroy(handle: *mut NesHandle) {
+    if !handle.is_null() {
+        // Dropping only frees memory; a panic here can't be reported anyway
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(handle))));
+    }
+}
+
+/// Run one frame, collecting its audio
+///
+/// # Safety
+///
+/// `handle` must be NULL or a live handle from [`nes_create`].
+#[no_mangle]
+pub unsafe extern "C" fn nes_run_frame(handle: *mut NesHandle) -> NesStatus {
+    with_handle(handle, |handle| {
+        let NesHandle { system, audio, .. } = handle;
+        let result = system.run_frame();
+        system.take_samples(NES_AUDIO_SAMPLE_RATE, audio);
+        if audio.len() > AUDIO_BUFFER_LIMIT {
+            audio.drain(..audio.len() - AUDIO_BUFFER_LIMIT);
+        }
+        result.map_err(|err| Failure::new(NesStatus::Emulation, err.to_string()))
+    })
+}
+
+/// Get the current picture as 24-bit RGB, row by row
+///
+/// The pixels stay valid until the next call on this handle.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a live handle from [`nes_create`]; `pixels`,
+/// `width` and `height` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn nes_framebuffer(
+    handle: *mut NesHandle,
+    pixels: *mut *const u8,
+    width: *mut u32,
+    height: *mut u32,
+) -> NesStatus {
+    with_handle(handle, |handle| {
+        if pixels.is_null() || width.is_null() || height.is_null() {
+            return Err(Failure::new(NesStatus::NullPointer, "pixels, width and height must not be NULL"));
+        }
+        handle.rgb = framebuffer_to_rgb(handle.system.framebuffer());
+        *pixels = handle.rgb.as_ptr();
+        *width = NES_FRAME_WIDTH;
+        *height = NES_FRAME_HEIGHT;
+        Ok(())
+    })
+}
+
+/// Press or release `button` (a [`NesButton`]) on controller `player` (0 or 1)
+///
+/// # Safety
+///
+/// `handle` must be NULL or a live handle from [`nes_create`].
+#[no_mangle]
+pub unsafe extern "C" fn nes_set_button(handle: *mut NesHandle, player: u32, button: u32, pressed: bool) -> NesStatus {
+    with_handle(handle, |handle| {
+        if button > NesButton::Right as u32 {
+            return Err(Failure::new(NesStatus::InvalidArgument, format!("no button {}", button)));
+        }
+        let button = Button::from_bits_truncate(1 << button);
+        let controller = match player {
+            0 => handle.system.controller1(),
+            1 => handle.system.controller2(),
+            _ => return Err(Failure::new(NesStatus::InvalidArgument, format!("no player {}", player))),
+        };
+        controller.state().set(button, pressed);
+        Ok(())
+    })
+}
+
+/// Move up to `max` samples (mono, [-1, 1], [`NES_AUDIO_SAMPLE_RATE`] Hz)
+/// into `out`, oldest first, and return how many were written
+///
+/// Up to one second is buffered; older samples are dropped. Returns 0 on
+/// failure.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a live handle from [`nes_create`], and `out`
+/// must have room for `max` floats.
+#[no_mangle]
+pub unsafe extern "C" fn nes_audio_read(handle: *mut NesHandle, out: *mut f32, max: usize) -> usize {
+    let mut written = 0;
+    with_handle(handle, |handle| {
+        if out.is_null() && max > 0 {
+            return Err(Failure::new(NesStatus::NullPointer, "out is NULL"));
+        }
+        written = max.min(handle.audio.len());
+        for (index, sample) in handle.audio.drain(..written).enumerate() {
+            *out.add(index) = sample;
+        }
+        Ok(())
+    });
+    written
+}
+
+/// Describe the last failed call on this thread, or NULL if none has failed
+///
+/// Errors are per thread rather than per handle, so this also explains a
+/// failed [`nes_create`]; `handle` may be NULL. The string stays valid until
+/// the next failed call on this thread.
+///
+/// # Safety
+///
+/// Always safe to call; the handle is not dereferenced.
+#[no_mangle]
+pub unsafe extern "C" fn nes_last_error(handle: *const NesHandle) -> *const c_char {
+    let _ = handle;
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emu_nes::apu_player::CPU_CLOCK_HZ;
+
+    fn last_error() -> String {
+        let message = unsafe { nes_last_error(ptr::null()) };
+        assert!(!message.is_null());
+        unsafe { std::ffi::CStr::from_ptr(message) }.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_errors_are_reported() {
+        unsafe {
+            let mut handle = ptr::NonNull::dangling().as_ptr();
+            assert_eq!(nes_create([0u8; 4].as_ptr(), 4, &mut handle), NesStatus::InvalidRom);
+            assert!(handle.is_null());
+            assert!(!last_error().is_empty());
+
+            assert_eq!(nes_create(ptr::null(), 0, &mut handle), NesStatus::NullPointer);
+            assert_eq!(last_error(), "rom is NULL");
+            assert_eq!(nes_run_frame(ptr::null_mut()), NesStatus::NullPointer);
+            assert_eq!(nes_audio_read(ptr::null_mut(), ptr::null_mut(), 0), 0);
+            nes_destroy(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_panics_poison_the_handle() {
+        let status = guard(|| panic!("boom"));
+        assert_eq!(status, NesStatus::Panic);
+        assert_eq!(last_error(), "panic: boom");
+
+        unsafe {
+            let mut handle = ptr::null_mut();
+            let rom = emu_nes_rom();
+            assert_eq!(nes_create(rom.as_ptr(), rom.len(), &mut handle), NesStatus::Ok);
+            assert_eq!(with_handle(handle, |_| panic!("inside")), NesStatus::Panic);
+            assert_eq!(nes_run_frame(handle), NesStatus::Panic);
+            assert!(last_error().contains("earlier panic"));
+            nes_destroy(handle);
+        }
+    }
+
+    #[test]
+    fn test_audio_keeps_pace_with_frames() {
+        unsafe {
+            let mut handle = ptr::null_mut();
+            let rom = emu_nes_rom();
+            assert_eq!(nes_create(rom.as_ptr(), rom.len(), &mut handle), NesStatus::Ok);
+            for _ in 0..60 {
+                assert_eq!(nes_run_frame(handle), NesStatus::Ok);
+            }
+            // 60 frames of 29780 cycles
+            let expected = (60.0 * 29780.0 * NES_AUDIO_SAMPLE_RATE as f64 / CPU_CLOCK_HZ as f64).ceil() as usize;
+            let mut samples = vec![0.0; 50_000];
+            let read = nes_audio_read(handle, samples.as_mut_ptr(), samples.len());
+            assert!(read.abs_diff(expected) <= 1, "{} samples, expected {}", read, expected);
+            assert_eq!(nes_audio_read(handle, samples.as_mut_ptr(), samples.len()), 0);
+
+            assert_eq!(nes_set_button(handle, 1, NesButton::Start as u32, true), NesStatus::Ok);
+            assert_eq!(nes_set_button(handle, 2, 0, true), NesStatus::InvalidArgument);
+            assert_eq!(nes_set_button(handle, 0, 8, true), NesStatus::InvalidArgument);
+            nes_destroy(handle);
+        }
+    }
+
+    /// NROM image that loops forever
+    fn emu_nes_rom() -> Vec<u8> {
+        let mut rom = b"NES\x1a\x01\x01\x00\x00".to_vec();
+        rom.resize(16, 0);
+        let mut prg = vec![0xEA; 0x4000];
+        prg[..3].copy_from_slice(&[0x4C, 0x00, 0xC0]); // JMP $C000
+        prg[0x3FFA..].copy_from_slice(&[0x00, 0xC0, 0x00, 0xC0, 0x00, 0xC0]);
+        rom.extend_from_slice(&prg);
+        rom.extend_from_slice(&[0; 0x2000]);
+        rom
+    }
+}
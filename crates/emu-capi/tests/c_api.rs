@@ -0,0 +1,56 @@
+//! Compiles `smoke.c` against the generated header and the cdylib and runs
+//! it on a generated test ROM, proving the ABI end to end
+
+#[path = "../../emu-nes/examples/generate_perfect_visual.rs"]
+#[allow(dead_code)]
+mod perfect_visual;
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+#[cfg(unix)]
+fn test_c_program_renders_a_frame() {
+    // Integration tests run from target/<profile>/deps, next to the cdylib
+    let exe = env::current_exe().unwrap();
+    let lib_dir = exe.parent().unwrap();
+    assert!(lib_dir.join(format!("{}emu_capi{}", env::consts::DLL_PREFIX, env::consts::DLL_SUFFIX)).exists());
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    let rom = lib_dir.join("capi_smoke.nes");
+    std::fs::write(&rom, perfect_visual::build_rom()).unwrap();
+    let program = lib_dir.join("capi_smoke");
+
+    let compiler = cc::Build::new()
+        .target(env!("TARGET"))
+        .host(env!("TARGET"))
+        .opt_level(0)
+        .cargo_metadata(false)
+        .cargo_warnings(false)
+        .get_compiler();
+    let output = compiler
+        .to_command()
+        .arg(crate_dir.join("tests/smoke.c"))
+        .arg("-I")
+        .arg(crate_dir.join("include"))
+        .arg("-o")
+        .arg(&program)
+        .arg("-L")
+        .arg(lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .arg("-lemu_capi")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "compiling smoke.c failed:\n{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = Command::new(&program).arg(&rom).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "smoke.c failed:\n{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.starts_with("ok"), "{}", stdout);
+}
@@ -0,0 +1,73 @@
+/* Drives the C API end to end: load the ROM named on the command line,
+ * run it, and check the picture and sound came through. */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "emu_capi.h"
+
+#define CHECK(cond, what)                                                   \
+    do {                                                                    \
+        if (!(cond)) {                                                      \
+            const char *error = nes_last_error(NULL);                       \
+            fprintf(stderr, "FAILED: %s (%s)\n", what, error ? error : "no error"); \
+            return 1;                                                       \
+        }                                                                   \
+    } while (0)
+
+static uint8_t *read_file(const char *path, size_t *len) {
+    FILE *file = fopen(path, "rb");
+    if (!file) {
+        return NULL;
+    }
+    fseek(file, 0, SEEK_END);
+    *len = (size_t)ftell(file);
+    fseek(file, 0, SEEK_SET);
+    uint8_t *data = malloc(*len);
+    if (data && fread(data, 1, *len, file) != *len) {
+        free(data);
+        data = NULL;
+    }
+    fclose(file);
+    return data;
+}
+
+int main(int argc, char **argv) {
+    CHECK(argc == 2, "usage: smoke ROM");
+    size_t len = 0;
+    uint8_t *rom = read_file(argv[1], &len);
+    CHECK(rom != NULL, "read ROM");
+
+    NesHandle *nes = NULL;
+    uint8_t junk[4] = {0};
+    CHECK(nes_create(junk, sizeof junk, &nes) == NES_STATUS_INVALID_ROM, "reject a bad ROM");
+    CHECK(nes == NULL && nes_last_error(NULL) != NULL, "explain the bad ROM");
+
+    CHECK(nes_create(rom, len, &nes) == NES_STATUS_OK, "create");
+    free(rom);
+
+    CHECK(nes_set_button(nes, 0, NES_BUTTON_START, true) == NES_STATUS_OK, "press Start");
+    CHECK(nes_set_button(nes, 2, NES_BUTTON_A, true) == NES_STATUS_INVALID_ARGUMENT, "reject player 3");
+    for (int frame = 0; frame < 10; frame++) {
+        CHECK(nes_run_frame(nes) == NES_STATUS_OK, "run frame");
+    }
+
+    const uint8_t *pixels = NULL;
+    uint32_t width = 0, height = 0;
+    CHECK(nes_framebuffer(nes, &pixels, &width, &height) == NES_STATUS_OK, "framebuffer");
+    CHECK(width == NES_FRAME_WIDTH && height == NES_FRAME_HEIGHT, "frame size");
+    size_t lit = 0;
+    for (size_t i = 0; i < (size_t)width * height * 3; i++) {
+        lit += pixels[i] != 0;
+    }
+    CHECK(lit > 0, "non-black frame");
+
+    float audio[1024];
+    CHECK(nes_audio_read(nes, audio, 1024) == 1024, "audio samples");
+
+    nes_destroy(nes);
+    nes_destroy(NULL);
+    printf("ok: %zu lit channels\n", lit);
+    return 0;
+}
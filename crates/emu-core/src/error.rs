@@ -6,10 +6,14 @@ use thiserror::Error;
 pub type Result<T> = std::result::Result<T, EmulatorError>;
 
 /// Errors that can occur during emulation
+///
+/// New kinds of failure get new variants, so matches outside this crate
+/// need a catch-all arm.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum EmulatorError {
     #[error("Invalid memory address: 0x{0:04X}")]
-    InvalidAddress(u16),
+    InvalidAddress(u32),
 
     #[error("Invalid opcode: 0x{0:02X}")]
     InvalidOpcode(u8),
@@ -17,6 +21,27 @@ pub enum EmulatorError {
     #[error("ROM loading error: {0}")]
     RomLoadError(String),
 
+    #[error("ROM too short: expected {expected} bytes, found {found}")]
+    RomTooShort { expected: usize, found: usize },
+
+    #[error("Wrong size for {region}: expected {expected} bytes, found {found}")]
+    RegionSizeMismatch { region: String, expected: usize, found: usize },
+
+    #[error("Invalid savestate: {0}")]
+    InvalidSaveState(String),
+
+    #[error("Invalid battery save: {0}")]
+    InvalidBatterySave(String),
+
+    #[error("Invalid patch: {0}")]
+    InvalidPatch(String),
+
+    #[error("Input script error at line {line}, column {column}: {message}")]
+    InputScript { line: usize, column: usize, message: String },
+
+    #[error("Emulator service stopped")]
+    ServiceStopped,
+
     #[error("Unsupported mapper: {0}")]
     UnsupportedMapper(u8),
 
@@ -0,0 +1,112 @@
+//! Input devices for emulators
+//!
+//! Consoles expose very different controllers (an 8-button joypad, a
+//! 16-key hex keypad, a keyboard matrix...). Cores only need a uniform way
+//! to query and update digital buttons, which is what [`InputDevice`]
+//! provides. [`ControllerState`] is the standard 8-button pad
+//! (D-pad, A, B, Select, Start) shared by the NES and the Game Boy.
+
+use bitflags::bitflags;
+
+/// A device made of digital buttons addressed by index
+pub trait InputDevice {
+    /// Number of buttons on the device
+    fn button_count(&self) -> usize;
+
+    /// Human-readable name of a button (for UIs and input recording)
+    fn button_name(&self, index: usize) -> &'static str;
+
+    /// Check whether the button at `index` is held
+    fn is_button_pressed(&self, index: usize) -> bool;
+
+    /// Press or release the button at `index`
+    fn set_button_pressed(&mut self, index: usize, pressed: bool);
+
+    /// Release all buttons
+    fn clear(&mut self) {
+        for index in 0..self.button_count() {
+            self.set_button_pressed(index, false);
+        }
+    }
+
+    /// All button states packed into a bitmask (bit n = button n)
+    fn bits(&self) -> u32 {
+        (0..self.button_count().min(32))
+            .filter(|&index| self.is_button_pressed(index))
+            .fold(0, |bits, index| bits | (1 << index))
+    }
+}
+
+bitflags! {
+    /// Standard 8-button pad flags
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Button: u8 {
+        const A      = 0b0000_0001;
+        const B      = 0b0000_0010;
+        const SELECT = 0b0000_0100;
+        const START  = 0b0000_1000;
+        const UP     = 0b0001_0000;
+        const DOWN   = 0b0010_0000;
+        const LEFT   = 0b0100_0000;
+        const RIGHT  = 0b1000_0000;
+    }
+}
+
+/// Button names in bit order
+const BUTTON_NAMES: [&str; 8] = ["A", "B", "Select", "Start", "Up", "Down", "Left", "Right"];
+
+/// Controller state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ControllerState {
+    pub buttons: Button,
+}
+
+impl ControllerState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.buttons.contains(button)
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.buttons.insert(button);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.buttons.remove(button);
+    }
+
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.press(button);
+        } else {
+            self.release(button);
+        }
+    }
+}
+
+impl InputDevice for ControllerState {
+    fn button_count(&self) -> usize {
+        BUTTON_NAMES.len()
+    }
+
+    fn button_name(&self, index: usize) -> &'static str {
+        BUTTON_NAMES.get(index).copied().unwrap_or("?")
+    }
+
+    fn is_button_pressed(&self, index: usize) -> bool {
+        index < 8 && self.is_pressed(Button::from_bits_truncate(1 << index))
+    }
+
+    fn set_button_pressed(&mut self, index: usize, pressed: bool) {
+        if index < 8 {
+            self.set(Button::from_bits_truncate(1 << index), pressed);
+        }
+    }
+
+    fn bits(&self) -> u32 {
+        self.buttons.bits() as u32
+    }
+}
@@ -1,15 +1,17 @@
 //! Core emulator traits and types for LumiEmu
 //!
 //! This crate provides the fundamental abstractions for building emulators,
-//! including memory bus interfaces, CPU traits, and instrumentation hooks
-//! for AI-driven memory analysis.
+//! including memory bus interfaces, CPU traits, input devices, and
+//! instrumentation hooks for AI-driven memory analysis. Nothing in here is
+//! tied to a particular console: each core (e.g. `emu-nes`) implements
+//! these traits for its own hardware.
 
 pub mod error;
+pub mod input;
 pub mod memory_bus;
 pub mod traits;
-pub mod types;
 
 pub use error::{EmulatorError, Result};
+pub use input::{Button, ControllerState, InputDevice};
 pub use memory_bus::{MemoryBus, MemoryObserver, MemoryAccess, AccessType, EmulatorContext};
 pub use traits::{Cpu, Emulator};
-pub use types::{Button, Controller, ControllerState};
@@ -1,4 +1,7 @@
 //! Memory bus with instrumentation hooks for AI observation
+//!
+//! Addresses are carried as `u32` so that cores with address spaces wider
+//! (or narrower) than 16 bits can share the same bus and observer types.
 
 /// Type of memory access
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -15,16 +18,18 @@ pub struct EmulatorContext {
     /// Current cycle count
     pub cycle: u64,
     /// Program counter at time of access
-    pub pc: u16,
-    /// Last controller input state
+    pub pc: u32,
+    /// Buttons controller 1 latched at the game's last strobe
     pub last_input: u8,
+    /// Buttons controller 2 latched at the game's last strobe
+    pub last_input_p2: u8,
 }
 
 /// Information about a memory access
 #[derive(Debug, Clone, Copy)]
 pub struct MemoryAccess {
     /// Memory address accessed
-    pub address: u16,
+    pub address: u32,
     /// Value read or written
     pub value: u8,
     /// Type of access
@@ -41,10 +46,10 @@ pub struct MemoryAccess {
 /// read and write for pattern detection and semantic discovery
 pub trait MemoryObserver: Send + Sync {
     /// Called when memory is read
-    fn on_read(&mut self, address: u16, value: u8, context: &EmulatorContext);
+    fn on_read(&mut self, address: u32, value: u8, context: &EmulatorContext);
 
     /// Called when memory is written
-    fn on_write(&mut self, address: u16, old_value: u8, new_value: u8, context: &EmulatorContext);
+    fn on_write(&mut self, address: u32, old_value: u8, new_value: u8, context: &EmulatorContext);
 
     /// Called at the end of each frame
     fn on_frame_end(&mut self, frame: u64) {
@@ -55,20 +60,23 @@ pub trait MemoryObserver: Send + Sync {
 /// Memory bus trait with observer support
 pub trait MemoryBus {
     /// Read a byte from memory
-    fn read(&mut self, addr: u16) -> u8;
+    fn read(&mut self, addr: u32) -> u8;
 
     /// Write a byte to memory
-    fn write(&mut self, addr: u16, value: u8);
+    fn write(&mut self, addr: u32, value: u8);
+
+    /// Size of the address space in bytes (e.g. 0x10000 for a 16-bit bus)
+    fn address_space_size(&self) -> u64;
 
     /// Read a 16-bit word (little-endian)
-    fn read_word(&mut self, addr: u16) -> u16 {
+    fn read_word(&mut self, addr: u32) -> u16 {
         let lo = self.read(addr) as u16;
         let hi = self.read(addr.wrapping_add(1)) as u16;
         (hi << 8) | lo
     }
 
     /// Write a 16-bit word (little-endian)
-    fn write_word(&mut self, addr: u16, value: u16) {
+    fn write_word(&mut self, addr: u32, value: u16) {
         let lo = (value & 0xFF) as u8;
         let hi = ((value >> 8) & 0xFF) as u8;
         self.write(addr, lo);
@@ -92,6 +100,6 @@ pub trait MemoryBus {
 pub struct NoOpObserver;
 
 impl MemoryObserver for NoOpObserver {
-    fn on_read(&mut self, _address: u16, _value: u8, _context: &EmulatorContext) {}
-    fn on_write(&mut self, _address: u16, _old_value: u8, _new_value: u8, _context: &EmulatorContext) {}
+    fn on_read(&mut self, _address: u32, _value: u8, _context: &EmulatorContext) {}
+    fn on_write(&mut self, _address: u32, _old_value: u8, _new_value: u8, _context: &EmulatorContext) {}
 }
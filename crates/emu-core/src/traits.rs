@@ -1,8 +1,13 @@
 //! Core traits for emulators
 
-use crate::Result;
+use crate::{InputDevice, Result};
+use std::any::Any;
 
 /// Core CPU trait
+///
+/// Register files differ wildly between architectures, so apart from the
+/// program counter and cycle count every register is exposed by name
+/// through [`Cpu::registers`].
 pub trait Cpu {
     /// Reset the CPU to its initial state
     fn reset(&mut self);
@@ -12,25 +17,29 @@ pub trait Cpu {
     fn step(&mut self) -> Result<u8>;
 
     /// Get the program counter
-    fn pc(&self) -> u16;
+    fn pc(&self) -> u32;
 
-    /// Get the stack pointer
-    fn sp(&self) -> u8;
+    /// Get the total number of cycles executed since reset
+    fn cycles(&self) -> u64;
 
-    /// Get the accumulator
-    fn a(&self) -> u8;
+    /// Get every architectural register as `(name, value)` pairs,
+    /// in the order a debugger should display them
+    fn registers(&self) -> Vec<(&'static str, u64)>;
 
-    /// Get the X register
-    fn x(&self) -> u8;
-
-    /// Get the Y register
-    fn y(&self) -> u8;
-
-    /// Get the status flags
-    fn status(&self) -> u8;
+    /// Look up a single register by name (case-insensitive)
+    fn register(&self, name: &str) -> Option<u64> {
+        self.registers()
+            .into_iter()
+            .find(|(reg, _)| reg.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
 }
 
 /// Core emulator trait
+///
+/// Everything a frontend needs to run a console without knowing which one
+/// it is: frame timing, the picture, sound and input. Anything further is
+/// specific to the core, reached through [`Emulator::as_any_mut`].
 pub trait Emulator {
     /// Reset the emulator to its initial state
     fn reset(&mut self);
@@ -39,9 +48,316 @@ pub trait Emulator {
     /// Returns the number of cycles executed
     fn run_frame(&mut self) -> Result<usize>;
 
+    /// Run one frame, appending (left, right) audio samples at `sample_rate` Hz
+    ///
+    /// Cores without sound can keep the default, which runs the frame and
+    /// adds nothing.
+    fn run_frame_with_audio(&mut self, sample_rate: u32, audio: &mut Vec<(f32, f32)>) -> Result<usize> {
+        let _ = (sample_rate, audio);
+        self.run_frame()
+    }
+
     /// Check if the emulator is paused
     fn is_paused(&self) -> bool;
 
     /// Pause or unpause the emulator
     fn set_paused(&mut self, paused: bool);
+
+    /// Picture width and height in pixels
+    fn screen_size(&self) -> (usize, usize);
+
+    /// Frames per second the console runs at
+    fn frame_rate(&self) -> f64;
+
+    /// The current picture as RGBA, row by row
+    ///
+    /// Each core converts from its own pixel format with its own palette.
+    fn framebuffer_rgba(&mut self) -> Vec<u8>;
+
+    /// The input device plugged into `port` (0 for player 1), if any
+    fn input_device(&mut self, port: usize) -> Option<&mut dyn InputDevice>;
+
+    /// The core itself, for frontends that offer core-specific features
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+#[cfg(test)]
+mod tests {
+    //! A deliberately tiny CHIP-8-style core: 4KB of memory with 12-bit
+    //! addresses, sixteen V registers, and a 16-key hex keypad. If it can
+    //! implement every emu-core trait, nothing console-specific has leaked
+    //! into them.
+
+    use super::*;
+    use crate::{EmulatorContext, EmulatorError, MemoryBus, MemoryObserver};
+    use std::sync::{Arc, Mutex};
+
+    const KEY_NAMES: [&str; 16] = [
+        "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "A", "B", "C", "D", "E", "F",
+    ];
+
+    #[derive(Default)]
+    struct Keypad {
+        keys: u16,
+    }
+
+    impl InputDevice for Keypad {
+        fn button_count(&self) -> usize {
+            16
+        }
+
+        fn button_name(&self, index: usize) -> &'static str {
+            KEY_NAMES[index]
+        }
+
+        fn is_button_pressed(&self, index: usize) -> bool {
+            self.keys & (1 << index) != 0
+        }
+
+        fn set_button_pressed(&mut self, index: usize, pressed: bool) {
+            if pressed {
+                self.keys |= 1 << index;
+            } else {
+                self.keys &= !(1 << index);
+            }
+        }
+    }
+
+    struct Chip8Bus {
+        ram: Vec<u8>,
+        observers: Vec<Box<dyn MemoryObserver>>,
+        context: EmulatorContext,
+    }
+
+    impl MemoryBus for Chip8Bus {
+        fn read(&mut self, addr: u32) -> u8 {
+            let value = self.ram[(addr & 0xFFF) as usize];
+            let context = self.context;
+            for observer in &mut self.observers {
+                observer.on_read(addr & 0xFFF, value, &context);
+            }
+            value
+        }
+
+        fn write(&mut self, addr: u32, value: u8) {
+            let old = self.ram[(addr & 0xFFF) as usize];
+            self.ram[(addr & 0xFFF) as usize] = value;
+            let context = self.context;
+            for observer in &mut self.observers {
+                observer.on_write(addr & 0xFFF, old, value, &context);
+            }
+        }
+
+        fn address_space_size(&self) -> u64 {
+            0x1000
+        }
+
+        fn attach_observer(&mut self, observer: Box<dyn MemoryObserver>) {
+            self.observers.push(observer);
+        }
+
+        fn clear_observers(&mut self) {
+            self.observers.clear();
+        }
+
+        fn context(&self) -> EmulatorContext {
+            self.context
+        }
+
+        fn update_context(&mut self, context: EmulatorContext) {
+            self.context = context;
+        }
+    }
+
+    struct Chip8 {
+        v: [u8; 16],
+        i: u16,
+        pc: u16,
+        cycles: u64,
+        bus: Chip8Bus,
+        keypad: Keypad,
+        paused: bool,
+    }
+
+    impl Chip8 {
+        fn new(program: &[u8]) -> Self {
+            let mut ram = vec![0; 0x1000];
+            ram[0x200..0x200 + program.len()].copy_from_slice(program);
+            Self {
+                v: [0; 16],
+                i: 0,
+                pc: 0x200,
+                cycles: 0,
+                bus: Chip8Bus {
+                    ram,
+                    observers: Vec::new(),
+                    context: EmulatorContext { frame: 0, cycle: 0, pc: 0x200, last_input: 0, last_input_p2: 0 },
+                },
+                keypad: Keypad::default(),
+                paused: false,
+            }
+        }
+    }
+
+    impl Cpu for Chip8 {
+        fn reset(&mut self) {
+            self.v = [0; 16];
+            self.i = 0;
+            self.pc = 0x200;
+            self.cycles = 0;
+        }
+
+        fn step(&mut self) -> Result<u8> {
+            let opcode = self.bus.read_word(self.pc as u32).swap_bytes();
+            self.pc = (self.pc + 2) & 0xFFF;
+            let x = ((opcode >> 8) & 0xF) as usize;
+            let nn = (opcode & 0xFF) as u8;
+            match opcode >> 12 {
+                // 1NNN: jump
+                0x1 => self.pc = opcode & 0xFFF,
+                // 6XNN: VX = NN
+                0x6 => self.v[x] = nn,
+                // 7XNN: VX += NN
+                0x7 => self.v[x] = self.v[x].wrapping_add(nn),
+                // ANNN: I = NNN
+                0xA => self.i = opcode & 0xFFF,
+                // EXA1: skip if key VX is not pressed
+                0xE if nn == 0xA1 => {
+                    if !self.keypad.is_button_pressed(self.v[x] as usize & 0xF) {
+                        self.pc = (self.pc + 2) & 0xFFF;
+                    }
+                }
+                // FX55: store V0..=VX at I
+                0xF if nn == 0x55 => {
+                    for reg in 0..=x {
+                        self.bus.write(self.i as u32 + reg as u32, self.v[reg]);
+                    }
+                }
+                _ => return Err(EmulatorError::Other(format!("opcode {:04X}", opcode))),
+            }
+            self.cycles += 1;
+            Ok(1)
+        }
+
+        fn pc(&self) -> u32 {
+            self.pc as u32
+        }
+
+        fn cycles(&self) -> u64 {
+            self.cycles
+        }
+
+        fn registers(&self) -> Vec<(&'static str, u64)> {
+            const NAMES: [&str; 16] = [
+                "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7",
+                "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF",
+            ];
+            let mut regs: Vec<_> = NAMES.iter().zip(self.v).map(|(&n, v)| (n, v as u64)).collect();
+            regs.push(("I", self.i as u64));
+            regs
+        }
+    }
+
+    impl Emulator for Chip8 {
+        fn reset(&mut self) {
+            Cpu::reset(self);
+        }
+
+        fn run_frame(&mut self) -> Result<usize> {
+            if self.paused {
+                return Ok(0);
+            }
+            for _ in 0..8 {
+                self.step()?;
+            }
+            Ok(8)
+        }
+
+        fn is_paused(&self) -> bool {
+            self.paused
+        }
+
+        fn set_paused(&mut self, paused: bool) {
+            self.paused = paused;
+        }
+
+        fn screen_size(&self) -> (usize, usize) {
+            (64, 32)
+        }
+
+        fn frame_rate(&self) -> f64 {
+            60.0
+        }
+
+        fn framebuffer_rgba(&mut self) -> Vec<u8> {
+            vec![0; 64 * 32 * 4]
+        }
+
+        fn input_device(&mut self, port: usize) -> Option<&mut dyn InputDevice> {
+            match port {
+                0 => Some(&mut self.keypad),
+                _ => None,
+            }
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    struct WriteLog(Arc<Mutex<Vec<(u32, u8)>>>);
+
+    impl MemoryObserver for WriteLog {
+        fn on_read(&mut self, _address: u32, _value: u8, _context: &EmulatorContext) {}
+
+        fn on_write(&mut self, address: u32, _old_value: u8, new_value: u8, _context: &EmulatorContext) {
+            self.0.lock().unwrap().push((address, new_value));
+        }
+    }
+
+    #[test]
+    fn test_second_core_implements_all_traits() {
+        #[rustfmt::skip]
+        let program = [
+            0x60, 0x05,  // V0 = 5
+            0x61, 0x0C,  // V1 = 12
+            0x71, 0x01,  // V1 += 1
+            0xA8, 0x00,  // I = $800
+            0xE0, 0xA1,  // skip next if key V0 not pressed
+            0xF1, 0x55,  // store V0..V1 at I
+            0x12, 0x0C,  // jump to self
+            0x12, 0x0E,  // jump to self
+        ];
+        let mut core = Chip8::new(&program);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        core.bus.attach_observer(Box::new(WriteLog(log.clone())));
+        Emulator::input_device(&mut core, 0).unwrap().set_button_pressed(5, true);
+        assert!(Emulator::input_device(&mut core, 1).is_none());
+
+        // Drive it through the generic traits only
+        let cycles = Emulator::run_frame(&mut core).unwrap();
+        assert_eq!(cycles, 8);
+        assert_eq!(core.register("v1"), Some(13));
+        assert_eq!(core.register("I"), Some(0x800));
+        assert_eq!(core.registers().len(), 17);
+        assert_eq!(core.bus.address_space_size(), 0x1000);
+        assert_eq!(*log.lock().unwrap(), vec![(0x800, 5), (0x801, 13)]);
+        assert_eq!(core.keypad.bits(), 1 << 5);
+        assert_eq!(core.keypad.button_name(0xA), "A");
+
+        // No sound, so the default audio frame adds nothing
+        let mut audio = Vec::new();
+        assert_eq!(core.run_frame_with_audio(44_100, &mut audio).unwrap(), 8);
+        assert!(audio.is_empty());
+        let (width, height) = core.screen_size();
+        assert_eq!(core.framebuffer_rgba().len(), width * height * 4);
+        assert!(core.as_any_mut().downcast_mut::<Chip8>().is_some());
+
+        core.set_paused(true);
+        assert_eq!(Emulator::run_frame(&mut core).unwrap(), 0);
+
+        Emulator::reset(&mut core);
+        assert_eq!(core.pc(), 0x200);
+        assert_eq!(core.cycles(), 0);
+    }
 }
@@ -0,0 +1,43 @@
+//! Frame to RGBA conversion: per pixel through `palette_to_rgb_emphasized`
+//! against `framebuffer_to_rgba_fast`'s lookup table
+//!
+//! The table is what `framebuffer_rgba` uses, so this is the per-frame cost
+//! every frontend pays.
+//!
+//! ```bash
+//! cargo bench -p emu-nes --bench palette
+//! ```
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use emu_nes::{framebuffer_to_rgba_fast, palette_to_rgb_emphasized};
+
+/// Pixels in a frame
+const PIXELS: usize = 256 * 240;
+
+fn palette(c: &mut Criterion) {
+    // Every index and then some, so no one color dominates
+    let framebuffer: Vec<u8> = (0..PIXELS).map(|pixel| (pixel * 7 % 64) as u8).collect();
+
+    let mut group = c.benchmark_group("frame_to_rgba");
+    group.throughput(Throughput::Elements(PIXELS as u64));
+    for emphasis in [0, 5] {
+        group.bench_function(format!("naive/emphasis {}", emphasis), |b| {
+            b.iter(|| {
+                let mut rgba = Vec::with_capacity(PIXELS * 4);
+                for &index in black_box(&framebuffer) {
+                    let (r, g, b) = palette_to_rgb_emphasized(index, black_box(emphasis));
+                    rgba.extend([r, g, b, 0xFF]);
+                }
+                rgba
+            })
+        });
+        let mut rgba = vec![0; PIXELS * 4];
+        group.bench_function(format!("table/emphasis {}", emphasis), |b| {
+            b.iter(|| framebuffer_to_rgba_fast(black_box(&framebuffer), black_box(emphasis), &mut rgba))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, palette);
+criterion_main!(benches);
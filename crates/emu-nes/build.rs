@@ -0,0 +1,22 @@
+//! Compiles data/romdb.csv into the table behind the `romdb` feature
+
+#[path = "src/romdb/csv.rs"]
+#[allow(dead_code)]
+mod csv;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    println!("cargo:rerun-if-changed=data/romdb.csv");
+    println!("cargo:rerun-if-changed=src/romdb/csv.rs");
+    if env::var_os("CARGO_FEATURE_ROMDB").is_none() {
+        return;
+    }
+
+    let text = fs::read_to_string("data/romdb.csv").expect("reading data/romdb.csv");
+    let rows = csv::parse(&text).unwrap_or_else(|e| panic!("data/romdb.csv: {}", e));
+    let out = Path::new(&env::var_os("OUT_DIR").unwrap()).join("romdb.rs");
+    fs::write(out, csv::generate(&rows)).expect("writing romdb.rs");
+}
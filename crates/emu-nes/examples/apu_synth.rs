@@ -0,0 +1,98 @@
+//! Play a C major arpeggio on the APU alone and save it as a WAV file
+//!
+//! No ROM is involved: the register writes a sound driver would make are
+//! listed per frame, and `ApuPlayer` renders the audio between them.
+
+use emu_nes::apu_player::CPU_CLOCK_HZ;
+use emu_nes::ApuPlayer;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+const SAMPLE_RATE: u32 = 44_100;
+
+/// Frames each arpeggio note lasts
+const NOTE_FRAMES: u32 = 12;
+
+/// C4, E4, G4, C5
+const ARPEGGIO: [f64; 4] = [261.63, 329.63, 392.00, 523.25];
+
+/// Pulse timer period for `freq`: f = CPU / (16 * (t + 1))
+fn pulse_timer(freq: f64) -> u16 {
+    (CPU_CLOCK_HZ as f64 / (16.0 * freq) - 1.0).round() as u16
+}
+
+/// Triangle timer period for `freq`: f = CPU / (32 * (t + 1))
+fn triangle_timer(freq: f64) -> u16 {
+    (CPU_CLOCK_HZ as f64 / (32.0 * freq) - 1.0).round() as u16
+}
+
+/// Register writes for every frame, as (frame, address, value)
+fn schedule() -> Vec<(u32, u16, u8)> {
+    let mut writes = vec![
+        (0, 0x4015, 0x05), // Pulse 1 and triangle on
+        (0, 0x4000, 0x86), // 50% duty, decaying envelope
+        (0, 0x4008, 0xFF), // Triangle held by the linear counter
+    ];
+    // A C3 bass note under the whole arpeggio
+    let bass = triangle_timer(130.81);
+    writes.push((0, 0x400A, bass as u8));
+    writes.push((0, 0x400B, (bass >> 8) as u8));
+
+    // Up and back down twice; writing $4003 restarts the envelope
+    let order = [0, 1, 2, 3, 2, 1, 0, 1, 2, 3, 2, 1, 0];
+    for (step, &note) in order.iter().enumerate() {
+        let frame = step as u32 * NOTE_FRAMES;
+        let timer = pulse_timer(ARPEGGIO[note]);
+        writes.push((frame, 0x4002, timer as u8));
+        writes.push((frame, 0x4003, (1 << 3) | (timer >> 8) as u8));
+    }
+    let end = order.len() as u32 * NOTE_FRAMES;
+    writes.push((end + 30, 0x4015, 0x00));
+    writes
+}
+
+/// Write mono 16-bit PCM
+fn write_wav(path: &str, samples: &[f32], sample_rate: u32) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let data_len = samples.len() as u32 * 2;
+    file.write_all(b"RIFF")?;
+    file.write_all(&(36 + data_len).to_le_bytes())?;
+    file.write_all(b"WAVEfmt ")?;
+    file.write_all(&16u32.to_le_bytes())?;
+    file.write_all(&1u16.to_le_bytes())?; // PCM
+    file.write_all(&1u16.to_le_bytes())?; // Mono
+    file.write_all(&sample_rate.to_le_bytes())?;
+    file.write_all(&(sample_rate * 2).to_le_bytes())?;
+    file.write_all(&2u16.to_le_bytes())?; // Block align
+    file.write_all(&16u16.to_le_bytes())?; // Bits per sample
+    file.write_all(b"data")?;
+    file.write_all(&data_len.to_le_bytes())?;
+    for &sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        file.write_all(&value.to_le_bytes())?;
+    }
+    file.flush()
+}
+
+fn main() -> io::Result<()> {
+    let writes = schedule();
+    let last_frame = writes.iter().map(|&(frame, _, _)| frame).max().unwrap_or(0) + 10;
+
+    let mut player = ApuPlayer::new(SAMPLE_RATE);
+    let mut samples = Vec::new();
+    for frame in 0..last_frame {
+        for &(_, addr, value) in writes.iter().filter(|write| write.0 == frame) {
+            player.write_register(addr, value);
+        }
+        samples.extend(player.step_frames(1));
+    }
+
+    write_wav("apu_synth.wav", &samples, SAMPLE_RATE)?;
+    println!(
+        "Wrote apu_synth.wav: {} samples, {:.2} s at {} Hz",
+        samples.len(),
+        samples.len() as f64 / SAMPLE_RATE as f64,
+        SAMPLE_RATE
+    );
+    Ok(())
+}
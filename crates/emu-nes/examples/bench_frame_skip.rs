@@ -0,0 +1,47 @@
+use emu_nes::NesSystem;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Frames timed in each mode
+const FRAMES: u32 = 300;
+
+fn main() -> io::Result<()> {
+    let path = std::env::args().nth(1).unwrap_or_else(|| "perfect_visual.nes".into());
+    let load = || {
+        NesSystem::load(&path).map_err(|e| {
+            io::Error::other(format!(
+                "{:?} (run `cargo run --example generate_perfect_visual -p emu-nes` first)",
+                e
+            ))
+        })
+    };
+
+    println!("Timing {} frames of {}\n", FRAMES, path);
+
+    let rendered = time_frames(&mut load()?, true)?;
+    let skipped = time_frames(&mut load()?, false)?;
+    report("Rendering", rendered);
+    report("Skipping", skipped);
+    println!("\nSpeedup: {:.1}x", rendered.as_secs_f64() / skipped.as_secs_f64());
+
+    Ok(())
+}
+
+/// Time `FRAMES` calls to `run_frame` with rendering on or off
+fn time_frames(system: &mut NesSystem, render: bool) -> io::Result<Duration> {
+    system.set_render_enabled(render);
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        system.run_frame().map_err(|e| io::Error::other(format!("{:?}", e)))?;
+    }
+    Ok(start.elapsed())
+}
+
+fn report(label: &str, elapsed: Duration) {
+    println!(
+        "{:>10}: {:8.2?} ({:.0} fps)",
+        label,
+        elapsed,
+        FRAMES as f64 / elapsed.as_secs_f64()
+    );
+}
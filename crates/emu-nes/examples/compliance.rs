@@ -0,0 +1,77 @@
+//! Run every compliance suite and print a scorecard
+//!
+//! ```text
+//! cargo run --release --example compliance -p emu-nes -- [--json] [--roms DIR]
+//! ```
+//!
+//! blargg's test ROMs are run from `--roms DIR`, or `./test-roms` when it
+//! exists. Exits with status 1 if any check failed.
+
+#[path = "generate_animation_test.rs"]
+#[allow(dead_code)]
+mod animation;
+
+#[path = "generate_controller_test.rs"]
+#[allow(dead_code)]
+mod controller;
+
+#[path = "generate_input_test.rs"]
+#[allow(dead_code)]
+mod input;
+
+#[path = "generate_perfect_visual.rs"]
+#[allow(dead_code)]
+mod perfect_visual;
+
+#[path = "generate_scrolling_tests.rs"]
+#[allow(dead_code)]
+mod scrolling;
+
+#[path = "generate_test_rom.rs"]
+#[allow(dead_code)]
+mod test_rom;
+
+use emu_nes::compliance;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Where the blargg ROMs are looked for without `--roms`
+const DEFAULT_ROM_DIR: &str = "test-roms";
+
+fn main() -> io::Result<ExitCode> {
+    let mut json = false;
+    let mut rom_dir = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--roms" => match args.next() {
+                Some(dir) => rom_dir = Some(PathBuf::from(dir)),
+                None => return Err(io::Error::other("--roms needs a directory")),
+            },
+            _ => return Err(io::Error::other(format!("unknown argument {}", arg))),
+        }
+    }
+    let rom_dir = rom_dir.or_else(|| {
+        let default = PathBuf::from(DEFAULT_ROM_DIR);
+        default.is_dir().then_some(default)
+    });
+
+    let roms = vec![
+        ("animation", animation::build_rom()),
+        ("controller", controller::build_rom()),
+        ("input", input::build_rom()),
+        ("perfect_visual", perfect_visual::build_rom()),
+        ("scrolling", scrolling::build_rom(64, 32)),
+        ("test_rom", test_rom::build_rom()),
+    ];
+    let scorecard = compliance::run_all(&roms, rom_dir.as_deref())?;
+
+    if json {
+        println!("{}", scorecard.to_json());
+    } else {
+        println!("{}", scorecard);
+    }
+    Ok(if scorecard.all_passed() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
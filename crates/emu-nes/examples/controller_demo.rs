@@ -1,12 +1,12 @@
-/// Controller Demo
-/// 
-/// Demonstrates controller input functionality by:
-/// 1. Loading the controller test ROM
-/// 2. Setting various button states
-/// 3. Running the ROM to read controller input
-/// 4. Verifying button states were correctly read
-/// 
-/// Usage: cargo run --example controller_demo -p emu-nes
+//! Controller Demo
+//! 
+//! Demonstrates controller input functionality by:
+//! 1. Loading the controller test ROM
+//! 2. Setting various button states
+//! 3. Running the ROM to read controller input
+//! 4. Verifying button states were correctly read
+//! 
+//! Usage: cargo run --example controller_demo -p emu-nes
 
 use emu_core::Button;
 use emu_nes::NesSystem;
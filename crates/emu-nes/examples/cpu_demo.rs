@@ -7,43 +7,11 @@
 //! 4. Uses branches and loops
 
 use emu_core::Cpu;
-use emu_nes::cpu::{Cpu6502, CpuMemory};
-
-/// Simple memory implementation for testing
-struct SimpleMemory {
-    ram: Vec<u8>,
-}
-
-impl SimpleMemory {
-    fn new() -> Self {
-        Self {
-            ram: vec![0; 0x10000], // 64KB
-        }
-    }
-
-    fn load_program(&mut self, start_addr: u16, program: &[u8]) {
-        for (i, &byte) in program.iter().enumerate() {
-            self.ram[start_addr as usize + i] = byte;
-        }
-    }
-}
-
-impl CpuMemory for SimpleMemory {
-    fn read(&mut self, addr: u16) -> u8 {
-        self.ram[addr as usize]
-    }
-
-    fn write(&mut self, addr: u16, value: u8) {
-        self.ram[addr as usize] = value;
-    }
-}
+use emu_nes::cpu::testing::{TestBoard, Until};
 
 fn main() {
     println!("=== LumiEmu 6502 CPU Demo ===\n");
 
-    // Create memory and CPU
-    let mut memory = SimpleMemory::new();
-    
     // Simple program that calculates: result = (10 + 5) * 2
     // Using the 6502's limited instruction set
     #[rustfmt::skip]
@@ -74,21 +42,15 @@ fn main() {
         0x60,              // RTS           ; Return
     ];
 
-    // Load program at $0200
-    memory.load_program(0x0200, &program);
-    
-    // Set reset vector to point to our program
-    memory.ram[0xFFFC] = 0x00;
-    memory.ram[0xFFFD] = 0x02;
-    
-    // Create CPU and reset
-    let mut cpu = Cpu6502::new(memory);
-    cpu.reset();
+    // Load program at $0200 and point the reset vector at it
+    let mut board = TestBoard::new();
+    board.load(0x0200, &program).set_reset_vector(0x0200);
     
+    let cpu = board.cpu();
     println!("Initial state:");
     println!("  PC: ${:04X}", cpu.pc());
-    println!("  A: ${:02X}, X: ${:02X}, Y: ${:02X}", cpu.a(), cpu.x(), cpu.y());
-    println!("  SP: ${:02X}", cpu.sp());
+    println!("  A: ${:02X}, X: ${:02X}, Y: ${:02X}", cpu.a, cpu.x, cpu.y);
+    println!("  SP: ${:02X}", cpu.sp);
     println!();
 
     // Execute the program step by step
@@ -98,16 +60,19 @@ fn main() {
     println!("Executing program...\n");
 
     while instruction_count < max_instructions {
-        let pc_before = cpu.pc();
+        let pc_before = board.cpu().pc();
+        let cycles_before = board.cpu().cycles();
         
-        match cpu.step() {
-            Ok(cycles) => {
+        match board.run_until(Until::Instructions(1)) {
+            Ok(_) => {
+                let cpu = board.cpu();
+                let cycles = cpu.cycles() - cycles_before;
                 instruction_count += 1;
                 
                 // Print first 10 instructions, then interesting milestones
                 if instruction_count <= 10 || instruction_count % 5 == 0 || instruction_count >= 25 {
                     println!("#{:2} PC=${:04X} A=${:02X} X=${:02X} Y=${:02X} SP=${:02X} Cycles={}",
-                        instruction_count, pc_before, cpu.a(), cpu.x(), cpu.y(), cpu.sp(), cycles);
+                        instruction_count, pc_before, cpu.a, cpu.x, cpu.y, cpu.sp, cycles);
                 }
 
                 // Check if we've reached the infinite loop (program completed successfully)
@@ -124,19 +89,26 @@ fn main() {
         }
     }
 
+    let cpu = board.cpu();
     println!("\nFinal state:");
     println!("  PC: ${:04X}", cpu.pc());
-    println!("  A: ${:02X}, X: ${:02X}, Y: ${:02X}", cpu.a(), cpu.x(), cpu.y());
-    println!("  SP: ${:02X}", cpu.sp());
+    println!("  A: ${:02X}, X: ${:02X}, Y: ${:02X}", cpu.a, cpu.x, cpu.y);
+    println!("  SP: ${:02X}", cpu.sp);
     println!("  Total cycles: {}", cpu.cycles);
     println!();
     
     // Read results from memory
     println!("Results in memory:");
-    println!("  $0020: ${:02X} (should be 15 = 10 + 5)", cpu.memory().read(0x20));
-    println!("  $0021: ${:02X} (should be 30 = 15 * 2)", cpu.memory().read(0x21));
-    println!("  $0022: ${:02X} (should be 5 = loop counter)", cpu.memory().read(0x22));
-    println!("  $0023: ${:02X} (should be 42 = returned from subroutine)", cpu.memory().read(0x23));
+    println!("  $0020: ${:02X} (should be 15 = 10 + 5)", board.read(0x20));
+    println!("  $0021: ${:02X} (should be 30 = 15 * 2)", board.read(0x21));
+    println!("  $0022: ${:02X} (should be 5 = loop counter)", board.read(0x22));
+    println!("  $0023: ${:02X} (should be 42 = returned from subroutine)", board.read(0x23));
+    
+    board
+        .assert_mem(0x20, 15)
+        .assert_mem(0x21, 30)
+        .assert_mem(0x22, 5)
+        .assert_mem(0x23, 42);
     
     println!("\n=== Demo Complete ===");
 }
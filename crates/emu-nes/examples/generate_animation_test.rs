@@ -1,8 +1,8 @@
-/// Generate a simpler animated test ROM - just a color-cycling screen
-/// 
-/// Creates a ROM that demonstrates smooth animation with minimal complexity
-/// 
-/// Usage: cargo run --example generate_animation_test -p emu-nes
+//! Generate a simpler animated test ROM - just a color-cycling screen
+//! 
+//! Creates a ROM that demonstrates smooth animation with minimal complexity
+//! 
+//! Usage: cargo run --example generate_animation_test -p emu-nes
 
 use std::fs::File;
 use std::io::{self, Write};
@@ -21,6 +21,21 @@ fn main() -> io::Result<()> {
 }
 
 fn generate_rom(filename: &str) -> io::Result<()> {
+    let rom = build_rom();
+    let mut file = File::create(filename)?;
+    file.write_all(&rom)?;
+    
+    // The vectors are the last six bytes of the 16KB PRG-ROM after the header
+    let vector = |offset: usize| u16::from_le_bytes([rom[16 + offset], rom[17 + offset]]);
+    println!("Generated {}", filename);
+    println!("Reset handler at: ${:04X}", vector(0x3FFC));
+    println!("NMI handler at: ${:04X}", vector(0x3FFA));
+    
+    Ok(())
+}
+
+/// Build the iNES image of the animation ROM
+pub fn build_rom() -> Vec<u8> {
     let mut prg = vec![0xEA; 0x4000]; // 16KB PRG-ROM
     let chr = generate_chr();
     
@@ -130,7 +145,7 @@ fn generate_rom(filename: &str) -> io::Result<()> {
     prg[pc] = 0xC9; pc += 1; // CMP #30
     prg[pc] = 30; pc += 1;
     prg[pc] = 0x90; pc += 1; // BCC skip_color
-    prg[pc] = 0x13; pc += 1; // Skip ahead
+    let to_skip_color = pc; pc += 1; // Patched below
     
     // Reset counter
     prg[pc] = 0xA9; pc += 1; // LDA #0
@@ -166,13 +181,18 @@ fn generate_rom(filename: &str) -> io::Result<()> {
     prg[pc] = 0x20; pc += 1;
     
     // skip_color:
-    // Move sprite in a square (1 pixel per frame)
+    prg[to_skip_color] = (pc - to_skip_color - 1) as u8;
+    
+    // Move sprite in a square (1 pixel per frame). The branch and jump
+    // operands aimed at write_oam are patched once it's placed
+    let mut branches_to_write_oam = Vec::new();
+    let mut jumps_to_write_oam = Vec::new();
     prg[pc] = 0xA5; pc += 1; // LDA $02 (state)
     prg[pc] = 0x02; pc += 1;
     prg[pc] = 0xC9; pc += 1; // CMP #0 (moving right?)
     prg[pc] = 0x00; pc += 1;
     prg[pc] = 0xD0; pc += 1; // BNE check_down
-    prg[pc] = 0x0E; pc += 1;
+    let to_check_down = pc; pc += 1;
     
     // Move right
     prg[pc] = 0xE6; pc += 1; // INC $00 (X++)
@@ -182,21 +202,20 @@ fn generate_rom(filename: &str) -> io::Result<()> {
     prg[pc] = 0xC9; pc += 1; // CMP #192 (reached right?)
     prg[pc] = 192; pc += 1;
     prg[pc] = 0x90; pc += 1; // BCC write_oam
-    prg[pc] = 0x37; pc += 1;
+    branches_to_write_oam.push(pc); pc += 1;
     prg[pc] = 0xA9; pc += 1; // LDA #1
     prg[pc] = 0x01; pc += 1;
     prg[pc] = 0x85; pc += 1; // STA $02 (state = down)
     prg[pc] = 0x02; pc += 1;
     prg[pc] = 0x4C; pc += 1; // JMP write_oam
-    let write_oam = pc + 2;
-    prg[pc] = ((write_oam + 0x8000) & 0xFF) as u8; pc += 1;
-    prg[pc] = ((write_oam + 0x8000) >> 8) as u8; pc += 1;
+    jumps_to_write_oam.push(pc); pc += 2;
     
     // check_down:
+    prg[to_check_down] = (pc - to_check_down - 1) as u8;
     prg[pc] = 0xC9; pc += 1; // CMP #1 (moving down?)
     prg[pc] = 0x01; pc += 1;
     prg[pc] = 0xD0; pc += 1; // BNE check_left
-    prg[pc] = 0x0E; pc += 1;
+    let to_check_left = pc; pc += 1;
     
     // Move down
     prg[pc] = 0xE6; pc += 1; // INC $01 (Y++)
@@ -206,20 +225,20 @@ fn generate_rom(filename: &str) -> io::Result<()> {
     prg[pc] = 0xC9; pc += 1; // CMP #192
     prg[pc] = 192; pc += 1;
     prg[pc] = 0x90; pc += 1; // BCC write_oam
-    prg[pc] = 0x25; pc += 1;
+    branches_to_write_oam.push(pc); pc += 1;
     prg[pc] = 0xA9; pc += 1; // LDA #2
     prg[pc] = 0x02; pc += 1;
     prg[pc] = 0x85; pc += 1; // STA $02 (state = left)
     prg[pc] = 0x02; pc += 1;
     prg[pc] = 0x4C; pc += 1; // JMP write_oam
-    prg[pc] = ((write_oam + 0x8000) & 0xFF) as u8; pc += 1;
-    prg[pc] = ((write_oam + 0x8000) >> 8) as u8; pc += 1;
+    jumps_to_write_oam.push(pc); pc += 2;
     
     // check_left:
+    prg[to_check_left] = (pc - to_check_left - 1) as u8;
     prg[pc] = 0xC9; pc += 1; // CMP #2 (moving left?)
     prg[pc] = 0x02; pc += 1;
     prg[pc] = 0xD0; pc += 1; // BNE move_up
-    prg[pc] = 0x0E; pc += 1;
+    let to_move_up = pc; pc += 1;
     
     // Move left
     prg[pc] = 0xC6; pc += 1; // DEC $00 (X--)
@@ -229,16 +248,16 @@ fn generate_rom(filename: &str) -> io::Result<()> {
     prg[pc] = 0xC9; pc += 1; // CMP #64
     prg[pc] = 64; pc += 1;
     prg[pc] = 0xB0; pc += 1; // BCS write_oam
-    prg[pc] = 0x13; pc += 1;
+    branches_to_write_oam.push(pc); pc += 1;
     prg[pc] = 0xA9; pc += 1; // LDA #3
     prg[pc] = 0x03; pc += 1;
     prg[pc] = 0x85; pc += 1; // STA $02 (state = up)
     prg[pc] = 0x02; pc += 1;
     prg[pc] = 0x4C; pc += 1; // JMP write_oam
-    prg[pc] = ((write_oam + 0x8000) & 0xFF) as u8; pc += 1;
-    prg[pc] = ((write_oam + 0x8000) >> 8) as u8; pc += 1;
+    jumps_to_write_oam.push(pc); pc += 2;
     
     // move_up:
+    prg[to_move_up] = (pc - to_move_up - 1) as u8;
     prg[pc] = 0xC6; pc += 1; // DEC $01 (Y--)
     prg[pc] = 0x01; pc += 1;
     prg[pc] = 0xA5; pc += 1; // LDA $01
@@ -246,14 +265,20 @@ fn generate_rom(filename: &str) -> io::Result<()> {
     prg[pc] = 0xC9; pc += 1; // CMP #64
     prg[pc] = 64; pc += 1;
     prg[pc] = 0xB0; pc += 1; // BCS write_oam
-    prg[pc] = 0x04; pc += 1;
+    branches_to_write_oam.push(pc); pc += 1;
     prg[pc] = 0xA9; pc += 1; // LDA #0
     prg[pc] = 0x00; pc += 1;
     prg[pc] = 0x85; pc += 1; // STA $02 (state = right)
     prg[pc] = 0x02; pc += 1;
     
     // write_oam: Write sprite
-    // (label for jumps, pc will reach here naturally)
+    let write_oam = pc;
+    for operand in branches_to_write_oam {
+        prg[operand] = (write_oam - operand - 1) as u8;
+    }
+    for operand in jumps_to_write_oam {
+        prg[operand..operand + 2].copy_from_slice(&((write_oam + 0x8000) as u16).to_le_bytes());
+    }
     prg[pc] = 0xA5; pc += 1; // LDA $01 (Y)
     prg[pc] = 0x01; pc += 1;
     prg[pc] = 0x8D; pc += 1; // STA $0200
@@ -288,10 +313,6 @@ fn generate_rom(filename: &str) -> io::Result<()> {
     // Return from NMI
     prg[pc] = 0x40; // RTI
     
-    println!("Reset handler at: ${:04X}", reset_handler + 0x8000);
-    println!("NMI handler at: ${:04X}", nmi_handler + 0x8000);
-    println!("Code ends at: ${:04X}", pc + 0x8000);
-    
     // Vectors
     prg[0x3FFA] = ((nmi_handler + 0x8000) & 0xFF) as u8;
     prg[0x3FFB] = ((nmi_handler + 0x8000) >> 8) as u8;
@@ -311,14 +332,7 @@ fn generate_rom(filename: &str) -> io::Result<()> {
         0x00, 0x00, 0x00, 0x00,
     ];
     
-    let mut file = File::create(filename)?;
-    file.write_all(&ines_header)?;
-    file.write_all(&prg)?;
-    file.write_all(&chr)?;
-    
-    println!("Generated {}", filename);
-    
-    Ok(())
+    [&ines_header[..], &prg, &chr].concat()
 }
 
 /// Generate CHR-ROM with a filled square sprite
@@ -0,0 +1,64 @@
+//! Generate an audio test ROM
+//!
+//! Creates audio_test_440hz.nes, which waits out the PPU warm-up and then
+//! holds a 440Hz (A4) square wave on pulse 1 forever. It has no CHR-ROM,
+//! so the cartridge gets CHR-RAM.
+//!
+//! Usage: cargo run --example generate_audio_test -p emu-nes
+
+use nes_asm::{Assembler, InesBuilder};
+use std::fs::File;
+use std::io::{self, Write};
+
+/// Pulse timer period for A4: CPU clock / (16 * 440Hz) - 1
+const A4_PERIOD: u16 = 253;
+
+/// Assemble the ROM image
+pub fn build_rom() -> Vec<u8> {
+    let mut asm = Assembler::new(0xC000, 0x4000);
+
+    asm.label("reset")
+        // Wait for PPU warmup (2 frames)
+        .label("vblank1")
+        .bit_abs(0x2002)
+        .bpl("vblank1")
+        .label("vblank2")
+        .bit_abs(0x2002)
+        .bpl("vblank2")
+        // Pulse 1: 50% duty, length counter halted, constant volume 15
+        .lda_imm(0xBF)
+        .sta_abs(0x4000)
+        // No sweep
+        .lda_imm(0x00)
+        .sta_abs(0x4001)
+        .lda_imm(A4_PERIOD as u8)
+        .sta_abs(0x4002)
+        // Enable pulse 1 before loading its length counter
+        .lda_imm(0x01)
+        .sta_abs(0x4015)
+        .lda_imm((A4_PERIOD >> 8) as u8)
+        .sta_abs(0x4003)
+        // Enable NMI
+        .lda_imm(0x80)
+        .sta_abs(0x2000)
+        .label("main")
+        .jmp("main")
+        // Nothing to do per frame: the tone keeps playing
+        .label("nmi")
+        .rti();
+
+    asm.vectors("nmi", "reset", "nmi");
+
+    let prg = asm.assemble().expect("audio test assembles");
+    InesBuilder::new(prg).build().expect("audio test has whole banks")
+}
+
+fn main() -> io::Result<()> {
+    let mut file = File::create("audio_test_440hz.nes")?;
+    file.write_all(&build_rom())?;
+
+    println!("Generated audio_test_440hz.nes");
+    println!("Plays a 440Hz tone (musical note A4)");
+
+    Ok(())
+}
@@ -1,15 +1,16 @@
-/// Generate a controller test ROM
-/// 
-/// This ROM reads controller input and stores button states in memory:
-/// - Addresses $00-$07: Button states (A, B, Select, Start, Up, Down, Left, Right)
-/// - Address $10: Success flag ($FF when complete)
-/// 
-/// Usage: cargo run --example generate_controller_test -p emu-nes
+//! Generate a controller test ROM
+//! 
+//! This ROM reads controller input and stores button states in memory:
+//! - Addresses $00-$07: Button states (A, B, Select, Start, Up, Down, Left, Right)
+//! - Address $10: Success flag ($FF when complete)
+//! 
+//! Usage: cargo run --example generate_controller_test -p emu-nes
 
 use std::fs::File;
 use std::io::{self, Write};
 
-fn main() -> io::Result<()> {
+/// Assemble the ROM image
+pub fn build_rom() -> Vec<u8> {
     let mut prg = vec![0xEA; 0x4000]; // Fill with NOPs
     
     let mut pc = 0;
@@ -63,7 +64,7 @@ fn main() -> io::Result<()> {
     let hang = pc;
     prg[pc] = 0x4C; pc += 1; // JMP hang
     prg[pc] = ((hang + 0x8000) & 0xFF) as u8; pc += 1;
-    prg[pc] = ((hang + 0x8000) >> 8) as u8; pc += 1;
+    prg[pc] = ((hang + 0x8000) >> 8) as u8;
     
     // Reset vector points to $8000
     prg[0x3FFC] = 0x00;
@@ -83,10 +84,15 @@ fn main() -> io::Result<()> {
         0x00, 0x00, 0x00, 0x00,
     ];
     
+    let mut rom = ines_header.to_vec();
+    rom.extend_from_slice(&prg);
+    rom.extend_from_slice(&chr);
+    rom
+}
+
+fn main() -> io::Result<()> {
     let mut file = File::create("controller_test.nes")?;
-    file.write_all(&ines_header)?;
-    file.write_all(&prg)?;
-    file.write_all(&chr)?;
+    file.write_all(&build_rom())?;
     
     println!("Generated controller_test.nes");
     println!();
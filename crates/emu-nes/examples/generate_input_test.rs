@@ -0,0 +1,158 @@
+//! Generate an interactive controller input test ROM
+//!
+//! Every frame the NMI handler strobes $4016, reads the 8 button bits of
+//! controller 1 and stores them at $00FF (A in bit 7 down to Right in bit 0).
+//! It then draws one tile per button in the middle of the screen, left to
+//! right in the same order: a solid block while the button is held and an
+//! outline otherwise. Handy for checking key mappings in the GUI.
+//!
+//! Usage: cargo run --example generate_input_test -p emu-nes
+
+use std::fs::File;
+use std::io::{self, Write};
+
+/// Where the button byte is stored
+pub const BUTTONS_ADDR: u16 = 0x00FF;
+
+/// Nametable row and column of the leftmost button tile
+pub const TILE_ROW: usize = 14;
+pub const TILE_COL: usize = 12;
+
+/// Tile drawn for a released button
+pub const TILE_RELEASED: u8 = 0x02;
+
+/// Tile drawn for a pressed button
+pub const TILE_PRESSED: u8 = 0x01;
+
+/// Append instruction bytes at `pc`
+fn emit(prg: &mut [u8], pc: &mut usize, bytes: &[u8]) {
+    prg[*pc..*pc + bytes.len()].copy_from_slice(bytes);
+    *pc += bytes.len();
+}
+
+/// Append a relative branch back to `target`
+fn branch_back(prg: &mut [u8], pc: &mut usize, opcode: u8, target: usize) {
+    let offset = target as isize - (*pc as isize + 2);
+    emit(prg, pc, &[opcode, offset as i8 as u8]);
+}
+
+/// Build the complete iNES image
+pub fn build_rom() -> Vec<u8> {
+    let mut prg = vec![0xEA; 0x4000]; // Fill with NOPs
+    let mut pc = 0;
+
+    // --- Reset ($8000) ---
+    emit(&mut prg, &mut pc, &[0x78]); // SEI
+    emit(&mut prg, &mut pc, &[0xD8]); // CLD
+    emit(&mut prg, &mut pc, &[0xA2, 0xFF]); // LDX #$FF
+    emit(&mut prg, &mut pc, &[0x9A]); // TXS
+
+    // Wait for two vblanks so the PPU has warmed up
+    for _ in 0..2 {
+        let wait = pc;
+        emit(&mut prg, &mut pc, &[0x2C, 0x02, 0x20]); // BIT $2002
+        branch_back(&mut prg, &mut pc, 0x10, wait); // BPL wait
+    }
+
+    // Background palette 0: black, then white for colours 1-3
+    emit(&mut prg, &mut pc, &[0xAD, 0x02, 0x20]); // LDA $2002 (reset address latch)
+    emit(&mut prg, &mut pc, &[0xA9, 0x3F, 0x8D, 0x06, 0x20]); // LDA #$3F; STA $2006
+    emit(&mut prg, &mut pc, &[0xA9, 0x00, 0x8D, 0x06, 0x20]); // LDA #$00; STA $2006
+    for color in [0x0F, 0x30, 0x30, 0x30] {
+        emit(&mut prg, &mut pc, &[0xA9, color, 0x8D, 0x07, 0x20]); // LDA #color; STA $2007
+    }
+
+    // Clear nametable 0 and its attributes ($2000-$23FF) to tile 0
+    emit(&mut prg, &mut pc, &[0xA9, 0x20, 0x8D, 0x06, 0x20]); // LDA #$20; STA $2006
+    emit(&mut prg, &mut pc, &[0xA9, 0x00, 0x8D, 0x06, 0x20]); // LDA #$00; STA $2006
+    emit(&mut prg, &mut pc, &[0xA0, 0x04]); // LDY #$04
+    emit(&mut prg, &mut pc, &[0xA2, 0x00]); // LDX #$00
+    let clear = pc;
+    emit(&mut prg, &mut pc, &[0x8D, 0x07, 0x20]); // STA $2007
+    emit(&mut prg, &mut pc, &[0xE8]); // INX
+    branch_back(&mut prg, &mut pc, 0xD0, clear); // BNE clear
+    emit(&mut prg, &mut pc, &[0x88]); // DEY
+    branch_back(&mut prg, &mut pc, 0xD0, clear); // BNE clear
+
+    emit(&mut prg, &mut pc, &[0x85, BUTTONS_ADDR as u8]); // STA $FF (A is still 0)
+
+    // Scroll to (0, 0), enable NMI and background rendering
+    emit(&mut prg, &mut pc, &[0x8D, 0x05, 0x20, 0x8D, 0x05, 0x20]); // STA $2005 x2
+    emit(&mut prg, &mut pc, &[0xA9, 0x80, 0x8D, 0x00, 0x20]); // LDA #$80; STA $2000
+    emit(&mut prg, &mut pc, &[0xA9, 0x0A, 0x8D, 0x01, 0x20]); // LDA #$0A; STA $2001
+
+    // Everything else happens in the NMI handler
+    let main_loop = 0x8000 + pc as u16;
+    emit(&mut prg, &mut pc, &[0x4C, main_loop as u8, (main_loop >> 8) as u8]); // JMP main_loop
+
+    // --- NMI ---
+    let nmi = 0x8000 + pc as u16;
+    emit(&mut prg, &mut pc, &[0x48, 0x8A, 0x48]); // PHA; TXA; PHA
+
+    // Strobe the controller, then shift its 8 bits into $FF (A ends up in bit 7)
+    emit(&mut prg, &mut pc, &[0xA9, 0x01, 0x8D, 0x16, 0x40]); // LDA #$01; STA $4016
+    emit(&mut prg, &mut pc, &[0xA9, 0x00, 0x8D, 0x16, 0x40]); // LDA #$00; STA $4016
+    emit(&mut prg, &mut pc, &[0xA2, 0x08]); // LDX #$08
+    let read = pc;
+    emit(&mut prg, &mut pc, &[0xAD, 0x16, 0x40]); // LDA $4016
+    emit(&mut prg, &mut pc, &[0x4A]); // LSR A (button bit into carry)
+    emit(&mut prg, &mut pc, &[0x26, BUTTONS_ADDR as u8]); // ROL $FF
+    emit(&mut prg, &mut pc, &[0xCA]); // DEX
+    branch_back(&mut prg, &mut pc, 0xD0, read); // BNE read
+
+    // Draw one tile per bit, most significant first
+    let tile_addr = 0x2000 + (TILE_ROW * 32 + TILE_COL) as u16;
+    emit(&mut prg, &mut pc, &[0xAD, 0x02, 0x20]); // LDA $2002 (reset address latch)
+    emit(&mut prg, &mut pc, &[0xA9, (tile_addr >> 8) as u8, 0x8D, 0x06, 0x20]); // LDA #hi; STA $2006
+    emit(&mut prg, &mut pc, &[0xA9, tile_addr as u8, 0x8D, 0x06, 0x20]); // LDA #lo; STA $2006
+    emit(&mut prg, &mut pc, &[0xA5, BUTTONS_ADDR as u8, 0x85, 0x00]); // LDA $FF; STA $00 (scratch copy)
+    emit(&mut prg, &mut pc, &[0xA2, 0x08]); // LDX #$08
+    let draw = pc;
+    emit(&mut prg, &mut pc, &[0xA9, TILE_RELEASED]); // LDA #released
+    emit(&mut prg, &mut pc, &[0x06, 0x00]); // ASL $00
+    emit(&mut prg, &mut pc, &[0x90, 0x02]); // BCC +2
+    emit(&mut prg, &mut pc, &[0xA9, TILE_PRESSED]); // LDA #pressed
+    emit(&mut prg, &mut pc, &[0x8D, 0x07, 0x20]); // STA $2007
+    emit(&mut prg, &mut pc, &[0xCA]); // DEX
+    branch_back(&mut prg, &mut pc, 0xD0, draw); // BNE draw
+
+    // PPUADDR writes clobber the scroll, so restore it
+    emit(&mut prg, &mut pc, &[0xA9, 0x00, 0x8D, 0x05, 0x20, 0x8D, 0x05, 0x20]); // LDA #$00; STA $2005 x2
+    emit(&mut prg, &mut pc, &[0xA9, 0x80, 0x8D, 0x00, 0x20]); // LDA #$80; STA $2000
+
+    emit(&mut prg, &mut pc, &[0x68, 0xAA, 0x68]); // PLA; TAX; PLA
+    let irq = 0x8000 + pc as u16;
+    emit(&mut prg, &mut pc, &[0x40]); // RTI (also used as the IRQ handler)
+
+    // Vectors
+    prg[0x3FFA..].copy_from_slice(&[nmi as u8, (nmi >> 8) as u8, 0x00, 0x80, irq as u8, (irq >> 8) as u8]);
+
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend_from_slice(&prg);
+    rom.extend_from_slice(&generate_chr());
+    rom
+}
+
+/// Tile 0 blank, tile 1 solid, tile 2 a one-pixel outline (all colour 1)
+fn generate_chr() -> Vec<u8> {
+    let mut chr = vec![0u8; 0x2000];
+    let solid = TILE_PRESSED as usize * 16;
+    chr[solid..solid + 8].fill(0xFF);
+    let outline = TILE_RELEASED as usize * 16;
+    chr[outline..outline + 8].copy_from_slice(&[0xFF, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0xFF]);
+    chr
+}
+
+fn main() -> io::Result<()> {
+    let mut file = File::create("input_test.nes")?;
+    file.write_all(&build_rom())?;
+
+    println!("Generated input_test.nes");
+    println!();
+    println!("Each frame the ROM reads controller 1 and:");
+    println!("  stores the buttons at ${:04X} (A = bit 7 ... Right = bit 0)", BUTTONS_ADDR);
+    println!("  draws A, B, Select, Start, Up, Down, Left, Right left to right");
+    println!("  (solid block = pressed, outline = released)");
+
+    Ok(())
+}
@@ -1,9 +1,10 @@
-/// Generate a PERFECT visual test ROM - no bugs this time!
+//! Generate a PERFECT visual test ROM - no bugs this time!
 
 use std::fs::File;
 use std::io::{self, Write};
 
-fn main() -> io::Result<()> {
+/// Assemble the ROM image
+pub fn build_rom() -> Vec<u8> {
     let mut prg = vec![0xEA; 0x4000]; // Fill with NOPs
     let chr = generate_chr();
     
@@ -100,18 +101,21 @@ fn main() -> io::Result<()> {
     let hang = pc;
     prg[pc] = 0x4C; pc += 1;
     prg[pc] = ((hang + 0x8000) & 0xFF) as u8; pc += 1;
-    prg[pc] = ((hang + 0x8000) >> 8) as u8; pc += 1;
+    prg[pc] = ((hang + 0x8000) >> 8) as u8;
     
     // Vectors
     prg[0x3FFC] = 0x00;
     prg[0x3FFD] = 0x80;
     
-    // Write ROM
-    let header = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend_from_slice(&prg);
+    rom.extend_from_slice(&chr);
+    rom
+}
+
+fn main() -> io::Result<()> {
     let mut file = File::create("perfect_visual.nes")?;
-    file.write_all(&header)?;
-    file.write_all(&prg)?;
-    file.write_all(&chr)?;
+    file.write_all(&build_rom())?;
     
     println!("Generated: perfect_visual.nes");
     Ok(())
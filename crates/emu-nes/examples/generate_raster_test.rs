@@ -0,0 +1,273 @@
+//! Generate raster effect test ROMs: three bands from mid-frame writes
+//!
+//! Nametable 0 is red on its left half and green on its right; nametable
+//! 1 is all blue. Each frame starts at X scroll 0 on nametable 0. Near
+//! scanline 64 the X scroll moves to 128, and a fixed delay later PPUCTRL
+//! switches the base nametable to 1, so the screen shows three bands:
+//!
+//! - top: red | green
+//! - middle: green | blue
+//! - bottom: blue | red
+//!
+//! Two ROMs find scanline 64 in different ways:
+//! - raster_test_sprite0.nes: polls for sprite 0 hit on a marker sprite
+//!   hidden behind the background (NROM)
+//! - raster_test_mmc3.nes: takes MMC3's scanline IRQ
+//!
+//! Usage: cargo run --example generate_raster_test -p emu-nes
+
+use nes_asm::{Assembler, InesBuilder};
+use std::fs::File;
+use std::io::{self, Write};
+
+/// How the ROM finds the scanline to split at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// Poll $2002 for sprite 0 hit
+    SpriteZero,
+    /// MMC3 scanline counter IRQ
+    Mmc3Irq,
+}
+
+/// Scanline the marker sprite (and so the sprite 0 hit) is on
+pub const MARKER_Y: u8 = 63;
+/// X of the marker sprite
+pub const MARKER_X: u8 = 32;
+/// Scanlines MMC3 counts before its IRQ
+pub const IRQ_LINES: u8 = 63;
+/// X scroll of the middle and bottom bands
+pub const SPLIT_SCROLL_X: u8 = 128;
+/// Outer iterations of the delay between the scroll and nametable
+/// writes, about 11 scanlines each
+pub const DELAY_LOOPS: u8 = 5;
+
+/// Backdrop, then the three band colours
+pub const PALETTE: [u8; 4] = [0x0F, 0x16, 0x2A, 0x12];
+/// Palette indices of the band colours
+pub const RED: u8 = PALETTE[1];
+pub const GREEN: u8 = PALETTE[2];
+pub const BLUE: u8 = PALETTE[3];
+
+fn main() -> io::Result<()> {
+    for (filename, trigger) in [
+        ("raster_test_sprite0.nes", Trigger::SpriteZero),
+        ("raster_test_mmc3.nes", Trigger::Mmc3Irq),
+    ] {
+        File::create(filename)?.write_all(&build_rom(trigger))?;
+        println!("Generated {}", filename);
+    }
+    Ok(())
+}
+
+/// Write `value` `count` times to $2007
+fn fill_vram(asm: &mut Assembler, label: &str, value: u8, count: u8) {
+    asm.lda_imm(value)
+        .ldx_imm(count)
+        .label(label)
+        .sta_abs(0x2007)
+        .dex()
+        .bne(label);
+}
+
+/// Point the PPU address at `addr`
+fn set_vram_addr(asm: &mut Assembler, addr: u16) {
+    asm.bit_abs(0x2002)
+        .lda_imm((addr >> 8) as u8)
+        .sta_abs(0x2006)
+        .lda_imm(addr as u8)
+        .sta_abs(0x2006);
+}
+
+/// Move sprites 1-63 below the screen, OAM address at sprite 1
+fn hide_sprites(asm: &mut Assembler) {
+    asm.lda_imm(0xFF)
+        .ldx_imm(252)
+        .label("hide_sprites")
+        .sta_abs(0x2004)
+        .dex()
+        .bne("hide_sprites");
+}
+
+/// Move the X scroll to the split, wait, then switch to nametable 1
+fn split(asm: &mut Assembler, ctrl: u8) {
+    asm.bit_abs(0x2002)
+        .lda_imm(SPLIT_SCROLL_X)
+        .sta_abs(0x2005)
+        .lda_imm(0)
+        .sta_abs(0x2005)
+        .ldx_imm(DELAY_LOOPS)
+        .label("delay_outer")
+        .ldy_imm(0)
+        .label("delay_inner")
+        .dey()
+        .bne("delay_inner")
+        .dex()
+        .bne("delay_outer")
+        .lda_imm(ctrl | 0x01)
+        .sta_abs(0x2000);
+}
+
+/// Build the iNES image of the raster test using `trigger`
+pub fn build_rom(trigger: Trigger) -> Vec<u8> {
+    // MMC3 keeps its last 8KB bank fixed at $E000, so the code lives there
+    let (base, size) = match trigger {
+        Trigger::SpriteZero => (0xC000, 0x4000),
+        Trigger::Mmc3Irq => (0xE000, 0x2000),
+    };
+    // NMI on; MMC3 counts the rises of A12 when sprites use $1000
+    let ctrl = match trigger {
+        Trigger::SpriteZero => 0x80,
+        Trigger::Mmc3Irq => 0x88,
+    };
+    let mut asm = Assembler::new(base, size).with_fill(0xEA);
+
+    asm.label("reset")
+        .sei()
+        .cld()
+        .ldx_imm(0xFF)
+        .txs()
+        .lda_imm(0x00)
+        .sta_abs(0x2000)
+        .sta_abs(0x2001)
+        .label("vblank1")
+        .bit_abs(0x2002)
+        .bpl("vblank1")
+        .label("vblank2")
+        .bit_abs(0x2002)
+        .bpl("vblank2");
+
+    if trigger == Trigger::Mmc3Irq {
+        // 2KB CHR banks 0-1 at $0000, 1KB banks 4-7 at $1000, vertical mirroring
+        for (register, bank) in [0, 2, 4, 5, 6, 7].into_iter().enumerate() {
+            asm.lda_imm(register as u8)
+                .sta_abs(0x8000)
+                .lda_imm(bank)
+                .sta_abs(0x8001);
+        }
+        asm.lda_imm(0x00).sta_abs(0xA000);
+    }
+
+    // Background palette 0 and sprite palette 0
+    for addr in [0x3F00, 0x3F10] {
+        set_vram_addr(&mut asm, addr);
+        for colour in PALETTE {
+            asm.lda_imm(colour).sta_abs(0x2007);
+        }
+    }
+
+    // Nametable 0: tile 1 on the left half of each row, tile 2 on the right
+    set_vram_addr(&mut asm, 0x2000);
+    asm.ldy_imm(30).label("nt0_row");
+    fill_vram(&mut asm, "nt0_left", 0x01, 16);
+    fill_vram(&mut asm, "nt0_right", 0x02, 16);
+    asm.dey().bne("nt0_row");
+    fill_vram(&mut asm, "nt0_attributes", 0x00, 64);
+
+    // Nametable 1: tile 3 everywhere
+    set_vram_addr(&mut asm, 0x2400);
+    asm.ldy_imm(30).label("nt1_row");
+    fill_vram(&mut asm, "nt1_columns", 0x03, 32);
+    asm.dey().bne("nt1_row");
+    fill_vram(&mut asm, "nt1_attributes", 0x00, 64);
+
+    // Sprite 0 is the marker, behind the background; the rest are off screen
+    asm.lda_imm(0x00).sta_abs(0x2003);
+    for byte in [MARKER_Y, 0x03, 0x20, MARKER_X] {
+        asm.lda_imm(byte).sta_abs(0x2004);
+    }
+    hide_sprites(&mut asm);
+
+    if trigger == Trigger::Mmc3Irq {
+        asm.lda_imm(IRQ_LINES)
+            .sta_abs(0xC000)
+            .sta_abs(0xC001)
+            .sta_abs(0xE001)
+            .cli();
+    }
+
+    asm.bit_abs(0x2002)
+        .lda_imm(0x00)
+        .sta_abs(0x2005)
+        .sta_abs(0x2005)
+        .lda_imm(ctrl)
+        .sta_abs(0x2000)
+        .lda_imm(0x1E)
+        .sta_abs(0x2001) // BG and sprites, including the left column
+        .label("idle")
+        .jmp("idle");
+
+    // Every frame starts unscrolled on nametable 0
+    asm.label("nmi")
+        .pha()
+        .txa()
+        .pha()
+        .tya()
+        .pha()
+        .lda_imm(ctrl)
+        .sta_abs(0x2000)
+        .bit_abs(0x2002)
+        .lda_imm(0x00)
+        .sta_abs(0x2005)
+        .sta_abs(0x2005);
+
+    match trigger {
+        Trigger::SpriteZero => {
+            // Last frame's hit stays set until the pre-render line
+            asm.label("wait_clear")
+                .bit_abs(0x2002)
+                .bvs("wait_clear")
+                .label("wait_hit")
+                .bit_abs(0x2002)
+                .bvc("wait_hit");
+            split(&mut asm, ctrl);
+        }
+        Trigger::Mmc3Irq => {
+            asm.sta_abs(0xC001).sta_abs(0xE001);
+        }
+    }
+    asm.pla().tay().pla().tax().pla().rti();
+
+    asm.label("irq")
+        .pha()
+        .txa()
+        .pha()
+        .tya()
+        .pha();
+    if trigger == Trigger::Mmc3Irq {
+        // Acknowledge, which also disables it until the next NMI
+        asm.sta_abs(0xE000);
+        split(&mut asm, ctrl);
+    }
+    asm.pla().tay().pla().tax().pla().rti();
+
+    asm.vectors("nmi", "reset", "irq");
+
+    let code = asm.assemble().expect("raster test assembles");
+    let (prg, mapper) = match trigger {
+        Trigger::SpriteZero => (code, 0),
+        Trigger::Mmc3Irq => {
+            let mut prg = vec![0xEA; 0x6000];
+            prg.extend_from_slice(&code);
+            (prg, 4)
+        }
+    };
+    InesBuilder::new(prg)
+        .chr(generate_chr())
+        .mapper(mapper)
+        .vertical_mirroring()
+        .build()
+        .expect("raster test has whole banks")
+}
+
+/// CHR with tiles 1-3 solid in colours 1-3, in both pattern tables
+fn generate_chr() -> Vec<u8> {
+    let mut chr = vec![0u8; 0x2000];
+    for table in [0x0000, 0x1000] {
+        for (tile, (low, high)) in [(1, (0xFF, 0x00)), (2, (0x00, 0xFF)), (3, (0xFF, 0xFF))] {
+            let addr = table + tile * 16;
+            chr[addr..addr + 8].fill(low);
+            chr[addr + 8..addr + 16].fill(high);
+        }
+    }
+    chr
+}
@@ -0,0 +1,70 @@
+use emu_nes::{InstructionHook, NesSystem};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Frames profiled
+const FRAMES: u32 = 600;
+
+/// Addresses listed
+const TOP: usize = 10;
+
+/// How many times an instruction has been executed from each address
+pub struct HotLoops {
+    counts: Box<[AtomicU64]>,
+}
+
+impl HotLoops {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            counts: (0..0x10000).map(|_| AtomicU64::new(0)).collect(),
+        })
+    }
+
+    /// A hook that counts every instruction against its address
+    pub fn hook(self: &Arc<Self>) -> InstructionHook {
+        let loops = self.clone();
+        Box::new(move |event| {
+            loops.counts[event.pc as usize].fetch_add(1, Ordering::Relaxed);
+        })
+    }
+
+    /// The `n` most executed addresses with their counts, busiest first and
+    /// lowest address first between equals
+    pub fn top(&self, n: usize) -> Vec<(u16, u64)> {
+        let mut counts: Vec<(u16, u64)> = self
+            .counts
+            .iter()
+            .enumerate()
+            .map(|(pc, count)| (pc as u16, count.load(Ordering::Relaxed)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts.truncate(n);
+        counts
+    }
+}
+
+fn main() -> io::Result<()> {
+    let path = std::env::args().nth(1).unwrap_or_else(|| "perfect_visual.nes".into());
+    let mut system = NesSystem::load(&path).map_err(|e| {
+        io::Error::other(format!(
+            "{:?} (run `cargo run --example generate_perfect_visual -p emu-nes` first)",
+            e
+        ))
+    })?;
+
+    let loops = HotLoops::new();
+    system.set_instruction_hook(Some(loops.hook()));
+    for _ in 0..FRAMES {
+        system.run_frame().map_err(|e| io::Error::other(format!("{:?}", e)))?;
+    }
+    system.set_instruction_hook(None);
+
+    println!("Top {} instructions over {} frames of {}\n", TOP, FRAMES, path);
+    for (pc, count) in loops.top(TOP) {
+        let line = &system.disassemble(pc, 1)[0];
+        println!("  ${:04X}  {:<14} {:>10}", pc, line.text, count);
+    }
+    Ok(())
+}
@@ -1,7 +1,7 @@
-/// Demonstration of CPU + NES Memory System working together
-/// 
-/// This shows the 6502 CPU running with the full NES memory map,
-/// including RAM mirroring and cartridge ROM access.
+//! Demonstration of CPU + NES Memory System working together
+//! 
+//! This shows the 6502 CPU running with the full NES memory map,
+//! including RAM mirroring and cartridge ROM access.
 
 use emu_core::Cpu;
 use emu_nes::{Cpu6502, NesMemory};
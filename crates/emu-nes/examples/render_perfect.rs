@@ -4,13 +4,13 @@ use std::io::{self, Write};
 
 fn main() -> io::Result<()> {
     let mut system = NesSystem::load("perfect_visual.nes")
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
+        .map_err(|e| io::Error::other(format!("{:?}", e)))?;
 
     println!("Running perfect visual test...\n");
     
     for _ in 0..10 {
         for _ in 0..29780 {
-            system.step().map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
+            system.step().map_err(|e| io::Error::other(format!("{:?}", e)))?;
         }
     }
 
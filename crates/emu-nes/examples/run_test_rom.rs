@@ -1,6 +1,6 @@
-/// Run the test ROM and verify it executes correctly
-/// 
-/// This demonstrates loading a real iNES ROM file and running it on the emulator.
+//! Run the test ROM and verify it executes correctly
+//! 
+//! This demonstrates loading a real iNES ROM file and running it on the emulator.
 
 use emu_nes::NesSystem;
 use std::path::Path;
@@ -1,10 +1,10 @@
-/// Scrolling Comparison Demo
-/// 
-/// Loads two ROMs side-by-side to demonstrate scrolling:
-/// 1. No scroll (X=0, Y=0)
-/// 2. With scroll (X=64, Y=32)
-/// 
-/// Usage: cargo run --example scrolling_compare -p emu-nes
+//! Scrolling Comparison Demo
+//! 
+//! Loads two ROMs side-by-side to demonstrate scrolling:
+//! 1. No scroll (X=0, Y=0)
+//! 2. With scroll (X=64, Y=32)
+//! 
+//! Usage: cargo run --example scrolling_compare -p emu-nes
 
 use emu_nes::NesSystem;
 use std::fs::File;
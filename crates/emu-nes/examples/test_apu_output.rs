@@ -1,7 +1,7 @@
 use emu_nes::Apu;
 
 fn main() {
-    let mut apu = Apu::new();
+    let mut apu = Apu::default();
     
     println!("Testing APU audio output...\n");
     
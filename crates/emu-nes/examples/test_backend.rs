@@ -1,4 +1,4 @@
-/// Quick test to verify NES emulator backend works with test ROM
+//! Quick test to verify NES emulator backend works with test ROM
 use emu_nes::system::NesSystem;
 use std::path::Path;
 
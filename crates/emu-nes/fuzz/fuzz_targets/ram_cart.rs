@@ -0,0 +1,32 @@
+//! Random instruction streams run from a RAM cart
+//!
+//! Every instruction has to take time, and nothing may panic. An opcode
+//! that jams the CPU ends the run.
+//!
+//! ```text
+//! cargo +nightly fuzz run ram_cart
+//! ```
+
+#![no_main]
+
+use emu_nes::NesSystem;
+use libfuzzer_sys::fuzz_target;
+
+/// Most instructions run per input
+const STEPS: usize = 10_000;
+
+fuzz_target!(|code: &[u8]| {
+    let mut system = NesSystem::with_ram_cart();
+    // Everything up to the vectors, which all point at the start
+    system.write_code(0x8000, &code[..code.len().min(0x7FFA)]).unwrap();
+    system.set_vectors(0x8000, 0x8000, 0x8000).unwrap();
+    system.reset();
+
+    for _ in 0..STEPS {
+        let before = system.cpu().cycles;
+        let Ok(cycles) = system.step() else {
+            break;
+        };
+        assert!(cycles > 0 && system.cpu().cycles > before, "no time passed at ${:04X}", system.cpu().pc);
+    }
+});
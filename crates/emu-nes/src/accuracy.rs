@@ -0,0 +1,36 @@
+//! Opt-in hardware behaviours
+//!
+//! Some real-console quirks break more than they fix when emulated by
+//! default, but are worth having on hand: for the few games and test ROMs
+//! that depend on them, and for homebrew developers checking their code
+//! would survive real hardware. Each is a flag in [`AccuracyFlags`], set
+//! with [`NesSystem::set_accuracy_flags`](crate::NesSystem::set_accuracy_flags).
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// Hardware behaviours that are off unless asked for
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct AccuracyFlags: u32 {
+        /// OAM is DRAM and fades without refresh: bytes left unwritten for
+        /// [`OAM_DECAY_FRAMES`](crate::ppu::OAM_DECAY_FRAMES) frames with
+        /// rendering off turn to garbage
+        const OAM_DECAY = 0b0000_0001;
+        /// Once sprite evaluation has found 8 sprites on a line, it looks
+        /// for a 9th by stepping through OAM diagonally (the next sprite's
+        /// Y, then the one after's tile, ...), so the overflow flag can be
+        /// set by a tile or missed for a real 9th sprite. Off, the flag is
+        /// set by any 9th sprite on the line.
+        const BUGGY_SPRITE_OVERFLOW = 0b0000_0010;
+        /// For about a frame after power-up or reset the PPU ignores writes
+        /// to PPUCTRL, PPUMASK, PPUSCROLL and PPUADDR, and PPUSTATUS comes
+        /// up with the vblank flag set. Games wait out the warm-up with two
+        /// vblank polls; code that doesn't breaks on a real console.
+        const PPU_WARMUP = 0b0000_0100;
+    }
+}
+
+impl AccuracyFlags {
+    /// Everything, for checking homebrew against real hardware
+    pub const HARDWARE_STRICT: Self = Self::all();
+}
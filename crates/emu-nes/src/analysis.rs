@@ -0,0 +1,68 @@
+//! Per-frame state snapshots for analysis
+//!
+//! [`MemoryObserver`](emu_core::MemoryObserver) sees every single memory
+//! access, which is far too fine-grained to feed to a model. An
+//! [`AnalysisSnapshot`] is instead a consistent copy of RAM, CPU registers,
+//! counters and controller input taken between instructions, cheap enough
+//! to take every frame. Register a [`SnapshotSink`] with
+//! [`NesSystem::add_snapshot_sink`](crate::NesSystem::add_snapshot_sink) to
+//! receive one at the end of every [`NesSystem::run_frame`](crate::NesSystem::run_frame).
+
+use emu_core::Button;
+
+/// Plain copy of the state an analysis host cares about
+///
+/// Contains no references, so it can be handed to another thread as is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnalysisSnapshot {
+    /// Frames completed by [`NesSystem::run_frame`](crate::NesSystem::run_frame)
+    pub frame: u64,
+    /// CPU cycles since power-on
+    pub cpu_cycles: u64,
+    /// Accumulator
+    pub a: u8,
+    /// X index register
+    pub x: u8,
+    /// Y index register
+    pub y: u8,
+    /// Stack pointer
+    pub sp: u8,
+    /// Program counter
+    pub pc: u16,
+    /// Processor status flags
+    pub status: u8,
+    /// Buttons held on controller 1
+    pub controller1: Button,
+    /// Buttons held on controller 2
+    pub controller2: Button,
+    /// The 2KB of internal RAM ($0000-$07FF)
+    pub ram: [u8; 0x0800],
+}
+
+impl AnalysisSnapshot {
+    /// Zero page ($0000-$00FF), where most games keep their hottest variables
+    pub fn zero_page(&self) -> &[u8] {
+        &self.ram[..0x100]
+    }
+
+    /// The hardware stack page ($0100-$01FF)
+    pub fn stack_page(&self) -> &[u8] {
+        &self.ram[0x100..0x200]
+    }
+}
+
+/// Receives an [`AnalysisSnapshot`] at every frame boundary
+///
+/// Called on the emulation thread, so implementations should hand the
+/// snapshot off (e.g. over a channel) rather than do heavy work inline.
+pub trait SnapshotSink: Send {
+    /// Called once per frame with the state at the end of that frame
+    fn on_snapshot(&mut self, snapshot: &AnalysisSnapshot);
+}
+
+/// Any `Send` closure taking a snapshot is a sink
+impl<F: FnMut(&AnalysisSnapshot) + Send> SnapshotSink for F {
+    fn on_snapshot(&mut self, snapshot: &AnalysisSnapshot) {
+        self(snapshot)
+    }
+}
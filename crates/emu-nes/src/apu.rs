@@ -1,22 +1,149 @@
-/// NES APU (Audio Processing Unit) Implementation
-/// 
-/// The APU generates audio for the NES. It has 5 channels:
-/// - 2 Pulse channels (square waves) for melody/harmony
-/// - 1 Triangle channel for bass lines
-/// - 1 Noise channel for percussion/sound effects
-/// - 1 DMC (Delta Modulation Channel) for playing samples
-///
-/// Audio output rate: ~1.789773 MHz (NTSC) / 2 = 894,886.5 Hz clock
-/// Sample rate: typically 44.1 kHz or 48 kHz for output
+//! NES APU (Audio Processing Unit) Implementation
+//! 
+//! The APU generates audio for the NES. It has 5 channels:
+//! - 2 Pulse channels (square waves) for melody/harmony
+//! - 1 Triangle channel for bass lines
+//! - 1 Noise channel for percussion/sound effects
+//! - 1 DMC (Delta Modulation Channel) for playing samples
+//!
+//! Audio output rate: ~1.789773 MHz (NTSC) / 2 = 894,886.5 Hz clock
+//! Sample rate: typically 44.1 kHz or 48 kHz for output
+//!
+//! APU Registers:
+//! $4000-$4003: Pulse 1
+//! $4004-$4007: Pulse 2
+//! $4008-$400B: Triangle
+//! $400C-$400F: Noise
+//! $4010-$4013: DMC
+//! $4015: Status
+//! $4017: Frame Counter
+//!
+//! A sound chip on the cartridge, such as the VRC6 ([`crate::vrc6`]), is
+//! clocked by the console, which hands its output to the APU to mix in
+//! each cycle.
+//!
+//! As on hardware, a period write only changes what a channel's timer
+//! reloads with: the timer keeps counting down from its current value, so
+//! a note slowed to a long period and then sped up still waits out the
+//! long count once. [`Apu::set_reduce_popping`] is an opt-in enhancement
+//! that cuts that wait short on the triangle and noise channels.
+
+use crate::apu_player::{CPU_CLOCK_HZ, PAL_CPU_CLOCK_HZ};
+use crate::apu_state::{ApuState, DmcState, FrameCounterState, NoiseState, PulseState, TriangleState};
+use crate::cartridge::Region;
+use crate::expansion_audio::ExpansionAudio;
+use crate::memory::IrqSource;
+use crate::sample_buffer::{Levels, SampleBuffer};
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+use emu_core::{EmulatorError, Result};
+use std::sync::OnceLock;
+use tracing::warn;
+
+/// CPU cycles from the start of a 4-step sequence to each of its steps
+/// (NESDev frame counter table, rounded up to whole CPU cycles)
+const FOUR_STEP_CYCLES: [u64; 4] = [7457, 14913, 22371, 29829];
+
+/// Length of a 4-step sequence in CPU cycles
+const FOUR_STEP_PERIOD: u64 = 29830;
+
+/// CPU cycles from the start of a 5-step sequence to each of its steps
+const FIVE_STEP_CYCLES: [u64; 5] = [7457, 14913, 22371, 29829, 37281];
+
+/// Length of a 5-step sequence in CPU cycles
+const FIVE_STEP_PERIOD: u64 = 37282;
+
+/// [`FOUR_STEP_CYCLES`] on a PAL console
+const PAL_FOUR_STEP_CYCLES: [u64; 4] = [8313, 16627, 24939, 33253];
+
+/// [`FOUR_STEP_PERIOD`] on a PAL console
+const PAL_FOUR_STEP_PERIOD: u64 = 33254;
+
+/// [`FIVE_STEP_CYCLES`] on a PAL console
+const PAL_FIVE_STEP_CYCLES: [u64; 5] = [8313, 16627, 24939, 33253, 41565];
+
+/// [`FIVE_STEP_PERIOD`] on a PAL console
+const PAL_FIVE_STEP_PERIOD: u64 = 41566;
+
+/// A sound channel, for muting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+    /// Channel n (0-7) of the sound chip on the cartridge, numbered the
+    /// way the chip does
+    Expansion(u8),
+}
+
+impl Channel {
+    /// Every channel but the cartridge's, which depend on the chip
+    pub const ALL: [Channel; 5] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+        Channel::Dmc,
+    ];
+    
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Pulse1 => "Pulse 1",
+            Channel::Pulse2 => "Pulse 2",
+            Channel::Triangle => "Triangle",
+            Channel::Noise => "Noise",
+            Channel::Dmc => "DMC",
+            Channel::Expansion(_) => "Expansion",
+        }
+    }
+    
+    /// Bit for the channel in `Apu::muted`, or `Apu::muted_expansion` for
+    /// the cartridge's; none past channel 7
+    fn mask(self) -> u8 {
+        let bit = match self {
+            Channel::Pulse1 => 0,
+            Channel::Pulse2 => 1,
+            Channel::Triangle => 2,
+            Channel::Noise => 3,
+            Channel::Dmc => 4,
+            Channel::Expansion(n) => n,
+        };
+        1u8.checked_shl(bit as u32).unwrap_or(0)
+    }
+}
+
+/// Stereo placement of the five channels
 ///
-/// APU Registers:
-/// $4000-$4003: Pulse 1
-/// $4004-$4007: Pulse 2
-/// $4008-$400B: Triangle
-/// $400C-$400F: Noise
-/// $4010-$4013: DMC
-/// $4015: Status
-/// $4017: Frame Counter
+/// The NES is mono; this spreads the channels across two speakers the way
+/// tracker exports do. Each pan runs from -1.0 (left only) through 0.0
+/// (centre, same level in both) to 1.0 (right only), in channel order
+/// pulse 1, pulse 2, triangle, noise, DMC. The default centres everything,
+/// which sounds exactly like the mono output.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StereoConfig {
+    pub pan: [f32; 5],
+}
+
+impl StereoConfig {
+    /// Pulse 1 panned left and pulse 2 right by `separation` (0.0 to 1.0),
+    /// everything else centred
+    pub fn with_separation(separation: f32) -> Self {
+        let separation = separation.clamp(0.0, 1.0);
+        Self {
+            pan: [-separation, separation, 0.0, 0.0, 0.0],
+        }
+    }
+    
+    /// Left and right gain for each channel
+    ///
+    /// A centred channel plays at full level in both speakers, and panning
+    /// turns the far side down rather than the near side up.
+    fn gains(&self) -> ([f32; 5], [f32; 5]) {
+        let pan = self.pan.map(|pan| pan.clamp(-1.0, 1.0));
+        (pan.map(|pan| 1.0 - pan.max(0.0)), pan.map(|pan| 1.0 + pan.min(0.0)))
+    }
+}
 
 /// Pulse channel (2 of these in the APU)
 /// Generates square waves with various duty cycles
@@ -153,6 +280,10 @@ impl PulseChannel {
     }
     
     /// Clock the envelope (called on quarter frame)
+    ///
+    /// The divider reloads with V when clocked at 0, so each level lasts
+    /// V+1 clocks. The clock that sees a restart only loads 15 and the
+    /// divider, as on hardware; the first decay comes V+1 clocks later.
     pub fn clock_envelope(&mut self) {
         if self.envelope_start {
             self.envelope_start = false;
@@ -209,6 +340,12 @@ impl PulseChannel {
     }
 }
 
+impl Default for PulseChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Triangle channel
 /// Generates triangle waves for bass lines
 #[derive(Debug, Clone)]
@@ -240,6 +377,10 @@ pub struct TriangleChannel {
     
     /// Sequence position (0-31)
     sequence_position: u8,
+    
+    /// Clamp the running timer on period writes ([`Apu::set_reduce_popping`]);
+    /// a setting, not state
+    reduce_popping: bool,
 }
 
 impl TriangleChannel {
@@ -254,6 +395,14 @@ impl TriangleChannel {
             linear_counter_reload: false,
             timer: 0,
             sequence_position: 0,
+            reduce_popping: false,
+        }
+    }
+    
+    /// Stop the running timer outlasting a shorter new period, if enabled
+    fn clamp_timer(&mut self) {
+        if self.reduce_popping {
+            self.timer = self.timer.min(self.timer_period);
         }
     }
     
@@ -266,11 +415,13 @@ impl TriangleChannel {
     /// Write to register 2 (timer low)
     pub fn write_reg2(&mut self, value: u8) {
         self.timer_period = (self.timer_period & 0x0700) | (value as u16);
+        self.clamp_timer();
     }
     
     /// Write to register 3 (length counter load, timer high)
     pub fn write_reg3(&mut self, value: u8) {
         self.timer_period = (self.timer_period & 0x00FF) | (((value & 0x07) as u16) << 8);
+        self.clamp_timer();
         
         if self.enabled {
             self.length_counter = LENGTH_TABLE[(value >> 3) as usize];
@@ -293,6 +444,9 @@ impl TriangleChannel {
     }
     
     /// Clock the timer
+    ///
+    /// Counts down from wherever it is and reloads with the period only on
+    /// reaching 0, so a new period is heard from the next step on.
     pub fn clock_timer(&mut self) {
         if self.timer == 0 {
             self.timer = self.timer_period;
@@ -343,6 +497,12 @@ impl TriangleChannel {
     }
 }
 
+impl Default for TriangleChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Noise channel
 /// Generates pseudo-random noise for percussion
 #[derive(Debug, Clone)]
@@ -383,6 +543,13 @@ pub struct NoiseChannel {
     
     /// Envelope start flag
     envelope_start: bool,
+    
+    /// Clamp the running timer on period writes ([`Apu::set_reduce_popping`]);
+    /// a setting, not state
+    reduce_popping: bool,
+    
+    /// Period table for the console's region, fixed at construction
+    periods: &'static [u16; 16],
 }
 
 impl NoiseChannel {
@@ -400,9 +567,19 @@ impl NoiseChannel {
             envelope_divider: 0,
             envelope_counter: 0,
             envelope_start: false,
+            reduce_popping: false,
+            periods: &NOISE_PERIOD_TABLE,
         }
     }
     
+    /// Timer reload value for the current period
+    ///
+    /// The timer is clocked every APU cycle (two CPU cycles) and shifts
+    /// when it runs out, so this gives a shift every period in the table.
+    fn reload_value(&self) -> u16 {
+        self.periods[self.timer_period as usize] / 2 - 1
+    }
+    
     /// Write to register 0 (envelope)
     pub fn write_reg0(&mut self, value: u8) {
         self.length_halt = (value & 0x20) != 0;
@@ -414,6 +591,9 @@ impl NoiseChannel {
     pub fn write_reg2(&mut self, value: u8) {
         self.mode = (value & 0x80) != 0;
         self.timer_period = value & 0x0F;
+        if self.reduce_popping {
+            self.timer = self.timer.min(self.reload_value());
+        }
     }
     
     /// Write to register 3 (length counter load)
@@ -438,13 +618,12 @@ impl NoiseChannel {
     }
     
     /// Clock the timer
+    ///
+    /// Like the triangle's, the timer takes up a new period on its next
+    /// reload, not when $400E is written.
     pub fn clock_timer(&mut self) {
-        const NOISE_PERIOD_TABLE: [u16; 16] = [
-            4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068
-        ];
-        
         if self.timer == 0 {
-            self.timer = NOISE_PERIOD_TABLE[self.timer_period as usize];
+            self.timer = self.reload_value();
             
             // Clock the shift register
             let feedback = if self.mode {
@@ -503,8 +682,36 @@ impl NoiseChannel {
     }
 }
 
+impl Default for NoiseChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads sample bytes for the DMC
+///
+/// The DMC fetches through the CPU bus, so on a console this is the
+/// cartridge with its current banking; any `FnMut(u16) -> u8` will do, which
+/// lets tests play samples from a plain array.
+pub trait DmcReader {
+    fn read(&mut self, addr: u16) -> u8;
+}
+
+impl<F: FnMut(u16) -> u8> DmcReader for F {
+    fn read(&mut self, addr: u16) -> u8 {
+        self(addr)
+    }
+}
+
 /// DMC (Delta Modulation Channel)
 /// Plays 1-bit delta-encoded samples
+///
+/// The memory reader fills a one-byte sample buffer whenever it's empty
+/// and bytes remain, walking the address up from $4012's start and
+/// wrapping from $FFFF to $8000. The output unit takes the buffer into
+/// its shift register every 8 bits and moves the level up or down by 2
+/// for each bit, staying put when the buffer was empty (silence). The CPU
+/// cycles the fetches steal aren't emulated.
 #[derive(Debug, Clone)]
 pub struct DmcChannel {
     /// Enable flag
@@ -513,6 +720,10 @@ pub struct DmcChannel {
     /// IRQ enable flag
     irq_enabled: bool,
     
+    /// IRQ raised when the last byte of a sample that doesn't loop is
+    /// fetched, until acknowledged
+    irq_flag: bool,
+    
     /// Loop flag
     loop_flag: bool,
     
@@ -537,6 +748,24 @@ pub struct DmcChannel {
     
     /// Current address
     current_address: u16,
+    
+    /// Current timer value, in APU cycles
+    timer: u16,
+    
+    /// Bits of the current byte still to play
+    shift_register: u8,
+    
+    /// Bits left in the output cycle (1-8)
+    bits_remaining: u8,
+    
+    /// Byte fetched and waiting for the output unit
+    sample_buffer: Option<u8>,
+    
+    /// The output cycle started with an empty buffer, so the level holds
+    silence: bool,
+    
+    /// Rate table for the console's region, fixed at construction
+    periods: &'static [u16; 16],
 }
 
 impl DmcChannel {
@@ -544,6 +773,7 @@ impl DmcChannel {
         Self {
             enabled: false,
             irq_enabled: false,
+            irq_flag: false,
             loop_flag: false,
             rate: 0,
             direct_load: 0,
@@ -552,12 +782,28 @@ impl DmcChannel {
             output_level: 0,
             bytes_remaining: 0,
             current_address: 0xC000,
+            timer: 0,
+            shift_register: 0,
+            bits_remaining: 8,
+            sample_buffer: None,
+            silence: true,
+            periods: &DMC_RATE_TABLE,
         }
     }
     
+    /// Timer reload value for the current rate
+    ///
+    /// Clocked every APU cycle like the noise timer, so the output unit
+    /// steps once per rate table period.
+    fn reload_value(&self) -> u16 {
+        self.periods[self.rate as usize] / 2 - 1
+    }
+    
     /// Write to register 0 (flags, rate)
     pub fn write_reg0(&mut self, value: u8) {
         self.irq_enabled = (value & 0x80) != 0;
+        // Disabling the IRQ also acknowledges it
+        self.irq_flag &= self.irq_enabled;
         self.loop_flag = (value & 0x40) != 0;
         self.rate = value & 0x0F;
     }
@@ -594,6 +840,63 @@ impl DmcChannel {
         self.bytes_remaining = self.sample_length;
     }
     
+    /// Fill the sample buffer if it's empty and the sample has bytes left
+    ///
+    /// Fetching the last byte restarts a looping sample, or raises the IRQ
+    /// if it's enabled.
+    pub fn fetch(&mut self, reader: &mut impl DmcReader) {
+        if self.sample_buffer.is_some() || self.bytes_remaining == 0 {
+            return;
+        }
+        self.sample_buffer = Some(reader.read(self.current_address));
+        self.current_address = match self.current_address {
+            0xFFFF => 0x8000,
+            addr => addr + 1,
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart_sample();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+    
+    /// Clock the timer (called every APU cycle), stepping the output unit
+    /// when it runs out
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.reload_value();
+        
+        if !self.silence {
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            // A new output cycle plays the buffered byte, or silence
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(byte) => {
+                    self.shift_register = byte;
+                    self.silence = false;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+    
     /// Get sample status (for $4015 reads)
     pub fn sample_status(&self) -> bool {
         self.bytes_remaining > 0
@@ -605,6 +908,32 @@ impl DmcChannel {
     }
 }
 
+impl Default for DmcChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// DMC periods in CPU cycles, by the rate index written to $4010
+const DMC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54
+];
+
+/// PAL DMC periods in CPU cycles
+const PAL_DMC_RATE_TABLE: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50
+];
+
+/// Noise periods in CPU cycles, by the 4-bit index written to $400E
+const NOISE_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068
+];
+
+/// [`NOISE_PERIOD_TABLE`] on a PAL console
+const PAL_NOISE_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778
+];
+
 /// Length counter lookup table
 const LENGTH_TABLE: [u8; 32] = [
     10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
@@ -612,6 +941,11 @@ const LENGTH_TABLE: [u8; 32] = [
 ];
 
 /// NES APU
+///
+/// The frame counter and noise timings differ between NTSC and PAL
+/// consoles; the region is picked at construction and kept through
+/// resets.
+#[derive(Debug, Clone)]
 pub struct Apu {
     /// Pulse channel 1
     pub pulse1: PulseChannel,
@@ -634,34 +968,207 @@ pub struct Apu {
     /// IRQ inhibit flag
     irq_inhibit: bool,
     
+    /// Frame IRQ raised by the 4-step sequence, until acknowledged
+    frame_irq: bool,
+    
     /// Current cycle count
     cycle: u64,
     
     /// Frame counter step
     frame_step: u8,
+    
+    /// Cycle the current frame counter sequence started on
+    sequence_start: u64,
+    
+    /// Channel placement for [`Apu::output_stereo`]; a setting, not state
+    stereo: StereoConfig,
+    
+    /// Muted channels, one bit per [`Channel`]; a setting, not state
+    muted: u8,
+    
+    /// Muted [`Channel::Expansion`] channels, bit n for channel n
+    muted_expansion: u8,
+    
+    /// Output of the cartridge's sound chip after the last clock, in
+    /// console pulse channels at full volume
+    cartridge_level: f32,
+    
+    /// TV system the timings are for, [`Region::Ntsc`] or [`Region::Pal`]
+    region: Region,
+    
+    /// Output since [`Apu::take_samples`] was last called
+    samples: SampleBuffer,
 }
 
 impl Apu {
-    pub fn new() -> Self {
-        Self {
+    /// Create an APU with `region`'s timings
+    ///
+    /// [`Region::Dual`] cartridges run on either console; they get NTSC's.
+    pub fn new(region: Region) -> Self {
+        let region = if region == Region::Pal { Region::Pal } else { Region::Ntsc };
+        let mut noise = NoiseChannel::new();
+        let mut dmc = DmcChannel::new();
+        if region == Region::Pal {
+            noise.periods = &PAL_NOISE_PERIOD_TABLE;
+            dmc.periods = &PAL_DMC_RATE_TABLE;
+        }
+        let mut apu = Self {
             pulse1: PulseChannel::new(),
             pulse2: PulseChannel::new(),
             triangle: TriangleChannel::new(),
-            noise: NoiseChannel::new(),
-            dmc: DmcChannel::new(),
+            noise,
+            dmc,
             frame_counter_mode: false,
             irq_inhibit: false,
+            frame_irq: false,
             cycle: 0,
             frame_step: 0,
+            sequence_start: 0,
+            stereo: StereoConfig::default(),
+            muted: 0,
+            muted_expansion: 0,
+            cartridge_level: 0.0,
+            region,
+            samples: SampleBuffer::new(0, Levels::default()),
+        };
+        apu.samples = SampleBuffer::new(0, apu.all_levels());
+        apu
+    }
+    
+    /// Switch to `region`'s timings, starting over from power-on
+    ///
+    /// The stereo, mute and popping settings are kept, as by [`Apu::reset`].
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.reset();
+    }
+    
+    /// TV system the APU's timings are for
+    pub fn region(&self) -> Region {
+        self.region
+    }
+    
+    /// Get the number of times the APU has been clocked since power-on
+    pub fn cycles(&self) -> u64 {
+        self.cycle
+    }
+    
+    /// CPU cycles a second on the console the timings are for
+    pub fn clock_hz(&self) -> u32 {
+        match self.region {
+            Region::Pal => PAL_CPU_CLOCK_HZ,
+            _ => CPU_CLOCK_HZ,
         }
     }
     
-    /// Reset the APU
+    /// Get the frame counter step that will run next (0-3, or 0-4 in 5-step mode)
+    pub fn frame_sequencer_step(&self) -> u8 {
+        self.frame_step
+    }
+    
+    /// Get the cycle (as counted by [`Apu::cycles`]) the next frame counter step runs on
+    pub fn next_sequencer_cycle(&self) -> u64 {
+        let offsets: &[u64] = match (self.region, self.frame_counter_mode) {
+            (Region::Pal, true) => &PAL_FIVE_STEP_CYCLES,
+            (Region::Pal, false) => &PAL_FOUR_STEP_CYCLES,
+            (_, true) => &FIVE_STEP_CYCLES,
+            (_, false) => &FOUR_STEP_CYCLES,
+        };
+        self.sequence_start + offsets[self.frame_step as usize]
+    }
+    
+    /// Length of a frame counter sequence in the current mode, in CPU cycles
+    fn sequence_period(&self) -> u64 {
+        match (self.region, self.frame_counter_mode) {
+            (Region::Pal, true) => PAL_FIVE_STEP_PERIOD,
+            (Region::Pal, false) => PAL_FOUR_STEP_PERIOD,
+            (_, true) => FIVE_STEP_PERIOD,
+            (_, false) => FOUR_STEP_PERIOD,
+        }
+    }
+    
+    /// Return the APU to its power-on state, keeping the stereo, mute and
+    /// popping settings
+    ///
+    /// This is a power cycle; the reset button is [`Apu::soft_reset`].
     pub fn reset(&mut self) {
-        *self = Self::new();
+        let reduce_popping = self.reduce_popping();
+        *self = Self {
+            stereo: self.stereo,
+            muted: self.muted,
+            muted_expansion: self.muted_expansion,
+            ..Self::new(self.region)
+        };
+        self.set_reduce_popping(reduce_popping);
+    }
+    
+    /// What the reset button does to the APU
+    ///
+    /// As on hardware, $4015 is cleared, which silences every channel and
+    /// acknowledges the DMC IRQ, and the frame IRQ is dropped. Everything
+    /// else carries on: channel registers and envelopes, the frame
+    /// counter's mode and phase, and the cycle count.
+    pub fn soft_reset(&mut self) {
+        self.write_register(0x4015, 0x00);
+        self.frame_irq = false;
+    }
+    
+    /// Check whether a channel is muted
+    pub fn is_muted(&self, channel: Channel) -> bool {
+        let muted = match channel {
+            Channel::Expansion(_) => self.muted_expansion,
+            _ => self.muted,
+        };
+        muted & channel.mask() != 0
+    }
+    
+    /// Mute or unmute a channel in the output
+    ///
+    /// The channel keeps running (and reporting its length counter in
+    /// $4015); it just isn't heard.
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        let bits = match channel {
+            Channel::Expansion(_) => &mut self.muted_expansion,
+            _ => &mut self.muted,
+        };
+        if muted {
+            *bits |= channel.mask();
+        } else {
+            *bits &= !channel.mask();
+        }
+    }
+    
+    /// Check whether period writes clamp the triangle and noise timers
+    pub fn reduce_popping(&self) -> bool {
+        self.triangle.reduce_popping
+    }
+    
+    /// Clamp the triangle and noise timers to the new period on period writes (off by default)
+    ///
+    /// Not hardware behaviour: a game that lengthens a period and then
+    /// shortens it (fast arpeggios do) leaves the channel counting out up
+    /// to 2047 cycles of the old one, holding its output still. That's
+    /// accurate, but heard as pops; with this on, the new period takes
+    /// effect straight away.
+    pub fn set_reduce_popping(&mut self, enabled: bool) {
+        self.triangle.reduce_popping = enabled;
+        self.noise.reduce_popping = enabled;
+    }
+    
+    /// Get the stereo placement used by [`Apu::output_stereo`]
+    pub fn stereo_config(&self) -> StereoConfig {
+        self.stereo
+    }
+    
+    /// Set the stereo placement used by [`Apu::output_stereo`]
+    pub fn set_stereo_config(&mut self, config: StereoConfig) {
+        self.stereo = config;
     }
     
     /// Write to APU register
+    ///
+    /// Takes $4000-$4013, $4015 and $4017, plus the VRC6 registers once
+    /// it's attached. Anything else is logged as a warning and ignored.
     pub fn write_register(&mut self, addr: u16, value: u8) {
         match addr {
             // Pulse 1
@@ -681,6 +1188,9 @@ impl Apu {
             0x400A => self.triangle.write_reg2(value),
             0x400B => self.triangle.write_reg3(value),
             
+            // Unused: the triangle and noise have no second register
+            0x4009 | 0x400D => {}
+            
             // Noise
             0x400C => self.noise.write_reg0(value),
             0x400E => self.noise.write_reg2(value),
@@ -699,15 +1209,19 @@ impl Apu {
                 self.triangle.set_enabled((value & 0x04) != 0);
                 self.noise.set_enabled((value & 0x08) != 0);
                 self.dmc.set_enabled((value & 0x10) != 0);
+                self.dmc.irq_flag = false;
             }
             
             // Frame Counter
             0x4017 => {
                 self.frame_counter_mode = (value & 0x80) != 0;
                 self.irq_inhibit = (value & 0x40) != 0;
+                self.frame_irq &= !self.irq_inhibit;
                 
-                // Reset frame counter
+                // Restart the sequence. (Hardware waits 3-4 CPU cycles
+                // first; that delay isn't modelled.)
                 self.frame_step = 0;
+                self.sequence_start = self.cycle;
                 
                 // If 5-step mode, clock immediately
                 if self.frame_counter_mode {
@@ -716,76 +1230,149 @@ impl Apu {
                 }
             }
             
-            _ => {} // Ignore writes to other addresses
+            // Someone else's register: NesMemory routes $4014 and $4016
+            // elsewhere (see io_write_owner)
+            _ => warn!(target: "emu_nes::apu", "write to ${:04X}, which isn't an APU register", addr),
         }
     }
     
     /// Read from APU register
     pub fn read_register(&self, addr: u16) -> u8 {
         match addr {
-            0x4015 => {
-                let mut status = 0;
-                
-                if self.pulse1.length_counter_status() {
-                    status |= 0x01;
-                }
-                if self.pulse2.length_counter_status() {
-                    status |= 0x02;
-                }
-                if self.triangle.length_counter_status() {
-                    status |= 0x04;
-                }
-                if self.noise.length_counter_status() {
-                    status |= 0x08;
-                }
-                if self.dmc.sample_status() {
-                    status |= 0x10;
-                }
-                
-                // TODO: DMC IRQ (bit 7), Frame IRQ (bit 6)
-                
-                status
-            }
+            0x4015 => self.dump_state().status(),
             _ => 0, // Open bus for other reads
         }
     }
     
-    /// Clock the APU (called every CPU cycle)
+    /// Read $4015 as the CPU does, acknowledging the frame IRQ
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.read_register(0x4015);
+        self.frame_irq = false;
+        status
+    }
+    
+    /// Sources currently holding the IRQ line
+    ///
+    /// Each flag stays up until the game acknowledges it: the frame IRQ by
+    /// reading $4015 or inhibiting it through $4017, the DMC IRQ by writing
+    /// $4015 or clearing its enable bit in $4010.
+    pub fn irq_lines(&self) -> IrqSource {
+        let mut lines = IrqSource::empty();
+        lines.set(IrqSource::FRAME, self.frame_irq);
+        lines.set(IrqSource::DMC, self.dmc.irq_flag);
+        lines
+    }
+    
+    /// Copy out the state of every channel and the frame counter
+    pub fn dump_state(&self) -> ApuState {
+        ApuState {
+            version: ApuState::VERSION,
+            pulse1: self.pulse1.state(),
+            pulse2: self.pulse2.state(),
+            triangle: self.triangle.state(),
+            noise: self.noise.state(),
+            dmc: self.dmc.state(),
+            frame_counter: FrameCounterState {
+                five_step: self.frame_counter_mode,
+                irq_inhibit: self.irq_inhibit,
+                frame_irq: self.frame_irq,
+                cycle: self.cycle,
+                step: self.frame_step,
+                sequence_start: self.sequence_start,
+            },
+        }
+    }
+    
+    /// Put back a state from [`Apu::dump_state`]
+    ///
+    /// Every counter, divider and sequence position is taken as it was, so
+    /// nothing restarts: the output carries on sample for sample from where
+    /// the state was dumped. Settings, the region and VRC6 audio are kept;
+    /// samples not yet taken are dropped.
+    /// A state from another version, or with a value out of range, is
+    /// refused and the APU left as it was.
+    pub fn restore_state(&mut self, state: &ApuState) -> Result<()> {
+        check_state(state)?;
+        self.pulse1.restore(&state.pulse1);
+        self.pulse2.restore(&state.pulse2);
+        self.triangle.restore(&state.triangle);
+        self.noise.restore(&state.noise);
+        self.dmc.restore(&state.dmc);
+        let frame_counter = &state.frame_counter;
+        self.frame_counter_mode = frame_counter.five_step;
+        self.irq_inhibit = frame_counter.irq_inhibit;
+        self.frame_irq = frame_counter.frame_irq;
+        self.cycle = frame_counter.cycle;
+        self.frame_step = frame_counter.step;
+        self.sequence_start = frame_counter.sequence_start;
+        self.samples = SampleBuffer::new(self.cycle, self.all_levels());
+        Ok(())
+    }
+    
+    /// Clock the cartridge's sound chip (called every CPU cycle, before
+    /// the APU itself), taking its output to mix in
+    ///
+    /// With no chip there is nothing to mix in, so one that was just
+    /// detached falls silent.
+    pub(crate) fn clock_expansion(&mut self, chip: Option<&mut (dyn ExpansionAudio + 'static)>) {
+        self.cartridge_level = chip.map_or(0.0, |chip| {
+            chip.clock();
+            chip.output_muted(self.muted_expansion) * chip.mix_level()
+        });
+    }
+    
+    /// Clock the APU (called every CPU cycle) with nothing behind the DMC
+    ///
+    /// Its sample fetches read $FF, as from an empty cartridge slot; a
+    /// console clocks through [`Apu::clock_with`] instead.
     pub fn clock(&mut self) {
+        self.clock_with(&mut |_| 0xFF);
+    }
+    
+    /// Clock the APU (called every CPU cycle), fetching DMC sample bytes
+    /// through `reader`
+    pub fn clock_with(&mut self, reader: &mut impl DmcReader) {
         // The APU runs at half CPU speed for most things
-        if self.cycle % 2 == 0 {
+        if self.cycle.is_multiple_of(2) {
             self.pulse1.clock_timer();
             self.pulse2.clock_timer();
             self.noise.clock_timer();
+            self.dmc.clock_timer();
         }
+        self.dmc.fetch(reader);
         
         // Triangle runs at CPU speed
         self.triangle.clock_timer();
         
-        // Frame counter (4-step mode: ~120 Hz, 5-step mode: ~96 Hz)
-        // Simplified implementation - clock every ~7457 cycles
-        if self.cycle % 7457 == 0 {
+        // Frame counter (4-step mode: ~240 Hz quarter / ~120 Hz half frames,
+        // 5-step mode: ~192 Hz / ~96 Hz)
+        if self.cycle >= self.next_sequencer_cycle() {
             self.clock_frame_counter();
         }
         
+        self.samples.record(self.cycle, self.all_levels());
         self.cycle += 1;
     }
     
     /// Clock the frame counter
     fn clock_frame_counter(&mut self) {
         if self.frame_counter_mode {
-            // 5-step mode
+            // 5-step mode: the fourth step does nothing
             match self.frame_step {
                 0 | 2 => self.clock_quarter_frame(),
-                1 | 3 => {
+                1 | 4 => {
                     self.clock_quarter_frame();
                     self.clock_half_frame();
                 }
-                4 => {} // Do nothing
+                3 => {} // Do nothing
                 _ => unreachable!(),
             }
             
-            self.frame_step = (self.frame_step + 1) % 5;
+            self.frame_step += 1;
+            if self.frame_step == 5 {
+                self.frame_step = 0;
+                self.sequence_start += self.sequence_period();
+            }
         } else {
             // 4-step mode
             match self.frame_step {
@@ -797,12 +1384,16 @@ impl Apu {
                 3 => {
                     self.clock_quarter_frame();
                     self.clock_half_frame();
-                    // TODO: Set frame IRQ if not inhibited
+                    self.frame_irq |= !self.irq_inhibit;
                 }
                 _ => unreachable!(),
             }
             
-            self.frame_step = (self.frame_step + 1) % 4;
+            self.frame_step += 1;
+            if self.frame_step == 4 {
+                self.frame_step = 0;
+                self.sequence_start += self.sequence_period();
+            }
         }
     }
     
@@ -823,31 +1414,361 @@ impl Apu {
         // TODO: Clock sweep units
     }
     
+    /// Output levels of the five console channels, muted ones as 0
+    fn levels(&self) -> [u8; 5] {
+        let levels = [
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        ];
+        std::array::from_fn(|i| if self.is_muted(Channel::ALL[i]) { 0 } else { levels[i] })
+    }
+    
+    /// [`Apu::levels`] and the cartridge's sound chip
+    fn all_levels(&self) -> Levels {
+        Levels {
+            channels: self.levels(),
+            cartridge: self.cartridge_level,
+        }
+    }
+    
     /// Get mixed audio output sample
     /// Returns a float in range [-1.0, 1.0]
+    ///
+    /// Expansion audio can push the sum past the top of the range, where
+    /// it clips.
     pub fn output(&self) -> f32 {
-        // Get individual channel outputs
-        let pulse1 = self.pulse1.output() as f32;
-        let pulse2 = self.pulse2.output() as f32;
-        let triangle = self.triangle.output() as f32;
-        let noise = self.noise.output() as f32;
-        let dmc = self.dmc.output() as f32;
-        
-        // Non-linear mixing (as per NESDev wiki)
-        let pulse_out = if pulse1 + pulse2 > 0.0 {
-            95.88 / ((8128.0 / (pulse1 + pulse2)) + 100.0)
-        } else {
-            0.0
-        };
-        
-        let tnd_out = if triangle + noise + dmc > 0.0 {
-            159.79 / ((1.0 / (triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0)) + 100.0)
-        } else {
-            0.0
-        };
-        
-        // Mix and normalize to [-1.0, 1.0]
-        (pulse_out + tnd_out) * 2.0 - 1.0
+        mix_mono(self.all_levels())
+    }
+    
+    /// Get the (left, right) audio output, each in [-1.0, 1.0]
+    ///
+    /// Each side goes through the same non-linear mixer as [`Apu::output`]
+    /// with the channels scaled by their gain for that side, so centred
+    /// channels give exactly the mono sample.
+    ///
+    /// Expansion audio is centred.
+    pub fn output_stereo(&self) -> (f32, f32) {
+        mix_stereo(self.all_levels(), self.stereo.gains())
+    }
+    
+    /// Append the output since the last call as samples at `output_rate` Hz
+    ///
+    /// Each sample is the output averaged over exactly its share of the
+    /// cycles clocked, so the samples only depend on how many cycles ran,
+    /// not on how they were split up between calls. A sample whose cycles
+    /// haven't all run yet comes in a later call. Output nobody takes is
+    /// kept for a few frames at most.
+    ///
+    /// # Panics
+    ///
+    /// If `output_rate` is zero or above [`Apu::clock_hz`].
+    pub fn take_samples(&mut self, output_rate: u32, out: &mut Vec<f32>) {
+        let (clock, end) = (self.clock_hz(), self.cycle);
+        self.samples.take(output_rate, clock, end, |levels| [mix_mono(levels)], |[sample]| out.push(sample));
+    }
+    
+    /// [`Apu::take_samples`] as (left, right) pairs, placed by the stereo
+    /// config in force when they're taken
+    pub fn take_samples_stereo(&mut self, output_rate: u32, out: &mut Vec<(f32, f32)>) {
+        let (clock, end, gains) = (self.clock_hz(), self.cycle, self.stereo.gains());
+        self.samples.take(
+            output_rate,
+            clock,
+            end,
+            |levels| {
+                let (left, right) = mix_stereo(levels, gains);
+                [left, right]
+            },
+            |[left, right]| out.push((left, right)),
+        );
+    }
+    
+    /// Output not yet taken, and where taking it has got to
+    pub(crate) fn sample_buffer(&self) -> &SampleBuffer {
+        &self.samples
+    }
+    
+    /// Put back what [`Apu::sample_buffer`] returned, after rewinding to
+    /// the cycle it was taken on
+    pub(crate) fn set_sample_buffer(&mut self, samples: SampleBuffer) {
+        self.samples = samples;
+    }
+}
+
+/// [`Apu::output`] for `levels`
+fn mix_mono(levels: Levels) -> f32 {
+    let [pulse1, pulse2, triangle, noise, dmc] = levels.channels;
+    let mixed = mix(pulse1, pulse2, triangle, noise, dmc);
+    (mixed + 2.0 * expansion_level(levels)).min(1.0)
+}
+
+/// [`Apu::output_stereo`] for `levels`, with the left and right channel gains
+fn mix_stereo(levels: Levels, (left, right): ([f32; 5], [f32; 5])) -> (f32, f32) {
+    let expansion = expansion_level(levels);
+    let levels = levels.channels.map(f32::from);
+    let side = |gains: [f32; 5]| {
+        let [pulse1, pulse2, triangle, noise, dmc] = std::array::from_fn(|i| levels[i] * gains[i]);
+        let console = pulse_level(pulse1 + pulse2) + tnd_level(3.0 * triangle + 2.0 * noise + dmc);
+        ((console + expansion) * 2.0 - 1.0).min(1.0)
+    };
+    (side(left), side(right))
+}
+
+/// Mixer lookup tables (NESDev "lookup table" mixer)
+struct MixerTables {
+    /// Indexed by pulse1 + pulse2 (0-30)
+    pulse: [f32; 31],
+    /// Indexed by 3 * triangle + 2 * noise + dmc (0-202)
+    tnd: [f32; 203],
+}
+
+static MIXER_TABLES: OnceLock<MixerTables> = OnceLock::new();
+
+fn mixer_tables() -> &'static MixerTables {
+    MIXER_TABLES.get_or_init(|| {
+        MixerTables {
+            pulse: std::array::from_fn(|n| pulse_level(n as f32)),
+            tnd: std::array::from_fn(|n| tnd_level(n as f32)),
+        }
+    })
+}
+
+/// Pulse group output for pulse1 + pulse2 = `n`
+fn pulse_level(n: f32) -> f32 {
+    if n > 0.0 {
+        95.88 / (8128.0 / n + 100.0)
+    } else {
+        0.0
+    }
+}
+
+/// Triangle/noise/DMC group output for 3 * triangle + 2 * noise + dmc = `n`
+///
+/// The tnd group isn't a function of one sum, so this uses the documented
+/// linear approximation (within ~0.013 of the formula).
+fn tnd_level(n: f32) -> f32 {
+    if n > 0.0 {
+        163.67 / (24329.0 / n + 100.0)
+    } else {
+        0.0
+    }
+}
+
+/// Output of the cartridge's sound chip for `levels`
+///
+/// Mixed linearly, at the chip's level relative to a console pulse at
+/// full volume.
+fn expansion_level(levels: Levels) -> f32 {
+    levels.cartridge * pulse_level(15.0)
+}
+
+/// Mix raw channel outputs into a sample in [-1.0, 1.0]
+///
+/// Non-linear mixing (as per NESDev wiki), using lookup tables.
+pub fn mix(pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let tables = mixer_tables();
+    let pulse_out = tables.pulse[(pulse1 as usize + pulse2 as usize).min(30)];
+    let tnd_index = 3 * triangle as usize + 2 * noise as usize + dmc as usize;
+    let tnd_out = tables.tnd[tnd_index.min(202)];
+    
+    // Mix and normalize to [-1.0, 1.0]
+    (pulse_out + tnd_out) * 2.0 - 1.0
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new(Region::Ntsc)
+    }
+}
+
+impl PulseChannel {
+    fn state(&self) -> PulseState {
+        PulseState {
+            enabled: self.enabled,
+            duty: self.duty,
+            length_halt: self.length_halt,
+            constant_volume: self.constant_volume,
+            volume: self.volume,
+            sweep_enabled: self.sweep_enabled,
+            sweep_period: self.sweep_period,
+            sweep_negate: self.sweep_negate,
+            sweep_shift: self.sweep_shift,
+            timer_period: self.timer_period,
+            length_counter: self.length_counter,
+            timer: self.timer,
+            duty_position: self.duty_position,
+            envelope_divider: self.envelope_divider,
+            envelope_counter: self.envelope_counter,
+            envelope_start: self.envelope_start,
+        }
+    }
+    
+    fn restore(&mut self, state: &PulseState) {
+        self.enabled = state.enabled;
+        self.duty = state.duty;
+        self.length_halt = state.length_halt;
+        self.constant_volume = state.constant_volume;
+        self.volume = state.volume;
+        self.sweep_enabled = state.sweep_enabled;
+        self.sweep_period = state.sweep_period;
+        self.sweep_negate = state.sweep_negate;
+        self.sweep_shift = state.sweep_shift;
+        self.timer_period = state.timer_period;
+        self.length_counter = state.length_counter;
+        self.timer = state.timer;
+        self.duty_position = state.duty_position;
+        self.envelope_divider = state.envelope_divider;
+        self.envelope_counter = state.envelope_counter;
+        self.envelope_start = state.envelope_start;
+    }
+}
+
+impl TriangleChannel {
+    fn state(&self) -> TriangleState {
+        TriangleState {
+            enabled: self.enabled,
+            length_halt: self.length_halt,
+            linear_counter_load: self.linear_counter_load,
+            timer_period: self.timer_period,
+            length_counter: self.length_counter,
+            linear_counter: self.linear_counter,
+            linear_counter_reload: self.linear_counter_reload,
+            timer: self.timer,
+            sequence_position: self.sequence_position,
+        }
+    }
+    
+    fn restore(&mut self, state: &TriangleState) {
+        self.enabled = state.enabled;
+        self.length_halt = state.length_halt;
+        self.linear_counter_load = state.linear_counter_load;
+        self.timer_period = state.timer_period;
+        self.length_counter = state.length_counter;
+        self.linear_counter = state.linear_counter;
+        self.linear_counter_reload = state.linear_counter_reload;
+        self.timer = state.timer;
+        self.sequence_position = state.sequence_position;
+    }
+}
+
+impl NoiseChannel {
+    fn state(&self) -> NoiseState {
+        NoiseState {
+            enabled: self.enabled,
+            length_halt: self.length_halt,
+            constant_volume: self.constant_volume,
+            volume: self.volume,
+            mode: self.mode,
+            timer_period: self.timer_period,
+            length_counter: self.length_counter,
+            timer: self.timer,
+            shift_register: self.shift_register,
+            envelope_divider: self.envelope_divider,
+            envelope_counter: self.envelope_counter,
+            envelope_start: self.envelope_start,
+        }
+    }
+    
+    /// Take on `state`, keeping the region's period table
+    fn restore(&mut self, state: &NoiseState) {
+        self.enabled = state.enabled;
+        self.length_halt = state.length_halt;
+        self.constant_volume = state.constant_volume;
+        self.volume = state.volume;
+        self.mode = state.mode;
+        self.timer_period = state.timer_period;
+        self.length_counter = state.length_counter;
+        self.timer = state.timer;
+        self.shift_register = state.shift_register;
+        self.envelope_divider = state.envelope_divider;
+        self.envelope_counter = state.envelope_counter;
+        self.envelope_start = state.envelope_start;
+    }
+}
+
+impl DmcChannel {
+    fn state(&self) -> DmcState {
+        DmcState {
+            enabled: self.enabled,
+            irq_enabled: self.irq_enabled,
+            irq_flag: self.irq_flag,
+            loop_flag: self.loop_flag,
+            rate: self.rate,
+            direct_load: self.direct_load,
+            sample_address: self.sample_address,
+            sample_length: self.sample_length,
+            output_level: self.output_level,
+            bytes_remaining: self.bytes_remaining,
+            current_address: self.current_address,
+            timer: self.timer,
+            shift_register: self.shift_register,
+            bits_remaining: self.bits_remaining,
+            sample_buffer: self.sample_buffer,
+            silence: self.silence,
+        }
+    }
+    
+    /// Take on `state`, keeping the region's rate table
+    fn restore(&mut self, state: &DmcState) {
+        self.enabled = state.enabled;
+        self.irq_enabled = state.irq_enabled;
+        self.irq_flag = state.irq_flag;
+        self.loop_flag = state.loop_flag;
+        self.rate = state.rate;
+        self.direct_load = state.direct_load;
+        self.sample_address = state.sample_address;
+        self.sample_length = state.sample_length;
+        self.output_level = state.output_level;
+        self.bytes_remaining = state.bytes_remaining;
+        self.current_address = state.current_address;
+        self.timer = state.timer;
+        self.shift_register = state.shift_register;
+        self.bits_remaining = state.bits_remaining;
+        self.sample_buffer = state.sample_buffer;
+        self.silence = state.silence;
+    }
+}
+
+/// Check the values `state` indexes tables or sequences with, so a bad
+/// one is refused instead of panicking later
+fn check_state(state: &ApuState) -> Result<()> {
+    let invalid = |what: String| Err(EmulatorError::InvalidSaveState(what));
+    if state.version != ApuState::VERSION {
+        return invalid(format!("APU state version {}, expected {}", state.version, ApuState::VERSION));
+    }
+    for pulse in [&state.pulse1, &state.pulse2] {
+        if pulse.duty > 3 || pulse.duty_position > 7 {
+            return invalid(format!("pulse duty {} at step {}", pulse.duty, pulse.duty_position));
+        }
+    }
+    if state.triangle.sequence_position > 31 {
+        return invalid(format!("triangle step {}", state.triangle.sequence_position));
+    }
+    if state.noise.timer_period > 15 {
+        return invalid(format!("noise period {}", state.noise.timer_period));
+    }
+    if state.dmc.rate > 15 || !(1..=8).contains(&state.dmc.bits_remaining) {
+        return invalid(format!("DMC rate {} with {} bits left", state.dmc.rate, state.dmc.bits_remaining));
+    }
+    let steps = if state.frame_counter.five_step { 5 } else { 4 };
+    if state.frame_counter.step >= steps {
+        return invalid(format!("frame counter step {}", state.frame_counter.step));
+    }
+    Ok(())
+}
+
+impl Snapshot for Apu {
+    fn save(&self, w: &mut StateWriter) {
+        self.dump_state().save(w);
+    }
+    
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        let mut state = self.dump_state();
+        state.load(r)?;
+        self.restore_state(&state)
     }
 }
 
@@ -857,14 +1778,14 @@ mod tests {
     
     #[test]
     fn test_apu_creation() {
-        let apu = Apu::new();
+        let apu = Apu::default();
         assert_eq!(apu.cycle, 0);
         assert!(!apu.pulse1.enabled);
     }
     
     #[test]
     fn test_enable_channels() {
-        let mut apu = Apu::new();
+        let mut apu = Apu::default();
         apu.write_register(0x4015, 0x0F); // Enable all channels except DMC
         
         assert!(apu.pulse1.enabled);
@@ -874,6 +1795,44 @@ mod tests {
         assert!(!apu.dmc.enabled);
     }
     
+    #[test]
+    fn test_irq_sources_acknowledged_independently() {
+        let mut apu = Apu::default();
+        while apu.irq_lines().is_empty() {
+            apu.clock();
+        }
+        assert_eq!(apu.cycles(), FOUR_STEP_CYCLES[3] + 1);
+        apu.dmc.write_reg0(0x80);
+        apu.dmc.irq_flag = true;
+        assert_eq!(apu.irq_lines(), IrqSource::FRAME | IrqSource::DMC);
+        assert_eq!(apu.read_register(0x4015) & 0xC0, 0xC0);
+        
+        // Acknowledging the frame IRQ leaves the DMC one holding the line
+        assert_eq!(apu.read_status() & 0xC0, 0xC0);
+        assert_eq!(apu.irq_lines(), IrqSource::DMC);
+        assert_eq!(apu.read_status() & 0xC0, 0x80);
+        apu.write_register(0x4015, 0x00);
+        assert!(apu.irq_lines().is_empty());
+        
+        // The other ways to acknowledge: inhibiting the frame IRQ, disabling the DMC IRQ
+        apu.frame_irq = true;
+        apu.dmc.irq_flag = true;
+        apu.write_register(0x4017, 0x40);
+        assert_eq!(apu.irq_lines(), IrqSource::DMC);
+        apu.write_register(0x4010, 0x00);
+        assert!(apu.irq_lines().is_empty());
+        
+        // Inhibited or in 5-step mode, the sequence never raises it
+        for mode in [0x40, 0x80] {
+            let mut apu = Apu::default();
+            apu.write_register(0x4017, mode);
+            for _ in 0..2 * FOUR_STEP_PERIOD {
+                apu.clock();
+            }
+            assert!(apu.irq_lines().is_empty(), "$4017 = ${:02X}", mode);
+        }
+    }
+    
     #[test]
     fn test_pulse_duty_cycles() {
         let mut pulse = PulseChannel::new();
@@ -896,4 +1855,769 @@ mod tests {
         pulse.duty_position = 2;
         assert_eq!(pulse.output(), 15);
     }
+    
+    /// Group envelope levels into runs of (level, clocks spent at it)
+    fn runs(levels: impl Iterator<Item = u8>) -> Vec<(u8, usize)> {
+        let mut runs: Vec<(u8, usize)> = Vec::new();
+        for level in levels {
+            match runs.last_mut() {
+                Some((last, count)) if *last == level => *count += 1,
+                _ => runs.push((level, 1)),
+            }
+        }
+        runs
+    }
+    
+    /// Pulse envelope levels after each quarter-frame clock, from a length write
+    fn pulse_envelope_runs(reg0: u8, clocks: usize) -> Vec<(u8, usize)> {
+        let mut pulse = PulseChannel::new();
+        pulse.write_reg0(reg0);
+        pulse.write_reg3(0);
+        runs((0..clocks).map(|_| {
+            pulse.clock_envelope();
+            pulse.envelope_counter
+        }))
+    }
+    
+    #[test]
+    fn test_envelope_decays_every_period_plus_one_clocks() {
+        for period in [0u8, 1, 15] {
+            let clocks_per_level = period as usize + 1;
+            let runs = pulse_envelope_runs(period, clocks_per_level * 16 + 10);
+            
+            // The start clock loads 15; each level then lasts V+1 clocks,
+            // and without the loop flag the envelope stays at 0
+            let expected: Vec<(u8, usize)> = (1..=15)
+                .rev()
+                .map(|level| (level, clocks_per_level))
+                .chain([(0, clocks_per_level + 10)])
+                .collect();
+            assert_eq!(runs, expected, "V = {}", period);
+        }
+    }
+    
+    #[test]
+    fn test_envelope_loop_wraps_to_fifteen() {
+        for period in [0u8, 1, 15] {
+            let clocks_per_level = period as usize + 1;
+            // Bit 5 is both the length counter halt and the envelope loop
+            let runs = pulse_envelope_runs(0x20 | period, clocks_per_level * 32);
+            
+            let cycle = (0..=15).rev().map(|level| (level, clocks_per_level));
+            let expected: Vec<(u8, usize)> = cycle.clone().chain(cycle).collect();
+            assert_eq!(runs, expected, "V = {}", period);
+        }
+    }
+    
+    #[test]
+    fn test_noise_envelope_matches_pulse() {
+        for reg0 in [0x00, 0x01, 0x0F, 0x21, 0x2F] {
+            let mut noise = NoiseChannel::new();
+            noise.write_reg0(reg0);
+            noise.write_reg3(0);
+            let levels = (0..600).map(|_| {
+                noise.clock_envelope();
+                noise.envelope_counter
+            });
+            assert_eq!(runs(levels), pulse_envelope_runs(reg0, 600), "$400C = ${:02X}", reg0);
+        }
+    }
+    
+    /// A playing triangle at period $7FF, part way through its count,
+    /// then switched to period 7
+    fn triangle_after_period_drop(reduce_popping: bool) -> TriangleChannel {
+        let mut triangle = TriangleChannel::new();
+        triangle.reduce_popping = reduce_popping;
+        triangle.enabled = true;
+        triangle.write_reg0(0x7F);
+        triangle.write_reg2(0xFF);
+        triangle.write_reg3(0x0F);
+        triangle.clock_linear_counter();
+        for _ in 0..100 {
+            triangle.clock_timer();
+        }
+        triangle.write_reg2(0x07);
+        triangle.write_reg3(0x08);
+        triangle
+    }
+    
+    /// Sequencer steps in `clocks` triangle timer clocks
+    fn triangle_steps(triangle: &mut TriangleChannel, clocks: usize) -> usize {
+        let mut steps = 0;
+        for _ in 0..clocks {
+            let position = triangle.sequence_position;
+            triangle.clock_timer();
+            if triangle.sequence_position != position {
+                steps += 1;
+            }
+        }
+        steps
+    }
+    
+    #[test]
+    fn test_triangle_period_write_waits_for_reload() {
+        // The old count of $7FF still has 1948 clocks to go; only then does
+        // the period of 7 (a step every 8 clocks) take over
+        let mut triangle = triangle_after_period_drop(false);
+        assert_eq!(triangle_steps(&mut triangle, 1948), 0);
+        assert_eq!(triangle_steps(&mut triangle, 1), 1);
+        assert_eq!(triangle_steps(&mut triangle, 80), 10);
+    }
+    
+    #[test]
+    fn test_triangle_reduce_popping_clamps_timer() {
+        let mut triangle = triangle_after_period_drop(true);
+        assert_eq!(triangle.timer, 7);
+        assert_eq!(triangle_steps(&mut triangle, 8), 1);
+        assert_eq!(triangle_steps(&mut triangle, 80), 10);
+        
+        // Lengthening the period leaves the running count alone
+        triangle.write_reg2(0xFF);
+        assert!(triangle.timer <= 7);
+    }
+    
+    /// Shift register clocks in `clocks` noise timer (APU) clocks
+    fn noise_shifts(noise: &mut NoiseChannel, clocks: usize) -> usize {
+        let mut shifts = 0;
+        for _ in 0..clocks {
+            let timer = noise.timer;
+            noise.clock_timer();
+            if timer == 0 {
+                shifts += 1;
+            }
+        }
+        shifts
+    }
+    
+    #[test]
+    fn test_noise_shifts_once_per_table_period() {
+        // Period 0 is 4 CPU cycles, so 2 APU clocks; period 15 is 4068 CPU cycles
+        let mut noise = NoiseChannel::new();
+        noise.write_reg2(0x00);
+        assert_eq!(noise_shifts(&mut noise, 200), 100);
+        noise.write_reg2(0x0F);
+        noise_shifts(&mut noise, 1);
+        assert_eq!(noise_shifts(&mut noise, 2034 * 3), 3);
+    }
+    
+    #[test]
+    fn test_noise_period_write_waits_for_reload() {
+        for reduce_popping in [false, true] {
+            let mut noise = NoiseChannel::new();
+            noise.reduce_popping = reduce_popping;
+            noise.write_reg2(0x0F);
+            noise_shifts(&mut noise, 1);
+            for _ in 0..34 {
+                noise.clock_timer();
+            }
+            // 2000 clocks of the long period are left; period 3 shifts every 16
+            noise.write_reg2(0x03);
+            let shifts = noise_shifts(&mut noise, 2000);
+            if reduce_popping {
+                assert_eq!(noise.reload_value(), 15);
+                assert_eq!(shifts, 125);
+            } else {
+                assert_eq!(shifts, 1);
+            }
+        }
+    }
+    
+    #[test]
+    fn test_reduce_popping_is_a_setting() {
+        let mut apu = Apu::default();
+        assert!(!apu.reduce_popping());
+        apu.set_reduce_popping(true);
+        apu.reset();
+        assert!(apu.reduce_popping());
+        assert!(apu.noise.reduce_popping);
+    }
+    
+    #[test]
+    fn test_muted_channels_are_silent() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0x01);
+        apu.write_register(0x4000, 0xBF);
+        apu.write_register(0x4003, 0x08);
+        apu.pulse1.duty_position = 1;
+        let idle = mix(0, 0, 0, 0, 0);
+        assert!(apu.output() > idle);
+        
+        apu.set_muted(Channel::Pulse1, true);
+        assert_eq!(apu.output(), idle);
+        assert_eq!(apu.output_stereo(), (idle, idle));
+        // Still running underneath
+        assert_eq!(apu.read_register(0x4015) & 0x01, 0x01);
+        
+        apu.reset();
+        assert!(apu.is_muted(Channel::Pulse1));
+        apu.set_muted(Channel::Pulse1, false);
+        assert!(Channel::ALL.iter().all(|&channel| !apu.is_muted(channel)));
+    }
+    
+    /// Reference formula mixer (NESDev), kept to check the tables against
+    fn mix_formula(pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        let pulse = (pulse1 + pulse2) as f32;
+        let pulse_out = if pulse > 0.0 {
+            95.88 / ((8128.0 / pulse) + 100.0)
+        } else {
+            0.0
+        };
+        
+        let (t, n, d) = (triangle as f32, noise as f32, dmc as f32);
+        let tnd_out = if t + n + d > 0.0 {
+            159.79 / ((1.0 / (t / 8227.0 + n / 12241.0 + d / 22638.0)) + 100.0)
+        } else {
+            0.0
+        };
+        
+        (pulse_out + tnd_out) * 2.0 - 1.0
+    }
+    
+    #[test]
+    fn test_mixer_tables_match_formula() {
+        // Pulse output depends only on the sum, so the table is exact
+        for p1 in 0..16 {
+            for p2 in 0..16 {
+                let diff = (mix(p1, p2, 0, 0, 0) - mix_formula(p1, p2, 0, 0, 0)).abs();
+                assert!(diff < 1e-6, "pulse {}+{}: {}", p1, p2, diff);
+            }
+        }
+        
+        // The tnd table is the documented approximation
+        for t in 0..16 {
+            for n in 0..16 {
+                for d in 0..128 {
+                    let diff = (mix(0, 0, t, n, d) - mix_formula(0, 0, t, n, d)).abs();
+                    assert!(diff < 0.03, "tnd {},{},{}: {}", t, n, d, diff);
+                }
+            }
+        }
+    }
+    
+    #[test]
+    fn test_mixer_exact_values() {
+        let tables = mixer_tables();
+        assert_eq!(tables.pulse[0], 0.0);
+        assert_eq!(tables.tnd[0], 0.0);
+        assert!((tables.pulse[15] - 0.149_376_82).abs() < 1e-6);
+        assert!((tables.pulse[30] - 0.258_483_1).abs() < 1e-6);
+        
+        // (triangle, noise, dmc) -> 3t + 2n + d
+        assert!((tables.tnd[45] - 0.255_477_12).abs() < 1e-6); // (15, 0, 0)
+        assert!((tables.tnd[30] - 0.179_666_3).abs() < 1e-6); // (0, 15, 0)
+        assert!((tables.tnd[127] - 0.561_346_2).abs() < 1e-6); // (0, 0, 127)
+        assert!((tables.tnd[202] - 0.742_467_6).abs() < 1e-6); // (15, 15, 127)
+        
+        // Silence is -1.0
+        assert_eq!(mix(0, 0, 0, 0, 0), -1.0);
+        assert!((mix(15, 0, 15, 0, 0) - ((0.149_376_82 + 0.255_477_12) * 2.0 - 1.0)).abs() < 1e-6);
+    }
+    
+    /// Clock `apu` for `cycles`, collecting the stereo and mono output after each
+    fn stereo_samples(apu: &mut Apu, cycles: usize) -> Vec<(f32, f32, f32)> {
+        (0..cycles)
+            .map(|_| {
+                apu.clock();
+                let (left, right) = apu.output_stereo();
+                (left, right, apu.output())
+            })
+            .collect()
+    }
+    
+    /// Pulse 1 at 50% duty and constant volume 15, with the triangle also
+    /// playing when `triangle` is set
+    fn tone_apu(triangle: bool) -> Apu {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, if triangle { 0x05 } else { 0x01 });
+        apu.write_register(0x4000, 0xBF);
+        apu.write_register(0x4002, 0x40);
+        apu.write_register(0x4003, 0x00);
+        if triangle {
+            apu.write_register(0x4008, 0xFF);
+            apu.write_register(0x400A, 0x40);
+            apu.write_register(0x400B, 0x00);
+        }
+        apu
+    }
+    
+    #[test]
+    fn test_stereo_defaults_to_mono() {
+        let mut apu = tone_apu(true);
+        assert_eq!(apu.stereo_config(), StereoConfig::with_separation(0.0));
+        let samples = stereo_samples(&mut apu, 2000);
+        assert!(samples.iter().any(|&(_, _, mono)| mono > -1.0));
+        for (left, right, mono) in samples {
+            assert_eq!((left, right), (mono, mono));
+        }
+    }
+    
+    #[test]
+    fn test_taken_samples_follow_the_clock() {
+        for (region, clock) in [(Region::Ntsc, CPU_CLOCK_HZ), (Region::Pal, PAL_CPU_CLOCK_HZ)] {
+            let mut apu = Apu::new(region);
+            assert_eq!(apu.clock_hz(), clock);
+            apu.write_register(0x4015, 0x01);
+            apu.write_register(0x4000, 0xBF);
+            apu.write_register(0x4002, 0xFD);
+            apu.write_register(0x4003, 0x00);
+            let (mut mono, mut stereo) = (Vec::new(), Vec::new());
+            for _ in 0..clock / 10 {
+                apu.clock();
+            }
+            apu.take_samples(48_000, &mut mono);
+            apu.take_samples_stereo(48_000, &mut stereo);
+            let expected = (clock / 10) as usize * 48_000 / clock as usize;
+            assert_eq!(mono.len(), expected);
+            assert!(mono.iter().any(|&sample| sample > -1.0));
+            // Centred, both sides are the mono sample
+            assert!(stereo.iter().zip(&mono).all(|(&(left, right), &sample)| left == sample && right == sample));
+            
+            // Taken once only
+            apu.take_samples(48_000, &mut mono);
+            assert_eq!(mono.len(), expected);
+        }
+    }
+    
+    #[test]
+    fn test_panned_channels() {
+        // Pulse 1 hard left: the right channel is silent, the left is as loud as mono
+        let mut apu = tone_apu(false);
+        apu.set_stereo_config(StereoConfig { pan: [-1.0, 0.0, 0.0, 0.0, 0.0] });
+        let samples = stereo_samples(&mut apu, 2000);
+        assert!(samples.iter().any(|&(left, _, _)| left > -1.0));
+        for (left, right, mono) in samples {
+            assert_eq!((left, right), (mono, -1.0));
+        }
+        
+        // Full separation moves the pulses apart but leaves the triangle centred
+        let mut apu = tone_apu(true);
+        apu.set_stereo_config(StereoConfig::with_separation(1.0));
+        for _ in 0..2000 {
+            apu.clock();
+            let (left, right) = apu.output_stereo();
+            assert_eq!(left, apu.output());
+            assert_eq!(right, mix(0, 0, apu.triangle.output(), 0, 0));
+        }
+        
+        // Settings survive a reset
+        apu.reset();
+        assert_eq!(apu.stereo_config(), StereoConfig::with_separation(1.0));
+    }
+    
+    /// Pulse 1 playing a note with the longest length counter (254), in the given frame counter mode
+    fn apu_with_long_note(frame_counter: u8) -> Apu {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0x01);
+        apu.write_register(0x4000, 0x00); // Length counter not halted
+        apu.write_register(0x4003, 0x08); // Length index 1 = 254
+        apu.write_register(0x4017, frame_counter);
+        apu
+    }
+    
+    /// Clock for `cycles` CPU cycles, returning how often pulse 1's length counter was clocked
+    fn length_clocks(apu: &mut Apu, cycles: u64) -> u8 {
+        let start = apu.pulse1.length_counter;
+        for _ in 0..cycles {
+            apu.clock();
+        }
+        start - apu.pulse1.length_counter
+    }
+    
+    #[test]
+    fn test_length_counter_rate_per_second() {
+        // One second is 1,789,773 CPU cycles, within 0.02% of 60 4-step or
+        // 48 5-step sequences
+        let mut apu = apu_with_long_note(0x00);
+        assert_eq!(length_clocks(&mut apu, 60 * FOUR_STEP_PERIOD), 120);
+        
+        let mut apu = apu_with_long_note(0x80);
+        assert_eq!(apu.pulse1.length_counter, 253, "5-step write clocks a half frame immediately");
+        assert_eq!(length_clocks(&mut apu, 48 * FIVE_STEP_PERIOD), 96);
+    }
+    
+    #[test]
+    fn test_five_step_sequence() {
+        let mut apu = apu_with_long_note(0x80);
+        assert_eq!(apu.frame_sequencer_step(), 0);
+        assert_eq!(apu.next_sequencer_cycle(), FIVE_STEP_CYCLES[0]);
+        
+        // Half frames land on the second and fifth steps; the fourth does nothing
+        let mut halves = Vec::new();
+        for step in 0..5 {
+            assert_eq!(apu.frame_sequencer_step(), step);
+            let cycles = apu.next_sequencer_cycle() + 1 - apu.cycles();
+            halves.push(length_clocks(&mut apu, cycles));
+        }
+        assert_eq!(halves, [0, 1, 0, 0, 1]);
+        assert_eq!(apu.frame_sequencer_step(), 0);
+        assert_eq!(apu.next_sequencer_cycle(), FIVE_STEP_PERIOD + FIVE_STEP_CYCLES[0]);
+    }
+    
+    #[test]
+    fn test_frame_counter_write_restarts_sequence() {
+        let mut apu = apu_with_long_note(0x00);
+        for _ in 0..20_000 {
+            apu.clock();
+        }
+        assert_eq!(apu.frame_sequencer_step(), 2);
+        
+        apu.write_register(0x4017, 0x00);
+        assert_eq!(apu.frame_sequencer_step(), 0);
+        assert_eq!(apu.next_sequencer_cycle(), 20_000 + FOUR_STEP_CYCLES[0]);
+    }
+    
+    /// Frame counter steps and noise shifts in one second of `region`'s CPU clock
+    fn one_second(region: Region, cpu_clock: u64) -> (usize, usize) {
+        let mut apu = Apu::new(region);
+        apu.write_register(0x400E, 0x02);
+        let (mut steps, mut shifts) = (0, 0);
+        for _ in 0..cpu_clock {
+            let (step, lfsr) = (apu.frame_step, apu.noise.shift_register);
+            apu.clock();
+            steps += usize::from(apu.frame_step != step);
+            shifts += usize::from(apu.noise.shift_register != lfsr);
+        }
+        (steps, shifts)
+    }
+    
+    #[test]
+    fn test_region_timings() {
+        assert_eq!(Apu::default().region(), Region::Ntsc);
+        assert_eq!(Apu::new(Region::Dual).region(), Region::Ntsc);
+        
+        // About 240 quarter frames a second on NTSC and 200 on PAL, the
+        // last one just past the second; noise period 2 shifts every 16 or
+        // 14 CPU cycles
+        assert_eq!(one_second(Region::Ntsc, 1_789_773), (239, 111_861));
+        assert_eq!(one_second(Region::Pal, 1_662_607), (199, 118_758));
+        
+        let mut apu = Apu::new(Region::Pal);
+        apu.write_register(0x4017, 0x80);
+        assert_eq!(apu.next_sequencer_cycle(), PAL_FIVE_STEP_CYCLES[0]);
+        apu.reset();
+        assert_eq!(apu.region(), Region::Pal);
+        assert_eq!(apu.next_sequencer_cycle(), PAL_FOUR_STEP_CYCLES[0]);
+    }
+    
+    /// An APU playing pulse 1 with a frame IRQ and a DMC IRQ pending
+    fn playing_apu() -> Apu {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0x01);
+        apu.write_register(0x4000, 0xBF);
+        apu.write_register(0x4002, 0xFD);
+        apu.write_register(0x4003, 0x08);
+        apu.write_register(0x4010, 0x80);
+        while !apu.frame_irq {
+            apu.clock();
+        }
+        apu.dmc.irq_flag = true;
+        assert_eq!(apu.irq_lines(), IrqSource::FRAME | IrqSource::DMC);
+        apu
+    }
+    
+    #[test]
+    fn test_soft_reset_keeps_registers() {
+        let mut apu = playing_apu();
+        let cycles = apu.cycles();
+        let next_step = apu.next_sequencer_cycle();
+        apu.soft_reset();
+        
+        // $4015 is cleared and both IRQs acknowledged
+        assert!(!apu.pulse1.enabled);
+        assert_eq!(apu.pulse1.length_counter, 0);
+        assert_eq!(apu.read_register(0x4015), 0x00);
+        assert!(apu.irq_lines().is_empty());
+        
+        // Registers, the envelope and the frame counter carry on
+        assert_eq!(apu.pulse1.duty, 2);
+        assert!(apu.pulse1.constant_volume);
+        assert_eq!(apu.pulse1.volume, 0x0F);
+        assert_eq!(apu.pulse1.timer_period, 0x0FD);
+        assert!(apu.dmc.irq_enabled);
+        assert_eq!(apu.cycles(), cycles);
+        assert_eq!(apu.next_sequencer_cycle(), next_step);
+        
+        // Re-enabling is all a game needs to play the same note again
+        apu.write_register(0x4015, 0x01);
+        apu.write_register(0x4003, 0x08);
+        assert_eq!(apu.read_register(0x4015) & 0x01, 0x01);
+    }
+    
+    #[test]
+    fn test_full_reset_returns_to_power_on() {
+        let mut apu = playing_apu();
+        apu.set_muted(Channel::Noise, true);
+        apu.reset();
+        
+        assert_eq!(apu.cycles(), 0);
+        assert_eq!(apu.frame_sequencer_step(), 0);
+        assert!(apu.irq_lines().is_empty());
+        assert_eq!(apu.pulse1.duty, 0);
+        assert_eq!(apu.pulse1.volume, 0);
+        assert_eq!(apu.pulse1.timer_period, 0);
+        assert!(!apu.dmc.irq_enabled);
+        // Settings survive
+        assert!(apu.is_muted(Channel::Noise));
+    }
+    
+    /// Every channel playing through envelopes, a sweep and the noise LFSR,
+    /// with the 4-step frame counter raising its IRQ
+    fn busy_apu() -> Apu {
+        let mut apu = Apu::default();
+        for (addr, value) in [
+            (0x4015, 0x1F),
+            (0x4000, 0x4A), // 25% duty, envelope period 10
+            (0x4001, 0xA3), // sweep up every 3 half frames
+            (0x4002, 0x71),
+            (0x4003, 0x12),
+            (0x4004, 0xC3),
+            (0x4006, 0x35),
+            (0x4007, 0x09),
+            (0x4008, 0x47),
+            (0x400A, 0x9C),
+            (0x400B, 0x21),
+            (0x400C, 0x25),
+            (0x400E, 0x86), // short mode
+            (0x400F, 0x38),
+            (0x4011, 0x33),
+            (0x4017, 0x00),
+        ] {
+            apu.write_register(addr, value);
+        }
+        apu
+    }
+    
+    fn samples(apu: &mut Apu, cycles: usize) -> Vec<f32> {
+        (0..cycles)
+            .map(|_| {
+                apu.clock();
+                apu.output()
+            })
+            .collect()
+    }
+    
+    #[test]
+    fn test_restored_state_plays_the_same_samples() {
+        let mut apu = busy_apu();
+        samples(&mut apu, 10_007);
+        let state = apu.dump_state();
+        
+        let first = samples(&mut apu, 29_989);
+        assert!(first.windows(2).any(|pair| pair[0] != pair[1]));
+        apu.restore_state(&state).unwrap();
+        assert_eq!(samples(&mut apu, 29_989), first);
+        
+        // A fresh APU picks up the same timeline
+        let mut other = Apu::default();
+        other.restore_state(&state).unwrap();
+        assert_eq!(samples(&mut other, 29_989), first);
+        assert_eq!(other.dump_state(), apu.dump_state());
+    }
+    
+    #[test]
+    fn test_restore_keeps_settings() {
+        let mut apu = busy_apu();
+        apu.set_muted(Channel::Triangle, true);
+        apu.set_stereo_config(StereoConfig::with_separation(0.5));
+        apu.restore_state(&Apu::default().dump_state()).unwrap();
+        assert!(apu.is_muted(Channel::Triangle));
+        assert_eq!(apu.stereo_config(), StereoConfig::with_separation(0.5));
+        assert_eq!(apu.cycles(), 0);
+    }
+    
+    #[test]
+    fn test_bad_state_is_refused() {
+        let mut apu = busy_apu();
+        samples(&mut apu, 101);
+        let before = apu.dump_state();
+        
+        let mut state = Apu::default().dump_state();
+        state.version = ApuState::VERSION + 1;
+        assert!(apu.restore_state(&state).is_err());
+        state.version = ApuState::VERSION;
+        state.noise.timer_period = 16;
+        assert!(apu.restore_state(&state).is_err());
+        state.noise.timer_period = 0;
+        state.frame_counter.step = 4;
+        assert!(apu.restore_state(&state).is_err());
+        assert_eq!(apu.dump_state(), before);
+    }
+    
+    #[test]
+    fn test_status_matches_register_read() {
+        let mut apu = busy_apu();
+        for _ in 0..4 {
+            samples(&mut apu, 7_919);
+            assert_eq!(apu.dump_state().status(), apu.read_register(0x4015));
+        }
+        // The frame IRQ is in the status until a CPU read acknowledges it
+        assert_ne!(apu.dump_state().status() & 0x40, 0);
+        apu.read_status();
+        assert_eq!(apu.dump_state().status() & 0x40, 0);
+    }
+    
+    #[test]
+    fn test_savestate_goes_through_apu_state() {
+        let mut apu = busy_apu();
+        samples(&mut apu, 3_001);
+        let mut w = StateWriter::new();
+        apu.save(&mut w);
+        let mut direct = StateWriter::new();
+        apu.dump_state().save(&mut direct);
+        assert_eq!(w.finish(), direct.finish());
+    }
+    
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_apu_state_serializes() {
+        let mut apu = busy_apu();
+        samples(&mut apu, 997);
+        let state = apu.dump_state();
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(json.contains(&format!("\"version\":{}", ApuState::VERSION)));
+        assert_eq!(serde_json::from_str::<ApuState>(&json).unwrap(), state);
+    }
+    
+    /// Run `dmc` for `apu_cycles`, fetching from `memory` and recording
+    /// each address read
+    fn play_dmc(dmc: &mut DmcChannel, memory: &[u8; 0x10000], reads: &mut Vec<u16>, apu_cycles: usize) {
+        let mut reader = |addr: u16| {
+            reads.push(addr);
+            memory[addr as usize]
+        };
+        for _ in 0..apu_cycles {
+            dmc.clock_timer();
+            dmc.fetch(&mut reader);
+        }
+    }
+    
+    /// APU cycles for one output bit at the fastest rate
+    const DMC_BIT: usize = 27;
+    
+    /// A DMC at the fastest rate playing `length` bytes from `address`
+    fn dmc_sample(address: u16, length: u16) -> DmcChannel {
+        let mut dmc = DmcChannel::new();
+        dmc.write_reg0(0x0F);
+        dmc.sample_address = address;
+        dmc.sample_length = length;
+        dmc.set_enabled(true);
+        dmc
+    }
+    
+    #[test]
+    fn test_dmc_address_wraps_to_8000() {
+        let mut memory = [0u8; 0x10000];
+        memory[0xFFFE] = 0x11;
+        memory[0xFFFF] = 0x22;
+        memory[0x8000] = 0x33;
+        let mut dmc = dmc_sample(0xFFFE, 3);
+        let mut reads = Vec::new();
+        play_dmc(&mut dmc, &memory, &mut reads, 3 * 8 * DMC_BIT);
+        assert_eq!(reads, [0xFFFE, 0xFFFF, 0x8000]);
+        assert_eq!(dmc.current_address, 0x8001);
+        assert!(!dmc.sample_status());
+        
+        // The same through the registers: the 65th byte from $FFC0
+        let mut apu = Apu::default();
+        for (addr, value) in [(0x4010, 0x0F), (0x4012, 0xFF), (0x4013, 0x04), (0x4015, 0x10)] {
+            apu.write_register(addr, value);
+        }
+        let mut reads = Vec::new();
+        for _ in 0..65 * 8 * 2 * DMC_BIT {
+            apu.clock_with(&mut |addr| {
+                reads.push(addr);
+                0
+            });
+        }
+        assert_eq!(reads.len(), 65);
+        assert_eq!(reads[63..], [0xFFFF, 0x8000]);
+    }
+    
+    #[test]
+    fn test_dmc_buffer_empties_into_shifter() {
+        let mut memory = [0u8; 0x10000];
+        memory[0xC000] = 0xFF;
+        memory[0xC001] = 0x0F;
+        let mut dmc = dmc_sample(0xC000, 2);
+        dmc.write_reg1(64);
+        let mut reads = Vec::new();
+        
+        // The first byte is fetched at once but waits out the silent
+        // output cycle the channel was in, whose first bit has just played
+        play_dmc(&mut dmc, &memory, &mut reads, 1);
+        assert_eq!((reads.len(), dmc.sample_buffer), (1, Some(0xFF)));
+        play_dmc(&mut dmc, &memory, &mut reads, 6 * DMC_BIT);
+        assert_eq!(dmc.output(), 64);
+        assert_eq!(reads.len(), 1);
+        
+        // Then it moves to the shifter, freeing the buffer for the second
+        play_dmc(&mut dmc, &memory, &mut reads, DMC_BIT);
+        assert_eq!(reads.len(), 2);
+        assert_eq!(dmc.sample_buffer, Some(0x0F));
+        play_dmc(&mut dmc, &memory, &mut reads, 8 * DMC_BIT);
+        assert_eq!(dmc.output(), 64 + 16);
+        
+        // 0x0F: four steps up, four down
+        play_dmc(&mut dmc, &memory, &mut reads, 4 * DMC_BIT);
+        assert_eq!(dmc.output(), 64 + 24);
+        play_dmc(&mut dmc, &memory, &mut reads, 4 * DMC_BIT);
+        assert_eq!(dmc.output(), 64 + 16);
+        
+        // With nothing left it falls silent and holds the level
+        play_dmc(&mut dmc, &memory, &mut reads, 32 * DMC_BIT);
+        assert!(dmc.silence);
+        assert_eq!(dmc.output(), 64 + 16);
+        assert_eq!(reads.len(), 2);
+    }
+    
+    #[test]
+    fn test_dmc_loop_restarts_at_sample_address() {
+        let memory = [0u8; 0x10000];
+        let mut dmc = dmc_sample(0xC040, 2);
+        dmc.write_reg0(0xCF); // loop, IRQ enabled
+        let mut reads = Vec::new();
+        play_dmc(&mut dmc, &memory, &mut reads, 5 * 8 * DMC_BIT);
+        assert_eq!(reads[..5], [0xC040, 0xC041, 0xC040, 0xC041, 0xC040]);
+        assert!(dmc.sample_status());
+        // A looping sample never raises the IRQ
+        assert!(!dmc.irq_flag);
+    }
+    
+    #[test]
+    fn test_dmc_irq_when_last_byte_fetched() {
+        let memory = [0u8; 0x10000];
+        let mut dmc = dmc_sample(0xC000, 2);
+        dmc.write_reg0(0x8F);
+        let mut reads = Vec::new();
+        
+        // The second fetch waits for the first byte to leave the buffer,
+        // at the end of the output cycle that was running
+        play_dmc(&mut dmc, &memory, &mut reads, 7 * DMC_BIT);
+        assert_eq!(reads.len(), 1);
+        assert_eq!(dmc.bytes_remaining, 1);
+        assert!(!dmc.irq_flag);
+        
+        play_dmc(&mut dmc, &memory, &mut reads, 1);
+        assert_eq!(reads.len(), 2);
+        assert_eq!(dmc.bytes_remaining, 0);
+        assert!(dmc.irq_flag);
+        
+        // Without the IRQ enabled the sample just ends
+        let mut dmc = dmc_sample(0xC000, 1);
+        play_dmc(&mut dmc, &memory, &mut reads, 8 * DMC_BIT);
+        assert!(!dmc.sample_status());
+        assert!(!dmc.irq_flag);
+    }
+    
+    #[test]
+    fn test_dmc_pal_rates() {
+        let ntsc = Apu::new(Region::Ntsc);
+        let pal = Apu::new(Region::Pal);
+        assert_eq!(ntsc.dmc.periods[0], 428);
+        assert_eq!(pal.dmc.periods[0], 398);
+        // Restoring a state keeps the region's table
+        let mut pal = pal;
+        pal.restore_state(&ntsc.dump_state()).unwrap();
+        assert_eq!(pal.dmc.periods[0], 398);
+    }
 }
@@ -0,0 +1,274 @@
+//! Standalone APU driver for tools that want NES sound without a ROM
+//!
+//! [`ApuPlayer`] owns an [`Apu`] and clocks it the way the console does,
+//! once per CPU cycle, so the frame counter's envelopes, sweeps and length
+//! counters run at the right rate. Register writes take effect between
+//! samples, which is as fine-grained as a tracker or a MIDI previewer needs.
+//! A VRC6 ([`crate::vrc6`]) can be attached with [`ApuPlayer::set_vrc6_enabled`],
+//! mixed in the way a console mixes the cartridge's sound chip.
+//!
+//! ```
+//! use emu_nes::ApuPlayer;
+//!
+//! let mut player = ApuPlayer::new(48_000);
+//! player.write_register(0x4015, 0x01); // Enable pulse 1
+//! player.write_register(0x4000, 0xBF); // 50% duty, constant volume 15
+//! player.write_register(0x4002, 0xFD); // Timer 253: about 440 Hz
+//! player.write_register(0x4003, 0x00);
+//!
+//! let mut samples = vec![0.0; 48_000];
+//! player.render(&mut samples);
+//! ```
+
+use crate::apu::{Apu, Channel};
+use crate::expansion_audio::ExpansionAudio;
+use crate::vrc6::Vrc6Audio;
+
+/// NTSC CPU clock in Hz, which is also the APU's clock
+pub const CPU_CLOCK_HZ: u32 = 1_789_773;
+
+/// PAL CPU clock in Hz
+pub const PAL_CPU_CLOCK_HZ: u32 = 1_662_607;
+
+/// CPU cycles per video frame, as [`NesSystem::run_frame`](crate::NesSystem::run_frame) counts them
+pub const CYCLES_PER_FRAME: u64 = 29780;
+
+/// CPU cycles per PAL video frame, as [`NesSystem::run_frame`](crate::NesSystem::run_frame)
+/// counts them (312 lines of 341 dots at 3.2 dots a cycle is 33247.5)
+pub const PAL_CYCLES_PER_FRAME: u64 = 33247;
+
+/// Corner frequency of the output high-pass filter in Hz
+///
+/// The console's first output stage is a 90 Hz high-pass, which is what
+/// centres the APU's all-positive output on zero.
+const HIGH_PASS_HZ: f32 = 90.0;
+
+/// An APU with its own clock, producing samples at a chosen rate
+pub struct ApuPlayer {
+    apu: Apu,
+    /// VRC6 expansion audio, when attached
+    vrc6: Option<Vrc6Audio>,
+    sample_rate: u32,
+    /// Sample periods elapsed, in units of 1 / (CPU clock * sample rate)
+    phase: u32,
+    /// Sum and count of APU outputs since the last sample
+    sum: f32,
+    count: u32,
+    /// High-pass filter coefficient and state
+    high_pass: f32,
+    previous_in: f32,
+    previous_out: f32,
+}
+
+impl ApuPlayer {
+    /// Create a silent player producing `sample_rate` samples per second
+    pub fn new(sample_rate: u32) -> Self {
+        Self::with_apu(Apu::default(), None, sample_rate)
+    }
+
+    fn with_apu(apu: Apu, vrc6: Option<Vrc6Audio>, sample_rate: u32) -> Self {
+        assert!(sample_rate > 0 && sample_rate <= CPU_CLOCK_HZ, "sample rate {} out of range", sample_rate);
+        let rc = 1.0 / (2.0 * std::f32::consts::PI * HIGH_PASS_HZ);
+        let dt = 1.0 / sample_rate as f32;
+        // Starting from the idle level keeps silence at exactly zero
+        let idle = apu.output();
+        Self {
+            apu,
+            vrc6,
+            sample_rate,
+            phase: 0,
+            sum: 0.0,
+            count: 0,
+            high_pass: rc / (rc + dt),
+            previous_in: idle,
+            previous_out: 0.0,
+        }
+    }
+
+    /// Output sample rate in Hz
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Write an APU register ($4000-$4017), as the CPU would
+    ///
+    /// Writes to $4020-$FFFF go to the VRC6 once it's attached, and are
+    /// ignored until then, as by a cartridge with nothing there.
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        if addr < 0x4020 {
+            self.apu.write_register(addr, value);
+        } else if let Some(vrc6) = &mut self.vrc6 {
+            ExpansionAudio::write_register(vrc6, addr, value);
+        }
+    }
+
+    /// The APU being driven, for reading channel state
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    /// Attach or detach VRC6 expansion audio
+    ///
+    /// Its registers are as on a mapper 24 board ($9000-$9003,
+    /// $A000-$A002, $B000-$B002) and its channels are muted as
+    /// [`Channel::Expansion`] 0-2. Attaching starts the chip from power-on.
+    pub fn set_vrc6_enabled(&mut self, enabled: bool) {
+        if enabled != self.vrc6.is_some() {
+            self.vrc6 = enabled.then(Vrc6Audio::new);
+        }
+    }
+
+    /// The VRC6 expansion audio, if attached
+    pub fn vrc6(&self) -> Option<&Vrc6Audio> {
+        self.vrc6.as_ref()
+    }
+
+    /// Mute or unmute a channel; see [`Apu::set_muted`]
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.apu.set_muted(channel, muted);
+    }
+
+    /// Silence everything and return to the power-on state
+    ///
+    /// VRC6 audio stays attached, back in its power-on state, and muted
+    /// channels stay muted.
+    pub fn reset(&mut self) {
+        let mut apu = std::mem::take(&mut self.apu);
+        apu.reset();
+        let mut vrc6 = self.vrc6.take();
+        if let Some(vrc6) = &mut vrc6 {
+            ExpansionAudio::reset(vrc6);
+        }
+        *self = Self::with_apu(apu, vrc6, self.sample_rate);
+    }
+
+    /// Clock one CPU cycle, returning a sample if one is due
+    ///
+    /// Each sample is the average APU output over the cycles since the
+    /// previous one, which filters out most of what would alias.
+    fn clock(&mut self) -> Option<f32> {
+        self.apu.clock_expansion(self.vrc6.as_mut().map(|vrc6| vrc6 as &mut dyn ExpansionAudio));
+        self.apu.clock();
+        self.sum += self.apu.output();
+        self.count += 1;
+        self.phase += self.sample_rate;
+        if self.phase < CPU_CLOCK_HZ {
+            return None;
+        }
+        self.phase -= CPU_CLOCK_HZ;
+        let input = self.sum / self.count as f32;
+        self.sum = 0.0;
+        self.count = 0;
+        let output = self.high_pass * (self.previous_out + input - self.previous_in);
+        self.previous_in = input;
+        self.previous_out = output;
+        Some(output)
+    }
+
+    /// Fill `out` with the next samples
+    pub fn render(&mut self, out: &mut [f32]) {
+        for sample in out {
+            *sample = loop {
+                if let Some(value) = self.clock() {
+                    break value;
+                }
+            };
+        }
+    }
+
+    /// Run `frames` video frames' worth of CPU cycles, returning the samples produced
+    pub fn step_frames(&mut self, frames: u32) -> Vec<f32> {
+        let cycles = CYCLES_PER_FRAME * frames as u64;
+        let expected = cycles * self.sample_rate as u64 / CPU_CLOCK_HZ as u64 + 1;
+        let mut samples = Vec::with_capacity(expected as usize);
+        for _ in 0..cycles {
+            if let Some(sample) = self.clock() {
+                samples.push(sample);
+            }
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pulse 1 at 50% duty and constant volume 15 with timer period `timer`
+    fn pulse_tone(player: &mut ApuPlayer, timer: u16) {
+        player.write_register(0x4015, 0x01);
+        player.write_register(0x4000, 0xBF);
+        player.write_register(0x4002, timer as u8);
+        player.write_register(0x4003, (timer >> 8) as u8);
+    }
+
+    /// Negative-to-positive crossings
+    fn rising_crossings(samples: &[f32]) -> usize {
+        samples.windows(2).filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0).count()
+    }
+
+    #[test]
+    fn test_440hz_pulse() {
+        let mut player = ApuPlayer::new(48_000);
+        // f = CPU / (16 * (t + 1)): t = 253 gives 440.4 Hz
+        pulse_tone(&mut player, 253);
+        let mut samples = vec![0.0; 48_000];
+        player.render(&mut samples);
+
+        let crossings = rising_crossings(&samples);
+        assert!((438..=442).contains(&crossings), "{} crossings", crossings);
+        // The high-pass centres the wave on zero
+        let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak > 0.05 && peak <= 1.0, "peak {}", peak);
+    }
+
+    #[test]
+    fn test_step_frames_keeps_time() {
+        let mut player = ApuPlayer::new(44_100);
+        let samples: usize = (0..60).map(|_| player.step_frames(1).len()).sum();
+        // 60 frames of 29780 cycles is 0.9983 s
+        let expected = 60 * CYCLES_PER_FRAME as usize * 44_100 / CPU_CLOCK_HZ as usize;
+        assert!(samples.abs_diff(expected) <= 1, "{} samples, expected {}", samples, expected);
+        assert!(player.apu().cycles() == 60 * CYCLES_PER_FRAME);
+    }
+
+    #[test]
+    fn test_reset_silences() {
+        let mut player = ApuPlayer::new(48_000);
+        pulse_tone(&mut player, 253);
+        player.render(&mut [0.0; 1000]);
+        player.reset();
+        let mut samples = vec![1.0; 1000];
+        player.render(&mut samples);
+        assert!(samples.iter().all(|&s| s == 0.0));
+        assert_eq!(player.sample_rate(), 48_000);
+    }
+
+    #[test]
+    fn test_vrc6_sawtooth_plays_and_mutes() {
+        let mut player = ApuPlayer::new(48_000);
+        // Ignored until VRC6 is attached
+        player.write_register(0xB000, 42);
+        assert!(player.vrc6().is_none());
+
+        player.set_vrc6_enabled(true);
+        player.write_register(0xB000, 42);
+        // f = CPU / (14 * (t + 1)): t = 289 gives 440.8 Hz
+        player.write_register(0xB001, 289u16 as u8);
+        player.write_register(0xB002, 0x80 | (289u16 >> 8) as u8);
+        let mut samples = vec![0.0; 48_000];
+        player.render(&mut samples);
+        let crossings = rising_crossings(&samples);
+        assert!((438..=443).contains(&crossings), "{} crossings", crossings);
+
+        player.set_muted(Channel::Expansion(2), true);
+        player.render(&mut samples);
+        let peak = samples[24_000..].iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak < 1e-3, "peak {}", peak);
+
+        // Both settings survive a reset
+        player.reset();
+        assert!(player.vrc6().is_some());
+        assert!(player.apu().is_muted(Channel::Expansion(2)));
+    }
+}
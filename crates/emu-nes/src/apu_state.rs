@@ -0,0 +1,353 @@
+//! APU state as plain data
+//!
+//! [`Apu::dump_state`](crate::Apu::dump_state) copies out everything the
+//! five channels and the frame counter hold, including what games can't
+//! see through the registers: envelope dividers, sweep and timer counters,
+//! the noise LFSR, the DMC's sample buffer and the sequencer's phase.
+//! [`Apu::restore_state`](crate::Apu::restore_state) puts it back exactly,
+//! so audio carries on from the same sample. Savestates store the APU this
+//! way, and tools can keep a channel setup around to return to.
+//!
+//! Settings (stereo placement, muting, popping reduction) and VRC6 audio
+//! aren't part of it; the first are the player's choice rather than state,
+//! and the second is on the cartridge, not the console.
+//!
+//! With the `serde` feature the types can be serialized. [`ApuState::version`]
+//! travels with them so a state from a later layout is refused rather than
+//! misread.
+
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+use emu_core::Result;
+
+/// Everything the APU's channels and frame counter hold
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ApuState {
+    /// Layout of this struct, [`ApuState::VERSION`] when dumped
+    pub version: u16,
+    pub pulse1: PulseState,
+    pub pulse2: PulseState,
+    pub triangle: TriangleState,
+    pub noise: NoiseState,
+    pub dmc: DmcState,
+    pub frame_counter: FrameCounterState,
+}
+
+impl ApuState {
+    /// Current layout version
+    pub const VERSION: u16 = 2;
+
+    /// What a read of $4015 returns in this state, without acknowledging
+    /// the frame IRQ as a CPU read would
+    pub fn status(&self) -> u8 {
+        let mut status = 0;
+        for (bit, playing) in [
+            self.pulse1.length_counter > 0,
+            self.pulse2.length_counter > 0,
+            self.triangle.length_counter > 0,
+            self.noise.length_counter > 0,
+            self.dmc.bytes_remaining > 0,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            status |= (playing as u8) << bit;
+        }
+        status |= (self.frame_counter.frame_irq as u8) << 6;
+        status | (self.dmc.irq_flag as u8) << 7
+    }
+}
+
+/// A pulse channel's registers and counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PulseState {
+    pub enabled: bool,
+    /// Duty cycle (0-3)
+    pub duty: u8,
+    pub length_halt: bool,
+    pub constant_volume: bool,
+    /// Volume or envelope period
+    pub volume: u8,
+    pub sweep_enabled: bool,
+    pub sweep_period: u8,
+    pub sweep_negate: bool,
+    pub sweep_shift: u8,
+    pub timer_period: u16,
+    pub length_counter: u8,
+    pub timer: u16,
+    /// Step of the duty sequence (0-7)
+    pub duty_position: u8,
+    pub envelope_divider: u8,
+    pub envelope_counter: u8,
+    pub envelope_start: bool,
+}
+
+/// The triangle channel's registers and counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TriangleState {
+    pub enabled: bool,
+    pub length_halt: bool,
+    pub linear_counter_load: u8,
+    pub timer_period: u16,
+    pub length_counter: u8,
+    pub linear_counter: u8,
+    pub linear_counter_reload: bool,
+    pub timer: u16,
+    /// Step of the 32-step sequence
+    pub sequence_position: u8,
+}
+
+/// The noise channel's registers and counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NoiseState {
+    pub enabled: bool,
+    pub length_halt: bool,
+    pub constant_volume: bool,
+    pub volume: u8,
+    /// Short (93-step) mode
+    pub mode: bool,
+    /// Index into the period table (0-15)
+    pub timer_period: u8,
+    pub length_counter: u8,
+    pub timer: u16,
+    /// The 15-bit LFSR
+    pub shift_register: u16,
+    pub envelope_divider: u8,
+    pub envelope_counter: u8,
+    pub envelope_start: bool,
+}
+
+/// The DMC's registers and playback position
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DmcState {
+    pub enabled: bool,
+    pub irq_enabled: bool,
+    pub irq_flag: bool,
+    pub loop_flag: bool,
+    /// Rate index (0-15)
+    pub rate: u8,
+    pub direct_load: u8,
+    pub sample_address: u16,
+    pub sample_length: u16,
+    pub output_level: u8,
+    pub bytes_remaining: u16,
+    pub current_address: u16,
+    pub timer: u16,
+    /// Bits of the current byte still to play
+    pub shift_register: u8,
+    /// Bits left in the output cycle (1-8)
+    pub bits_remaining: u8,
+    /// Byte fetched and waiting for the output unit
+    pub sample_buffer: Option<u8>,
+    pub silence: bool,
+}
+
+/// Where the frame counter is in its sequence
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameCounterState {
+    /// 5-step mode rather than 4-step
+    pub five_step: bool,
+    pub irq_inhibit: bool,
+    /// Frame IRQ raised and not yet acknowledged
+    pub frame_irq: bool,
+    /// APU cycles since power-on
+    pub cycle: u64,
+    /// Step that runs next
+    pub step: u8,
+    /// Cycle the current sequence started on
+    pub sequence_start: u64,
+}
+
+// Savestates already carry a format version, so these write the fields alone
+
+impl Snapshot for PulseState {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.u8(self.duty);
+        w.bool(self.length_halt);
+        w.bool(self.constant_volume);
+        w.u8(self.volume);
+        w.bool(self.sweep_enabled);
+        w.u8(self.sweep_period);
+        w.bool(self.sweep_negate);
+        w.u8(self.sweep_shift);
+        w.u16(self.timer_period);
+        w.u8(self.length_counter);
+        w.u16(self.timer);
+        w.u8(self.duty_position);
+        w.u8(self.envelope_divider);
+        w.u8(self.envelope_counter);
+        w.bool(self.envelope_start);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.enabled = r.bool()?;
+        self.duty = r.u8()?;
+        self.length_halt = r.bool()?;
+        self.constant_volume = r.bool()?;
+        self.volume = r.u8()?;
+        self.sweep_enabled = r.bool()?;
+        self.sweep_period = r.u8()?;
+        self.sweep_negate = r.bool()?;
+        self.sweep_shift = r.u8()?;
+        self.timer_period = r.u16()?;
+        self.length_counter = r.u8()?;
+        self.timer = r.u16()?;
+        self.duty_position = r.u8()?;
+        self.envelope_divider = r.u8()?;
+        self.envelope_counter = r.u8()?;
+        self.envelope_start = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for TriangleState {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.bool(self.length_halt);
+        w.u8(self.linear_counter_load);
+        w.u16(self.timer_period);
+        w.u8(self.length_counter);
+        w.u8(self.linear_counter);
+        w.bool(self.linear_counter_reload);
+        w.u16(self.timer);
+        w.u8(self.sequence_position);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.enabled = r.bool()?;
+        self.length_halt = r.bool()?;
+        self.linear_counter_load = r.u8()?;
+        self.timer_period = r.u16()?;
+        self.length_counter = r.u8()?;
+        self.linear_counter = r.u8()?;
+        self.linear_counter_reload = r.bool()?;
+        self.timer = r.u16()?;
+        self.sequence_position = r.u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for NoiseState {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.bool(self.length_halt);
+        w.bool(self.constant_volume);
+        w.u8(self.volume);
+        w.bool(self.mode);
+        w.u8(self.timer_period);
+        w.u8(self.length_counter);
+        w.u16(self.timer);
+        w.u16(self.shift_register);
+        w.u8(self.envelope_divider);
+        w.u8(self.envelope_counter);
+        w.bool(self.envelope_start);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.enabled = r.bool()?;
+        self.length_halt = r.bool()?;
+        self.constant_volume = r.bool()?;
+        self.volume = r.u8()?;
+        self.mode = r.bool()?;
+        self.timer_period = r.u8()?;
+        self.length_counter = r.u8()?;
+        self.timer = r.u16()?;
+        self.shift_register = r.u16()?;
+        self.envelope_divider = r.u8()?;
+        self.envelope_counter = r.u8()?;
+        self.envelope_start = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for DmcState {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.bool(self.irq_enabled);
+        w.bool(self.irq_flag);
+        w.bool(self.loop_flag);
+        w.u8(self.rate);
+        w.u8(self.direct_load);
+        w.u16(self.sample_address);
+        w.u16(self.sample_length);
+        w.u8(self.output_level);
+        w.u16(self.bytes_remaining);
+        w.u16(self.current_address);
+        w.u16(self.timer);
+        w.u8(self.shift_register);
+        w.u8(self.bits_remaining);
+        w.bool(self.sample_buffer.is_some());
+        w.u8(self.sample_buffer.unwrap_or(0));
+        w.bool(self.silence);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.enabled = r.bool()?;
+        self.irq_enabled = r.bool()?;
+        self.irq_flag = r.bool()?;
+        self.loop_flag = r.bool()?;
+        self.rate = r.u8()?;
+        self.direct_load = r.u8()?;
+        self.sample_address = r.u16()?;
+        self.sample_length = r.u16()?;
+        self.output_level = r.u8()?;
+        self.bytes_remaining = r.u16()?;
+        self.current_address = r.u16()?;
+        self.timer = r.u16()?;
+        self.shift_register = r.u8()?;
+        self.bits_remaining = r.u8()?;
+        let buffered = r.bool()?;
+        let byte = r.u8()?;
+        self.sample_buffer = buffered.then_some(byte);
+        self.silence = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for FrameCounterState {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.five_step);
+        w.bool(self.irq_inhibit);
+        w.bool(self.frame_irq);
+        w.u64(self.cycle);
+        w.u8(self.step);
+        w.u64(self.sequence_start);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.five_step = r.bool()?;
+        self.irq_inhibit = r.bool()?;
+        self.frame_irq = r.bool()?;
+        self.cycle = r.u64()?;
+        self.step = r.u8()?;
+        self.sequence_start = r.u64()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for ApuState {
+    fn save(&self, w: &mut StateWriter) {
+        self.pulse1.save(w);
+        self.pulse2.save(w);
+        self.triangle.save(w);
+        self.noise.save(w);
+        self.dmc.save(w);
+        self.frame_counter.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.version = Self::VERSION;
+        self.pulse1.load(r)?;
+        self.pulse2.load(r)?;
+        self.triangle.load(r)?;
+        self.noise.load(r)?;
+        self.dmc.load(r)?;
+        self.frame_counter.load(r)
+    }
+}
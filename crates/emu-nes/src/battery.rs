@@ -0,0 +1,120 @@
+//! Battery saves
+//!
+//! What a cartridge's battery keeps while the console is off, for a
+//! frontend to write to a `.sav` file when the game closes and load back
+//! before it starts (see [`NesSystem::battery_save`](crate::NesSystem::battery_save)).
+//!
+//! Usually that's the battery-backed PRG-RAM alone (all of it, unless an
+//! NES 2.0 header sets some aside as volatile), and the file is just its
+//! bytes, as other emulators write it. A battery cart with CHR-RAM keeps its pattern tables
+//! too, so its file is sectioned:
+//!
+//! `"LSAV"`, PRG-RAM length (u32), CHR-RAM length (u32), the PRG-RAM, then
+//! the CHR-RAM. Lengths are little-endian. A plain PRG-RAM file still loads
+//! into such a cart, leaving CHR-RAM as it was.
+
+use emu_core::{EmulatorError, Result};
+
+/// Magic bytes at the start of a sectioned battery save
+pub const MAGIC: &[u8; 4] = b"LSAV";
+
+/// Length of the sectioned header
+const HEADER_LEN: usize = 12;
+
+/// The contents of a battery save
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BatterySave<'a> {
+    /// Battery-backed PRG-RAM
+    pub prg_ram: &'a [u8],
+    /// Only in sectioned saves
+    pub chr_ram: Option<&'a [u8]>,
+}
+
+impl<'a> BatterySave<'a> {
+    /// The file contents: raw PRG-RAM, or sectioned if there's CHR-RAM
+    pub fn encode(&self) -> Vec<u8> {
+        let Some(chr_ram) = self.chr_ram else {
+            return self.prg_ram.to_vec();
+        };
+        let mut data = Vec::with_capacity(HEADER_LEN + self.prg_ram.len() + chr_ram.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&(self.prg_ram.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(chr_ram.len() as u32).to_le_bytes());
+        data.extend_from_slice(self.prg_ram);
+        data.extend_from_slice(chr_ram);
+        data
+    }
+
+    /// Split a file written by [`BatterySave::encode`]
+    ///
+    /// A file is only taken as sectioned if the section sizes add up to its
+    /// length; anything else is raw PRG-RAM.
+    pub fn decode(data: &'a [u8]) -> Self {
+        let raw = Self { prg_ram: data, chr_ram: None };
+        if data.len() < HEADER_LEN || &data[..4] != MAGIC {
+            return raw;
+        }
+        let length = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize;
+        let (prg_len, chr_len) = (length(4), length(8));
+        if prg_len.checked_add(chr_len).and_then(|len| len.checked_add(HEADER_LEN)) != Some(data.len()) {
+            return raw;
+        }
+        let (prg_ram, chr_ram) = data[HEADER_LEN..].split_at(prg_len);
+        Self { prg_ram, chr_ram: Some(chr_ram) }
+    }
+
+    /// Check the sections fit a cartridge with this much PRG-RAM and CHR-RAM
+    pub fn check(&self, prg_len: usize, chr_len: Option<usize>) -> Result<()> {
+        if self.prg_ram.len() != prg_len {
+            return Err(EmulatorError::InvalidBatterySave(format!(
+                "{} bytes of PRG-RAM, the cartridge has {}",
+                self.prg_ram.len(),
+                prg_len
+            )));
+        }
+        match (self.chr_ram.map(<[u8]>::len), chr_len) {
+            (Some(found), Some(expected)) if found != expected => Err(EmulatorError::InvalidBatterySave(format!(
+                "{} bytes of CHR-RAM, the cartridge has {}",
+                found, expected
+            ))),
+            (Some(_), None) => Err(EmulatorError::InvalidBatterySave("CHR-RAM saved for a CHR-ROM cartridge".into())),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_prg_ram_is_raw() {
+        let save = BatterySave { prg_ram: &[1, 2, 3], chr_ram: None };
+        assert_eq!(save.encode(), [1, 2, 3]);
+        assert_eq!(BatterySave::decode(&[1, 2, 3]), save);
+    }
+
+    #[test]
+    fn test_sectioned_round_trip() {
+        let save = BatterySave { prg_ram: &[1, 2, 3], chr_ram: Some(&[4, 5]) };
+        let data = save.encode();
+        assert_eq!(data, b"LSAV\x03\0\0\0\x02\0\0\0\x01\x02\x03\x04\x05");
+        assert_eq!(BatterySave::decode(&data), save);
+
+        // Sizes that don't add up mean it wasn't a header after all
+        let mut data = data;
+        data.push(6);
+        assert_eq!(BatterySave::decode(&data).chr_ram, None);
+        assert_eq!(BatterySave::decode(&data).prg_ram.len(), 18);
+    }
+
+    #[test]
+    fn test_check() {
+        let save = BatterySave { prg_ram: &[0; 4], chr_ram: Some(&[0; 2]) };
+        assert!(save.check(4, Some(2)).is_ok());
+        assert!(save.check(8, Some(2)).is_err());
+        assert!(save.check(4, Some(8)).is_err());
+        assert!(save.check(4, None).is_err());
+        assert!(BatterySave { chr_ram: None, ..save }.check(4, Some(2)).is_ok());
+    }
+}
@@ -1,23 +1,76 @@
-/// NES Cartridge and iNES ROM file format support
-/// 
-/// Implements loading and parsing of iNES format ROM files (.nes)
-/// and provides memory mapping for different mappers.
+//! NES Cartridge and iNES ROM file format support
+//! 
+//! Implements loading and parsing of iNES format ROM files (.nes)
+//! and hands memory mapping to the board's mapper.
+//!
+//! With the `romdb` feature, headers of dumps known to the ROM database
+//! are corrected before the mapper is chosen; see [`crate::romdb`].
 
-use std::fs::File;
-use std::io::Read;
+use std::fmt;
 use std::path::Path;
+use crate::expansion_audio::ExpansionAudio;
+use crate::mapper::{self, Mapper};
+use crate::patch::Patch;
+use crate::savestate::{crc32, Snapshot, StateReader, StateWriter};
+use crate::state::Bytes;
 use emu_core::{EmulatorError, Result};
+use tracing::warn;
 
 /// Mirroring mode for nametables
+///
+/// Boards with mapper-controlled layouts may add modes.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Mirroring {
     Horizontal,
     Vertical,
     FourScreen,
+    /// All four nametables are the first 1KB of VRAM
+    SingleScreenLower,
+    /// All four nametables are the second 1KB of VRAM
+    SingleScreenUpper,
+}
+
+/// TV system a cartridge was made for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    /// Works on either
+    Dual,
+}
+
+/// One window of CPU or PPU address space and the ROM behind it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BankMapping {
+    /// First address of the window
+    pub window_start: u16,
+    /// Window size in bytes
+    pub size: u16,
+    /// Which `size`-byte bank of the ROM is mapped, `None` for open bus
+    pub bank_index: Option<usize>,
+    /// Offset into PRG-ROM or CHR-ROM/RAM of `window_start`, `None` for open bus
+    pub rom_offset: Option<usize>,
+}
+
+/// Which banks the mapper currently has mapped, for debugging
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BankState {
+    /// The four 8KB PRG windows at $8000, $A000, $C000 and $E000
+    pub prg: Vec<BankMapping>,
+    /// The eight 1KB CHR windows from $0000 to $1C00
+    pub chr: Vec<BankMapping>,
+    /// Nametable mirroring
+    pub mirroring: Mirroring,
+    /// Whether the mapper is asserting IRQ
+    pub irq_pending: bool,
+    /// IRQ counter value, for mappers that have one
+    pub irq_counter: Option<u16>,
 }
 
 /// iNES file format header
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct INesHeader {
     /// Number of 16KB PRG-ROM banks
     pub prg_rom_banks: u8,
@@ -31,6 +84,12 @@ pub struct INesHeader {
     pub has_battery: bool,
     /// Has 512-byte trainer
     pub has_trainer: bool,
+    /// PRG-RAM size in bytes, battery-backed or not
+    pub prg_ram_size: usize,
+    /// How much of the PRG-RAM is battery-backed (PRG-NVRAM), in bytes
+    pub prg_nvram_size: usize,
+    /// TV system
+    pub region: Region,
 }
 
 impl INesHeader {
@@ -67,6 +126,26 @@ impl INesHeader {
         let has_battery = flags6 & 0x02 != 0;
         let has_trainer = flags6 & 0x04 != 0;
         
+        let (prg_ram_size, prg_nvram_size, region) = if flags7 & 0x0C == 0x08 {
+            // NES 2.0: byte 10 holds volatile and battery-backed sizes
+            // as shift counts, and byte 12 the timing
+            let volatile = nes2_ram_size(bytes[10] & 0x0F);
+            let nvram = nes2_ram_size(bytes[10] >> 4);
+            let region = match bytes[12] & 0x03 {
+                0 => Region::Ntsc,
+                2 => Region::Dual,
+                // PAL, and Dendy, which runs PAL-length frames
+                _ => Region::Pal,
+            };
+            (volatile + nvram, nvram, region)
+        } else {
+            // Byte 8 counts 8KB units, with 0 meaning 8KB for compatibility;
+            // a battery keeps all of it
+            let size = bytes[8].max(1) as usize * 0x2000;
+            let region = if bytes[9] & 0x01 != 0 { Region::Pal } else { Region::Ntsc };
+            (size, if has_battery { size } else { 0 }, region)
+        };
+        
         Ok(Self {
             prg_rom_banks,
             chr_rom_banks,
@@ -74,75 +153,276 @@ impl INesHeader {
             mirroring,
             has_battery,
             has_trainer,
+            prg_ram_size,
+            prg_nvram_size,
+            region,
         })
     }
 }
 
+/// Bytes of RAM for a NES 2.0 size field: none for 0, otherwise 64 << `shift`
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+/// What a frontend might want to show about a loaded cartridge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeInfo {
+    /// CRC32 of the PRG and CHR data, after any patch
+    pub crc32: u32,
+    /// Mapper in use
+    pub mapper: u8,
+    /// Nametable mirroring the board was wired for
+    pub mirroring: Mirroring,
+    /// PRG-RAM size in bytes, battery-backed or not
+    pub prg_ram_size: usize,
+    /// How much of the PRG-RAM is battery-backed, in bytes
+    pub prg_nvram_size: usize,
+    /// TV system
+    pub region: Region,
+    /// The ROM database knew this dump and overrode its header
+    pub header_corrected: bool,
+    /// File name of the patch applied when loading, if any
+    pub patch: Option<String>,
+}
+
 /// NES Cartridge
+#[derive(Clone)]
 pub struct Cartridge {
     /// PRG-ROM (program code)
-    pub(crate) prg_rom: Vec<u8>,
+    prg_rom: Vec<u8>,
     /// CHR-ROM (graphics data) - may be empty if using CHR-RAM
-    pub(crate) chr_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
     /// Cartridge header
-    pub(crate) header: INesHeader,
-    /// Mapper state (for banking)
-    pub(crate) mapper_state: MapperState,
-}
-
-/// Mapper-specific state
-#[derive(Debug, Default)]
-pub(crate) struct MapperState {
-    /// Current PRG bank (for mapper 66)
-    pub(crate) prg_bank: u8,
-    /// Current CHR bank (for mapper 66)
-    pub(crate) chr_bank: u8,
+    header: INesHeader,
+    /// Banking hardware, chosen from the header
+    mapper: Box<dyn Mapper>,
+    /// PRG-RAM at $6000-$7FFF, as much as the header declares: the
+    /// battery-backed part first, then the volatile part
+    prg_ram: Vec<u8>,
+    /// CRC32 of the PRG and CHR data
+    crc32: u32,
+    /// The header was corrected from the ROM database
+    header_corrected: bool,
+    /// Name of the patch applied when loading
+    patch: Option<String>,
+    /// RAM cart: writes to $8000-$FFFF store into `prg_rom`
+    prg_writable: bool,
+    /// Sound chip on the board, if it has one
+    expansion_audio: Option<Box<dyn ExpansionAudio>>,
 }
 
 impl Cartridge {
     /// Load a cartridge from an iNES file
+    ///
+    /// An `.ips` or `.bps` patch next to it with the same name is applied
+    /// (see [`Patch::find_for`]).
     pub fn load(path: &Path) -> Result<Self> {
-        let mut file = File::open(path)
+        Self::load_with_patch(path, None)
+    }
+    
+    /// Load a cartridge from an iNES file, applying the patch at `patch`,
+    /// or the one next to the ROM if that's None
+    pub fn load_with_patch(path: &Path, patch: Option<&Path>) -> Result<Self> {
+        let data = std::fs::read(path)
             .map_err(|e| EmulatorError::RomLoadError(format!("Failed to open ROM: {}", e)))?;
+        match patch.map(Path::to_path_buf).or_else(|| Patch::find_for(path)) {
+            Some(patch) => Self::from_bytes_with_patch(&data, &Patch::load(&patch)?),
+            None => Self::from_bytes(&data),
+        }
+    }
+    
+    /// Patch an in-memory iNES image, header included, then parse it
+    pub fn from_bytes_with_patch(data: &[u8], patch: &Patch) -> Result<Self> {
+        let mut cartridge = Self::from_bytes(&patch.apply(data)?)?;
+        cartridge.patch = Some(patch.name.clone());
+        Ok(cartridge)
+    }
+    
+    /// Parse a cartridge from an in-memory iNES image
+    ///
+    /// The header is trusted for sizes but checked against the data: a
+    /// short image is an error, while trailing bytes (overdumps) are logged
+    /// and ignored. With the `romdb` feature, the embedded ROM database
+    /// then gets to correct the mapper, mirroring, PRG-RAM and region.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        #[cfg(feature = "romdb")]
+        return Self::from_bytes_with_db(data, crate::romdb::RomDb::embedded());
+        #[cfg(not(feature = "romdb"))]
+        Self::parse(data, |_, _| false)
+    }
+    
+    /// Like [`Cartridge::from_bytes`], with header corrections from `db`
+    #[cfg(feature = "romdb")]
+    pub fn from_bytes_with_db(data: &[u8], db: &crate::romdb::RomDb) -> Result<Self> {
+        Self::parse(data, |crc, header| db.lookup(crc).is_some_and(|entry| entry.apply(header)))
+    }
+    
+    /// Parse an iNES image, letting `fix_header` correct the header given
+    /// the data's CRC32 (returning whether it changed anything)
+    fn parse(data: &[u8], fix_header: impl FnOnce(u32, &mut INesHeader) -> bool) -> Result<Self> {
+        let mut header = INesHeader::parse(data)?;
         
-        // Read header
-        let mut header_bytes = [0u8; 16];
-        file.read_exact(&mut header_bytes)
-            .map_err(|e| EmulatorError::RomLoadError(format!("Failed to read header: {}", e)))?;
+        if header.prg_rom_banks == 0 {
+            return Err(EmulatorError::RomLoadError("Header declares 0 PRG-ROM banks".into()));
+        }
         
-        let header = INesHeader::parse(&header_bytes)?;
+        let prg_size = header.prg_rom_banks as usize * 0x4000; // 16KB banks
+        let chr_size = header.chr_rom_banks as usize * 0x2000; // 8KB banks
+        let trainer_size = if header.has_trainer { 512 } else { 0 };
+        let prg_start = 16 + trainer_size;
+        let chr_start = prg_start + prg_size;
+        let expected = chr_start + chr_size;
         
-        // Skip trainer if present
-        if header.has_trainer {
-            let mut trainer = [0u8; 512];
-            file.read_exact(&mut trainer)
-                .map_err(|e| EmulatorError::RomLoadError(format!("Failed to read trainer: {}", e)))?;
+        if data.len() < expected {
+            return Err(EmulatorError::RomTooShort {
+                expected,
+                found: data.len(),
+            });
+        }
+        if data.len() > expected {
+            warn!(
+                "ROM has {} bytes beyond the {} declared by its header; ignoring them",
+                data.len() - expected,
+                expected
+            );
         }
         
-        // Read PRG-ROM
-        let prg_size = header.prg_rom_banks as usize * 0x4000; // 16KB banks
-        let mut prg_rom = vec![0u8; prg_size];
-        file.read_exact(&mut prg_rom)
-            .map_err(|e| EmulatorError::RomLoadError(format!("Failed to read PRG-ROM: {}", e)))?;
+        let crc32 = crc32(&data[prg_start..expected]);
+        let original_mapper = header.mapper;
+        let header_corrected = fix_header(crc32, &mut header);
+        if header_corrected {
+            warn!(
+                "Header of ROM {:08X} corrected from the ROM database (mapper {} -> {})",
+                crc32, original_mapper, header.mapper
+            );
+        }
         
-        // Read CHR-ROM (if present)
-        let chr_size = header.chr_rom_banks as usize * 0x2000; // 8KB banks
+        let prg_rom = data[prg_start..chr_start].to_vec();
         let chr_rom = if chr_size > 0 {
-            let mut chr = vec![0u8; chr_size];
-            file.read_exact(&mut chr)
-                .map_err(|e| EmulatorError::RomLoadError(format!("Failed to read CHR-ROM: {}", e)))?;
-            chr
+            data[chr_start..expected].to_vec()
         } else {
             // CHR-RAM: 8KB
             vec![0; 0x2000]
         };
         
-        Ok(Self {
+        let mut cartridge = Self {
+            mapper: mapper::create(&header, prg_size, chr_size)?,
+            prg_ram: vec![0; header.prg_ram_size],
             prg_rom,
             chr_rom,
             header,
-            mapper_state: MapperState::default(),
-        })
+            crc32,
+            header_corrected,
+            patch: None,
+            prg_writable: false,
+            expansion_audio: None,
+        };
+        if let Some(chip) = mapper::expansion_audio(&cartridge.header) {
+            cartridge.set_expansion_audio(chip);
+        }
+        
+        if cartridge.reset_vector() == 0xFFFF {
+            warn!(
+                "Reset vector is $FFFF; the PRG-ROM is probably blank, truncated, \
+                 or the header declares the wrong bank count"
+            );
+        }
+        
+        Ok(cartridge)
+    }
+    
+    /// A 16KB NROM board around `prg_rom`, with 8KB of CHR and PRG-RAM,
+    /// for tests that only need code to run
+    pub(crate) fn with_prg_rom(prg_rom: Vec<u8>) -> Self {
+        let header = INesHeader {
+            prg_rom_banks: 1,
+            chr_rom_banks: 1,
+            mapper: 0,
+            mirroring: Mirroring::Horizontal,
+            has_battery: false,
+            has_trainer: false,
+            prg_ram_size: 0x2000,
+            prg_nvram_size: 0,
+            region: Region::Ntsc,
+        };
+        Self {
+            mapper: mapper::create(&header, prg_rom.len(), 0x2000).expect("NROM is always supported"),
+            prg_ram: vec![0; header.prg_ram_size],
+            crc32: crc32(&prg_rom),
+            prg_rom,
+            chr_rom: vec![0; 0x2000],
+            header,
+            header_corrected: false,
+            patch: None,
+            prg_writable: false,
+            expansion_audio: None,
+        }
+    }
+    
+    /// A board with 32KB of RAM where PRG-ROM would be, so tests can write
+    /// code and vectors at any time (see [`NesSystem::with_ram_cart`](crate::NesSystem::with_ram_cart))
+    ///
+    /// Otherwise it's the NROM board of [`Self::with_prg_rom`]. The RAM
+    /// starts zeroed and keeps its contents through resets.
+    pub(crate) fn ram_cart() -> Self {
+        Self { prg_writable: true, ..Self::with_prg_rom(vec![0; 0x8000]) }
+    }
+    
+    /// Whether $8000-$FFFF is RAM, as on [`Self::ram_cart`]
+    pub fn is_ram_cart(&self) -> bool {
+        self.prg_writable
+    }
+    
+    /// Get the reset vector ($FFFC-$FFFD) as mapped at power-on
+    pub fn reset_vector(&self) -> u16 {
+        u16::from_le_bytes([self.read_prg(0xFFFC), self.read_prg(0xFFFD)])
+    }
+    
+    /// Get the CRC32 of the PRG and CHR data
+    ///
+    /// The header is left out so that header fixes don't change a ROM's
+    /// identity.
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+    
+    /// Summary of the cartridge as loaded, after any header correction
+    pub fn info(&self) -> CartridgeInfo {
+        CartridgeInfo {
+            crc32: self.crc32,
+            mapper: self.header.mapper,
+            mirroring: self.header.mirroring,
+            prg_ram_size: self.header.prg_ram_size,
+            prg_nvram_size: self.header.prg_nvram_size,
+            region: self.header.region,
+            header_corrected: self.header_corrected,
+            patch: self.patch.clone(),
+        }
+    }
+    
+    /// PRG-RAM at $6000-$7FFF, all of it
+    pub fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+    
+    /// Mutable PRG-RAM
+    pub fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+    
+    /// The battery-backed part of PRG-RAM, which goes in a `.sav` file
+    pub fn prg_nvram(&self) -> &[u8] {
+        &self.prg_ram[..self.header.prg_nvram_size]
+    }
+    
+    /// Mutable battery-backed PRG-RAM, e.g. to restore a battery save
+    pub fn prg_nvram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram[..self.header.prg_nvram_size]
     }
     
     /// Get PRG-ROM data
@@ -165,135 +445,220 @@ impl Cartridge {
         &self.header
     }
     
-    /// Read from PRG-ROM address space ($8000-$FFFF)
-    /// Implements Mapper 0 (NROM) and Mapper 66 (GxROM) logic
-    pub fn read_prg(&self, addr: u16) -> u8 {
-        match self.header.mapper {
-            0 => self.read_prg_mapper0(addr),
-            66 => self.read_prg_mapper66(addr),
-            _ => {
-                // Unsupported mapper - return open bus
-                0xFF
-            }
-        }
+    /// Whether the pattern tables are RAM, there being no CHR-ROM
+    pub fn has_chr_ram(&self) -> bool {
+        self.header.chr_rom_banks == 0
     }
     
-    /// Mapper 0 (NROM) PRG-ROM read
-    /// - 16KB: $8000-$BFFF and $C000-$FFFF mirror the same 16KB
-    /// - 32KB: $8000-$FFFF is linear
-    fn read_prg_mapper0(&self, addr: u16) -> u8 {
-        let rom_addr = if self.prg_rom.len() <= 0x4000 {
-            // 16KB ROM: mirror at $C000
-            (addr & 0x3FFF) as usize
+    /// Number of `bank_size` banks in `len` bytes of ROM, at least one
+    pub fn bank_count(len: usize, bank_size: usize) -> usize {
+        (len / bank_size).max(1)
+    }
+    
+    /// Mask for bank numbers into `len` bytes of `bank_size` banks
+    ///
+    /// A board only wires as many bank lines as its ROM needs, so higher
+    /// bits of a bank selection are dropped and the banks mirror.
+    pub fn bank_mask(len: usize, bank_size: usize) -> usize {
+        Self::bank_count(len, bank_size).next_power_of_two() - 1
+    }
+    
+    /// Put the board back in its power-on state
+    ///
+    /// The mapper loses its bank registers, the sound chip (if any) goes
+    /// quiet, and CHR-RAM is cleared, as is PRG-RAM other than the
+    /// battery-backed part.
+    pub fn power_up(&mut self) {
+        self.prg_ram[self.header.prg_nvram_size..].fill(0);
+        let chr_len = if self.header.chr_rom_banks == 0 {
+            self.chr_rom.fill(0);
+            0
         } else {
-            // 32KB ROM: linear mapping
-            (addr - 0x8000) as usize
+            self.chr_rom.len()
         };
-        
-        if rom_addr < self.prg_rom.len() {
-            self.prg_rom[rom_addr]
-        } else {
-            0xFF
+        self.mapper = mapper::create(&self.header, self.prg_rom.len(), chr_len)
+            .expect("the mapper was supported when the cartridge loaded");
+        if let Some(chip) = &mut self.expansion_audio {
+            chip.reset();
         }
     }
     
-    /// Mapper 66 (GxROM) PRG-ROM read
-    /// - 32KB banks switchable
-    /// - Bank selected by bits 4-5 of mapper register
-    fn read_prg_mapper66(&self, addr: u16) -> u8 {
-        // PRG-ROM is only at $8000-$FFFF
-        if addr < 0x8000 {
-            return 0xFF; // Open bus for $4020-$7FFF
+    /// Put a sound chip on the board (see [`crate::expansion_audio`])
+    ///
+    /// Loading does this for the boards [`mapper::expansion_audio`] knows
+    /// carry one.
+    pub(crate) fn set_expansion_audio(&mut self, chip: Box<dyn ExpansionAudio>) {
+        self.expansion_audio = Some(chip);
+    }
+    
+    /// The board's sound chip, if it has one
+    pub(crate) fn expansion_audio_mut(&mut self) -> Option<&mut (dyn ExpansionAudio + 'static)> {
+        self.expansion_audio.as_deref_mut()
+    }
+    
+    /// Whether the board has a sound chip, mixed with the APU's output
+    pub fn has_expansion_audio(&self) -> bool {
+        self.expansion_audio.is_some()
+    }
+    
+    /// Hand a write to $4020-$FFFF to the sound chip, if there is one
+    fn write_expansion_audio(&mut self, addr: u16, value: u8) {
+        if let Some(chip) = &mut self.expansion_audio {
+            chip.write_register(addr, value);
         }
-        
-        let bank = self.mapper_state.prg_bank as usize;
-        let offset = (addr - 0x8000) as usize;
-        let rom_addr = (bank * 0x8000) + offset;
-        
-        if rom_addr < self.prg_rom.len() {
-            self.prg_rom[rom_addr]
-        } else {
-            0xFF
+    }
+    
+    /// Read from the expansion area ($4020-$5FFF), `None` for open bus
+    pub fn read_expansion(&self, addr: u16) -> Option<u8> {
+        self.mapper.read_expansion(addr)
+    }
+    
+    /// Write to the expansion area ($4020-$5FFF)
+    pub fn write_expansion(&mut self, addr: u16, value: u8) {
+        self.mapper.write_expansion(addr, value);
+        self.write_expansion_audio(addr, value);
+    }
+    
+    /// Offset into PRG-RAM that `addr` ($6000-$7FFF) reaches, if there's any
+    ///
+    /// A 2KB or 4KB RAM repeats across the 8KB window, as its upper address
+    /// lines aren't connected. Only the first 8KB of a bigger one is
+    /// reachable; no supported board banks it.
+    fn prg_ram_offset(&self, addr: u16) -> Option<usize> {
+        let len = self.prg_ram.len();
+        (len > 0).then(|| (addr & 0x1FFF) as usize % len)
+    }
+    
+    /// Read PRG-RAM ($6000-$7FFF), `None` for open bus when there's none
+    pub fn read_prg_ram(&self, addr: u16) -> Option<u8> {
+        self.prg_ram_offset(addr).map(|offset| self.prg_ram[offset])
+    }
+    
+    /// Write PRG-RAM ($6000-$7FFF)
+    ///
+    /// The mapper sees the write too, for boards with registers there.
+    pub fn write_prg_ram(&mut self, addr: u16, value: u8) {
+        if let Some(offset) = self.prg_ram_offset(addr) {
+            self.prg_ram[offset] = value;
         }
+        self.mapper.write_prg(addr, value);
+        self.write_expansion_audio(addr, value);
     }
     
-    /// Write to PRG address space (for mapper register updates)
-    pub fn write_prg(&mut self, addr: u16, value: u8) {
-        match self.header.mapper {
-            0 => {
-                // Mapper 0 has no writable registers
-            }
-            66 => self.write_prg_mapper66(addr, value),
-            _ => {}
+    /// Read from PRG-ROM space ($8000-$FFFF)
+    ///
+    /// Lower addresses read as open bus ($FF).
+    pub fn read_prg(&self, addr: u16) -> u8 {
+        if addr < 0x8000 {
+            return 0xFF;
         }
+        self.mapper.read_prg(&self.prg_rom, addr)
     }
     
-    /// Mapper 66 (GxROM) register write
-    /// Write to $8000-$FFFF sets banking
-    /// Bits 4-5: PRG bank (0-3)
-    /// Bits 0-1: CHR bank (0-3)
-    fn write_prg_mapper66(&mut self, _addr: u16, value: u8) {
-        self.mapper_state.prg_bank = (value >> 4) & 0x03;
-        self.mapper_state.chr_bank = value & 0x03;
+    /// Write to PRG-ROM space ($8000-$FFFF), for mapper register updates
+    ///
+    /// On a RAM cart the value is stored as well.
+    pub fn write_prg(&mut self, addr: u16, value: u8) {
+        if self.prg_writable && addr >= 0x8000 {
+            if let Some(offset) = self.mapper.prg_offset(self.prg_rom.len(), addr) {
+                self.prg_rom[offset] = value;
+            }
+        }
+        self.mapper.write_prg(addr, value);
+        self.write_expansion_audio(addr, value);
     }
     
     /// Read from CHR-ROM/RAM address space ($0000-$1FFF)
     /// Used by PPU for pattern tables
     pub fn read_chr(&self, addr: u16) -> u8 {
-        match self.header.mapper {
-            0 => {
-                // Mapper 0: direct access
-                let addr = addr as usize;
-                if addr < self.chr_rom.len() {
-                    self.chr_rom[addr]
-                } else {
-                    0
-                }
-            }
-            66 => {
-                // Mapper 66: 8KB banks
-                let bank = self.mapper_state.chr_bank as usize;
-                let offset = addr as usize;
-                let chr_addr = (bank * 0x2000) + offset;
-                
-                if chr_addr < self.chr_rom.len() {
-                    self.chr_rom[chr_addr]
-                } else {
-                    0
-                }
-            }
-            _ => 0,
-        }
+        self.mapper.read_chr(&self.chr_rom, addr)
     }
     
     /// Write to CHR-ROM/RAM address space ($0000-$1FFF)
     /// Only works for CHR-RAM (when chr_rom_banks == 0)
     pub fn write_chr(&mut self, addr: u16, value: u8) {
-        match self.header.mapper {
-            0 => {
-                // Mapper 0: direct access if CHR-RAM
-                if self.header.chr_rom_banks == 0 {
-                    let addr = addr as usize;
-                    if addr < self.chr_rom.len() {
-                        self.chr_rom[addr] = value;
-                    }
-                }
-            }
-            66 => {
-                // Mapper 66: CHR-RAM write through bank
-                if self.header.chr_rom_banks == 0 {
-                    let bank = self.mapper_state.chr_bank as usize;
-                    let offset = addr as usize;
-                    let chr_addr = (bank * 0x2000) + offset;
-                    
-                    if chr_addr < self.chr_rom.len() {
-                        self.chr_rom[chr_addr] = value;
-                    }
-                }
-            }
-            _ => {}
+        if self.header.chr_rom_banks == 0 {
+            self.mapper.write_chr(&mut self.chr_rom, addr, value);
         }
     }
+    
+    /// The 8KB of pattern tables the mapper currently has at $0000-$1FFF
+    pub fn mapped_chr(&self) -> Vec<u8> {
+        (0..0x2000).map(|addr| self.read_chr(addr)).collect()
+    }
+    
+    /// Current nametable mirroring, which some mappers switch at runtime
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.mirroring()
+    }
+    
+    /// Run the mapper for one CPU cycle, for boards with a cycle-counting IRQ
+    pub fn clock_cpu(&mut self) {
+        self.mapper.clock_cpu();
+    }
+    
+    /// Whether the mapper is asserting IRQ
+    pub fn irq_pending(&self) -> bool {
+        self.mapper.irq_pending()
+    }
+    
+    /// Banks currently mapped into each PRG and CHR window
+    pub fn bank_state(&self) -> BankState {
+        self.mapper.bank_state(self.prg_rom.len(), self.chr_rom.len())
+    }
+    
+    /// Selected PRG bank
+    pub fn prg_bank(&self) -> usize {
+        self.mapper.prg_bank()
+    }
+    
+    /// Selected CHR bank
+    pub fn chr_bank(&self) -> usize {
+        self.mapper.chr_bank()
+    }
+}
+
+impl fmt::Debug for Cartridge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cartridge")
+            .field("prg_rom", &Bytes(&self.prg_rom))
+            .field("chr_rom", &Bytes(&self.chr_rom))
+            .field("header", &self.header)
+            .field("mapper", &self.mapper)
+            .field("prg_ram", &Bytes(&self.prg_ram))
+            .field("crc32", &format_args!("{:08X}", self.crc32))
+            .field("header_corrected", &self.header_corrected)
+            .field("patch", &self.patch)
+            .field("prg_writable", &self.prg_writable)
+            .field("expansion_audio", &self.expansion_audio)
+            .finish()
+    }
+}
+
+impl Snapshot for Cartridge {
+    fn save(&self, w: &mut StateWriter) {
+        // A RAM cart's code is state too; ROM isn't
+        if self.prg_writable {
+            w.bytes(&self.prg_rom);
+        }
+        w.bytes(&self.prg_ram);
+        self.mapper.save(w);
+        if let Some(chip) = &self.expansion_audio {
+            chip.save(w);
+        }
+    }
+    
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        if self.prg_writable {
+            r.bytes_into(&mut self.prg_rom)?;
+        }
+        r.bytes_into(&mut self.prg_ram)?;
+        self.mapper.load(r)?;
+        if let Some(chip) = &mut self.expansion_audio {
+            chip.load(r)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -362,4 +727,220 @@ mod tests {
         
         assert!(INesHeader::parse(&header_bytes).is_err());
     }
+    
+    /// Build an iNES image with the given header bank counts and data length
+    fn rom_image(prg_banks: u8, chr_banks: u8, data_len: usize) -> Vec<u8> {
+        let mut image = vec![b'N', b'E', b'S', 0x1A, prg_banks, chr_banks, 0, 0];
+        image.resize(16, 0);
+        image.extend((0..data_len).map(|i| i as u8));
+        image
+    }
+    
+    #[test]
+    fn test_from_bytes_zero_prg_banks() {
+        let image = rom_image(0, 1, 0x2000);
+        assert!(matches!(Cartridge::from_bytes(&image), Err(EmulatorError::RomLoadError(_))));
+    }
+    
+    #[test]
+    fn test_from_bytes_too_short() {
+        let image = rom_image(2, 1, 0x4000);
+        match Cartridge::from_bytes(&image) {
+            Err(EmulatorError::RomTooShort { expected, found }) => {
+                assert_eq!(expected, 16 + 0x8000 + 0x2000);
+                assert_eq!(found, 16 + 0x4000);
+            }
+            other => panic!("expected RomTooShort, got {:?}", other.map(|_| ())),
+        }
+    }
+    
+    #[test]
+    fn test_from_bytes_ignores_overdump() {
+        // Header says 1 PRG + 1 CHR bank, file holds twice that
+        let image = rom_image(1, 1, 2 * (0x4000 + 0x2000));
+        let cartridge = Cartridge::from_bytes(&image).unwrap();
+        
+        assert_eq!(cartridge.prg_rom().len(), 0x4000);
+        assert_eq!(cartridge.chr_rom().len(), 0x2000);
+        assert_eq!(cartridge.chr_rom()[0], image[16 + 0x4000]);
+    }
+    
+    #[test]
+    fn test_reset_vector() {
+        let mut image = rom_image(1, 0, 0x4000);
+        image[16 + 0x3FFC] = 0x34;
+        image[16 + 0x3FFD] = 0x82;
+        assert_eq!(Cartridge::from_bytes(&image).unwrap().reset_vector(), 0x8234);
+        
+        // Blank PRG-ROM still loads, but the vector is the $FFFF tell
+        let mut image = rom_image(1, 0, 0);
+        image.resize(16 + 0x4000, 0xFF);
+        assert_eq!(Cartridge::from_bytes(&image).unwrap().reset_vector(), 0xFFFF);
+    }
+    
+    #[test]
+    fn test_prg_ram_follows_the_header() {
+        // NROM with the default 8KB
+        let mut cart = Cartridge::from_bytes(&rom_image(1, 1, 0x6000)).unwrap();
+        cart.write_prg_ram(0x7FFF, 0x5A);
+        assert_eq!(cart.read_prg_ram(0x7FFF), Some(0x5A));
+        assert_eq!(cart.read_prg(0x7FFF), 0xFF);
+        cart.power_up();
+        assert_eq!(cart.read_prg_ram(0x7FFF), Some(0));
+        
+        // Battery-backed RAM survives a power cycle
+        let mut image = rom_image(1, 1, 0x6000);
+        image[6] |= 0x02;
+        let mut cart = Cartridge::from_bytes(&image).unwrap();
+        cart.write_prg_ram(0x6000, 0x5A);
+        cart.power_up();
+        assert_eq!(cart.read_prg_ram(0x6000), Some(0x5A));
+    }
+    
+    /// [`rom_image`] with an NES 2.0 header declaring `ram` and `nvram`
+    /// as size shifts
+    fn nes2_image(ram: u8, nvram: u8) -> Vec<u8> {
+        let mut image = rom_image(1, 1, 0x6000);
+        image[7] |= 0x08;
+        image[10] = nvram << 4 | ram;
+        image
+    }
+    
+    #[test]
+    fn test_nes2_ram_sizes() {
+        let sizes = |image: &[u8]| {
+            let header = INesHeader::parse(image).unwrap();
+            (header.prg_ram_size, header.prg_nvram_size)
+        };
+        // 64 << shift, with 0 meaning none
+        assert_eq!(sizes(&nes2_image(5, 0)), (0x800, 0));
+        assert_eq!(sizes(&nes2_image(7, 0)), (0x2000, 0));
+        assert_eq!(sizes(&nes2_image(0, 7)), (0x2000, 0x2000));
+        assert_eq!(sizes(&nes2_image(7, 5)), (0x2800, 0x800));
+        assert_eq!(sizes(&nes2_image(0, 0)), (0, 0));
+        assert_eq!(sizes(&nes2_image(1, 0)), (128, 0));
+        
+        // iNES 1.0 counts 8KB units in byte 8, 0 still being 8KB, and a
+        // battery keeps all of it
+        let mut image = rom_image(1, 1, 0x6000);
+        assert_eq!(sizes(&image), (0x2000, 0));
+        image[8] = 2;
+        image[6] |= 0x02;
+        assert_eq!(sizes(&image), (0x4000, 0x4000));
+        
+        // NES 2.0 timing is in byte 12, not byte 9
+        let mut image = nes2_image(7, 0);
+        image[9] = 0x01;
+        assert_eq!(INesHeader::parse(&image).unwrap().region, Region::Ntsc);
+        image[12] = 0x01;
+        assert_eq!(INesHeader::parse(&image).unwrap().region, Region::Pal);
+        image[12] = 0x02;
+        assert_eq!(INesHeader::parse(&image).unwrap().region, Region::Dual);
+    }
+    
+    #[test]
+    fn test_small_prg_ram_mirrors_across_the_window() {
+        let mut cart = Cartridge::from_bytes(&nes2_image(5, 0)).unwrap();
+        assert_eq!(cart.info().prg_ram_size, 0x800);
+        assert_eq!(cart.prg_ram().len(), 0x800);
+        cart.write_prg_ram(0x6000, 0xA5);
+        for addr in [0x6800, 0x7000, 0x7800] {
+            assert_eq!(cart.read_prg_ram(addr), Some(0xA5), "${:04X}", addr);
+        }
+        // A size probe: the write at $7801 lands on $6001
+        cart.write_prg_ram(0x7801, 0x3C);
+        assert_eq!(cart.read_prg_ram(0x6001), Some(0x3C));
+        
+        let mut cart = Cartridge::from_bytes(&nes2_image(7, 0)).unwrap();
+        cart.write_prg_ram(0x6000, 0xA5);
+        for addr in [0x6800, 0x7000, 0x7800] {
+            assert_eq!(cart.read_prg_ram(addr), Some(0), "${:04X}", addr);
+        }
+        
+        // No RAM at all is open bus
+        let mut cart = Cartridge::from_bytes(&nes2_image(0, 0)).unwrap();
+        cart.write_prg_ram(0x6000, 0xA5);
+        assert_eq!(cart.read_prg_ram(0x6000), None);
+    }
+    
+    #[test]
+    fn test_only_nvram_survives_power_cycles() {
+        // 2KB battery-backed, then 8KB volatile
+        let mut image = nes2_image(7, 5);
+        image[6] |= 0x02;
+        let mut cart = Cartridge::from_bytes(&image).unwrap();
+        assert_eq!((cart.info().prg_ram_size, cart.info().prg_nvram_size), (0x2800, 0x800));
+        cart.write_prg_ram(0x6000, 0x11);
+        cart.write_prg_ram(0x6800, 0x22);
+        assert_eq!(cart.prg_nvram(), &cart.prg_ram()[..0x800]);
+        cart.power_up();
+        assert_eq!(cart.read_prg_ram(0x6000), Some(0x11));
+        assert_eq!(cart.read_prg_ram(0x6800), Some(0));
+    }
+    
+    #[test]
+    fn test_vrc6_boards_carry_their_sound_chip() {
+        for (flags6, flags7) in [(0x80, 0x10), (0xA0, 0x10)] {
+            let mut image = rom_image(2, 1, 0xA000);
+            image[6] = flags6;
+            image[7] = flags7;
+            assert!(Cartridge::from_bytes(&image).unwrap().has_expansion_audio());
+        }
+        assert!(!Cartridge::from_bytes(&rom_image(2, 1, 0xA000)).unwrap().has_expansion_audio());
+    }
+    
+    #[test]
+    fn test_nina001_registers_sit_on_prg_ram() {
+        // Mapper 34 with CHR-ROM is NINA-001
+        let mut image = rom_image(4, 8, 0x10000 + 0x10000);
+        image[6] |= 0x20;
+        image[7] |= 0x20;
+        let mut cart = Cartridge::from_bytes(&image).unwrap();
+        assert_eq!(cart.header().mapper, 34);
+        
+        cart.write_prg_ram(0x6000, 0x42);
+        cart.write_prg_ram(0x7FFE, 0x05);
+        assert_eq!(cart.read_prg_ram(0x6000), Some(0x42));
+        assert_eq!(cart.read_prg_ram(0x7FFE), Some(0x05));
+        assert_eq!(cart.bank_state().chr[0].rom_offset, Some(5 * 0x1000));
+    }
+    
+    #[test]
+    fn test_gxrom_bank_3_mirrors_bank_1_of_two() {
+        // 64KB of PRG-ROM, each 32KB bank signed with its number
+        let mut image = rom_image(4, 1, 0x10000 + 0x2000);
+        image[6] |= 0x20;
+        image[7] |= 0x40;
+        image[16] = 0xB0;
+        image[16 + 0x8000] = 0xB1;
+        let mut cart = Cartridge::from_bytes(&image).unwrap();
+        assert_eq!(cart.header().mapper, 66);
+        
+        cart.write_prg(0x8000, 0x30);
+        assert_eq!(cart.read_prg(0x8000), 0xB1);
+        assert_eq!(cart.prg_bank(), 1);
+    }
+    
+    #[test]
+    fn test_only_ram_carts_store_prg_writes() {
+        let mut rom = Cartridge::with_prg_rom(vec![0x11; 0x8000]);
+        rom.write_prg(0x8000, 0x42);
+        assert_eq!(rom.read_prg(0x8000), 0x11);
+        assert!(!rom.is_ram_cart());
+        
+        let mut ram = Cartridge::ram_cart();
+        ram.write_prg(0x8000, 0x42);
+        ram.write_prg(0xFFFD, 0x80);
+        assert_eq!(ram.read_prg(0x8000), 0x42);
+        assert_eq!(ram.reset_vector(), 0x8000);
+        
+        // Code is kept by a power cycle and carried by savestates
+        ram.power_up();
+        assert_eq!(ram.read_prg(0x8000), 0x42);
+        let mut w = StateWriter::new();
+        ram.save(&mut w);
+        let mut restored = Cartridge::ram_cart();
+        restored.load(&mut StateReader::new(&w.finish())).unwrap();
+        assert_eq!(restored.prg_rom(), ram.prg_rom());
+    }
 }
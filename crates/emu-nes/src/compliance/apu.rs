@@ -0,0 +1,124 @@
+//! NTSC frame counter sequencing and frame IRQ handling
+//!
+//! Checks clock a bare [`Apu`] and watch [`Apu::frame_sequencer_step`] and
+//! [`Apu::irq_lines`], so they measure the sequencer rather than trusting
+//! [`Apu::next_sequencer_cycle`].
+
+use super::{expect, CheckFn, Suite};
+use crate::{Apu, IrqSource, Region};
+
+/// CPU cycles from the start of a 4-step sequence to each of its steps
+pub const FOUR_STEP_CYCLES: [u64; 4] = [7457, 14913, 22371, 29829];
+
+/// CPU cycles from the start of a 5-step sequence to each of its steps
+pub const FIVE_STEP_CYCLES: [u64; 5] = [7457, 14913, 22371, 29829, 37281];
+
+/// Length of a 4-step sequence in CPU cycles
+pub const FOUR_STEP_PERIOD: u64 = 29830;
+
+/// Every APU frame counter check, by name
+pub const CHECKS: &[(&str, CheckFn)] = &[
+    ("4-step step cycles", four_step_cycles),
+    ("5-step step cycles", five_step_cycles),
+    ("4-step frame IRQ cycle", frame_irq_cycle),
+    ("5-step raises no IRQ", five_step_no_irq),
+    ("$4017 bit 6 inhibits the IRQ", irq_inhibit),
+    ("$4015 read acknowledges the IRQ", status_read_acknowledges),
+    ("$4017 write restarts the sequence", write_restarts_sequence),
+];
+
+/// Run every APU frame counter check
+pub fn run() -> Suite {
+    Suite::run("APU frame counter", CHECKS)
+}
+
+/// An NTSC APU with `frame_counter` written to $4017
+fn apu(frame_counter: u8) -> Apu {
+    let mut apu = Apu::new(Region::Ntsc);
+    apu.write_register(0x4017, frame_counter);
+    apu
+}
+
+/// Check whether the frame IRQ is holding the line
+fn frame_irq(apu: &Apu) -> bool {
+    apu.irq_lines().contains(IrqSource::FRAME)
+}
+
+/// Clock until the frame IRQ is raised, for at most `limit` cycles;
+/// returns the cycle it was raised on
+fn clock_to_irq(apu: &mut Apu, limit: u64) -> Option<u64> {
+    for _ in 0..limit {
+        apu.clock();
+        if frame_irq(apu) {
+            return Some(apu.cycles() - 1);
+        }
+    }
+    None
+}
+
+/// The cycles the first `steps` sequencer steps run on
+fn step_cycles(frame_counter: u8, steps: usize) -> Vec<u64> {
+    let mut apu = apu(frame_counter);
+    let mut cycles = Vec::with_capacity(steps);
+    while cycles.len() < steps {
+        let step = apu.frame_sequencer_step();
+        apu.clock();
+        if apu.frame_sequencer_step() != step {
+            cycles.push(apu.cycles() - 1);
+        }
+    }
+    cycles
+}
+
+pub fn four_step_cycles() -> Result<(), String> {
+    expect("step cycles", step_cycles(0x00, 4), FOUR_STEP_CYCLES.to_vec())
+}
+
+pub fn five_step_cycles() -> Result<(), String> {
+    expect("step cycles", step_cycles(0x80, 5), FIVE_STEP_CYCLES.to_vec())
+}
+
+pub fn frame_irq_cycle() -> Result<(), String> {
+    let mut apu = apu(0x00);
+    expect("first IRQ", clock_to_irq(&mut apu, 2 * FOUR_STEP_PERIOD), Some(FOUR_STEP_CYCLES[3]))?;
+    apu.read_status();
+    expect(
+        "second IRQ",
+        clock_to_irq(&mut apu, 2 * FOUR_STEP_PERIOD),
+        Some(FOUR_STEP_PERIOD + FOUR_STEP_CYCLES[3]),
+    )
+}
+
+pub fn five_step_no_irq() -> Result<(), String> {
+    let mut apu = apu(0x80);
+    expect("IRQ", clock_to_irq(&mut apu, 3 * FOUR_STEP_PERIOD), None)
+}
+
+pub fn irq_inhibit() -> Result<(), String> {
+    let mut apu = apu(0x00);
+    clock_to_irq(&mut apu, 2 * FOUR_STEP_PERIOD);
+    apu.write_register(0x4017, 0x40);
+    expect("IRQ after inhibiting", frame_irq(&apu), false)?;
+    expect("IRQ while inhibited", clock_to_irq(&mut apu, 3 * FOUR_STEP_PERIOD), None)
+}
+
+pub fn status_read_acknowledges() -> Result<(), String> {
+    let mut apu = apu(0x00);
+    clock_to_irq(&mut apu, 2 * FOUR_STEP_PERIOD);
+    expect("$4015 bit 6 on the first read", apu.read_status() & 0x40, 0x40)?;
+    expect("IRQ after reading $4015", frame_irq(&apu), false)?;
+    expect("$4015 bit 6 on the second read", apu.read_status() & 0x40, 0x00)
+}
+
+pub fn write_restarts_sequence() -> Result<(), String> {
+    let mut apu = apu(0x00);
+    for _ in 0..20_000 {
+        apu.clock();
+    }
+    expect("step before the write", apu.frame_sequencer_step(), 2)?;
+    apu.write_register(0x4017, 0x00);
+    expect("step after the write", apu.frame_sequencer_step(), 0)?;
+    expect("next step cycle", apu.next_sequencer_cycle(), 20_000 + FOUR_STEP_CYCLES[0])?;
+    expect("IRQ", clock_to_irq(&mut apu, 2 * FOUR_STEP_PERIOD), Some(20_000 + FOUR_STEP_CYCLES[3]))
+}
+
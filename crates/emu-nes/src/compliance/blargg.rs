@@ -0,0 +1,188 @@
+//! blargg's test ROMs, run headless through their $6000 result protocol
+//!
+//! The ROMs themselves aren't part of this repository; point
+//! [`run_dir`] at a local copy (the `compliance` example looks in
+//! `./test-roms`). A ROM reports through PRG-RAM once it has written the
+//! signature `DE B0 61` to $6001-$6003:
+//!
+//! - $6000 = $80 while the test is running
+//! - $6000 = $81 when it wants the reset button pressed
+//! - $6000 = anything else when it's done: 0 is a pass, any other value
+//!   is the failure code, with a NUL-terminated message from $6004
+
+use super::{Check, Suite};
+use crate::NesSystem;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Result status, valid once the signature is present
+pub const STATUS: u16 = 0x6000;
+
+/// Where the signature is written
+pub const SIGNATURE_ADDR: u16 = 0x6001;
+
+/// The bytes that mark $6000 as a result
+pub const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+
+/// Start of the NUL-terminated result message
+pub const MESSAGE: u16 = 0x6004;
+
+/// Status while the test is running
+pub const RUNNING: u8 = 0x80;
+
+/// Status asking for the reset button
+pub const RESET_REQUESTED: u8 = 0x81;
+
+/// Frames to wait before pressing reset; the ROMs want at least 100ms
+pub const RESET_DELAY_FRAMES: u64 = 10;
+
+/// Frames a ROM gets to report a result, one minute
+pub const FRAME_LIMIT: u64 = 3600;
+
+/// Run one test ROM image to its result
+pub fn run_rom(rom: &[u8]) -> Result<(), String> {
+    let mut system = NesSystem::from_bytes(rom).map_err(|e| e.to_string())?;
+    system.set_render_enabled(false);
+    let mut reset_at = None;
+
+    for frame in 0..FRAME_LIMIT {
+        system.run_frame().map_err(|e| e.to_string())?;
+        let signature = [0, 1, 2].map(|offset| system.peek_memory(SIGNATURE_ADDR + offset));
+        if signature != SIGNATURE {
+            continue;
+        }
+        match system.peek_memory(STATUS) {
+            RUNNING => {}
+            RESET_REQUESTED => match reset_at {
+                None => reset_at = Some(frame + RESET_DELAY_FRAMES),
+                Some(at) if frame >= at => {
+                    system.reset();
+                    reset_at = None;
+                }
+                Some(_) => {}
+            },
+            0 => return Ok(()),
+            code => {
+                let message = message(&mut system);
+                return Err(if message.is_empty() {
+                    format!("result {}", code)
+                } else {
+                    format!("result {}: {}", code, message)
+                });
+            }
+        }
+    }
+    Err(format!("no result after {} frames", FRAME_LIMIT))
+}
+
+/// The result message, with its line breaks and indentation collapsed
+fn message(system: &mut NesSystem) -> String {
+    let bytes: Vec<u8> = (MESSAGE..0x8000)
+        .map(|addr| system.peek_memory(addr))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Run every `.nes` file under `dir`, in path order
+///
+/// Checks are named by their path relative to `dir`.
+pub fn run_dir(dir: &Path) -> io::Result<Suite> {
+    let mut roms = Vec::new();
+    find_roms(dir, &mut roms)?;
+    roms.sort();
+
+    let mut checks = Vec::with_capacity(roms.len());
+    for path in roms {
+        let rom = fs::read(&path)?;
+        let name = path.strip_prefix(dir).unwrap_or(&path);
+        let name: Vec<_> = name.components().map(|part| part.as_os_str().to_string_lossy()).collect();
+        checks.push(Check::new(name.join("/"), run_rom(&rom)));
+    }
+    Ok(Suite {
+        area: "blargg ROMs".to_string(),
+        checks,
+    })
+}
+
+/// Collect the `.nes` files under `dir`, recursively
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_roms(&path, roms)?;
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("nes")) {
+            roms.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// LDA #value; STA addr
+    fn store(addr: u16, value: u8) -> Vec<u8> {
+        let [lo, hi] = addr.to_le_bytes();
+        vec![0xA9, value, 0x8D, lo, hi]
+    }
+
+    /// An NROM image running `program` from $8000, then looping
+    fn rom(mut program: Vec<u8>) -> Vec<u8> {
+        let [lo, hi] = (0x8000 + program.len() as u16).to_le_bytes();
+        program.extend([0x4C, lo, hi]);
+        let mut prg = vec![0xEA; 0x4000];
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3FFA..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+
+        let mut image = vec![b'N', b'E', b'S', 0x1A, 1, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0];
+        image.extend(prg);
+        image.extend(vec![0; 0x2000]);
+        image
+    }
+
+    /// Write the signature, then `status` and `message`
+    fn report(status: u8, message: &str) -> Vec<u8> {
+        let mut program = Vec::new();
+        for (offset, &byte) in SIGNATURE.iter().enumerate() {
+            program.extend(store(SIGNATURE_ADDR + offset as u16, byte));
+        }
+        for (offset, byte) in message.bytes().chain([0]).enumerate() {
+            program.extend(store(MESSAGE + offset as u16, byte));
+        }
+        program.extend(store(STATUS, status));
+        program
+    }
+
+    #[test]
+    fn test_pass_and_fail() {
+        assert_eq!(run_rom(&rom(report(0, "Passed\n"))), Ok(()));
+        assert_eq!(
+            run_rom(&rom(report(3, "\n  JMP ($xxFF)\n\nFailed #3\n"))),
+            Err("result 3: JMP ($xxFF) Failed #3".to_string())
+        );
+        assert_eq!(run_rom(&rom(report(2, ""))), Err("result 2".to_string()));
+    }
+
+    #[test]
+    fn test_running_needs_a_result() {
+        assert_eq!(
+            run_rom(&rom(report(RUNNING, ""))),
+            Err(format!("no result after {} frames", FRAME_LIMIT))
+        );
+        assert!(run_rom(&[0; 4]).is_err());
+    }
+
+    #[test]
+    fn test_reset_request() {
+        // Ask for a reset, marking PRG-RAM; pass when the mark survived it
+        let mut program = vec![0xAD, 0x10, 0x60, 0xC9, 0x42, 0xD0, 0x08]; // LDA $6010; CMP #$42; BNE +8
+        program.extend(store(STATUS, 0));
+        program.extend([0x4C, 0x0C, 0x80]); // JMP to itself
+        program.extend(store(0x6010, 0x42));
+        program.extend(report(RESET_REQUESTED, ""));
+        assert_eq!(run_rom(&rom(program)), Ok(()));
+    }
+}
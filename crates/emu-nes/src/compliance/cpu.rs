@@ -0,0 +1,195 @@
+//! 6502 arithmetic flags, addressing-mode wrapping and cycle costs
+//!
+//! Each check runs a few instructions on a [`TestBoard`] from
+//! [`ORIGIN`] to the first BRK and looks at registers, flags and memory.
+
+use super::{expect, expect_byte, CheckFn, Suite};
+use crate::cpu::testing::{TestBoard, Until};
+use crate::cpu::StatusFlags;
+
+/// Where the programs are loaded and run from
+pub const ORIGIN: u16 = 0x0200;
+
+/// Flags the arithmetic checks look at
+const NZCV: StatusFlags = StatusFlags::NEGATIVE
+    .union(StatusFlags::ZERO)
+    .union(StatusFlags::CARRY)
+    .union(StatusFlags::OVERFLOW);
+
+/// Every CPU check, by name
+pub const CHECKS: &[(&str, CheckFn)] = &[
+    ("ADC carry and zero", adc_carry_and_zero),
+    ("ADC signed overflow", adc_signed_overflow),
+    ("SBC borrow", sbc_borrow),
+    ("SBC signed overflow", sbc_signed_overflow),
+    ("Decimal flag ignored", decimal_flag_ignored),
+    ("CMP flags", cmp_flags),
+    ("BIT flags", bit_flags),
+    ("PHP pushes B and bit 5", php_pushes_break),
+    ("PLP ignores B", plp_ignores_break),
+    ("Zero page,X wraps", zero_page_x_wraps),
+    ("(zp,X) pointer wraps", indexed_indirect_wraps),
+    ("(zp),Y pointer wraps", indirect_indexed_wraps),
+    ("JMP ($xxFF) page wrap", jmp_indirect_page_wrap),
+    ("Page crossing costs a cycle", page_crossing_cycle),
+    ("Branch cycles", branch_cycles),
+    ("Stack pointer wraps", stack_wraps),
+    ("BRK pushes PC+2", brk_pushes_return_address),
+];
+
+/// Run every CPU check
+pub fn run() -> Suite {
+    Suite::run("CPU", CHECKS)
+}
+
+/// A board with `ram` poked in and `program` at [`ORIGIN`], reset
+fn board(program: &[u8], ram: &[(u16, u8)]) -> TestBoard {
+    let mut board = TestBoard::new();
+    for &(addr, value) in ram {
+        board.write(addr, value);
+    }
+    board.load(ORIGIN, program).set_reset_vector(ORIGIN);
+    board
+}
+
+/// Run `program` to its BRK
+fn run_program(program: &[u8], ram: &[(u16, u8)]) -> Result<TestBoard, String> {
+    let mut board = board(program, ram);
+    board.run_until(Until::Brk).map_err(|e| e.to_string())?;
+    Ok(board)
+}
+
+/// Check A and the N, Z, C and V flags after `program`
+fn expect_result(program: &[u8], a: u8, flags: StatusFlags) -> Result<(), String> {
+    let board = run_program(program, &[])?;
+    expect_byte("A", board.cpu().a, a)?;
+    expect("flags", board.cpu().status & NZCV, flags)
+}
+
+/// Cycles each of the first `count` instructions of `program` takes
+fn instruction_cycles(program: &[u8], count: usize) -> Result<Vec<u64>, String> {
+    let mut board = board(program, &[]);
+    let mut cycles = Vec::with_capacity(count);
+    for _ in 0..count {
+        let before = board.cpu().cycles;
+        board.run_until(Until::Instructions(1)).map_err(|e| e.to_string())?;
+        cycles.push(board.cpu().cycles - before);
+    }
+    Ok(cycles)
+}
+
+fn adc_carry_and_zero() -> Result<(), String> {
+    // CLC; LDA #$FF; ADC #$01
+    expect_result(&[0x18, 0xA9, 0xFF, 0x69, 0x01, 0x00], 0x00, StatusFlags::ZERO | StatusFlags::CARRY)
+}
+
+fn adc_signed_overflow() -> Result<(), String> {
+    // CLC; LDA #$50; ADC #$50: two positives make a negative
+    expect_result(&[0x18, 0xA9, 0x50, 0x69, 0x50, 0x00], 0xA0, StatusFlags::NEGATIVE | StatusFlags::OVERFLOW)
+}
+
+fn sbc_borrow() -> Result<(), String> {
+    // SEC; LDA #$00; SBC #$01: the borrow clears carry
+    expect_result(&[0x38, 0xA9, 0x00, 0xE9, 0x01, 0x00], 0xFF, StatusFlags::NEGATIVE)
+}
+
+fn sbc_signed_overflow() -> Result<(), String> {
+    // SEC; LDA #$80; SBC #$01: -128 - 1 overflows to +127
+    expect_result(&[0x38, 0xA9, 0x80, 0xE9, 0x01, 0x00], 0x7F, StatusFlags::CARRY | StatusFlags::OVERFLOW)
+}
+
+fn decimal_flag_ignored() -> Result<(), String> {
+    // SED; CLC; LDA #$09; ADC #$01: the 2A03 has no decimal mode
+    expect_result(&[0xF8, 0x18, 0xA9, 0x09, 0x69, 0x01, 0x00], 0x0A, StatusFlags::empty())
+}
+
+fn cmp_flags() -> Result<(), String> {
+    // LDA #$40; CMP #$41; PHP; CMP #$40; PHP
+    let mut board = run_program(&[0xA9, 0x40, 0xC9, 0x41, 0x08, 0xC9, 0x40, 0x08, 0x00], &[])?;
+    let less = StatusFlags::from_bits_truncate(board.read(0x01FD)) & NZCV;
+    expect("flags after CMP less", less, StatusFlags::NEGATIVE)?;
+    let equal = StatusFlags::from_bits_truncate(board.read(0x01FC)) & NZCV;
+    expect("flags after CMP equal", equal, StatusFlags::ZERO | StatusFlags::CARRY)
+}
+
+fn bit_flags() -> Result<(), String> {
+    // LDA #$01; BIT $10: N and V come from memory, Z from A AND memory
+    let board = run_program(&[0xA9, 0x01, 0x24, 0x10, 0x00], &[(0x10, 0xC0)])?;
+    expect(
+        "flags",
+        board.cpu().status & NZCV,
+        StatusFlags::NEGATIVE | StatusFlags::OVERFLOW | StatusFlags::ZERO,
+    )
+}
+
+fn php_pushes_break() -> Result<(), String> {
+    // PHP
+    let mut board = run_program(&[0x08, 0x00], &[])?;
+    expect_byte("pushed B and bit 5", board.read(0x01FD) & 0x30, 0x30)
+}
+
+fn plp_ignores_break() -> Result<(), String> {
+    // LDA #$FF; PHA; PLP
+    let board = run_program(&[0xA9, 0xFF, 0x48, 0x28, 0x00], &[])?;
+    let status = board.cpu().status;
+    expect("B after PLP", status.contains(StatusFlags::BREAK), false)?;
+    expect("NZCV after PLP", status & NZCV, NZCV)
+}
+
+fn zero_page_x_wraps() -> Result<(), String> {
+    // LDX #$01; LDA $FF,X reads $00, not $0100
+    let board = run_program(&[0xA2, 0x01, 0xB5, 0xFF, 0x00], &[(0x0000, 0x42), (0x0100, 0xEE)])?;
+    expect_byte("A", board.cpu().a, 0x42)
+}
+
+fn indexed_indirect_wraps() -> Result<(), String> {
+    // LDX #$01; LDA ($FE,X): the pointer is $FF and $00
+    let ram = [(0x00FF, 0x34), (0x0000, 0x12), (0x0100, 0xEE), (0x1234, 0x99)];
+    let board = run_program(&[0xA2, 0x01, 0xA1, 0xFE, 0x00], &ram)?;
+    expect_byte("A", board.cpu().a, 0x99)
+}
+
+fn indirect_indexed_wraps() -> Result<(), String> {
+    // LDY #$01; LDA ($FF),Y: the pointer is $FF and $00
+    let ram = [(0x00FF, 0x00), (0x0000, 0x30), (0x0100, 0xEE), (0x3001, 0x77)];
+    let board = run_program(&[0xA0, 0x01, 0xB1, 0xFF, 0x00], &ram)?;
+    expect_byte("A", board.cpu().a, 0x77)
+}
+
+fn jmp_indirect_page_wrap() -> Result<(), String> {
+    // JMP ($04FF) takes its high byte from $0400, landing on LDA #$55
+    let ram = [(0x04FF, 0x00), (0x0400, 0x05), (0x0500, 0xA9), (0x0501, 0x55)];
+    let board = run_program(&[0x6C, 0xFF, 0x04], &ram)?;
+    expect("PC", board.cpu().pc, 0x0502)?;
+    expect_byte("A", board.cpu().a, 0x55)
+}
+
+fn page_crossing_cycle() -> Result<(), String> {
+    // LDX #$01; LDA $0200,X; LDA $02FF,X; STA $02FF,X
+    let program = [0xA2, 0x01, 0xBD, 0x00, 0x02, 0xBD, 0xFF, 0x02, 0x9D, 0xFF, 0x02];
+    expect("cycles", instruction_cycles(&program, 4)?, vec![2, 4, 5, 5])
+}
+
+fn branch_cycles() -> Result<(), String> {
+    // LDX #$01; BEQ (not taken); BNE +0 (taken); BNE back into page 1
+    let program = [0xA2, 0x01, 0xF0, 0x00, 0xD0, 0x00, 0xD0, 0xF0];
+    expect("cycles", instruction_cycles(&program, 4)?, vec![2, 2, 3, 4])
+}
+
+fn stack_wraps() -> Result<(), String> {
+    // LDA #$5A; LDX #$00; TXS; PHA: the push goes to $0100 and S wraps to $FF
+    let mut board = run_program(&[0xA9, 0x5A, 0xA2, 0x00, 0x9A, 0x48, 0x00], &[])?;
+    expect_byte("$0100", board.read(0x0100), 0x5A)?;
+    expect_byte("S", board.cpu().sp, 0xFF)
+}
+
+fn brk_pushes_return_address() -> Result<(), String> {
+    // BRK at $0200, handled at $0300
+    let mut board = board(&[0x00, 0xEA], &[(0xFFFE, 0x00), (0xFFFF, 0x03)]);
+    board.run_until(Until::Pc(0x0300)).map_err(|e| e.to_string())?;
+    let pushed = u16::from_le_bytes([board.read(0x01FC), board.read(0x01FD)]);
+    expect("pushed return address", pushed, 0x0202)?;
+    expect_byte("pushed B and bit 5", board.read(0x01FB) & 0x30, 0x30)?;
+    expect("I after BRK", board.cpu().status.contains(StatusFlags::INTERRUPT), true)
+}
+
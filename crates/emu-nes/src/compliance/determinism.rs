@@ -0,0 +1,167 @@
+//! Same ROM and inputs, same machine state
+//!
+//! Movies and rewind replay inputs against a savestate, which only works if
+//! the same ROM and the same inputs always produce the same machine state.
+//! Each ROM is run with a scripted input sequence in two separate systems,
+//! and once more resumed from a savestate in a fresh system; all of them
+//! must agree on the picture, RAM and full state at every checkpoint. So
+//! must a clone of a running system and the original it was taken from.
+
+use super::{Check, Suite};
+use crate::savestate::crc32;
+use crate::{NesSystem, StateDigest};
+use emu_core::Button;
+
+/// Frames at which the runs are compared
+pub const CHECKPOINTS: [u64; 3] = [60, 300, 600];
+
+/// Frame the resumed run is saved at
+pub const SAVE_FRAME: u64 = 300;
+
+/// Last frame run
+const LAST_FRAME: u64 = CHECKPOINTS[CHECKPOINTS.len() - 1];
+
+/// A check run on one ROM image
+type RomCheck = fn(&[u8]) -> Result<(), String>;
+
+/// Run every determinism check on each named ROM
+pub fn run(roms: &[(&str, Vec<u8>)]) -> Suite {
+    let checks: [(&str, RomCheck); 3] = [
+        ("same inputs", same_inputs),
+        ("resumed state", resumed_state),
+        ("clone", clone_runs),
+    ];
+    Suite {
+        area: "Determinism".to_string(),
+        checks: roms
+            .iter()
+            .flat_map(|(name, rom)| {
+                checks
+                    .iter()
+                    .map(move |(check, run)| Check::new(format!("{}: {}", name, check), run(rom)))
+            })
+            .collect(),
+    }
+}
+
+/// Buttons held during `frame`: a fixed pseudo-random pattern that changes
+/// every few frames, so it only depends on the frame number
+pub fn scripted_buttons(frame: u64) -> Button {
+    let seed = (frame / 4).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    Button::from_bits_truncate((seed >> 56) as u8)
+}
+
+/// What the runs are compared on
+#[derive(Debug, PartialEq)]
+struct Checkpoint {
+    frame: u64,
+    framebuffer_crc: u32,
+    ram: Vec<u8>,
+    digest: StateDigest,
+}
+
+fn checkpoint(system: &mut NesSystem) -> Checkpoint {
+    Checkpoint {
+        frame: system.frame(),
+        framebuffer_crc: crc32(system.framebuffer()),
+        ram: (0..0x800).map(|addr| system.read_memory(addr)).collect(),
+        digest: system.state_digest(),
+    }
+}
+
+fn load(rom: &[u8]) -> Result<NesSystem, String> {
+    NesSystem::from_bytes(rom).map_err(|e| e.to_string())
+}
+
+/// Run frames up to `until` with the scripted inputs, recording checkpoints
+fn run_to(system: &mut NesSystem, until: u64, checkpoints: &mut Vec<Checkpoint>) -> Result<(), String> {
+    while system.frame() < until {
+        let buttons = scripted_buttons(system.frame());
+        for button in Button::all().iter() {
+            system.set_button(button, buttons.contains(button));
+        }
+        system.run_frame().map_err(|e| e.to_string())?;
+        if CHECKPOINTS.contains(&system.frame()) {
+            checkpoints.push(checkpoint(system));
+        }
+    }
+    Ok(())
+}
+
+/// Compare two runs checkpoint by checkpoint, naming the first difference
+fn compare(expected: &[Checkpoint], actual: &[Checkpoint]) -> Result<(), String> {
+    if expected.len() != actual.len() {
+        return Err(format!("{} checkpoints, expected {}", actual.len(), expected.len()));
+    }
+    for (expected, actual) in expected.iter().zip(actual) {
+        let difference = if expected.frame != actual.frame {
+            Some(format!("frame {}", actual.frame))
+        } else if expected.framebuffer_crc != actual.framebuffer_crc {
+            Some("framebuffer differs".to_string())
+        } else if expected.ram != actual.ram {
+            Some("RAM differs".to_string())
+        } else if expected.digest != actual.digest {
+            Some("state differs".to_string())
+        } else {
+            None
+        };
+        if let Some(difference) = difference {
+            return Err(format!("{} at frame {}", difference, expected.frame));
+        }
+    }
+    Ok(())
+}
+
+/// Two systems given the same inputs stay in step
+pub fn same_inputs(rom: &[u8]) -> Result<(), String> {
+    let mut first = Vec::new();
+    run_to(&mut load(rom)?, LAST_FRAME, &mut first)?;
+    let mut second = Vec::new();
+    run_to(&mut load(rom)?, LAST_FRAME, &mut second)?;
+    compare(&first, &second)
+}
+
+/// A fresh system resumed from a savestate runs like the one that saved it
+pub fn resumed_state(rom: &[u8]) -> Result<(), String> {
+    let mut original = load(rom)?;
+    let mut expected = Vec::new();
+    run_to(&mut original, SAVE_FRAME, &mut expected)?;
+    let state = original.save_state();
+    run_to(&mut original, LAST_FRAME, &mut expected)?;
+
+    let mut resumed = load(rom)?;
+    resumed.load_state(&state).map_err(|e| e.to_string())?;
+    let mut actual = vec![checkpoint(&mut resumed)];
+    run_to(&mut resumed, LAST_FRAME, &mut actual)?;
+    compare(&expected[1..], &actual)
+}
+
+/// A clone of a running system runs like the original
+pub fn clone_runs(rom: &[u8]) -> Result<(), String> {
+    let mut original = load(rom)?;
+    let mut expected = Vec::new();
+    run_to(&mut original, CHECKPOINTS[0] - 1, &mut expected)?;
+
+    // Equal when taken, apart once only one copy has run
+    let mut clone = original.clone();
+    if clone.state_digest() != original.state_digest() {
+        return Err("clone differs when taken".to_string());
+    }
+    if StateDigest::of(&clone) != clone.state_digest() {
+        return Err("clone's digest isn't StateDigest::of".to_string());
+    }
+    clone.run_frame().map_err(|e| e.to_string())?;
+    if clone.state_digest() == original.state_digest() {
+        return Err("running the clone changed nothing".to_string());
+    }
+    original.run_frame().map_err(|e| e.to_string())?;
+    if clone.state_digest() != original.state_digest() {
+        return Err("clone and original differ after a frame each".to_string());
+    }
+
+    // Then in step for the rest of the run
+    run_to(&mut original, LAST_FRAME, &mut expected)?;
+    let mut actual = Vec::new();
+    run_to(&mut clone, LAST_FRAME, &mut actual)?;
+    compare(&expected, &actual)
+}
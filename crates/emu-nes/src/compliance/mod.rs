@@ -0,0 +1,306 @@
+//! Accuracy test batteries that report instead of panicking
+//!
+//! Each area's checks live in a submodule as plain functions returning
+//! `Result<(), String>`, gathered by the submodule's `run` into a
+//! [`Suite`]. The crate's `#[test]`s call the same functions, and the
+//! `compliance` example runs every suite and prints a [`Scorecard`]:
+//!
+//! ```text
+//! cargo run --release -p emu-nes --example compliance -- --json
+//! ```
+//!
+//! The suites only cover what this repository controls: hand-written CPU,
+//! PPU and APU checks, the determinism runs over the example ROMs, and any
+//! blargg test ROMs present locally (see [`blargg`]).
+
+pub mod apu;
+pub mod blargg;
+pub mod cpu;
+pub mod determinism;
+pub mod ppu;
+
+use std::fmt;
+use std::path::Path;
+
+/// Failures listed per area in the scorecard table; the rest are counted
+pub const NOTED_FAILURES: usize = 3;
+
+/// A check: `Ok`, or why it failed
+pub type CheckFn = fn() -> Result<(), String>;
+
+/// One named check and why it failed, if it did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub failure: Option<String>,
+}
+
+impl Check {
+    /// Record the outcome of the check called `name`
+    pub fn new(name: impl Into<String>, outcome: Result<(), String>) -> Self {
+        Self {
+            name: name.into(),
+            failure: outcome.err(),
+        }
+    }
+
+    /// Check whether it passed
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// The checks of one area
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suite {
+    pub area: String,
+    pub checks: Vec<Check>,
+}
+
+impl Suite {
+    /// Run each named check in `checks`
+    pub fn run(area: impl Into<String>, checks: &[(&str, CheckFn)]) -> Self {
+        Self {
+            area: area.into(),
+            checks: checks.iter().map(|&(name, check)| Check::new(name, check())).collect(),
+        }
+    }
+
+    /// Number of checks that passed
+    pub fn passed(&self) -> usize {
+        self.checks.iter().filter(|check| check.passed()).count()
+    }
+
+    /// Number of checks run
+    pub fn total(&self) -> usize {
+        self.checks.len()
+    }
+
+    /// The checks that failed, in order
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|check| !check.passed())
+    }
+
+    /// Every failure as one message, for asserting a whole suite passed
+    pub fn failure_report(&self) -> Option<String> {
+        let failures: Vec<String> = self
+            .failures()
+            .map(|check| format!("{}: {}", check.name, check.failure.as_deref().unwrap_or_default()))
+            .collect();
+        (!failures.is_empty()).then(|| format!("{} failed:\n{}", self.area, failures.join("\n")))
+    }
+}
+
+/// Results of every suite run
+///
+/// `Display` is the table for people; [`Scorecard::to_json`] is for tools.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scorecard {
+    pub suites: Vec<Suite>,
+}
+
+impl Scorecard {
+    /// Number of checks that passed, over all suites
+    pub fn passed(&self) -> usize {
+        self.suites.iter().map(Suite::passed).sum()
+    }
+
+    /// Number of checks run, over all suites
+    pub fn total(&self) -> usize {
+        self.suites.iter().map(Suite::total).sum()
+    }
+
+    /// Check whether every check passed
+    pub fn all_passed(&self) -> bool {
+        self.passed() == self.total()
+    }
+
+    /// The scorecard as a JSON object
+    ///
+    /// `{"passed", "total", "suites": [{"area", "passed", "total",
+    /// "checks": [{"name", "passed", "failure"?}]}]}`
+    pub fn to_json(&self) -> String {
+        let suites: Vec<String> = self
+            .suites
+            .iter()
+            .map(|suite| {
+                let checks: Vec<String> = suite
+                    .checks
+                    .iter()
+                    .map(|check| match &check.failure {
+                        None => format!("{{\"name\":{},\"passed\":true}}", json_string(&check.name)),
+                        Some(failure) => format!(
+                            "{{\"name\":{},\"passed\":false,\"failure\":{}}}",
+                            json_string(&check.name),
+                            json_string(failure)
+                        ),
+                    })
+                    .collect();
+                format!(
+                    "{{\"area\":{},\"passed\":{},\"total\":{},\"checks\":[{}]}}",
+                    json_string(&suite.area),
+                    suite.passed(),
+                    suite.total(),
+                    checks.join(",")
+                )
+            })
+            .collect();
+        format!(
+            "{{\"passed\":{},\"total\":{},\"suites\":[{}]}}",
+            self.passed(),
+            self.total(),
+            suites.join(",")
+        )
+    }
+}
+
+impl fmt::Display for Scorecard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.suites.iter().map(|suite| suite.area.len()).chain(["Total".len()]).max().unwrap_or(0);
+        writeln!(f, "{:width$}  {:>9}  Notable failures", "Area", "Passed")?;
+        for suite in &self.suites {
+            let mut noted: Vec<String> = suite
+                .failures()
+                .take(NOTED_FAILURES)
+                .map(|check| format!("{} ({})", check.name, check.failure.as_deref().unwrap_or_default()))
+                .collect();
+            let unnoted = suite.total() - suite.passed() - noted.len();
+            if unnoted > 0 {
+                noted.push(format!("{} more", unnoted));
+            }
+            let score = format!("{}/{}", suite.passed(), suite.total());
+            let line = format!("{:width$}  {:>9}  {}", suite.area, score, noted.join("; "));
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        write!(f, "{:width$}  {:>9}", "Total", format!("{}/{}", self.passed(), self.total()))
+    }
+}
+
+/// Run every suite
+///
+/// Determinism runs over `determinism_roms` (skipped when there are none)
+/// and the blargg suite over the ROMs under `blargg_dir`, if given.
+pub fn run_all(determinism_roms: &[(&str, Vec<u8>)], blargg_dir: Option<&Path>) -> std::io::Result<Scorecard> {
+    let mut scorecard = Scorecard {
+        suites: vec![cpu::run(), ppu::run(), apu::run()],
+    };
+    if !determinism_roms.is_empty() {
+        scorecard.suites.push(determinism::run(determinism_roms));
+    }
+    if let Some(dir) = blargg_dir {
+        scorecard.suites.push(blargg::run_dir(dir)?);
+    }
+    Ok(scorecard)
+}
+
+/// Fail with what `what` was when it isn't `expected`
+pub(crate) fn expect<T: PartialEq + fmt::Debug>(what: &str, actual: T, expected: T) -> Result<(), String> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!("{} was {:?}, expected {:?}", what, actual, expected))
+    }
+}
+
+/// [`expect`] for bytes, shown in hex
+pub(crate) fn expect_byte(what: &str, actual: u8, expected: u8) -> Result<(), String> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!("{} was ${:02X}, expected ${:02X}", what, actual, expected))
+    }
+}
+
+/// `text` as a quoted JSON string
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pass() -> Result<(), String> {
+        Ok(())
+    }
+
+    fn fail() -> Result<(), String> {
+        Err("A was $01, expected $02".to_string())
+    }
+
+    fn scorecard() -> Scorecard {
+        Scorecard {
+            suites: vec![
+                Suite::run("CPU", &[("adc", pass), ("sbc", pass)]),
+                Suite::run("PPU timing", &[("a", fail), ("b", pass), ("c", fail), ("d", fail), ("e", fail)]),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_aggregation() {
+        let scorecard = scorecard();
+        assert_eq!((scorecard.suites[0].passed(), scorecard.suites[0].total()), (2, 2));
+        assert_eq!((scorecard.suites[1].passed(), scorecard.suites[1].total()), (1, 5));
+        assert_eq!((scorecard.passed(), scorecard.total()), (3, 7));
+        assert!(!scorecard.all_passed());
+        let names: Vec<&str> = scorecard.suites[1].failures().map(|check| check.name.as_str()).collect();
+        assert_eq!(names, ["a", "c", "d", "e"]);
+
+        assert_eq!(scorecard.suites[0].failure_report(), None);
+        assert!(Scorecard::default().all_passed());
+        let report = scorecard.suites[1].failure_report().unwrap();
+        assert!(report.starts_with("PPU timing failed:\na: A was $01"), "{}", report);
+    }
+
+    #[test]
+    fn test_table() {
+        assert_eq!(
+            scorecard().to_string(),
+            "Area           Passed  Notable failures\n\
+             CPU               2/2\n\
+             PPU timing        1/5  a (A was $01, expected $02); c (A was $01, expected $02); \
+             d (A was $01, expected $02); 1 more\n\
+             Total             3/7"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let scorecard = Scorecard {
+            suites: vec![Suite {
+                area: "blargg \"ROMs\"".to_string(),
+                checks: vec![
+                    Check::new("cpu/01.nes", Ok(())),
+                    Check::new("cpu/02.nes", Err("result 2:\n\tJMP failed".to_string())),
+                ],
+            }],
+        };
+        assert_eq!(
+            scorecard.to_json(),
+            r#"{"passed":1,"total":2,"suites":[{"area":"blargg \"ROMs\"","passed":1,"total":2,"checks":[{"name":"cpu/01.nes","passed":true},{"name":"cpu/02.nes","passed":false,"failure":"result 2:\n\tJMP failed"}]}]}"#
+        );
+        assert_eq!(json_string("\u{1}\\"), r#""\u0001\\""#);
+    }
+
+    #[test]
+    fn test_expect_messages() {
+        assert_eq!(expect("cycles", 4, 4), Ok(()));
+        assert_eq!(expect("cycles", 5, 4), Err("cycles was 5, expected 4".to_string()));
+        assert_eq!(expect_byte("A", 0x0A, 0x10), Err("A was $0A, expected $10".to_string()));
+    }
+}
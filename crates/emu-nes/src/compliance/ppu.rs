@@ -0,0 +1,185 @@
+//! Vblank flag, NMI and frame-length timing
+//!
+//! Most checks tick a bare [`Ppu`]; the vbl/NMI race runs
+//! [`vbl_nmi_timing_prg`] on a whole [`NesSystem`] so CPU reads land on
+//! real bus cycles.
+
+use super::{expect, CheckFn, Suite};
+use crate::ppu::{Ppu, PpuStatus};
+use crate::NesSystem;
+
+/// Dots in a frame with rendering off, and in even frames with it on
+pub const FRAME_DOTS: u64 = 341 * 262;
+
+/// Zero page counter incremented by the NMI handler of [`vbl_nmi_timing_prg`]
+pub const NMI_COUNT: u16 = 0x10;
+
+/// Zero page counter incremented when the poll loop of
+/// [`vbl_nmi_timing_prg`] sees the flag
+pub const FLAG_COUNT: u16 = 0x11;
+
+/// Frames for the reads of [`vbl_nmi_timing_prg`] to cover every offset once
+pub const CYCLE_FRAMES: u64 = 21;
+
+/// Every PPU timing check, by name
+pub const CHECKS: &[(&str, CheckFn)] = &[
+    ("Vblank set at 241,1", vblank_set),
+    ("Vblank cleared at 261,1", vblank_cleared),
+    ("Frame length, rendering off", frame_length),
+    ("Odd frame skips a dot", odd_frame_skip),
+    ("$2002 read racing vblank", vblank_read_race),
+    ("vbl/NMI timing over 21 frames", vbl_nmi_timing),
+];
+
+/// Run every PPU timing check
+pub fn run() -> Suite {
+    Suite::run("PPU timing", CHECKS)
+}
+
+/// Tick `ppu` until it reaches `cycle` of `scanline`
+fn tick_to(ppu: &mut Ppu, scanline: u16, cycle: u16) {
+    while (ppu.scanline(), ppu.cycle()) != (scanline, cycle) {
+        ppu.tick();
+    }
+}
+
+/// Tick `ppu` until frame `frame` starts and return the dots so far
+fn dots_at_frame(ppu: &mut Ppu, frame: u64) -> u64 {
+    while ppu.frame() < frame {
+        ppu.tick();
+    }
+    ppu.dots()
+}
+
+pub fn vblank_set() -> Result<(), String> {
+    let mut ppu = Ppu::new();
+    // Clear the flag the PPU may power up with
+    ppu.read_register(0x2002);
+    tick_to(&mut ppu, 241, 1);
+    expect("vblank before 241,1", ppu.status.contains(PpuStatus::VBLANK), false)?;
+    ppu.tick();
+    expect("vblank after 241,1", ppu.status.contains(PpuStatus::VBLANK), true)
+}
+
+pub fn vblank_cleared() -> Result<(), String> {
+    let mut ppu = Ppu::new();
+    tick_to(&mut ppu, 261, 1);
+    expect("vblank before 261,1", ppu.status.contains(PpuStatus::VBLANK), true)?;
+    ppu.tick();
+    expect("vblank after 261,1", ppu.status.contains(PpuStatus::VBLANK), false)
+}
+
+pub fn frame_length() -> Result<(), String> {
+    let mut ppu = Ppu::new();
+    expect("dots in frame 0", dots_at_frame(&mut ppu, 1), FRAME_DOTS)?;
+    expect("dots in frames 0-1", dots_at_frame(&mut ppu, 2), FRAME_DOTS * 2)
+}
+
+pub fn odd_frame_skip() -> Result<(), String> {
+    let mut ppu = Ppu::new();
+    ppu.write_register(0x2001, 0x08); // Show background
+    expect("dots in even frame", dots_at_frame(&mut ppu, 1), FRAME_DOTS)?;
+    expect("dots in even and odd frames", dots_at_frame(&mut ppu, 2), FRAME_DOTS * 2 - 1)
+}
+
+/// Read $2002 just before dot `cycle` of scanline 241 with NMI enabled,
+/// then finish the dot that sets vblank; returns (flag read, NMI raised)
+pub fn race_vblank(cycle: u16) -> (bool, bool) {
+    let mut ppu = Ppu::new();
+    ppu.write_register(0x2000, 0x80);
+    tick_to(&mut ppu, 241, cycle);
+    let status = ppu.read_register(0x2002);
+    while ppu.cycle() < 2 {
+        ppu.tick();
+    }
+    (status & 0x80 != 0, ppu.nmi_interrupt)
+}
+
+pub fn vblank_read_race() -> Result<(), String> {
+    // A read before dot 1 lands after dot 0. Earlier than that changes
+    // nothing; one dot before the flag: flag and NMI both suppressed. Same
+    // dot and one after: flag reads set, NMI lost. Later reads don't
+    // affect the NMI.
+    let expected = [(false, true), (false, false), (true, false), (true, false), (true, true)];
+    for (cycle, expected) in (0..).zip(expected) {
+        expect(&format!("(flag, NMI) reading at 241,{}", cycle), race_vblank(cycle), expected)?;
+    }
+    Ok(())
+}
+
+/// $2002 reads racing the start of vblank, in the style of blargg's
+/// vbl_nmi_timing tests
+///
+/// The ROM polls $2002 in a 7-cycle loop with NMI enabled. The loop, the
+/// path taken when it sees the flag and the NMI handler all take a multiple
+/// of 7 cycles, so the reads stay on a 21-dot grid. A frame is 89342 dots,
+/// 8 more than a multiple of 21, so over any 21 consecutive frames the read
+/// nearest vblank lands on every dot offset exactly once:
+///
+/// - one dot before vblank: the flag reads clear and no NMI happens
+/// - the same dot or one after: the flag reads set but the NMI is lost
+/// - anywhere else: the flag is seen and the NMI happens
+pub fn vbl_nmi_timing_prg() -> Vec<u8> {
+    let mut prg = vec![0xEA; 0x4000];
+
+    // --- Reset ($8000) ---
+    prg[..15].copy_from_slice(&[
+        0x78, // SEI
+        0xA2, 0xFF, 0x9A, // LDX #$FF; TXS
+        0xA9, 0x00, 0x85, NMI_COUNT as u8, 0x85, FLAG_COUNT as u8, // LDA #$00; STA $10; STA $11
+        0xA9, 0x80, 0x8D, 0x00, 0x20, // LDA #$80; STA $2000
+    ]);
+    prg[15..18].copy_from_slice(&[0x4C, 0x00, 0x81]); // JMP poll
+
+    // --- Poll loop ($8100): 7 cycles, 14 when the flag is seen ---
+    prg[0x100..0x10C].copy_from_slice(&[
+        0xAD, 0x02, 0x20, // poll: LDA $2002
+        0x10, 0xFB, // BPL poll
+        0xE6, FLAG_COUNT as u8, // INC $11
+        0x4C, 0x00, 0x81, // JMP poll
+        0xEA, 0xEA,
+    ]);
+
+    // --- NMI ($8200): 21 cycles including the 7 to take it ---
+    prg[0x200..0x205].copy_from_slice(&[
+        0xE6, NMI_COUNT as u8, // INC $10
+        0x24, 0x00, // BIT $00 (padding)
+        0x40, // RTI
+    ]);
+
+    prg[0x3FFA..].copy_from_slice(&[0x00, 0x82, 0x00, 0x80, 0x00, 0x82]);
+    prg
+}
+
+/// Step until the PPU starts a new frame
+pub fn next_frame(system: &mut NesSystem) -> Result<(), String> {
+    let frame = system.ppu().frame();
+    while system.ppu().frame() == frame {
+        system.step().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Run `frames` PPU frames and return how many NMIs and flag reads happened
+pub fn count(system: &mut NesSystem, frames: u64) -> Result<(u8, u8), String> {
+    let nmis = system.read_memory(NMI_COUNT);
+    let flags = system.read_memory(FLAG_COUNT);
+    for _ in 0..frames {
+        next_frame(system)?;
+    }
+    Ok((
+        system.read_memory(NMI_COUNT).wrapping_sub(nmis),
+        system.read_memory(FLAG_COUNT).wrapping_sub(flags),
+    ))
+}
+
+pub fn vbl_nmi_timing() -> Result<(), String> {
+    let mut system = NesSystem::with_prg_rom(vbl_nmi_timing_prg()).map_err(|e| e.to_string())?;
+    next_frame(&mut system)?;
+
+    // Each window of 21 frames has one suppressed flag and three lost NMIs
+    for window in 0..4 {
+        expect(&format!("(NMIs, flags seen) in window {}", window), count(&mut system, CYCLE_FRAMES)?, (18, 20))?;
+    }
+    Ok(())
+}
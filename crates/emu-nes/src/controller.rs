@@ -0,0 +1,336 @@
+//! NES standard controller hardware
+//!
+//! The controller is read serially through $4016/$4017: a write to $4016
+//! strobes the pads, then each read shifts out one button in the order
+//! A, B, Select, Start, Up, Down, Left, Right.
+//!
+//! While the strobe bit is high the shift register keeps reloading, so
+//! every read returns the live A button. The 1->0 transition latches the
+//! buttons, and once all 8 have been shifted out, official controllers
+//! return 1 on every further read.
+//!
+//! Only bit 0 of a $4016 write is the strobe. Bits 1-2 are latched by the
+//! console's [`ControllerPort`] and go out on the expansion port alone, so
+//! games writing $04 or $07 for an expansion device don't re-latch the pads.
+
+use std::fmt;
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+use crate::input_script::ScriptMode;
+use emu_core::{Button, ControllerState, Result};
+
+/// NES controller hardware (handles shift register)
+#[derive(Debug, Clone)]
+pub struct Controller {
+    /// Current button state
+    state: ControllerState,
+    /// Shift register (buttons are read one at a time)
+    shift_register: u8,
+    /// Strobe mode (if true, continuously reload shift register)
+    strobe: bool,
+    /// Buttons latched by the most recent strobe
+    latched: u8,
+    /// Buttons an input script holds this frame, and how they combine with
+    /// `state`; set by the system each frame, not state
+    scripted: Option<(Button, ScriptMode)>,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self {
+            state: ControllerState::new(),
+            shift_register: 0,
+            strobe: false,
+            latched: 0,
+            scripted: None,
+        }
+    }
+
+    /// Buttons the game sees: the held ones, with a script's instead or on top
+    fn buttons(&self) -> Button {
+        match self.scripted {
+            Some((buttons, ScriptMode::Override)) => buttons,
+            Some((buttons, ScriptMode::Merge)) => buttons | self.state.buttons,
+            None => self.state.buttons,
+        }
+    }
+
+    /// Set the buttons an input script holds, leaving the live state alone
+    pub(crate) fn set_scripted(&mut self, scripted: Option<(Button, ScriptMode)>) {
+        self.scripted = scripted;
+    }
+
+    /// Write to $4016 (strobe)
+    ///
+    /// Only bit 0 counts; the rest of `value` is ignored. Returns true when
+    /// the write latched the buttons (the strobe's falling edge).
+    pub fn write(&mut self, value: u8) -> bool {
+        let new_strobe = (value & 1) != 0;
+        
+        // Strobe falling edge: latch button states into shift register
+        let latching = self.strobe && !new_strobe;
+        if latching {
+            self.latched = self.buttons().bits();
+            self.shift_register = self.latched;
+        }
+        
+        self.strobe = new_strobe;
+        latching
+    }
+
+    /// Read from $4016 (shift out one button state)
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            // While strobing, always return A button state
+            self.buttons().bits() & 1
+        } else {
+            // Return lowest bit and shift right
+            let result = self.shift_register & 1;
+            self.shift_register >>= 1;
+            // Official controllers shift in 1s, so reads past the 8th return 1
+            self.shift_register |= 0x80;
+            result
+        }
+    }
+
+    /// Get current controller state (for external modification)
+    pub fn state(&mut self) -> &mut ControllerState {
+        &mut self.state
+    }
+
+    /// Get immutable controller state
+    pub fn state_ref(&self) -> &ControllerState {
+        &self.state
+    }
+
+    /// Buttons the game latched at its most recent strobe
+    ///
+    /// This is what the game is actually reading this frame, which can lag
+    /// behind [`state_ref`](Self::state_ref) by up to a frame.
+    pub fn last_latched(&self) -> u8 {
+        self.latched
+    }
+}
+
+impl Default for Controller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Called with the expansion port bits (OUT1 in bit 0, OUT2 in bit 1) on
+/// every $4016 write
+pub type ExpansionCallback = Box<dyn FnMut(u8) + Send + Sync>;
+
+/// The console's $4016 output latch
+///
+/// A write latches three lines: OUT0 strobes both controllers, and OUT1-OUT2
+/// only reach the expansion port, where devices such as the Famicom
+/// keyboard listen. The port hands the strobe on and keeps the other two
+/// for an expansion device, which can be attached as a callback.
+///
+/// A clone has no callback, like [`NesMemory`](crate::NesMemory)'s
+/// observers. The latch isn't saved in savestates; each controller keeps
+/// its own strobe, and nothing reads the expansion bits back yet.
+#[derive(Default)]
+pub struct ControllerPort {
+    /// OUT0-OUT2 as last written
+    out: u8,
+    expansion: Option<ExpansionCallback>,
+}
+
+impl ControllerPort {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Latch a $4016 write, returning the strobe (OUT0) for the controllers
+    pub fn write(&mut self, value: u8) -> u8 {
+        self.out = value & 0b111;
+        let bits = self.expansion_bits();
+        if let Some(expansion) = self.expansion.as_mut() {
+            expansion(bits);
+        }
+        self.out & 1
+    }
+
+    /// Strobe line, as last written
+    pub fn strobe(&self) -> bool {
+        self.out & 1 != 0
+    }
+
+    /// OUT1 and OUT2 as last written, in bits 0-1
+    pub fn expansion_bits(&self) -> u8 {
+        self.out >> 1
+    }
+
+    /// Hand the expansion bits of every write to `callback`, or stop with None
+    pub fn set_expansion_callback(&mut self, callback: Option<ExpansionCallback>) {
+        self.expansion = callback;
+    }
+}
+
+impl Clone for ControllerPort {
+    fn clone(&self) -> Self {
+        Self {
+            out: self.out,
+            expansion: None,
+        }
+    }
+}
+
+impl fmt::Debug for ControllerPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ControllerPort")
+            .field("out", &self.out)
+            .field("expansion", &self.expansion.is_some())
+            .finish()
+    }
+}
+
+/// Only the serial port is saved; which buttons are held belongs to the
+/// frontend, not the game.
+impl Snapshot for Controller {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.shift_register);
+        w.bool(self.strobe);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.shift_register = r.u8()?;
+        self.strobe = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strobe_high_returns_live_a_button() {
+        let mut controller = Controller::new();
+        controller.state().press(Button::A | Button::B);
+        controller.write(1);
+
+        // Repeated reads don't shift while strobe is high
+        for _ in 0..10 {
+            assert_eq!(controller.read(), 1);
+        }
+
+        // ...and track the button live
+        controller.state().release(Button::A);
+        assert_eq!(controller.read(), 0);
+    }
+
+    #[test]
+    fn test_reload_on_falling_edge() {
+        let mut controller = Controller::new();
+        controller.write(1);
+        controller.state().press(Button::START | Button::LEFT);
+        controller.write(0);
+
+        // Changes after the latch aren't visible until the next strobe
+        controller.state().release(Button::START);
+        assert_eq!(controller.last_latched(), (Button::START | Button::LEFT).bits());
+        let bits: Vec<u8> = (0..8).map(|_| controller.read()).collect();
+        assert_eq!(bits, vec![0, 0, 0, 1, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_scripted_buttons_override_or_merge() {
+        let mut controller = Controller::new();
+        controller.state().press(Button::LEFT);
+        let latch = |controller: &mut Controller| {
+            controller.write(1);
+            controller.write(0);
+            controller.last_latched()
+        };
+
+        controller.set_scripted(Some((Button::A, ScriptMode::Override)));
+        assert_eq!(latch(&mut controller), Button::A.bits());
+        controller.set_scripted(Some((Button::A, ScriptMode::Merge)));
+        assert_eq!(latch(&mut controller), (Button::A | Button::LEFT).bits());
+
+        // The live state was never touched
+        controller.set_scripted(None);
+        assert_eq!(latch(&mut controller), Button::LEFT.bits());
+        assert_eq!(controller.state_ref().buttons, Button::LEFT);
+
+        // Strobing reads the scripted A button too
+        controller.set_scripted(Some((Button::A, ScriptMode::Override)));
+        controller.write(1);
+        assert_eq!(controller.read(), 1);
+    }
+
+    #[test]
+    fn test_only_bit_zero_strobes() {
+        let mut controller = Controller::new();
+        controller.state().press(Button::A | Button::SELECT);
+        controller.write(0x01);
+        controller.write(0x00);
+        assert_eq!(controller.read(), 1);
+
+        // Bit 0 clear, so no falling edge and no reload mid-read
+        controller.state().release(Button::SELECT);
+        assert!(!controller.write(0x02));
+        assert!(!controller.write(0x06));
+        let bits: Vec<u8> = (0..7).map(|_| controller.read()).collect();
+        assert_eq!(bits, vec![0, 1, 0, 0, 0, 0, 0]);
+
+        // $01 then $00 latches, and eight reads return what was held
+        controller.state().press(Button::RIGHT);
+        controller.write(0x01);
+        controller.write(0x00);
+        let bits: Vec<u8> = (0..8).map(|_| controller.read()).collect();
+        assert_eq!(bits, vec![1, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_expansion_bits_dont_change_what_the_pad_sees() {
+        let read_with = |strobe: u8, release: u8| {
+            let mut controller = Controller::new();
+            controller.state().press(Button::B | Button::UP);
+            let latched = (controller.write(strobe), controller.write(release));
+            let bits: Vec<u8> = (0..10).map(|_| controller.read()).collect();
+            (latched, bits)
+        };
+        assert_eq!(read_with(0x03, 0x02), read_with(0x01, 0x00));
+        assert_eq!(read_with(0x07, 0x06), read_with(0x01, 0x00));
+    }
+
+    #[test]
+    fn test_port_passes_strobe_and_keeps_expansion_bits() {
+        use std::sync::{Arc, Mutex};
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut port = ControllerPort::new();
+        let sink = seen.clone();
+        port.set_expansion_callback(Some(Box::new(move |bits| sink.lock().unwrap().push(bits))));
+
+        assert_eq!(port.write(0x07), 1);
+        assert!(port.strobe());
+        assert_eq!(port.write(0x04), 0);
+        assert_eq!(port.expansion_bits(), 0b10);
+        assert_eq!(port.write(0xF9), 1);
+        assert_eq!(*seen.lock().unwrap(), vec![0b11, 0b10, 0b00]);
+
+        // A copy keeps the latch but not the device
+        let mut copy = port.clone();
+        assert!(copy.strobe());
+        copy.write(0x02);
+        assert_eq!(seen.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_reads_after_eighth_return_one() {
+        let mut controller = Controller::new();
+        controller.write(1);
+        controller.write(0);
+
+        for _ in 0..8 {
+            assert_eq!(controller.read(), 0);
+        }
+        assert_eq!(controller.read(), 1);
+        assert_eq!(controller.read(), 1);
+    }
+}
@@ -10,7 +10,7 @@ impl<M: CpuMemory> Cpu6502<M> {
         use super::opcodes::*;
         
         let info = get_opcode_info(opcode)
-            .ok_or_else(|| emu_core::EmulatorError::InvalidOpcode(opcode))?;
+            .ok_or(emu_core::EmulatorError::InvalidOpcode(opcode))?;
         
         let mut cycles = info.cycles;
         
@@ -117,7 +117,7 @@ impl<M: CpuMemory> Cpu6502<M> {
             
             // JMP - Jump
             0x4C => { self.pc = self.addr_absolute(); }
-            0x6C => { self.pc = self.addr_indirect(); }
+            0x6C => { self.pc = self.addr_indirect(); if self.is_cmos() { cycles += 1; } }
             
             // JSR - Jump to Subroutine
             0x20 => { let a = self.fetch_word(); self.push_word(self.pc.wrapping_sub(1)); self.pc = a; }
@@ -239,6 +239,11 @@ impl<M: CpuMemory> Cpu6502<M> {
             _ => return Err(emu_core::EmulatorError::InvalidOpcode(opcode)),
         }
         
+        // The 65C02 spends a cycle fixing up the flags of a BCD result
+        if self.is_cmos() && self.get_flag(StatusFlags::DECIMAL) && matches!(info.mnemonic, "ADC" | "SBC") {
+            cycles += 1;
+        }
+        
         self.cycles += cycles as u64;
         Ok(cycles)
     }
@@ -247,6 +252,10 @@ impl<M: CpuMemory> Cpu6502<M> {
     
     /// ADC - Add with Carry
     fn adc(&mut self, value: u8) {
+        if self.is_cmos() && self.get_flag(StatusFlags::DECIMAL) {
+            self.adc_decimal(value);
+            return;
+        }
         let carry = if self.get_flag(StatusFlags::CARRY) { 1 } else { 0 };
         let sum = self.a as u16 + value as u16 + carry;
         
@@ -262,9 +271,60 @@ impl<M: CpuMemory> Cpu6502<M> {
     
     /// SBC - Subtract with Carry
     fn sbc(&mut self, value: u8) {
+        if self.is_cmos() && self.get_flag(StatusFlags::DECIMAL) {
+            self.sbc_decimal(value);
+            return;
+        }
         self.adc(!value);
     }
     
+    /// ADC in decimal mode, as the 65C02 does it
+    ///
+    /// V comes from the sum before the high digit is adjusted; invalid BCD
+    /// digits give the same results as on the chip.
+    fn adc_decimal(&mut self, value: u8) {
+        let carry = if self.get_flag(StatusFlags::CARRY) { 1 } else { 0 };
+        let mut low = (self.a & 0x0F) as i16 + (value & 0x0F) as i16 + carry;
+        if low >= 0x0A {
+            low = ((low + 0x06) & 0x0F) + 0x10;
+        }
+        let mut sum = (self.a & 0xF0) as i16 + (value & 0xF0) as i16 + low;
+        
+        let signed = (self.a & 0xF0) as i8 as i16 + (value & 0xF0) as i8 as i16 + low;
+        self.set_flag(StatusFlags::OVERFLOW, !(-128..=127).contains(&signed));
+        
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
+        self.set_flag(StatusFlags::CARRY, sum >= 0x100);
+        self.a = sum as u8;
+        self.update_zn(self.a);
+    }
+    
+    /// SBC in decimal mode, as the 65C02 does it
+    ///
+    /// C and V are those of the binary subtraction.
+    fn sbc_decimal(&mut self, value: u8) {
+        let borrow = if self.get_flag(StatusFlags::CARRY) { 0 } else { 1 };
+        let binary = self.a as i16 - value as i16 - borrow;
+        let low = (self.a & 0x0F) as i16 - (value & 0x0F) as i16 - borrow;
+        
+        let result = binary as u8;
+        let overflow = (self.a ^ value) & (self.a ^ result) & 0x80 != 0;
+        self.set_flag(StatusFlags::OVERFLOW, overflow);
+        self.set_flag(StatusFlags::CARRY, binary >= 0);
+        
+        let mut sum = binary;
+        if sum < 0 {
+            sum -= 0x60;
+        }
+        if low < 0 {
+            sum -= 0x06;
+        }
+        self.a = sum as u8;
+        self.update_zn(self.a);
+    }
+    
     /// AND - Logical AND
     fn and(&mut self, value: u8) {
         self.a &= value;
@@ -312,18 +372,31 @@ impl<M: CpuMemory> Cpu6502<M> {
         self.compare(self.y, value);
     }
     
-    /// INC - Increment Memory
-    fn inc(&mut self, addr: u16) {
-        let value = self.memory.read(addr).wrapping_add(1);
+    /// Read-modify-write of `addr`
+    ///
+    /// The 6502 writes the unmodified value back before the result, through
+    /// [`CpuMemory::rmw_dummy_write`] so the bus decides what it reaches.
+    /// The 65C02 reads the address a second time instead.
+    fn modify(&mut self, addr: u16, op: impl FnOnce(&mut Self, u8) -> u8) {
+        let value = self.memory.read(addr);
+        if self.is_cmos() {
+            self.memory.read(addr);
+        } else {
+            self.memory.rmw_dummy_write(addr, value);
+        }
+        let value = op(self, value);
         self.memory.write(addr, value);
         self.update_zn(value);
     }
     
+    /// INC - Increment Memory
+    fn inc(&mut self, addr: u16) {
+        self.modify(addr, |_, value| value.wrapping_add(1));
+    }
+    
     /// DEC - Decrement Memory
     fn dec(&mut self, addr: u16) {
-        let value = self.memory.read(addr).wrapping_sub(1);
-        self.memory.write(addr, value);
-        self.update_zn(value);
+        self.modify(addr, |_, value| value.wrapping_sub(1));
     }
     
     /// ASL - Arithmetic Shift Left (Accumulator)
@@ -335,11 +408,10 @@ impl<M: CpuMemory> Cpu6502<M> {
     
     /// ASL - Arithmetic Shift Left (Memory)
     fn asl(&mut self, addr: u16) {
-        let mut value = self.memory.read(addr);
-        self.set_flag(StatusFlags::CARRY, value & 0x80 != 0);
-        value <<= 1;
-        self.memory.write(addr, value);
-        self.update_zn(value);
+        self.modify(addr, |cpu, value| {
+            cpu.set_flag(StatusFlags::CARRY, value & 0x80 != 0);
+            value << 1
+        });
     }
     
     /// LSR - Logical Shift Right (Accumulator)
@@ -351,11 +423,10 @@ impl<M: CpuMemory> Cpu6502<M> {
     
     /// LSR - Logical Shift Right (Memory)
     fn lsr(&mut self, addr: u16) {
-        let mut value = self.memory.read(addr);
-        self.set_flag(StatusFlags::CARRY, value & 0x01 != 0);
-        value >>= 1;
-        self.memory.write(addr, value);
-        self.update_zn(value);
+        self.modify(addr, |cpu, value| {
+            cpu.set_flag(StatusFlags::CARRY, value & 0x01 != 0);
+            value >> 1
+        });
     }
     
     /// ROL - Rotate Left (Accumulator)
@@ -368,12 +439,11 @@ impl<M: CpuMemory> Cpu6502<M> {
     
     /// ROL - Rotate Left (Memory)
     fn rol(&mut self, addr: u16) {
-        let mut value = self.memory.read(addr);
-        let carry = if self.get_flag(StatusFlags::CARRY) { 1 } else { 0 };
-        self.set_flag(StatusFlags::CARRY, value & 0x80 != 0);
-        value = (value << 1) | carry;
-        self.memory.write(addr, value);
-        self.update_zn(value);
+        self.modify(addr, |cpu, value| {
+            let carry = if cpu.get_flag(StatusFlags::CARRY) { 1 } else { 0 };
+            cpu.set_flag(StatusFlags::CARRY, value & 0x80 != 0);
+            (value << 1) | carry
+        });
     }
     
     /// ROR - Rotate Right (Accumulator)
@@ -386,12 +456,11 @@ impl<M: CpuMemory> Cpu6502<M> {
     
     /// ROR - Rotate Right (Memory)
     fn ror(&mut self, addr: u16) {
-        let mut value = self.memory.read(addr);
-        let carry = if self.get_flag(StatusFlags::CARRY) { 0x80 } else { 0 };
-        self.set_flag(StatusFlags::CARRY, value & 0x01 != 0);
-        value = (value >> 1) | carry;
-        self.memory.write(addr, value);
-        self.update_zn(value);
+        self.modify(addr, |cpu, value| {
+            let carry = if cpu.get_flag(StatusFlags::CARRY) { 0x80 } else { 0 };
+            cpu.set_flag(StatusFlags::CARRY, value & 0x01 != 0);
+            (value >> 1) | carry
+        });
     }
     
     /// Branch helper - returns cycle count
@@ -409,11 +478,18 @@ impl<M: CpuMemory> Cpu6502<M> {
     }
     
     /// BRK - Force Interrupt
+    ///
+    /// An NMI pending by the vector fetch hijacks the BRK: the stacked status
+    /// still has B set, but the NMI vector is used and the NMI is consumed.
     fn brk(&mut self) {
         self.pc = self.pc.wrapping_add(1);
         self.push_word(self.pc);
         self.push(self.status.bits() | StatusFlags::BREAK.bits() | StatusFlags::UNUSED.bits());
         self.set_flag(StatusFlags::INTERRUPT, true);
-        self.pc = self.memory.read_word(0xFFFE);
+        if self.is_cmos() {
+            self.set_flag(StatusFlags::DECIMAL, false);
+        }
+        let vector = if self.memory.poll_nmi() { 0xFFFA } else { 0xFFFE };
+        self.pc = self.memory.read_word(vector);
     }
 }
@@ -1,9 +1,11 @@
 //! 6502 CPU implementation for NES
 
 mod instructions;
-mod opcodes;
+pub(crate) mod opcodes;
+pub mod testing;
 
 use bitflags::bitflags;
+use crate::savestate::{Snapshot, StateReader, StateWriter};
 use emu_core::{Cpu as CpuTrait, Result};
 
 bitflags! {
@@ -34,9 +36,50 @@ pub trait CpuMemory {
         let hi = self.read(addr.wrapping_add(1)) as u16;
         (hi << 8) | lo
     }
+
+    /// Take an NMI that is waiting to be serviced, if any
+    ///
+    /// BRK calls this right before fetching its vector: an NMI that arrived
+    /// during the pushes hijacks the BRK, which then jumps through $FFFA.
+    /// Whoever normally delivers NMIs must not deliver one taken here.
+    fn poll_nmi(&mut self) -> bool {
+        false
+    }
+
+    /// The unmodified value a read-modify-write instruction writes back
+    /// before its result
+    ///
+    /// The NMOS 6502 makes this write wherever the address is, so by
+    /// default it's an ordinary write. A bus can drop it where nothing can
+    /// tell the difference.
+    fn rmw_dummy_write(&mut self, addr: u16, value: u8) {
+        self.write(addr, value);
+    }
+}
+
+/// Which 6502 the CPU behaves as
+///
+/// The NES has an NMOS 6502 (a 2A03, with decimal mode wired off). The
+/// CMOS variant is for using the CPU outside a NES; it keeps the NMOS
+/// opcode table, including the undocumented opcodes, and differs in:
+///
+/// - `JMP ($xxFF)` reads its high byte from the next page, taking 6 cycles
+/// - ADC and SBC do BCD arithmetic when D is set, with valid N and Z flags
+///   and one extra cycle
+/// - read-modify-write instructions re-read the address instead of
+///   writing the old value back
+/// - BRK and interrupts clear D
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CpuVariant {
+    /// NMOS 6502 as in the NES, bugs and all
+    #[default]
+    Nmos6502,
+    /// WDC/Rockwell 65C02 corrections
+    Cmos65C02,
 }
 
 /// 6502 CPU implementation
+#[derive(Debug, Clone)]
 pub struct Cpu6502<M: CpuMemory> {
     /// Accumulator
     pub a: u8,
@@ -54,6 +97,8 @@ pub struct Cpu6502<M: CpuMemory> {
     memory: M,
     /// Total cycles executed
     pub cycles: u64,
+    /// Which 6502 to behave as
+    variant: CpuVariant,
 }
 
 impl<M: CpuMemory> Cpu6502<M> {
@@ -68,9 +113,26 @@ impl<M: CpuMemory> Cpu6502<M> {
             status: StatusFlags::INTERRUPT | StatusFlags::UNUSED,
             memory,
             cycles: 0,
+            variant: CpuVariant::default(),
         }
     }
 
+    /// Create a CPU that behaves as `variant`
+    pub fn with_variant(memory: M, variant: CpuVariant) -> Self {
+        Self { variant, ..Self::new(memory) }
+    }
+
+    /// Which 6502 the CPU behaves as
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
+
+    /// Whether the CMOS corrections apply
+    #[inline]
+    fn is_cmos(&self) -> bool {
+        self.variant == CpuVariant::Cmos65C02
+    }
+
     /// Get a reference to the memory interface
     pub fn memory(&mut self) -> &mut M {
         &mut self.memory
@@ -198,8 +260,9 @@ impl<M: CpuMemory> Cpu6502<M> {
         let ptr = self.fetch_word();
         
         // 6502 bug: if ptr is at page boundary (e.g. $xxFF),
-        // it wraps within the same page instead of crossing to next page
-        if ptr & 0xFF == 0xFF {
+        // it wraps within the same page instead of crossing to next page.
+        // The 65C02 fixed it.
+        if ptr & 0xFF == 0xFF && !self.is_cmos() {
             let lo = self.memory.read(ptr) as u16;
             let hi = self.memory.read(ptr & 0xFF00) as u16;
             (hi << 8) | lo
@@ -232,17 +295,32 @@ impl<M: CpuMemory> Cpu6502<M> {
     
     /// Trigger NMI (Non-Maskable Interrupt)
     pub fn nmi(&mut self) {
-        // Push PC and status to stack
+        self.interrupt(0xFFFA);
+    }
+    
+    /// Take an IRQ unless the I flag masks it
+    ///
+    /// IRQ is level-triggered: the caller samples the line before each
+    /// instruction and calls this for as long as it's held, so a handler
+    /// that doesn't acknowledge its source is re-entered right after RTI.
+    /// Returns whether the interrupt was taken.
+    pub fn irq(&mut self) -> bool {
+        if self.get_flag(StatusFlags::INTERRUPT) {
+            return false;
+        }
+        self.interrupt(0xFFFE);
+        true
+    }
+    
+    /// Push PC and status, mask IRQs and jump through `vector` (7 cycles)
+    fn interrupt(&mut self, vector: u16) {
         self.push_word(self.pc);
         self.push(self.status.bits() & !StatusFlags::BREAK.bits() | StatusFlags::UNUSED.bits());
-        
-        // Set interrupt disable flag
         self.set_flag(StatusFlags::INTERRUPT, true);
-        
-        // Load NMI vector from $FFFA-$FFFB
-        self.pc = self.memory.read_word(0xFFFA);
-        
-        // NMI takes 7 cycles
+        if self.is_cmos() {
+            self.set_flag(StatusFlags::DECIMAL, false);
+        }
+        self.pc = self.memory.read_word(vector);
         self.cycles += 7;
     }
 }
@@ -269,34 +347,54 @@ impl<M: CpuMemory> CpuTrait for Cpu6502<M> {
         self.execute(opcode)
     }
 
-    fn pc(&self) -> u16 {
-        self.pc
-    }
-
-    fn sp(&self) -> u8 {
-        self.sp
+    fn pc(&self) -> u32 {
+        self.pc as u32
     }
 
-    fn a(&self) -> u8 {
-        self.a
+    fn cycles(&self) -> u64 {
+        self.cycles
     }
 
-    fn x(&self) -> u8 {
-        self.x
+    fn registers(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("A", self.a as u64),
+            ("X", self.x as u64),
+            ("Y", self.y as u64),
+            ("SP", self.sp as u64),
+            ("P", self.status.bits() as u64),
+        ]
     }
+}
 
-    fn y(&self) -> u8 {
-        self.y
+/// Saves the registers followed by everything on the bus
+impl<M: CpuMemory + Snapshot> Snapshot for Cpu6502<M> {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.a);
+        w.u8(self.x);
+        w.u8(self.y);
+        w.u8(self.sp);
+        w.u16(self.pc);
+        w.u8(self.status.bits());
+        w.u64(self.cycles);
+        self.memory.save(w);
     }
 
-    fn status(&self) -> u8 {
-        self.status.bits()
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.a = r.u8()?;
+        self.x = r.u8()?;
+        self.y = r.u8()?;
+        self.sp = r.u8()?;
+        self.pc = r.u16()?;
+        self.status = StatusFlags::from_bits_retain(r.u8()?);
+        self.cycles = r.u64()?;
+        self.memory.load(r)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::testing::{TestBoard, Until};
 
     struct TestMemory {
         ram: Vec<u8>,
@@ -406,91 +504,63 @@ mod tests {
 
     #[test]
     fn test_adc_no_carry() {
-        let mut memory = TestMemory::new();
-        memory.ram[0] = 0xA9;  // LDA #$10
-        memory.ram[1] = 0x10;
-        memory.ram[2] = 0x69;  // ADC #$20
-        memory.ram[3] = 0x20;
+        let mut board = TestBoard::new();
+        board.load(0x0000, &[
+            0xA9, 0x10,  // LDA #$10
+            0x69, 0x20,  // ADC #$20
+        ]);
         
-        let mut cpu = Cpu6502::new(memory);
-        cpu.pc = 0;
+        board.run_until(Until::Instructions(2)).unwrap();
         
-        cpu.step().unwrap();  // LDA
-        cpu.step().unwrap();  // ADC
-        
-        assert_eq!(cpu.a, 0x30);
-        assert!(!cpu.get_flag(StatusFlags::CARRY));
-        assert!(!cpu.get_flag(StatusFlags::ZERO));
-        assert!(!cpu.get_flag(StatusFlags::OVERFLOW));
+        board
+            .assert_a(0x30)
+            .assert_flags_clear(StatusFlags::CARRY | StatusFlags::ZERO | StatusFlags::OVERFLOW);
     }
 
     #[test]
     fn test_adc_with_carry() {
-        let mut memory = TestMemory::new();
-        memory.ram[0] = 0xA9;  // LDA #$FF
-        memory.ram[1] = 0xFF;
-        memory.ram[2] = 0x69;  // ADC #$02
-        memory.ram[3] = 0x02;
+        let mut board = TestBoard::new();
+        board.load(0x0000, &[
+            0xA9, 0xFF,  // LDA #$FF
+            0x69, 0x02,  // ADC #$02
+        ]);
         
-        let mut cpu = Cpu6502::new(memory);
-        cpu.pc = 0;
+        board.run_until(Until::Instructions(2)).unwrap();
         
-        cpu.step().unwrap();  // LDA
-        cpu.step().unwrap();  // ADC
-        
-        assert_eq!(cpu.a, 0x01);
-        assert!(cpu.get_flag(StatusFlags::CARRY));
+        board.assert_a(0x01).assert_flags(StatusFlags::CARRY);
     }
 
     #[test]
     fn test_sbc() {
-        let mut memory = TestMemory::new();
-        memory.ram[0] = 0xA9;  // LDA #$50
-        memory.ram[1] = 0x50;
-        memory.ram[2] = 0x38;  // SEC (set carry)
-        memory.ram[3] = 0xE9;  // SBC #$30
-        memory.ram[4] = 0x30;
+        let mut board = TestBoard::new();
+        board.load(0x0000, &[
+            0xA9, 0x50,  // LDA #$50
+            0x38,        // SEC (set carry)
+            0xE9, 0x30,  // SBC #$30
+        ]);
         
-        let mut cpu = Cpu6502::new(memory);
-        cpu.pc = 0;
+        board.run_until(Until::Instructions(3)).unwrap();
         
-        cpu.step().unwrap();  // LDA
-        cpu.step().unwrap();  // SEC
-        cpu.step().unwrap();  // SBC
-        
-        assert_eq!(cpu.a, 0x20);
-        assert!(cpu.get_flag(StatusFlags::CARRY));
+        board.assert_a(0x20).assert_flags(StatusFlags::CARRY);
     }
 
     #[test]
     fn test_inc_dec() {
-        let mut memory = TestMemory::new();
-        memory.ram[0] = 0xA9;  // LDA #$10
-        memory.ram[1] = 0x10;
-        memory.ram[2] = 0x85;  // STA $20
-        memory.ram[3] = 0x20;
-        memory.ram[4] = 0xE6;  // INC $20
-        memory.ram[5] = 0x20;
-        memory.ram[6] = 0xA5;  // LDA $20
-        memory.ram[7] = 0x20;
-        memory.ram[8] = 0xC6;  // DEC $20
-        memory.ram[9] = 0x20;
-        memory.ram[10] = 0xA5; // LDA $20
-        memory.ram[11] = 0x20;
-        
-        let mut cpu = Cpu6502::new(memory);
-        cpu.pc = 0;
-        
-        cpu.step().unwrap();  // LDA #$10
-        cpu.step().unwrap();  // STA $20
-        
-        cpu.step().unwrap();  // INC $20
-        cpu.step().unwrap();  // LDA $20
-        assert_eq!(cpu.a, 0x11);
-        
-        cpu.step().unwrap();  // DEC $20
-        cpu.step().unwrap();  // LDA $20
-        assert_eq!(cpu.a, 0x10);
+        let mut board = TestBoard::new();
+        board.load(0x0000, &[
+            0xA9, 0x10,  // LDA #$10
+            0x85, 0x20,  // STA $20
+            0xE6, 0x20,  // INC $20
+            0xA5, 0x20,  // LDA $20
+            0xC6, 0x20,  // DEC $20
+            0xA5, 0x20,  // LDA $20
+        ]);
+        
+        board.run_until(Until::Pc(0x0008)).unwrap();
+        board.assert_a(0x11).assert_mem(0x20, 0x11);
+        
+        board.run_until(Until::Pc(0x000C)).unwrap();
+        board.assert_a(0x10).assert_mem(0x20, 0x10);
     }
 
     #[test]
@@ -683,22 +753,17 @@ mod tests {
 
     #[test]
     fn test_jsr_rts() {
-        let mut memory = TestMemory::new();
-        memory.ram[0x00] = 0x20;  // JSR $1000
-        memory.ram[0x01] = 0x00;
-        memory.ram[0x02] = 0x10;
-        memory.ram[0x1000] = 0x60;  // RTS
+        let mut board = TestBoard::new();
+        board
+            .load(0x0000, &[0x20, 0x00, 0x10])  // JSR $1000
+            .load(0x1000, &[0x60]);             // RTS
         
-        let mut cpu = Cpu6502::new(memory);
-        cpu.pc = 0;
-        
-        cpu.step().unwrap();  // JSR
-        assert_eq!(cpu.pc, 0x1000);
-        assert_eq!(cpu.sp, 0xFB);  // SP decremented by 2
+        board.run_until(Until::Pc(0x1000)).unwrap();
+        assert_eq!(board.cpu().sp, 0xFB);  // SP decremented by 2
         
-        cpu.step().unwrap();  // RTS
-        assert_eq!(cpu.pc, 0x03);  // Returns to next instruction after JSR
-        assert_eq!(cpu.sp, 0xFD);  // SP restored
+        board.run_until(Until::Instructions(1)).unwrap();
+        assert_eq!(board.cpu().pc, 0x03);  // Returns to next instruction after JSR
+        assert_eq!(board.cpu().sp, 0xFD);  // SP restored
     }
 
     #[test]
@@ -725,4 +790,690 @@ mod tests {
         assert_eq!(cpu.a, 0x42);
         assert_eq!(cpu.sp, 0xFD);
     }
+
+    /// PC wrapping at $FFFF and SP wrapping within page 1
+    mod wrap {
+        use super::super::testing::{TestBoard, Until};
+        use super::super::*;
+
+        /// Run one instruction at `pc`
+        fn step_at(board: &mut TestBoard, pc: u16) {
+            board.cpu_mut().pc = pc;
+            board.run_until(Until::Instructions(1)).unwrap();
+        }
+
+        #[test]
+        fn test_jsr_rts_across_the_wrap() {
+            // JSR at $FFFD returns to $0000; at $FFFE its operand wraps too
+            for jsr in [0xFFFD, 0xFFFE] {
+                let mut board = TestBoard::new();
+                board
+                    .load(jsr, &[0x20, 0x00, 0x10]) // JSR $1000
+                    .load(0x1000, &[0x60]); // RTS
+                board.cpu_mut().sp = 0xFD;
+
+                step_at(&mut board, jsr);
+                assert_eq!(board.cpu().pc, 0x1000);
+                let pushed = jsr.wrapping_add(2);
+                board
+                    .assert_mem(0x01FD, (pushed >> 8) as u8)
+                    .assert_mem(0x01FC, pushed as u8);
+
+                board.run_until(Until::Instructions(1)).unwrap();
+                assert_eq!(board.cpu().pc, jsr.wrapping_add(3));
+                assert_eq!(board.cpu().sp, 0xFD);
+            }
+        }
+
+        #[test]
+        fn test_rts_from_ffff_resumes_at_zero() {
+            let mut board = TestBoard::new();
+            board.load(0x0200, &[0x60]).load(0x01FE, &[0xFF, 0xFF]);
+            board.cpu_mut().sp = 0xFD;
+
+            step_at(&mut board, 0x0200);
+            assert_eq!(board.cpu().pc, 0x0000);
+            assert_eq!(board.cpu().sp, 0xFF);
+        }
+
+        #[test]
+        fn test_stack_overflow_stays_in_page_one() {
+            let mut board = TestBoard::new();
+            board.load(0x0200, &[0x48]); // PHA
+            board.cpu_mut().sp = 0xFD;
+
+            for i in 0..300u16 {
+                board.cpu_mut().a = i as u8;
+                step_at(&mut board, 0x0200);
+            }
+            assert_eq!(board.cpu().sp, 0xFDu8.wrapping_sub(44));
+
+            // The last 256 pushes fill page 1 and nothing spills out
+            for i in 44..300u16 {
+                board.assert_mem(0x0100 | 0xFDu8.wrapping_sub(i as u8) as u16, i as u8);
+            }
+            for addr in (0x0000..0x0100).chain(0x0201..0x0300) {
+                board.assert_mem(addr, 0x00);
+            }
+
+            // Popping them back wraps the other way
+            board.load(0x0201, &[0x68]); // PLA
+            for i in (44..300u16).rev() {
+                step_at(&mut board, 0x0201);
+                board.assert_a(i as u8);
+            }
+        }
+
+        #[test]
+        fn test_rti_ignores_break_flag() {
+            let mut board = TestBoard::new();
+            board
+                .load(0x0200, &[0x40]) // RTI
+                .load(0x01FB, &[0xFF, 0x34, 0x12]); // status, return address
+            board.cpu_mut().sp = 0xFA;
+
+            step_at(&mut board, 0x0200);
+            assert_eq!(board.cpu().pc, 0x1234);
+            assert_eq!(board.cpu().sp, 0xFD);
+            board
+                .assert_flags(StatusFlags::CARRY | StatusFlags::NEGATIVE | StatusFlags::UNUSED)
+                .assert_flags_clear(StatusFlags::BREAK);
+        }
+
+        #[test]
+        fn test_nmi_with_wrapping_stack() {
+            let mut board = TestBoard::new();
+            board
+                .load(0xFFFA, &[0x00, 0x30]) // NMI vector
+                .load(0x3000, &[0x40]); // RTI
+            board.cpu_mut().sp = 0x01;
+            board.cpu_mut().pc = 0xABCD;
+
+            board.cpu_mut().nmi();
+            assert_eq!(board.cpu().pc, 0x3000);
+            assert_eq!(board.cpu().sp, 0xFE);
+            board.assert_mem(0x0101, 0xAB).assert_mem(0x0100, 0xCD);
+            board.assert_mem(0x01FF, (StatusFlags::INTERRUPT | StatusFlags::UNUSED).bits());
+
+            board.run_until(Until::Instructions(1)).unwrap();
+            assert_eq!(board.cpu().pc, 0xABCD);
+            assert_eq!(board.cpu().sp, 0x01);
+        }
+
+        #[test]
+        fn test_irq_respects_interrupt_flag() {
+            let mut board = TestBoard::new();
+            board.load(0xFFFE, &[0x00, 0x40]); // IRQ vector
+            board.cpu_mut().pc = 0x1234;
+
+            board.cpu_mut().set_flag(StatusFlags::INTERRUPT, true);
+            assert!(!board.cpu_mut().irq());
+            assert_eq!(board.cpu().pc, 0x1234);
+
+            board.cpu_mut().set_flag(StatusFlags::INTERRUPT, false);
+            let cycles = board.cpu().cycles;
+            assert!(board.cpu_mut().irq());
+            assert_eq!(board.cpu().pc, 0x4000);
+            assert_eq!(board.cpu().cycles, cycles + 7);
+            // Masked while the handler runs
+            assert!(board.cpu().get_flag(StatusFlags::INTERRUPT));
+            assert!(!board.cpu_mut().irq());
+        }
+
+        #[test]
+        fn test_nmi_hijacks_brk() {
+            let mut board = TestBoard::new();
+            board
+                .load(0xFFFA, &[0x00, 0x30]) // NMI vector
+                .load(0xFFFE, &[0x00, 0x40]); // IRQ/BRK vector
+            board.cpu_mut().sp = 0xFD;
+            board.cpu_mut().memory().nmi_pending = true;
+
+            step_at(&mut board, 0x0200);
+            assert_eq!(board.cpu().pc, 0x3000);
+            board.assert_mem(0x01FD, 0x02).assert_mem(0x01FC, 0x02);
+            assert_ne!(board.read(0x01FB) & StatusFlags::BREAK.bits(), 0);
+            assert!(!board.cpu_mut().memory().nmi_pending, "the NMI is consumed");
+
+            // Without an NMI, BRK uses its own vector
+            step_at(&mut board, 0x0200);
+            assert_eq!(board.cpu().pc, 0x4000);
+        }
+
+        #[test]
+        fn test_brk_at_ffff_pushes_wrapped_address() {
+            let mut board = TestBoard::new();
+            // $FFFF is both the BRK and the vector's high byte: vector $0040
+            board.load(0xFFFE, &[0x40, 0x00]).load(0x0040, &[0x40]); // RTI
+            board.cpu_mut().sp = 0xFD;
+
+            step_at(&mut board, 0xFFFF);
+            assert_eq!(board.cpu().pc, 0x0040);
+            board.assert_mem(0x01FD, 0x00).assert_mem(0x01FC, 0x01);
+            assert_ne!(board.read(0x01FB) & StatusFlags::BREAK.bits(), 0);
+
+            board.run_until(Until::Instructions(1)).unwrap();
+            assert_eq!(board.cpu().pc, 0x0001);
+            board.assert_flags_clear(StatusFlags::BREAK);
+        }
+    }
+
+    /// Where the 65C02 differs from the NES's 6502
+    mod variants {
+        use super::super::testing::{TestBoard, Until};
+        use super::super::*;
+
+        const BOTH: [CpuVariant; 2] = [CpuVariant::Nmos6502, CpuVariant::Cmos65C02];
+
+        /// Run `program` from $0200 with `status` and A set, for one instruction
+        fn run_one(variant: CpuVariant, program: &[u8], a: u8, status: StatusFlags) -> (TestBoard, u8) {
+            let mut board = TestBoard::with_variant(variant);
+            board.load(0x0200, program);
+            board.cpu_mut().pc = 0x0200;
+            board.cpu_mut().a = a;
+            board.cpu_mut().status = status | StatusFlags::UNUSED;
+            let before = board.cpu().cycles;
+            board.run_until(Until::Instructions(1)).unwrap();
+            let cycles = (board.cpu().cycles - before) as u8;
+            (board, cycles)
+        }
+
+        #[test]
+        fn test_nmos_is_the_default() {
+            assert_eq!(TestBoard::new().cpu().variant(), CpuVariant::Nmos6502);
+            assert_eq!(CpuVariant::default(), CpuVariant::Nmos6502);
+        }
+
+        #[test]
+        fn test_jmp_indirect_page_wrap() {
+            // JMP ($10FF): low byte from $10FF, high byte from $1000 or $1100
+            let targets = [(CpuVariant::Nmos6502, 0x1234, 5), (CpuVariant::Cmos65C02, 0x5634, 6)];
+            for (variant, target, expected_cycles) in targets {
+                let mut board = TestBoard::with_variant(variant);
+                board.load(0x10FF, &[0x34]).load(0x1000, &[0x12]).load(0x1100, &[0x56]);
+                board.load(0x0200, &[0x6C, 0xFF, 0x10]);
+                board.cpu_mut().pc = 0x0200;
+                board.run_until(Until::Instructions(1)).unwrap();
+                assert_eq!(board.cpu().pc, target, "{:?}", variant);
+                assert_eq!(board.cpu().cycles, expected_cycles, "{:?}", variant);
+            }
+
+            // Away from a page end they agree
+            for variant in BOTH {
+                let mut board = TestBoard::with_variant(variant);
+                board.load(0x1080, &[0x34, 0x12]).load(0x0200, &[0x6C, 0x80, 0x10]);
+                board.cpu_mut().pc = 0x0200;
+                board.run_until(Until::Instructions(1)).unwrap();
+                assert_eq!(board.cpu().pc, 0x1234, "{:?}", variant);
+            }
+        }
+
+        #[test]
+        fn test_decimal_adc() {
+            // (A, operand, carry in) -> (A, carry out)
+            let vectors = [
+                (0x99, 0x01, false, 0x00, true),
+                (0x99, 0x00, true, 0x00, true),
+                (0x12, 0x34, false, 0x46, false),
+                (0x58, 0x46, true, 0x05, true),
+                (0x15, 0x26, false, 0x41, false),
+                (0x81, 0x92, false, 0x73, true),
+                (0x09, 0x01, false, 0x10, false),
+            ];
+            for (a, value, carry, expected, carry_out) in vectors {
+                let status = StatusFlags::DECIMAL | if carry { StatusFlags::CARRY } else { StatusFlags::empty() };
+                let (mut board, cycles) = run_one(CpuVariant::Cmos65C02, &[0x69, value], a, status);
+                let case = format!("${:02X} + ${:02X} + {}", a, value, carry as u8);
+                assert_eq!(board.cpu().a, expected, "{}", case);
+                assert_eq!(board.cpu().get_flag(StatusFlags::CARRY), carry_out, "{}: carry", case);
+                assert_eq!(board.cpu().get_flag(StatusFlags::ZERO), expected == 0, "{}: zero", case);
+                assert_eq!(board.cpu().get_flag(StatusFlags::NEGATIVE), expected & 0x80 != 0, "{}: negative", case);
+                assert_eq!(cycles, 3, "{}: cycles", case);
+                board.assert_flags(StatusFlags::DECIMAL);
+            }
+        }
+
+        #[test]
+        fn test_decimal_sbc() {
+            // (A, operand, carry in) -> (A, carry out)
+            let vectors = [
+                (0x46, 0x12, true, 0x34, true),
+                (0x40, 0x13, true, 0x27, true),
+                (0x32, 0x02, false, 0x29, true),
+                (0x00, 0x01, true, 0x99, false),
+                (0x12, 0x21, true, 0x91, false),
+                (0x21, 0x34, true, 0x87, false),
+            ];
+            for (a, value, carry, expected, carry_out) in vectors {
+                let status = StatusFlags::DECIMAL | if carry { StatusFlags::CARRY } else { StatusFlags::empty() };
+                let (board, cycles) = run_one(CpuVariant::Cmos65C02, &[0xE9, value], a, status);
+                let case = format!("${:02X} - ${:02X} - {}", a, value, !carry as u8);
+                assert_eq!(board.cpu().a, expected, "{}", case);
+                assert_eq!(board.cpu().get_flag(StatusFlags::CARRY), carry_out, "{}: carry", case);
+                assert_eq!(cycles, 3, "{}: cycles", case);
+            }
+        }
+
+        #[test]
+        fn test_nmos_ignores_decimal_flag() {
+            let (board, cycles) = run_one(CpuVariant::Nmos6502, &[0x69, 0x01], 0x99, StatusFlags::DECIMAL);
+            assert_eq!(board.cpu().a, 0x9A);
+            assert!(!board.cpu().get_flag(StatusFlags::CARRY));
+            assert_eq!(cycles, 2);
+
+            let (board, _) = run_one(CpuVariant::Nmos6502, &[0xE9, 0x01], 0x00, StatusFlags::DECIMAL | StatusFlags::CARRY);
+            assert_eq!(board.cpu().a, 0xFF);
+
+            // With D clear the variants agree
+            for variant in BOTH {
+                let (board, cycles) = run_one(variant, &[0x69, 0x01], 0x99, StatusFlags::empty());
+                assert_eq!((board.cpu().a, cycles), (0x9A, 2), "{:?}", variant);
+            }
+        }
+
+        #[test]
+        fn test_brk_clears_decimal_on_cmos() {
+            for (variant, decimal) in [(CpuVariant::Nmos6502, true), (CpuVariant::Cmos65C02, false)] {
+                let (mut board, _) = run_one(variant, &[0x00], 0, StatusFlags::DECIMAL);
+                assert_eq!(board.cpu().get_flag(StatusFlags::DECIMAL), decimal, "{:?} BRK", variant);
+
+                board.cpu_mut().set_flag(StatusFlags::DECIMAL, true);
+                board.cpu_mut().nmi();
+                assert_eq!(board.cpu().get_flag(StatusFlags::DECIMAL), decimal, "{:?} NMI", variant);
+            }
+        }
+    }
+
+    /// Effective addresses, page crossings and the exact bus accesses of
+    /// every addressing mode
+    ///
+    /// The CPU doesn't model most of the dummy reads and writes real
+    /// hardware makes, so the access logs here are the minimal ones: operand
+    /// bytes, pointer bytes, then the data access. The exception is the
+    /// read-modify-write dummy write.
+    mod addressing {
+        use super::super::*;
+
+        /// Operands are placed here unless a case says otherwise
+        const PC: u16 = 0x0300;
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        enum Access {
+            Read(u16),
+            Write(u16, u8),
+        }
+        use Access::{Read, Write};
+
+        /// Flat RAM that logs every access
+        struct RecordingMemory {
+            ram: Vec<u8>,
+            log: Vec<Access>,
+        }
+
+        impl CpuMemory for RecordingMemory {
+            fn read(&mut self, addr: u16) -> u8 {
+                self.log.push(Read(addr));
+                self.ram[addr as usize]
+            }
+
+            fn write(&mut self, addr: u16, value: u8) {
+                self.log.push(Write(addr, value));
+                self.ram[addr as usize] = value;
+            }
+        }
+
+        /// One addressing-mode case
+        struct Case {
+            name: &'static str,
+            /// Where the operand bytes go (PC when the helper runs)
+            pc: u16,
+            operand: &'static [u8],
+            x: u8,
+            y: u8,
+            /// Pointer or data bytes to place in RAM
+            ram: &'static [(u16, u8)],
+            addr: u16,
+            crossed: bool,
+            /// Every access the helper should make, in order
+            reads: &'static [u16],
+        }
+
+        impl Case {
+            const fn new(name: &'static str, operand: &'static [u8], addr: u16) -> Self {
+                Self { name, pc: PC, operand, x: 0, y: 0, ram: &[], addr, crossed: false, reads: &[] }
+            }
+        }
+
+        /// CPU with the case's RAM, operand at its PC and an empty log
+        fn cpu_for(case: &Case) -> Cpu6502<RecordingMemory> {
+            let mut memory = RecordingMemory { ram: vec![0; 0x10000], log: Vec::new() };
+            for (i, &byte) in case.operand.iter().enumerate() {
+                memory.ram[case.pc.wrapping_add(i as u16) as usize] = byte;
+            }
+            for &(addr, value) in case.ram {
+                memory.ram[addr as usize] = value;
+            }
+            let mut cpu = Cpu6502::new(memory);
+            cpu.pc = case.pc;
+            cpu.x = case.x;
+            cpu.y = case.y;
+            cpu
+        }
+
+        /// Run `mode` for each case and check address, page crossing, accesses and PC
+        fn check(cases: &[Case], mode: fn(&mut Cpu6502<RecordingMemory>) -> (u16, bool)) {
+            for case in cases {
+                let mut cpu = cpu_for(case);
+                let (addr, crossed) = mode(&mut cpu);
+                assert_eq!(addr, case.addr, "{}: effective address", case.name);
+                assert_eq!(crossed, case.crossed, "{}: page crossed", case.name);
+                let reads: Vec<_> = case.reads.iter().map(|&addr| Read(addr)).collect();
+                assert_eq!(cpu.memory.log, reads, "{}: accesses", case.name);
+                let operand_len = case.operand.len() as u16;
+                assert_eq!(cpu.pc, case.pc.wrapping_add(operand_len), "{}: PC", case.name);
+            }
+        }
+
+        #[test]
+        fn test_zero_page() {
+            check(
+                &[
+                    Case { reads: &[PC], ..Case::new("$42", &[0x42], 0x0042) },
+                    Case { reads: &[PC], ..Case::new("$FF", &[0xFF], 0x00FF) },
+                ],
+                |cpu| (cpu.addr_zero_page(), false),
+            );
+        }
+
+        #[test]
+        fn test_zero_page_x_wraps_in_page_zero() {
+            check(
+                &[
+                    Case { x: 0x05, reads: &[PC], ..Case::new("$80,X", &[0x80], 0x0085) },
+                    Case { x: 0x01, reads: &[PC], ..Case::new("$FF+1", &[0xFF], 0x0000) },
+                    Case { x: 0xFF, reads: &[PC], ..Case::new("$80+$FF", &[0x80], 0x007F) },
+                    // Y plays no part
+                    Case { y: 0x10, reads: &[PC], ..Case::new("Y ignored", &[0x10], 0x0010) },
+                ],
+                |cpu| (cpu.addr_zero_page_x(), false),
+            );
+        }
+
+        #[test]
+        fn test_zero_page_y_wraps_in_page_zero() {
+            check(
+                &[
+                    Case { y: 0x10, reads: &[PC], ..Case::new("$10,Y", &[0x10], 0x0020) },
+                    Case { y: 0x02, reads: &[PC], ..Case::new("$FF+2", &[0xFF], 0x0001) },
+                    Case { x: 0x10, reads: &[PC], ..Case::new("X ignored", &[0x10], 0x0010) },
+                ],
+                |cpu| (cpu.addr_zero_page_y(), false),
+            );
+        }
+
+        #[test]
+        fn test_absolute() {
+            check(
+                &[
+                    Case { reads: &[PC, PC + 1], ..Case::new("$1234", &[0x34, 0x12], 0x1234) },
+                    // Operand fetch wraps from $FFFF to $0000
+                    Case {
+                        pc: 0xFFFF,
+                        reads: &[0xFFFF, 0x0000],
+                        ..Case::new("operand at $FFFF", &[0xCD, 0xAB], 0xABCD)
+                    },
+                ],
+                |cpu| (cpu.addr_absolute(), false),
+            );
+        }
+
+        #[test]
+        fn test_absolute_x() {
+            check(
+                &[
+                    Case { x: 0x10, reads: &[PC, PC + 1], ..Case::new("$8000,X", &[0x00, 0x80], 0x8010) },
+                    Case { x: 0xFF, reads: &[PC, PC + 1], ..Case::new("$8000+$FF", &[0x00, 0x80], 0x80FF) },
+                    Case {
+                        x: 0x01,
+                        crossed: true,
+                        reads: &[PC, PC + 1],
+                        ..Case::new("$80FF+1", &[0xFF, 0x80], 0x8100)
+                    },
+                    Case {
+                        x: 0xFF,
+                        crossed: true,
+                        reads: &[PC, PC + 1],
+                        ..Case::new("$80FF+$FF", &[0xFF, 0x80], 0x81FE)
+                    },
+                    // Indexing past $FFFF wraps to page zero
+                    Case {
+                        x: 0x02,
+                        crossed: true,
+                        reads: &[PC, PC + 1],
+                        ..Case::new("$FFFF+2", &[0xFF, 0xFF], 0x0001)
+                    },
+                    Case { y: 0x01, reads: &[PC, PC + 1], ..Case::new("Y ignored", &[0xFF, 0x80], 0x80FF) },
+                ],
+                |cpu| cpu.addr_absolute_x(),
+            );
+        }
+
+        #[test]
+        fn test_absolute_y() {
+            check(
+                &[
+                    Case { y: 0x10, reads: &[PC, PC + 1], ..Case::new("$8000,Y", &[0x00, 0x80], 0x8010) },
+                    Case {
+                        y: 0x01,
+                        crossed: true,
+                        reads: &[PC, PC + 1],
+                        ..Case::new("$80FF+1", &[0xFF, 0x80], 0x8100)
+                    },
+                    Case {
+                        y: 0x80,
+                        crossed: true,
+                        reads: &[PC, PC + 1],
+                        ..Case::new("$FFC0+$80", &[0xC0, 0xFF], 0x0040)
+                    },
+                    Case { x: 0x01, reads: &[PC, PC + 1], ..Case::new("X ignored", &[0xFF, 0x80], 0x80FF) },
+                ],
+                |cpu| cpu.addr_absolute_y(),
+            );
+        }
+
+        #[test]
+        fn test_indirect_page_wrap_bug() {
+            check(
+                &[
+                    Case {
+                        ram: &[(0x1234, 0x78), (0x1235, 0x56)],
+                        reads: &[PC, PC + 1, 0x1234, 0x1235],
+                        ..Case::new("($1234)", &[0x34, 0x12], 0x5678)
+                    },
+                    // The high byte comes from the start of the same page
+                    Case {
+                        ram: &[(0x12FF, 0x78), (0x1200, 0x56), (0x1300, 0xEE)],
+                        reads: &[PC, PC + 1, 0x12FF, 0x1200],
+                        ..Case::new("($12FF)", &[0xFF, 0x12], 0x5678)
+                    },
+                    Case {
+                        ram: &[(0xFFFF, 0x34), (0xFF00, 0x12), (0x0000, 0xEE)],
+                        reads: &[PC, PC + 1, 0xFFFF, 0xFF00],
+                        ..Case::new("($FFFF)", &[0xFF, 0xFF], 0x1234)
+                    },
+                    Case {
+                        ram: &[(0x00FF, 0x34), (0x0000, 0x12)],
+                        reads: &[PC, PC + 1, 0x00FF, 0x0000],
+                        ..Case::new("($00FF)", &[0xFF, 0x00], 0x1234)
+                    },
+                ],
+                |cpu| (cpu.addr_indirect(), false),
+            );
+        }
+
+        #[test]
+        fn test_indexed_indirect_pointer_wraps() {
+            check(
+                &[
+                    Case {
+                        x: 0x04,
+                        ram: &[(0x24, 0x74), (0x25, 0x20)],
+                        reads: &[PC, 0x24, 0x25],
+                        ..Case::new("($20,X)", &[0x20], 0x2074)
+                    },
+                    // Pointer at $FF: high byte from $00, not $0100
+                    Case {
+                        x: 0x01,
+                        ram: &[(0xFF, 0x34), (0x00, 0x12), (0x100, 0xEE)],
+                        reads: &[PC, 0xFF, 0x00],
+                        ..Case::new("($FE,X) X=1", &[0xFE], 0x1234)
+                    },
+                    // Operand plus X wraps before the pointer is read
+                    Case {
+                        x: 0x80,
+                        ram: &[(0x00, 0x00), (0x01, 0x90)],
+                        reads: &[PC, 0x00, 0x01],
+                        ..Case::new("($80,X) X=$80", &[0x80], 0x9000)
+                    },
+                    Case {
+                        x: 0xFF,
+                        ram: &[(0xFF, 0x01), (0x00, 0x02)],
+                        reads: &[PC, 0xFF, 0x00],
+                        ..Case::new("($00,X) X=$FF", &[0x00], 0x0201)
+                    },
+                ],
+                |cpu| (cpu.addr_indexed_indirect(), false),
+            );
+        }
+
+        #[test]
+        fn test_indirect_indexed_pointer_wraps() {
+            check(
+                &[
+                    Case {
+                        y: 0x10,
+                        ram: &[(0x86, 0x28), (0x87, 0x40)],
+                        reads: &[PC, 0x86, 0x87],
+                        ..Case::new("($86),Y", &[0x86], 0x4038)
+                    },
+                    // Pointer at $FF: high byte from $00, not $0100
+                    Case {
+                        y: 0x01,
+                        ram: &[(0xFF, 0x00), (0x00, 0x30), (0x100, 0xEE)],
+                        reads: &[PC, 0xFF, 0x00],
+                        ..Case::new("($FF),Y", &[0xFF], 0x3001)
+                    },
+                    Case {
+                        y: 0x01,
+                        crossed: true,
+                        ram: &[(0x40, 0xFF), (0x41, 0x80)],
+                        reads: &[PC, 0x40, 0x41],
+                        ..Case::new("$80FF+1", &[0x40], 0x8100)
+                    },
+                    Case {
+                        y: 0x01,
+                        crossed: true,
+                        ram: &[(0x40, 0xFF), (0x41, 0xFF)],
+                        reads: &[PC, 0x40, 0x41],
+                        ..Case::new("$FFFF+1", &[0x40], 0x0000)
+                    },
+                    // X plays no part
+                    Case {
+                        x: 0x05,
+                        ram: &[(0x40, 0x00), (0x41, 0x12)],
+                        reads: &[PC, 0x40, 0x41],
+                        ..Case::new("X ignored", &[0x40], 0x1200)
+                    },
+                ],
+                |cpu| cpu.addr_indirect_indexed(),
+            );
+        }
+
+        /// Run the instruction at PC and return its cycles and bus accesses
+        fn run(program: &'static [u8], x: u8, y: u8, ram: &'static [(u16, u8)]) -> (u8, Vec<Access>) {
+            let mut cpu = cpu_for(&Case { x, y, ram, ..Case::new("", program, 0) });
+            let cycles = CpuTrait::step(&mut cpu).unwrap();
+            (cycles, std::mem::take(&mut cpu.memory.log))
+        }
+
+        #[test]
+        fn test_instructions_touch_only_the_effective_address() {
+            // LDA $80FF,X crossing into $8100 costs a cycle
+            let (cycles, log) = run(&[0xBD, 0xFF, 0x80], 0x01, 0, &[]);
+            assert_eq!(log, [Read(PC), Read(PC + 1), Read(PC + 2), Read(0x8100)]);
+            assert_eq!(cycles, 5);
+
+            // STA abs,X never takes the page-cross discount or penalty
+            let (cycles, log) = run(&[0x9D, 0xFF, 0x80], 0x01, 0, &[]);
+            assert_eq!(log, [Read(PC), Read(PC + 1), Read(PC + 2), Write(0x8100, 0)]);
+            assert_eq!(cycles, 5);
+
+            // LDA ($FF),Y reads the pointer from $FF and $00
+            let (cycles, log) = run(&[0xB1, 0xFF], 0, 0x01, &[(0xFF, 0xFF), (0x00, 0x30)]);
+            assert_eq!(log, [Read(PC), Read(PC + 1), Read(0xFF), Read(0x00), Read(0x3100)]);
+            assert_eq!(cycles, 6);
+
+            // LDA ($FF,X) with X=0 does the same, without the penalty
+            let (cycles, log) = run(&[0xA1, 0xFF], 0, 0, &[(0xFF, 0xFF), (0x00, 0x30)]);
+            assert_eq!(log, [Read(PC), Read(PC + 1), Read(0xFF), Read(0x00), Read(0x30FF)]);
+            assert_eq!(cycles, 6);
+
+            // INC $FF,X with X=2 lands on $01, with the dummy write
+            let (cycles, log) = run(&[0xF6, 0xFF], 0x02, 0, &[(0x01, 0x41)]);
+            assert_eq!(log, [Read(PC), Read(PC + 1), Read(0x01), Write(0x01, 0x41), Write(0x01, 0x42)]);
+            assert_eq!(cycles, 6);
+
+            // LDX $FF,Y with Y=1 wraps to $00
+            let (cycles, log) = run(&[0xB6, 0xFF], 0, 0x01, &[]);
+            assert_eq!(log, [Read(PC), Read(PC + 1), Read(0x00)]);
+            assert_eq!(cycles, 4);
+
+            // JMP ($02FF) takes its high byte from $0200
+            let (cycles, log) = run(&[0x6C, 0xFF, 0x02], 0, 0, &[(0x02FF, 0x00), (0x0200, 0x90)]);
+            assert_eq!(log, [Read(PC), Read(PC + 1), Read(PC + 2), Read(0x02FF), Read(0x0200)]);
+            assert_eq!(cycles, 5);
+        }
+
+        #[test]
+        fn test_rmw_writes_twice() {
+            // INC $8000: the mapper sees the old value, then the new one
+            let (cycles, log) = run(&[0xEE, 0x00, 0x80], 0, 0, &[(0x8000, 0x41)]);
+            assert_eq!(
+                log,
+                [Read(PC), Read(PC + 1), Read(PC + 2), Read(0x8000), Write(0x8000, 0x41), Write(0x8000, 0x42)]
+            );
+            assert_eq!(cycles, 6);
+
+            // The other RMW instructions go through the same path, here on
+            // MMC1's serial port at $FFFF
+            let cases = [
+                (&[0xCE, 0xFF, 0xFF], &[(0xFFFF, 0x41)], 0x41, 0x40), // DEC
+                (&[0x0E, 0xFF, 0xFF], &[(0xFFFF, 0x81)], 0x81, 0x02), // ASL
+                (&[0x4E, 0xFF, 0xFF], &[(0xFFFF, 0x81)], 0x81, 0x40), // LSR
+                (&[0x2E, 0xFF, 0xFF], &[(0xFFFF, 0x81)], 0x81, 0x02), // ROL
+                (&[0x6E, 0xFF, 0xFF], &[(0xFFFF, 0x81)], 0x81, 0x40), // ROR
+            ];
+            for (program, ram, old, new) in cases {
+                let (_, log) = run(program, 0, 0, ram);
+                assert_eq!(log[3..], [Read(0xFFFF), Write(0xFFFF, old), Write(0xFFFF, new)], "opcode ${:02X}", program[0]);
+            }
+
+            // Anywhere on the bus, not just in NES cartridge space
+            let (_, log) = run(&[0xEE, 0x10, 0x00], 0, 0, &[(0x0010, 0x41)]);
+            assert_eq!(log[3..], [Read(0x0010), Write(0x0010, 0x41), Write(0x0010, 0x42)]);
+
+            // The 65C02 reads twice instead, RAM included
+            let cases = [
+                Case { ram: &[(0x8000, 0x41)], ..Case::new("cartridge", &[0xEE, 0x00, 0x80], 0x8000) },
+                Case { ram: &[(0x0010, 0x41)], ..Case::new("RAM", &[0xEE, 0x10, 0x00], 0x0010) },
+            ];
+            for case in cases {
+                let addr = case.addr;
+                let mut cpu = cpu_for(&case);
+                cpu.variant = CpuVariant::Cmos65C02;
+                CpuTrait::step(&mut cpu).unwrap();
+                assert_eq!(cpu.memory.log[3..], [Read(addr), Read(addr), Write(addr, 0x42)], "${:04X}", addr);
+            }
+        }
+    }
 }
@@ -18,6 +18,20 @@ pub enum AddressingMode {
     IndirectIndexed,  // (Indirect),Y
 }
 
+impl AddressingMode {
+    /// Operand bytes after the opcode
+    pub fn operand_len(self) -> u8 {
+        match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => 0,
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect => 2,
+            _ => 1,
+        }
+    }
+}
+
 /// Opcode information
 #[allow(dead_code)]
 pub struct OpcodeInfo {
@@ -0,0 +1,245 @@
+//! Harness for running raw 6502 programs outside of a NES
+//!
+//! [`TestBoard`] wires a [`Cpu6502`] to 64KB of flat RAM with no memory
+//! mapped IO, which is all you need to unit-test instruction sequences,
+//! script small routines, or use the CPU for teaching.
+//!
+//! ```
+//! use emu_nes::cpu::testing::{TestBoard, Until};
+//! use emu_nes::cpu::StatusFlags;
+//!
+//! let mut board = TestBoard::new();
+//! board
+//!     .load(0x0200, &[
+//!         0xA9, 0x0A, // LDA #$0A
+//!         0x69, 0x05, // ADC #$05
+//!         0x85, 0x20, // STA $20
+//!         0x00,       // BRK
+//!     ])
+//!     .set_reset_vector(0x0200);
+//!
+//! board.run_until(Until::Brk).unwrap();
+//!
+//! board
+//!     .assert_a(15)
+//!     .assert_mem(0x20, 15)
+//!     .assert_flags_clear(StatusFlags::ZERO | StatusFlags::CARRY);
+//! ```
+
+use super::{Cpu6502, CpuMemory, CpuVariant, StatusFlags};
+use emu_core::{Cpu as CpuTrait, EmulatorError, Result};
+
+/// Upper bound on instructions for [`Until::Pc`] and [`Until::Brk`], so a
+/// program that never reaches its stop condition fails instead of hanging
+pub const DEFAULT_INSTRUCTION_LIMIT: usize = 1_000_000;
+
+/// 64KB of flat RAM with no mirroring or IO
+pub struct FlatMemory {
+    pub ram: Vec<u8>,
+    /// NMI waiting to be taken, for exercising BRK hijacking
+    pub nmi_pending: bool,
+}
+
+impl FlatMemory {
+    pub fn new() -> Self {
+        Self { ram: vec![0; 0x10000], nmi_pending: false }
+    }
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CpuMemory for FlatMemory {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.ram[addr as usize] = value;
+    }
+
+    fn poll_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+}
+
+/// Stop condition for [`TestBoard::run_until`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Until {
+    /// Stop when PC reaches this address (before executing it)
+    Pc(u16),
+    /// Stop after executing this many instructions
+    Instructions(usize),
+    /// Stop when the next opcode is BRK (without executing it)
+    Brk,
+}
+
+/// A 6502 on a bare 64KB RAM board
+pub struct TestBoard {
+    cpu: Cpu6502<FlatMemory>,
+}
+
+impl TestBoard {
+    /// Create a board with zeroed RAM and PC at $0000
+    pub fn new() -> Self {
+        Self {
+            cpu: Cpu6502::new(FlatMemory::new()),
+        }
+    }
+
+    /// Create a board whose CPU behaves as `variant`
+    pub fn with_variant(variant: CpuVariant) -> Self {
+        Self {
+            cpu: Cpu6502::with_variant(FlatMemory::new(), variant),
+        }
+    }
+
+    /// Copy `bytes` into RAM starting at `addr` (wrapping at $FFFF)
+    pub fn load(&mut self, addr: u16, bytes: &[u8]) -> &mut Self {
+        for (offset, &byte) in bytes.iter().enumerate() {
+            self.cpu.memory().write(addr.wrapping_add(offset as u16), byte);
+        }
+        self
+    }
+
+    /// Point the reset vector at `addr` and reset the CPU
+    pub fn set_reset_vector(&mut self, addr: u16) -> &mut Self {
+        self.load(0xFFFC, &addr.to_le_bytes());
+        self.cpu.reset();
+        self
+    }
+
+    /// Execute instructions until `until` is met
+    ///
+    /// Returns the number of instructions executed.
+    pub fn run_until(&mut self, until: Until) -> Result<usize> {
+        let limit = match until {
+            Until::Instructions(count) => count,
+            Until::Pc(_) | Until::Brk => DEFAULT_INSTRUCTION_LIMIT,
+        };
+
+        for executed in 0..limit {
+            match until {
+                Until::Pc(pc) if self.cpu.pc == pc => return Ok(executed),
+                Until::Brk if self.read(self.cpu.pc) == 0x00 => return Ok(executed),
+                _ => {}
+            }
+            self.cpu.step()?;
+        }
+
+        match until {
+            Until::Instructions(count) => Ok(count),
+            Until::Pc(pc) if self.cpu.pc == pc => Ok(limit),
+            Until::Brk if self.read(self.cpu.pc) == 0x00 => Ok(limit),
+            _ => Err(EmulatorError::Other(format!(
+                "{:?} not reached after {} instructions (PC=${:04X})",
+                until, limit, self.cpu.pc
+            ))),
+        }
+    }
+
+    /// Get the CPU
+    pub fn cpu(&self) -> &Cpu6502<FlatMemory> {
+        &self.cpu
+    }
+
+    /// Get the CPU mutably (to poke registers or flags)
+    pub fn cpu_mut(&mut self) -> &mut Cpu6502<FlatMemory> {
+        &mut self.cpu
+    }
+
+    /// Read a byte of RAM
+    pub fn read(&mut self, addr: u16) -> u8 {
+        self.cpu.memory().read(addr)
+    }
+
+    /// Write a byte of RAM
+    pub fn write(&mut self, addr: u16, value: u8) {
+        self.cpu.memory().write(addr, value);
+    }
+
+    /// Assert that RAM at `addr` holds `value`
+    #[track_caller]
+    pub fn assert_mem(&mut self, addr: u16, value: u8) -> &mut Self {
+        let actual = self.read(addr);
+        assert_eq!(actual, value, "memory at ${:04X}: expected ${:02X}, got ${:02X}", addr, value, actual);
+        self
+    }
+
+    /// Assert the accumulator
+    #[track_caller]
+    pub fn assert_a(&mut self, value: u8) -> &mut Self {
+        assert_eq!(self.cpu.a, value, "A: expected ${:02X}, got ${:02X}", value, self.cpu.a);
+        self
+    }
+
+    /// Assert the X register
+    #[track_caller]
+    pub fn assert_x(&mut self, value: u8) -> &mut Self {
+        assert_eq!(self.cpu.x, value, "X: expected ${:02X}, got ${:02X}", value, self.cpu.x);
+        self
+    }
+
+    /// Assert the Y register
+    #[track_caller]
+    pub fn assert_y(&mut self, value: u8) -> &mut Self {
+        assert_eq!(self.cpu.y, value, "Y: expected ${:02X}, got ${:02X}", value, self.cpu.y);
+        self
+    }
+
+    /// Assert that every flag in `flags` is set
+    #[track_caller]
+    pub fn assert_flags(&mut self, flags: StatusFlags) -> &mut Self {
+        assert!(self.cpu.status.contains(flags), "flags: expected {:?} set, status is {:?}", flags, self.cpu.status);
+        self
+    }
+
+    /// Assert that every flag in `flags` is clear
+    #[track_caller]
+    pub fn assert_flags_clear(&mut self, flags: StatusFlags) -> &mut Self {
+        assert!(!self.cpu.status.intersects(flags), "flags: expected {:?} clear, status is {:?}", flags, self.cpu.status);
+        self
+    }
+}
+
+impl Default for TestBoard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_until_pc() {
+        let mut board = TestBoard::new();
+        board
+            .load(0x8000, &[0xE8, 0xE8, 0xE8, 0x4C, 0x03, 0x80]) // INX x3; JMP $8003
+            .set_reset_vector(0x8000);
+
+        assert_eq!(board.run_until(Until::Pc(0x8003)).unwrap(), 3);
+        board.assert_x(3);
+    }
+
+    #[test]
+    fn test_run_until_instructions() {
+        let mut board = TestBoard::new();
+        board.load(0x0000, &[0xC8; 16]); // INY
+
+        assert_eq!(board.run_until(Until::Instructions(5)).unwrap(), 5);
+        board.assert_y(5);
+    }
+
+    #[test]
+    fn test_run_until_unreachable_pc_errors() {
+        let mut board = TestBoard::new();
+        board.load(0x0000, &[0x4C, 0x00, 0x00]); // JMP $0000
+
+        assert!(board.run_until(Until::Pc(0x1234)).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+//! 6502 disassembly, for the debugger
+//!
+//! Output uses the usual assembler syntax (`LDA ($10),Y`, `ASL A`), with
+//! branch targets shown as absolute addresses. Opcodes outside the
+//! official set come out as a single `.DB` byte.
+
+use crate::cpu::opcodes::{get_opcode_info, AddressingMode};
+
+/// One disassembled instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisasmLine {
+    /// Address of the opcode
+    pub addr: u16,
+    /// The opcode and its operand bytes
+    pub bytes: Vec<u8>,
+    /// Assembly text, e.g. `STA $2007`
+    pub text: String,
+}
+
+impl DisasmLine {
+    /// Address of the instruction after this one
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.bytes.len() as u16)
+    }
+}
+
+/// Disassemble the instruction at `addr`, fetching bytes with `read`
+pub fn disassemble_one(addr: u16, mut read: impl FnMut(u16) -> u8) -> DisasmLine {
+    let opcode = read(addr);
+    let Some(info) = get_opcode_info(opcode) else {
+        return DisasmLine {
+            addr,
+            bytes: vec![opcode],
+            text: format!(".DB ${:02X}", opcode),
+        };
+    };
+
+    let mut bytes = vec![opcode];
+    bytes.extend((1..=info.mode.operand_len() as u16).map(|i| read(addr.wrapping_add(i))));
+    let byte = bytes.get(1).copied().unwrap_or(0);
+    let word = u16::from_le_bytes([byte, bytes.get(2).copied().unwrap_or(0)]);
+
+    let operand = match info.mode {
+        AddressingMode::Implied => String::new(),
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::Immediate => format!("#${:02X}", byte),
+        AddressingMode::ZeroPage => format!("${:02X}", byte),
+        AddressingMode::ZeroPageX => format!("${:02X},X", byte),
+        AddressingMode::ZeroPageY => format!("${:02X},Y", byte),
+        AddressingMode::Relative => {
+            let target = addr.wrapping_add(2).wrapping_add(byte as i8 as u16);
+            format!("${:04X}", target)
+        }
+        AddressingMode::Absolute => format!("${:04X}", word),
+        AddressingMode::AbsoluteX => format!("${:04X},X", word),
+        AddressingMode::AbsoluteY => format!("${:04X},Y", word),
+        AddressingMode::Indirect => format!("(${:04X})", word),
+        AddressingMode::IndexedIndirect => format!("(${:02X},X)", byte),
+        AddressingMode::IndirectIndexed => format!("(${:02X}),Y", byte),
+    };
+    let text = if operand.is_empty() {
+        info.mnemonic.to_string()
+    } else {
+        format!("{} {}", info.mnemonic, operand)
+    };
+    DisasmLine { addr, bytes, text }
+}
+
+/// Disassemble `count` instructions in a row, starting at `addr`
+pub fn disassemble(addr: u16, count: usize, mut read: impl FnMut(u16) -> u8) -> Vec<DisasmLine> {
+    let mut lines = Vec::with_capacity(count);
+    let mut addr = addr;
+    for _ in 0..count {
+        let line = disassemble_one(addr, &mut read);
+        addr = line.next_addr();
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_of(program: &[u8], addr: u16) -> Vec<String> {
+        let read = |a: u16| program.get(a.wrapping_sub(addr) as usize).copied().unwrap_or(0xEA);
+        let mut lines = Vec::new();
+        let mut pc = addr;
+        while (pc.wrapping_sub(addr) as usize) < program.len() {
+            let line = disassemble_one(pc, read);
+            pc = line.next_addr();
+            lines.push(line.text);
+        }
+        lines
+    }
+
+    #[test]
+    fn test_every_addressing_mode() {
+        #[rustfmt::skip]
+        let program = [
+            0xEA,             // NOP
+            0x0A,             // ASL A
+            0xA9, 0x42,       // LDA #$42
+            0xA5, 0x10,       // LDA $10
+            0xB5, 0x10,       // LDA $10,X
+            0xB6, 0x10,       // LDX $10,Y
+            0xAD, 0x02, 0x20, // LDA $2002
+            0xBD, 0x00, 0x03, // LDA $0300,X
+            0xB9, 0x00, 0x03, // LDA $0300,Y
+            0x6C, 0xFC, 0xFF, // JMP ($FFFC)
+            0xA1, 0x20,       // LDA ($20,X)
+            0xB1, 0x20,       // LDA ($20),Y
+            0xD0, 0xFE,       // BNE *
+            0x10, 0x02,       // BPL +2
+            0x02,             // unofficial (KIL)
+        ];
+        assert_eq!(text_of(&program, 0x8000), [
+            "NOP", "ASL A", "LDA #$42", "LDA $10", "LDA $10,X", "LDX $10,Y", "LDA $2002",
+            "LDA $0300,X", "LDA $0300,Y", "JMP ($FFFC)", "LDA ($20,X)", "LDA ($20),Y",
+            "BNE $801A", "BPL $8020", ".DB $02",
+        ]);
+    }
+
+    #[test]
+    fn test_disassemble_runs_on_and_wraps() {
+        let lines = disassemble(0xFFFE, 2, |addr| match addr {
+            0xFFFE => 0x4C, // JMP $8000, its operand wrapping to $0000
+            0xFFFF => 0x00,
+            0x0000 => 0x80,
+            _ => 0xEA,
+        });
+        assert_eq!(lines[0].text, "JMP $8000");
+        assert_eq!(lines[0].bytes, [0x4C, 0x00, 0x80]);
+        assert_eq!(lines[1].addr, 0x0001);
+        assert_eq!(lines[1].text, "NOP");
+    }
+}
@@ -0,0 +1,272 @@
+//! Running a system on a thread of its own
+//!
+//! [`NesSystem`] is driven through `&mut self`, so anything that drives it
+//! from more than one place (a GUI, a bot harness, an async task) ends up
+//! with a thread to run it and channels to talk to that thread.
+//! [`EmulatorService`] is that thread: it owns the system, and
+//! [`EmulatorHandle`]s send it commands.
+//!
+//! Commands run one at a time in the order they were sent, each to the
+//! end, so they always land between frames; a long
+//! [`EmulatorHandle::run_frames`] holds up whatever is queued behind it.
+//! Every command answers with a [`Reply`], which can be waited for from a
+//! plain thread ([`Reply::wait`]) or awaited under any async runtime, being
+//! a [`Future`] the service thread wakes.
+//!
+//! The service stops on [`EmulatorHandle::shutdown`] or once every handle
+//! is dropped, and joining its thread gives the system back.
+
+use std::future::Future;
+use std::ops::{Bound, RangeBounds};
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+use emu_core::{Button, Emulator, EmulatorError, Result};
+use crate::NesSystem;
+
+/// What the service thread is asked to do
+enum Command<S> {
+    Call(Box<dyn FnOnce(&mut S) + Send>),
+    Shutdown(Responder<()>),
+}
+
+/// The thread that owns a system and runs the commands sent to it
+pub struct EmulatorService;
+
+impl EmulatorService {
+    /// Move `system` onto a new thread
+    ///
+    /// Usually a [`NesSystem`], which has commands of its own on the handle;
+    /// anything else can be driven with [`EmulatorHandle::call`].
+    pub fn spawn<S: Send + 'static>(system: S) -> (EmulatorHandle<S>, JoinHandle<S>) {
+        let (commands, receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("emulator".into())
+            .spawn(move || Self::run(system, receiver))
+            .expect("spawning the emulator thread");
+        (EmulatorHandle { commands }, thread)
+    }
+
+    fn run<S>(mut system: S, commands: mpsc::Receiver<Command<S>>) -> S {
+        // Ends when the last handle is dropped, if not told to before
+        while let Ok(command) = commands.recv() {
+            match command {
+                Command::Call(call) => call(&mut system),
+                Command::Shutdown(done) => {
+                    done.send(Ok(()));
+                    break;
+                }
+            }
+        }
+        // Whatever is still queued answers ServiceStopped as it's dropped
+        system
+    }
+}
+
+/// Sends commands to an [`EmulatorService`]
+///
+/// Cheap to clone, and each clone can be moved to another thread.
+pub struct EmulatorHandle<S = NesSystem> {
+    commands: mpsc::Sender<Command<S>>,
+}
+
+impl<S> Clone for EmulatorHandle<S> {
+    fn clone(&self) -> Self {
+        Self {
+            commands: self.commands.clone(),
+        }
+    }
+}
+
+impl<S: 'static> EmulatorHandle<S> {
+    /// Run `f` on the system between frames, answering with what it returns
+    pub fn call<R, F>(&self, f: F) -> Reply<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut S) -> R + Send + 'static,
+    {
+        self.try_call(move |system| Ok(f(system)))
+    }
+
+    /// Like [`EmulatorHandle::call`], with `f`'s error as the reply's
+    pub fn try_call<R, F>(&self, f: F) -> Reply<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut S) -> Result<R> + Send + 'static,
+    {
+        let (responder, reply) = Reply::new();
+        // If the service has stopped, dropping the command answers for it
+        self.commands
+            .send(Command::Call(Box::new(move |system| responder.send(f(system)))))
+            .ok();
+        reply
+    }
+
+    /// Stop the service once the commands already sent have run
+    ///
+    /// Anything sent afterwards answers [`EmulatorError::ServiceStopped`].
+    pub fn shutdown(&self) -> Reply<()> {
+        let (responder, reply) = Reply::new();
+        self.commands.send(Command::Shutdown(responder)).ok();
+        reply
+    }
+}
+
+/// The picture, as [`EmulatorHandle::request_frame`] copies it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameData {
+    /// Frames run so far
+    pub frame: u64,
+    pub width: usize,
+    pub height: usize,
+    /// `width` x `height` RGBA pixels
+    pub rgba: Vec<u8>,
+}
+
+impl EmulatorHandle<NesSystem> {
+    /// Run `frames` frames, answering with the frame count reached
+    ///
+    /// Stops at the first error, e.g. a CPU jam, and answers with that.
+    pub fn run_frames(&self, frames: u32) -> Reply<u64> {
+        self.try_call(move |system| {
+            for _ in 0..frames {
+                system.run_frame()?;
+            }
+            Ok(system.frame())
+        })
+    }
+
+    /// Hold exactly `buttons` on controller `player` (1 or 2)
+    pub fn set_buttons(&self, player: u8, buttons: Button) -> Reply<()> {
+        self.try_call(move |system| {
+            let controller = match player {
+                1 => system.controller1(),
+                2 => system.controller2(),
+                _ => return Err(EmulatorError::Other(format!("No controller {}", player))),
+            };
+            controller.state().buttons = buttons;
+            Ok(())
+        })
+    }
+
+    /// Copy the last frame drawn
+    pub fn request_frame(&self) -> Reply<FrameData> {
+        self.call(|system| {
+            let (width, height) = system.screen_size();
+            FrameData {
+                frame: system.frame(),
+                width,
+                height,
+                rgba: system.framebuffer_rgba(),
+            }
+        })
+    }
+
+    /// Take a savestate, as [`NesSystem::save_state`]
+    pub fn save_state(&self) -> Reply<Vec<u8>> {
+        self.call(NesSystem::save_state)
+    }
+
+    /// Restore a savestate, as [`NesSystem::load_state`]
+    pub fn load_state(&self, state: Vec<u8>) -> Reply<()> {
+        self.try_call(move |system| system.load_state(&state))
+    }
+
+    /// Read CPU memory without side effects, as the debugger does
+    pub fn read_memory(&self, range: impl RangeBounds<u16>) -> Reply<Vec<u8>> {
+        let start = match range.start_bound() {
+            Bound::Included(&addr) => addr as u32,
+            Bound::Excluded(&addr) => addr as u32 + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&addr) => addr as u32 + 1,
+            Bound::Excluded(&addr) => addr as u32,
+            Bound::Unbounded => 0x10000,
+        };
+        self.call(move |system| (start..end).map(|addr| system.peek_memory(addr as u16)).collect())
+    }
+}
+
+/// Where an answer waits for whoever asked
+struct Slot<T> {
+    value: Option<Result<T>>,
+    waker: Option<Waker>,
+}
+
+type Shared<T> = Arc<(Mutex<Slot<T>>, Condvar)>;
+
+/// The answer to a command, once the service gets to it
+///
+/// Dropping it doesn't cancel the command.
+pub struct Reply<T> {
+    shared: Shared<T>,
+}
+
+impl<T> Reply<T> {
+    fn new() -> (Responder<T>, Self) {
+        let shared = Arc::new((Mutex::new(Slot { value: None, waker: None }), Condvar::new()));
+        (Responder { shared: Some(shared.clone()) }, Self { shared })
+    }
+
+    /// Block until the service answers
+    pub fn wait(self) -> Result<T> {
+        let (slot, answered) = &*self.shared;
+        let mut slot = slot.lock().unwrap();
+        loop {
+            if let Some(value) = slot.value.take() {
+                return value;
+            }
+            slot = answered.wait(slot).unwrap();
+        }
+    }
+}
+
+impl<T> Future for Reply<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<T>> {
+        let mut slot = self.shared.0.lock().unwrap();
+        match slot.value.take() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The service's end of a [`Reply`]; answers `ServiceStopped` if dropped unused
+struct Responder<T> {
+    shared: Option<Shared<T>>,
+}
+
+impl<T> Responder<T> {
+    fn send(mut self, value: Result<T>) {
+        self.fill(value);
+    }
+
+    fn fill(&mut self, value: Result<T>) {
+        let Some(shared) = self.shared.take() else {
+            return;
+        };
+        let (slot, answered) = &*shared;
+        let waker = {
+            let mut slot = slot.lock().unwrap();
+            slot.value = Some(value);
+            slot.waker.take()
+        };
+        answered.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Responder<T> {
+    fn drop(&mut self) {
+        self.fill(Err(EmulatorError::ServiceStopped));
+    }
+}
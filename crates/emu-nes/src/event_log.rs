@@ -0,0 +1,157 @@
+//! Per-frame timeline of notable events
+//!
+//! Timing bugs are about *when* a game did something relative to the
+//! picture: a scroll write a few dots too late, an NMI handler still
+//! running when rendering starts. With the log on
+//! ([`NesSystem::set_event_log_enabled`](crate::NesSystem::set_event_log_enabled)),
+//! each interrupt taken and each write to a register that shapes the
+//! frame is stamped with the PPU position it happened at. Drain them with
+//! [`NesSystem::take_frame_events`](crate::NesSystem::take_frame_events)
+//! after every frame.
+
+use std::fmt;
+use crate::IrqSource;
+
+/// Most events kept between two calls to `take_frame_events`
+///
+/// A frame has a few dozen in a typical game; a runaway loop writing
+/// PPUADDR could log tens of thousands, so past this they're counted
+/// instead of kept.
+pub const MAX_EVENTS_PER_FRAME: usize = 4096;
+
+/// What happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmuEventKind {
+    /// The CPU entered the NMI handler
+    Nmi,
+    /// The CPU entered the IRQ handler, with the sources holding the line
+    Irq(IrqSource),
+    /// A write to PPUCTRL, PPUMASK, PPUSCROLL or PPUADDR (`addr` is
+    /// $2000, $2001, $2005 or $2006 whichever mirror was written)
+    PpuWrite { addr: u16, value: u8 },
+    /// A $4014 write copied page `page` into OAM
+    OamDma { page: u8 },
+    /// A write to $4015, enabling and disabling APU channels
+    ApuStatus(u8),
+    /// The mapper switched the PRG bank it reports
+    PrgBank(usize),
+    /// The mapper switched the CHR bank it reports
+    ChrBank(usize),
+    /// This many events past [`MAX_EVENTS_PER_FRAME`] weren't kept; always last
+    Dropped(usize),
+}
+
+/// One entry in the timeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmuEvent {
+    /// Frames completed by [`NesSystem::run_frame`](crate::NesSystem::run_frame)
+    pub frame: u64,
+    /// PPU scanline (0-261, 261 being the pre-render line)
+    pub scanline: u16,
+    /// PPU dot within the scanline (0-340)
+    pub cycle: u16,
+    pub kind: EmuEventKind,
+}
+
+impl fmt::Display for EmuEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmuEventKind::Nmi => write!(f, "NMI"),
+            EmuEventKind::Irq(sources) => write!(f, "IRQ ({:?})", sources),
+            EmuEventKind::PpuWrite { addr, value } => write!(f, "${:04X} = ${:02X}", addr, value),
+            EmuEventKind::OamDma { page } => write!(f, "OAM DMA from ${:02X}00", page),
+            EmuEventKind::ApuStatus(value) => write!(f, "$4015 = ${:02X}", value),
+            EmuEventKind::PrgBank(bank) => write!(f, "PRG bank {}", bank),
+            EmuEventKind::ChrBank(bank) => write!(f, "CHR bank {}", bank),
+            EmuEventKind::Dropped(count) => write!(f, "{} more events dropped", count),
+        }
+    }
+}
+
+impl fmt::Display for EmuEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:3},{:3}  {}", self.scanline, self.cycle, self.kind)
+    }
+}
+
+/// Events since the last take, with the count of those that didn't fit
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EventLog {
+    events: Vec<EmuEvent>,
+    dropped: usize,
+}
+
+impl EventLog {
+    pub fn push(&mut self, event: EmuEvent) {
+        if self.events.len() < MAX_EVENTS_PER_FRAME {
+            self.events.push(event);
+        } else {
+            self.dropped += 1;
+        }
+    }
+
+    /// Drain the events, ending with a [`EmuEventKind::Dropped`] if any were
+    pub fn take(&mut self) -> Vec<EmuEvent> {
+        let mut events = std::mem::take(&mut self.events);
+        let dropped = std::mem::take(&mut self.dropped);
+        if let Some(&last) = events.last().filter(|_| dropped > 0) {
+            events.push(EmuEvent {
+                kind: EmuEventKind::Dropped(dropped),
+                ..last
+            });
+        }
+        events
+    }
+
+    /// How much has been logged, to go back to with [`EventLog::rewind`]
+    pub fn mark(&self) -> (usize, usize) {
+        (self.events.len(), self.dropped)
+    }
+
+    /// Forget what was logged since `mark`
+    pub fn rewind(&mut self, (len, dropped): (usize, usize)) {
+        self.events.truncate(len);
+        self.dropped = self.dropped.min(dropped);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(scanline: u16) -> EmuEvent {
+        EmuEvent {
+            frame: 0,
+            scanline,
+            cycle: 1,
+            kind: EmuEventKind::Nmi,
+        }
+    }
+
+    #[test]
+    fn test_overflow_is_counted() {
+        let mut log = EventLog::default();
+        for i in 0..MAX_EVENTS_PER_FRAME + 10 {
+            log.push(event(i as u16));
+        }
+        let events = log.take();
+        assert_eq!(events.len(), MAX_EVENTS_PER_FRAME + 1);
+        assert_eq!(events.last().unwrap().kind, EmuEventKind::Dropped(10));
+        assert_eq!(events.last().unwrap().to_string(), "4095,  1  10 more events dropped");
+
+        // The count starts over
+        log.push(event(0));
+        assert_eq!(log.take(), [event(0)]);
+        assert!(log.take().is_empty());
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut log = EventLog::default();
+        log.push(event(1));
+        let mark = log.mark();
+        log.push(event(2));
+        log.rewind(mark);
+        assert_eq!(log.take(), [event(1)]);
+    }
+}
@@ -0,0 +1,262 @@
+//! Sound chips on the cartridge
+//!
+//! On a Famicom the cartridge connector carries the APU's output out and
+//! back in, so a board can mix in a sound chip of its own; VRC6, Namco 163
+//! and the FDS all do. A board with one gives its
+//! [`Cartridge`](crate::Cartridge) an [`ExpansionAudio`]. The console then
+//! clocks it every CPU cycle alongside the APU, hands it every write to
+//! $4020-$FFFF, and adds its output to the APU's at the chip's own level.
+//! Its channels are muted as [`Channel::Expansion`](crate::Channel::Expansion).
+//!
+//! The boards with one are listed in [`crate::mapper::expansion_audio`].
+
+use crate::savestate::Snapshot;
+use std::fmt;
+
+/// A sound chip on the cartridge
+///
+/// Chips are `Clone`; [`ExpansionAudioClone`] lets a boxed one be cloned
+/// too. Their [`Snapshot`] goes in the cartridge's part of a savestate.
+pub(crate) trait ExpansionAudio: ExpansionAudioClone + Snapshot + fmt::Debug + Send {
+    /// Run for one CPU cycle
+    fn clock(&mut self);
+
+    /// Current output of every channel together, from 0.0 (silent) to 1.0
+    /// (full scale)
+    fn output(&self) -> f32;
+
+    /// Write to $4020-$FFFF
+    ///
+    /// Every write the cartridge sees comes here, bank registers included;
+    /// a chip ignores the addresses it doesn't decode.
+    fn write_register(&mut self, addr: u16, value: u8);
+
+    /// How loud full scale is in the mix, in console pulse channels at full
+    /// volume
+    ///
+    /// Each chip reaches the connector through its own resistors, so this
+    /// is a constant for the chip.
+    fn mix_level(&self) -> f32;
+
+    /// Go back to the power-on state
+    fn reset(&mut self);
+
+    /// [`Self::output`] with channel n left out where bit n of `muted` is set
+    ///
+    /// The default suits a chip with one channel.
+    fn output_muted(&self, muted: u8) -> f32 {
+        if muted & 1 != 0 {
+            0.0
+        } else {
+            self.output()
+        }
+    }
+}
+
+/// Clones a chip behind a `Box<dyn ExpansionAudio>`, for every `Clone` chip
+pub(crate) trait ExpansionAudioClone {
+    fn clone_box(&self) -> Box<dyn ExpansionAudio>;
+}
+
+impl<T: ExpansionAudio + Clone + 'static> ExpansionAudioClone for T {
+    fn clone_box(&self) -> Box<dyn ExpansionAudio> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn ExpansionAudio> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// A square wave with two registers, to test the plumbing with
+///
+/// The chip only sees A0-A11, so its registers repeat every 4KB:
+/// $x000 is the volume (0-15) and $x001 how many CPU cycles each half of
+/// the wave lasts (0 holds it high).
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TestTone {
+    volume: u8,
+    half_period: u8,
+    timer: u8,
+    low: bool,
+}
+
+#[cfg(test)]
+impl TestTone {
+    /// The level [`TestTone::mix_level`] gives
+    pub const MIX_LEVEL: f32 = 0.5;
+}
+
+#[cfg(test)]
+impl ExpansionAudio for TestTone {
+    fn clock(&mut self) {
+        if self.half_period == 0 {
+            return;
+        }
+        self.timer += 1;
+        if self.timer == self.half_period {
+            self.timer = 0;
+            self.low = !self.low;
+        }
+    }
+
+    fn output(&self) -> f32 {
+        if self.low {
+            0.0
+        } else {
+            self.volume as f32 / 15.0
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr & 0x0FFF {
+            0x000 => self.volume = value & 0x0F,
+            0x001 => {
+                self.half_period = value;
+                self.timer = 0;
+                self.low = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn mix_level(&self) -> f32 {
+        Self::MIX_LEVEL
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+impl Snapshot for TestTone {
+    fn save(&self, w: &mut crate::savestate::StateWriter) {
+        w.u8(self.volume);
+        w.u8(self.half_period);
+        w.u8(self.timer);
+        w.bool(self.low);
+    }
+
+    fn load(&mut self, r: &mut crate::savestate::StateReader) -> emu_core::Result<()> {
+        self.volume = r.u8()?;
+        self.half_period = r.u8()?;
+        self.timer = r.u8()?;
+        self.low = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::mix;
+    use crate::cpu::CpuMemory;
+    use crate::savestate::{StateReader, StateWriter};
+    use crate::{Cartridge, Channel, NesMemory};
+
+    /// A console with the test tone on the cartridge, volume and period set
+    /// by the CPU
+    fn console(volume: u8, half_period: u8) -> NesMemory {
+        let mut cartridge = Cartridge::with_prg_rom(vec![0xEA; 0x8000]);
+        cartridge.set_expansion_audio(Box::new(TestTone::default()));
+        let mut memory = NesMemory::new();
+        memory.load_cartridge(cartridge);
+        memory.write(0x5000, volume);
+        memory.write(0x5001, half_period);
+        memory
+    }
+
+    /// How much the cartridge adds to the mixed output
+    fn expansion(memory: &NesMemory) -> f32 {
+        memory.apu().output() - mix(0, 0, 0, 0, 0)
+    }
+
+    /// A console pulse channel at full volume, in the mixed output
+    fn full_pulse() -> f32 {
+        mix(15, 0, 0, 0, 0) - mix(0, 0, 0, 0, 0)
+    }
+
+    #[test]
+    fn test_mixed_at_the_chips_level() {
+        let mut memory = console(15, 0);
+        memory.clock_cycle();
+        let expected = TestTone::MIX_LEVEL * full_pulse();
+        assert!((expansion(&memory) - expected).abs() < 1e-6, "{} vs {}", expansion(&memory), expected);
+
+        // Reaches the stereo output too, centred
+        let (left, right) = memory.apu().output_stereo();
+        assert_eq!(left, right);
+        assert!((left - memory.apu().output()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_clocked_every_cpu_cycle() {
+        // High for 3 cycles, low for 3
+        let mut memory = console(15, 3);
+        let heard: Vec<bool> = (0..12)
+            .map(|_| {
+                memory.clock_cycle();
+                expansion(&memory) > 0.0
+            })
+            .collect();
+        let high = [true, true, false, false, false, true];
+        assert_eq!(heard, [high, high].concat());
+    }
+
+    #[test]
+    fn test_cartridge_writes_reach_the_chip() {
+        // Volume through PRG-RAM space, period through a bank register
+        // address; both decode to the chip's registers
+        let mut memory = console(0, 0);
+        memory.write(0x6000, 15);
+        memory.write(0x9001, 0);
+        memory.clock_cycle();
+        assert!(expansion(&memory) > 0.0);
+
+        // APU registers aren't the cartridge's
+        memory.write(0x4000, 0);
+        memory.clock_cycle();
+        assert!(expansion(&memory) > 0.0);
+        memory.write(0xF000, 0);
+        memory.clock_cycle();
+        assert_eq!(expansion(&memory), 0.0);
+    }
+
+    #[test]
+    fn test_saved_with_the_cartridge_and_reset_by_power_up() {
+        let board = || {
+            let mut cartridge = Cartridge::with_prg_rom(vec![0xEA; 0x8000]);
+            cartridge.set_expansion_audio(Box::new(TestTone::default()));
+            cartridge
+        };
+        let mut cartridge = board();
+        cartridge.write_expansion(0x5000, 15);
+        let mut w = StateWriter::new();
+        cartridge.save(&mut w);
+
+        let mut restored = board();
+        restored.load(&mut StateReader::new(&w.finish())).unwrap();
+        assert!(restored.has_expansion_audio());
+        assert_eq!(restored.expansion_audio_mut().unwrap().output(), 1.0);
+        restored.power_up();
+        assert_eq!(restored.expansion_audio_mut().unwrap().output(), 0.0);
+    }
+    
+    #[test]
+    fn test_muted_as_an_expansion_channel() {
+        let mut memory = console(15, 0);
+        memory.apu_mut().set_muted(Channel::Expansion(0), true);
+        memory.clock_cycle();
+        assert!(memory.apu().is_muted(Channel::Expansion(0)));
+        assert!(!memory.apu().is_muted(Channel::Pulse1));
+        assert_eq!(expansion(&memory), 0.0);
+
+        memory.apu_mut().set_muted(Channel::Expansion(0), false);
+        memory.clock_cycle();
+        assert!(expansion(&memory) > 0.0);
+    }
+}
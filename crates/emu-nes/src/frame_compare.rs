@@ -0,0 +1,138 @@
+//! Comparing frames and keeping them as files
+//!
+//! Frames are 256x240 palette indices, as returned by
+//! [`NesSystem::framebuffer`](crate::NesSystem::framebuffer).
+//!
+//! Reference frames are stored as binary PGM (P5) files with the palette
+//! index as the grey level, so they stay 60KB and open in any image viewer.
+//! A diff image is a PPM with the expected frame, the actual frame and the
+//! differences side by side. The rendering tests (through
+//! [`test_util`](crate::test_util)) and `nes-run compare` both use them.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::palette_to_rgb;
+
+/// Frame width in pixels
+pub const WIDTH: usize = 256;
+
+/// Frame height in pixels
+pub const HEIGHT: usize = 240;
+
+/// Colour of a differing pixel in the diff panel
+const DIFF_RED: [u8; 3] = [255, 0, 0];
+
+/// Header of a reference frame file
+const PGM_HEADER: &[u8] = b"P5\n256 240\n63\n";
+
+/// Number of pixels that differ between two frames
+pub fn count_differences(expected: &[u8], actual: &[u8]) -> usize {
+    assert_eq!(expected.len(), actual.len(), "frames have different sizes");
+    expected.iter().zip(actual).filter(|(e, a)| e != a).count()
+}
+
+/// RGB image with the expected frame, the actual frame and a diff panel
+///
+/// The diff panel is the actual frame dimmed to a quarter, with every
+/// differing pixel in bright red. The image is three frames wide.
+pub fn diff_image(expected: &[u8], actual: &[u8]) -> Vec<u8> {
+    assert_eq!(expected.len(), WIDTH * HEIGHT, "expected frame is not 256x240");
+    assert_eq!(actual.len(), WIDTH * HEIGHT, "actual frame is not 256x240");
+
+    let rgb = |index: u8| {
+        let (r, g, b) = palette_to_rgb(index);
+        [r, g, b]
+    };
+    let mut image = Vec::with_capacity(WIDTH * 3 * HEIGHT * 3);
+    for y in 0..HEIGHT {
+        let row = y * WIDTH..(y + 1) * WIDTH;
+        for &pixel in &expected[row.clone()] {
+            image.extend(rgb(pixel));
+        }
+        for &pixel in &actual[row.clone()] {
+            image.extend(rgb(pixel));
+        }
+        for (&e, &a) in expected[row.clone()].iter().zip(&actual[row]) {
+            if e == a {
+                image.extend(rgb(a).map(|channel| channel / 4));
+            } else {
+                image.extend(DIFF_RED);
+            }
+        }
+    }
+    image
+}
+
+/// Write [`diff_image`] as a binary (P6) PPM at `path`
+pub fn save_diff_image(expected: &[u8], actual: &[u8], path: &Path) -> io::Result<()> {
+    let mut data = format!("P6\n{} {}\n255\n", WIDTH * 3, HEIGHT).into_bytes();
+    data.extend(diff_image(expected, actual));
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, data)
+}
+
+/// Read a reference frame saved by [`save_reference_frame`]
+pub fn load_reference_frame(path: &Path) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    match data.strip_prefix(PGM_HEADER) {
+        Some(pixels) if pixels.len() == WIDTH * HEIGHT => Ok(pixels.to_vec()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a 256x240 reference frame", path.display()),
+        )),
+    }
+}
+
+/// Save a frame of palette indices as a reference frame
+pub fn save_reference_frame(path: &Path, frame: &[u8]) -> io::Result<()> {
+    assert_eq!(frame.len(), WIDTH * HEIGHT, "frame is not 256x240");
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, [PGM_HEADER, frame].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_output_dir;
+
+    fn frame(fill: u8) -> Vec<u8> {
+        vec![fill; WIDTH * HEIGHT]
+    }
+
+    #[test]
+    fn test_diff_image_panels() {
+        let expected = frame(0x0F);
+        let mut actual = frame(0x0F);
+        actual[10 * WIDTH + 20] = 0x30;
+
+        let image = diff_image(&expected, &actual);
+        assert_eq!(image.len(), WIDTH * 3 * HEIGHT * 3);
+        let pixel = |x: usize, y: usize| image[(y * WIDTH * 3 + x) * 3..][..3].to_vec();
+
+        let (r, g, b) = palette_to_rgb(0x30);
+        assert_eq!(pixel(20, 10), [0, 0, 0]);
+        assert_eq!(pixel(WIDTH + 20, 10), [r, g, b]);
+        assert_eq!(pixel(2 * WIDTH + 20, 10), DIFF_RED);
+        assert_eq!(pixel(2 * WIDTH + 21, 10), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_reference_frame_round_trip() {
+        let dir = test_output_dir().join("frame_compare");
+        let path = dir.join("round_trip.pgm");
+        let mut original = frame(0x0F);
+        original[WIDTH * HEIGHT - 1] = 0x3F;
+
+        save_reference_frame(&path, &original).unwrap();
+        assert_eq!(load_reference_frame(&path).unwrap(), original);
+
+        fs::write(&path, b"P5\n1 1\n63\n\0").unwrap();
+        assert!(load_reference_frame(&path).is_err());
+    }
+}
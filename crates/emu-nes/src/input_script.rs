@@ -0,0 +1,474 @@
+//! Scripted controller input
+//!
+//! Input scripts drive controller 1 from text, for automated tests and for
+//! turning a long button sequence into one action. A script is a list of
+//! statements separated by `;` or line breaks, with `#` starting a comment
+//! that runs to the end of the line:
+//!
+//! - `wait N`: hold nothing new for N frames
+//! - `press BUTTONS [N]`: hold BUTTONS for N frames (1 if left out), then let go
+//! - `hold BUTTONS { ... }`: hold BUTTONS while the block runs
+//! - `hold BUTTONS`: hold BUTTONS until a `release`
+//! - `release BUTTONS`: let go of buttons held without a block
+//! - `repeat N { ... }`: run the block N times
+//!
+//! BUTTONS is one button name or several joined with `+` (`A+B`), any case.
+//! Only `wait` and `press` take time; the rest change what later frames hold.
+//! For example, `wait 60; press START; hold RIGHT { repeat 3 { press A 10; wait 20 } }`
+//! waits a second, taps Start and then walks right while jumping three
+//! times.
+//!
+//! [`InputSchedule::parse`] expands a script into the buttons for each
+//! frame, and [`NesSystem::attach_input_script`](crate::NesSystem::attach_input_script)
+//! plays one.
+
+use emu_core::{Button, ControllerState, EmulatorError, Result};
+
+/// Longest schedule a script may expand to: a day at 60 frames a second
+pub const MAX_FRAMES: usize = 24 * 60 * 60 * 60;
+
+/// How a script's buttons combine with the player's
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScriptMode {
+    /// The game sees only the script's buttons
+    #[default]
+    Override,
+    /// The game sees the script's buttons and the player's
+    Merge,
+}
+
+/// Controller 1 buttons for each frame, from an input script
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputSchedule {
+    frames: Vec<ControllerState>,
+}
+
+impl InputSchedule {
+    /// Parse and expand an input script
+    ///
+    /// Errors are [`EmulatorError::InputScript`], with the line and column
+    /// (both from 1) of the statement or token at fault.
+    pub fn parse(script: &str) -> Result<Self> {
+        let tokens = tokenize(script)?;
+        let mut parser = Parser { tokens: &tokens, next: 0 };
+        let statements = parser.block(None)?;
+        let mut frames = Vec::new();
+        expand(&statements, Button::empty(), &mut Button::empty(), &mut frames)?;
+        Ok(Self { frames })
+    }
+
+    /// A schedule of the given frames
+    pub fn from_frames(frames: Vec<ControllerState>) -> Self {
+        Self { frames }
+    }
+
+    /// Number of frames the schedule covers
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Check whether the schedule covers no frames
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The buttons for `frame`, counted from the start of the schedule
+    pub fn get(&self, frame: usize) -> Option<ControllerState> {
+        self.frames.get(frame).copied()
+    }
+
+    /// The buttons for every frame
+    pub fn frames(&self) -> &[ControllerState] {
+        &self.frames
+    }
+
+    /// Hold `other`'s buttons as well, frame by frame from the start
+    ///
+    /// The result is as long as the longer of the two.
+    pub fn merge(&mut self, other: &InputSchedule) {
+        if self.frames.len() < other.frames.len() {
+            self.frames.resize(other.frames.len(), ControllerState::default());
+        }
+        for (frame, other) in self.frames.iter_mut().zip(&other.frames) {
+            frame.buttons |= other.buttons;
+        }
+    }
+}
+
+/// A schedule being played from `start_frame`
+#[derive(Debug, Clone)]
+pub(crate) struct ScriptPlayback {
+    pub schedule: InputSchedule,
+    pub mode: ScriptMode,
+    pub start_frame: u64,
+}
+
+impl ScriptPlayback {
+    /// The scripted buttons during system frame `frame`, if the script covers it
+    pub fn buttons_at(&self, frame: u64) -> Option<Button> {
+        let offset = usize::try_from(frame.checked_sub(self.start_frame)?).ok()?;
+        self.schedule.get(offset).map(|state| state.buttons)
+    }
+
+    /// Check whether frame `frame` is past the end of the script
+    pub fn finished_by(&self, frame: u64) -> bool {
+        frame.saturating_sub(self.start_frame) >= self.schedule.len() as u64
+    }
+}
+
+/// Where in the script something is, for error messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Position {
+    line: usize,
+    column: usize,
+}
+
+impl Position {
+    fn error(self, message: impl Into<String>) -> EmulatorError {
+        EmulatorError::InputScript {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Word(&'a str),
+    Number(u32),
+    Open,
+    Close,
+    Separator,
+}
+
+impl Token<'_> {
+    fn describe(&self) -> String {
+        match self {
+            Token::Word(word) => format!("'{}'", word),
+            Token::Number(n) => format!("'{}'", n),
+            Token::Open => "'{'".to_string(),
+            Token::Close => "'}'".to_string(),
+            Token::Separator => "';'".to_string(),
+        }
+    }
+}
+
+/// Split a script into tokens, dropping whitespace and comments
+fn tokenize(script: &str) -> Result<Vec<(Token<'_>, Position)>> {
+    let mut tokens = Vec::new();
+    for (line_index, line) in script.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut chars = line.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            let position = Position {
+                line: line_index + 1,
+                column: line[..start].chars().count() + 1,
+            };
+            let token = match c {
+                '{' => Token::Open,
+                '}' => Token::Close,
+                ';' => Token::Separator,
+                c if c.is_whitespace() => continue,
+                c if c.is_alphanumeric() || c == '+' || c == '_' => {
+                    let mut end = start + c.len_utf8();
+                    while let Some(&(index, c)) = chars.peek() {
+                        if !(c.is_alphanumeric() || c == '+' || c == '_') {
+                            break;
+                        }
+                        end = index + c.len_utf8();
+                        chars.next();
+                    }
+                    let word = &line[start..end];
+                    if word.bytes().all(|b| b.is_ascii_digit()) {
+                        let n = word.parse().map_err(|_| position.error(format!("{} is too large", word)))?;
+                        Token::Number(n)
+                    } else {
+                        Token::Word(word)
+                    }
+                }
+                c => return Err(position.error(format!("unexpected '{}'", c))),
+            };
+            tokens.push((token, position));
+        }
+    }
+    Ok(tokens)
+}
+
+/// One statement of the script
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Wait(u32),
+    Press(Button, u32),
+    /// Hold while the block runs, or until released if there's no block
+    Hold(Button, Option<Vec<Statement>>),
+    Release(Button),
+    Repeat(u32, Vec<Statement>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Statement {
+    step: Step,
+    position: Position,
+}
+
+struct Parser<'t, 'a> {
+    tokens: &'t [(Token<'a>, Position)],
+    next: usize,
+}
+
+impl<'a> Parser<'_, 'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.next).map(|&(token, _)| token)
+    }
+
+    /// Position of the next token, or just past the last one at the end
+    fn position(&self) -> Position {
+        match self.tokens.get(self.next).or(self.tokens.last()) {
+            Some(&(_, position)) => position,
+            None => Position { line: 1, column: 1 },
+        }
+    }
+
+    /// Statements up to the end of the script, or up to and including the
+    /// `}` closing a block opened at `opened`
+    fn block(&mut self, opened: Option<Position>) -> Result<Vec<Statement>> {
+        let mut statements = Vec::new();
+        loop {
+            match self.peek() {
+                None => {
+                    return match opened {
+                        Some(position) => Err(position.error("'{' is never closed")),
+                        None => Ok(statements),
+                    };
+                }
+                Some(Token::Close) if opened.is_some() => {
+                    self.next += 1;
+                    return Ok(statements);
+                }
+                Some(Token::Separator) => self.next += 1,
+                Some(_) => statements.push(self.statement()?),
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement> {
+        let position = self.position();
+        let (token, _) = self.tokens[self.next];
+        self.next += 1;
+        let Token::Word(command) = token else {
+            return Err(position.error(format!("expected a command, found {}", token.describe())));
+        };
+        let step = match command.to_ascii_lowercase().as_str() {
+            "wait" => Step::Wait(self.number()?),
+            "press" => {
+                let buttons = self.buttons()?;
+                let frames = match self.peek() {
+                    Some(Token::Number(_)) => self.number()?,
+                    _ => 1,
+                };
+                Step::Press(buttons, frames)
+            }
+            "hold" => {
+                let buttons = self.buttons()?;
+                Step::Hold(buttons, self.optional_block()?)
+            }
+            "release" => Step::Release(self.buttons()?),
+            "repeat" => {
+                let count = self.number()?;
+                let body = self.optional_block()?.ok_or_else(|| self.position().error("expected '{' after repeat"))?;
+                Step::Repeat(count, body)
+            }
+            _ => return Err(position.error(format!("unknown command '{}'", command))),
+        };
+        Ok(Statement { step, position })
+    }
+
+    fn number(&mut self) -> Result<u32> {
+        match self.peek() {
+            Some(Token::Number(n)) => {
+                self.next += 1;
+                Ok(n)
+            }
+            other => Err(self.position().error(format!("expected a frame count, found {}", describe(other)))),
+        }
+    }
+
+    fn buttons(&mut self) -> Result<Button> {
+        let position = self.position();
+        let Some(Token::Word(names)) = self.peek() else {
+            return Err(position.error(format!("expected a button, found {}", describe(self.peek()))));
+        };
+        self.next += 1;
+        names.split('+').try_fold(Button::empty(), |buttons, name| {
+            Button::from_name(&name.to_ascii_uppercase())
+                .map(|button| buttons | button)
+                .ok_or_else(|| position.error(format!("unknown button '{}'", name)))
+        })
+    }
+
+    /// A `{ ... }` block if one comes next
+    fn optional_block(&mut self) -> Result<Option<Vec<Statement>>> {
+        if self.peek() != Some(Token::Open) {
+            return Ok(None);
+        }
+        let opened = self.position();
+        self.next += 1;
+        self.block(Some(opened)).map(Some)
+    }
+}
+
+fn describe(token: Option<Token<'_>>) -> String {
+    token.map_or_else(|| "the end of the script".to_string(), |token| token.describe())
+}
+
+/// Append the frames `statements` produce, with `base` held by enclosing
+/// blocks and `latched` by `hold` statements without one
+fn expand(statements: &[Statement], base: Button, latched: &mut Button, out: &mut Vec<ControllerState>) -> Result<()> {
+    for statement in statements {
+        let mut hold_for = |buttons: Button, frames: u32| {
+            if out.len() + frames as usize > MAX_FRAMES {
+                return Err(statement.position.error(format!("the script runs past {} frames", MAX_FRAMES)));
+            }
+            out.resize(out.len() + frames as usize, ControllerState { buttons });
+            Ok(())
+        };
+        match &statement.step {
+            Step::Wait(frames) => hold_for(base | *latched, *frames)?,
+            Step::Press(buttons, frames) => hold_for(base | *latched | *buttons, *frames)?,
+            Step::Hold(buttons, Some(body)) => expand(body, base | *buttons, latched, out)?,
+            Step::Hold(buttons, None) => *latched |= *buttons,
+            Step::Release(buttons) => *latched -= *buttons,
+            Step::Repeat(count, body) => {
+                for _ in 0..*count {
+                    let before = out.len();
+                    expand(body, base, latched, out)?;
+                    // Holds and releases alone come out the same every time
+                    if out.len() == before {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buttons(schedule: &InputSchedule) -> Vec<Button> {
+        schedule.frames().iter().map(|state| state.buttons).collect()
+    }
+
+    fn error_at(script: &str) -> (usize, usize, String) {
+        match InputSchedule::parse(script) {
+            Err(EmulatorError::InputScript { line, column, message }) => (line, column, message),
+            other => panic!("{:?} parsed as {:?}", script, other),
+        }
+    }
+
+    #[test]
+    fn test_wait_and_press() {
+        let schedule = InputSchedule::parse("wait 2; press A 3; press b; wait 1").unwrap();
+        let none = Button::empty();
+        assert_eq!(buttons(&schedule), [none, none, Button::A, Button::A, Button::A, Button::B, none]);
+    }
+
+    #[test]
+    fn test_holds_and_releases() {
+        let schedule = InputSchedule::parse("hold RIGHT { press B 2; wait 1 }\nhold up\nwait 1\npress A+Select\nrelease UP\nwait 1").unwrap();
+        assert_eq!(
+            buttons(&schedule),
+            [
+                Button::RIGHT | Button::B,
+                Button::RIGHT | Button::B,
+                Button::RIGHT,
+                Button::UP,
+                Button::UP | Button::A | Button::SELECT,
+                Button::empty(),
+            ]
+        );
+        // A release of something not held is harmless
+        assert_eq!(InputSchedule::parse("press A 2; release A").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_nested_repeats() {
+        let schedule = InputSchedule::parse("repeat 2 { press A; repeat 3 { wait 1 } }").unwrap();
+        let none = Button::empty();
+        assert_eq!(buttons(&schedule), [Button::A, none, none, none, Button::A, none, none, none]);
+
+        let schedule = InputSchedule::parse("hold START { repeat 2 { hold A { wait 1 } press B } }").unwrap();
+        let s = Button::START;
+        assert_eq!(buttons(&schedule), [s | Button::A, s | Button::B, s | Button::A, s | Button::B]);
+
+        // Blocks that take no time finish straight away, however many times
+        assert!(InputSchedule::parse("repeat 4000000000 { hold A; release A }").unwrap().is_empty());
+        assert!(InputSchedule::parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_example_from_the_docs() {
+        let schedule = InputSchedule::parse(
+            "wait 60; press A 10; release A; hold RIGHT { press B 2; wait 8; } repeat 3 { press A 1; wait 10; }",
+        )
+        .unwrap();
+        assert_eq!(schedule.len(), 60 + 10 + 10 + 3 * 11);
+        assert_eq!(schedule.get(59).unwrap().buttons, Button::empty());
+        assert_eq!(schedule.get(60).unwrap().buttons, Button::A);
+        assert_eq!(schedule.get(70).unwrap().buttons, Button::RIGHT | Button::B);
+        assert_eq!(schedule.get(79).unwrap().buttons, Button::RIGHT);
+        assert_eq!(schedule.get(80).unwrap().buttons, Button::A);
+        assert_eq!(schedule.get(91).unwrap().buttons, Button::A);
+        assert_eq!(schedule.get(schedule.len()), None);
+    }
+
+    #[test]
+    fn test_comments_and_separators() {
+        let script = "# title screen\nwait 1 # settle\n;; press START\n\n";
+        assert_eq!(buttons(&InputSchedule::parse(script).unwrap()), [Button::empty(), Button::START]);
+    }
+
+    #[test]
+    fn test_error_positions() {
+        assert_eq!(error_at("wait 1; jump 3"), (1, 9, "unknown command 'jump'".to_string()));
+        assert_eq!(error_at("wait 1\n  press TURBO"), (2, 9, "unknown button 'TURBO'".to_string()));
+        assert_eq!(error_at("press A+X 2"), (1, 7, "unknown button 'X'".to_string()));
+        assert_eq!(error_at("wait"), (1, 1, "expected a frame count, found the end of the script".to_string()));
+        assert_eq!(error_at("wait A"), (1, 6, "expected a frame count, found 'A'".to_string()));
+        assert_eq!(error_at("repeat 2 wait 1"), (1, 10, "expected '{' after repeat".to_string()));
+        assert_eq!(error_at("hold A {\n  wait 1\n"), (1, 8, "'{' is never closed".to_string()));
+        assert_eq!(error_at("wait 1 }"), (1, 8, "expected a command, found '}'".to_string()));
+        assert_eq!(error_at("wait 1, 2"), (1, 7, "unexpected ','".to_string()));
+        assert_eq!(error_at("wait 99999999999"), (1, 6, "99999999999 is too large".to_string()));
+        assert_eq!(error_at("repeat 100000 { wait 100 }").0, 1);
+        assert_eq!(
+            InputSchedule::parse("press Start Start").unwrap_err().to_string(),
+            "Input script error at line 1, column 13: unknown command 'Start'"
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut schedule = InputSchedule::parse("press A 2").unwrap();
+        schedule.merge(&InputSchedule::parse("wait 1; press B 2").unwrap());
+        assert_eq!(buttons(&schedule), [Button::A, Button::A | Button::B, Button::B]);
+    }
+
+    #[test]
+    fn test_playback_offsets() {
+        let playback = ScriptPlayback {
+            schedule: InputSchedule::parse("press A; press B").unwrap(),
+            mode: ScriptMode::Override,
+            start_frame: 10,
+        };
+        assert_eq!(playback.buttons_at(9), None);
+        assert_eq!(playback.buttons_at(10), Some(Button::A));
+        assert_eq!(playback.buttons_at(11), Some(Button::B));
+        assert_eq!(playback.buttons_at(12), None);
+        assert!(!playback.finished_by(11));
+        assert!(playback.finished_by(12));
+    }
+}
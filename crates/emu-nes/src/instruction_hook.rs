@@ -0,0 +1,181 @@
+//! A callback per executed instruction, for tracing inside the process
+//!
+//! [`NesSystem::set_instruction_hook`](crate::NesSystem::set_instruction_hook)
+//! installs an [`InstructionHook`], which [`NesSystem::step`](crate::NesSystem::step)
+//! calls with an [`InstrEvent`] once every instruction has retired. With no
+//! hook installed a step costs one `is_some` check more, so coverage
+//! collectors, profilers and fuzzers can leave it off the rest of the time.
+//!
+//! The hook is `'static` and gets the event by value: it can't borrow the
+//! system it's watching. Results go out the way [`PcCoverage`] sends them,
+//! through something shared. The hook runs with the system mid-step, so
+//! it must not try to get back to it either (through a shared
+//! [`EmulatorHandle`](crate::EmulatorHandle), say); that deadlocks.
+
+use crate::cpu::opcodes::get_opcode_info;
+use crate::cpu::{Cpu6502, CpuMemory};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Called with every instruction [`NesSystem::step`](crate::NesSystem::step) executes
+pub type InstructionHook = Box<dyn FnMut(InstrEvent) + Send>;
+
+/// One executed instruction and the registers it left behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstrEvent {
+    /// Address of the opcode
+    pub pc: u16,
+    /// The opcode byte
+    pub opcode: u8,
+    /// Operand bytes, the first [`Self::operand_len`] of them used
+    pub operand_bytes: [u8; 2],
+    /// Operand bytes the instruction has, 0 to 2
+    pub operand_len: u8,
+    /// Mnemonic from the opcode table, e.g. `"LDA"`
+    pub mnemonic: &'static str,
+    /// CPU cycles it took, with the OAM DMA it started if it wrote $4014;
+    /// an interrupt taken after it isn't counted
+    pub cycles: u16,
+    /// Accumulator after it
+    pub a: u8,
+    /// X after it
+    pub x: u8,
+    /// Y after it
+    pub y: u8,
+    /// Stack pointer after it
+    pub sp: u8,
+    /// Processor status after it
+    pub status: u8,
+    /// Program counter after it: the next instruction, before any
+    /// interrupt is taken
+    pub next_pc: u16,
+}
+
+impl InstrEvent {
+    /// The event for the instruction whose bytes at `pc` were `bytes`,
+    /// with `cpu` as it left it, or None if it isn't one the CPU executes
+    pub(crate) fn new<M: CpuMemory>(pc: u16, bytes: [u8; 3], cycles: u16, cpu: &Cpu6502<M>) -> Option<Self> {
+        let info = get_opcode_info(bytes[0])?;
+        Some(Self {
+            pc,
+            opcode: bytes[0],
+            operand_bytes: [bytes[1], bytes[2]],
+            operand_len: info.mode.operand_len(),
+            mnemonic: info.mnemonic,
+            cycles,
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            sp: cpu.sp,
+            status: cpu.status.bits(),
+            next_pc: cpu.pc,
+        })
+    }
+
+    /// The operand bytes the instruction has
+    pub fn operands(&self) -> &[u8] {
+        &self.operand_bytes[..self.operand_len as usize]
+    }
+}
+
+/// Which addresses instructions have been executed from, one bit each
+///
+/// Shared between a hook and whoever reads it, without a lock:
+///
+/// ```
+/// use emu_nes::{NesSystem, PcCoverage};
+///
+/// let mut system = NesSystem::with_prg_rom(vec![0xEA; 0x4000]).unwrap();
+/// let coverage = PcCoverage::new();
+/// system.set_instruction_hook(Some(coverage.hook()));
+/// for _ in 0..10 {
+///     system.step().unwrap();
+/// }
+/// assert_eq!(coverage.len(), 10);
+/// ```
+#[derive(Debug)]
+pub struct PcCoverage {
+    bits: Box<[AtomicU64]>,
+}
+
+impl PcCoverage {
+    /// An empty bitmap, ready to share
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            bits: (0..0x10000 / 64).map(|_| AtomicU64::new(0)).collect(),
+        })
+    }
+
+    /// A hook that marks the PC of every instruction in this bitmap
+    pub fn hook(self: &Arc<Self>) -> InstructionHook {
+        let coverage = self.clone();
+        Box::new(move |event| coverage.insert(event.pc))
+    }
+
+    /// Mark `pc` as executed
+    pub fn insert(&self, pc: u16) {
+        self.bits[pc as usize / 64].fetch_or(1 << (pc % 64), Ordering::Relaxed);
+    }
+
+    /// Whether an instruction has been executed from `pc`
+    pub fn contains(&self, pc: u16) -> bool {
+        self.bits[pc as usize / 64].load(Ordering::Relaxed) & 1 << (pc % 64) != 0
+    }
+
+    /// Distinct addresses executed from
+    pub fn len(&self) -> usize {
+        self.bits.iter().map(|word| word.load(Ordering::Relaxed).count_ones() as usize).sum()
+    }
+
+    /// Whether nothing has been executed yet
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NesSystem;
+
+    #[test]
+    fn test_coverage_bits() {
+        let coverage = PcCoverage::new();
+        assert!(coverage.is_empty());
+        for pc in [0x0000, 0x003F, 0x0040, 0xFFFF, 0x0040] {
+            coverage.insert(pc);
+        }
+        assert_eq!(coverage.len(), 4);
+        assert!(coverage.contains(0xFFFF));
+        assert!(!coverage.contains(0x0041));
+    }
+
+    #[test]
+    fn test_event_describes_the_instruction() {
+        // LDA #$80; STA $0200; LDX $0200
+        let mut system = NesSystem::with_ram_cart();
+        system.write_code(0x8000, &[0xA9, 0x80, 0x8D, 0x00, 0x02, 0xA6, 0x10]).unwrap();
+        system.set_vectors(0x8000, 0x8000, 0x8000).unwrap();
+        system.reset();
+        let (sender, events) = std::sync::mpsc::channel();
+        system.set_instruction_hook(Some(Box::new(move |event| sender.send(event).unwrap())));
+        for _ in 0..3 {
+            system.step().unwrap();
+        }
+        let events: Vec<InstrEvent> = events.try_iter().collect();
+
+        let lda = events[0];
+        assert_eq!((lda.pc, lda.opcode, lda.mnemonic, lda.operands()), (0x8000, 0xA9, "LDA", &[0x80][..]));
+        assert_eq!((lda.a, lda.cycles, lda.next_pc), (0x80, 2, 0x8002));
+        // Negative flag set by the load
+        assert_ne!(lda.status & 0x80, 0);
+
+        let sta = events[1];
+        assert_eq!((sta.mnemonic, sta.operands(), sta.cycles, sta.next_pc), ("STA", &[0x00, 0x02][..], 4, 0x8005));
+
+        let ldx = events[2];
+        assert_eq!((ldx.mnemonic, ldx.operands(), ldx.cycles), ("LDX", &[0x10][..], 3));
+        assert_eq!(ldx.x, system.cpu().x);
+        assert_eq!(ldx.sp, system.cpu().sp);
+    }
+}
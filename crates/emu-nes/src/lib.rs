@@ -2,19 +2,132 @@
 //!
 //! This crate implements a Nintendo Entertainment System emulator,
 //! including the 6502 CPU, PPU, APU, and memory system.
+//!
+//! Frontends mostly need [`NesSystem`] and what [`prelude`] gathers up;
+//! the modules expose the hardware piece by piece for debuggers and tests.
+//!
+//! # Features
+//!
+//! - `romdb`: correct bad iNES headers from the embedded ROM database
+//!   (`data/romdb.csv`) when a cartridge loads, in `romdb`
+//! - `test-util`: frame assertions for rendering tests, in `test_util`
+//! - `serde`: `Serialize` and `Deserialize` for [`ApuState`]
+//!
+//! None is on by default.
 
+pub mod accuracy;
+pub mod analysis;
 pub mod apu;
+pub mod apu_state;
+pub mod apu_player;
+pub mod battery;
 pub mod cartridge;
+pub mod compliance;
+pub mod controller;
 pub mod cpu;
+pub mod disasm;
+pub mod emu_service;
+pub mod event_log;
+mod expansion_audio;
+pub mod frame_compare;
+pub mod input_script;
+pub mod instruction_hook;
+pub mod lint;
+mod mapper;
 pub mod memory;
+pub mod memory_region;
 pub mod palette;
+pub mod patch;
 pub mod ppu;
+pub mod prelude;
+pub mod profile;
+#[cfg(feature = "romdb")]
+pub mod romdb;
+mod sample_buffer;
+pub mod savestate;
+pub mod state;
 pub mod system;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod vrc6;
+
+pub use accuracy::AccuracyFlags;
+pub use analysis::{AnalysisSnapshot, SnapshotSink};
+pub use apu::{Apu, Channel, StereoConfig};
+pub use apu_player::ApuPlayer;
+pub use apu_state::ApuState;
+pub use cartridge::{BankMapping, BankState, Cartridge, CartridgeInfo, Mirroring, Region};
+pub use controller::{Controller, ControllerPort};
+pub use cpu::{Cpu6502, CpuMemory, CpuVariant};
+pub use disasm::DisasmLine;
+pub use emu_service::{EmulatorHandle, EmulatorService, FrameData, Reply};
+pub use event_log::{EmuEvent, EmuEventKind};
+pub use input_script::{InputSchedule, ScriptMode};
+pub use instruction_hook::{InstrEvent, InstructionHook, PcCoverage};
+pub use lint::{LintFinding, LintKind, LintReport, LintSink};
+pub use memory::{io_write_owner, IoWriteOwner, IrqSource, NesMemory};
+pub use memory_region::MemoryRegion;
+pub use patch::Patch;
+pub use palette::{
+    emphasized_palette, framebuffer_to_rgb, framebuffer_to_rgb_emphasized, framebuffer_to_rgba_fast, greyscale,
+    palette_to_rgb, palette_to_rgb_emphasized, rgba_table, NES_PALETTE,
+};
+pub use ppu::{PowerUpState, Ppu, PpuCtrl, PpuMask, PpuStatus};
+pub use profile::{ProfileReport, Subsystem};
+pub use state::StateDigest;
+pub use system::{ClockStats, CycleBudget, NesSystem, QuickState, SystemEvent};
+pub use vrc6::Vrc6Audio;
+pub use emu_core::{Button, ControllerState, Emulator, EmulatorError};
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    /// Collect `.rs` files under `dir`
+    fn rust_sources(dir: &Path, out: &mut Vec<std::path::PathBuf>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                rust_sources(&path, out);
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                out.push(path);
+            }
+        }
+    }
+
+    #[test]
+    fn test_core_crates_do_not_write_to_stdout() {
+        // Built up at runtime so this file doesn't match itself
+        let needles: Vec<String> = ["print", "eprint", "dbg"]
+            .iter()
+            .flat_map(|name| [format!("{}!(", name), format!("{}ln!(", name)])
+            .chain(["stdout()", "stderr()"].map(|call| format!("io::{}", call)))
+            .collect();
+
+        let crates_dir = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+        let mut sources = Vec::new();
+        for entry in fs::read_dir(crates_dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.file_name().unwrap().to_string_lossy().starts_with("emu-") {
+                rust_sources(&path.join("src"), &mut sources);
+            }
+        }
+        assert!(!sources.is_empty());
 
-pub use apu::Apu;
-pub use cartridge::Cartridge;
-pub use cpu::Cpu6502;
-pub use memory::NesMemory;
-pub use palette::{framebuffer_to_rgb, palette_to_rgb, NES_PALETTE};
-pub use ppu::Ppu;
-pub use system::NesSystem;
+        for path in sources {
+            let text = fs::read_to_string(&path).unwrap();
+            for (line_no, line) in text.lines().enumerate() {
+                let code = line.split("//").next().unwrap();
+                for needle in &needles {
+                    assert!(
+                        !code.contains(needle.as_str()),
+                        "{}:{} writes to stdout/stderr directly; use tracing instead",
+                        path.display(),
+                        line_no + 1
+                    );
+                }
+            }
+        }
+    }
+}
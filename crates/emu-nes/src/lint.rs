@@ -0,0 +1,413 @@
+//! Homebrew lint: code that works in an emulator but not on a console
+//!
+//! With [`NesSystem::set_lint_enabled`](crate::NesSystem::set_lint_enabled)
+//! on, the system watches for habits that emulators forgive and hardware
+//! doesn't, and collects each as a [`LintFinding`] in a [`LintSink`]: what
+//! it was, the PC of the code responsible, the frame it was first seen in
+//! and how often since. A finding is kept once per kind and PC, so code
+//! that repeats a mistake every frame shows up once, with a count.
+//!
+//! The checks look at what the game does, not at what the emulator is set
+//! to forgive: a write during the PPU warm-up is reported whether or not
+//! [`AccuracyFlags::PPU_WARMUP`](crate::AccuracyFlags::PPU_WARMUP) drops it.
+
+use crate::cpu::opcodes::get_opcode_info;
+use crate::memory::BusNotes;
+use crate::ppu::OAM_DECAY_FRAMES;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Frames after reset a game has to write the APU frame counter ($4017)
+/// before [`LintKind::FrameCounterNotSet`] is reported
+pub const FRAME_COUNTER_GRACE_FRAMES: u32 = 10;
+
+const RTI: u8 = 0x40;
+const AND_IMMEDIATE: u8 = 0x29;
+
+/// A kind of hardware-compatibility mistake
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LintKind {
+    /// $2000 or $2001 set to anything but 0, or $2005 or $2006 written,
+    /// while the PPU was warming up
+    WarmupWrite,
+    /// Sprites shown from OAM that was never written, or that went
+    /// [`OAM_DECAY_FRAMES`] frames of rendering off without a rewrite
+    StaleOam,
+    /// $2007 written outside the NMI handler while the PPU was drawing
+    VramDuringRendering,
+    /// $2002 loaded and masked with bit 5, the sprite overflow flag
+    SpriteOverflowTest,
+    /// An opcode outside the documented 6502 set
+    UnofficialOpcode,
+    /// The NMI handler wrote $2007 or started OAM DMA after vblank ended;
+    /// reported at the first such update in each run of the handler
+    NmiOverBudget,
+    /// No write to $4017 in the first [`FRAME_COUNTER_GRACE_FRAMES`] frames
+    FrameCounterNotSet,
+}
+
+impl LintKind {
+    pub const ALL: [LintKind; 7] = [
+        LintKind::WarmupWrite,
+        LintKind::StaleOam,
+        LintKind::VramDuringRendering,
+        LintKind::SpriteOverflowTest,
+        LintKind::UnofficialOpcode,
+        LintKind::NmiOverBudget,
+        LintKind::FrameCounterNotSet,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LintKind::WarmupWrite => "PPU write during warm-up",
+            LintKind::StaleOam => "Sprites shown from stale OAM",
+            LintKind::VramDuringRendering => "$2007 write while rendering",
+            LintKind::SpriteOverflowTest => "Sprite overflow flag tested",
+            LintKind::UnofficialOpcode => "Unofficial opcode",
+            LintKind::NmiOverBudget => "NMI handler past vblank",
+            LintKind::FrameCounterNotSet => "APU frame counter never set",
+        }
+    }
+
+    /// What happens on hardware, and what to do instead
+    pub fn advice(self) -> &'static str {
+        match self {
+            LintKind::WarmupWrite => {
+                "writes to $2000, $2001, $2005 and $2006 before the first vblank are ignored on hardware — wait for two vblanks after reset"
+            }
+            LintKind::StaleOam => {
+                "OAM starts out random and fades after a few frames with rendering off — copy the sprites in with OAM DMA ($4014) before turning sprites on"
+            }
+            LintKind::VramDuringRendering => {
+                "VRAM writes while the PPU draws land at the wrong address and corrupt the scroll — write during vblank or with rendering off"
+            }
+            LintKind::SpriteOverflowTest => {
+                "the sprite overflow flag misfires on hardware, with false positives and negatives — time splits with sprite 0 hit or a mapper IRQ"
+            }
+            LintKind::UnofficialOpcode => {
+                "undocumented opcodes behave differently across CPU revisions and clones, and this emulator doesn't run them — use official instructions"
+            }
+            LintKind::NmiOverBudget => {
+                "vblank is about 2270 CPU cycles on NTSC, and VRAM or OAM updates after it corrupt the picture — do the PPU updates first in the NMI handler, or fewer per frame"
+            }
+            LintKind::FrameCounterNotSet => {
+                "the frame counter IRQ is left to its power-up state and can fire unexpectedly — write $40 to $4017 at reset"
+            }
+        }
+    }
+}
+
+impl fmt::Display for LintKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// One mistake, at one place in the code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LintFinding {
+    pub kind: LintKind,
+    /// Address of the instruction responsible; the reset vector for
+    /// [`LintKind::FrameCounterNotSet`]
+    pub pc: u16,
+    /// Frame it was first seen in, counted from the last reset
+    pub first_frame: u64,
+    /// Times it has been seen
+    pub count: u32,
+}
+
+impl fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "${:04X}  {} (frame {}, ×{}): {}",
+            self.pc,
+            self.kind,
+            self.first_frame,
+            self.count,
+            self.kind.advice()
+        )
+    }
+}
+
+/// Findings collected so far, one per kind and PC
+#[derive(Debug, Clone, Default)]
+pub struct LintSink {
+    /// In the order they were first seen
+    findings: Vec<LintFinding>,
+    /// Index into `findings` by kind and PC
+    index: HashMap<(LintKind, u16), usize>,
+}
+
+impl LintSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count one sighting of `kind` at `pc` in `frame`
+    pub fn record(&mut self, kind: LintKind, pc: u16, frame: u64) {
+        match self.index.get(&(kind, pc)) {
+            Some(&i) => {
+                let finding = &mut self.findings[i];
+                finding.count = finding.count.saturating_add(1);
+            }
+            None => {
+                self.index.insert((kind, pc), self.findings.len());
+                self.findings.push(LintFinding { kind, pc, first_frame: frame, count: 1 });
+            }
+        }
+    }
+
+    /// Findings in the order they were first seen
+    pub fn findings(&self) -> &[LintFinding] {
+        &self.findings
+    }
+
+    pub fn len(&self) -> usize {
+        self.findings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.findings.clear();
+        self.index.clear();
+    }
+
+    pub fn report(&self) -> LintReport {
+        LintReport { findings: self.findings.clone() }
+    }
+}
+
+/// The findings at one point, for printing or showing in a list
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LintReport {
+    findings: Vec<LintFinding>,
+}
+
+impl LintReport {
+    /// Findings in the order they were first seen
+    pub fn findings(&self) -> &[LintFinding] {
+        &self.findings
+    }
+
+    pub fn len(&self) -> usize {
+        self.findings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.findings.is_empty()
+    }
+}
+
+impl fmt::Display for LintReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.findings.is_empty() {
+            return writeln!(f, "Lint: no findings");
+        }
+        writeln!(f, "Lint: {} finding(s)", self.findings.len())?;
+        for finding in &self.findings {
+            writeln!(f, "  {}", finding)?;
+        }
+        Ok(())
+    }
+}
+
+/// The detectors, fed by [`NesSystem`](crate::NesSystem) around every
+/// instruction
+#[derive(Debug, Clone)]
+pub(crate) struct Linter {
+    sink: LintSink,
+    /// Address of the instruction in progress
+    pc: u16,
+    /// Between taking an NMI and the next RTI
+    in_nmi: bool,
+    /// The NMI handler in progress has run past vblank and been reported
+    nmi_late: bool,
+    /// Frames ended with rendering off since OAM was last written, up to
+    /// [`OAM_DECAY_FRAMES`]; OAM is random at power-up, so it starts there
+    dark_frames: u8,
+    /// Where sprites were last turned on
+    sprites_on_pc: u16,
+    frame_counter_set: bool,
+    frames_since_reset: u32,
+}
+
+impl Linter {
+    pub fn new() -> Self {
+        Self {
+            sink: LintSink::new(),
+            pc: 0,
+            in_nmi: false,
+            nmi_late: false,
+            dark_frames: OAM_DECAY_FRAMES,
+            sprites_on_pc: 0,
+            frame_counter_set: false,
+            frames_since_reset: 0,
+        }
+    }
+
+    pub fn sink(&self) -> &LintSink {
+        &self.sink
+    }
+
+    /// Start watching again after a reset or a loaded state; findings are
+    /// kept, and so is what's known about OAM, which a reset leaves alone
+    pub fn reset(&mut self) {
+        *self = Self {
+            sink: std::mem::take(&mut self.sink),
+            dark_frames: self.dark_frames,
+            sprites_on_pc: self.sprites_on_pc,
+            ..Self::new()
+        };
+    }
+
+    /// Look at the instruction about to run at `pc`: its opcode and the
+    /// four bytes after it
+    pub fn before_step(&mut self, pc: u16, bytes: [u8; 5], frame: u64) {
+        self.pc = pc;
+        let [opcode, low, high, next, mask] = bytes;
+        if get_opcode_info(opcode).is_none() {
+            self.sink.record(LintKind::UnofficialOpcode, pc, frame);
+        }
+        if opcode == RTI {
+            self.in_nmi = false;
+        }
+        // LDA, LDX or LDY $2002 (or a mirror), then AND with bit 5
+        let addr = u16::from_le_bytes([low, high]);
+        if (0xAC..=0xAE).contains(&opcode)
+            && (0x2000..0x4000).contains(&addr)
+            && addr & 0x07 == 2
+            && next == AND_IMMEDIATE
+            && mask & 0x20 != 0
+        {
+            self.sink.record(LintKind::SpriteOverflowTest, pc, frame);
+        }
+    }
+
+    /// Look at what the instruction just run did on the bus
+    pub fn after_step(&mut self, notes: BusNotes, frame: u64) {
+        let pc = self.pc;
+        if notes.contains(BusNotes::WARMUP_WRITE) {
+            self.sink.record(LintKind::WarmupWrite, pc, frame);
+        }
+        // An overrun is reported once, at the first update it made late
+        let late = notes.intersects(BusNotes::VRAM_WHILE_RENDERING | BusNotes::LATE_OAM_DMA);
+        if late && self.in_nmi {
+            if !self.nmi_late {
+                self.sink.record(LintKind::NmiOverBudget, pc, frame);
+            }
+            self.nmi_late = true;
+        } else if notes.contains(BusNotes::VRAM_WHILE_RENDERING) {
+            self.sink.record(LintKind::VramDuringRendering, pc, frame);
+        }
+        if notes.contains(BusNotes::OAM_WRITE) {
+            self.dark_frames = 0;
+        }
+        if notes.contains(BusNotes::SPRITES_ON) {
+            self.sprites_on_pc = pc;
+        }
+        if notes.contains(BusNotes::FRAME_COUNTER) {
+            self.frame_counter_set = true;
+        }
+    }
+
+    pub fn nmi_taken(&mut self) {
+        self.in_nmi = true;
+        self.nmi_late = false;
+    }
+
+    /// The PPU finished a frame and starts drawing the next with `rendering`
+    /// and `sprites` enabled; `reset_vector` is where the game starts
+    pub fn end_frame(&mut self, rendering: bool, sprites: bool, reset_vector: u16, frame: u64) {
+        if sprites && self.dark_frames >= OAM_DECAY_FRAMES {
+            self.sink.record(LintKind::StaleOam, self.sprites_on_pc, frame);
+        }
+        if !rendering {
+            self.dark_frames = (self.dark_frames + 1).min(OAM_DECAY_FRAMES);
+        }
+
+        self.frames_since_reset = self.frames_since_reset.saturating_add(1);
+        if self.frames_since_reset == FRAME_COUNTER_GRACE_FRAMES && !self.frame_counter_set {
+            self.sink.record(LintKind::FrameCounterNotSet, reset_vector, frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_findings_are_kept_once_per_kind_and_pc() {
+        let mut sink = LintSink::new();
+        sink.record(LintKind::WarmupWrite, 0xC010, 0);
+        sink.record(LintKind::VramDuringRendering, 0xC020, 3);
+        sink.record(LintKind::WarmupWrite, 0xC010, 1);
+        sink.record(LintKind::WarmupWrite, 0xC013, 1);
+        sink.record(LintKind::VramDuringRendering, 0xC010, 4);
+        sink.record(LintKind::VramDuringRendering, 0xC020, 5);
+
+        let found: Vec<_> = sink.findings().iter().map(|f| (f.kind, f.pc, f.first_frame, f.count)).collect();
+        assert_eq!(found, vec![
+            (LintKind::WarmupWrite, 0xC010, 0, 2),
+            (LintKind::VramDuringRendering, 0xC020, 3, 2),
+            (LintKind::WarmupWrite, 0xC013, 1, 1),
+            (LintKind::VramDuringRendering, 0xC010, 4, 1),
+        ]);
+
+        sink.clear();
+        assert!(sink.is_empty());
+        sink.record(LintKind::WarmupWrite, 0xC010, 9);
+        assert_eq!(sink.findings()[0].count, 1);
+    }
+
+    #[test]
+    fn test_report_lists_findings_with_advice() {
+        let mut sink = LintSink::new();
+        assert_eq!(sink.report().to_string(), "Lint: no findings\n");
+
+        for frame in 0..3 {
+            sink.record(LintKind::WarmupWrite, 0x8004, frame);
+        }
+        let report = sink.report();
+        assert_eq!(report.len(), 1);
+        let text = report.to_string();
+        assert!(text.starts_with("Lint: 1 finding(s)\n  $8004  PPU write during warm-up (frame 0, ×3): "), "{}", text);
+        assert!(text.contains("wait for two vblanks after reset"), "{}", text);
+
+        // Every kind has a name and advice of its own
+        for kind in LintKind::ALL {
+            assert!(!kind.name().is_empty() && !kind.advice().is_empty());
+            assert_eq!(LintKind::ALL.iter().filter(|other| other.advice() == kind.advice()).count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_overflow_test_needs_the_mask() {
+        let mut linter = Linter::new();
+        // LDA $2002 / AND #$20, then LDA $2002 / AND #$80, then LDX $200A / AND #$E0
+        linter.before_step(0x8000, [0xAD, 0x02, 0x20, 0x29, 0x20], 0);
+        linter.before_step(0x8010, [0xAD, 0x02, 0x20, 0x29, 0x80], 0);
+        linter.before_step(0x8020, [0xAE, 0x0A, 0x20, 0x29, 0xE0], 0);
+        let pcs: Vec<u16> = linter.sink().findings().iter().map(|finding| finding.pc).collect();
+        assert_eq!(pcs, vec![0x8000, 0x8020]);
+    }
+
+    #[test]
+    fn test_reset_keeps_findings_and_rearms_checks() {
+        let mut linter = Linter::new();
+        for frame in 0..FRAME_COUNTER_GRACE_FRAMES as u64 * 2 {
+            linter.end_frame(false, false, 0x8000, frame);
+        }
+        assert_eq!(linter.sink().len(), 1);
+        linter.reset();
+        assert_eq!(linter.sink().len(), 1);
+        for frame in 0..FRAME_COUNTER_GRACE_FRAMES as u64 {
+            linter.end_frame(false, false, 0x8000, frame);
+        }
+        assert_eq!(linter.sink().findings()[0].count, 2);
+    }
+}
@@ -0,0 +1,1037 @@
+//! Cartridge mappers
+//!
+//! A mapper is the banking hardware on the cartridge board. Each supported
+//! board is one struct implementing [`Mapper`], picked from the iNES header
+//! by [`create`] when the cartridge is loaded. The ROM data and PRG-RAM stay
+//! in the [`Cartridge`](crate::Cartridge) and ROM is passed in on every
+//! access, so a mapper only holds its registers.
+//!
+//! Bank numbers wrap to the size of the ROM, the way an unconnected address
+//! line would, so oversized bank values never read past the data. Boards
+//! with a bank latch mask what's written to it by
+//! [`Cartridge::bank_mask`](crate::Cartridge::bank_mask) as well, so the
+//! banks they report are the ones actually mapped.
+
+use crate::cartridge::{BankMapping, BankState, Cartridge, INesHeader, Mirroring};
+use crate::expansion_audio::ExpansionAudio;
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+use crate::vrc6::{self, Vrc6Audio};
+use emu_core::{EmulatorError, Result};
+use std::fmt;
+use tracing::warn;
+
+/// Banking hardware on a cartridge board
+///
+/// A mapper describes where each address lands in the ROM; reads and CHR-RAM
+/// writes go through those offsets, as does [`Mapper::bank_state`].
+/// Mappers are `Clone`; [`MapperClone`] lets a boxed one be cloned too.
+pub(crate) trait Mapper: Snapshot + MapperClone + fmt::Debug + Send {
+    /// Offset into PRG-ROM of CPU address `addr` ($8000-$FFFF), `None` for open bus
+    fn prg_offset(&self, prg_len: usize, addr: u16) -> Option<usize>;
+
+    /// Offset into CHR-ROM/RAM of PPU address `addr` ($0000-$1FFF)
+    fn chr_offset(&self, chr_len: usize, addr: u16) -> Option<usize>;
+
+    /// Write to $6000-$FFFF, usually a bank register
+    ///
+    /// Writes to $6000-$7FFF also land in PRG-RAM; boards with registers
+    /// there see them here as well.
+    fn write_prg(&mut self, addr: u16, value: u8);
+
+    /// Current nametable mirroring
+    fn mirroring(&self) -> Mirroring;
+
+    /// Read from PRG-ROM space ($8000-$FFFF)
+    fn read_prg(&self, prg_rom: &[u8], addr: u16) -> u8 {
+        self.prg_offset(prg_rom.len(), addr).map_or(0xFF, |offset| prg_rom[offset])
+    }
+
+    /// Read from the expansion area ($4020-$5FFF), `None` for open bus
+    ///
+    /// Where boards like MMC5 and the FDS put extra registers and
+    /// expansion audio; none of the supported boards decode it.
+    fn read_expansion(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    /// Write to the expansion area ($4020-$5FFF)
+    fn write_expansion(&mut self, _addr: u16, _value: u8) {}
+
+    /// Read from the pattern tables ($0000-$1FFF)
+    fn read_chr(&self, chr: &[u8], addr: u16) -> u8 {
+        self.chr_offset(chr.len(), addr).map_or(0, |offset| chr[offset])
+    }
+
+    /// Write to the pattern tables ($0000-$1FFF)
+    ///
+    /// The cartridge only calls this for CHR-RAM.
+    fn write_chr(&mut self, chr: &mut [u8], addr: u16, value: u8) {
+        if let Some(offset) = self.chr_offset(chr.len(), addr) {
+            chr[offset] = value;
+        }
+    }
+
+    /// Run for one CPU cycle
+    ///
+    /// Only boards that count cycles for an IRQ do anything.
+    fn clock_cpu(&mut self) {}
+
+    /// Whether the mapper is asserting IRQ
+    ///
+    /// None of the discrete boards have an IRQ source.
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    /// IRQ counter value, for boards that have one
+    fn irq_counter(&self) -> Option<u16> {
+        None
+    }
+
+    /// Selected PRG bank, for logging
+    fn prg_bank(&self) -> usize {
+        0
+    }
+
+    /// Selected CHR bank
+    ///
+    /// Changes exactly when the CHR mapping does, which is when the PPU's
+    /// copy of the pattern tables needs refreshing. Boards with more than
+    /// one CHR register pack them into one number.
+    fn chr_bank(&self) -> usize {
+        0
+    }
+
+    /// What is mapped where, in 8KB PRG and 1KB CHR windows
+    fn bank_state(&self, prg_len: usize, chr_len: usize) -> BankState {
+        BankState {
+            prg: windows(0x8000, 0x2000, 4, |addr| self.prg_offset(prg_len, addr)),
+            chr: windows(0x0000, 0x0400, 8, |addr| self.chr_offset(chr_len, addr)),
+            mirroring: self.mirroring(),
+            irq_pending: self.irq_pending(),
+            irq_counter: self.irq_counter(),
+        }
+    }
+}
+
+/// Clones a mapper behind a `Box<dyn Mapper>`, for every `Clone` mapper
+pub(crate) trait MapperClone {
+    fn clone_box(&self) -> Box<dyn Mapper>;
+}
+
+impl<T: Mapper + Clone + 'static> MapperClone for T {
+    fn clone_box(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Mapper> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Describe `count` windows of `size` bytes from `start`, using the mapping at each start
+fn windows(start: u16, size: u16, count: u16, offset: impl Fn(u16) -> Option<usize>) -> Vec<BankMapping> {
+    (0..count)
+        .map(|i| {
+            let window_start = start + i * size;
+            let rom_offset = offset(window_start);
+            BankMapping {
+                window_start,
+                size,
+                bank_index: rom_offset.map(|offset| offset / size as usize),
+                rom_offset,
+            }
+        })
+        .collect()
+}
+
+/// Build the mapper named by `header`
+pub(crate) fn create(header: &INesHeader, prg_len: usize, chr_len: usize) -> Result<Box<dyn Mapper>> {
+    let mirroring = header.mirroring;
+    let prg32_mask = Cartridge::bank_mask(prg_len, 0x8000) as u8;
+    let chr8_mask = Cartridge::bank_mask(chr_len, 0x2000) as u8;
+    let mapper: Box<dyn Mapper> = match header.mapper {
+        0 => {
+            if prg_len > 0x8000 {
+                warn!(
+                    "NROM with {}KB of PRG-ROM; only the first 32KB are mapped",
+                    prg_len / 1024
+                );
+            }
+            Box::new(Nrom { mirroring })
+        }
+        11 => Box::new(ColorDreams { mirroring, prg_mask: prg32_mask, chr_mask: chr8_mask, prg_bank: 0, chr_bank: 0 }),
+        24 | 26 => Box::new(Vrc6::new(header.mapper == 26, prg_len, chr_len)),
+        // NINA-001 boards carry CHR-ROM, BNROM boards carry CHR-RAM
+        34 if chr_len > 0x2000 => Box::new(Nina001::new(
+            mirroring,
+            prg32_mask,
+            Cartridge::bank_mask(chr_len, 0x1000) as u8,
+        )),
+        34 => Box::new(Bnrom { mirroring, prg_mask: prg32_mask, prg_bank: 0 }),
+        66 => Box::new(Gxrom { mirroring, prg_mask: prg32_mask, chr_mask: chr8_mask, prg_bank: 0, chr_bank: 0 }),
+        87 => Box::new(Jaleco87 { mirroring, chr_mask: chr8_mask, chr_bank: 0 }),
+        mapper => return Err(EmulatorError::UnsupportedMapper(mapper)),
+    };
+    Ok(mapper)
+}
+
+/// The sound chip on `header`'s board, for the boards that carry one
+pub(crate) fn expansion_audio(header: &INesHeader) -> Option<Box<dyn ExpansionAudio>> {
+    match header.mapper {
+        24 => Some(Box::new(Vrc6Audio::new())),
+        26 => Some(Box::new(Vrc6Audio::with_lines_swapped())),
+        _ => None,
+    }
+}
+
+/// Offset of byte `offset` in bank `bank`, with the bank number wrapped to the ROM
+fn bank_offset(len: usize, bank_size: usize, bank: usize, offset: usize) -> Option<usize> {
+    let banks = Cartridge::bank_count(len, bank_size);
+    Some((bank % banks) * bank_size + offset).filter(|&offset| offset < len)
+}
+
+/// PRG offset for boards with one fixed 16KB or 32KB PRG-ROM
+///
+/// 16KB is mirrored at $8000 and $C000, 32KB is mapped linearly and
+/// anything past 32KB is out of reach.
+fn fixed_prg_offset(prg_len: usize, addr: u16) -> Option<usize> {
+    if addr < 0x8000 {
+        return None;
+    }
+    let mask = if prg_len <= 0x4000 { 0x3FFF } else { 0x7FFF };
+    bank_offset(prg_len, 0x8000, 0, (addr & mask) as usize)
+}
+
+/// PRG offset for boards with one switchable 32KB bank at $8000
+fn prg32_offset(prg_len: usize, bank: u8, addr: u16) -> Option<usize> {
+    if addr < 0x8000 {
+        return None;
+    }
+    bank_offset(prg_len, 0x8000, bank as usize, (addr & 0x7FFF) as usize)
+}
+
+/// CHR offset for boards with one 8KB CHR bank
+fn chr8_offset(chr_len: usize, bank: u8, addr: u16) -> Option<usize> {
+    bank_offset(chr_len, 0x2000, bank as usize, (addr & 0x1FFF) as usize)
+}
+
+/// Mapper 0 (NROM): no banking
+#[derive(Debug, Clone)]
+struct Nrom {
+    mirroring: Mirroring,
+}
+
+impl Mapper for Nrom {
+    fn prg_offset(&self, prg_len: usize, addr: u16) -> Option<usize> {
+        fixed_prg_offset(prg_len, addr)
+    }
+
+    fn chr_offset(&self, chr_len: usize, addr: u16) -> Option<usize> {
+        chr8_offset(chr_len, 0, addr)
+    }
+
+    fn write_prg(&mut self, _addr: u16, _value: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl Snapshot for Nrom {
+    fn save(&self, _w: &mut StateWriter) {}
+
+    fn load(&mut self, _r: &mut StateReader) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Mapper 66 (GxROM): 32KB PRG bank in bits 4-5, 8KB CHR bank in bits 0-1
+#[derive(Debug, Clone)]
+struct Gxrom {
+    mirroring: Mirroring,
+    prg_mask: u8,
+    chr_mask: u8,
+    prg_bank: u8,
+    chr_bank: u8,
+}
+
+impl Mapper for Gxrom {
+    fn prg_offset(&self, prg_len: usize, addr: u16) -> Option<usize> {
+        prg32_offset(prg_len, self.prg_bank, addr)
+    }
+
+    fn chr_offset(&self, chr_len: usize, addr: u16) -> Option<usize> {
+        chr8_offset(chr_len, self.chr_bank, addr)
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            self.prg_bank = (value >> 4) & 0x03 & self.prg_mask;
+            self.chr_bank = value & 0x03 & self.chr_mask;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_bank(&self) -> usize {
+        self.prg_bank as usize
+    }
+
+    fn chr_bank(&self) -> usize {
+        self.chr_bank as usize
+    }
+}
+
+impl Snapshot for Gxrom {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.prg_bank);
+        w.u8(self.chr_bank);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.prg_bank = r.u8()?;
+        self.chr_bank = r.u8()?;
+        Ok(())
+    }
+}
+
+/// Mapper 11 (Color Dreams): 32KB PRG bank in bits 0-1, 8KB CHR bank in bits 4-7
+#[derive(Debug, Clone)]
+struct ColorDreams {
+    mirroring: Mirroring,
+    prg_mask: u8,
+    chr_mask: u8,
+    prg_bank: u8,
+    chr_bank: u8,
+}
+
+impl Mapper for ColorDreams {
+    fn prg_offset(&self, prg_len: usize, addr: u16) -> Option<usize> {
+        prg32_offset(prg_len, self.prg_bank, addr)
+    }
+
+    fn chr_offset(&self, chr_len: usize, addr: u16) -> Option<usize> {
+        chr8_offset(chr_len, self.chr_bank, addr)
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            self.prg_bank = value & 0x03 & self.prg_mask;
+            self.chr_bank = (value >> 4) & self.chr_mask;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_bank(&self) -> usize {
+        self.prg_bank as usize
+    }
+
+    fn chr_bank(&self) -> usize {
+        self.chr_bank as usize
+    }
+}
+
+impl Snapshot for ColorDreams {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.prg_bank);
+        w.u8(self.chr_bank);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.prg_bank = r.u8()?;
+        self.chr_bank = r.u8()?;
+        Ok(())
+    }
+}
+
+/// Mapper 87 (Jaleco/Konami): 8KB CHR bank written to $6000-$7FFF
+///
+/// The board wires the two data lines to the bank register swapped, so
+/// bit 0 selects the upper half of the CHR-ROM. PRG is fixed, as on NROM.
+#[derive(Debug, Clone)]
+struct Jaleco87 {
+    mirroring: Mirroring,
+    chr_mask: u8,
+    chr_bank: u8,
+}
+
+impl Mapper for Jaleco87 {
+    fn prg_offset(&self, prg_len: usize, addr: u16) -> Option<usize> {
+        fixed_prg_offset(prg_len, addr)
+    }
+
+    fn chr_offset(&self, chr_len: usize, addr: u16) -> Option<usize> {
+        chr8_offset(chr_len, self.chr_bank, addr)
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if (0x6000..0x8000).contains(&addr) {
+            self.chr_bank = (((value & 0x01) << 1) | ((value >> 1) & 0x01)) & self.chr_mask;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn chr_bank(&self) -> usize {
+        self.chr_bank as usize
+    }
+}
+
+impl Snapshot for Jaleco87 {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.chr_bank);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.chr_bank = r.u8()?;
+        Ok(())
+    }
+}
+
+/// Mapper 34, BNROM board: 32KB PRG bank written to $8000-$FFFF, CHR-RAM
+///
+/// The whole written value is the bank number, which covers the oversized
+/// homebrew boards with more than the original 128KB.
+#[derive(Debug, Clone)]
+struct Bnrom {
+    mirroring: Mirroring,
+    prg_mask: u8,
+    prg_bank: u8,
+}
+
+impl Mapper for Bnrom {
+    fn prg_offset(&self, prg_len: usize, addr: u16) -> Option<usize> {
+        prg32_offset(prg_len, self.prg_bank, addr)
+    }
+
+    fn chr_offset(&self, chr_len: usize, addr: u16) -> Option<usize> {
+        chr8_offset(chr_len, 0, addr)
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            self.prg_bank = value & self.prg_mask;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_bank(&self) -> usize {
+        self.prg_bank as usize
+    }
+}
+
+impl Snapshot for Bnrom {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.prg_bank);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.prg_bank = r.u8()?;
+        Ok(())
+    }
+}
+
+/// Mapper 34, NINA-001 board: 8KB PRG-RAM with the bank registers at its top
+///
+/// $7FFD selects the 32KB PRG bank, $7FFE and $7FFF the 4KB CHR banks at
+/// $0000 and $1000. The registers are write-only and the RAM underneath
+/// them is written too.
+#[derive(Debug, Clone)]
+struct Nina001 {
+    mirroring: Mirroring,
+    prg_mask: u8,
+    chr_mask: u8,
+    prg_bank: u8,
+    chr_banks: [u8; 2],
+}
+
+impl Nina001 {
+    fn new(mirroring: Mirroring, prg_mask: u8, chr_mask: u8) -> Self {
+        Self {
+            mirroring,
+            prg_mask,
+            chr_mask,
+            prg_bank: 0,
+            chr_banks: [0, 0],
+        }
+    }
+}
+
+impl Mapper for Nina001 {
+    fn prg_offset(&self, prg_len: usize, addr: u16) -> Option<usize> {
+        prg32_offset(prg_len, self.prg_bank, addr)
+    }
+
+    fn chr_offset(&self, chr_len: usize, addr: u16) -> Option<usize> {
+        let bank = self.chr_banks[((addr >> 12) & 1) as usize];
+        bank_offset(chr_len, 0x1000, bank as usize, (addr & 0x0FFF) as usize)
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x7FFD => self.prg_bank = value & 0x01 & self.prg_mask,
+            0x7FFE => self.chr_banks[0] = value & 0x0F & self.chr_mask,
+            0x7FFF => self.chr_banks[1] = value & 0x0F & self.chr_mask,
+            _ => {}
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_bank(&self) -> usize {
+        self.prg_bank as usize
+    }
+
+    fn chr_bank(&self) -> usize {
+        ((self.chr_banks[1] as usize) << 4) | self.chr_banks[0] as usize
+    }
+}
+
+impl Snapshot for Nina001 {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.prg_bank);
+        w.u8(self.chr_banks[0]);
+        w.u8(self.chr_banks[1]);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.prg_bank = r.u8()?;
+        self.chr_banks = [r.u8()?, r.u8()?];
+        Ok(())
+    }
+}
+
+/// Mappers 24 and 26 (Konami VRC6): 16KB and 8KB PRG banks, eight 1KB CHR
+/// banks, mapper-controlled mirroring and a CPU cycle IRQ
+///
+/// The last 8KB of PRG-ROM is fixed at $E000. Each register repeats
+/// through its 4KB page; mapper 26 boards swap A0 and A1, as for the sound
+/// chip (see [`vrc6::register`]), which [`expansion_audio`] puts on both.
+/// Of $B003 only the mirroring is modelled: the three VRC6 games all use
+/// its first banking mode.
+#[derive(Debug, Clone)]
+struct Vrc6 {
+    lines_swapped: bool,
+    prg16_mask: u8,
+    prg8_mask: u8,
+    chr_mask: u8,
+    /// 16KB bank at $8000
+    prg16_bank: u8,
+    /// 8KB bank at $C000
+    prg8_bank: u8,
+    /// 1KB banks at $0000-$1FFF
+    chr_banks: [u8; 8],
+    /// $B003, which holds the mirroring in bits 2-3
+    ppu_banking: u8,
+    irq: VrcIrq,
+}
+
+impl Vrc6 {
+    fn new(lines_swapped: bool, prg_len: usize, chr_len: usize) -> Self {
+        Self {
+            lines_swapped,
+            prg16_mask: Cartridge::bank_mask(prg_len, 0x4000) as u8,
+            prg8_mask: Cartridge::bank_mask(prg_len, 0x2000) as u8,
+            // CHR-RAM is 8KB
+            chr_mask: Cartridge::bank_mask(chr_len.max(0x2000), 0x0400) as u8,
+            prg16_bank: 0,
+            prg8_bank: 0,
+            chr_banks: [0; 8],
+            ppu_banking: 0,
+            irq: VrcIrq::default(),
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn prg_offset(&self, prg_len: usize, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xBFFF => bank_offset(prg_len, 0x4000, self.prg16_bank as usize, (addr & 0x3FFF) as usize),
+            0xC000..=0xDFFF => bank_offset(prg_len, 0x2000, self.prg8_bank as usize, (addr & 0x1FFF) as usize),
+            0xE000..=0xFFFF => {
+                let last = Cartridge::bank_count(prg_len, 0x2000) - 1;
+                bank_offset(prg_len, 0x2000, last, (addr & 0x1FFF) as usize)
+            }
+            _ => None,
+        }
+    }
+
+    fn chr_offset(&self, chr_len: usize, addr: u16) -> Option<usize> {
+        let bank = self.chr_banks[(addr >> 10) as usize & 7];
+        bank_offset(chr_len, 0x0400, bank as usize, (addr & 0x03FF) as usize)
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        match vrc6::register(addr, self.lines_swapped) {
+            0x8000..=0x8003 => self.prg16_bank = value & 0x0F & self.prg16_mask,
+            0xB003 => self.ppu_banking = value,
+            0xC000..=0xC003 => self.prg8_bank = value & 0x1F & self.prg8_mask,
+            reg @ (0xD000..=0xD003 | 0xE000..=0xE003) => {
+                let bank = ((reg >> 12) - 0xD) * 4 + (reg & 3);
+                self.chr_banks[bank as usize] = value & self.chr_mask;
+            }
+            0xF000 => self.irq.latch = value,
+            0xF001 => self.irq.write_control(value),
+            0xF002 => self.irq.acknowledge(),
+            // The sound registers are the chip's
+            _ => {}
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.ppu_banking >> 2) & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn irq_counter(&self) -> Option<u16> {
+        Some(self.irq.counter as u16)
+    }
+
+    /// Both PRG registers, the 8KB one in the upper byte
+    fn prg_bank(&self) -> usize {
+        (self.prg8_bank as usize) << 8 | self.prg16_bank as usize
+    }
+
+    /// All eight CHR registers, the one for $0000 in the low byte
+    fn chr_bank(&self) -> usize {
+        u64::from_le_bytes(self.chr_banks) as usize
+    }
+}
+
+impl Snapshot for Vrc6 {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.prg16_bank);
+        w.u8(self.prg8_bank);
+        w.bytes(&self.chr_banks);
+        w.u8(self.ppu_banking);
+        self.irq.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.prg16_bank = r.u8()?;
+        self.prg8_bank = r.u8()?;
+        r.bytes_into(&mut self.chr_banks)?;
+        self.ppu_banking = r.u8()?;
+        self.irq.load(r)
+    }
+}
+
+/// CPU cycles per scanline, as the VRC IRQ prescaler counts them in
+/// thirds of a cycle
+const VRC_IRQ_PRESCALER: i16 = 341;
+
+/// The IRQ counter Konami's VRC boards share
+///
+/// An 8-bit counter climbs towards $FF, then reloads from the latch and
+/// raises IRQ. In scanline mode a prescaler steps it once every 113⅔
+/// CPU cycles, in cycle mode it steps every cycle.
+#[derive(Debug, Clone, Default)]
+struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    /// Thirds of a CPU cycle left before the next scanline step
+    prescaler: i16,
+    enabled: bool,
+    /// `enabled` after an acknowledge
+    enabled_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    /// Control register: bit 0 enable after acknowledge, bit 1 enable,
+    /// bit 2 cycle mode
+    ///
+    /// Acknowledges IRQ, and enabling reloads the counter.
+    fn write_control(&mut self, value: u8) {
+        self.enabled_after_ack = value & 0x01 != 0;
+        self.enabled = value & 0x02 != 0;
+        self.cycle_mode = value & 0x04 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = VRC_IRQ_PRESCALER;
+        }
+    }
+
+    fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enabled_after_ack;
+    }
+
+    fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if !self.cycle_mode {
+            self.prescaler -= 3;
+            if self.prescaler > 0 {
+                return;
+            }
+            self.prescaler += VRC_IRQ_PRESCALER;
+        }
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+impl Snapshot for VrcIrq {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.latch);
+        w.u8(self.counter);
+        w.u16(self.prescaler as u16);
+        w.bool(self.enabled);
+        w.bool(self.enabled_after_ack);
+        w.bool(self.cycle_mode);
+        w.bool(self.pending);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.latch = r.u8()?;
+        self.counter = r.u8()?;
+        self.prescaler = (r.u16()? as i16).clamp(1, VRC_IRQ_PRESCALER);
+        self.enabled = r.bool()?;
+        self.enabled_after_ack = r.bool()?;
+        self.cycle_mode = r.bool()?;
+        self.pending = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(mapper: u8, prg_rom_banks: u8, chr_rom_banks: u8) -> INesHeader {
+        INesHeader {
+            prg_rom_banks,
+            chr_rom_banks,
+            mapper,
+            mirroring: Mirroring::Vertical,
+            has_battery: false,
+            has_trainer: false,
+            prg_ram_size: 0x2000,
+            prg_nvram_size: 0,
+            region: crate::cartridge::Region::Ntsc,
+        }
+    }
+
+    /// ROM of `len` bytes where every `bank_size` bank is filled with its number
+    fn numbered_banks(len: usize, bank_size: usize) -> Vec<u8> {
+        (0..len).map(|i| (i / bank_size) as u8).collect()
+    }
+
+    fn build(mapper: u8, prg: &[u8], chr: &[u8]) -> Box<dyn Mapper> {
+        let header = header(mapper, (prg.len() / 0x4000) as u8, (chr.len() / 0x2000) as u8);
+        create(&header, prg.len(), chr.len()).unwrap()
+    }
+
+    #[test]
+    fn test_unsupported_mapper() {
+        assert!(matches!(
+            create(&header(4, 2, 1), 0x8000, 0x2000),
+            Err(EmulatorError::UnsupportedMapper(4))
+        ));
+    }
+
+    #[test]
+    fn test_color_dreams_banks() {
+        let prg = numbered_banks(0x20000, 0x8000);
+        let chr = numbered_banks(0x20000, 0x2000);
+        let mut mapper = build(11, &prg, &chr);
+
+        mapper.write_prg(0xC123, 0xA3);
+        assert_eq!(mapper.read_prg(&prg, 0x8000), 3);
+        assert_eq!(mapper.read_prg(&prg, 0xFFFF), 3);
+        assert_eq!(mapper.read_chr(&chr, 0x1FFF), 10);
+        assert_eq!(mapper.chr_bank(), 10);
+
+        // 16 CHR banks need all four upper bits, a smaller ROM wraps
+        mapper.write_prg(0x8000, 0xF0);
+        assert_eq!(mapper.read_chr(&chr, 0x0000), 15);
+        let small_chr = numbered_banks(0x4000, 0x2000);
+        assert_eq!(mapper.read_chr(&small_chr, 0x0000), 1);
+    }
+
+    #[test]
+    fn test_gxrom_masks_to_the_rom_size() {
+        // 64KB of PRG is two banks, 16KB of CHR two more
+        let prg = numbered_banks(0x10000, 0x8000);
+        let chr = numbered_banks(0x4000, 0x2000);
+        let mut mapper = build(66, &prg, &chr);
+
+        mapper.write_prg(0x8000, 0x33);
+        assert_eq!(mapper.read_prg(&prg, 0x8000), 1);
+        assert_eq!(mapper.read_prg(&prg, 0xFFFF), 1);
+        assert_eq!(mapper.read_chr(&chr, 0x0000), 1);
+        assert_eq!((mapper.prg_bank(), mapper.chr_bank()), (1, 1));
+
+        mapper.write_prg(0x8000, 0x22);
+        assert_eq!(mapper.read_prg(&prg, 0x8000), 0);
+        assert_eq!((mapper.prg_bank(), mapper.chr_bank()), (0, 0));
+    }
+
+    #[test]
+    fn test_bank_mask() {
+        assert_eq!(Cartridge::bank_mask(0x10000, 0x8000), 1);
+        assert_eq!(Cartridge::bank_mask(0x20000, 0x8000), 3);
+        // A partial ROM masks to the power of two above it
+        assert_eq!(Cartridge::bank_mask(0x6000, 0x2000), 3);
+        assert_eq!(Cartridge::bank_mask(0, 0x2000), 0);
+    }
+
+    #[test]
+    fn test_mapper87_swaps_bank_bits() {
+        let prg = numbered_banks(0x8000, 0x4000);
+        let chr = numbered_banks(0x8000, 0x2000);
+        let mut mapper = build(87, &prg, &chr);
+
+        for (value, bank) in [(0x00, 0), (0x01, 2), (0x02, 1), (0xFF, 3)] {
+            mapper.write_prg(0x6000, value);
+            assert_eq!(mapper.read_chr(&chr, 0x0010), bank, "value {:#04X}", value);
+        }
+
+        // Writes to ROM space don't reach the register, and PRG is fixed
+        mapper.write_prg(0x8000, 0x00);
+        assert_eq!(mapper.chr_bank(), 3);
+        assert_eq!(mapper.read_prg(&prg, 0x8000), 0);
+        assert_eq!(mapper.read_prg(&prg, 0xC000), 1);
+    }
+
+    #[test]
+    fn test_bnrom_oversize_prg_and_chr_ram() {
+        // 512KB is beyond the two bits of the original board
+        let prg = numbered_banks(0x80000, 0x8000);
+        let mut chr = vec![0; 0x2000];
+        let mut mapper = build(34, &prg, &[]);
+
+        mapper.write_prg(0x8000, 13);
+        assert_eq!(mapper.read_prg(&prg, 0x9234), 13);
+        mapper.write_prg(0x8000, 17);
+        assert_eq!(mapper.read_prg(&prg, 0x9234), 1);
+
+        mapper.write_chr(&mut chr, 0x1234, 0x5A);
+        assert_eq!(mapper.read_chr(&chr, 0x1234), 0x5A);
+    }
+
+    #[test]
+    fn test_nina001_registers() {
+        let prg = numbered_banks(0x10000, 0x8000);
+        let chr = numbered_banks(0x10000, 0x1000);
+        let mut mapper = build(34, &prg, &chr);
+
+        mapper.write_prg(0x6000, 0x42);
+        mapper.write_prg(0x7FFD, 0x01);
+        mapper.write_prg(0x7FFE, 0x05);
+        mapper.write_prg(0x7FFF, 0x0C);
+        assert_eq!(mapper.read_prg(&prg, 0x8000), 1);
+        assert_eq!(mapper.read_chr(&chr, 0x0FFF), 5);
+        assert_eq!(mapper.read_chr(&chr, 0x1000), 12);
+    }
+
+    /// ROM of `len` bytes with no two nearby bytes alike
+    fn scrambled(len: usize, seed: usize) -> Vec<u8> {
+        (0..len).map(|i| ((i * 7 + seed) ^ (i >> 8) ^ (i >> 13)) as u8).collect()
+    }
+
+    /// Check every window's reported offset against what reads return
+    fn assert_bank_state_matches_reads(mapper: &dyn Mapper, prg: &[u8], chr: &[u8]) {
+        let state = mapper.bank_state(prg.len(), chr.len());
+        assert_eq!(state.prg.len(), 4);
+        assert_eq!(state.chr.len(), 8);
+        for window in &state.prg {
+            let rom_offset = window.rom_offset.unwrap();
+            assert_eq!(window.bank_index, Some(rom_offset / 0x2000));
+            for delta in [0, 1, 0x0ABC, window.size - 1] {
+                let addr = window.window_start + delta;
+                assert_eq!(mapper.read_prg(prg, addr), prg[rom_offset + delta as usize], "PRG ${:04X}", addr);
+            }
+        }
+        for window in &state.chr {
+            let rom_offset = window.rom_offset.unwrap();
+            for delta in [0, 0x123, window.size - 1] {
+                let addr = window.window_start + delta;
+                assert_eq!(mapper.read_chr(chr, addr), chr[rom_offset + delta as usize], "CHR ${:04X}", addr);
+            }
+        }
+    }
+
+    /// Mapper number, PRG size, CHR size and register writes
+    type Case = (u8, usize, usize, &'static [(u16, u8)]);
+
+    #[test]
+    fn test_bank_state_matches_reads() {
+        let cases: [Case; 9] = [
+            (0, 0x4000, 0x2000, &[]),
+            (0, 0x8000, 0x2000, &[]),
+            (11, 0x20000, 0x20000, &[(0x8000, 0x72)]),
+            (34, 0x20000, 0, &[(0x8000, 0x03)]),
+            (34, 0x10000, 0x10000, &[(0x7FFD, 1), (0x7FFE, 9), (0x7FFF, 4)]),
+            (66, 0x20000, 0x8000, &[(0x8000, 0x21)]),
+            (87, 0x8000, 0x8000, &[(0x6000, 0x01)]),
+            (24, 0x40000, 0x40000, &[(0x8000, 0x05), (0xC000, 0x11), (0xD002, 0x33), (0xE003, 0xC0)]),
+            (26, 0x20000, 0, &[(0x8003, 0x02), (0xC002, 0x0B), (0xE001, 0x06)]),
+        ];
+        for (number, prg_len, chr_len, writes) in cases {
+            let prg = scrambled(prg_len, 0);
+            let chr = if chr_len == 0 { scrambled(0x2000, 5) } else { scrambled(chr_len, 3) };
+            let header = header(number, (prg_len / 0x4000) as u8, (chr_len / 0x2000) as u8);
+            let mut mapper = create(&header, prg_len, chr_len).unwrap();
+            assert_bank_state_matches_reads(mapper.as_ref(), &prg, &chr);
+            for &(addr, value) in writes {
+                mapper.write_prg(addr, value);
+            }
+            assert_bank_state_matches_reads(mapper.as_ref(), &prg, &chr);
+        }
+    }
+
+    #[test]
+    fn test_bank_state_reports_switched_banks() {
+        let prg = numbered_banks(0x20000, 0x8000);
+        let chr = numbered_banks(0x10000, 0x1000);
+        let mut mapper = build(34, &prg[..0x10000], &chr);
+        mapper.write_prg(0x7FFD, 1);
+        mapper.write_prg(0x7FFF, 6);
+
+        let state = mapper.bank_state(0x10000, chr.len());
+        let prg_banks: Vec<_> = state.prg.iter().map(|window| window.bank_index).collect();
+        assert_eq!(prg_banks, [Some(4), Some(5), Some(6), Some(7)]);
+        let chr_banks: Vec<_> = state.chr.iter().map(|window| window.bank_index.unwrap()).collect();
+        assert_eq!(chr_banks, [0, 1, 2, 3, 24, 25, 26, 27]);
+        assert_eq!(state.chr[4].rom_offset, Some(6 * 0x1000));
+        assert_eq!(state.mirroring, Mirroring::Vertical);
+        assert!(!state.irq_pending);
+        assert_eq!(state.irq_counter, None);
+    }
+
+    #[test]
+    fn test_vrc6_banks_and_mirroring() {
+        let prg = numbered_banks(0x40000, 0x2000);
+        let chr = numbered_banks(0x40000, 0x0400);
+        let mut mapper = build(24, &prg, &chr);
+
+        mapper.write_prg(0x8000, 3);
+        mapper.write_prg(0xC000, 9);
+        assert_eq!(mapper.read_prg(&prg, 0x8000), 6);
+        assert_eq!(mapper.read_prg(&prg, 0xBFFF), 7);
+        assert_eq!(mapper.read_prg(&prg, 0xC000), 9);
+        assert_eq!(mapper.read_prg(&prg, 0xE000), 31);
+        assert_eq!(mapper.prg_bank(), 0x0903);
+
+        // Registers repeat through their page
+        mapper.write_prg(0xD7F0, 0x10);
+        mapper.write_prg(0xE003, 0xFF);
+        assert_eq!(mapper.read_chr(&chr, 0x0000), 0x10);
+        assert_eq!(mapper.read_chr(&chr, 0x1FFF), 0xFF);
+
+        for (value, mirroring) in [
+            (0x00, Mirroring::Vertical),
+            (0x04, Mirroring::Horizontal),
+            (0x08, Mirroring::SingleScreenLower),
+            (0x2C, Mirroring::SingleScreenUpper),
+        ] {
+            mapper.write_prg(0xB003, value);
+            assert_eq!(mapper.mirroring(), mirroring, "value {:#04X}", value);
+        }
+    }
+
+    #[test]
+    fn test_mapper26_swaps_a0_and_a1() {
+        let prg = numbered_banks(0x20000, 0x2000);
+        let chr = numbered_banks(0x20000, 0x0400);
+        let mut mapper = build(26, &prg, &chr);
+
+        mapper.write_prg(0xD001, 5);
+        mapper.write_prg(0xD002, 6);
+        assert_eq!(mapper.read_chr(&chr, 0x0400), 6);
+        assert_eq!(mapper.read_chr(&chr, 0x0800), 5);
+
+        // $B003 is the same either way round
+        mapper.write_prg(0xB003, 0x04);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        assert_eq!(expansion_audio(&header(26, 8, 16)).unwrap().mix_level(), 61.0 / 15.0);
+        assert!(expansion_audio(&header(66, 8, 16)).is_none());
+    }
+
+    #[test]
+    fn test_vrc_irq() {
+        let mut mapper = build(24, &[0; 0x8000], &[0; 0x2000]);
+
+        // Cycle mode: counts from the latch to $FF, then raises IRQ and reloads
+        mapper.write_prg(0xF000, 0xFD);
+        mapper.write_prg(0xF001, 0x06);
+        for counter in [0xFE, 0xFF] {
+            mapper.clock_cpu();
+            assert_eq!(mapper.irq_counter(), Some(counter));
+        }
+        assert!(!mapper.irq_pending());
+        mapper.clock_cpu();
+        assert!(mapper.irq_pending());
+        assert_eq!(mapper.irq_counter(), Some(0xFD));
+
+        // Acknowledging takes the enable from bit 0 of the control
+        mapper.write_prg(0xF002, 0);
+        assert!(!mapper.irq_pending());
+        for _ in 0..1000 {
+            mapper.clock_cpu();
+        }
+        assert!(!mapper.irq_pending());
+
+        // Scanline mode: one step per 113⅔ CPU cycles
+        mapper.write_prg(0xF000, 0xFF);
+        mapper.write_prg(0xF001, 0x03);
+        for _ in 0..113 {
+            mapper.clock_cpu();
+        }
+        assert!(!mapper.irq_pending());
+        mapper.clock_cpu();
+        assert!(mapper.irq_pending());
+        mapper.write_prg(0xF002, 0);
+        assert!(!mapper.irq_pending());
+        let cycles = (1..200).find(|_| {
+            mapper.clock_cpu();
+            mapper.irq_pending()
+        });
+        assert_eq!(cycles, Some(114));
+    }
+
+    #[test]
+    fn test_nrom_oversize_prg_maps_first_32kb() {
+        let prg = numbered_banks(0x10000, 0x4000);
+        let mapper = build(0, &prg, &[0; 0x2000]);
+        assert_eq!(mapper.read_prg(&prg, 0x8000), 0);
+        assert_eq!(mapper.read_prg(&prg, 0xFFFF), 1);
+        assert_eq!(mapper.read_prg(&prg, 0x6000), 0xFF);
+    }
+}
@@ -1,25 +1,100 @@
-/// NES Memory System
-/// 
-/// The NES has a 16-bit address space with the following memory map:
-/// - $0000-$07FF: 2KB internal RAM
-/// - $0800-$1FFF: Mirrors of $0000-$07FF (repeats 3 times)
-/// - $2000-$2007: PPU registers
-/// - $2008-$3FFF: Mirrors of $2000-$2007 (repeats ~1024 times)
-/// - $4000-$4017: APU and I/O registers
-/// - $4018-$401F: APU and I/O functionality that is normally disabled
-/// - $4020-$FFFF: Cartridge space (PRG-ROM, PRG-RAM, and mapper registers)
-///
-/// For Phase 2, we'll implement basic RAM and stub out PPU/APU registers.
-/// Cartridge memory will be handled by the Cartridge module.
+//! NES Memory System
+//! 
+//! The NES has a 16-bit address space with the following memory map:
+//! - $0000-$07FF: 2KB internal RAM
+//! - $0800-$1FFF: Mirrors of $0000-$07FF (repeats 3 times)
+//! - $2000-$2007: PPU registers
+//! - $2008-$3FFF: Mirrors of $2000-$2007 (repeats ~1024 times)
+//! - $4000-$4017: APU and I/O registers
+//! - $4018-$401F: APU and I/O functionality that is normally disabled
+//! - $4020-$FFFF: Cartridge space (PRG-ROM, PRG-RAM, and mapper registers)
+//!
+//! For Phase 2, we'll implement basic RAM and stub out PPU/APU registers.
+//! Cartridge memory will be handled by the Cartridge module.
+//!
+//! Bank switches are logged as `debug` events under the `emu_nes::mapper`
+//! target, with `mapper`, `bank`, `value` and `addr` fields.
 
+use bitflags::bitflags;
 use crate::apu::Apu;
 use crate::cpu::CpuMemory;
-use crate::cartridge::Cartridge;
-use crate::ppu::Ppu;
-use emu_core::{Controller, MemoryBus, MemoryObserver, EmulatorContext};
-use tracing::trace;
+use crate::cartridge::{BankState, Cartridge, Region};
+use crate::controller::{Controller, ControllerPort};
+use crate::event_log::{EmuEvent, EmuEventKind, EventLog};
+use crate::ppu::{dots_for_cycles, PowerUpState, Ppu, PpuMask};
+use crate::profile::ComponentTimes;
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+use crate::state::Bytes;
+use emu_core::{EmulatorContext, EmulatorError, MemoryBus, MemoryObserver, Result};
+use std::fmt;
+use std::time::Instant;
+use tracing::debug;
+
+bitflags! {
+    /// Devices that can hold the CPU's IRQ line
+    ///
+    /// Each device owns its flag and has its own way of acknowledging it;
+    /// this is only a read-only view of who is asserting right now.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct IrqSource: u8 {
+        /// APU frame counter ($4015 bit 6)
+        const FRAME  = 0b001;
+        /// DMC end of sample ($4015 bit 7)
+        const DMC    = 0b010;
+        /// Cartridge mapper, e.g. a scanline counter
+        const MAPPER = 0b100;
+    }
+}
+
+bitflags! {
+    /// What the instruction in progress did on the bus that the homebrew
+    /// lint looks at (see [`crate::lint`])
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub(crate) struct BusNotes: u8 {
+        /// Set $2000 or $2001, or wrote $2005 or $2006, while the PPU warmed up
+        const WARMUP_WRITE = 0b0000_0001;
+        /// Wrote $2007 while the PPU was drawing
+        const VRAM_WHILE_RENDERING = 0b0000_0010;
+        /// Wrote OAM, through $2004 or DMA
+        const OAM_WRITE = 0b0000_0100;
+        /// Started OAM DMA while the PPU was drawing
+        const LATE_OAM_DMA = 0b0000_1000;
+        /// Turned sprites on through $2001
+        const SPRITES_ON = 0b0001_0000;
+        /// Wrote the APU frame counter, $4017
+        const FRAME_COUNTER = 0b0010_0000;
+    }
+}
+
+/// What handles a CPU write in $4000-$401F
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoWriteOwner {
+    /// The sound registers and frame counter: $4000-$4013 (the unused
+    /// $4009 and $400D included), $4015 and $4017
+    Apu,
+    /// $4014: copy a page of CPU memory to OAM
+    OamDma,
+    /// $4016: the strobe for both controllers ($4017 reads controller 2
+    /// but writes go to the APU frame counter)
+    ControllerStrobe,
+}
+
+/// Who a write to `addr` goes to, or `None` for the CPU test mode
+/// registers ($4018-$401F), which do nothing on a retail console
+pub fn io_write_owner(addr: u16) -> Option<IoWriteOwner> {
+    match addr {
+        0x4014 => Some(IoWriteOwner::OamDma),
+        0x4016 => Some(IoWriteOwner::ControllerStrobe),
+        0x4000..=0x4017 => Some(IoWriteOwner::Apu),
+        _ => None,
+    }
+}
 
 /// NES Memory system
+///
+/// A clone has no memory observers: they're attached from outside and may
+/// hold state of their own, so whoever attached them decides whether the
+/// copy gets any. Per-instruction profiling is left off too.
 pub struct NesMemory {
     /// 2KB of internal RAM ($0000-$07FF, mirrored to $1FFF)
     ram: [u8; 0x0800],
@@ -36,6 +111,9 @@ pub struct NesMemory {
     /// Controller 2
     controller2: Controller,
     
+    /// $4016 output latch: the strobe, and the expansion port's bits
+    controller_port: ControllerPort,
+    
     /// Cartridge (optional)
     cartridge: Option<Cartridge>,
     
@@ -44,6 +122,34 @@ pub struct NesMemory {
     
     /// Current emulator context
     context: EmulatorContext,
+    
+    /// Set when a $2002 read returns with VBLANK set (for hang detection)
+    vblank_seen: bool,
+    
+    /// Skip mapper logging (see [`NesSystem::new_quiet`](crate::NesSystem::new_quiet))
+    quiet: bool,
+    
+    /// Bus accesses made by the instruction in progress (one per CPU cycle)
+    bus_cycles: u64,
+    
+    /// Cycles of the instruction in progress already clocked into the PPU and APU
+    clocked_cycles: u64,
+    
+    /// Set between [`begin_instruction`](Self::begin_instruction) and
+    /// [`end_instruction`](Self::end_instruction)
+    in_instruction: bool,
+    
+    /// A $4014 write copied a page into OAM and the CPU still owes the stall
+    oam_dma_pending: bool,
+    
+    /// Bus accesses of the instruction in progress, for the lint
+    bus_notes: BusNotes,
+    
+    /// Per-component time while the instruction in progress is being profiled
+    timing: Option<ComponentTimes>,
+    
+    /// Timeline of notable events, while switched on
+    event_log: Option<EventLog>,
 }
 
 impl NesMemory {
@@ -52,9 +158,10 @@ impl NesMemory {
         Self {
             ram: [0; 0x0800],
             ppu: Ppu::new(),
-            apu: Apu::new(),
+            apu: Apu::default(),
             controller1: Controller::new(),
             controller2: Controller::new(),
+            controller_port: ControllerPort::new(),
             cartridge: None,
             observers: Vec::new(),
             context: EmulatorContext {
@@ -62,8 +169,194 @@ impl NesMemory {
                 cycle: 0,
                 pc: 0,
                 last_input: 0,
+                last_input_p2: 0,
             },
+            vblank_seen: false,
+            quiet: false,
+            bus_cycles: 0,
+            clocked_cycles: 0,
+            in_instruction: false,
+            oam_dma_pending: false,
+            bus_notes: BusNotes::empty(),
+            timing: None,
+            event_log: None,
+        }
+    }
+    
+    /// Clock the cartridge and APU once and the PPU three times (one CPU
+    /// cycle)
+    ///
+    /// A PAL PPU runs 3.2 dots a CPU cycle, so there it's clocked three
+    /// times and then a fourth every fifth cycle.
+    pub fn clock_cycle(&mut self) {
+        if let Some(cart) = &mut self.cartridge {
+            cart.clock_cpu();
+        }
+        if self.timing.is_some() {
+            let started = Instant::now();
+            self.clock_apu();
+            let apu_done = Instant::now();
+            self.ppu.tick_dots(self.ppu_dots_due());
+            if let Some(times) = &mut self.timing {
+                times.apu += apu_done - started;
+                times.ppu += apu_done.elapsed();
+            }
+            return;
+        }
+        self.clock_apu();
+        self.ppu.tick_dots(self.ppu_dots_due());
+    }
+    
+    /// PPU dots the CPU cycle the APU was just clocked for takes
+    fn ppu_dots_due(&self) -> u16 {
+        let region = self.ppu.region();
+        let cycles = self.apu.cycles();
+        (dots_for_cycles(region, cycles) - dots_for_cycles(region, cycles - 1)) as u16
+    }
+    
+    /// Clock the APU, with DMC samples fetched from PRG-ROM as the
+    /// cartridge currently maps it (samples always live at $8000-$FFFF),
+    /// and the cartridge's sound chip if it has one
+    fn clock_apu(&mut self) {
+        self.apu.clock_expansion(self.cartridge.as_mut().and_then(Cartridge::expansion_audio_mut));
+        let cartridge = &self.cartridge;
+        self.apu.clock_with(&mut |addr| cartridge.as_ref().map_or(0xFF, |cart| cart.read_prg(addr)));
+    }
+    
+    /// Start timing the PPU, APU and observers separately
+    pub(crate) fn start_timing(&mut self) {
+        self.timing = Some(ComponentTimes::default());
+    }
+    
+    /// Stop timing and return what was measured since [`Self::start_timing`]
+    pub(crate) fn take_timing(&mut self) -> ComponentTimes {
+        self.timing.take().unwrap_or_default()
+    }
+    
+    /// Call `notify` for every observer, timing them while profiling
+    fn notify_observers(&mut self, mut notify: impl FnMut(&mut dyn MemoryObserver, &EmulatorContext)) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let started = self.timing.is_some().then(Instant::now);
+        let context = self.context;
+        for observer in &mut self.observers {
+            notify(observer.as_mut(), &context);
+        }
+        if let (Some(times), Some(started)) = (&mut self.timing, started) {
+            times.observers += started.elapsed();
+        }
+    }
+    
+    /// Start or stop logging events; stopping drops any not yet taken
+    pub(crate) fn set_event_log_enabled(&mut self, enabled: bool) {
+        if enabled != self.event_log.is_some() {
+            self.event_log = enabled.then(EventLog::default);
+        }
+    }
+    
+    pub(crate) fn event_log_mut(&mut self) -> Option<&mut EventLog> {
+        self.event_log.as_mut()
+    }
+    
+    /// Log an event at the PPU's current position, if logging is on
+    pub(crate) fn log_event(&mut self, kind: EmuEventKind) {
+        if let Some(log) = &mut self.event_log {
+            log.push(EmuEvent {
+                frame: self.context.frame,
+                scanline: self.ppu.scanline(),
+                cycle: self.ppu.cycle(),
+                kind,
+            });
+        }
+    }
+    
+    /// Start counting bus accesses for an instruction
+    ///
+    /// Until [`end_instruction`](Self::end_instruction), every PPU or APU
+    /// register access first clocks both up to the cycle it happens on, so
+    /// e.g. a $2002 read sees the PPU mid-instruction rather than where it
+    /// was when the instruction started.
+    pub fn begin_instruction(&mut self) {
+        self.bus_cycles = 0;
+        self.clocked_cycles = 0;
+        self.in_instruction = true;
+        self.bus_notes = BusNotes::empty();
+    }
+    
+    /// Stop counting and return how many cycles were already clocked
+    pub fn end_instruction(&mut self) -> u64 {
+        self.in_instruction = false;
+        self.clocked_cycles
+    }
+    
+    /// Clock the PPU and APU up to the access in progress
+    ///
+    /// The access's own cycle is included, so the register sees the state
+    /// at the end of it. The CPU doesn't make dummy accesses, so this
+    /// lands early on instructions with internal cycles before the access;
+    /// plain absolute loads and stores are exact.
+    fn catch_up(&mut self) {
+        if !self.in_instruction {
+            return;
+        }
+        while self.clocked_cycles < self.bus_cycles {
+            self.clock_cycle();
+            self.clocked_cycles += 1;
+        }
+    }
+    
+    /// Whether a $4014 write happened since the last call
+    ///
+    /// The copy itself is done at the write; the caller halts the CPU for
+    /// the 513-514 cycles it takes on hardware.
+    pub fn take_oam_dma(&mut self) -> bool {
+        std::mem::take(&mut self.oam_dma_pending)
+    }
+    
+    /// What the instruction just run did that the lint looks at
+    pub(crate) fn take_bus_notes(&mut self) -> BusNotes {
+        std::mem::take(&mut self.bus_notes)
+    }
+    
+    /// Copy CPU page `page` ($XX00-$XXFF) into OAM, one OAMDATA write per byte
+    fn oam_dma(&mut self, page: u8) {
+        let base = (page as u16) << 8;
+        for offset in 0..=0xFF {
+            let value = self.read_internal(base | offset);
+            self.ppu.oam_dma_write(value);
         }
+        self.oam_dma_pending = true;
+    }
+    
+    /// Enable or disable mapper and PPU logging
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+        self.ppu.set_quiet(quiet);
+    }
+    
+    /// Get the 2KB of internal RAM
+    pub fn ram(&self) -> &[u8; 0x0800] {
+        &self.ram
+    }
+    
+    pub(crate) fn ram_mut(&mut self) -> &mut [u8; 0x0800] {
+        &mut self.ram
+    }
+    
+    /// Get controller 1 without needing mutable access
+    pub fn controller1_ref(&self) -> &Controller {
+        &self.controller1
+    }
+    
+    /// Get controller 2 without needing mutable access
+    pub fn controller2_ref(&self) -> &Controller {
+        &self.controller2
+    }
+    
+    /// Check whether the CPU has read $2002 with VBLANK set since the last call
+    pub fn take_vblank_seen(&mut self) -> bool {
+        std::mem::take(&mut self.vblank_seen)
     }
     
     /// Get PPU reference
@@ -96,42 +389,104 @@ impl NesMemory {
         &mut self.controller2
     }
     
+    /// The $4016 latch, where expansion port devices attach
+    pub fn controller_port(&mut self) -> &mut ControllerPort {
+        &mut self.controller_port
+    }
+    
     /// Load a cartridge
+    ///
+    /// The APU starts over with the timings for the cartridge's region.
     pub fn load_cartridge(&mut self, cartridge: Cartridge) {
-        // Load CHR-ROM into PPU
-        // For mappers with CHR banking (like mapper 66), only load the first bank
-        if cartridge.header().mapper == 66 {
-            // Load only first 8KB bank for mapper 66
-            let chr_bank_0 = if cartridge.chr_rom().len() >= 0x2000 {
-                cartridge.chr_rom()[0..0x2000].to_vec()
-            } else {
-                cartridge.chr_rom().to_vec()
-            };
-            self.ppu.load_chr_rom(chr_bank_0);
-        } else {
-            // Mapper 0: load all CHR-ROM (max 8KB)
-            self.ppu.load_chr_rom(cartridge.chr_rom().to_vec());
-        }
+        self.apu = Apu::new(cartridge.header().region);
+        self.ppu.set_region(cartridge.header().region);
+        self.ppu.set_mirroring(cartridge.mirroring());
+        // The PPU keeps its own copy of whatever 8KB the mapper starts with
+        self.ppu.load_chr_rom(cartridge.mapped_chr());
         self.cartridge = Some(cartridge);
     }
     
+    /// Time the PPU and APU as `region`'s console does
+    ///
+    /// [`Self::load_cartridge`] picks the cartridge's region; this is for
+    /// running a game as the other one. The APU starts over; the rest is
+    /// only consistent after a [`Self::power_cycle`]. [`Region::Dual`] is NTSC.
+    pub fn set_region(&mut self, region: Region) {
+        self.apu.set_region(region);
+        self.ppu.set_region(region);
+    }
+    
+    /// TV system the console is timed for, [`Region::Ntsc`] or [`Region::Pal`]
+    pub fn region(&self) -> Region {
+        self.ppu.region()
+    }
+    
+    /// Switch the console off and on again
+    ///
+    /// RAM, the PPU, the APU and the cartridge's mapper all return to their
+    /// power-on state, with palette RAM as `state` says. The CPU is left to
+    /// the caller.
+    pub fn power_cycle(&mut self, state: PowerUpState) {
+        self.ram = [0; 0x0800];
+        self.ppu.power_up(state);
+        self.apu.reset();
+        if let Some(ref mut cart) = self.cartridge {
+            cart.power_up();
+            self.ppu.set_mirroring(cart.mirroring());
+            self.ppu.load_chr_rom(cart.mapped_chr());
+        }
+        self.vblank_seen = false;
+        self.oam_dma_pending = false;
+    }
+    
     /// Load PRG-ROM data directly (for testing, bypasses cartridge system)
     pub fn load_prg_rom(&mut self, data: Vec<u8>) {
-        // Create a fake cartridge for testing
-        let fake_cart = Cartridge {
-            prg_rom: data,
-            chr_rom: vec![0; 0x2000],
-            header: crate::cartridge::INesHeader {
-                prg_rom_banks: 1,
-                chr_rom_banks: 1,
-                mapper: 0,
-                mirroring: crate::cartridge::Mirroring::Horizontal,
-                has_battery: false,
-                has_trainer: false,
-            },
-            mapper_state: Default::default(),
-        };
-        self.cartridge = Some(fake_cart);
+        self.cartridge = Some(Cartridge::with_prg_rom(data));
+    }
+    
+    /// Get the CRC32 of the loaded cartridge, if any
+    pub fn rom_crc32(&self) -> Option<u32> {
+        self.cartridge.as_ref().map(Cartridge::crc32)
+    }
+    
+    /// Set the frame number observers see in the context
+    pub fn set_frame(&mut self, frame: u64) {
+        self.context.frame = frame;
+    }
+    
+    /// Get the loaded cartridge, if any
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+    
+    pub(crate) fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
+    
+    /// IRQ sources currently asserting
+    ///
+    /// IRQ is level-triggered, so the CPU keeps taking it (whenever the I
+    /// flag is clear) until every source here has been acknowledged.
+    pub fn irq_lines(&self) -> IrqSource {
+        let mut lines = self.apu.irq_lines();
+        lines.set(IrqSource::MAPPER, self.cartridge.as_ref().is_some_and(Cartridge::irq_pending));
+        lines
+    }
+    
+    /// Get the loaded cartridge's mapped banks, if any
+    pub fn bank_state(&self) -> Option<BankState> {
+        self.cartridge.as_ref().map(Cartridge::bank_state)
+    }
+    
+    /// Read without side effects, for debuggers
+    ///
+    /// The PPU, APU and controller registers read as open bus rather than
+    /// being touched.
+    pub fn peek(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x2000..=0x401F => Self::open_bus(addr),
+            _ => self.read_internal(addr),
+        }
     }
     
     /// Internal read without observer notification
@@ -145,45 +500,59 @@ impl NesMemory {
             
             // PPU registers (mirrored every 8 bytes)
             0x2000..=0x3FFF => {
-                self.ppu.read_register(addr)
-            }
-            
-            // APU and I/O registers
-            0x4000..=0x4017 => {
-                match addr {
-                    0x4016 => {
-                        // Controller 1
-                        // Return bit 0 = controller data, bits 1-4 = open bus, bits 5-7 = 0
-                        self.controller1.read() | 0x40
-                    }
-                    0x4017 => {
-                        // Controller 2
-                        self.controller2.read() | 0x40
-                    }
-                    0x4015 => {
-                        // APU status register
-                        self.apu.read_register(addr)
-                    }
-                    _ => {
-                        // Other APU registers (write-only)
-                        0
-                    }
+                let value = self.ppu.read_register(addr);
+                if addr & 0x07 == 2 && value & 0x80 != 0 {
+                    self.vblank_seen = true;
                 }
+                value
             }
             
-            // Cartridge space
-            0x4020..=0xFFFF => {
+            // APU channel registers (write-only)
+            0x4000..=0x4013 => Self::open_bus(addr),
+            
+            // OAMDMA (write-only)
+            0x4014 => Self::open_bus(addr),
+            
+            // APU status
+            0x4015 => self.apu.read_status(),
+            
+            // Controllers: bit 0 is the serial data, the upper bits are open bus
+            0x4016 => self.controller1.read() | (Self::open_bus(addr) & 0xE0),
+            0x4017 => self.controller2.read() | (Self::open_bus(addr) & 0xE0),
+            
+            // CPU test mode registers, disabled on retail consoles
+            0x4018..=0x401F => Self::open_bus(addr),
+            
+            // Cartridge expansion area (MMC5, FDS and Namco registers)
+            0x4020..=0x5FFF => self.cartridge.as_ref()
+                .and_then(|cart| cart.read_expansion(addr))
+                .unwrap_or_else(|| Self::open_bus(addr)),
+            
+            // PRG-RAM
+            0x6000..=0x7FFF => self.cartridge.as_ref()
+                .and_then(|cart| cart.read_prg_ram(addr))
+                .unwrap_or_else(|| Self::open_bus(addr)),
+            
+            // PRG-ROM
+            0x8000..=0xFFFF => {
                 if let Some(ref cart) = self.cartridge {
                     cart.read_prg(addr)
                 } else {
                     0xFF // No cartridge loaded
                 }
             }
-            
-            _ => 0xFF, // Open bus
         }
     }
     
+    /// Value read from an unmapped address
+    ///
+    /// There's no data bus latch yet, so this returns the address high byte:
+    /// the last value on the bus for an absolute-mode read, which is how
+    /// nearly all code reaches these registers.
+    fn open_bus(addr: u16) -> u8 {
+        (addr >> 8) as u8
+    }
+    
     /// Internal write without observer notification
     fn write_internal(&mut self, addr: u16, value: u8) {
         match addr {
@@ -195,48 +564,95 @@ impl NesMemory {
             
             // PPU registers (mirrored every 8 bytes)
             0x2000..=0x3FFF => {
+                // The registers that shape the frame go in the event log
+                if let 0 | 1 | 5 | 6 = addr & 0x07 {
+                    self.log_event(EmuEventKind::PpuWrite { addr: 0x2000 | (addr & 0x07), value });
+                    // Clearing $2000 and $2001 is what they hold after reset anyway
+                    if self.ppu.warming_up() && (value != 0 || addr & 0x07 >= 5) {
+                        self.bus_notes.insert(BusNotes::WARMUP_WRITE);
+                    }
+                }
+                match addr & 0x07 {
+                    1 if value & PpuMask::SHOW_SPRITES.bits() != 0 && !self.ppu.mask.contains(PpuMask::SHOW_SPRITES) => {
+                        self.bus_notes.insert(BusNotes::SPRITES_ON);
+                    }
+                    4 => self.bus_notes.insert(BusNotes::OAM_WRITE),
+                    7 if self.ppu.rendering_line() => self.bus_notes.insert(BusNotes::VRAM_WHILE_RENDERING),
+                    _ => {}
+                }
                 self.ppu.write_register(addr, value);
             }
             
             // APU and I/O registers
-            0x4000..=0x4017 => {
-                match addr {
-                    0x4016 => {
-                        // Controller strobe
-                        self.controller1.write(value);
-                        self.controller2.write(value);
+            0x4000..=0x401F => match io_write_owner(addr) {
+                Some(IoWriteOwner::Apu) => {
+                    if addr == 0x4015 {
+                        self.log_event(EmuEventKind::ApuStatus(value));
                     }
-                    0x4000..=0x4015 | 0x4017 => {
-                        // APU registers
-                        self.apu.write_register(addr, value);
+                    if addr == 0x4017 {
+                        self.bus_notes.insert(BusNotes::FRAME_COUNTER);
                     }
-                    _ => {}
+                    self.apu.write_register(addr, value);
                 }
-            }
+                Some(IoWriteOwner::OamDma) => {
+                    self.log_event(EmuEventKind::OamDma { page: value });
+                    self.bus_notes.insert(BusNotes::OAM_WRITE);
+                    if self.ppu.rendering_line() {
+                        self.bus_notes.insert(BusNotes::LATE_OAM_DMA);
+                    }
+                    self.oam_dma(value);
+                }
+                Some(IoWriteOwner::ControllerStrobe) => {
+                    // Only the strobe reaches the pads; on its falling edge
+                    // observers start seeing the newly latched buttons
+                    let strobe = self.controller_port.write(value);
+                    let latched = self.controller1.write(strobe);
+                    self.controller2.write(strobe);
+                    if latched {
+                        self.context.last_input = self.controller1.last_latched();
+                        self.context.last_input_p2 = self.controller2.last_latched();
+                    }
+                }
+                None => {}
+            },
             
             // Cartridge space - mapper registers
             0x4020..=0xFFFF => {
+                let mut switches = [None; 2];
                 if let Some(ref mut cart) = self.cartridge {
-                    let old_chr_bank = cart.mapper_state.chr_bank;
-                    let old_prg_bank = cart.mapper_state.prg_bank;
-                    cart.write_prg(addr, value);
+                    let old_chr_bank = cart.chr_bank();
+                    let old_prg_bank = cart.prg_bank();
+                    let old_mirroring = cart.mirroring();
+                    match addr {
+                        0x4020..=0x5FFF => cart.write_expansion(addr, value),
+                        0x6000..=0x7FFF => cart.write_prg_ram(addr, value),
+                        _ => cart.write_prg(addr, value),
+                    }
                     
-                    // For Mapper 66: Update PPU CHR bank if it changed
-                    if cart.header().mapper == 66 {
-                        if cart.mapper_state.chr_bank != old_chr_bank {
-                            let chr_bank = cart.mapper_state.chr_bank as usize;
-                            trace!("Mapper 66: CHR bank changed to {} (value=${:02X} at ${:04X})", chr_bank, value, addr);
-                            self.ppu.load_chr_bank(cart.chr_rom(), chr_bank);
+                    // Keep the PPU's copy of the pattern tables in step with the mapper
+                    let mapper = cart.header().mapper;
+                    if cart.chr_bank() != old_chr_bank {
+                        let chr_bank = cart.chr_bank();
+                        if !self.quiet {
+                            debug!(target: "emu_nes::mapper", mapper, bank = chr_bank, value, addr, "CHR bank switch");
                         }
-                        if cart.mapper_state.prg_bank != old_prg_bank {
-                            trace!("Mapper 66: PRG bank changed to {} (value=${:02X} at ${:04X})", cart.mapper_state.prg_bank, value, addr);
+                        self.ppu.load_chr_rom(cart.mapped_chr());
+                        switches[1] = Some(EmuEventKind::ChrBank(chr_bank));
+                    }
+                    if cart.prg_bank() != old_prg_bank {
+                        let prg_bank = cart.prg_bank();
+                        if !self.quiet {
+                            debug!(target: "emu_nes::mapper", mapper, bank = prg_bank, value, addr, "PRG bank switch");
                         }
+                        switches[0] = Some(EmuEventKind::PrgBank(prg_bank));
+                    }
+                    if cart.mirroring() != old_mirroring {
+                        self.ppu.set_mirroring(cart.mirroring());
                     }
                 }
-            }
-            
-            _ => {
-                // Ignore writes to invalid addresses
+                for kind in switches.into_iter().flatten() {
+                    self.log_event(kind);
+                }
             }
         }
     }
@@ -248,43 +664,126 @@ impl Default for NesMemory {
     }
 }
 
+impl Clone for NesMemory {
+    fn clone(&self) -> Self {
+        Self {
+            ram: self.ram,
+            ppu: self.ppu.clone(),
+            apu: self.apu.clone(),
+            controller1: self.controller1.clone(),
+            controller2: self.controller2.clone(),
+            controller_port: self.controller_port.clone(),
+            cartridge: self.cartridge.clone(),
+            observers: Vec::new(),
+            context: self.context,
+            vblank_seen: self.vblank_seen,
+            quiet: self.quiet,
+            bus_cycles: self.bus_cycles,
+            clocked_cycles: self.clocked_cycles,
+            in_instruction: self.in_instruction,
+            oam_dma_pending: self.oam_dma_pending,
+            bus_notes: self.bus_notes,
+            timing: None,
+            event_log: self.event_log.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for NesMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NesMemory")
+            .field("ram", &Bytes(&self.ram))
+            .field("ppu", &self.ppu)
+            .field("apu", &self.apu)
+            .field("controller1", &self.controller1)
+            .field("controller2", &self.controller2)
+            .field("controller_port", &self.controller_port)
+            .field("cartridge", &self.cartridge)
+            .field("observers", &self.observers.len())
+            .field("context", &self.context)
+            .field("bus_cycles", &self.bus_cycles)
+            .field("clocked_cycles", &self.clocked_cycles)
+            .field("in_instruction", &self.in_instruction)
+            .field("oam_dma_pending", &self.oam_dma_pending)
+            .finish_non_exhaustive()
+    }
+}
+
 impl CpuMemory for NesMemory {
     fn read(&mut self, addr: u16) -> u8 {
+        self.bus_cycles += 1;
+        if let 0x2000..=0x4017 = addr {
+            self.catch_up();
+        }
         let value = self.read_internal(addr);
         
         // Notify observers
-        let context = self.context;
-        for observer in &mut self.observers {
-            observer.on_read(addr, value, &context);
-        }
+        self.notify_observers(|observer, context| observer.on_read(addr as u32, value, context));
         
         value
     }
     
+    /// Bring the PPU up to the current cycle and take its NMI, if raised
+    ///
+    /// BRK's dummy read isn't modelled, so this sees the PPU one cycle
+    /// before the vector fetch rather than at it; an NMI raised on that last
+    /// cycle is delivered after the BRK instead of hijacking it.
+    fn poll_nmi(&mut self) -> bool {
+        self.catch_up();
+        let nmi = std::mem::take(&mut self.ppu.nmi_interrupt);
+        if nmi {
+            self.log_event(EmuEventKind::Nmi);
+        }
+        nmi
+    }
+    
     fn write(&mut self, addr: u16, value: u8) {
-        // Get old value for observers
-        let old_value = self.read_internal(addr);
+        self.bus_cycles += 1;
+        if let 0x2000..=0x4017 = addr {
+            self.catch_up();
+        }
+        
+        // Get old value for observers. Register reads have side effects
+        // (PPUDATA increments the VRAM address, $4016 shifts the controller),
+        // so registers report 0 instead of being read.
+        let old_value = match addr {
+            _ if self.observers.is_empty() => 0,
+            0x2000..=0x401F => 0,
+            _ => self.read_internal(addr),
+        };
         
         // Perform write
         self.write_internal(addr, value);
         
         // Notify observers
-        let context = self.context;
-        for observer in &mut self.observers {
-            observer.on_write(addr, old_value, value, &context);
+        self.notify_observers(|observer, context| observer.on_write(addr as u32, old_value, value, context));
+    }
+    
+    /// Pass the write on in cartridge space only
+    ///
+    /// Mapper registers see both writes: an `INC` on MMC1's serial port
+    /// clocks it twice. RAM can't tell them apart, and the console's own
+    /// registers get the single write.
+    fn rmw_dummy_write(&mut self, addr: u16, value: u8) {
+        if addr >= 0x4020 {
+            CpuMemory::write(self, addr, value);
         }
     }
 }
 
 impl MemoryBus for NesMemory {
-    fn read(&mut self, addr: u16) -> u8 {
-        // Use CpuMemory implementation
-        CpuMemory::read(self, addr)
+    fn read(&mut self, addr: u32) -> u8 {
+        // Use CpuMemory implementation (the CPU bus is 16 bits wide)
+        CpuMemory::read(self, addr as u16)
     }
     
-    fn write(&mut self, addr: u16, value: u8) {
-        // Use CpuMemory implementation
-        CpuMemory::write(self, addr, value)
+    fn write(&mut self, addr: u32, value: u8) {
+        // Use CpuMemory implementation (the CPU bus is 16 bits wide)
+        CpuMemory::write(self, addr as u16, value)
+    }
+    
+    fn address_space_size(&self) -> u64 {
+        0x10000
     }
     
     fn attach_observer(&mut self, observer: Box<dyn MemoryObserver>) {
@@ -304,9 +803,298 @@ impl MemoryBus for NesMemory {
     }
 }
 
+impl Snapshot for NesMemory {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.ram);
+        self.ppu.save(w);
+        self.apu.save(w);
+        self.controller1.save(w);
+        self.controller2.save(w);
+        w.bool(self.cartridge.is_some());
+        if let Some(ref cart) = self.cartridge {
+            cart.save(w);
+            // CHR-ROM is reloaded from the cartridge instead
+            w.bool(cart.has_chr_ram());
+            if cart.has_chr_ram() {
+                w.bytes(self.ppu.chr());
+            }
+        }
+    }
+    
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        r.bytes_into(&mut self.ram)?;
+        self.ppu.load(r)?;
+        self.apu.load(r)?;
+        self.controller1.load(r)?;
+        self.controller2.load(r)?;
+        match (r.bool()?, self.cartridge.as_mut()) {
+            (true, Some(cart)) => {
+                cart.load(r)?;
+                self.ppu.set_mirroring(cart.mirroring());
+                if r.bool()? != cart.has_chr_ram() {
+                    return Err(EmulatorError::InvalidSaveState("CHR-RAM presence mismatch".into()));
+                }
+                if cart.has_chr_ram() {
+                    r.bytes_into(self.ppu.chr_mut())?;
+                } else {
+                    self.ppu.load_chr_rom(cart.mapped_chr());
+                }
+            }
+            (false, None) => {}
+            _ => return Err(EmulatorError::InvalidSaveState("cartridge presence mismatch".into())),
+        }
+        self.vblank_seen = false;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::Layer;
+    
+    /// An event's target and fields, formatted with `{:?}`
+    type CapturedEvent = (String, Vec<(String, String)>);
+    
+    /// Layer that records every event it sees
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<CapturedEvent>>>);
+    
+    impl<S: tracing::Subscriber> Layer<S> for Capture {
+        fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+            struct Fields(Vec<(String, String)>);
+            impl Visit for Fields {
+                fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                    self.0.push((field.name().to_string(), format!("{:?}", value)));
+                }
+            }
+            let mut fields = Fields(Vec::new());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push((event.metadata().target().to_string(), fields.0));
+        }
+    }
+    
+    /// Observer that ignores everything
+    struct NullObserver;
+    
+    impl MemoryObserver for NullObserver {
+        fn on_read(&mut self, _address: u32, _value: u8, _context: &EmulatorContext) {}
+        fn on_write(&mut self, _address: u32, _old_value: u8, _new_value: u8, _context: &EmulatorContext) {}
+    }
+    
+    /// Mapper 66 memory with 2 PRG banks and 4 CHR banks
+    fn mapper66_memory() -> NesMemory {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 4, 4, 0x20, 0x40, 0, 0, 0, 0, 0, 0, 0, 0];
+        rom.resize(16 + 0x10000 + 0x8000, 0);
+        let mut mem = NesMemory::new();
+        mem.load_cartridge(Cartridge::from_bytes(&rom).unwrap());
+        mem
+    }
+    
+    /// Run `f` with a capturing subscriber and return the events it logged
+    fn capture_events(f: impl FnOnce()) -> Vec<CapturedEvent> {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+        tracing::subscriber::with_default(subscriber, f);
+        let events = capture.0.lock().unwrap().clone();
+        events
+    }
+    
+    #[test]
+    fn test_register_writes_have_no_read_side_effects() {
+        let mut mem = NesMemory::new();
+        mem.attach_observer(Box::new(NullObserver));
+        
+        // PPUDATA writes land on consecutive addresses
+        CpuMemory::write(&mut mem, 0x2006, 0x21);
+        CpuMemory::write(&mut mem, 0x2006, 0x00);
+        CpuMemory::write(&mut mem, 0x2007, 0x11);
+        CpuMemory::write(&mut mem, 0x2007, 0x22);
+        assert_eq!(mem.ppu().read_nametable_direct(0x2100), 0x11);
+        assert_eq!(mem.ppu().read_nametable_direct(0x2101), 0x22);
+        
+        // Writing $4016 doesn't clock the controller's shift register
+        mem.controller1().state().press(emu_core::Button::A);
+        mem.controller2().state().press(emu_core::Button::B);
+        CpuMemory::write(&mut mem, 0x4016, 0x01);
+        assert_eq!(mem.context().last_input, 0);
+        CpuMemory::write(&mut mem, 0x4016, 0x00);
+        assert_eq!(CpuMemory::read(&mut mem, 0x4016) & 1, 1);
+        
+        // The falling edge hands the latched buttons to observers
+        let context = mem.context();
+        assert_eq!((context.last_input, context.last_input_p2), (0x01, 0x02));
+    }
+    
+    #[test]
+    fn test_expansion_writes_dont_relatch_the_pads() {
+        let mut mem = NesMemory::new();
+        let seen = std::sync::Arc::new(std::sync::atomic::AtomicU8::new(0xFF));
+        let sink = seen.clone();
+        mem.controller_port().set_expansion_callback(Some(Box::new(move |bits| {
+            sink.store(bits, std::sync::atomic::Ordering::Relaxed);
+        })));
+        mem.controller1().state().press(emu_core::Button::A | emu_core::Button::B);
+        CpuMemory::write(&mut mem, 0x4016, 0x01);
+        CpuMemory::write(&mut mem, 0x4016, 0x00);
+        assert_eq!(CpuMemory::read(&mut mem, 0x4016) & 1, 1);
+        
+        // $04 and $06 keep bit 0 clear, so the shift register keeps its old latch
+        mem.controller1().state().release(emu_core::Button::B);
+        CpuMemory::write(&mut mem, 0x4016, 0x04);
+        assert_eq!(seen.load(std::sync::atomic::Ordering::Relaxed), 0b10);
+        assert_eq!(CpuMemory::read(&mut mem, 0x4016) & 1, 1);
+        CpuMemory::write(&mut mem, 0x4016, 0x06);
+        assert_eq!(CpuMemory::read(&mut mem, 0x4016) & 1, 0);
+        assert_eq!(seen.load(std::sync::atomic::Ordering::Relaxed), 0b11);
+    }
+    
+    #[test]
+    fn test_clone_is_independent_and_drops_observers() {
+        let mut mem = mapper66_memory();
+        mem.attach_observer(Box::new(NullObserver));
+        CpuMemory::write(&mut mem, 0x8000, 0x12);
+        CpuMemory::write(&mut mem, 0x0010, 0xAA);
+        
+        // Same banks and RAM, no observers
+        let mut copy = mem.clone();
+        assert_eq!(copy.cartridge().unwrap().bank_state(), mem.cartridge().unwrap().bank_state());
+        assert_eq!(CpuMemory::read(&mut copy, 0x0010), 0xAA);
+        assert!(format!("{:?}", copy).contains("observers: 0"));
+        assert!(format!("{:?}", mem).contains("observers: 1"));
+        
+        // Writes to the copy stay in the copy
+        CpuMemory::write(&mut copy, 0x0010, 0x55);
+        CpuMemory::write(&mut copy, 0x8000, 0x00);
+        assert_eq!(CpuMemory::read(&mut mem, 0x0010), 0xAA);
+        assert_ne!(copy.cartridge().unwrap().bank_state(), mem.cartridge().unwrap().bank_state());
+    }
+    
+    #[test]
+    fn test_mapper_switch_is_logged() {
+        let mut mem = mapper66_memory();
+        let events = capture_events(|| CpuMemory::write(&mut mem, 0x8000, 0x12));
+        
+        let fields = |message: &str| {
+            events
+                .iter()
+                .find(|(_, fields)| fields.contains(&("message".to_string(), message.to_string())))
+                .unwrap_or_else(|| panic!("no {:?} event in {:?}", message, events))
+                .clone()
+        };
+        let (target, chr) = fields("CHR bank switch");
+        assert_eq!(target, "emu_nes::mapper");
+        for field in [("mapper", "66"), ("bank", "2"), ("value", "18"), ("addr", "32768")] {
+            assert!(chr.contains(&(field.0.to_string(), field.1.to_string())), "{:?}", chr);
+        }
+        let (_, prg) = fields("PRG bank switch");
+        assert!(prg.contains(&("bank".to_string(), "1".to_string())), "{:?}", prg);
+        
+        // Rewriting the same banks is not a switch, nor is selecting a
+        // mirror of them: PRG bank 3 is bank 1 on a 2-bank board
+        let events = capture_events(|| CpuMemory::write(&mut mem, 0x8000, 0x12));
+        assert!(events.is_empty(), "{:?}", events);
+        let events = capture_events(|| CpuMemory::write(&mut mem, 0x8000, 0x32));
+        assert!(events.is_empty(), "{:?}", events);
+        assert_eq!(mem.cartridge().unwrap().prg_bank(), 1);
+    }
+    
+    #[test]
+    fn test_quiet_memory_logs_nothing() {
+        let mut mem = mapper66_memory();
+        mem.set_quiet(true);
+        let events = capture_events(|| {
+            CpuMemory::write(&mut mem, 0x8000, 0x33);
+            for _ in 0..(341 * 262) {
+                mem.ppu_mut().tick();
+            }
+        });
+        assert!(events.is_empty(), "{:?}", events);
+        assert_eq!(mem.cartridge.as_ref().unwrap().chr_bank(), 3);
+    }
+    
+    #[test]
+    fn test_poll_nmi_takes_the_ppu_nmi() {
+        let mut mem = NesMemory::new();
+        assert!(!mem.poll_nmi());
+        mem.ppu_mut().nmi_interrupt = true;
+        assert!(mem.poll_nmi());
+        assert!(!mem.ppu().nmi_interrupt, "a hijacked NMI must not be delivered again");
+        assert!(!mem.poll_nmi());
+    }
+    
+    #[test]
+    fn test_io_write_routing() {
+        /// What a write could have touched
+        fn observe(mem: &mut NesMemory) -> (Vec<u8>, [u8; 256], u8) {
+            let mut apu = StateWriter::new();
+            mem.apu().save(&mut apu);
+            let oam = *mem.ppu().oam();
+            // While strobed, controller 1 reports A (held) on every read
+            let strobed = CpuMemory::read(mem, 0x4016) & 1;
+            (apu.finish(), oam, strobed)
+        }
+        
+        for addr in 0x4000..=0x401F {
+            let mut mem = NesMemory::new();
+            mem.controller1().state().press(emu_core::Button::A);
+            let before = observe(&mut mem);
+            CpuMemory::write(&mut mem, addr, 0xC1);
+            let after = observe(&mut mem);
+            
+            let owner = io_write_owner(addr);
+            // The unused triangle and noise registers are the APU's, but do nothing
+            let apu_changed = owner == Some(IoWriteOwner::Apu) && addr != 0x4009 && addr != 0x400D;
+            assert_eq!(before.0 != after.0, apu_changed, "APU, ${:04X}", addr);
+            assert_eq!(before.1 != after.1, owner == Some(IoWriteOwner::OamDma), "OAM, ${:04X}", addr);
+            assert_eq!(after.2 == 1, owner == Some(IoWriteOwner::ControllerStrobe), "strobe, ${:04X}", addr);
+        }
+        
+        assert_eq!(io_write_owner(0x4017), Some(IoWriteOwner::Apu));
+        assert_eq!(io_write_owner(0x4018), None);
+        
+        // Misrouted writes don't reach the APU's state
+        let mut apu = Apu::default();
+        let mut before = StateWriter::new();
+        apu.save(&mut before);
+        apu.write_register(0x4014, 0xC1);
+        apu.write_register(0x4016, 0xC1);
+        let mut after = StateWriter::new();
+        apu.save(&mut after);
+        assert_eq!(before.finish(), after.finish());
+    }
+    
+    #[test]
+    fn test_io_register_read_decode() {
+        let mut mem = NesMemory::new();
+        
+        // Pulse 1 playing, so APU status reads back non-zero
+        CpuMemory::write(&mut mem, 0x4015, 0x01);
+        CpuMemory::write(&mut mem, 0x4003, 0x08);
+        mem.controller1().state().press(emu_core::Button::A);
+        CpuMemory::write(&mut mem, 0x4016, 0x01);
+        CpuMemory::write(&mut mem, 0x4016, 0x00);
+        
+        let cases = [
+            (0x4000, 0x40), // first APU channel register
+            (0x4013, 0x40), // last APU channel register
+            (0x4014, 0x40), // OAMDMA
+            (0x4015, 0x01), // APU status
+            (0x4016, 0x41), // controller 1, A pressed
+            (0x4017, 0x40), // controller 2, nothing pressed
+            (0x4018, 0x40), // first CPU test mode register
+            (0x401F, 0x40), // last CPU test mode register
+            (0x4020, 0x40), // expansion area, nothing loaded
+            (0x6000, 0x60), // PRG-RAM, nothing loaded
+            (0x8000, 0xFF), // PRG-ROM, nothing loaded
+        ];
+        for (addr, expected) in cases {
+            assert_eq!(CpuMemory::read(&mut mem, addr), expected, "${:04X}", addr);
+        }
+    }
     
     #[test]
     fn test_ram_basic_readwrite() {
@@ -384,4 +1172,55 @@ mod tests {
         assert_eq!(CpuMemory::read(&mut mem, 0xC000), 0x22);
         assert_eq!(CpuMemory::read(&mut mem, 0xFFFF), 0x22);
     }
+    
+    #[test]
+    fn test_cartridge_below_prg_rom() {
+        let mut mem = NesMemory::new();
+        mem.load_prg_rom(vec![0x42; 0x4000]);
+        
+        // Expansion area: nothing decodes it on NROM, so open bus
+        assert_eq!(CpuMemory::read(&mut mem, 0x4020), 0x40);
+        assert_eq!(CpuMemory::read(&mut mem, 0x5000), 0x50);
+        CpuMemory::write(&mut mem, 0x5000, 0x99);
+        assert_eq!(CpuMemory::read(&mut mem, 0x5000), 0x50);
+        
+        // PRG-RAM
+        assert_eq!(CpuMemory::read(&mut mem, 0x6000), 0x00);
+        assert_eq!(CpuMemory::read(&mut mem, 0x7FFF), 0x00);
+        CpuMemory::write(&mut mem, 0x6000, 0x12);
+        CpuMemory::write(&mut mem, 0x7FFF, 0x34);
+        assert_eq!(CpuMemory::read(&mut mem, 0x6000), 0x12);
+        assert_eq!(CpuMemory::read(&mut mem, 0x7FFF), 0x34);
+        assert_eq!(CpuMemory::read(&mut mem, 0x8000), 0x42);
+    }
+    
+    #[test]
+    fn test_rmw_dummy_write_only_reaches_the_cartridge() {
+        let mut mem = NesMemory::new();
+        mem.load_prg_rom(vec![0x42; 0x4000]);
+        mem.rmw_dummy_write(0x6000, 0x12);
+        assert_eq!(CpuMemory::read(&mut mem, 0x6000), 0x12);
+        
+        // A different value than was read, so dropping it shows
+        CpuMemory::write(&mut mem, 0x0010, 0x41);
+        mem.rmw_dummy_write(0x0010, 0x99);
+        assert_eq!(CpuMemory::read(&mut mem, 0x0010), 0x41);
+    }
+    
+    #[test]
+    fn test_dmc_plays_from_the_cartridge() {
+        // A sample of zero bytes at $C000, where an empty slot would read $FF
+        let mut mem = NesMemory::new();
+        let mut rom = vec![0xFF; 0x4000];
+        rom[..17].fill(0x00);
+        mem.load_prg_rom(rom);
+        for (addr, value) in [(0x4010, 0x0F), (0x4011, 100), (0x4012, 0x00), (0x4013, 0x01), (0x4015, 0x10)] {
+            CpuMemory::write(&mut mem, addr, value);
+        }
+        for _ in 0..4000 {
+            mem.clock_cycle();
+        }
+        assert!(mem.apu().dmc.output() < 100, "{}", mem.apu().dmc.output());
+        assert!(mem.apu().dmc.sample_status());
+    }
 }
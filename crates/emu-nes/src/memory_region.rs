@@ -0,0 +1,88 @@
+//! Memory regions that can be exported and imported whole
+//!
+//! Analysis tools snapshot a region with
+//! [`NesSystem::export_region`](crate::NesSystem::export_region), change
+//! it, and load it back with
+//! [`NesSystem::import_region`](crate::NesSystem::import_region). Regions
+//! are the physical memory, not the address space: `PpuVram` is the 2KB
+//! of nametable RAM whatever the mirroring, and `PpuPalette` the 32
+//! palette RAM slots including the mirrored ones.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A block of memory inside the console or cartridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryRegion {
+    /// The 2KB of internal RAM at $0000-$07FF
+    CpuRam,
+    /// The PPU's 2KB of nametable RAM
+    PpuVram,
+    /// The 32 bytes of palette RAM
+    PpuPalette,
+    /// The 256 bytes of sprite memory
+    Oam,
+    /// Cartridge RAM at $6000-$7FFF, as much as the cartridge has
+    PrgRam,
+    /// The 8KB of pattern table RAM on boards without CHR-ROM
+    ChrRam,
+}
+
+impl MemoryRegion {
+    /// Every region, in declaration order
+    pub const ALL: [MemoryRegion; 6] = [
+        MemoryRegion::CpuRam,
+        MemoryRegion::PpuVram,
+        MemoryRegion::PpuPalette,
+        MemoryRegion::Oam,
+        MemoryRegion::PrgRam,
+        MemoryRegion::ChrRam,
+    ];
+
+    /// Name used on the command line and in file names, e.g. `cpu-ram`
+    pub fn name(self) -> &'static str {
+        match self {
+            MemoryRegion::CpuRam => "cpu-ram",
+            MemoryRegion::PpuVram => "ppu-vram",
+            MemoryRegion::PpuPalette => "ppu-palette",
+            MemoryRegion::Oam => "oam",
+            MemoryRegion::PrgRam => "prg-ram",
+            MemoryRegion::ChrRam => "chr-ram",
+        }
+    }
+}
+
+impl fmt::Display for MemoryRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for MemoryRegion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|region| region.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|region| region.name()).collect();
+                format!("unknown memory region '{}' (expected one of {})", s, names.join(", "))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_round_trip() {
+        for region in MemoryRegion::ALL {
+            assert_eq!(region.name().parse::<MemoryRegion>(), Ok(region));
+        }
+        assert_eq!("OAM".parse::<MemoryRegion>(), Ok(MemoryRegion::Oam));
+        let err = "wram".parse::<MemoryRegion>().unwrap_err();
+        assert!(err.contains("cpu-ram, ppu-vram"), "{}", err);
+    }
+}
@@ -1,7 +1,18 @@
-/// NES Color Palette
-/// 
-/// The NES has a palette of 64 colors (actually 512, but games use 64).
-/// Each palette entry is an RGB color.
+//! NES Color Palette
+//! 
+//! The NES has a palette of 64 colors (actually 512, but games use 64).
+//! Each palette entry is an RGB color.
+//!
+//! The other 448 colors come from the PPUMASK emphasis bits, which darken
+//! the channels that aren't emphasized. [`emphasized_palette`] builds those
+//! tables on first use.
+//!
+//! Frontends converting whole frames every frame should use
+//! [`framebuffer_to_rgba_fast`], which looks each pixel up in [`rgba_table`]
+//! and stores it as one word, instead of the per-pixel pushes of
+//! [`framebuffer_to_rgb`].
+
+use std::sync::OnceLock;
 
 /// NES color palette (64 colors in RGB format)
 /// Index corresponds to the palette index used by the PPU
@@ -75,17 +86,121 @@ pub const NES_PALETTE: [(u8, u8, u8); 64] = [
     (0, 0, 0),          // 0x3F
 ];
 
+/// Attenuation applied to a channel for each emphasis bit that isn't its own
+const EMPHASIS_ATTENUATION: f32 = 0.816328;
+
+/// All 512 colors: one 64-color table per emphasis combination
+static EMPHASIZED_PALETTES: OnceLock<[[(u8, u8, u8); 64]; 8]> = OnceLock::new();
+
+/// Get the 64-color table for a set of emphasis bits
+///
+/// `emphasis_bits` is PPUMASK >> 5: bit 0 = red, bit 1 = green, bit 2 = blue.
+/// Higher bits are ignored. Each set bit darkens the other two channels,
+/// so emphasizing all three darkens everything.
+pub fn emphasized_palette(emphasis_bits: u8) -> &'static [(u8, u8, u8); 64] {
+    let palettes = EMPHASIZED_PALETTES.get_or_init(|| {
+        let mut palettes = [NES_PALETTE; 8];
+        for (bits, palette) in palettes.iter_mut().enumerate() {
+            for color in palette.iter_mut() {
+                let (r, g, b) = *color;
+                let mut channels = [r as f32, g as f32, b as f32];
+                for emphasized in 0..3 {
+                    if bits & (1 << emphasized) == 0 {
+                        continue;
+                    }
+                    for (channel, value) in channels.iter_mut().enumerate() {
+                        if channel != emphasized {
+                            *value *= EMPHASIS_ATTENUATION;
+                        }
+                    }
+                }
+                *color = (
+                    channels[0].round() as u8,
+                    channels[1].round() as u8,
+                    channels[2].round() as u8,
+                );
+            }
+        }
+        palettes
+    });
+    &palettes[(emphasis_bits & 0x07) as usize]
+}
+
+/// All 512 colors as RGBA words, built from [`EMPHASIZED_PALETTES`]
+static RGBA_TABLE: OnceLock<[u32; 512]> = OnceLock::new();
+
+/// All 512 colors as words whose bytes in memory are R, G, B and 255
+///
+/// Indexed by `emphasis_bits << 6 | palette_index`, so each emphasis
+/// combination is a run of 64. The byte order is what Slint and most
+/// image APIs take as RGBA8, on any endianness.
+pub fn rgba_table() -> &'static [u32; 512] {
+    RGBA_TABLE.get_or_init(|| {
+        let mut table = [0; 512];
+        for (emphasis, colors) in table.chunks_exact_mut(64).enumerate() {
+            for (word, &(r, g, b)) in colors.iter_mut().zip(emphasized_palette(emphasis as u8)) {
+                *word = u32::from_ne_bytes([r, g, b, 0xFF]);
+            }
+        }
+        table
+    })
+}
+
+/// Convert a framebuffer of palette indices to RGBA, with emphasis
+/// applied, into `out`
+///
+/// The same bytes as [`framebuffer_to_rgb_emphasized`] with an opaque
+/// alpha after each pixel, written a word at a time with no branches.
+///
+/// # Panics
+///
+/// If `out` isn't four bytes per pixel of `framebuffer`.
+pub fn framebuffer_to_rgba_fast(framebuffer: &[u8], emphasis_bits: u8, out: &mut [u8]) {
+    assert_eq!(out.len(), framebuffer.len() * 4, "RGBA output must be four bytes per pixel");
+    let start = ((emphasis_bits & 0x07) as usize) << 6;
+    let colors: &[u32; 64] = rgba_table()[start..start + 64].try_into().unwrap();
+    let color = |index: u8| colors[(index & 0x3F) as usize].to_ne_bytes();
+    // Four pixels at a time lets the lookups overlap; about a fifth faster
+    let mut quads = out.chunks_exact_mut(16);
+    let mut indices = framebuffer.chunks_exact(4);
+    for (quad, indices) in (&mut quads).zip(&mut indices) {
+        let words = [color(indices[0]), color(indices[1]), color(indices[2]), color(indices[3])];
+        quad.copy_from_slice(words.as_flattened());
+    }
+    for (pixel, &index) in quads.into_remainder().chunks_exact_mut(4).zip(indices.remainder()) {
+        pixel.copy_from_slice(&color(index));
+    }
+}
+
+/// Apply the PPUMASK greyscale bit to a palette index
+///
+/// Greyscale mode forces every color into the grey column ($x0).
+pub fn greyscale(palette_index: u8) -> u8 {
+    palette_index & 0x30
+}
+
 /// Convert a palette index to RGB color
 pub fn palette_to_rgb(palette_index: u8) -> (u8, u8, u8) {
     NES_PALETTE[(palette_index & 0x3F) as usize]
 }
 
+/// Convert a palette index to RGB color with emphasis applied
+pub fn palette_to_rgb_emphasized(palette_index: u8, emphasis_bits: u8) -> (u8, u8, u8) {
+    emphasized_palette(emphasis_bits)[(palette_index & 0x3F) as usize]
+}
+
 /// Convert framebuffer (palette indices) to RGB image data
 pub fn framebuffer_to_rgb(framebuffer: &[u8]) -> Vec<u8> {
+    framebuffer_to_rgb_emphasized(framebuffer, 0)
+}
+
+/// Convert framebuffer (palette indices) to RGB image data with emphasis applied
+pub fn framebuffer_to_rgb_emphasized(framebuffer: &[u8], emphasis_bits: u8) -> Vec<u8> {
+    let palette = emphasized_palette(emphasis_bits);
     let mut rgb_data = Vec::with_capacity(framebuffer.len() * 3);
     
     for &palette_index in framebuffer {
-        let (r, g, b) = palette_to_rgb(palette_index);
+        let (r, g, b) = palette[(palette_index & 0x3F) as usize];
         rgb_data.push(r);
         rgb_data.push(g);
         rgb_data.push(b);
@@ -119,4 +234,62 @@ mod tests {
         assert_eq!(&rgb[0..3], &[84, 84, 84]); // First pixel
         assert_eq!(&rgb[3..6], &[0, 30, 116]); // Second pixel
     }
+    
+    #[test]
+    fn test_emphasized_palette() {
+        // No emphasis is the base palette
+        assert_eq!(emphasized_palette(0), &NES_PALETTE);
+        
+        // $16 with red emphasis keeps red, darkens green and blue
+        assert_eq!(emphasized_palette(0b001)[0x16], (152, 28, 26));
+        assert_eq!(palette_to_rgb_emphasized(0x16, 0b001), (152, 28, 26));
+        
+        // All three bits darken every channel twice
+        assert_eq!(emphasized_palette(0b111)[0x30], (157, 159, 157));
+        
+        // Only the low 3 bits matter
+        assert_eq!(emphasized_palette(0b1001), emphasized_palette(0b001));
+        
+        let rgb = framebuffer_to_rgb_emphasized(&[0x16], 0b001);
+        assert_eq!(rgb, vec![152, 28, 26]);
+    }
+    
+    #[test]
+    fn test_fast_rgba_matches_rgb_for_every_color() {
+        // Every index, including ones with the unused top bits set
+        let framebuffer: Vec<u8> = (0..=0xFF).collect();
+        let mut rgba = vec![0; framebuffer.len() * 4];
+        for emphasis in 0..8 {
+            framebuffer_to_rgba_fast(&framebuffer, emphasis, &mut rgba);
+            let rgb = framebuffer_to_rgb_emphasized(&framebuffer, emphasis);
+            for (pixel, (expected, actual)) in rgb.chunks_exact(3).zip(rgba.chunks_exact(4)).enumerate() {
+                assert_eq!(&actual[..3], expected, "index {:02X}, emphasis {:03b}", pixel, emphasis);
+                assert_eq!(actual[3], 0xFF);
+            }
+        }
+        
+        // Only the low 3 emphasis bits matter, as for the tables
+        let mut high = vec![0; rgba.len()];
+        framebuffer_to_rgba_fast(&framebuffer, 0b1111_1111, &mut high);
+        framebuffer_to_rgba_fast(&framebuffer, 0b111, &mut rgba);
+        assert_eq!(high, rgba);
+        
+        // Pixels left over from the groups of four
+        let mut odd = [0; 7 * 4];
+        framebuffer_to_rgba_fast(&framebuffer[9..16], 0b111, &mut odd);
+        assert_eq!(odd, rgba[9 * 4..16 * 4]);
+    }
+    
+    #[test]
+    #[should_panic(expected = "four bytes per pixel")]
+    fn test_fast_rgba_needs_room_for_every_pixel() {
+        framebuffer_to_rgba_fast(&[0; 4], 0, &mut [0; 12]);
+    }
+    
+    #[test]
+    fn test_greyscale() {
+        assert_eq!(greyscale(0x16), 0x10);
+        assert_eq!(greyscale(0x3F), 0x30);
+        assert_eq!(greyscale(0x0D), 0x00);
+    }
 }
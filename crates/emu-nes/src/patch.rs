@@ -0,0 +1,490 @@
+//! IPS and BPS patches
+//!
+//! Translations and ROM hacks are handed out as patches against the
+//! original dump rather than as ROMs. Both formats here patch the whole
+//! `.nes` file, header included, as the usual patching tools do, so a
+//! patch is applied to the image before it's parsed; see
+//! [`Cartridge::from_bytes_with_patch`](crate::Cartridge::from_bytes_with_patch).
+//!
+//! IPS: `"PATCH"`, then records of a 3-byte offset and a 2-byte length
+//! (big-endian) followed by that many bytes. A zero length marks an RLE
+//! record: a 2-byte count and the byte to repeat. `"EOF"` ends the records
+//! and may be followed by a 3-byte length to truncate the result to.
+//!
+//! BPS: `"BPS1"`, the source, target and metadata sizes as variable-length
+//! numbers, the metadata, then actions building the target front to back,
+//! each copying from the source, the patch, or earlier in the target.
+//! The CRC32s of the source, the target and the patch itself close the
+//! file, so a patch for a different dump is refused rather than applied.
+
+use std::path::{Path, PathBuf};
+use emu_core::{EmulatorError, Result};
+use crate::savestate::crc32;
+
+/// Magic bytes at the start of an IPS patch
+pub const IPS_MAGIC: &[u8; 5] = b"PATCH";
+
+/// Magic bytes at the start of a BPS patch
+pub const BPS_MAGIC: &[u8; 4] = b"BPS1";
+
+/// Record offset that ends an IPS patch ("EOF")
+const IPS_EOF: usize = 0x454F46;
+
+/// Length of the three CRC32s closing a BPS patch
+const BPS_FOOTER_LEN: usize = 12;
+
+/// Patch file extensions, in the order [`Patch::find_for`] looks for them
+pub const EXTENSIONS: [&str; 2] = ["ips", "bps"];
+
+fn invalid(message: impl Into<String>) -> EmulatorError {
+    EmulatorError::InvalidPatch(message.into())
+}
+
+/// Reads the fields of a patch, failing on a truncated one
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| invalid(format!("ends in the middle of a record at byte {}", self.pos)))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// A big-endian number `len` bytes long, as IPS stores them
+    fn big_endian(&mut self, len: usize) -> Result<usize> {
+        Ok(self.bytes(len)?.iter().fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+
+    /// A BPS number: seven bits per byte, low first, the top bit set on the
+    /// last byte, with each continuation also adding one so every value has
+    /// a single encoding
+    fn varint(&mut self) -> Result<usize> {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let byte = self.u8()?;
+            value = (byte as usize & 0x7F)
+                .checked_mul(shift)
+                .and_then(|part| value.checked_add(part))
+                .ok_or_else(|| invalid("number too large"))?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or_else(|| invalid("number too large"))?;
+            value = value.checked_add(shift).ok_or_else(|| invalid("number too large"))?;
+        }
+    }
+}
+
+/// Apply an IPS patch to `rom`
+///
+/// Records may write past the end of the ROM to grow it, but only from
+/// where the data so far ends; a record starting beyond that is refused.
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    if !patch.starts_with(IPS_MAGIC) {
+        return Err(invalid("not an IPS patch"));
+    }
+    let mut out = rom.to_vec();
+    let mut reader = Reader::new(patch, IPS_MAGIC.len());
+    loop {
+        let offset = reader.big_endian(3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+        if offset > out.len() {
+            return Err(invalid(format!(
+                "record at ${:06X} starts past the end of the {}-byte ROM",
+                offset,
+                out.len()
+            )));
+        }
+        let len = reader.big_endian(2)?;
+        let (len, fill) = match len {
+            0 => (reader.big_endian(2)?, Some(reader.u8()?)),
+            len => (len, None),
+        };
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        match fill {
+            Some(value) => out[offset..offset + len].fill(value),
+            None => out[offset..offset + len].copy_from_slice(reader.bytes(len)?),
+        }
+    }
+    // Lunar IPS' truncation extension
+    match patch.len() - reader.pos {
+        0 => {}
+        3 => {
+            let len = reader.big_endian(3)?;
+            if len > out.len() {
+                return Err(invalid(format!("truncates to {} bytes, past the end", len)));
+            }
+            out.truncate(len);
+        }
+        extra => return Err(invalid(format!("{} unexpected bytes after EOF", extra))),
+    }
+    Ok(out)
+}
+
+/// Apply a BPS patch to `rom`
+///
+/// The ROM must be the exact file the patch was made from: its size and
+/// CRC32 are checked before anything is applied, and the result's after.
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    if !patch.starts_with(BPS_MAGIC) || patch.len() < BPS_MAGIC.len() + BPS_FOOTER_LEN {
+        return Err(invalid("not a BPS patch"));
+    }
+    let footer = patch.len() - BPS_FOOTER_LEN;
+    let stored_crc = |at: usize| u32::from_le_bytes(patch[footer + at..footer + at + 4].try_into().unwrap());
+    let (source_crc, target_crc, patch_crc) = (stored_crc(0), stored_crc(4), stored_crc(8));
+    if crc32(&patch[..footer + 8]) != patch_crc {
+        return Err(invalid("the patch file is damaged (CRC32 mismatch)"));
+    }
+
+    // The actions stop at the footer
+    let mut reader = Reader::new(&patch[..footer], BPS_MAGIC.len());
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+    if rom.len() != source_size {
+        return Err(invalid(format!(
+            "made for a {}-byte ROM, not this {}-byte one",
+            source_size,
+            rom.len()
+        )));
+    }
+    if crc32(rom) != source_crc {
+        return Err(invalid(format!(
+            "made for ROM CRC32 {:08X}, not this one ({:08X})",
+            source_crc,
+            crc32(rom)
+        )));
+    }
+
+    let mut out = Vec::with_capacity(target_size);
+    let mut source_offset = 0usize;
+    let mut target_offset = 0usize;
+    // Moves a copy offset by the signed distance in the patch
+    let relative = |reader: &mut Reader, offset: usize| -> Result<usize> {
+        let data = reader.varint()?;
+        let distance = data >> 1;
+        match data & 1 {
+            0 => offset.checked_add(distance),
+            _ => offset.checked_sub(distance),
+        }
+        .ok_or_else(|| invalid("copy offset out of range"))
+    };
+    while reader.pos < footer {
+        let data = reader.varint()?;
+        let len = (data >> 2) + 1;
+        if out.len() + len > target_size {
+            return Err(invalid("writes past the end of the target"));
+        }
+        match data & 3 {
+            // Source read: the source's bytes at the same place
+            0 => {
+                let start = out.len();
+                out.extend_from_slice(rom.get(start..start + len).ok_or_else(|| invalid("source read out of range"))?);
+            }
+            // Target read: bytes from the patch
+            1 => out.extend_from_slice(reader.bytes(len)?),
+            // Source copy: from anywhere in the source
+            2 => {
+                source_offset = relative(&mut reader, source_offset)?;
+                let bytes = source_offset
+                    .checked_add(len)
+                    .and_then(|end| rom.get(source_offset..end))
+                    .ok_or_else(|| invalid("source copy out of range"))?;
+                out.extend_from_slice(bytes);
+                source_offset += len;
+            }
+            // Target copy: from what's been written, byte by byte so it
+            // can repeat a run that overlaps its own output
+            _ => {
+                target_offset = relative(&mut reader, target_offset)?;
+                if target_offset >= out.len() {
+                    return Err(invalid("target copy out of range"));
+                }
+                for _ in 0..len {
+                    out.push(out[target_offset]);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if out.len() != target_size {
+        return Err(invalid(format!("built {} bytes, expected {}", out.len(), target_size)));
+    }
+    if crc32(&out) != target_crc {
+        return Err(invalid("the patched ROM doesn't match the patch's CRC32"));
+    }
+    Ok(out)
+}
+
+/// Apply an IPS or BPS patch, whichever `patch` is
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(invalid("not an IPS or BPS patch"))
+    }
+}
+
+/// A patch file, read into memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    /// File name, as shown in [`CartridgeInfo`](crate::CartridgeInfo)
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl Patch {
+    /// Read the patch at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)
+            .map_err(|e| invalid(format!("couldn't read {}: {}", path.display(), e)))?;
+        Ok(Self {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            data,
+        })
+    }
+
+    /// The patch next to the ROM at `rom_path` with the same name and an
+    /// `.ips` or `.bps` extension, if there is one
+    pub fn find_for(rom_path: &Path) -> Option<PathBuf> {
+        EXTENSIONS
+            .iter()
+            .map(|extension| rom_path.with_extension(extension))
+            .find(|path| path.is_file())
+    }
+
+    /// Patch `rom`
+    pub fn apply(&self, rom: &[u8]) -> Result<Vec<u8>> {
+        apply(rom, &self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An IPS patch of `records` (offset, data), then EOF
+    fn ips(records: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut patch = IPS_MAGIC.to_vec();
+        for &(offset, data) in records {
+            patch.extend_from_slice(&offset.to_be_bytes()[5..]);
+            patch.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            patch.extend_from_slice(data);
+        }
+        patch.extend_from_slice(b"EOF");
+        patch
+    }
+
+    /// A BPS number
+    fn varint(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let low = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(low | 0x80);
+                return bytes;
+            }
+            bytes.push(low);
+            value -= 1;
+        }
+    }
+
+    /// A BPS patch from `source` to `target` with `actions`, CRCs filled in
+    fn bps(source: &[u8], target: &[u8], actions: &[u8]) -> Vec<u8> {
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(varint(source.len()));
+        patch.extend(varint(target.len()));
+        patch.extend(varint(3));
+        patch.extend_from_slice(b"xyz");
+        patch.extend_from_slice(actions);
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    /// A BPS action: `command` (0-3) on `len` bytes
+    fn action(command: usize, len: usize) -> Vec<u8> {
+        varint((len - 1) << 2 | command)
+    }
+
+    /// A signed BPS copy offset
+    fn offset(distance: isize) -> Vec<u8> {
+        varint(distance.unsigned_abs() << 1 | (distance < 0) as usize)
+    }
+
+    #[test]
+    fn test_ips_records() {
+        let rom = [0u8; 8];
+        let patched = apply_ips(&rom, &ips(&[(1, &[0xAA, 0xBB]), (6, &[0xCC])])).unwrap();
+        assert_eq!(patched, [0, 0xAA, 0xBB, 0, 0, 0, 0xCC, 0]);
+
+        // A record at the very end grows the ROM
+        let patched = apply_ips(&rom, &ips(&[(8, &[1, 2])])).unwrap();
+        assert_eq!(patched.len(), 10);
+        assert_eq!(patched[8..], [1, 2]);
+    }
+
+    #[test]
+    fn test_ips_rle() {
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x05, 0x7F]);
+        patch.extend_from_slice(b"EOF");
+        let patched = apply_ips(&[0; 10], &patch).unwrap();
+        assert_eq!(patched, [0, 0, 0x7F, 0x7F, 0x7F, 0x7F, 0x7F, 0, 0, 0]);
+
+        // Runs can grow the ROM too
+        let patched = apply_ips(&[0; 4], &patch).unwrap();
+        assert_eq!(patched, [0, 0, 0x7F, 0x7F, 0x7F, 0x7F, 0x7F]);
+    }
+
+    #[test]
+    fn test_ips_truncation() {
+        let mut patch = ips(&[(0, &[9])]);
+        patch.extend_from_slice(&[0x00, 0x00, 0x03]);
+        assert_eq!(apply_ips(&[0; 8], &patch).unwrap(), [9, 0, 0]);
+
+        let mut patch = ips(&[]);
+        patch.extend_from_slice(&[0x00, 0x00, 0x10]);
+        assert!(apply_ips(&[0; 8], &patch).is_err());
+    }
+
+    #[test]
+    fn test_ips_errors() {
+        // A record leaving a gap past the end
+        let error = apply_ips(&[0; 8], &ips(&[(9, &[1])])).unwrap_err();
+        assert!(error.to_string().contains("past the end"), "{}", error);
+
+        // Cut off mid-record, or before EOF
+        let patch = ips(&[(0, &[1, 2, 3])]);
+        assert!(apply_ips(&[0; 8], &patch[..patch.len() - 5]).is_err());
+        assert!(apply_ips(&[0; 8], &patch[..patch.len() - 3]).is_err());
+
+        assert!(apply_ips(&[0; 8], b"PATCHEOFjunk").is_err());
+        assert!(apply_ips(&[0; 8], b"BPS1").is_err());
+    }
+
+    #[test]
+    fn test_bps_numbers() {
+        for value in [0, 1, 127, 128, 255, 16511, 16512, 1 << 20, 0x40_0000] {
+            let bytes = varint(value);
+            assert_eq!(Reader::new(&bytes, 0).varint().unwrap(), value, "{:02X?}", bytes);
+        }
+        assert!(Reader::new(&[0x00], 0).varint().is_err());
+    }
+
+    #[test]
+    fn test_bps_actions() {
+        let source = b"ABCDEFGH";
+        let target = b"ABxyFGAGAGAG";
+        let mut actions = Vec::new();
+        // "AB" from the source, in place
+        actions.extend(action(0, 2));
+        // "xy" from the patch
+        actions.extend(action(1, 2));
+        actions.extend_from_slice(b"xy");
+        // "FG" from source offset 5, then "A" from 0 (moving back 7)
+        actions.extend(action(2, 2));
+        actions.extend(offset(5));
+        actions.extend(action(2, 1));
+        actions.extend(offset(-7));
+        // "GAGAG" from target offset 5, overlapping its own output
+        actions.extend(action(3, 5));
+        actions.extend(offset(5));
+
+        let patch = bps(source, target, &actions);
+        assert_eq!(apply_bps(source, &patch).unwrap(), target);
+        assert_eq!(apply(source, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn test_bps_checks_crcs() {
+        let source = b"ABCD";
+        let target = b"ABCE";
+        let mut actions = action(0, 3);
+        actions.extend(action(1, 1));
+        actions.push(b'E');
+        let patch = bps(source, target, &actions);
+
+        // Another ROM of the same size
+        let error = apply_bps(b"ABCX", &patch).unwrap_err();
+        assert!(error.to_string().contains("made for ROM CRC32"), "{}", error);
+        assert!(apply_bps(b"ABC", &patch).is_err());
+
+        // Damage to the patch, or a wrong target checksum
+        let mut damaged = patch.clone();
+        let last_action = damaged.len() - BPS_FOOTER_LEN - 1;
+        damaged[last_action] = b'F';
+        assert!(apply_bps(source, &damaged).unwrap_err().to_string().contains("damaged"));
+        let mut wrong_target = bps(source, b"ABCF", &actions);
+        assert!(apply_bps(source, &wrong_target).unwrap_err().to_string().contains("CRC32"));
+        wrong_target.truncate(6);
+        assert!(apply_bps(source, &wrong_target).is_err());
+    }
+
+    #[test]
+    fn test_bps_out_of_range_copies() {
+        let source = b"ABCD";
+        let mut actions = action(2, 2);
+        actions.extend(offset(3));
+        assert!(apply_bps(source, &bps(source, b"DX", &actions)).is_err());
+
+        let mut actions = action(3, 1);
+        actions.extend(offset(0));
+        assert!(apply_bps(source, &bps(source, b"A", &actions)).is_err());
+
+        // More output than the target size
+        assert!(apply_bps(source, &bps(source, b"AB", &action(0, 3))).is_err());
+    }
+
+    #[test]
+    fn test_apply_detects_the_format() {
+        assert_eq!(apply(&[0; 2], &ips(&[(0, &[5])])).unwrap(), [5, 0]);
+        assert!(apply(&[0; 2], b"nothing").is_err());
+    }
+
+    #[test]
+    fn test_find_for_sibling_patch() {
+        let dir = std::env::temp_dir().join(format!("lumi_patch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("game.nes");
+        assert_eq!(Patch::find_for(&rom), None);
+
+        std::fs::write(dir.join("game.bps"), b"BPS1").unwrap();
+        assert_eq!(Patch::find_for(&rom), Some(dir.join("game.bps")));
+        std::fs::write(dir.join("game.ips"), ips(&[])).unwrap();
+        assert_eq!(Patch::find_for(&rom), Some(dir.join("game.ips")));
+
+        let patch = Patch::load(&dir.join("game.ips")).unwrap();
+        assert_eq!(patch.name, "game.ips");
+        assert_eq!(patch.apply(&[1, 2]).unwrap(), [1, 2]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
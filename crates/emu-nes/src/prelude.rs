@@ -0,0 +1,24 @@
+//! What a frontend or test harness needs, in one import
+//!
+//! ```
+//! use emu_nes::prelude::*;
+//!
+//! fn press_start(system: &mut NesSystem) {
+//!     system.press_button(Button::START);
+//! }
+//! ```
+//!
+//! Everything here is also exported from the crate root or its module;
+//! the prelude only saves listing it. `emu_core::Result` is left out so a
+//! glob import doesn't shadow the standard one.
+
+pub use crate::cartridge::{CartridgeInfo, Mirroring, Region};
+pub use crate::controller::Controller;
+pub use crate::palette::{
+    framebuffer_to_rgb, framebuffer_to_rgb_emphasized, framebuffer_to_rgba_fast, palette_to_rgb, NES_PALETTE,
+};
+pub use crate::patch::Patch;
+pub use crate::savestate::crc32;
+pub use crate::system::{NesSystem, QuickState, SystemEvent};
+pub use crate::{EmulatorHandle, EmulatorService, FrameData};
+pub use emu_core::{Button, ControllerState, Emulator, EmulatorError, InputDevice};
@@ -0,0 +1,277 @@
+//! Frame-time profiling
+//!
+//! [`NesSystem::enable_profiling`](crate::NesSystem::enable_profiling)
+//! measures where the wall-clock time of each frame goes. Timing every
+//! PPU and APU clock would cost more than the work being timed, so only
+//! every [`SAMPLE_INTERVAL`]th instruction is broken down by subsystem;
+//! the shares those samples show are then applied to the frame's measured
+//! total, which keeps the parts summing to the whole.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+
+/// Frames the report covers
+pub const PROFILE_FRAMES: usize = 120;
+
+/// One instruction in this many is broken down by subsystem
+pub const SAMPLE_INTERVAL: u32 = 64;
+
+/// Where frame time is spent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    /// Instruction execution and everything else not listed below
+    Cpu,
+    Ppu,
+    Apu,
+    /// Memory observers and snapshot sinks
+    Observers,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 4] = [Subsystem::Cpu, Subsystem::Ppu, Subsystem::Apu, Subsystem::Observers];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::Cpu => "CPU",
+            Subsystem::Ppu => "PPU",
+            Subsystem::Apu => "APU",
+            Subsystem::Observers => "Observers",
+        }
+    }
+}
+
+/// Time spent in the PPU, APU and observers during a sampled instruction
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ComponentTimes {
+    pub ppu: Duration,
+    pub apu: Duration,
+    pub observers: Duration,
+}
+
+/// One frame's time, split by subsystem
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct FrameTimes {
+    total: Duration,
+    parts: [Duration; 4],
+}
+
+/// Collects frame times while profiling is enabled
+#[derive(Debug, Default)]
+pub(crate) struct Profiler {
+    /// The last [`PROFILE_FRAMES`] frames, oldest first
+    frames: VecDeque<FrameTimes>,
+    /// Sampled time per subsystem in the frame in progress
+    sampled: [Duration; 4],
+    /// Instructions left until the next sample
+    countdown: u32,
+}
+
+impl Profiler {
+    /// Whether the next instruction should be timed, counting it
+    pub fn sample_next_step(&mut self) -> bool {
+        if self.countdown == 0 {
+            self.countdown = SAMPLE_INTERVAL - 1;
+            true
+        } else {
+            self.countdown -= 1;
+            false
+        }
+    }
+
+    /// Add a sampled instruction that took `elapsed` in all
+    pub fn record_step(&mut self, elapsed: Duration, parts: ComponentTimes) {
+        let components = parts.ppu + parts.apu + parts.observers;
+        self.sampled[Subsystem::Cpu as usize] += elapsed.saturating_sub(components);
+        self.sampled[Subsystem::Ppu as usize] += parts.ppu;
+        self.sampled[Subsystem::Apu as usize] += parts.apu;
+        self.sampled[Subsystem::Observers as usize] += parts.observers;
+    }
+
+    /// Close a frame that took `total`, of which `sinks` went to snapshot sinks
+    pub fn end_frame(&mut self, total: Duration, sinks: Duration) {
+        let emulated = total.saturating_sub(sinks);
+        let sampled: Duration = self.sampled.iter().sum();
+        let mut parts = [Duration::ZERO; 4];
+        if sampled.is_zero() {
+            parts[Subsystem::Cpu as usize] = emulated;
+        } else {
+            let scale = emulated.as_secs_f64() / sampled.as_secs_f64();
+            for subsystem in [Subsystem::Ppu, Subsystem::Apu, Subsystem::Observers] {
+                parts[subsystem as usize] = self.sampled[subsystem as usize].mul_f64(scale);
+            }
+            // Whatever rounding left over goes to the CPU, so the parts add up
+            let others: Duration = parts.iter().sum();
+            parts[Subsystem::Cpu as usize] = emulated.saturating_sub(others);
+        }
+        parts[Subsystem::Observers as usize] += sinks;
+
+        if self.frames.len() == PROFILE_FRAMES {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(FrameTimes { total, parts });
+        self.sampled = [Duration::ZERO; 4];
+    }
+
+    pub fn report(&self) -> ProfileReport {
+        let stats = |time: fn(&FrameTimes) -> Duration| Stats::of(self.frames.iter().map(time));
+        ProfileReport {
+            frames: self.frames.len(),
+            frame: stats(|frame| frame.total),
+            parts: [
+                stats(|frame| frame.parts[0]),
+                stats(|frame| frame.parts[1]),
+                stats(|frame| frame.parts[2]),
+                stats(|frame| frame.parts[3]),
+            ],
+        }
+    }
+}
+
+/// Minimum, average and maximum of a time over the profiled frames
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+}
+
+impl Stats {
+    fn of(times: impl Iterator<Item = Duration>) -> Self {
+        let mut count = 0;
+        let mut sum = Duration::ZERO;
+        let mut min = Duration::MAX;
+        let mut max = Duration::ZERO;
+        for time in times {
+            count += 1;
+            sum += time;
+            min = min.min(time);
+            max = max.max(time);
+        }
+        if count == 0 {
+            return Self::default();
+        }
+        Self { min, avg: sum / count, max }
+    }
+}
+
+/// Frame times over the last [`PROFILE_FRAMES`] frames
+///
+/// Displays as a small table with each subsystem's share of the average
+/// frame.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProfileReport {
+    /// Frames the figures cover (0 until a frame finishes with profiling on)
+    pub frames: usize,
+    /// Whole frames
+    pub frame: Stats,
+    parts: [Stats; 4],
+}
+
+impl ProfileReport {
+    /// Time spent in one subsystem
+    pub fn stats(&self, subsystem: Subsystem) -> Stats {
+        self.parts[subsystem as usize]
+    }
+
+    /// A subsystem's share of the average frame, in percent
+    pub fn percentage(&self, subsystem: Subsystem) -> f64 {
+        if self.frame.avg.is_zero() {
+            return 0.0;
+        }
+        100.0 * self.stats(subsystem).avg.as_secs_f64() / self.frame.avg.as_secs_f64()
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+        let row = |f: &mut fmt::Formatter<'_>, name: &str, stats: Stats| {
+            write!(f, "{:<10} {:>8.3} {:>8.3} {:>8.3}", name, ms(stats.min), ms(stats.avg), ms(stats.max))
+        };
+        writeln!(f, "Frame times over {} frames (ms):", self.frames)?;
+        writeln!(f, "{:<10} {:>8} {:>8} {:>8}", "", "min", "avg", "max")?;
+        for subsystem in Subsystem::ALL {
+            row(f, subsystem.name(), self.stats(subsystem))?;
+            writeln!(f, " {:>6.1}%", self.percentage(subsystem))?;
+        }
+        row(f, "Frame", self.frame)?;
+        writeln!(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    fn times(ppu: u64, apu: u64, observers: u64) -> ComponentTimes {
+        ComponentTimes { ppu: ms(ppu), apu: ms(apu), observers: ms(observers) }
+    }
+
+    #[test]
+    fn test_samples_one_step_in_each_interval() {
+        let mut profiler = Profiler::default();
+        let sampled: Vec<bool> = (0..SAMPLE_INTERVAL * 2).map(|_| profiler.sample_next_step()).collect();
+        assert_eq!(sampled.iter().filter(|&&s| s).count(), 2);
+        assert!(sampled[0] && sampled[SAMPLE_INTERVAL as usize]);
+    }
+
+    #[test]
+    fn test_samples_scale_to_the_frame_total() {
+        let mut profiler = Profiler::default();
+        // Samples show CPU 4, PPU 3, APU 2, observers 1
+        profiler.record_step(ms(6), times(2, 1, 1));
+        profiler.record_step(ms(4), times(1, 1, 0));
+        // The frame took 22ms, 2 of them in snapshot sinks
+        profiler.end_frame(ms(22), ms(2));
+
+        let report = profiler.report();
+        assert_eq!(report.frames, 1);
+        assert_eq!(report.frame.avg, ms(22));
+        assert_eq!(report.stats(Subsystem::Cpu).avg, ms(8));
+        assert_eq!(report.stats(Subsystem::Ppu).avg, ms(6));
+        assert_eq!(report.stats(Subsystem::Apu).avg, ms(4));
+        assert_eq!(report.stats(Subsystem::Observers).avg, ms(4));
+        let total: f64 = Subsystem::ALL.iter().map(|&s| report.percentage(s)).sum();
+        assert!((total - 100.0).abs() < 1e-9);
+        assert!((report.percentage(Subsystem::Cpu) - 800.0 / 22.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_unsampled_frame_is_all_cpu() {
+        let mut profiler = Profiler::default();
+        profiler.end_frame(ms(5), Duration::ZERO);
+        let report = profiler.report();
+        assert_eq!(report.stats(Subsystem::Cpu).avg, ms(5));
+        assert_eq!(report.stats(Subsystem::Ppu), Stats::default());
+    }
+
+    #[test]
+    fn test_min_avg_max_over_the_last_frames() {
+        let mut profiler = Profiler::default();
+        // The first frames fall out of the window
+        for _ in 0..10 {
+            profiler.end_frame(ms(100), Duration::ZERO);
+        }
+        for i in 0..PROFILE_FRAMES as u64 {
+            profiler.end_frame(ms(1 + i % 3), Duration::ZERO);
+        }
+        let report = profiler.report();
+        assert_eq!(report.frames, PROFILE_FRAMES);
+        assert_eq!(report.frame, Stats { min: ms(1), avg: ms(2), max: ms(3) });
+    }
+
+    #[test]
+    fn test_empty_report() {
+        let report = Profiler::default().report();
+        assert_eq!(report, ProfileReport::default());
+        assert_eq!(report.percentage(Subsystem::Cpu), 0.0);
+        let text = report.to_string();
+        assert!(text.starts_with("Frame times over 0 frames"));
+        assert!(text.contains("Observers"));
+    }
+}
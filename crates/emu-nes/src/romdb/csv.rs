@@ -0,0 +1,120 @@
+//! Parsing and code generation for `data/romdb.csv`
+//!
+//! build.rs includes this file with `#[path]` to turn the CSV into a
+//! sorted Rust table, so it only uses std. The format:
+//!
+//! ```text
+//! # Comments and blank lines are skipped
+//! crc32,mapper,mirroring,prg_ram_kb,region,name
+//! 1A2B3C4D,4,vertical,8,ntsc,Some Game (USA)
+//! ```
+//!
+//! `crc32` is the CRC32 of the PRG and CHR data in hex, `mirroring` is one
+//! of `horizontal`, `vertical` or `four_screen`, `region` one of `ntsc`,
+//! `pal` or `dual`. The name is everything after the fifth comma, so it
+//! may contain commas itself.
+
+use std::fmt::Write;
+
+/// The header line every database starts with
+pub const HEADER: &str = "crc32,mapper,mirroring,prg_ram_kb,region,name";
+
+/// CSV spellings of each `Mirroring` variant
+const MIRRORING: [(&str, &str); 3] = [
+    ("horizontal", "Horizontal"),
+    ("vertical", "Vertical"),
+    ("four_screen", "FourScreen"),
+];
+
+/// CSV spellings of each `Region` variant
+const REGIONS: [(&str, &str); 3] = [("ntsc", "Ntsc"), ("pal", "Pal"), ("dual", "Dual")];
+
+/// One database entry, with enums given by variant name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub crc32: u32,
+    pub mapper: u8,
+    /// `Mirroring` variant name
+    pub mirroring: &'static str,
+    pub prg_ram_kb: u16,
+    /// `Region` variant name
+    pub region: &'static str,
+    pub name: String,
+}
+
+/// Look up the variant for a CSV spelling
+fn variant(table: &[(&str, &'static str)], value: &str, what: &str) -> Result<&'static str, String> {
+    table
+        .iter()
+        .find(|(spelling, _)| *spelling == value)
+        .map(|&(_, variant)| variant)
+        .ok_or_else(|| format!("unknown {} `{}`", what, value))
+}
+
+/// Parse one data line
+fn parse_row(line: &str) -> Result<Row, String> {
+    let fields: Vec<&str> = line.splitn(6, ',').map(str::trim).collect();
+    let [crc32, mapper, mirroring, prg_ram_kb, region, name] = fields[..] else {
+        return Err(format!("expected 6 fields, found {}", fields.len()));
+    };
+    Ok(Row {
+        crc32: u32::from_str_radix(crc32, 16).map_err(|e| format!("bad CRC32 `{}`: {}", crc32, e))?,
+        mapper: mapper.parse().map_err(|e| format!("bad mapper `{}`: {}", mapper, e))?,
+        mirroring: variant(&MIRRORING, mirroring, "mirroring")?,
+        prg_ram_kb: prg_ram_kb.parse().map_err(|e| format!("bad PRG-RAM size `{}`: {}", prg_ram_kb, e))?,
+        region: variant(&REGIONS, region, "region")?,
+        name: name.to_string(),
+    })
+}
+
+/// Parse the whole CSV into rows sorted by CRC32
+///
+/// Errors name the 1-based line they were found on. A CRC32 listed twice is
+/// an error, since the entries can't both apply.
+pub fn parse(text: &str) -> Result<Vec<Row>, String> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+    match lines.next() {
+        Some((_, HEADER)) => {}
+        Some((number, line)) => return Err(format!("line {}: expected header `{}`, found `{}`", number, HEADER, line)),
+        None => return Err(format!("missing header `{}`", HEADER)),
+    }
+
+    let mut rows = Vec::new();
+    for (number, line) in lines {
+        rows.push(parse_row(line).map_err(|e| format!("line {}: {}", number, e))?);
+    }
+    rows.sort_by_key(|row| row.crc32);
+    if let Some(pair) = rows.windows(2).find(|pair| pair[0].crc32 == pair[1].crc32) {
+        return Err(format!("CRC32 {:08X} is listed twice", pair[0].crc32));
+    }
+    Ok(rows)
+}
+
+/// Rust source for a `&[RomDbEntry]` expression holding `rows`
+///
+/// Expects `RomDbEntry`, `Mirroring`, `Region` and `Cow` in scope where it's
+/// included.
+pub fn generate(rows: &[Row]) -> String {
+    let mut out = String::from("&[\n");
+    for row in rows {
+        writeln!(
+            out,
+            "    RomDbEntry {{ crc32: 0x{:08X}, mapper: {}, mirroring: Mirroring::{}, prg_ram_size: {}, \
+             region: Region::{}, name: Cow::Borrowed({:?}) }},",
+            row.crc32,
+            row.mapper,
+            row.mirroring,
+            row.prg_ram_kb as usize * 1024,
+            row.region,
+            row.name
+        )
+        .unwrap();
+    }
+    out.push(']');
+    out
+}
@@ -0,0 +1,202 @@
+//! ROM database for correcting bad iNES headers
+//!
+//! Many dumps in circulation have wrong headers, most famously garbage in
+//! the mapper bits. The database maps the CRC32 of a dump's PRG and CHR
+//! data (the same CRC32 as [`Cartridge::crc32`](crate::Cartridge::crc32))
+//! to the board it really uses. [`Cartridge::from_bytes`](crate::Cartridge::from_bytes)
+//! consults the embedded copy, compiled from `data/romdb.csv` by build.rs.
+
+// Shared with build.rs, which is the only user of `generate` outside tests
+#[allow(dead_code)]
+mod csv;
+
+use crate::cartridge::{INesHeader, Mirroring, Region};
+use emu_core::{EmulatorError, Result};
+use std::borrow::Cow;
+
+/// What the database knows about one dump
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomDbEntry {
+    /// CRC32 of the PRG and CHR data
+    pub crc32: u32,
+    pub mapper: u8,
+    pub mirroring: Mirroring,
+    /// PRG-RAM size in bytes
+    pub prg_ram_size: usize,
+    pub region: Region,
+    /// Title, for tools that list the database
+    pub name: Cow<'static, str>,
+}
+
+impl RomDbEntry {
+    /// Overwrite the header fields the database knows about
+    ///
+    /// The database only has a total PRG-RAM size; with a battery, all of
+    /// it is taken to be battery-backed, as in an iNES 1.0 header.
+    /// Returns whether anything actually changed.
+    pub fn apply(&self, header: &mut INesHeader) -> bool {
+        let fields = |header: &INesHeader| {
+            (header.mapper, header.mirroring, header.prg_ram_size, header.prg_nvram_size, header.region)
+        };
+        let before = fields(header);
+        header.mapper = self.mapper;
+        header.mirroring = self.mirroring;
+        header.prg_ram_size = self.prg_ram_size;
+        header.prg_nvram_size = if header.has_battery { self.prg_ram_size } else { 0 };
+        header.region = self.region;
+        fields(header) != before
+    }
+}
+
+/// A set of entries, sorted by CRC32
+#[derive(Debug, Clone)]
+pub struct RomDb {
+    entries: Cow<'static, [RomDbEntry]>,
+}
+
+static EMBEDDED: RomDb = RomDb {
+    entries: Cow::Borrowed(include!(concat!(env!("OUT_DIR"), "/romdb.rs"))),
+};
+
+impl RomDb {
+    /// The database built into the crate
+    pub fn embedded() -> &'static RomDb {
+        &EMBEDDED
+    }
+
+    /// Build a database from CSV text in the `data/romdb.csv` format
+    pub fn from_csv(text: &str) -> Result<Self> {
+        let rows = csv::parse(text).map_err(|e| EmulatorError::Other(format!("ROM database: {}", e)))?;
+        let entries = rows
+            .into_iter()
+            .map(|row| RomDbEntry {
+                crc32: row.crc32,
+                mapper: row.mapper,
+                mirroring: match row.mirroring {
+                    "Horizontal" => Mirroring::Horizontal,
+                    "Vertical" => Mirroring::Vertical,
+                    _ => Mirroring::FourScreen,
+                },
+                prg_ram_size: row.prg_ram_kb as usize * 1024,
+                region: match row.region {
+                    "Ntsc" => Region::Ntsc,
+                    "Pal" => Region::Pal,
+                    _ => Region::Dual,
+                },
+                name: Cow::Owned(row.name),
+            })
+            .collect();
+        Ok(Self { entries: Cow::Owned(entries) })
+    }
+
+    /// Find the entry for the dump with this CRC32
+    pub fn lookup(&self, crc32: u32) -> Option<RomDbEntry> {
+        self.entries
+            .binary_search_by_key(&crc32, |entry| entry.crc32)
+            .ok()
+            .map(|index| self.entries[index].clone())
+    }
+
+    /// Every entry, sorted by CRC32
+    pub fn entries(&self) -> &[RomDbEntry] {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "\
+# Test database
+crc32,mapper,mirroring,prg_ram_kb,region,name
+
+DEADBEEF,66,vertical,0,pal,Later, With A Comma
+0000ABCD, 4 ,four_screen,8,dual,Earlier
+";
+
+    #[test]
+    fn test_parse_sorts_and_reads_every_field() {
+        let rows = csv::parse(CSV).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                csv::Row {
+                    crc32: 0xABCD,
+                    mapper: 4,
+                    mirroring: "FourScreen",
+                    prg_ram_kb: 8,
+                    region: "Dual",
+                    name: "Earlier".into(),
+                },
+                csv::Row {
+                    crc32: 0xDEAD_BEEF,
+                    mapper: 66,
+                    mirroring: "Vertical",
+                    prg_ram_kb: 0,
+                    region: "Pal",
+                    name: "Later, With A Comma".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors_name_the_line() {
+        let error = |text: &str| csv::parse(text).unwrap_err();
+        assert!(error("").contains("missing header"));
+        assert!(error("crc32,mapper\n").starts_with("line 1: expected header"));
+        let body = |line: &str| format!("{}\n{}\n", csv::HEADER, line);
+        assert_eq!(error(&body("XYZ,0,vertical,0,ntsc,Game")).split(':').next(), Some("line 2"));
+        assert!(error(&body("1234,256,vertical,0,ntsc,Game")).contains("bad mapper `256`"));
+        assert!(error(&body("1234,0,diagonal,0,ntsc,Game")).contains("unknown mirroring `diagonal`"));
+        assert!(error(&body("1234,0,vertical,0,secam,Game")).contains("unknown region `secam`"));
+        assert!(error(&body("1234,0,vertical,0,ntsc")).contains("expected 6 fields, found 5"));
+        let twice = format!("{}\n1234,0,vertical,0,ntsc,A\n1234,1,vertical,0,ntsc,B\n", csv::HEADER);
+        assert_eq!(error(&twice), "CRC32 00001234 is listed twice");
+    }
+
+    #[test]
+    fn test_generated_table_compiles_to_the_same_entries() {
+        let generated = csv::generate(&csv::parse(CSV).unwrap());
+        assert_eq!(
+            generated,
+            "&[\n    RomDbEntry { crc32: 0x0000ABCD, mapper: 4, mirroring: Mirroring::FourScreen, prg_ram_size: 8192, \
+             region: Region::Dual, name: Cow::Borrowed(\"Earlier\") },\n    RomDbEntry { crc32: 0xDEADBEEF, mapper: 66, \
+             mirroring: Mirroring::Vertical, prg_ram_size: 0, region: Region::Pal, name: Cow::Borrowed(\"Later, With A Comma\") },\n]"
+        );
+        assert_eq!(csv::generate(&[]), "&[\n]");
+    }
+
+    #[test]
+    fn test_lookup() {
+        let db = RomDb::from_csv(CSV).unwrap();
+        let entry = db.lookup(0xDEAD_BEEF).unwrap();
+        assert_eq!((entry.mapper, entry.mirroring, entry.region), (66, Mirroring::Vertical, Region::Pal));
+        assert_eq!(entry.name, "Later, With A Comma");
+        assert_eq!(db.lookup(0xABCD).unwrap().prg_ram_size, 8 * 1024);
+        assert_eq!(db.lookup(0x1234), None);
+        assert_eq!(db.entries().len(), 2);
+
+        // The embedded table is checked when it's built, so it's sorted and loads
+        let embedded = RomDb::embedded().entries();
+        assert!(embedded.windows(2).all(|pair| pair[0].crc32 < pair[1].crc32));
+        assert!(RomDb::from_csv("# nothing\n").is_err());
+    }
+
+    #[test]
+    fn test_apply_reports_changes() {
+        let mut header = INesHeader::parse(b"NES\x1a\x02\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00").unwrap();
+        let db = RomDb::from_csv(CSV).unwrap();
+        let entry = db.lookup(0xDEAD_BEEF).unwrap();
+        let mut same = entry.clone();
+        same.mapper = 0;
+        same.prg_ram_size = 0x2000;
+        same.region = Region::Ntsc;
+
+        assert!(!same.apply(&mut header));
+        assert!(entry.apply(&mut header));
+        assert_eq!((header.mapper, header.prg_ram_size, header.region), (66, 0, Region::Pal));
+        assert!(!entry.apply(&mut header));
+    }
+}
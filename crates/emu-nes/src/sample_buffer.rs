@@ -0,0 +1,185 @@
+//! APU output recorded as it's clocked, resampled when it's taken
+//!
+//! Every cycle the APU hands over its channel levels; only changes are
+//! kept, stamped with the cycle they took effect on. Taking samples turns
+//! those steps into output samples, each the exact average of the output
+//! over its share of the cycles (a box filter, with cycles split where a
+//! sample boundary falls inside one). A sample is only produced once all
+//! its cycles have run, and the part-done one is worked out afresh next
+//! time, so the stream is the same however the caller slices execution.
+
+use std::fmt;
+
+/// Channel output levels
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Levels {
+    /// Pulse 1, pulse 2, triangle, noise and DMC
+    pub channels: [u8; 5],
+    /// The cartridge's sound chip, in console pulse channels at full volume
+    pub cartridge: f32,
+}
+
+/// Most level changes kept before the oldest half is dropped
+///
+/// The noise channel at its highest pitch changes every few cycles, so this
+/// is a few frames; callers taking samples every frame never get near it.
+const STEP_LIMIT: usize = 1 << 16;
+
+/// Level changes since the samples last taken
+#[derive(Clone)]
+pub(crate) struct SampleBuffer {
+    /// Cycle each change took effect on, and the levels from then; the
+    /// first is in effect at `start`, the rest come after it
+    steps: Vec<(u64, Levels)>,
+    /// First cycle not taken in full
+    start: u64,
+    /// How much of cycle `start` has been taken, in 1/`rate` cycles
+    taken: u64,
+    /// Output rate `taken` is counted in
+    rate: u32,
+}
+
+impl SampleBuffer {
+    /// Start with nothing pending, at `cycle` with `levels` in effect
+    pub fn new(cycle: u64, levels: Levels) -> Self {
+        Self { steps: vec![(cycle, levels)], start: cycle, taken: 0, rate: 0 }
+    }
+
+    /// Record the levels after clocking `cycle`
+    pub fn record(&mut self, cycle: u64, levels: Levels) {
+        if self.steps.last().is_some_and(|(_, last)| *last == levels) {
+            return;
+        }
+        self.steps.push((cycle, levels));
+        if self.steps.len() > STEP_LIMIT {
+            // Nobody is taking them; drop the oldest rather than grow
+            let keep = self.steps.len() / 2;
+            self.start = self.steps[keep].0;
+            self.taken = 0;
+            self.steps.drain(..keep);
+        }
+    }
+
+    /// Append the samples at `rate` that the cycles up to `end` complete,
+    /// the console running at `clock` cycles a second
+    ///
+    /// `mix` turns levels into one value per output channel.
+    pub fn take<const N: usize>(
+        &mut self,
+        rate: u32,
+        clock: u32,
+        end: u64,
+        mix: impl Fn(Levels) -> [f32; N],
+        mut out: impl FnMut([f32; N]),
+    ) {
+        assert!(rate > 0 && rate <= clock, "sample rate {} out of range", rate);
+        if rate != self.rate {
+            self.taken = if self.rate == 0 { 0 } else { self.taken * rate as u64 / self.rate as u64 };
+            self.rate = rate;
+        }
+        let (rate, clock) = (rate as u64, clock as u64);
+        // Positions are in 1/rate cycles from `start`, so a sample is `clock` long
+        let available = end.saturating_sub(self.start) * rate;
+        let step_position = |cycle: u64| (cycle - self.start) * rate;
+        let mut position = self.taken;
+        let mut step = 0;
+        while position + clock <= available {
+            let sample_end = position + clock;
+            let mut sum = [0.0; N];
+            let mut from = position;
+            while from < sample_end {
+                while self.steps.get(step + 1).is_some_and(|&(cycle, _)| step_position(cycle) <= from) {
+                    step += 1;
+                }
+                let to = self.steps.get(step + 1).map_or(sample_end, |&(cycle, _)| step_position(cycle).min(sample_end));
+                let value = mix(self.steps[step].1);
+                for (total, channel) in sum.iter_mut().zip(value) {
+                    *total += channel * (to - from) as f32;
+                }
+                from = to;
+            }
+            out(sum.map(|total| total / clock as f32));
+            position = sample_end;
+        }
+
+        self.start += position / rate;
+        self.taken = position % rate;
+        let in_effect = self.steps.partition_point(|&(cycle, _)| cycle <= self.start);
+        self.steps.drain(..in_effect.saturating_sub(1));
+    }
+}
+
+impl fmt::Debug for SampleBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SampleBuffer")
+            .field("steps", &self.steps.len())
+            .field("start", &self.start)
+            .field("taken", &self.taken)
+            .field("rate", &self.rate)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first level as the only output channel
+    fn first(levels: Levels) -> [f32; 1] {
+        [levels.channels[0] as f32]
+    }
+    
+    /// Pulse 1 at `level`, everything else silent
+    fn pulse(level: u8) -> Levels {
+        Levels { channels: [level, 0, 0, 0, 0], cartridge: 0.0 }
+    }
+
+    fn take_all(buffer: &mut SampleBuffer, rate: u32, clock: u32, end: u64) -> Vec<f32> {
+        let mut samples = Vec::new();
+        buffer.take(rate, clock, end, first, |[sample]| samples.push(sample));
+        samples
+    }
+
+    #[test]
+    fn test_samples_average_their_cycles() {
+        // Four cycles a sample; level 4 for cycles 2-5 of 0-7
+        let mut buffer = SampleBuffer::new(0, Levels::default());
+        buffer.record(2, pulse(4));
+        buffer.record(6, Levels::default());
+        assert_eq!(take_all(&mut buffer, 1, 4, 8), vec![2.0, 2.0]);
+
+        // A sample boundary inside a cycle splits it: 1.5 cycles a sample,
+        // the first a cycle of 0 and half of 3
+        let mut buffer = SampleBuffer::new(0, Levels::default());
+        buffer.record(1, pulse(3));
+        assert_eq!(take_all(&mut buffer, 2, 3, 3), vec![1.0, 3.0]);
+    }
+
+    #[test]
+    fn test_taking_in_pieces_changes_nothing() {
+        let levels = |cycle: u64| pulse((cycle * 7 % 16) as u8);
+        let mut whole = SampleBuffer::new(0, levels(0));
+        let mut pieces = whole.clone();
+        let mut piecewise = Vec::new();
+        for cycle in 0..10_000 {
+            whole.record(cycle, levels(cycle / 3));
+            pieces.record(cycle, levels(cycle / 3));
+            piecewise.extend(take_all(&mut pieces, 44_100, 1_789_773, cycle + 1));
+        }
+        let all = take_all(&mut whole, 44_100, 1_789_773, 10_000);
+        assert_eq!(all.len(), (10_000 * 44_100 / 1_789_773) as usize);
+        assert_eq!(piecewise, all);
+    }
+
+    #[test]
+    fn test_unclaimed_steps_are_capped() {
+        let mut buffer = SampleBuffer::new(0, Levels::default());
+        for cycle in 0..STEP_LIMIT as u64 * 2 {
+            buffer.record(cycle, pulse((cycle % 2) as u8));
+        }
+        assert!(buffer.steps.len() <= STEP_LIMIT);
+        let samples = take_all(&mut buffer, 1, 2, STEP_LIMIT as u64 * 2);
+        assert!(samples.len() <= STEP_LIMIT / 2 + 1);
+        assert!(samples.iter().all(|&sample| sample == 0.5));
+    }
+}
@@ -0,0 +1,217 @@
+//! Savestates
+//!
+//! A savestate is a snapshot of everything that changes while a game runs:
+//! CPU registers, RAM, PPU/APU internals, controller shift registers and
+//! mapper banks. ROM contents are not included, so a state is only valid
+//! for the ROM it was taken from; the header records that ROM's CRC32 and
+//! [`NesSystem::load_state`](crate::NesSystem::load_state) refuses a
+//! mismatch.
+//!
+//! Layout: `"LUMI"`, format version (u16), ROM CRC32 (u32), then each
+//! component's fields in a fixed order. Integers are little-endian and
+//! byte buffers are length-prefixed.
+//!
+//! The components go CPU registers, internal RAM, PPU, APU, the two
+//! controllers, then the cartridge: PRG-RAM, the mapper, and last a flag
+//! saying whether the pattern tables follow. They only do for CHR-RAM
+//! carts; a CHR-ROM cart's are reloaded from the ROM for the restored
+//! banks, so they cost nothing. Frame and clock counters close the body.
+
+use emu_core::{EmulatorError, Result};
+
+/// Magic bytes at the start of every savestate
+pub const MAGIC: &[u8; 4] = b"LUMI";
+
+/// Current savestate format version
+pub const VERSION: u16 = 13;
+
+/// CRC32 (IEEE) of `data`, as used by No-Intro and most ROM databases
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Component state that can be written to and restored from a savestate
+pub(crate) trait Snapshot {
+    /// Append this component's state
+    fn save(&self, w: &mut StateWriter);
+    /// Restore state written by [`Snapshot::save`]
+    fn load(&mut self, r: &mut StateReader) -> Result<()>;
+}
+
+/// Serializes savestate fields
+#[derive(Default)]
+pub(crate) struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.buf.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Write bytes as-is, with no length prefix
+    pub fn raw(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Write a length-prefixed byte buffer
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Deserializes savestate fields, failing cleanly on truncated input
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len()).ok_or_else(|| {
+            EmulatorError::InvalidSaveState(format!("truncated at byte {}", self.pos))
+        })?;
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    /// Read `len` bytes written with [`StateWriter::raw`]
+    pub fn raw(&mut self, len: usize) -> Result<&'a [u8]> {
+        self.take(len)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Read a length-prefixed byte buffer
+    pub fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// Read a length-prefixed byte buffer into `dest`, which must match its length
+    pub fn bytes_into(&mut self, dest: &mut [u8]) -> Result<()> {
+        let data = self.bytes()?;
+        if data.len() != dest.len() {
+            return Err(EmulatorError::InvalidSaveState(format!(
+                "expected {} bytes, found {}",
+                dest.len(),
+                data.len()
+            )));
+        }
+        dest.copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Fail if anything is left over (a sign of a format mismatch)
+    pub fn finish(&self) -> Result<()> {
+        if self.pos != self.data.len() {
+            return Err(EmulatorError::InvalidSaveState(format!(
+                "{} unexpected trailing bytes",
+                self.data.len() - self.pos
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32_check_value() {
+        // Standard CRC-32 check value
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn test_writer_reader_round_trip() {
+        let mut w = StateWriter::new();
+        w.u8(0xAB);
+        w.bool(true);
+        w.u16(0x1234);
+        w.u32(0xDEAD_BEEF);
+        w.u64(u64::MAX - 1);
+        w.bytes(&[1, 2, 3]);
+        let data = w.finish();
+
+        let mut r = StateReader::new(&data);
+        assert_eq!(r.u8().unwrap(), 0xAB);
+        assert!(r.bool().unwrap());
+        assert_eq!(r.u16().unwrap(), 0x1234);
+        assert_eq!(r.u32().unwrap(), 0xDEAD_BEEF);
+        assert_eq!(r.u64().unwrap(), u64::MAX - 1);
+        let mut buf = [0; 3];
+        r.bytes_into(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+        r.finish().unwrap();
+    }
+
+    #[test]
+    fn test_reader_rejects_truncated_and_mismatched_data() {
+        let mut w = StateWriter::new();
+        w.bytes(&[0; 4]);
+        let data = w.finish();
+
+        let mut buf = [0; 8];
+        assert!(StateReader::new(&data).bytes_into(&mut buf).is_err());
+        assert!(StateReader::new(&data[..6]).bytes().is_err());
+        assert!(StateReader::new(&data).finish().is_err());
+    }
+}
@@ -0,0 +1,76 @@
+//! Comparing machine states
+//!
+//! Tests that check two runs agree (a cloned system against the original,
+//! a resumed savestate against an uninterrupted run) compare a
+//! [`StateDigest`] rather than whole savestates: it's eight bytes, and
+//! prints as something short enough to read in an assertion failure.
+//!
+//! The digest covers what a savestate covers, so two systems with equal
+//! digests run identically from there on given the same inputs. Settings
+//! that aren't state (accuracy flags, muted channels, breakpoints) and the
+//! framebuffer are left out.
+
+use std::fmt;
+use crate::savestate::crc32;
+use crate::NesSystem;
+
+/// A 64-bit hash of a system's emulation state
+///
+/// FNV-1a over the savestate body, so it's stable across runs, platforms
+/// and builds with the same savestate [`VERSION`](crate::savestate::VERSION).
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StateDigest(pub u64);
+
+impl StateDigest {
+    /// Digest the state of `system`
+    pub fn of(system: &NesSystem) -> Self {
+        Self::of_bytes(&system.state_body())
+    }
+
+    fn of_bytes(data: &[u8]) -> Self {
+        const OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+        const PRIME: u64 = 0x0000_0100_0000_01B3;
+        Self(data.iter().fold(OFFSET_BASIS, |hash, &byte| (hash ^ byte as u64).wrapping_mul(PRIME)))
+    }
+}
+
+impl fmt::Debug for StateDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "StateDigest({:016X})", self.0)
+    }
+}
+
+impl fmt::Display for StateDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016X}", self.0)
+    }
+}
+
+/// A byte buffer in `Debug` output, as its length and CRC32
+///
+/// For the memories inside the core types, which are too big to print but
+/// whose contents still matter when comparing two dumps.
+pub(crate) struct Bytes<'a>(pub &'a [u8]);
+
+impl fmt::Debug for Bytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{} bytes, crc32 {:08X}>", self.0.len(), crc32(self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a_reference_values() {
+        assert_eq!(StateDigest::of_bytes(b""), StateDigest(0xCBF2_9CE4_8422_2325));
+        assert_eq!(StateDigest::of_bytes(b"a"), StateDigest(0xAF63_DC4C_8601_EC8C));
+        assert_eq!(StateDigest::of_bytes(b"foobar").to_string(), "85944171F73967E8");
+    }
+
+    #[test]
+    fn test_bytes_summary() {
+        assert_eq!(format!("{:?}", Bytes(b"123456789")), "<9 bytes, crc32 CBF43926>");
+    }
+}
@@ -1,37 +1,311 @@
-/// Top-level NES System
-/// 
-/// Ties together CPU, memory, and cartridge into a complete NES emulator.
+//! Top-level NES System
+//! 
+//! Ties together CPU, memory, and cartridge into a complete NES emulator.
 
-use crate::{Cartridge, Cpu6502, NesMemory};
+use crate::{AccuracyFlags, AnalysisSnapshot, BankState, Cartridge, CartridgeInfo, Channel, Controller, ControllerPort, Cpu6502, MemoryRegion, NesMemory, Patch, Region, SnapshotSink, StereoConfig};
 use crate::cpu::CpuMemory;
-use emu_core::{Button, Controller, Cpu, EmulatorError, Result};
+use crate::disasm::{self, DisasmLine};
+use crate::event_log::{EmuEvent, EmuEventKind};
+use crate::input_script::{InputSchedule, ScriptMode, ScriptPlayback};
+use crate::instruction_hook::{InstrEvent, InstructionHook};
+use crate::apu_player::{CPU_CLOCK_HZ, CYCLES_PER_FRAME, PAL_CPU_CLOCK_HZ, PAL_CYCLES_PER_FRAME};
+use crate::battery::BatterySave;
+use crate::lint::{LintReport, LintSink, Linter};
+use crate::palette::framebuffer_to_rgba_fast;
+use crate::ppu::{dots_for_cycles, PowerUpState, PpuMask, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::profile::{ProfileReport, Profiler};
+use crate::sample_buffer::SampleBuffer;
+use crate::savestate::{self, Snapshot, StateReader, StateWriter};
+use crate::state::StateDigest;
+use emu_core::{Button, Cpu, Emulator, EmulatorError, InputDevice, Result};
+use std::any::Any;
+use std::collections::BTreeSet;
+use std::fmt;
 use std::path::Path;
+use std::time::Instant;
 use tracing::debug;
 
+/// Most distinct PCs a loop can span and still count as a possible hang
+const HANG_MAX_LOOP_LEN: usize = 8;
+
+/// Most cycles [`NesSystem::step_over`] and [`NesSystem::step_out`] run
+/// looking for the return, about a second
+const STEP_LIMIT_CYCLES: u64 = CYCLES_PER_FRAME * 60;
+
+/// Opcodes the step commands look for
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+
+/// CPU cycles an OAM DMA halts the CPU for, one more when it starts on an odd cycle
+const OAM_DMA_CYCLES: u64 = 513;
+
+/// Notable conditions raised while running, drained with [`NesSystem::poll_events`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemEvent {
+    /// The CPU spent a whole frame in a tight loop without ever seeing
+    /// vblank or taking an NMI. This usually means the game is waiting on
+    /// a feature the emulator doesn't support.
+    PossibleHang {
+        /// Lowest address in the loop
+        pc: u16,
+        /// Number of distinct instruction addresses in the loop
+        loop_len: usize,
+    },
+    /// The CPU reached a breakpoint and the system paused before running
+    /// the instruction there
+    BreakpointHit {
+        pc: u16,
+    },
+    /// Under [`AccuracyFlags::PPU_WARMUP`], the game wrote PPU registers
+    /// before the PPU had warmed up and the writes were lost, as they would
+    /// be on a real console. Raised once the warm-up is over.
+    WarmupWritesIgnored {
+        /// Register the first ignored write went to
+        addr: u16,
+        /// Number of writes ignored
+        count: u32,
+    },
+}
+
+/// Per-frame bookkeeping for the "waiting forever" heuristic
+///
+/// A frame is suspicious when the CPU touched between 2 and
+/// [`HANG_MAX_LOOP_LEN`] distinct PCs, never saw the VBLANK flag set, and
+/// took no NMI. A single-instruction `JMP *` is how programs park once
+/// they're done, so it is deliberately not reported.
+#[derive(Clone)]
+struct HangDetector {
+    enabled: bool,
+    /// Distinct PCs executed this frame (capped at `HANG_MAX_LOOP_LEN + 1`)
+    pcs: Vec<u16>,
+    nmi_taken: bool,
+    /// Set once a hang is reported, so each hang is only reported once
+    reported: bool,
+    /// The previous frame window already looked hung
+    suspect: bool,
+    /// PPU frame number the current window started in
+    ppu_frame: u64,
+}
+
+impl HangDetector {
+    fn new() -> Self {
+        Self {
+            enabled: true,
+            pcs: Vec::with_capacity(HANG_MAX_LOOP_LEN + 1),
+            nmi_taken: false,
+            reported: false,
+            suspect: false,
+            ppu_frame: 0,
+        }
+    }
+
+    fn record_pc(&mut self, pc: u16) {
+        if self.pcs.len() <= HANG_MAX_LOOP_LEN && !self.pcs.contains(&pc) {
+            self.pcs.push(pc);
+        }
+    }
+
+    /// Close the current frame window, returning an event if it looked hung
+    fn end_frame(&mut self, vblank_seen: bool) -> Option<SystemEvent> {
+        let looping = (2..=HANG_MAX_LOOP_LEN).contains(&self.pcs.len())
+            && !vblank_seen
+            && !self.nmi_taken;
+        // A $2002 poll that races the start of vblank misses that frame's
+        // flag, so only a second quiet frame in a row counts
+        let hung = looping && self.suspect;
+        self.suspect = looping;
+
+        let event = match self.pcs.iter().min() {
+            Some(&pc) if hung && !self.reported => Some(SystemEvent::PossibleHang {
+                pc,
+                loop_len: self.pcs.len(),
+            }),
+            _ => None,
+        };
+
+        self.reported = hung;
+        self.pcs.clear();
+        self.nmi_taken = false;
+        event
+    }
+}
+
+/// Clock counters since the last reset, for A/V sync diagnostics
+///
+/// The PPU runs exactly 3 dots per CPU cycle on NTSC (16 every 5 on PAL)
+/// and the APU is clocked once per CPU cycle, so both drift values should
+/// always be zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ClockStats {
+    /// CPU cycles executed (including interrupt entry)
+    pub cpu_cycles: u64,
+    /// PPU dots ticked
+    pub ppu_dots: u64,
+    /// PPU dots `cpu_cycles` call for
+    pub ppu_dots_due: u64,
+    /// APU clocks
+    pub apu_cycles: u64,
+    /// Frames completed by `run_frame`
+    pub frames: u64,
+    /// Frames completed by the PPU
+    pub ppu_frames: u64,
+}
+
+impl ClockStats {
+    /// PPU dots ahead of (positive) or behind (negative) `ppu_dots_due`
+    pub fn ppu_drift(&self) -> i64 {
+        self.ppu_dots as i64 - self.ppu_dots_due as i64
+    }
+
+    /// APU clocks ahead of (positive) or behind (negative) the CPU
+    pub fn apu_drift(&self) -> i64 {
+        self.apu_cycles as i64 - self.cpu_cycles as i64
+    }
+
+    /// PPU frames ahead of (positive) or behind (negative) `run_frame` calls
+    pub fn frame_drift(&self) -> i64 {
+        self.ppu_frames as i64 - self.frames as i64
+    }
+}
+
+/// Carries the cycles one slice of emulation ran over into the next
+///
+/// Instructions can't be split, so running "N cycles" an instruction at a
+/// time usually ends a few cycles late. Left alone those overruns add up;
+/// a budget takes each one off the next slice instead, so any number of
+/// slices averages out to exactly the cycles asked for. [`NesSystem::run_cycles`]
+/// keeps one; frontends stepping with [`NesSystem::step`] can keep their own:
+///
+/// ```ignore
+/// let allotted = budget.allot(cycles_per_sample);
+/// let mut ran = 0;
+/// while ran < allotted {
+///     ran += system.step()? as u64;
+/// }
+/// budget.settle(allotted, ran);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CycleBudget {
+    /// Cycles already run on behalf of the next slice
+    overshoot: u64,
+}
+
+impl CycleBudget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cycles to run for a slice of `requested`, after taking off what
+    /// earlier slices ran over
+    pub fn allot(&mut self, requested: u64) -> u64 {
+        let credit = self.overshoot.min(requested);
+        self.overshoot -= credit;
+        requested - credit
+    }
+
+    /// Record that a slice [`allot`](Self::allot)ted `allotted` cycles ran `ran`
+    pub fn settle(&mut self, allotted: u64, ran: u64) {
+        self.overshoot += ran.saturating_sub(allotted);
+    }
+
+    /// Cycles already run on behalf of the next slice
+    pub fn overshoot(&self) -> u64 {
+        self.overshoot
+    }
+}
+
+/// PPU/APU counter values at the last reset (their counters run from power-on)
+#[derive(Debug, Clone, Copy, Default)]
+struct ClockBase {
+    ppu_dots: u64,
+    apu_cycles: u64,
+    ppu_frames: u64,
+}
+
+/// An in-memory machine state from [`NesSystem::quick_save`]
+///
+/// The savestate body without its header, plus the bookkeeping a savestate
+/// leaves out (hang detection, pending events, audio not yet taken, lint
+/// findings), so after [`NesSystem::quick_load`] it's as if the frames
+/// since never ran.
+#[derive(Clone)]
+pub struct QuickState {
+    rom_crc: u32,
+    body: Vec<u8>,
+    hang_detector: HangDetector,
+    events: usize,
+    event_log: Option<(usize, usize)>,
+    audio: SampleBuffer,
+    lint: Option<Box<Linter>>,
+}
+
 /// NES Emulator System
+///
+/// Cloning gives an independent machine in the same state, with the same
+/// settings and breakpoints. What's attached from outside stays with the
+/// original: the clone has no snapshot sinks, memory observers or
+/// instruction hook, and profiling is off.
 pub struct NesSystem {
     /// 6502 CPU
     cpu: Cpu6502<NesMemory>,
     /// Frame counter
     frame: u64,
+    /// Paused flag (checked by the `Emulator` trait's `run_frame`)
+    paused: bool,
+    /// Hang heuristic state
+    hang_detector: HangDetector,
+    /// Events waiting for `poll_events`
+    events: Vec<SystemEvent>,
+    /// Counter values at the last reset
+    clock_base: ClockBase,
+    /// Cycles `run_cycles` has run past its targets, credited to the next call
+    cycle_budget: CycleBudget,
+    /// Receivers of the per-frame analysis snapshot
+    snapshot_sinks: Vec<Box<dyn SnapshotSink>>,
+    /// Memory contents [`NesSystem::power_cycle`] starts from
+    power_up_state: PowerUpState,
+    /// Frame times, while profiling is enabled
+    profiler: Option<Box<Profiler>>,
+    /// Homebrew lint checks and findings, while enabled
+    lint: Option<Box<Linter>>,
+    /// Addresses to pause at before executing
+    breakpoints: BTreeSet<u16>,
+    /// The breakpoint just stopped at, which lets the CPU past when resumed
+    stopped_at: Option<u16>,
+    /// Input script playing on controller 1
+    input_script: Option<ScriptPlayback>,
+    /// TV system the PPU and APU are timed for, never [`Region::Dual`]
+    region: Region,
+    /// Called with every instruction executed, while set
+    instruction_hook: Option<InstructionHook>,
 }
 
 impl NesSystem {
     /// Create a new NES system with a cartridge loaded from file
     pub fn new(rom_path: &Path) -> Result<Self> {
-        // Load cartridge
-        let cartridge = Cartridge::load(rom_path)?;
-        
-        // Check mapper support
-        let mapper = cartridge.header().mapper;
-        debug!("Loading ROM: mapper={}, PRG={}KB, CHR={}KB", 
-                 mapper, 
-                 cartridge.prg_rom().len() / 1024,
-                 cartridge.chr_rom().len() / 1024);
-        
-        if mapper != 0 && mapper != 66 {
-            return Err(EmulatorError::UnsupportedMapper(mapper));
-        }
+        Self::from_cartridge(Cartridge::load(rom_path)?)
+    }
+    
+    /// Create a new NES system from an in-memory iNES image
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        Self::from_cartridge(Cartridge::from_bytes(data)?)
+    }
+    
+    /// Create a new NES system from an in-memory iNES image and a patch for it
+    pub fn from_bytes_with_patch(data: &[u8], patch: &Patch) -> Result<Self> {
+        Self::from_cartridge(Cartridge::from_bytes_with_patch(data, patch)?)
+    }
+    
+    /// Insert an already loaded cartridge and reset the CPU
+    pub fn from_cartridge(cartridge: Cartridge) -> Result<Self> {
+        // Unsupported mappers were already refused when the cartridge loaded
+        debug!(
+            mapper = cartridge.header().mapper,
+            prg_kb = cartridge.prg_rom().len() / 1024,
+            chr_kb = cartridge.chr_rom().len() / 1024,
+            "Loading ROM"
+        );
         
         // Create memory system and load cartridge
         let mut memory = NesMemory::new();
@@ -43,12 +317,29 @@ impl NesSystem {
         // Reset CPU (this will read the reset vector from $FFFC-$FFFD)
         cpu.reset();
         
-        debug!("CPU reset to PC=${:04X}", cpu.pc);
+        debug!(pc = cpu.pc, "CPU reset");
         
-        Ok(Self {
-            cpu,
-            frame: 0,
-        })
+        Ok(Self::from_cpu(cpu))
+    }
+    
+    /// Create a NES system that emits no log events
+    ///
+    /// For running many instances side by side (test harnesses, ROM corpus
+    /// runs), where per-instance logging would only be noise. Loading is
+    /// done with logging disabled for this thread, and the mapper and PPU
+    /// events are switched off for the life of the system.
+    pub fn new_quiet(rom_path: &Path) -> Result<Self> {
+        Self::new_quiet_with_patch(rom_path, None)
+    }
+    
+    /// Like [`NesSystem::new_quiet`], applying the patch at `patch`, or
+    /// the one next to the ROM if that's None (see [`Cartridge::load_with_patch`])
+    pub fn new_quiet_with_patch(rom_path: &Path, patch: Option<&Path>) -> Result<Self> {
+        let mut system = tracing::subscriber::with_default(tracing::subscriber::NoSubscriber::default(), || {
+            Self::from_cartridge(Cartridge::load_with_patch(rom_path, patch)?)
+        })?;
+        system.cpu.memory().set_quiet(true);
+        Ok(system)
     }
     
     /// Load a ROM from a file path (convenience method)
@@ -64,60 +355,801 @@ impl NesSystem {
         let mut cpu = Cpu6502::new(memory);
         cpu.reset();
         
-        Ok(Self {
+        Ok(Self::from_cpu(cpu))
+    }
+    
+    /// Create a NES system with 32KB of RAM at $8000-$FFFF instead of ROM
+    /// (for testing the CPU and bus without building a ROM)
+    ///
+    /// The RAM starts zeroed, vectors included. Put code in with
+    /// [`Self::write_code`] and [`Self::set_vectors`] and then
+    /// [`Self::reset`]; code the CPU writes there itself stays written.
+    pub fn with_ram_cart() -> Self {
+        let mut memory = NesMemory::new();
+        memory.load_cartridge(Cartridge::ram_cart());
+        
+        let mut cpu = Cpu6502::new(memory);
+        cpu.reset();
+        
+        Self::from_cpu(cpu)
+    }
+    
+    /// Wrap a freshly reset CPU
+    fn from_cpu(mut cpu: Cpu6502<NesMemory>) -> Self {
+        let region = cpu.memory().region();
+        Self {
             cpu,
             frame: 0,
-        })
+            paused: false,
+            hang_detector: HangDetector::new(),
+            events: Vec::new(),
+            clock_base: ClockBase::default(),
+            cycle_budget: CycleBudget::new(),
+            snapshot_sinks: Vec::new(),
+            power_up_state: PowerUpState::default(),
+            profiler: None,
+            lint: None,
+            breakpoints: BTreeSet::new(),
+            stopped_at: None,
+            input_script: None,
+            region,
+            instruction_hook: None,
+        }
     }
     
     /// Reset the system
+    ///
+    /// Like the console's reset button: the APU is silenced but keeps its
+    /// registers (see [`Apu::soft_reset`](crate::Apu::soft_reset)), and the
+    /// PPU goes through its warm-up again. An input script that was playing
+    /// is stopped.
     pub fn reset(&mut self) {
+        self.detach_input_script();
         self.cpu.reset();
+        self.cpu.memory().apu_mut().soft_reset();
+        self.cpu.memory().ppu_mut().start_warmup();
         self.frame = 0;
+        self.cpu.memory().set_frame(0);
+        let enabled = self.hang_detector.enabled;
+        self.hang_detector = HangDetector::new();
+        self.hang_detector.enabled = enabled;
+        self.hang_detector.ppu_frame = self.cpu.memory().ppu().frame();
+        self.cpu.memory().take_vblank_seen();
+        if let Some(lint) = self.lint.as_mut() {
+            lint.reset();
+        }
+        self.clock_base = ClockBase {
+            ppu_dots: self.cpu.memory().ppu().dots(),
+            apu_cycles: self.cpu.memory().apu().cycles(),
+            ppu_frames: self.cpu.memory().ppu().frame(),
+        };
+        self.cycle_budget = CycleBudget::new();
+    }
+    
+    /// Switch the console off and on again
+    ///
+    /// Unlike [`NesSystem::reset`], RAM, palette RAM, the PPU, the APU and
+    /// the mapper's registers all start over, as set by
+    /// [`NesSystem::set_power_up_state`].
+    pub fn power_cycle(&mut self) {
+        self.cpu.memory().power_cycle(self.power_up_state);
+        self.reset();
+    }
+    
+    /// TV system the console runs as: the cartridge's, unless overridden
+    ///
+    /// [`Region::Dual`] cartridges run as NTSC.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+    
+    /// Run as `region` whatever the cartridge says, or as the cartridge
+    /// says again with `None`
+    ///
+    /// For ROMs whose header has the wrong TV system. Switching mid-game
+    /// isn't meaningful, so when the region changes the system is power
+    /// cycled with the new timings; otherwise nothing happens. Returns
+    /// whether it changed.
+    pub fn set_region_override(&mut self, region: Option<Region>) -> bool {
+        let header = self.cpu.memory().cartridge().map_or(Region::Ntsc, |cart| cart.header().region);
+        let region = match region.unwrap_or(header) {
+            Region::Pal => Region::Pal,
+            _ => Region::Ntsc,
+        };
+        if region == self.region {
+            return false;
+        }
+        self.region = region;
+        self.cpu.memory().set_region(region);
+        self.power_cycle();
+        true
+    }
+    
+    /// CPU cycles [`Self::run_frame`] runs: 29780 on NTSC, 33247 on PAL
+    pub fn cycles_per_frame(&self) -> u64 {
+        match self.region {
+            Region::Pal => PAL_CYCLES_PER_FRAME,
+            _ => CYCLES_PER_FRAME,
+        }
+    }
+    
+    /// Choose what memory holds after a [`NesSystem::power_cycle`]
+    ///
+    /// The system is created in the canonical state; deterministic tests
+    /// can pick [`PowerUpState::AllZeros`] and power cycle.
+    pub fn set_power_up_state(&mut self, state: PowerUpState) {
+        self.power_up_state = state;
+    }
+    
+    /// Get the clock counters since the last reset
+    pub fn clock_stats(&mut self) -> ClockStats {
+        let region = self.region;
+        let base = self.clock_base.apu_cycles;
+        let ppu_dots_due = dots_for_cycles(region, base + self.cpu.cycles) - dots_for_cycles(region, base);
+        let memory = self.cpu.memory();
+        let ppu_dots = memory.ppu().dots() - self.clock_base.ppu_dots;
+        let apu_cycles = memory.apu().cycles() - self.clock_base.apu_cycles;
+        let ppu_frames = memory.ppu().frame() - self.clock_base.ppu_frames;
+        ClockStats {
+            cpu_cycles: self.cpu.cycles,
+            ppu_dots,
+            ppu_dots_due,
+            apu_cycles,
+            frames: self.frame,
+            ppu_frames,
+        }
+    }
+    
+    /// Enable or disable hang detection (enabled by default)
+    pub fn set_hang_detection(&mut self, enabled: bool) {
+        self.hang_detector.enabled = enabled;
+    }
+    
+    /// Pause before executing the instruction at `addr`, or stop doing so
+    ///
+    /// Reaching a breakpoint while running raises
+    /// [`SystemEvent::BreakpointHit`] and pauses the system (see
+    /// [`Emulator::set_paused`]); resuming carries on from there.
+    /// Breakpoints are kept across resets and savestate loads.
+    pub fn set_breakpoint(&mut self, addr: u16, enabled: bool) {
+        if enabled {
+            self.breakpoints.insert(addr);
+        } else {
+            self.breakpoints.remove(&addr);
+        }
+    }
+    
+    /// Flip the breakpoint at `addr`, returning whether it's now set
+    pub fn toggle_breakpoint(&mut self, addr: u16) -> bool {
+        let enabled = !self.breakpoints.contains(&addr);
+        self.set_breakpoint(addr, enabled);
+        enabled
+    }
+    
+    /// Check whether there's a breakpoint at `addr`
+    pub fn has_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.contains(&addr)
+    }
+    
+    /// Every breakpoint, in address order
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+    
+    /// Remove every breakpoint
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+    
+    /// Stop if the next instruction has a breakpoint, unless it's the one
+    /// already stopped at
+    fn check_breakpoint(&mut self) -> bool {
+        let pc = self.cpu.pc;
+        if self.stopped_at == Some(pc) || !self.breakpoints.contains(&pc) {
+            return false;
+        }
+        self.stopped_at = Some(pc);
+        self.paused = true;
+        self.events.push(SystemEvent::BreakpointHit { pc });
+        true
+    }
+    
+    /// Run one instruction, or a whole subroutine if it's a `JSR`
+    ///
+    /// Stops early at a breakpoint, and gives up after about a second of
+    /// emulated time if the subroutine doesn't return.
+    pub fn step_over(&mut self) -> Result<()> {
+        let pc = self.cpu.pc;
+        if self.cpu.memory().peek(pc) != JSR {
+            return self.step().map(drop);
+        }
+        let (return_to, sp) = (pc.wrapping_add(3), self.cpu.sp);
+        self.step()?;
+        self.run_until(|system, _| system.cpu.pc == return_to && system.cpu.sp == sp)
+    }
+    
+    /// Run until the current subroutine or interrupt handler returns
+    ///
+    /// Stops at the `RTS` or `RTI` that pops the stack above where it is
+    /// now, with the same limits as [`NesSystem::step_over`].
+    pub fn step_out(&mut self) -> Result<()> {
+        let sp = self.cpu.sp;
+        self.run_until(|system, opcode| matches!(opcode, RTS | RTI) && system.cpu.sp > sp)
+    }
+    
+    /// Step until `done` (given the opcode just run) says so, a breakpoint
+    /// is reached, or [`STEP_LIMIT_CYCLES`] pass
+    fn run_until<F: FnMut(&Self, u8) -> bool>(&mut self, mut done: F) -> Result<()> {
+        let start = self.cpu.cycles;
+        while self.cpu.cycles - start < STEP_LIMIT_CYCLES {
+            let pc = self.cpu.pc;
+            let opcode = self.cpu.memory().peek(pc);
+            self.step()?;
+            if done(self, opcode) || self.check_breakpoint() {
+                break;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Disassemble `count` instructions starting at `addr`
+    ///
+    /// Reads memory without side effects (see [`NesMemory::peek`]).
+    pub fn disassemble(&mut self, addr: u16, count: usize) -> Vec<DisasmLine> {
+        let memory = self.cpu.memory();
+        disasm::disassemble(addr, count, |addr| memory.peek(addr))
+    }
+    
+    /// Read memory without side effects, for debug views
+    pub fn peek_memory(&mut self, addr: u16) -> u8 {
+        self.cpu.memory().peek(addr)
+    }
+    
+    /// Check whether hang detection is enabled
+    pub fn hang_detection(&self) -> bool {
+        self.hang_detector.enabled
+    }
+    
+    /// Turn the event timeline on or off (off by default)
+    ///
+    /// A setting, so it survives resets. Turning it off drops the events
+    /// not yet taken.
+    pub fn set_event_log_enabled(&mut self, enabled: bool) {
+        self.cpu.memory().set_event_log_enabled(enabled);
+    }
+    
+    /// Whether the event timeline is on
+    pub fn event_log_enabled(&mut self) -> bool {
+        self.cpu.memory().event_log_mut().is_some()
+    }
+    
+    /// Drain the timeline logged since the last call, oldest first
+    ///
+    /// Call it after every [`NesSystem::run_frame`]: at most
+    /// [`MAX_EVENTS_PER_FRAME`](crate::event_log::MAX_EVENTS_PER_FRAME) are
+    /// kept between calls, and the rest only counted. Loading a state
+    /// drops the events logged before it; a quick load only those logged
+    /// since the quick save.
+    pub fn take_frame_events(&mut self) -> Vec<EmuEvent> {
+        self.cpu.memory().event_log_mut().map(|log| log.take()).unwrap_or_default()
+    }
+    
+    /// Drain the events raised since the last call
+    pub fn poll_events(&mut self) -> Vec<SystemEvent> {
+        std::mem::take(&mut self.events)
+    }
+    
+    /// Enable or disable frame-time profiling (disabled by default)
+    ///
+    /// While enabled, each frame's wall-clock time is split between the
+    /// CPU, PPU, APU and observers; see [`crate::profile`]. Disabling
+    /// drops the collected frames.
+    pub fn enable_profiling(&mut self, enabled: bool) {
+        match (enabled, &self.profiler) {
+            (true, None) => self.profiler = Some(Box::default()),
+            (false, Some(_)) => self.profiler = None,
+            _ => {}
+        }
+    }
+    
+    /// Install or remove the hook called with every executed instruction,
+    /// returning the one it replaces
+    ///
+    /// [`NesSystem::step`] calls it once the instruction and any OAM DMA
+    /// it started are done, before an interrupt is taken. The hook can't
+    /// reach this system: it's `'static` and gets an
+    /// [`InstrEvent`](crate::InstrEvent) by value. It must not call back
+    /// into it through a shared handle either, which would deadlock; see
+    /// [`crate::instruction_hook`].
+    pub fn set_instruction_hook(&mut self, hook: Option<InstructionHook>) -> Option<InstructionHook> {
+        std::mem::replace(&mut self.instruction_hook, hook)
+    }
+    
+    /// Check whether profiling is enabled
+    pub fn profiling(&self) -> bool {
+        self.profiler.is_some()
+    }
+    
+    /// Frame times over the last [`PROFILE_FRAMES`](crate::profile::PROFILE_FRAMES)
+    /// profiled frames (empty while profiling is disabled)
+    pub fn profile_report(&self) -> ProfileReport {
+        self.profiler.as_ref().map(|profiler| profiler.report()).unwrap_or_default()
+    }
+    
+    /// Turn the homebrew lint on or off (off by default)
+    ///
+    /// While on, every instruction is checked for habits that work in an
+    /// emulator but not on a console; see [`crate::lint`]. A setting, so
+    /// it survives resets, which keep the findings too. Turning it off
+    /// drops them.
+    pub fn set_lint_enabled(&mut self, enabled: bool) {
+        match (enabled, &self.lint) {
+            (true, None) => self.lint = Some(Box::new(Linter::new())),
+            (false, Some(_)) => self.lint = None,
+            _ => {}
+        }
+    }
+    
+    /// Whether the homebrew lint is on
+    pub fn lint_enabled(&self) -> bool {
+        self.lint.is_some()
+    }
+    
+    /// The lint findings so far, while the lint is on
+    pub fn lint(&self) -> Option<&LintSink> {
+        self.lint.as_ref().map(|lint| lint.sink())
+    }
+    
+    /// The lint findings so far (empty while the lint is off)
+    pub fn lint_report(&self) -> LintReport {
+        self.lint().map(LintSink::report).unwrap_or_default()
     }
     
     /// Step one CPU instruction
-    pub fn step(&mut self) -> Result<u8> {
-        let cycles = self.cpu.step()?;
+    ///
+    /// Returns the cycles consumed, including entering an NMI or IRQ
+    /// handler and the CPU halt for an OAM DMA.
+    pub fn step(&mut self) -> Result<u16> {
+        let Some(profiler) = self.profiler.as_mut() else {
+            return self.execute_step();
+        };
+        if !profiler.sample_next_step() {
+            return self.execute_step();
+        }
+        
+        self.cpu.memory().start_timing();
+        let started = Instant::now();
+        let result = self.execute_step();
+        let elapsed = started.elapsed();
+        let parts = self.cpu.memory().take_timing();
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record_step(elapsed, parts);
+        }
+        result
+    }
+    
+    /// [`Self::step`] without the profiling
+    fn execute_step(&mut self) -> Result<u16> {
+        self.stopped_at = None;
+        if self.hang_detector.enabled {
+            self.hang_detector.record_pc(self.cpu.pc);
+        }
+        
+        if let Some(lint) = self.lint.as_mut() {
+            let pc = self.cpu.pc;
+            let memory = self.cpu.memory();
+            let bytes = std::array::from_fn(|i| memory.peek(pc.wrapping_add(i as u16)));
+            lint.before_step(pc, bytes, self.frame);
+        }
+        
+        let hooked = self.instruction_hook.is_some().then(|| {
+            let pc = self.cpu.pc;
+            let memory = self.cpu.memory();
+            (pc, std::array::from_fn(|i| memory.peek(pc.wrapping_add(i as u16))))
+        });
+        
+        let start = self.cpu.cycles;
+        self.cpu.memory().begin_instruction();
+        let result = self.cpu.step();
+        let mut clocked = self.cpu.memory().end_instruction();
+        result?;
+        
+        if self.cpu.memory().take_oam_dma() {
+            // The CPU halts while DMA runs, plus a cycle to align on odd cycles
+            self.cpu.cycles += OAM_DMA_CYCLES + (self.cpu.cycles & 1);
+        }
+        if let Some(lint) = self.lint.as_mut() {
+            lint.after_step(self.cpu.memory().take_bus_notes(), self.frame);
+        }
+        if let (Some(hook), Some((pc, bytes))) = (self.instruction_hook.as_mut(), hooked) {
+            let cycles = (self.cpu.cycles - start) as u16;
+            if let Some(event) = InstrEvent::new(pc, bytes, cycles, &self.cpu) {
+                hook(event);
+            }
+        }
         
         // PPU runs 3x faster than CPU
         // APU runs at CPU speed
-        for _ in 0..cycles {
-            // Clock APU once per CPU cycle
-            self.cpu.memory().apu_mut().clock();
-            
-            // Clock PPU 3 times per CPU cycle
-            for _ in 0..3 {
-                self.cpu.memory().ppu_mut().tick();
-                
-                // Check for NMI interrupt
-                if self.cpu.memory().ppu().nmi_interrupt {
-                    self.cpu.memory().ppu_mut().nmi_interrupt = false;
-                    self.cpu.nmi();
+        // Register accesses already clocked them up to that point of the
+        // instruction; clock the rest. An interrupt taken along the way adds
+        // its own 7 cycles, which have to be clocked too. IRQ is a level, so
+        // it's taken again after RTI until every source is acknowledged
+        loop {
+            if self.cpu.memory().ppu().nmi_interrupt {
+                self.cpu.memory().ppu_mut().nmi_interrupt = false;
+                self.cpu.memory().log_event(EmuEventKind::Nmi);
+                self.cpu.nmi();
+                self.hang_detector.nmi_taken = true;
+                if let Some(lint) = self.lint.as_mut() {
+                    lint.nmi_taken();
                 }
+            } else {
+                let lines = self.cpu.memory().irq_lines();
+                if !lines.is_empty() && self.cpu.irq() {
+                    self.cpu.memory().log_event(EmuEventKind::Irq(lines));
+                }
+            }
+            if start + clocked >= self.cpu.cycles {
+                break;
             }
+            self.cpu.memory().clock_cycle();
+            clocked += 1;
+        }
+        
+        #[cfg(debug_assertions)]
+        {
+            let stats = self.clock_stats();
+            debug_assert_eq!(stats.ppu_drift(), 0, "PPU out of sync with CPU: {:?}", stats);
+            debug_assert_eq!(stats.apu_drift(), 0, "APU out of sync with CPU: {:?}", stats);
         }
         
-        Ok(cycles)
+        let ppu_frame = self.cpu.memory().ppu().frame();
+        if ppu_frame != self.hang_detector.ppu_frame {
+            self.hang_detector.ppu_frame = ppu_frame;
+            let vblank_seen = self.cpu.memory().take_vblank_seen();
+            if self.hang_detector.enabled {
+                if let Some(event) = self.hang_detector.end_frame(vblank_seen) {
+                    self.events.push(event);
+                }
+            }
+            if let Some((addr, count)) = self.cpu.memory().ppu_mut().take_ignored_warmup_writes() {
+                self.events.push(SystemEvent::WarmupWritesIgnored { addr, count });
+            }
+            if let Some(lint) = self.lint.as_mut() {
+                let memory = self.cpu.memory();
+                let mask = memory.ppu().mask;
+                let reset_vector = u16::from_le_bytes([memory.peek(0xFFFC), memory.peek(0xFFFD)]);
+                lint.end_frame(
+                    mask.intersects(PpuMask::SHOW_BG | PpuMask::SHOW_SPRITES),
+                    mask.contains(PpuMask::SHOW_SPRITES),
+                    reset_vector,
+                    self.frame,
+                );
+            }
+        }
+        
+        Ok(clocked as u16)
     }
     
     /// Run for a specified number of cycles
-    pub fn run_cycles(&mut self, cycles: u64) -> Result<()> {
-        let target = self.cpu.cycles + cycles;
-        while self.cpu.cycles < target {
+    ///
+    /// Instructions can't be split, so the last one may run up to one
+    /// instruction (plus an interrupt entry) past the target. The overrun
+    /// is taken off the next call (see [`CycleBudget`]), so repeated calls
+    /// average out to exactly the requested rate. Returns the cycles run
+    /// minus `cycles`: positive when this call ran over, negative when it
+    /// made up for an earlier one, or cut short by a breakpoint.
+    pub fn run_cycles(&mut self, cycles: u64) -> Result<i64> {
+        self.run_cycles_with(cycles, |_| {})
+    }
+    
+    /// [`Self::run_cycles`], calling `before_step` before every instruction
+    fn run_cycles_with<F: FnMut(&mut Self)>(&mut self, cycles: u64, mut before_step: F) -> Result<i64> {
+        let start = self.cpu.cycles;
+        let allotted = self.cycle_budget.allot(cycles);
+        while self.cpu.cycles - start < allotted {
+            if self.check_breakpoint() {
+                break;
+            }
+            before_step(self);
             self.step()?;  // Use self.step() instead of cpu.step() to run PPU
         }
-        Ok(())
+        let ran = self.cpu.cycles - start;
+        self.cycle_budget.settle(allotted, ran);
+        Ok(ran as i64 - cycles as i64)
     }
     
-    /// Run for one frame (approximately 29780 cycles for NTSC)
+    /// Run for one frame ([`Self::cycles_per_frame`]: 29780 cycles on
+    /// NTSC, 33247 on PAL)
+    ///
+    /// A breakpoint ends the frame early; it still counts as a frame.
     pub fn run_frame(&mut self) -> Result<()> {
-        const CYCLES_PER_FRAME: u64 = 29780;
-        self.run_cycles(CYCLES_PER_FRAME)?;
+        self.run_frame_with(|_| {})
+    }
+    
+    /// [`Self::run_frame`], calling `before_step` before every instruction
+    ///
+    /// For instruction traces: the closure sees the CPU about to execute
+    /// the instruction at PC. Timing is identical to [`Self::run_frame`].
+    pub fn run_frame_with<F: FnMut(&mut Self)>(&mut self, before_step: F) -> Result<()> {
+        self.apply_input_script();
+        let started = self.profiler.is_some().then(Instant::now);
+        self.run_cycles_with(self.cycles_per_frame(), before_step)?;
         self.frame += 1;
+        self.cpu.memory().set_frame(self.frame);
+        
+        let sinks_started = started.map(|_| Instant::now());
+        if !self.snapshot_sinks.is_empty() {
+            let snapshot = self.snapshot_for_analysis();
+            for sink in &mut self.snapshot_sinks {
+                sink.on_snapshot(&snapshot);
+            }
+        }
+        if let (Some(profiler), Some(started), Some(sinks_started)) = (self.profiler.as_mut(), started, sinks_started) {
+            profiler.end_frame(started.elapsed(), sinks_started.elapsed());
+        }
+        Ok(())
+    }
+    
+    /// Run `frames` frames, optionally drawing only what the last one shows
+    ///
+    /// A frame's worth of CPU cycles doesn't start at the top of the PPU
+    /// frame, so the picture after a [`Self::run_frame`] is drawn partly in
+    /// that call and partly in the one before. With `render_last_only` the
+    /// final two calls draw and the rest only keep time. The previous
+    /// [`Self::set_render_enabled`] setting is restored afterwards.
+    pub fn run_frames(&mut self, frames: u64, render_last_only: bool) -> Result<()> {
+        let rendering = self.render_enabled();
+        let result = (0..frames).try_for_each(|i| {
+            if render_last_only {
+                self.set_render_enabled(rendering && frames - i <= 2);
+            }
+            self.run_frame()
+        });
+        self.set_render_enabled(rendering);
+        result
+    }
+    
+    /// Turn PPU pixel output on or off (on by default)
+    ///
+    /// With it off, timing, vblank and NMI are unchanged but the framebuffer
+    /// keeps its last contents, which makes headless runs several times
+    /// faster. The next full frame drawn after turning it back on matches a
+    /// run that never turned it off.
+    pub fn set_render_enabled(&mut self, enabled: bool) {
+        self.cpu.memory().ppu_mut().set_render_enabled(enabled);
+    }
+    
+    /// Check whether the PPU is drawing pixels
+    pub fn render_enabled(&mut self) -> bool {
+        self.cpu.memory().ppu().render_enabled()
+    }
+    
+    /// Choose which opt-in hardware behaviours to emulate (none by default)
+    ///
+    /// These are settings, so they survive resets and savestate loads.
+    pub fn set_accuracy_flags(&mut self, flags: AccuracyFlags) {
+        self.cpu.memory().ppu_mut().set_accuracy(flags);
+    }
+    
+    /// Opt-in hardware behaviours in effect
+    pub fn accuracy_flags(&mut self) -> AccuracyFlags {
+        self.cpu.memory().ppu().accuracy()
+    }
+    
+    /// Copy RAM, CPU registers, counters and controller input
+    ///
+    /// Taken between instructions, so it is always consistent. Costs one
+    /// 2KB copy.
+    pub fn snapshot_for_analysis(&mut self) -> AnalysisSnapshot {
+        let memory = self.cpu.memory();
+        let ram = *memory.ram();
+        let controller1 = memory.controller1_ref().state_ref().buttons;
+        let controller2 = memory.controller2_ref().state_ref().buttons;
+        AnalysisSnapshot {
+            frame: self.frame,
+            cpu_cycles: self.cpu.cycles,
+            a: self.cpu.a,
+            x: self.cpu.x,
+            y: self.cpu.y,
+            sp: self.cpu.sp,
+            pc: self.cpu.pc,
+            status: self.cpu.status.bits(),
+            controller1,
+            controller2,
+            ram,
+        }
+    }
+    
+    /// Register a sink to receive a snapshot at the end of every frame
+    pub fn add_snapshot_sink(&mut self, sink: Box<dyn SnapshotSink>) {
+        self.snapshot_sinks.push(sink);
+    }
+    
+    /// Remove all snapshot sinks
+    pub fn clear_snapshot_sinks(&mut self) {
+        self.snapshot_sinks.clear();
+    }
+    
+    /// Get the CRC32 of the loaded ROM's PRG and CHR data
+    pub fn rom_crc32(&mut self) -> Option<u32> {
+        self.cpu.memory().rom_crc32()
+    }
+    
+    /// Get the loaded cartridge's iNES mapper number
+    pub fn mapper(&mut self) -> Option<u8> {
+        self.cpu.memory().cartridge().map(|cart| cart.header().mapper)
+    }
+    
+    /// Describe the loaded cartridge, after any ROM database correction
+    pub fn cartridge_info(&mut self) -> Option<CartridgeInfo> {
+        self.cpu.memory().cartridge().map(Cartridge::info)
+    }
+    
+    /// Get which PRG and CHR banks the cartridge has mapped, if one is loaded
+    pub fn bank_state(&mut self) -> Option<BankState> {
+        self.cpu.memory().bank_state()
+    }
+    
+    /// Snapshot the full machine state
+    ///
+    /// The state is tied to the current ROM; see [`savestate`] for the format.
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.raw(savestate::MAGIC);
+        w.u16(savestate::VERSION);
+        w.u32(self.rom_crc32().unwrap_or(0));
+        self.save_body(&mut w);
+        w.finish()
+    }
+    
+    /// Restore a state from [`NesSystem::save_state`]
+    ///
+    /// Fails without changing anything if the data is corrupt, from another
+    /// format version, or was saved with a different ROM.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        self.load_state_checked(data, true)
+    }
+    
+    /// Restore a state saved with a different build of the loaded game
+    ///
+    /// Like [`NesSystem::load_state`] but skips the ROM CRC32 check, for
+    /// carrying a session over when a homebrew ROM is rebuilt. It still fails
+    /// without changing anything if the layout doesn't match (e.g. the
+    /// mapper or RAM sizes changed).
+    pub fn transplant_state(&mut self, data: &[u8]) -> Result<()> {
+        self.load_state_checked(data, false)
+    }
+    
+    fn load_state_checked(&mut self, data: &[u8], check_rom: bool) -> Result<()> {
+        let mut r = StateReader::new(data);
+        if r.raw(savestate::MAGIC.len())? != savestate::MAGIC {
+            return Err(EmulatorError::InvalidSaveState("not a savestate".into()));
+        }
+        let version = r.u16()?;
+        if version != savestate::VERSION {
+            return Err(EmulatorError::InvalidSaveState(format!(
+                "format version {} (expected {})",
+                version,
+                savestate::VERSION
+            )));
+        }
+        let crc = r.u32()?;
+        let rom_crc = self.rom_crc32().unwrap_or(0);
+        if check_rom && crc != rom_crc {
+            return Err(EmulatorError::InvalidSaveState(format!(
+                "saved with ROM CRC32 {:08X}, loaded ROM is {:08X}",
+                crc, rom_crc
+            )));
+        }
+        
+        // Components load in place, so keep a copy to roll back a half-applied state
+        let mut backup = StateWriter::new();
+        self.save_body(&mut backup);
+        let backup = backup.finish();
+        if let Err(e) = self.load_body(&mut r) {
+            self.load_body(&mut StateReader::new(&backup))
+                .expect("restoring our own savestate");
+            return Err(e);
+        }
+        
+        let enabled = self.hang_detector.enabled;
+        self.hang_detector = HangDetector::new();
+        self.hang_detector.enabled = enabled;
+        self.hang_detector.ppu_frame = self.cpu.memory().ppu().frame();
+        self.events.clear();
+        if let Some(log) = self.cpu.memory().event_log_mut() {
+            log.take();
+        }
+        if let Some(lint) = self.lint.as_mut() {
+            lint.reset();
+        }
+        Ok(())
+    }
+    
+    /// Snapshot the machine state in memory, for run-ahead
+    ///
+    /// Cheaper than [`NesSystem::save_state`]: there's no header to write,
+    /// and [`NesSystem::quick_load`] skips the format checks and rollback
+    /// copy. Quick states can't be written out; use savestates for that.
+    pub fn quick_save(&mut self) -> QuickState {
+        QuickState {
+            rom_crc: self.rom_crc32().unwrap_or(0),
+            body: self.state_body(),
+            hang_detector: self.hang_detector.clone(),
+            events: self.events.len(),
+            event_log: self.cpu.memory().event_log_mut().map(|log| log.mark()),
+            audio: self.cpu.memory().apu().sample_buffer().clone(),
+            lint: self.lint.clone(),
+        }
+    }
+    
+    /// Go back to a state from [`NesSystem::quick_save`]
+    ///
+    /// Events raised since it was taken are dropped. Fails without changing
+    /// anything if the state was taken with a different ROM loaded.
+    pub fn quick_load(&mut self, state: &QuickState) -> Result<()> {
+        let rom_crc = self.rom_crc32().unwrap_or(0);
+        if state.rom_crc != rom_crc {
+            return Err(EmulatorError::InvalidSaveState(format!(
+                "quick state taken with ROM CRC32 {:08X}, loaded ROM is {:08X}",
+                state.rom_crc, rom_crc
+            )));
+        }
+        self.load_body(&mut StateReader::new(&state.body))
+            .expect("restoring a quick state taken with this ROM");
+        
+        let enabled = self.hang_detector.enabled;
+        self.hang_detector = state.hang_detector.clone();
+        self.hang_detector.enabled = enabled;
+        self.events.truncate(state.events);
+        if let (Some(log), Some(mark)) = (self.cpu.memory().event_log_mut(), state.event_log) {
+            log.rewind(mark);
+        }
+        self.cpu.memory().apu_mut().set_sample_buffer(state.audio.clone());
+        // Whether the lint is on is a setting, and stays as it is
+        if let (Some(lint), Some(saved)) = (self.lint.as_mut(), state.lint.as_ref()) {
+            lint.clone_from(saved);
+        }
         Ok(())
     }
     
+    /// Hash of the emulation state, for cheap equality checks
+    ///
+    /// Two systems with the same digest behave the same from here on.
+    pub fn state_digest(&self) -> StateDigest {
+        StateDigest::of(self)
+    }
+    
+    /// The savestate body, without the header
+    pub(crate) fn state_body(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        self.save_body(&mut w);
+        w.finish()
+    }
+    
+    /// Save everything after the savestate header
+    fn save_body(&self, w: &mut StateWriter) {
+        self.cpu.save(w);
+        w.u64(self.frame);
+        w.u64(self.clock_base.ppu_dots);
+        w.u64(self.clock_base.apu_cycles);
+        w.u64(self.clock_base.ppu_frames);
+        w.u64(self.cycle_budget.overshoot);
+    }
+    
+    /// Load everything after the savestate header
+    fn load_body(&mut self, r: &mut StateReader) -> Result<()> {
+        self.cpu.load(r)?;
+        self.frame = r.u64()?;
+        self.cpu.memory().set_frame(self.frame);
+        self.clock_base.ppu_dots = r.u64()?;
+        self.clock_base.apu_cycles = r.u64()?;
+        self.clock_base.ppu_frames = r.u64()?;
+        self.cycle_budget.overshoot = r.u64()?;
+        r.finish()
+    }
+    
     /// Get current frame number
     pub fn frame(&self) -> u64 {
         self.frame
@@ -148,6 +1180,148 @@ impl NesSystem {
         self.cpu.memory().ppu()
     }
     
+    /// Copy out a whole memory region
+    ///
+    /// A region the cartridge doesn't have (PRG-RAM on a board without
+    /// it, CHR-RAM on a board with CHR-ROM) comes out empty.
+    pub fn export_region(&mut self, region: MemoryRegion) -> Vec<u8> {
+        self.region_mut(region).to_vec()
+    }
+    
+    /// Overwrite a whole memory region with `data`
+    ///
+    /// `data` must be exactly the region's size, as [`NesSystem::export_region`]
+    /// returns it; anything else is [`EmulatorError::RegionSizeMismatch`] and
+    /// changes nothing. Importing is safe at any time, paused or not, but
+    /// the PPU regions take effect from the next pixel drawn, so an import
+    /// mid-frame can tear that frame.
+    pub fn import_region(&mut self, region: MemoryRegion, data: &[u8]) -> Result<()> {
+        let target = self.region_mut(region);
+        if target.len() != data.len() {
+            return Err(EmulatorError::RegionSizeMismatch {
+                region: region.to_string(),
+                expected: target.len(),
+                found: data.len(),
+            });
+        }
+        target.copy_from_slice(data);
+        Ok(())
+    }
+    
+    /// Write a memory region to a file, as [`NesSystem::export_region`] returns it
+    pub fn export_region_to<P: AsRef<Path>>(&mut self, region: MemoryRegion, path: P) -> Result<()> {
+        std::fs::write(path, self.export_region(region))?;
+        Ok(())
+    }
+    
+    /// Load a memory region from a file written by [`NesSystem::export_region_to`]
+    pub fn import_region_from<P: AsRef<Path>>(&mut self, region: MemoryRegion, path: P) -> Result<()> {
+        let data = std::fs::read(path)?;
+        self.import_region(region, &data)
+    }
+    
+    /// What the cartridge's battery keeps, to write to a `.sav` file
+    ///
+    /// The battery-backed part of PRG-RAM, and CHR-RAM too if the cartridge
+    /// has it; see [`battery`](crate::battery) for the format. `None`
+    /// without a cartridge or if it has no battery.
+    pub fn battery_save(&mut self) -> Option<Vec<u8>> {
+        let memory = self.cpu.memory();
+        let cart = memory.cartridge().filter(|cart| cart.header().has_battery)?;
+        let save = BatterySave {
+            prg_ram: cart.prg_nvram(),
+            chr_ram: cart.has_chr_ram().then(|| memory.ppu().chr()),
+        };
+        Some(save.encode())
+    }
+    
+    /// Restore a battery save from [`NesSystem::battery_save`]
+    ///
+    /// Load it before the first frame, as the game expects to find it at
+    /// power-on. Fails without changing anything if there's no battery or
+    /// the sizes don't match the cartridge.
+    pub fn load_battery_save(&mut self, data: &[u8]) -> Result<()> {
+        let save = BatterySave::decode(data);
+        let memory = self.cpu.memory();
+        let chr_len = memory.ppu().chr().len();
+        let Some(cart) = memory.cartridge_mut().filter(|cart| cart.header().has_battery) else {
+            return Err(EmulatorError::InvalidBatterySave("the cartridge has no battery".into()));
+        };
+        save.check(cart.prg_nvram().len(), cart.has_chr_ram().then_some(chr_len))?;
+        cart.prg_nvram_mut().copy_from_slice(save.prg_ram);
+        if let Some(chr) = save.chr_ram {
+            memory.ppu_mut().chr_mut().copy_from_slice(chr);
+        }
+        Ok(())
+    }
+    
+    fn region_mut(&mut self, region: MemoryRegion) -> &mut [u8] {
+        let memory = self.cpu.memory();
+        let chr_ram = memory.cartridge().is_some_and(Cartridge::has_chr_ram);
+        match region {
+            MemoryRegion::CpuRam => memory.ram_mut(),
+            MemoryRegion::PpuVram => memory.ppu_mut().vram_mut(),
+            MemoryRegion::PpuPalette => memory.ppu_mut().palette_mut(),
+            MemoryRegion::Oam => memory.ppu_mut().oam_mut(),
+            MemoryRegion::PrgRam => match memory.cartridge_mut() {
+                Some(cart) => cart.prg_ram_mut(),
+                None => &mut [],
+            },
+            // CHR-RAM lives in the PPU's copy of the pattern tables
+            MemoryRegion::ChrRam if chr_ram => memory.ppu_mut().chr_mut(),
+            MemoryRegion::ChrRam => &mut [],
+        }
+    }
+    
+    /// Write pattern table data directly (debug override, works on CHR-ROM carts)
+    pub fn write_chr(&mut self, addr: u16, data: &[u8]) {
+        self.cpu.memory().ppu_mut().poke_chr(addr, data);
+    }
+    
+    /// Write into nametable `nt` (0-3) at `offset` (0-$3FF, including attributes)
+    ///
+    /// Bytes past the end of the nametable are dropped.
+    pub fn write_nametable(&mut self, nt: usize, offset: u16, data: &[u8]) {
+        let offset = offset.min(0x400);
+        let len = data.len().min((0x400 - offset) as usize);
+        let addr = 0x2000 + (nt as u16 & 0x03) * 0x400 + offset;
+        self.cpu.memory().ppu_mut().poke_nametable(addr, &data[..len]);
+    }
+    
+    /// Write `code` into the RAM cart from `addr` ($8000-$FFFF)
+    ///
+    /// Bytes past $FFFF are dropped. Fails if the cartridge isn't a RAM
+    /// cart (see [`Self::with_ram_cart`]) or `addr` is below $8000.
+    pub fn write_code(&mut self, addr: u16, code: &[u8]) -> Result<()> {
+        let cart = self.cpu.memory().cartridge_mut().filter(|cart| cart.is_ram_cart());
+        let Some(cart) = cart.filter(|_| addr >= 0x8000) else {
+            return Err(EmulatorError::InvalidAddress(addr as u32));
+        };
+        for (addr, &value) in (addr..=0xFFFF).zip(code) {
+            cart.write_prg(addr, value);
+        }
+        Ok(())
+    }
+    
+    /// Point the NMI, reset and IRQ vectors of the RAM cart at `nmi`,
+    /// `reset` and `irq`
+    ///
+    /// The CPU goes to the new reset vector at the next [`Self::reset`].
+    pub fn set_vectors(&mut self, nmi: u16, reset: u16, irq: u16) -> Result<()> {
+        let [nmi, reset, irq] = [nmi, reset, irq].map(u16::to_le_bytes);
+        self.write_code(0xFFFA, &[nmi[0], nmi[1], reset[0], reset[1], irq[0], irq[1]])
+    }
+    
+    /// Write a palette RAM entry (index 0-31)
+    pub fn write_palette(&mut self, index: u8, value: u8) {
+        self.cpu.memory().ppu_mut().poke_palette(index, value);
+    }
+    
+    /// Write OAM bytes starting at `offset` (wraps at 256)
+    pub fn write_oam(&mut self, offset: u8, data: &[u8]) {
+        self.cpu.memory().ppu_mut().poke_oam(offset, data);
+    }
+    
     /// Get APU reference
     pub fn apu(&mut self) -> &crate::apu::Apu {
         self.cpu.memory().apu()
@@ -159,6 +1333,40 @@ impl NesSystem {
         self.cpu.memory().apu().output()
     }
     
+    /// Get the current (left, right) audio sample, placed by the stereo config
+    pub fn audio_sample_stereo(&mut self) -> (f32, f32) {
+        self.cpu.memory().apu().output_stereo()
+    }
+    
+    /// Append the sound since the last call as samples at `output_rate` Hz
+    ///
+    /// The samples only depend on the cycles run, however they were split
+    /// into frames or [`NesSystem::run_cycles`] calls; see
+    /// [`crate::apu::Apu::take_samples`].
+    pub fn take_samples(&mut self, output_rate: u32, out: &mut Vec<f32>) {
+        self.cpu.memory().apu_mut().take_samples(output_rate, out);
+    }
+    
+    /// [`NesSystem::take_samples`] as (left, right) pairs, placed by the stereo config
+    pub fn take_samples_stereo(&mut self, output_rate: u32, out: &mut Vec<(f32, f32)>) {
+        self.cpu.memory().apu_mut().take_samples_stereo(output_rate, out);
+    }
+    
+    /// Set how [`NesSystem::audio_sample_stereo`] spreads the channels
+    pub fn set_stereo_config(&mut self, config: StereoConfig) {
+        self.cpu.memory().apu_mut().set_stereo_config(config);
+    }
+    
+    /// Mute or unmute a sound channel; see [`crate::apu::Apu::set_muted`]
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.cpu.memory().apu_mut().set_muted(channel, muted);
+    }
+    
+    /// Clamp the triangle and noise timers on period writes; see [`crate::apu::Apu::set_reduce_popping`]
+    pub fn set_reduce_popping(&mut self, enabled: bool) {
+        self.cpu.memory().apu_mut().set_reduce_popping(enabled);
+    }
+    
     /// Get controller 1 reference
     pub fn controller1(&mut self) -> &mut Controller {
         self.cpu.memory().controller1()
@@ -169,6 +1377,24 @@ impl NesSystem {
         self.cpu.memory().controller2()
     }
     
+    /// The $4016 latch, for attaching an expansion port device
+    pub fn controller_port(&mut self) -> &mut ControllerPort {
+        self.cpu.memory().controller_port()
+    }
+    
+    /// Buttons the game latched on controller `player` (1 or 2) at its last strobe
+    ///
+    /// Unlike the controller's button state, this is what the game actually
+    /// saw, so it's what an input display should show. Other player numbers
+    /// have nothing latched.
+    pub fn last_latched_input(&mut self, player: u8) -> u8 {
+        match player {
+            1 => self.controller1().last_latched(),
+            2 => self.controller2().last_latched(),
+            _ => 0,
+        }
+    }
+    
     /// Set controller 1 button state
     pub fn set_button(&mut self, button: Button, pressed: bool) {
         self.controller1().state().set(button, pressed);
@@ -183,6 +1409,154 @@ impl NesSystem {
     pub fn release_button(&mut self, button: Button) {
         self.controller1().state().release(button);
     }
+    
+    /// Play an input script on controller 1, starting with the next frame
+    ///
+    /// Frame `n` of the schedule is what the game sees during the `n`th
+    /// call to [`NesSystem::run_frame`] from now, instead of or as well as
+    /// the buttons held (see [`ScriptMode`]). Held buttons aren't changed,
+    /// so the player has the controller back once the schedule runs out.
+    /// Replaces a script already playing.
+    pub fn attach_input_script(&mut self, schedule: InputSchedule, mode: ScriptMode) {
+        self.input_script = Some(ScriptPlayback {
+            schedule,
+            mode,
+            start_frame: self.frame,
+        });
+    }
+    
+    /// Stop the input script, if one is playing
+    pub fn detach_input_script(&mut self) {
+        self.input_script = None;
+        self.controller1().set_scripted(None);
+    }
+    
+    /// Check whether an input script still has frames to play
+    pub fn input_script_running(&self) -> bool {
+        self.input_script.as_ref().is_some_and(|script| !script.finished_by(self.frame))
+    }
+    
+    /// Hand controller 1 this frame's scripted buttons
+    ///
+    /// A finished script is kept until detached: loading an earlier state
+    /// (run-ahead does every frame) goes back into it.
+    fn apply_input_script(&mut self) {
+        let frame = self.frame;
+        let scripted = self
+            .input_script
+            .as_ref()
+            .and_then(|script| Some((script.buttons_at(frame)?, script.mode)));
+        self.controller1().set_scripted(scripted);
+    }
+}
+
+impl Clone for NesSystem {
+    fn clone(&self) -> Self {
+        Self {
+            cpu: self.cpu.clone(),
+            frame: self.frame,
+            paused: self.paused,
+            hang_detector: self.hang_detector.clone(),
+            events: self.events.clone(),
+            clock_base: self.clock_base,
+            cycle_budget: self.cycle_budget,
+            snapshot_sinks: Vec::new(),
+            power_up_state: self.power_up_state,
+            profiler: None,
+            lint: self.lint.clone(),
+            breakpoints: self.breakpoints.clone(),
+            stopped_at: self.stopped_at,
+            input_script: self.input_script.clone(),
+            region: self.region,
+            instruction_hook: None,
+        }
+    }
+}
+
+impl fmt::Debug for NesSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NesSystem")
+            .field("cpu", &self.cpu)
+            .field("frame", &self.frame)
+            .field("paused", &self.paused)
+            .field("events", &self.events)
+            .field("clock_base", &self.clock_base)
+            .field("cycle_budget", &self.cycle_budget)
+            .field("snapshot_sinks", &self.snapshot_sinks.len())
+            .field("power_up_state", &self.power_up_state)
+            .field("profiling", &self.profiler.is_some())
+            .field("lint", &self.lint.as_ref().map(|lint| lint.sink().len()))
+            .field("breakpoints", &self.breakpoints)
+            .field("stopped_at", &self.stopped_at)
+            .field("input_script", &self.input_script.as_ref().map(|script| script.schedule.len()))
+            .field("region", &self.region)
+            .field("instruction_hook", &self.instruction_hook.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Emulator for NesSystem {
+    fn reset(&mut self) {
+        NesSystem::reset(self);
+    }
+
+    fn run_frame(&mut self) -> Result<usize> {
+        if self.paused {
+            return Ok(0);
+        }
+        let start = self.cpu.cycles;
+        NesSystem::run_frame(self)?;
+        Ok((self.cpu.cycles - start) as usize)
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn run_frame_with_audio(&mut self, sample_rate: u32, audio: &mut Vec<(f32, f32)>) -> Result<usize> {
+        if self.paused {
+            return Ok(0);
+        }
+        let start = self.cpu.cycles;
+        let result = NesSystem::run_frame(self);
+        self.take_samples_stereo(sample_rate, audio);
+        result.map(|()| (self.cpu.cycles - start) as usize)
+    }
+    
+    fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+    
+    fn screen_size(&self) -> (usize, usize) {
+        (SCREEN_WIDTH, SCREEN_HEIGHT)
+    }
+    
+    fn frame_rate(&self) -> f64 {
+        let clock_hz = match self.region {
+            Region::Pal => PAL_CPU_CLOCK_HZ,
+            _ => CPU_CLOCK_HZ,
+        };
+        clock_hz as f64 / self.cycles_per_frame() as f64
+    }
+    
+    fn framebuffer_rgba(&mut self) -> Vec<u8> {
+        let framebuffer = self.framebuffer();
+        let mut rgba = vec![0; framebuffer.len() * 4];
+        framebuffer_to_rgba_fast(framebuffer, 0, &mut rgba);
+        rgba
+    }
+    
+    fn input_device(&mut self, port: usize) -> Option<&mut dyn InputDevice> {
+        match port {
+            0 => Some(self.controller1().state()),
+            1 => Some(self.controller2().state()),
+            _ => None,
+        }
+    }
+    
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 #[cfg(test)]
@@ -228,4 +1602,667 @@ mod tests {
         system.step().unwrap();
         assert_eq!(system.read_memory(0x00), 0x42);
     }
+    
+    #[test]
+    fn test_mid_frame_nametable_switch() {
+        // Each frame: wait for vblank, select nametable 0, burn ~13680 cycles
+        // (to around scanline 99 of the next frame), then select nametable 1
+        #[rustfmt::skip]
+        let program = [
+            0x78,             // SEI
+            0x2C, 0x02, 0x20, // vbl: BIT $2002
+            0x10, 0xFB,       //      BPL vbl
+            0xA9, 0x00,       //      LDA #$00
+            0x8D, 0x05, 0x20, //      STA $2005
+            0x8D, 0x05, 0x20, //      STA $2005
+            0x8D, 0x00, 0x20, //      STA $2000
+            0xA0, 0x1B,       //      LDY #27
+            0xA2, 0x64,       // out: LDX #100
+            0xCA,             // in:  DEX
+            0xD0, 0xFD,       //      BNE in
+            0x88,             //      DEY
+            0xD0, 0xF8,       //      BNE out
+            0xA9, 0x01,       //      LDA #$01
+            0x8D, 0x00, 0x20, //      STA $2000
+            0x4C, 0x01, 0x80, //      JMP vbl
+        ];
+        let mut prg_rom = vec![0xEA; 0x4000];
+        prg_rom[..program.len()].copy_from_slice(&program);
+        prg_rom[0x3FFC] = 0x00;
+        prg_rom[0x3FFD] = 0x80;
+        
+        let mut system = NesSystem::with_prg_rom(prg_rom).unwrap();
+        {
+            let ppu = system.cpu_mut().memory().ppu_mut();
+            let mut write_vram = |addr: u16, values: &[u8]| {
+                ppu.write_register(0x2006, (addr >> 8) as u8);
+                ppu.write_register(0x2006, addr as u8);
+                for &value in values {
+                    ppu.write_register(0x2007, value);
+                }
+            };
+            // Tile 1 is solid colour 1; nametable 0 stays all tile 0
+            write_vram(0x0010, &[0xFF; 8]);
+            write_vram(0x2400, &[0x01; 960]);
+            write_vram(0x3F00, &[0x0F, 0x30]);
+            ppu.write_register(0x2001, 0x08);
+        }
+        
+        for _ in 0..3 {
+            system.run_frame().unwrap();
+        }
+        
+        let framebuffer = system.framebuffer();
+        for y in (0..95).step_by(5) {
+            assert_eq!(framebuffer[y * 256 + 128], 0x0F, "row {} should use nametable 0", y);
+        }
+        for y in (105..240).step_by(5) {
+            assert_eq!(framebuffer[y * 256 + 128], 0x30, "row {} should use nametable 1", y);
+        }
+    }
+    
+    /// Build a 16KB PRG-ROM with `program` at $8000 and an RTI NMI handler at $9000
+    fn rom_with_program(program: &[u8]) -> Vec<u8> {
+        let mut prg_rom = vec![0xEA; 0x4000];
+        prg_rom[..program.len()].copy_from_slice(program);
+        prg_rom[0x1000] = 0x40; // RTI
+        prg_rom[0x3FFA] = 0x00;
+        prg_rom[0x3FFB] = 0x90;
+        prg_rom[0x3FFC] = 0x00;
+        prg_rom[0x3FFD] = 0x80;
+        prg_rom
+    }
+    
+    fn events_after_frames(system: &mut NesSystem, frames: usize) -> Vec<SystemEvent> {
+        for _ in 0..frames {
+            system.run_frame().unwrap();
+        }
+        system.poll_events()
+    }
+    
+    #[test]
+    fn test_hang_detected_waiting_for_nmi_flag() {
+        // NMI is never enabled, so the flag at $10 is never set
+        #[rustfmt::skip]
+        let program = [
+            0xA5, 0x10, // wait: LDA $10
+            0xF0, 0xFC, //       BEQ wait
+        ];
+        let mut system = NesSystem::with_prg_rom(rom_with_program(&program)).unwrap();
+        
+        let events = events_after_frames(&mut system, 5);
+        assert_eq!(events, vec![SystemEvent::PossibleHang { pc: 0x8000, loop_len: 2 }]);
+        
+        // Reported once, not every frame
+        assert!(events_after_frames(&mut system, 5).is_empty());
+    }
+    
+    #[test]
+    fn test_hang_detection_opt_out() {
+        let program = [0xA5, 0x10, 0xF0, 0xFC];
+        let mut system = NesSystem::with_prg_rom(rom_with_program(&program)).unwrap();
+        system.set_hang_detection(false);
+        
+        assert!(!system.hang_detection());
+        assert!(events_after_frames(&mut system, 5).is_empty());
+    }
+    
+    #[test]
+    fn test_no_false_hang_on_test_roms() {
+        // midi2nes output: polls $2002 for vblank every frame
+        let mary = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../examples/midi2nes/test_mary.nes");
+        let mut system = NesSystem::new_quiet(&mary).unwrap();
+        assert!(events_after_frames(&mut system, 120).is_empty());
+        
+        // generate_test_rom / generate_controller_test: park in JMP * with NMI off
+        let mut system = NesSystem::with_prg_rom(rom_with_program(&[0x4C, 0x00, 0x80])).unwrap();
+        assert!(events_after_frames(&mut system, 60).is_empty());
+        
+        // generate_perfect_visual / generate_scrolling_tests: NMI on, then JMP *
+        #[rustfmt::skip]
+        let program = [
+            0xA9, 0x80,       // LDA #$80
+            0x8D, 0x00, 0x20, // STA $2000
+            0x4C, 0x05, 0x80, // JMP *
+        ];
+        let mut system = NesSystem::with_prg_rom(rom_with_program(&program)).unwrap();
+        assert!(events_after_frames(&mut system, 60).is_empty());
+        
+        // Tight vblank wait loop
+        #[rustfmt::skip]
+        let program = [
+            0x2C, 0x02, 0x20, // wait: BIT $2002
+            0x10, 0xFB,       //       BPL wait
+            0x4C, 0x00, 0x80, //       JMP wait
+        ];
+        let mut system = NesSystem::with_prg_rom(rom_with_program(&program)).unwrap();
+        assert!(events_after_frames(&mut system, 60).is_empty());
+    }
+    
+    #[test]
+    fn test_clock_drift_bounded() {
+        // NMI every frame (7 extra cycles each) and rendering on (odd-frame skip)
+        #[rustfmt::skip]
+        let program = [
+            0xA9, 0x80,       // LDA #$80
+            0x8D, 0x00, 0x20, // STA $2000
+            0xA9, 0x08,       // LDA #$08
+            0x8D, 0x01, 0x20, // STA $2001
+            0xE6, 0x10,       // loop: INC $10
+            0x4C, 0x0A, 0x80, //       JMP loop
+        ];
+        let mut system = NesSystem::with_prg_rom(rom_with_program(&program)).unwrap();
+        
+        for _ in 0..10_000 {
+            system.run_frame().unwrap();
+        }
+        
+        let stats = system.clock_stats();
+        assert_eq!(stats.frames, 10_000);
+        assert_eq!(stats.ppu_drift(), 0);
+        assert_eq!(stats.apu_drift(), 0);
+        assert!(stats.frame_drift().abs() <= 1, "{:?}", stats);
+        // run_cycles credits overruns, so the average rate is exact
+        assert!(stats.cpu_cycles - 10_000 * 29780 < 8, "{:?}", stats);
+    }
+    
+    #[test]
+    fn test_cycle_budget_averages_out() {
+        // Instructions of 2-7 cycles, cycling through every length
+        let mut budget = CycleBudget::new();
+        let mut lengths = (2..=7u64).cycle();
+        let mut total = 0;
+        for _ in 0..100 {
+            let allotted = budget.allot(1000);
+            let mut ran = 0;
+            while ran < allotted {
+                ran += lengths.next().unwrap();
+            }
+            budget.settle(allotted, ran);
+            total += ran;
+        }
+        assert!(total - 100_000 < 7, "{} cycles", total);
+        assert_eq!(total - 100_000, budget.overshoot());
+        
+        // An overshoot bigger than the next slice carries on past it
+        let mut budget = CycleBudget::new();
+        budget.settle(0, 10);
+        assert_eq!(budget.allot(4), 0);
+        assert_eq!(budget.overshoot(), 6);
+        assert_eq!(budget.allot(10), 4);
+    }
+    
+    #[test]
+    fn test_run_cycles_reports_its_overrun() {
+        // INC abs,X (7 cycles) in a loop, so overruns are common
+        #[rustfmt::skip]
+        let program = [
+            0xFE, 0x00, 0x02, // loop: INC $0200,X
+            0x4C, 0x00, 0x80, //       JMP loop
+        ];
+        let mut system = NesSystem::with_prg_rom(rom_with_program(&program)).unwrap();
+        let start = system.clock_stats().cpu_cycles;
+        let mut reported = 0;
+        for _ in 0..100 {
+            reported += system.run_cycles(1000).unwrap();
+        }
+        let total = system.clock_stats().cpu_cycles - start;
+        assert!(total - 100_000 < 7, "{} cycles", total);
+        assert_eq!(reported, total as i64 - 100_000);
+    }
+    
+    #[test]
+    fn test_poke_tile_shows_up_in_frame() {
+        // Spin forever with the background on
+        #[rustfmt::skip]
+        let program = [
+            0xA9, 0x08,       // LDA #$08
+            0x8D, 0x01, 0x20, // STA $2001
+            0x4C, 0x05, 0x80, // JMP *
+        ];
+        let mut system = NesSystem::with_prg_rom(rom_with_program(&program)).unwrap();
+        system.write_palette(0, 0x0F);
+        system.write_palette(3, 0x30);
+        system.run_frame().unwrap();
+        system.run_frame().unwrap();
+        assert_eq!(system.framebuffer()[8 * 256 + 40], 0x0F);
+        
+        // Tile 1 = solid colour 3, placed at tile (5, 1) of nametable 0
+        system.write_chr(0x0010, &[0xFF; 16]);
+        system.write_nametable(0, 32 + 5, &[0x01]);
+        system.run_frame().unwrap();
+        system.run_frame().unwrap();
+        
+        let framebuffer = system.framebuffer();
+        for y in 8..16 {
+            for x in 40..48 {
+                assert_eq!(framebuffer[y * 256 + x], 0x30, "pixel ({}, {})", x, y);
+            }
+        }
+        assert_eq!(framebuffer[8 * 256 + 48], 0x0F);
+        assert_eq!(framebuffer[16 * 256 + 40], 0x0F);
+    }
+    
+    #[test]
+    fn test_poke_oam_and_nametable_bounds() {
+        let mut system = NesSystem::with_prg_rom(rom_with_program(&[0x4C, 0x00, 0x80])).unwrap();
+        system.write_oam(0xFE, &[1, 2, 3]);
+        assert_eq!(system.ppu().oam()[0xFE], 1);
+        assert_eq!(system.ppu().oam()[0xFF], 2);
+        assert_eq!(system.ppu().oam()[0x00], 3);
+        
+        // Writes stop at the end of the nametable
+        system.write_nametable(1, 0x3FF, &[0xAA, 0xBB]);
+        assert_eq!(system.ppu().read_nametable_direct(0x27FF), 0xAA);
+        assert_eq!(system.ppu().read_nametable_direct(0x2800), 0x00);
+    }
+    
+    /// Program that enables NMI and rendering and counts frames into $10
+    fn counting_rom() -> Vec<u8> {
+        #[rustfmt::skip]
+        let program = [
+            0xA9, 0x80, 0x8D, 0x00, 0x20, // LDA #$80; STA $2000
+            0xA9, 0x1E, 0x8D, 0x01, 0x20, // LDA #$1E; STA $2001
+            0x4C, 0x0A, 0x80,             // JMP *
+        ];
+        let mut prg_rom = rom_with_program(&program);
+        prg_rom[0x1000..0x1003].copy_from_slice(&[0xE6, 0x10, 0x40]); // NMI: INC $10; RTI
+        prg_rom
+    }
+    
+    #[test]
+    fn test_breakpoint_pauses_and_resumes() {
+        let mut system = NesSystem::with_prg_rom(counting_rom()).unwrap();
+        // The NMI handler's INC $10
+        system.set_breakpoint(0x9000, true);
+        let run = |system: &mut NesSystem| Emulator::run_frame(system).unwrap();
+        
+        // Frames end early at the breakpoint, then nothing runs until resumed
+        while system.poll_events().is_empty() {
+            run(&mut system);
+        }
+        assert!(system.is_paused());
+        assert_eq!(system.cpu().pc, 0x9000);
+        assert_eq!(run(&mut system), 0);
+        let counter = system.read_memory(0x10);
+        
+        // Resuming runs the instruction at the breakpoint, and stops there
+        // again at the next NMI
+        system.set_paused(false);
+        let mut cycles = 0;
+        while !system.is_paused() {
+            cycles += run(&mut system);
+        }
+        assert_eq!(system.read_memory(0x10), counter + 1);
+        assert_eq!(system.poll_events(), [SystemEvent::BreakpointHit { pc: 0x9000 }]);
+        assert!((29_000..31_000).contains(&cycles), "{} cycles between NMIs", cycles);
+        
+        // Breakpoints are settings: reset keeps them
+        system.reset();
+        assert_eq!(system.breakpoints().collect::<Vec<_>>(), [0x9000]);
+        assert!(!system.toggle_breakpoint(0x9000));
+        assert!(!system.has_breakpoint(0x9000));
+    }
+    
+    #[test]
+    fn test_savestate_round_trip_is_deterministic() {
+        let mut system = NesSystem::with_prg_rom(counting_rom()).unwrap();
+        events_after_frames(&mut system, 30);
+        let state = system.save_state();
+        
+        events_after_frames(&mut system, 30);
+        let expected = (system.framebuffer().to_vec(), system.read_memory(0x10), system.clock_stats());
+        
+        let mut restored = NesSystem::with_prg_rom(counting_rom()).unwrap();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.frame(), 30);
+        events_after_frames(&mut restored, 30);
+        let actual = (restored.framebuffer().to_vec(), restored.read_memory(0x10), restored.clock_stats());
+        assert_eq!(actual, expected);
+        assert!(expected.1 > 0);
+    }
+    
+    #[test]
+    fn test_quick_state_rewinds_exactly() {
+        let mut system = NesSystem::with_prg_rom(counting_rom()).unwrap();
+        events_after_frames(&mut system, 10);
+        let state = system.quick_save();
+        
+        let run = |system: &mut NesSystem| {
+            let mut audio = Vec::new();
+            for _ in 0..5 {
+                system.run_frame_with_audio(44_100, &mut audio).unwrap();
+            }
+            (audio, system.save_state())
+        };
+        let first = run(&mut system);
+        system.quick_load(&state).unwrap();
+        assert_eq!(system.frame(), 10);
+        assert_eq!(run(&mut system), first);
+        
+        // Another ROM's state is refused, leaving the machine as it was
+        let mut other = NesSystem::with_prg_rom(rom_with_program(&[0x4C, 0x00, 0x80])).unwrap();
+        let before = other.save_state();
+        assert!(matches!(other.quick_load(&state), Err(EmulatorError::InvalidSaveState(_))));
+        assert_eq!(other.save_state(), before);
+    }
+    
+    #[test]
+    fn test_quick_state_is_cheap() {
+        let mut system = NesSystem::with_prg_rom(counting_rom()).unwrap();
+        system.run_frame().unwrap();
+        
+        let start = std::time::Instant::now();
+        for _ in 0..100 {
+            let state = system.quick_save();
+            system.quick_load(&state).unwrap();
+        }
+        let per_call = start.elapsed() / 100;
+        assert!(per_call.as_micros() < 1000, "{:?} per quick save and load", per_call);
+    }
+    
+    #[test]
+    fn test_snapshot_sink_gets_one_snapshot_per_frame() {
+        let mut system = NesSystem::with_prg_rom(counting_rom()).unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        system.add_snapshot_sink(Box::new(move |snapshot: &AnalysisSnapshot| {
+            tx.send(snapshot.clone()).unwrap();
+        }));
+        system.press_button(Button::START);
+        
+        for _ in 0..10 {
+            system.run_frame().unwrap();
+        }
+        let snapshots: Vec<AnalysisSnapshot> = rx.try_iter().collect();
+        assert_eq!(snapshots.len(), 10);
+        assert!(snapshots.windows(2).all(|pair| pair[1].frame > pair[0].frame && pair[1].cpu_cycles > pair[0].cpu_cycles));
+        assert_eq!(snapshots[9].frame, system.frame());
+        
+        // The NMI counter in zero page moves every frame
+        assert!(snapshots.windows(2).all(|pair| pair[1].zero_page()[0x10] != pair[0].zero_page()[0x10]));
+        
+        let last = &snapshots[9];
+        assert_eq!(last.pc, system.cpu().pc);
+        assert_eq!(last.controller1, Button::START);
+        for addr in 0..0x0800u16 {
+            assert_eq!(last.ram[addr as usize], system.read_memory(addr), "${:04X}", addr);
+        }
+        
+        system.clear_snapshot_sinks();
+        system.run_frame().unwrap();
+    }
+    
+    #[test]
+    fn test_snapshot_is_cheap() {
+        let mut system = NesSystem::with_prg_rom(counting_rom()).unwrap();
+        system.run_frame().unwrap();
+        
+        let start = std::time::Instant::now();
+        let mut checksum = 0u32;
+        for _ in 0..1000 {
+            checksum = checksum.wrapping_add(system.snapshot_for_analysis().ram[0x10] as u32);
+        }
+        let per_call = start.elapsed() / 1000;
+        assert!(per_call.as_micros() < 50, "{:?} per snapshot (checksum {})", per_call, checksum);
+    }
+    
+    #[test]
+    fn test_savestate_rejects_other_rom_and_corrupt_data() {
+        let mut system = NesSystem::with_prg_rom(counting_rom()).unwrap();
+        events_after_frames(&mut system, 10);
+        let state = system.save_state();
+        
+        // Different ROM: CRC mismatch
+        let mut other = NesSystem::with_prg_rom(rom_with_program(&[0x4C, 0x00, 0x80])).unwrap();
+        assert!(matches!(other.load_state(&state), Err(EmulatorError::InvalidSaveState(_))));
+        
+        // Truncated and padded data fail without touching the running state
+        events_after_frames(&mut system, 5);
+        let before = system.save_state();
+        assert!(system.load_state(&state[..state.len() - 100]).is_err());
+        assert!(system.load_state(&[state.as_slice(), &[0]].concat()).is_err());
+        assert!(system.load_state(b"not a savestate").is_err());
+        assert_eq!(system.save_state(), before);
+    }
+    
+    #[test]
+    fn test_frame_irq_is_level_triggered() {
+        #[rustfmt::skip]
+        let mut prg_rom = rom_with_program(&[
+            0x58,             // CLI
+            0x4C, 0x01, 0x80, // JMP *
+        ]);
+        #[rustfmt::skip]
+        prg_rom[0x1100..0x110C].copy_from_slice(&[
+            0xE6, 0x10,       // INC $10
+            0xA5, 0x10,       // LDA $10
+            0xC9, 0x03,       // CMP #3
+            0x90, 0x03,       // BCC done
+            0xAD, 0x15, 0x40, // LDA $4015 (acknowledge from the third entry on)
+            0x40,             // done: RTI
+        ]);
+        prg_rom[0x3FFE..].copy_from_slice(&[0x00, 0x91]);
+        let mut system = NesSystem::with_prg_rom(prg_rom).unwrap();
+        let run_to = |system: &mut NesSystem, cycles: u64| {
+            while system.cpu().cycles < cycles {
+                system.step().unwrap();
+            }
+            system.read_memory(0x10)
+        };
+        
+        // Nothing until the frame counter's fourth step
+        assert_eq!(run_to(&mut system, 29_000), 0);
+        // Unacknowledged, the handler is re-entered straight after RTI
+        assert_eq!(run_to(&mut system, 31_000), 3);
+        assert!(system.cpu_mut().memory().irq_lines().is_empty());
+        assert_eq!(run_to(&mut system, 58_000), 3);
+        // Next sequence, acknowledged first time
+        assert_eq!(run_to(&mut system, 61_000), 4);
+    }
+    
+    #[test]
+    fn test_transplant_state_into_rebuilt_rom() {
+        let ines = |prg: &[u8]| [b"NES\x1a\x01\x01\x00\x00".as_slice(), &[0; 8], prg, &[0; 0x2000]].concat();
+        let mut system = NesSystem::from_bytes(&ines(&counting_rom())).unwrap();
+        events_after_frames(&mut system, 10);
+        let counter = system.read_memory(0x10);
+        let state = system.save_state();
+        
+        let mut rebuilt_prg = counting_rom();
+        rebuilt_prg[0x2000] ^= 0xFF; // Unused byte, so only the CRC changes
+        let mut rebuilt = NesSystem::from_bytes(&ines(&rebuilt_prg)).unwrap();
+        assert!(rebuilt.load_state(&state).is_err());
+        rebuilt.transplant_state(&state).unwrap();
+        assert_eq!(rebuilt.frame(), 10);
+        assert_eq!(rebuilt.read_memory(0x10), counter);
+        
+        // Corrupt data is still rejected
+        assert!(rebuilt.transplant_state(&state[..state.len() - 100]).is_err());
+    }
+    
+    #[test]
+    fn test_generic_frontend_interface() {
+        let mut system = NesSystem::with_prg_rom(rom_with_program(&[0x4C, 0x00, 0x80])).unwrap();
+        let core: &mut dyn Emulator = &mut system;
+        
+        // 60 frames at 44.1kHz is a little under a second of audio
+        let mut audio = Vec::new();
+        for _ in 0..60 {
+            core.run_frame_with_audio(44_100, &mut audio).unwrap();
+        }
+        let expected = 60.0 * CYCLES_PER_FRAME as f64 * 44_100.0 / CPU_CLOCK_HZ as f64;
+        assert!((audio.len() as f64 - expected).abs() <= 1.0, "{} samples", audio.len());
+        assert!((core.frame_rate() - 60.1).abs() < 0.01);
+        
+        let (width, height) = core.screen_size();
+        assert_eq!(core.framebuffer_rgba().len(), width * height * 4);
+        let pad = core.input_device(1).unwrap();
+        let start = (0..pad.button_count()).find(|&index| pad.button_name(index) == "Start").unwrap();
+        pad.set_button_pressed(start, true);
+        assert!(core.input_device(2).is_none());
+        
+        let system = core.as_any_mut().downcast_mut::<NesSystem>().unwrap();
+        assert_eq!(system.frame(), 60);
+        assert!(system.controller2().state_ref().is_pressed(Button::START));
+    }
+    
+    /// Pulse 1, triangle and noise all playing
+    fn noisy_rom() -> Vec<u8> {
+        let mut asm = nes_asm::Assembler::new(0x8000, 0x4000);
+        asm.label("reset")
+            // Pulses, triangle and noise
+            .lda_imm(0x0F)
+            .sta_abs(0x4015)
+            // 50% duty, constant volume 15
+            .lda_imm(0xBF)
+            .sta_abs(0x4000)
+            .lda_imm(0xFD)
+            .sta_abs(0x4002)
+            .lda_imm(0x00)
+            .sta_abs(0x4003)
+            // Linear counter held
+            .lda_imm(0xFF)
+            .sta_abs(0x4008)
+            .lda_imm(0x40)
+            .sta_abs(0x400A)
+            .lda_imm(0x00)
+            .sta_abs(0x400B)
+            // Noise at constant volume 15
+            .lda_imm(0x3F)
+            .sta_abs(0x400C)
+            .lda_imm(0x03)
+            .sta_abs(0x400E)
+            .sta_abs(0x400F)
+            .label("idle")
+            .jmp("idle")
+            .label("nmi")
+            .rti();
+        asm.vectors("nmi", "reset", "nmi");
+        asm.assemble().unwrap()
+    }
+    
+    #[test]
+    fn test_samples_dont_depend_on_chunking() {
+        let mut frames = NesSystem::with_prg_rom(noisy_rom()).unwrap();
+        let mut whole = Vec::new();
+        for _ in 0..3 {
+            frames.run_frame().unwrap();
+            frames.take_samples(44_100, &mut whole);
+        }
+        let expected = frames.apu().cycles() * 44_100 / CPU_CLOCK_HZ as u64;
+        assert_eq!(whole.len() as u64, expected);
+        assert!(whole.iter().any(|&sample| sample != whole[0]), "no sound");
+        
+        // Single cycles, taking after each, and odd-sized chunks taken now
+        // and then; neither runs past the instruction the frames ended on,
+        // so both stop exactly where the frames did
+        for chunk in [1, 1000] {
+            let mut steps = NesSystem::with_prg_rom(noisy_rom()).unwrap();
+            let mut pieces = Vec::new();
+            while steps.cpu().cycles < frames.cpu().cycles {
+                steps.run_cycles(chunk.min(frames.cpu().cycles - steps.cpu().cycles)).unwrap();
+                if chunk == 1 || steps.cpu().cycles.is_multiple_of(3) {
+                    steps.take_samples(44_100, &mut pieces);
+                }
+            }
+            steps.take_samples(44_100, &mut pieces);
+            assert_eq!(steps.cpu().cycles, frames.cpu().cycles);
+            assert_eq!(pieces, whole, "{}-cycle chunks", chunk);
+        }
+    }
+    
+    #[test]
+    fn test_quick_load_rewinds_untaken_audio() {
+        let mut system = NesSystem::with_prg_rom(noisy_rom()).unwrap();
+        let mut audio = Vec::new();
+        system.run_frame().unwrap();
+        system.run_cycles(1234).unwrap();
+        let state = system.quick_save();
+        
+        let mut first = Vec::new();
+        system.run_frame_with_audio(48_000, &mut first).unwrap();
+        system.quick_load(&state).unwrap();
+        system.run_frame_with_audio(48_000, &mut audio).unwrap();
+        assert_eq!(audio, first);
+        // The part-frame before the state was taken came out with it
+        let cycles = system.apu().cycles();
+        assert_eq!(audio.len() as u64, cycles * 48_000 / CPU_CLOCK_HZ as u64);
+    }
+    
+    #[test]
+    fn test_palette_powers_up_with_canonical_values() {
+        #[rustfmt::skip]
+        let program = [
+            0xA9, 0x0A, 0x8D, 0x01, 0x20, // LDA #$0A; STA $2001 (background, no palette writes)
+            0x4C, 0x05, 0x80,             // JMP *
+        ];
+        let mut system = NesSystem::with_prg_rom(rom_with_program(&program)).unwrap();
+        let backdrop = |system: &mut NesSystem| {
+            system.run_frame().unwrap();
+            system.run_frame().unwrap();
+            let framebuffer = system.framebuffer();
+            assert!(framebuffer.iter().all(|&colour| colour == framebuffer[0]));
+            framebuffer[0]
+        };
+        
+        // Blank CHR draws everything in the backdrop colour, $3F00
+        assert_eq!(backdrop(&mut system), 0x09);
+        assert_eq!(system.ppu().read_palette_direct(0x3F1F), 0x08);
+        
+        // A reset leaves palette RAM alone; a power cycle restores it
+        system.write_palette(0x00, 0x21);
+        system.reset();
+        assert_eq!(backdrop(&mut system), 0x21);
+        system.power_cycle();
+        assert_eq!(backdrop(&mut system), 0x09);
+        
+        system.set_power_up_state(PowerUpState::AllZeros);
+        system.power_cycle();
+        assert_eq!(system.frame(), 0);
+        assert_eq!(backdrop(&mut system), 0x00);
+    }
+    
+    #[test]
+    fn test_oam_dma_from_nonzero_oamaddr() {
+        #[rustfmt::skip]
+        let program = [
+            0xA9, 0x04, 0x8D, 0x03, 0x20, // LDA #$04; STA $2003
+            0xA9, 0x14, 0x8D, 0x01, 0x20, // LDA #$14; STA $2001 (sprites)
+            0xA9, 0x02, 0x8D, 0x14, 0x40, // LDA #$02; STA $4014
+            0x4C, 0x0F, 0x80,             // JMP *
+        ];
+        let mut system = NesSystem::with_prg_rom(rom_with_program(&program)).unwrap();
+        system.write_chr(0x10, &[0xFF; 16]); // Tile 1: solid colour 3
+        system.write_palette(0x13, 0x16);
+        system.write_palette(0x17, 0x2A);
+        
+        // Two sprites on the same spot: the first and last in the page
+        let memory = system.cpu_mut().memory();
+        for addr in 0x0200..0x0300 {
+            memory.write(addr, 0xF0);
+        }
+        for (addr, attributes) in [(0x0200, 0x00), (0x02FC, 0x01)] {
+            for (offset, byte) in [10, 0x01, attributes, 20].into_iter().enumerate() {
+                memory.write(addr + offset as u16, byte);
+            }
+        }
+        
+        for _ in 0..5 {
+            system.step().unwrap();
+        }
+        let cycles = system.step().unwrap();
+        assert!(cycles == 4 + 513 || cycles == 4 + 514, "{}", cycles);
+        
+        // Everything lands 4 bytes on, so the last sprite wraps to slot 0
+        let oam = *system.ppu().oam();
+        assert_eq!(oam[..4], [10, 0x01, 0x01, 20]);
+        assert_eq!(oam[4..8], [10, 0x01, 0x00, 20]);
+        assert_eq!(system.ppu().oam_addr, 0x04);
+        
+        // Slot 0 has priority, so the overlap shows palette 1
+        for _ in 0..2 {
+            system.run_frame().unwrap();
+        }
+        assert_eq!(system.framebuffer()[12 * 256 + 24], 0x2A);
+    }
 }
@@ -0,0 +1,75 @@
+//! Framebuffer comparison helpers for rendering tests
+//!
+//! Only built for tests and with the `test-util` feature. The frame files
+//! and diff images come from [`frame_compare`](crate::frame_compare),
+//! re-exported here; what this adds is the assertion.
+//! When two frames differ, [`assert_frames_match`] writes the diff image
+//! into `target/test-output` and names it in the panic message.
+
+use std::path::{Path, PathBuf};
+
+pub use crate::frame_compare::{
+    count_differences, diff_image, load_reference_frame, save_diff_image, save_reference_frame, HEIGHT, WIDTH,
+};
+
+/// Where failed comparisons write their diff images
+pub fn test_output_dir() -> PathBuf {
+    match std::env::var_os("CARGO_TARGET_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("../../target"),
+    }
+    .join("test-output")
+}
+
+/// Panic unless `actual` differs from `expected` in at most `tolerance_pixels` pixels
+///
+/// On failure the diff image is saved under [`test_output_dir`], named
+/// after the running test, and its path is part of the panic message.
+#[track_caller]
+pub fn assert_frames_match(expected: &[u8], actual: &[u8], tolerance_pixels: usize) {
+    let differences = count_differences(expected, actual);
+    if differences <= tolerance_pixels {
+        return;
+    }
+
+    // libtest names each test's thread after the test, except when it
+    // runs everything on the main thread
+    let caller = std::panic::Location::caller();
+    let name = match std::thread::current().name() {
+        Some(name) if name != "main" => name.to_string(),
+        _ => format!("{}_{}", caller.file(), caller.line()),
+    }
+    .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    let path = test_output_dir().join(format!("{}.ppm", name));
+    let saved = match save_diff_image(expected, actual, &path) {
+        Ok(()) => format!("diff image: {}", path.display()),
+        Err(err) => format!("couldn't save diff image to {}: {}", path.display(), err),
+    };
+    panic!(
+        "frames differ in {} pixels (tolerance {}); {}",
+        differences, tolerance_pixels, saved
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(fill: u8) -> Vec<u8> {
+        vec![fill; WIDTH * HEIGHT]
+    }
+
+    #[test]
+    fn test_tolerance() {
+        let expected = frame(0x01);
+        let mut actual = frame(0x01);
+        actual[..3].fill(0x02);
+        assert_eq!(count_differences(&expected, &actual), 3);
+        assert_frames_match(&expected, &actual, 3);
+
+        let result = std::panic::catch_unwind(|| assert_frames_match(&expected, &actual, 2));
+        let message = *result.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.contains("differ in 3 pixels"), "{}", message);
+        assert!(message.contains("diff image:") && message.contains(".ppm"), "{}", message);
+    }
+}
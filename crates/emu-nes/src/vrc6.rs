@@ -0,0 +1,495 @@
+//! Konami VRC6 expansion audio
+//!
+//! The VRC6 (mappers 24 and 26) adds three channels to the console's own:
+//! two pulse channels with 16-step duty and no sweep or envelope, and a
+//! sawtooth built from an accumulator. All three are clocked every CPU
+//! cycle. Registers, as wired on mapper 24 (mapper 26 swaps A0 and A1):
+//!
+//! $9000-$9002: Pulse 1
+//! $9003: Frequency control (halt, 16x and 256x speed-up)
+//! $A000-$A002: Pulse 2
+//! $B000-$B002: Sawtooth
+//!
+//! On a cartridge the chip is the board's [`ExpansionAudio`], attached when
+//! the ROM loads; its channels are muted as
+//! [`Channel::Expansion`](crate::Channel::Expansion) 0-2, in the order
+//! above. [`ApuPlayer::set_vrc6_enabled`](crate::ApuPlayer::set_vrc6_enabled)
+//! plays it without a cartridge.
+
+use crate::expansion_audio::ExpansionAudio;
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+use emu_core::Result;
+
+/// Loudest the three channels get together: two pulses at 15 and the
+/// sawtooth at 31
+const MAX_LEVEL: f32 = 61.0;
+
+/// The register a CPU write to `addr` reaches, in the mapper 24 layout
+///
+/// The chip only sees A12-A15 and two low address lines, so each register
+/// repeats through its 4KB page. Mapper 26 boards connect those two lines
+/// the other way round.
+pub(crate) fn register(addr: u16, lines_swapped: bool) -> u16 {
+    let low = addr & 0x0003;
+    let low = if lines_swapped { (low & 1) << 1 | low >> 1 } else { low };
+    addr & 0xF000 | low
+}
+
+/// VRC6 pulse channel (2 of these on the chip)
+#[derive(Debug, Clone, Default)]
+pub struct Vrc6Pulse {
+    /// Volume (0-15)
+    volume: u8,
+    /// Duty (0-7): the first `duty + 1` of 16 steps are high
+    duty: u8,
+    /// Ignore the duty and output the volume constantly
+    digitized: bool,
+    /// Timer period (12 bits)
+    period: u16,
+    /// Channel enabled
+    enabled: bool,
+    /// Timer counter
+    timer: u16,
+    /// Duty step (0-15)
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    /// Write to register 0 (mode, duty, volume)
+    pub fn write_reg0(&mut self, value: u8) {
+        self.digitized = value & 0x80 != 0;
+        self.duty = (value >> 4) & 0x07;
+        self.volume = value & 0x0F;
+    }
+
+    /// Write to register 1 (period low)
+    pub fn write_reg1(&mut self, value: u8) {
+        self.period = (self.period & 0x0F00) | value as u16;
+    }
+
+    /// Write to register 2 (enable, period high)
+    ///
+    /// Disabling the channel also sends the duty back to its first step.
+    pub fn write_reg2(&mut self, value: u8) {
+        self.period = (self.period & 0x00FF) | (((value & 0x0F) as u16) << 8);
+        self.enabled = value & 0x80 != 0;
+        if !self.enabled {
+            self.step = 0;
+        }
+    }
+
+    /// Clock the timer, with the period shifted right by `shift`
+    fn clock_timer(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) & 0x0F;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Current output level (0-15)
+    pub fn output(&self) -> u8 {
+        if self.enabled && (self.digitized || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+/// VRC6 sawtooth channel
+///
+/// Each timer expiry advances a 14-step sequence: the accumulator clears
+/// on step 0 and gains the rate on every other step after that, so it
+/// climbs through 7 levels. The output is the accumulator's top 5 bits.
+#[derive(Debug, Clone, Default)]
+pub struct Vrc6Sawtooth {
+    /// Accumulator rate (6 bits)
+    rate: u8,
+    /// Timer period (12 bits)
+    period: u16,
+    /// Channel enabled
+    enabled: bool,
+    /// Timer counter
+    timer: u16,
+    /// Sequence step (0-13)
+    step: u8,
+    /// Accumulator
+    accumulator: u8,
+}
+
+impl Vrc6Sawtooth {
+    /// Write to register 0 (accumulator rate)
+    pub fn write_reg0(&mut self, value: u8) {
+        self.rate = value & 0x3F;
+    }
+
+    /// Write to register 1 (period low)
+    pub fn write_reg1(&mut self, value: u8) {
+        self.period = (self.period & 0x0F00) | value as u16;
+    }
+
+    /// Write to register 2 (enable, period high)
+    ///
+    /// Disabling the channel clears the accumulator and the sequence.
+    pub fn write_reg2(&mut self, value: u8) {
+        self.period = (self.period & 0x00FF) | (((value & 0x0F) as u16) << 8);
+        self.enabled = value & 0x80 != 0;
+        if !self.enabled {
+            self.step = 0;
+            self.accumulator = 0;
+        }
+    }
+
+    /// Clock the timer, with the period shifted right by `shift`
+    fn clock_timer(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) % 14;
+            if self.step == 0 {
+                self.accumulator = 0;
+            } else if self.step.is_multiple_of(2) {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Current output level (0-31)
+    pub fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+/// The three VRC6 channels and their shared frequency control
+#[derive(Debug, Clone, Default)]
+pub struct Vrc6Audio {
+    pub pulse1: Vrc6Pulse,
+    pub pulse2: Vrc6Pulse,
+    pub sawtooth: Vrc6Sawtooth,
+    /// All timers stopped ($9003 bit 0)
+    halt: bool,
+    /// Right shift applied to every period ($9003 bits 1-2)
+    period_shift: u8,
+    /// Wired as on mapper 26; the board's, not state
+    lines_swapped: bool,
+}
+
+impl Vrc6Audio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A chip wired as on mapper 26, with A0 and A1 swapped
+    pub fn with_lines_swapped() -> Self {
+        Self { lines_swapped: true, ..Self::default() }
+    }
+
+    /// Summed level of the three channels (0-61), leaving out channel n
+    /// where bit n of `muted` is set
+    fn level(&self, muted: u8) -> u8 {
+        [self.pulse1.output(), self.pulse2.output(), self.sawtooth.output()]
+            .into_iter()
+            .enumerate()
+            .filter(|&(channel, _)| muted & 1 << channel == 0)
+            .map(|(_, level)| level)
+            .sum()
+    }
+
+    /// Write a register, in the mapper 24 layout; other addresses are ignored
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x9000 => self.pulse1.write_reg0(value),
+            0x9001 => self.pulse1.write_reg1(value),
+            0x9002 => self.pulse1.write_reg2(value),
+            0x9003 => {
+                self.halt = value & 0x01 != 0;
+                // 256x takes priority over 16x
+                self.period_shift = if value & 0x04 != 0 {
+                    8
+                } else if value & 0x02 != 0 {
+                    4
+                } else {
+                    0
+                };
+            }
+            0xA000 => self.pulse2.write_reg0(value),
+            0xA001 => self.pulse2.write_reg1(value),
+            0xA002 => self.pulse2.write_reg2(value),
+            0xB000 => self.sawtooth.write_reg0(value),
+            0xB001 => self.sawtooth.write_reg1(value),
+            0xB002 => self.sawtooth.write_reg2(value),
+            _ => {}
+        }
+    }
+
+    /// Clock the channels (called every CPU cycle)
+    pub fn clock(&mut self) {
+        if self.halt {
+            return;
+        }
+        self.pulse1.clock_timer(self.period_shift);
+        self.pulse2.clock_timer(self.period_shift);
+        self.sawtooth.clock_timer(self.period_shift);
+    }
+}
+
+impl ExpansionAudio for Vrc6Audio {
+    fn clock(&mut self) {
+        Vrc6Audio::clock(self);
+    }
+
+    fn output(&self) -> f32 {
+        self.level(0) as f32 / MAX_LEVEL
+    }
+
+    /// Decodes the address as the board wires it, so the registers
+    /// repeat through their pages
+    fn write_register(&mut self, addr: u16, value: u8) {
+        Vrc6Audio::write_register(self, register(addr, self.lines_swapped), value);
+    }
+
+    /// A pulse channel at full volume is as loud as a console pulse at
+    /// full volume
+    fn mix_level(&self) -> f32 {
+        MAX_LEVEL / 15.0
+    }
+
+    fn reset(&mut self) {
+        *self = Self { lines_swapped: self.lines_swapped, ..Self::default() };
+    }
+
+    fn output_muted(&self, muted: u8) -> f32 {
+        self.level(muted) as f32 / MAX_LEVEL
+    }
+}
+
+impl Snapshot for Vrc6Pulse {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.volume);
+        w.u8(self.duty);
+        w.bool(self.digitized);
+        w.u16(self.period);
+        w.bool(self.enabled);
+        w.u16(self.timer);
+        w.u8(self.step);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.volume = r.u8()? & 0x0F;
+        self.duty = r.u8()? & 0x07;
+        self.digitized = r.bool()?;
+        self.period = r.u16()? & 0x0FFF;
+        self.enabled = r.bool()?;
+        self.timer = r.u16()?;
+        self.step = r.u8()? & 0x0F;
+        Ok(())
+    }
+}
+
+impl Snapshot for Vrc6Sawtooth {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.rate);
+        w.u16(self.period);
+        w.bool(self.enabled);
+        w.u16(self.timer);
+        w.u8(self.step);
+        w.u8(self.accumulator);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.rate = r.u8()? & 0x3F;
+        self.period = r.u16()? & 0x0FFF;
+        self.enabled = r.bool()?;
+        self.timer = r.u16()?;
+        self.step = r.u8()? % 14;
+        self.accumulator = r.u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Vrc6Audio {
+    fn save(&self, w: &mut StateWriter) {
+        self.pulse1.save(w);
+        self.pulse2.save(w);
+        self.sawtooth.save(w);
+        w.bool(self.halt);
+        w.u8(self.period_shift);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<()> {
+        self.pulse1.load(r)?;
+        self.pulse2.load(r)?;
+        self.sawtooth.load(r)?;
+        self.halt = r.bool()?;
+        self.period_shift = r.u8()?.min(8);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::mix;
+    use crate::cpu::CpuMemory;
+    use crate::{Cartridge, Channel, NesMemory};
+
+    /// Sawtooth output after each of `steps` timer expiries
+    fn sawtooth_levels(audio: &mut Vrc6Audio, steps: usize) -> Vec<u8> {
+        let period = audio.sawtooth.period as usize + 1;
+        (0..steps)
+            .map(|_| {
+                for _ in 0..period {
+                    audio.clock();
+                }
+                audio.sawtooth.output()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sawtooth_ramp() {
+        // Rate 42 is the loudest that doesn't wrap: 6 * 42 = 252
+        let mut audio = Vrc6Audio::new();
+        audio.write_register(0xB000, 42);
+        audio.write_register(0xB001, 0x10);
+        audio.write_register(0xB002, 0x80);
+
+        // The first expiry is immediate, landing on step 1; the ramp
+        // then holds each level for two steps and restarts after 14
+        let ramp = [0, 5, 5, 10, 10, 15, 15, 21, 21, 26, 26, 31, 31, 0];
+        assert_eq!(sawtooth_levels(&mut audio, 14), ramp);
+        assert_eq!(sawtooth_levels(&mut audio, 14), ramp);
+        assert_eq!(audio.sawtooth.accumulator, 0);
+    }
+
+    #[test]
+    fn test_sawtooth_accumulator_wraps_and_clears_on_disable() {
+        let mut audio = Vrc6Audio::new();
+        audio.write_register(0xB000, 0x3F);
+        audio.write_register(0xB002, 0x80);
+        let levels = sawtooth_levels(&mut audio, 13);
+        // 6 * 63 = 378 wraps to 122, which outputs 15
+        assert_eq!(levels[12], 15);
+
+        audio.write_register(0xB002, 0x00);
+        assert_eq!(audio.sawtooth.output(), 0);
+        assert_eq!(sawtooth_levels(&mut audio, 4), [0; 4]);
+    }
+
+    /// Pulse 1 levels over 16 steps with period 0
+    fn pulse_steps(audio: &mut Vrc6Audio) -> Vec<u8> {
+        (0..16)
+            .map(|_| {
+                audio.clock();
+                audio.pulse1.output()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pulse_duty_and_digitized_mode() {
+        let mut audio = Vrc6Audio::new();
+        // Duty 3 (4/16 high), volume 9
+        audio.write_register(0x9000, 0x39);
+        audio.write_register(0x9002, 0x80);
+        let levels = pulse_steps(&mut audio);
+        assert_eq!(levels.iter().filter(|&&level| level == 9).count(), 4);
+        assert_eq!(levels.iter().filter(|&&level| level == 0).count(), 12);
+
+        // Digitized mode ignores the duty
+        audio.write_register(0x9000, 0xB9);
+        assert!(pulse_steps(&mut audio).iter().all(|&level| level == 9));
+
+        audio.write_register(0x9002, 0x00);
+        assert_eq!(audio.pulse1.output(), 0);
+    }
+
+    #[test]
+    fn test_frequency_control() {
+        let mut audio = Vrc6Audio::new();
+        audio.write_register(0x9000, 0x0F);
+        audio.write_register(0x9001, 0xFF);
+        audio.write_register(0x9002, 0x8F);
+
+        // Halted: nothing moves
+        audio.write_register(0x9003, 0x01);
+        for _ in 0..10_000 {
+            audio.clock();
+        }
+        assert_eq!(audio.pulse1.step, 0);
+
+        // 256x: period $FFF runs as $F, a step every 16 cycles
+        audio.write_register(0x9003, 0x06);
+        for _ in 0..16 * 16 {
+            audio.clock();
+        }
+        assert_eq!(audio.pulse1.step, 0);
+        assert_eq!(audio.pulse1.timer, 0);
+        audio.clock();
+        assert_eq!(audio.pulse1.step, 1);
+    }
+
+    /// A console with a VRC6 board, mapper 24 or 26
+    fn console(mapper: u8) -> NesMemory {
+        let mut image = vec![b'N', b'E', b'S', 0x1A, 2, 1, (mapper & 0x0F) << 4, mapper & 0xF0];
+        image.resize(16 + 0x8000 + 0x2000, 0);
+        let mut memory = NesMemory::new();
+        memory.load_cartridge(Cartridge::from_bytes(&image).unwrap());
+        memory
+    }
+
+    /// How much the cartridge adds to the mixed output
+    fn expansion(memory: &NesMemory) -> f32 {
+        memory.apu().output() - mix(0, 0, 0, 0, 0)
+    }
+
+    #[test]
+    fn test_pulse_as_loud_as_the_consoles() {
+        for (mapper, enable) in [(24, 0x9002), (26, 0x9001)] {
+            let mut memory = console(mapper);
+            memory.write(0x9000, 0x8F); // Digitized, volume 15
+            memory.write(enable, 0x80);
+            memory.clock_cycle();
+            let console_pulse = mix(15, 0, 0, 0, 0) - mix(0, 0, 0, 0, 0);
+            assert!((expansion(&memory) - console_pulse).abs() < 1e-6, "mapper {}", mapper);
+        }
+    }
+
+    #[test]
+    fn test_channels_muted_separately() {
+        let mut memory = console(24);
+        memory.write(0x9000, 0x8F);
+        memory.write(0x9002, 0x80);
+        memory.write(0xA000, 0x8F);
+        memory.write(0xA002, 0x80);
+        memory.clock_cycle();
+        let both = expansion(&memory);
+
+        memory.apu_mut().set_muted(Channel::Expansion(0), true);
+        memory.clock_cycle();
+        assert!((expansion(&memory) - both / 2.0).abs() < 1e-3);
+        memory.apu_mut().set_muted(Channel::Expansion(1), true);
+        memory.clock_cycle();
+        assert_eq!(expansion(&memory), 0.0);
+    }
+
+    #[test]
+    fn test_reset_keeps_the_wiring() {
+        let mut audio = Vrc6Audio::with_lines_swapped();
+        ExpansionAudio::write_register(&mut audio, 0xB000, 42);
+        ExpansionAudio::reset(&mut audio);
+        ExpansionAudio::write_register(&mut audio, 0xB001, 0x80);
+        assert!(audio.sawtooth.enabled);
+        assert_eq!(register(0x9FFE, true), 0x9001);
+        assert_eq!(register(0x9FFE, false), 0x9002);
+    }
+}
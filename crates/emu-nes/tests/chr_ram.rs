@@ -0,0 +1,189 @@
+//! CHR-RAM in savestates and battery saves
+
+use emu_nes::{MemoryRegion, NesSystem};
+use nes_asm::{Assembler, InesBuilder};
+
+/// An NROM game that fills the screen with tile 1, then in every NMI
+/// redraws that tile with a frame counter through $2007 and copies the
+/// counter to PRG-RAM
+fn program() -> Vec<u8> {
+    let mut asm = Assembler::new(0x8000, 0x8000);
+    asm.label("reset")
+        .sei()
+        .label("vblank1")
+        .bit_abs(0x2002)
+        .bpl("vblank1")
+        .label("vblank2")
+        .bit_abs(0x2002)
+        .bpl("vblank2")
+        // Palette 0
+        .lda_imm(0x3F)
+        .sta_abs(0x2006)
+        .lda_imm(0x00)
+        .sta_abs(0x2006)
+        .lda_imm(0x0F)
+        .sta_abs(0x2007)
+        .lda_imm(0x16)
+        .sta_abs(0x2007)
+        .lda_imm(0x2A)
+        .sta_abs(0x2007)
+        .lda_imm(0x30)
+        .sta_abs(0x2007)
+        // Nametable 0 is all tile 1
+        .lda_imm(0x20)
+        .sta_abs(0x2006)
+        .lda_imm(0x00)
+        .sta_abs(0x2006)
+        .lda_imm(0x01)
+        .ldx_imm(4)
+        .label("fill_page")
+        .ldy_imm(240)
+        .label("fill")
+        .sta_abs(0x2007)
+        .dey()
+        .bne("fill")
+        .dex()
+        .bne("fill_page")
+        .lda_imm(0x0A)
+        .sta_abs(0x2001)
+        .lda_imm(0x80)
+        .sta_abs(0x2000)
+        .label("main")
+        .jmp("main")
+        .label("nmi")
+        .inc_zp(0x00)
+        .lda_zp(0x00)
+        .sta_abs(0x6000)
+        // Both planes of tile 1
+        .ldx_imm(0x00)
+        .stx_abs(0x2006)
+        .ldx_imm(0x10)
+        .stx_abs(0x2006)
+        .label("tile")
+        .sta_abs(0x2007)
+        .dex()
+        .bne("tile")
+        .lda_imm(0x00)
+        .sta_abs(0x2005)
+        .sta_abs(0x2005)
+        .lda_imm(0x80)
+        .sta_abs(0x2000)
+        .rti();
+    asm.vectors("nmi", "reset", "nmi");
+    asm.assemble().unwrap()
+}
+
+fn run(system: &mut NesSystem, frames: usize) {
+    for _ in 0..frames {
+        system.run_frame().unwrap();
+    }
+}
+
+#[test]
+fn test_savestate_restores_chr_ram() {
+    let mut system = NesSystem::from_bytes(&InesBuilder::new(program()).build().unwrap()).unwrap();
+    run(&mut system, 30);
+    let state = system.save_state();
+    let chr = system.export_region(MemoryRegion::ChrRam);
+    assert!(chr[0x10..0x20].iter().all(|&byte| byte != 0));
+    system.run_frame().unwrap();
+    let frame = system.framebuffer().to_vec();
+
+    run(&mut system, 10);
+    assert_ne!(system.export_region(MemoryRegion::ChrRam), chr);
+
+    system.load_state(&state).unwrap();
+    assert_eq!(system.export_region(MemoryRegion::ChrRam), chr);
+    system.run_frame().unwrap();
+    assert_eq!(system.framebuffer(), frame);
+}
+
+#[test]
+fn test_quick_load_restores_chr_ram() {
+    let mut system = NesSystem::from_bytes(&InesBuilder::new(program()).build().unwrap()).unwrap();
+    run(&mut system, 30);
+    let state = system.quick_save();
+    let chr = system.export_region(MemoryRegion::ChrRam);
+    run(&mut system, 10);
+    system.quick_load(&state).unwrap();
+    assert_eq!(system.export_region(MemoryRegion::ChrRam), chr);
+}
+
+#[test]
+fn test_chr_rom_reloads_from_the_cartridge() {
+    let rom = InesBuilder::new(program()).chr(vec![0x55; 0x2000]).build().unwrap();
+    let mut system = NesSystem::from_bytes(&rom).unwrap();
+    run(&mut system, 30);
+    let state = system.save_state();
+
+    system.write_chr(0x0000, &[0xAA; 0x100]);
+    system.load_state(&state).unwrap();
+    assert!((0..0x2000).all(|addr| system.ppu().read_chr_direct(addr) == 0x55));
+}
+
+#[test]
+fn test_battery_save_round_trip() {
+    let rom = InesBuilder::new(program()).battery().build().unwrap();
+    let mut system = NesSystem::from_bytes(&rom).unwrap();
+    run(&mut system, 30);
+    let counter = system.read_memory(0x6000);
+    assert_ne!(counter, 0);
+    let save = system.battery_save().unwrap();
+    assert_eq!(&save[..4], b"LSAV");
+    assert_eq!(save.len(), 12 + 0x2000 + 0x2000);
+
+    let mut restored = NesSystem::from_bytes(&rom).unwrap();
+    restored.load_battery_save(&save).unwrap();
+    for region in [MemoryRegion::PrgRam, MemoryRegion::ChrRam] {
+        assert_eq!(restored.export_region(region), system.export_region(region), "{}", region);
+    }
+    assert_eq!(restored.read_memory(0x6000), counter);
+
+    // A plain PRG-RAM file loads too, leaving CHR-RAM alone
+    let mut plain = NesSystem::from_bytes(&rom).unwrap();
+    plain.load_battery_save(&system.export_region(MemoryRegion::PrgRam)).unwrap();
+    assert_eq!(plain.read_memory(0x6000), counter);
+    assert!(plain.export_region(MemoryRegion::ChrRam).iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn test_battery_save_without_chr_ram_is_raw_prg_ram() {
+    let rom = InesBuilder::new(program()).chr(vec![0x55; 0x2000]).battery().build().unwrap();
+    let mut system = NesSystem::from_bytes(&rom).unwrap();
+    run(&mut system, 30);
+    let counter = system.read_memory(0x6000);
+    let save = system.battery_save().unwrap();
+    assert_eq!(save, system.export_region(MemoryRegion::PrgRam));
+
+    // CHR-RAM has nowhere to go
+    let sectioned = [b"LSAV\x00\x20\0\0\x00\x20\0\0".as_slice(), &[0; 0x4000]].concat();
+    assert!(system.load_battery_save(&sectioned).is_err());
+    assert_eq!(system.read_memory(0x6000), counter);
+}
+
+#[test]
+fn test_battery_save_keeps_only_nvram() {
+    // NES 2.0: 2KB battery-backed at $6000, then 8KB of work RAM
+    let mut rom = InesBuilder::new(program()).chr(vec![0x55; 0x2000]).battery().build().unwrap();
+    rom[7] |= 0x08;
+    rom[10] = 0x57;
+    let mut system = NesSystem::from_bytes(&rom).unwrap();
+    assert_eq!(system.cartridge_info().map(|info| (info.prg_ram_size, info.prg_nvram_size)), Some((0x2800, 0x800)));
+    run(&mut system, 30);
+    let counter = system.read_memory(0x6000);
+    let save = system.battery_save().unwrap();
+    assert_eq!(save, system.export_region(MemoryRegion::PrgRam)[..0x800]);
+
+    let mut restored = NesSystem::from_bytes(&rom).unwrap();
+    restored.load_battery_save(&save).unwrap();
+    assert_eq!(restored.read_memory(0x6000), counter);
+    // A file of all the PRG-RAM doesn't fit
+    assert!(restored.load_battery_save(&system.export_region(MemoryRegion::PrgRam)).is_err());
+}
+
+#[test]
+fn test_no_battery() {
+    let mut system = NesSystem::from_bytes(&InesBuilder::new(program()).build().unwrap()).unwrap();
+    assert_eq!(system.battery_save(), None);
+    assert!(system.load_battery_save(&[0; 0x2000]).is_err());
+}
@@ -0,0 +1,12 @@
+//! Fixtures shared by the integration tests
+
+use emu_nes::NesSystem;
+
+/// A RAM cart with `code` at $8000 and every vector pointing at it, reset
+pub fn ram_cart(code: &[u8]) -> NesSystem {
+    let mut system = NesSystem::with_ram_cart();
+    system.write_code(0x8000, code).unwrap();
+    system.set_vectors(0x8000, 0x8000, 0x8000).unwrap();
+    system.reset();
+    system
+}
@@ -0,0 +1,25 @@
+//! The hand-written compliance suites; determinism has its own file and
+//! blargg's ROMs aren't in the repository
+
+use emu_nes::compliance::{apu, cpu, ppu, Suite};
+
+fn assert_passed(suite: Suite) {
+    if let Some(report) = suite.failure_report() {
+        panic!("{}", report);
+    }
+}
+
+#[test]
+fn test_cpu_suite() {
+    assert_passed(cpu::run());
+}
+
+#[test]
+fn test_ppu_suite() {
+    assert_passed(ppu::run());
+}
+
+#[test]
+fn test_apu_suite() {
+    assert_passed(apu::run());
+}
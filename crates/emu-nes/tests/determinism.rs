@@ -0,0 +1,62 @@
+//! Determinism tests for every generated example ROM
+//!
+//! The runs and comparisons are [`emu_nes::compliance::determinism`]'s.
+
+#[path = "../examples/generate_animation_test.rs"]
+#[allow(dead_code)]
+mod animation;
+
+#[path = "../examples/generate_controller_test.rs"]
+#[allow(dead_code)]
+mod controller;
+
+#[path = "../examples/generate_input_test.rs"]
+#[allow(dead_code)]
+mod input;
+
+#[path = "../examples/generate_perfect_visual.rs"]
+#[allow(dead_code)]
+mod perfect_visual;
+
+#[path = "../examples/generate_scrolling_tests.rs"]
+#[allow(dead_code)]
+mod scrolling;
+
+#[path = "../examples/generate_test_rom.rs"]
+#[allow(dead_code)]
+mod test_rom;
+
+use emu_nes::compliance::determinism::{clone_runs, resumed_state, same_inputs};
+
+/// Every ROM the examples can generate
+fn roms() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("animation", animation::build_rom()),
+        ("controller", controller::build_rom()),
+        ("input", input::build_rom()),
+        ("perfect_visual", perfect_visual::build_rom()),
+        ("scrolling", scrolling::build_rom(64, 32)),
+        ("test_rom", test_rom::build_rom()),
+    ]
+}
+
+#[test]
+fn test_same_inputs_give_same_state() {
+    for (name, rom) in roms() {
+        assert_eq!(same_inputs(&rom), Ok(()), "{}", name);
+    }
+}
+
+#[test]
+fn test_resumed_state_matches_uninterrupted_run() {
+    for (name, rom) in roms() {
+        assert_eq!(resumed_state(&rom), Ok(()), "{}", name);
+    }
+}
+
+#[test]
+fn test_clone_runs_like_the_original() {
+    for (name, rom) in roms() {
+        assert_eq!(clone_runs(&rom), Ok(()), "{}", name);
+    }
+}
@@ -0,0 +1,149 @@
+//! Driving a system through the emulator service
+//!
+//! From a plain thread and from a minimal executor, since the service is
+//! meant to work under any async runtime without depending on one.
+
+#[path = "../examples/generate_input_test.rs"]
+#[allow(dead_code)]
+mod generator;
+
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use emu_core::{Button, Emulator, EmulatorError};
+use emu_nes::{EmulatorService, NesSystem};
+use generator::{build_rom, BUTTONS_ADDR};
+
+fn system() -> NesSystem {
+    NesSystem::from_bytes(&build_rom()).unwrap()
+}
+
+/// Poll `future` to completion on this thread, parking between polls
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(thread::Thread);
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn test_driven_from_a_thread() {
+    let (handle, service) = EmulatorService::spawn(system());
+    let client = thread::spawn(move || {
+        handle.set_buttons(1, Button::A | Button::START).wait().unwrap();
+        assert_eq!(handle.run_frames(10).wait().unwrap(), 10);
+        let buttons = handle.read_memory(BUTTONS_ADDR..=BUTTONS_ADDR).wait().unwrap();
+        let frame = handle.request_frame().wait().unwrap();
+        handle.shutdown().wait().unwrap();
+        (buttons, frame)
+    });
+    let (buttons, frame) = client.join().unwrap();
+    // The ROM stores the buttons bit-reversed
+    assert_eq!(buttons, [0x90]);
+    assert_eq!((frame.frame, frame.width, frame.height), (10, 256, 240));
+    assert_eq!(frame.rgba.len(), 256 * 240 * 4);
+
+    let mut system = service.join().unwrap();
+    assert_eq!(system.frame(), 10);
+    assert_eq!(frame.rgba, system.framebuffer_rgba());
+}
+
+#[test]
+fn test_driven_from_an_executor() {
+    let (handle, service) = EmulatorService::spawn(system());
+    let (state, resumed, rerun) = block_on(async {
+        handle.run_frames(5).await?;
+        let state = handle.save_state().await?;
+        handle.set_buttons(1, Button::B).await?;
+        handle.run_frames(5).await?;
+        let rerun = handle.request_frame().await?;
+        handle.load_state(state.clone()).await?;
+        handle.set_buttons(1, Button::B).await?;
+        handle.run_frames(5).await?;
+        let resumed = handle.request_frame().await?;
+        Ok::<_, EmulatorError>((state, resumed, rerun))
+    })
+    .unwrap();
+    assert!(!state.is_empty());
+    assert_eq!(resumed, rerun);
+    drop(handle);
+    service.join().unwrap();
+}
+
+#[test]
+fn test_commands_run_in_order() {
+    let (handle, service) = EmulatorService::spawn(system());
+    // Queue everything before waiting for any of it
+    let first = handle.run_frames(3);
+    let after_first = handle.call(|system| system.frame());
+    let press = handle.set_buttons(1, Button::RIGHT);
+    let second = handle.run_frames(4);
+    let buttons = handle.read_memory(BUTTONS_ADDR..BUTTONS_ADDR + 1);
+    let log = handle.call(|system| system.frame());
+
+    assert_eq!(log.wait().unwrap(), 7);
+    assert_eq!(buttons.wait().unwrap(), [0x01]);
+    assert_eq!(second.wait().unwrap(), 7);
+    press.wait().unwrap();
+    assert_eq!(after_first.wait().unwrap(), 3);
+    assert_eq!(first.wait().unwrap(), 3);
+    handle.shutdown().wait().unwrap();
+    service.join().unwrap();
+}
+
+#[test]
+fn test_clean_shutdown() {
+    let (handle, service) = EmulatorService::spawn(system());
+    let other = handle.clone();
+    // What was sent first still runs; what comes after is refused
+    let run = handle.run_frames(5);
+    let shutdown = handle.shutdown();
+    let late = other.run_frames(5);
+    assert_eq!(run.wait().unwrap(), 5);
+    shutdown.wait().unwrap();
+    assert!(matches!(late.wait(), Err(EmulatorError::ServiceStopped)));
+    assert!(matches!(block_on(other.request_frame()), Err(EmulatorError::ServiceStopped)));
+    assert_eq!(service.join().unwrap().frame(), 5);
+
+    // Dropping every handle stops it too
+    let (handle, service) = EmulatorService::spawn(system());
+    let clone = handle.clone();
+    thread::spawn(move || clone.run_frames(2).wait().unwrap()).join().unwrap();
+    drop(handle);
+    assert_eq!(service.join().unwrap().frame(), 2);
+}
+
+#[test]
+fn test_errors_are_answered() {
+    let (handle, service) = EmulatorService::spawn(system());
+    assert!(handle.set_buttons(3, Button::A).wait().is_err());
+    assert!(matches!(handle.load_state(vec![1, 2, 3]).wait(), Err(EmulatorError::InvalidSaveState(_))));
+    // The service carries on
+    assert_eq!(handle.run_frames(1).wait().unwrap(), 1);
+    drop(handle);
+    service.join().unwrap();
+}
+
+#[test]
+fn test_any_system() {
+    let (handle, service) = EmulatorService::spawn(Vec::new());
+    for i in 0..10 {
+        handle.call(move |items: &mut Vec<i32>| items.push(i));
+    }
+    assert_eq!(handle.call(|items| items.len()).wait().unwrap(), 10);
+    drop(handle);
+    assert_eq!(service.join().unwrap(), (0..10).collect::<Vec<_>>());
+}
@@ -0,0 +1,120 @@
+//! The per-frame event timeline
+
+use emu_nes::{EmuEvent, EmuEventKind, NesSystem};
+use nes_asm::{Assembler, InesBuilder};
+
+/// A GxROM game that sets the backdrop, switches to CHR bank 1, turns on
+/// rendering and NMI, then does an OAM DMA in every NMI
+fn build_rom() -> Vec<u8> {
+    let mut asm = Assembler::new(0x8000, 0x8000);
+    asm.label("reset")
+        .sei()
+        .label("vblank1")
+        .bit_abs(0x2002)
+        .bpl("vblank1")
+        .label("vblank2")
+        .bit_abs(0x2002)
+        .bpl("vblank2")
+        // Backdrop colour
+        .lda_imm(0x3F)
+        .sta_abs(0x2006)
+        .lda_imm(0x00)
+        .sta_abs(0x2006)
+        .lda_imm(0x0F)
+        .sta_abs(0x2007)
+        // CHR bank 1
+        .lda_imm(0x01)
+        .sta_abs(0x8000)
+        .lda_imm(0x1E)
+        .sta_abs(0x2001)
+        .lda_imm(0x80)
+        .sta_abs(0x2000)
+        .label("main")
+        .jmp("main")
+        .label("nmi")
+        .lda_imm(0x02)
+        .sta_abs(0x4014)
+        .rti();
+    asm.vectors("nmi", "reset", "nmi");
+    InesBuilder::new(asm.assemble().unwrap())
+        .mapper(66)
+        .chr(vec![0; 0x4000])
+        .build()
+        .unwrap()
+}
+
+/// The events of each of the first `frames` frames
+fn frames(system: &mut NesSystem, frames: usize) -> Vec<Vec<EmuEvent>> {
+    (0..frames)
+        .map(|_| {
+            system.run_frame().unwrap();
+            system.take_frame_events()
+        })
+        .collect()
+}
+
+#[test]
+fn test_off_by_default() {
+    let mut system = NesSystem::from_bytes(&build_rom()).unwrap();
+    assert!(!system.event_log_enabled());
+    assert!(frames(&mut system, 5).iter().all(Vec::is_empty));
+}
+
+#[test]
+fn test_known_rom_timeline() {
+    let mut system = NesSystem::from_bytes(&build_rom()).unwrap();
+    system.set_event_log_enabled(true);
+    let log = frames(&mut system, 10);
+
+    // Initialisation, in program order, in the frame it finishes
+    let init = log.iter().position(|events| !events.is_empty()).unwrap();
+    let kinds: Vec<EmuEventKind> = log[init].iter().map(|event| event.kind).collect();
+    assert_eq!(
+        kinds[..5],
+        [
+            EmuEventKind::PpuWrite { addr: 0x2006, value: 0x3F },
+            EmuEventKind::PpuWrite { addr: 0x2006, value: 0x00 },
+            EmuEventKind::ChrBank(1),
+            EmuEventKind::PpuWrite { addr: 0x2001, value: 0x1E },
+            EmuEventKind::PpuWrite { addr: 0x2000, value: 0x80 },
+        ]
+    );
+    // Stamped in order, during the vblank after the warm-up
+    let init_events = &log[init][..5];
+    assert!(init_events.windows(2).all(|pair| (pair[0].scanline, pair[0].cycle) < (pair[1].scanline, pair[1].cycle)));
+    assert!(init_events.iter().all(|event| event.frame == init as u64 && event.scanline >= 241));
+
+    // From then on, one NMI per frame, each followed by its DMA
+    for (frame, events) in log.iter().enumerate().skip(init + 1) {
+        let kinds: Vec<EmuEventKind> = events.iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, [EmuEventKind::Nmi, EmuEventKind::OamDma { page: 0x02 }], "frame {}", frame);
+        assert_eq!(events[0].scanline, 241);
+        assert!(events[0].cycle < 30, "NMI at dot {}", events[0].cycle);
+        assert_eq!(events[0].frame, frame as u64);
+    }
+}
+
+#[test]
+fn test_quick_load_rewinds_the_log() {
+    let mut system = NesSystem::from_bytes(&build_rom()).unwrap();
+    system.set_event_log_enabled(true);
+    frames(&mut system, 5);
+
+    let state = system.quick_save();
+    system.run_frame().unwrap();
+    let expected = system.take_frame_events();
+
+    // Speculative frames leave nothing behind
+    system.quick_load(&state).unwrap();
+    system.run_frame().unwrap();
+    let state = system.quick_save();
+    system.run_frame().unwrap();
+    system.quick_load(&state).unwrap();
+    assert_eq!(system.take_frame_events(), expected);
+
+    // Turning the log off drops what wasn't taken
+    system.run_frame().unwrap();
+    system.set_event_log_enabled(false);
+    system.set_event_log_enabled(true);
+    assert!(system.take_frame_events().is_empty());
+}
@@ -0,0 +1,79 @@
+//! Skipping rendering for fast headless runs
+//!
+//! Uses the input test ROM, whose screen changes with the buttons held, so
+//! a frame drawn after a stretch of skipped ones has something to get wrong.
+
+#[path = "../examples/generate_input_test.rs"]
+#[allow(dead_code)]
+mod generator;
+
+use emu_core::Button;
+use emu_nes::NesSystem;
+use generator::build_rom;
+
+/// Load the ROM and run past its warm-up wait
+fn boot() -> NesSystem {
+    let mut system = NesSystem::from_bytes(&build_rom()).unwrap();
+    for _ in 0..6 {
+        system.run_frame().unwrap();
+    }
+    system
+}
+
+/// Run both systems `frames` frames with the same input
+fn run_both(a: &mut NesSystem, b: &mut NesSystem, frames: usize) {
+    for _ in 0..frames {
+        a.run_frame().unwrap();
+        b.run_frame().unwrap();
+    }
+}
+
+#[test]
+fn test_skipped_frames_resume_identically() {
+    let mut continuous = boot();
+    let mut skipping = boot();
+    let before = skipping.framebuffer().to_vec();
+
+    skipping.set_render_enabled(false);
+    continuous.press_button(Button::A);
+    skipping.press_button(Button::A);
+    run_both(&mut continuous, &mut skipping, 4);
+    continuous.press_button(Button::LEFT);
+    skipping.press_button(Button::LEFT);
+    run_both(&mut continuous, &mut skipping, 4);
+
+    // The game kept running, but nothing was drawn
+    assert_eq!(skipping.read_memory(generator::BUTTONS_ADDR), 0x82);
+    assert_eq!(skipping.framebuffer(), &before[..]);
+    assert_ne!(continuous.framebuffer(), &before[..]);
+
+    skipping.set_render_enabled(true);
+    run_both(&mut continuous, &mut skipping, 2);
+    assert_eq!(skipping.framebuffer(), continuous.framebuffer());
+    assert_eq!(skipping.frame(), continuous.frame());
+}
+
+#[test]
+fn test_run_frames_renders_the_last_frame() {
+    let mut continuous = boot();
+    let mut skipping = boot();
+    continuous.press_button(Button::START);
+    skipping.press_button(Button::START);
+
+    for _ in 0..10 {
+        continuous.run_frame().unwrap();
+    }
+    skipping.run_frames(10, true).unwrap();
+
+    assert_eq!(skipping.framebuffer(), continuous.framebuffer());
+    assert_eq!(skipping.frame(), continuous.frame());
+    assert!(skipping.render_enabled());
+
+    // Rendering turned off by the caller stays off
+    let before = skipping.framebuffer().to_vec();
+    skipping.release_button(Button::START);
+    skipping.set_render_enabled(false);
+    skipping.run_frames(4, true).unwrap();
+    assert_eq!(skipping.framebuffer(), &before[..]);
+    assert!(!skipping.render_enabled());
+}
@@ -0,0 +1,98 @@
+//! Golden-image tests for the generated scrolling, animation and raster ROMs
+//!
+//! Reference frames live in `tests/golden` as palette-index PGMs. After an
+//! intended rendering change, regenerate them with
+//! `UPDATE_GOLDEN=1 cargo test -p emu-nes --test golden_frames` and look
+//! over the new images before committing them.
+
+#[path = "../examples/generate_scrolling_tests.rs"]
+#[allow(dead_code)]
+mod scrolling;
+
+#[path = "../examples/generate_animation_test.rs"]
+#[allow(dead_code)]
+mod animation;
+
+#[path = "../examples/generate_raster_test.rs"]
+#[allow(dead_code)]
+mod raster;
+
+use std::path::PathBuf;
+
+use emu_nes::test_util::{assert_frames_match, load_reference_frame, save_reference_frame};
+use emu_core::EmulatorError;
+use emu_nes::NesSystem;
+
+/// Run `frames` frames of `rom` and return the last one
+fn render(rom: &[u8], frames: usize) -> Vec<u8> {
+    let mut system = NesSystem::from_bytes(rom).unwrap();
+    for _ in 0..frames {
+        system.run_frame().unwrap();
+    }
+    system.framebuffer().to_vec()
+}
+
+/// Compare `frame` with the reference `name`, allowing `tolerance_pixels`
+/// differences, or replace it under UPDATE_GOLDEN
+fn check_golden(name: &str, frame: &[u8], tolerance_pixels: usize) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.pgm", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        save_reference_frame(&path, frame).unwrap();
+        return;
+    }
+    let expected = load_reference_frame(&path)
+        .unwrap_or_else(|err| panic!("{} (run with UPDATE_GOLDEN=1 to create it)", err));
+    assert_frames_match(&expected, frame, tolerance_pixels);
+}
+
+#[test]
+fn test_scrolling_golden() {
+    check_golden("scrolling_noscroll", &render(&scrolling::build_rom(0, 0), 10), 0);
+    check_golden("scrolling_scroll", &render(&scrolling::build_rom(64, 32), 10), 0);
+}
+
+#[test]
+fn test_animation_golden() {
+    let rom = animation::build_rom();
+    check_golden("animation_frame30", &render(&rom, 30), 0);
+    check_golden("animation_frame90", &render(&rom, 90), 0);
+}
+
+/// Check the three bands of a raster test frame at rows `top`, `middle`
+/// and `bottom`, five points on each half of the screen
+fn assert_raster_bands(frame: &[u8], top: usize, middle: usize, bottom: usize) {
+    let bands = [
+        (top, raster::RED, raster::GREEN),
+        (middle, raster::GREEN, raster::BLUE),
+        (bottom, raster::BLUE, raster::RED),
+    ];
+    for (y, left, right) in bands {
+        for x in [8, 40, 64, 96, 120] {
+            assert_eq!(frame[y * 256 + x], left, "left half at ({}, {})", x, y);
+            assert_eq!(frame[y * 256 + 128 + x], right, "right half at ({}, {})", 128 + x, y);
+        }
+    }
+}
+
+#[test]
+fn test_raster_sprite0_golden() {
+    let frame = render(&raster::build_rom(raster::Trigger::SpriteZero), 10);
+    assert_raster_bands(&frame, 30, 90, 190);
+    // The split lines depend on instruction timing; allow a line of drift
+    check_golden("raster_sprite0", &frame, 256);
+}
+
+#[test]
+fn test_raster_mmc3_not_supported() {
+    let result = NesSystem::from_bytes(&raster::build_rom(raster::Trigger::Mmc3Irq));
+    assert!(matches!(result, Err(EmulatorError::UnsupportedMapper(4))));
+}
+
+#[test]
+#[ignore = "needs MMC3 (mapper 4)"]
+fn test_raster_mmc3_bands() {
+    let frame = render(&raster::build_rom(raster::Trigger::Mmc3Irq), 10);
+    assert_raster_bands(&frame, 30, 90, 190);
+}
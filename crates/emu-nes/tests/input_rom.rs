@@ -0,0 +1,168 @@
+//! End-to-end controller test using the generated input test ROM
+//!
+//! Covers the press/release API, the $4016 mapping and the controller shift
+//! register together: the ROM reads the buttons itself and both stores and
+//! draws what it read.
+
+#[path = "../examples/generate_input_test.rs"]
+#[allow(dead_code)]
+mod generator;
+
+use emu_core::{Button, EmulatorContext, MemoryBus, MemoryObserver};
+use std::sync::{Arc, Mutex};
+use emu_nes::{InputSchedule, NesSystem, ScriptMode};
+use generator::{build_rom, BUTTONS_ADDR, TILE_COL, TILE_ROW};
+
+/// Background colour the ROM loads into the palette
+const BLACK: u8 = 0x0F;
+
+/// Colour index of the centre pixel of each of the 8 button tiles
+fn tile_centres(system: &mut NesSystem) -> Vec<u8> {
+    let y = TILE_ROW * 8 + 4;
+    (0..8)
+        .map(|i| system.framebuffer()[y * 256 + (TILE_COL + i) * 8 + 4])
+        .collect()
+}
+
+/// Run enough frames for the NMI to read the buttons and the result to be drawn
+fn settle(system: &mut NesSystem) {
+    for _ in 0..3 {
+        system.run_frame().unwrap();
+    }
+}
+
+/// Load the ROM and run past its warm-up wait until the tiles are on screen
+fn boot() -> NesSystem {
+    let mut system = NesSystem::from_bytes(&build_rom()).unwrap();
+    for _ in 0..2 {
+        settle(&mut system);
+    }
+    system
+}
+
+#[test]
+fn test_buttons_reach_ram_and_screen() {
+    let mut system = boot();
+    assert_eq!(system.read_memory(BUTTONS_ADDR), 0x00);
+    assert_eq!(tile_centres(&mut system), vec![BLACK; 8]);
+
+    system.press_button(Button::A);
+    system.press_button(Button::RIGHT);
+    settle(&mut system);
+    assert_eq!(system.read_memory(BUTTONS_ADDR), 0x81);
+    let centres = tile_centres(&mut system);
+    assert_ne!(centres[0], BLACK);
+    assert_eq!(centres[1..7], [BLACK; 6]);
+    assert_eq!(centres[7], centres[0]);
+
+    system.release_button(Button::A);
+    system.press_button(Button::START);
+    settle(&mut system);
+    assert_eq!(system.read_memory(BUTTONS_ADDR), 0x11);
+    let lit: Vec<usize> = (0..8).filter(|&i| tile_centres(&mut system)[i] != BLACK).collect();
+    assert_eq!(lit, vec![3, 7]);
+}
+
+#[test]
+fn test_last_latched_input_follows_the_game() {
+    let mut system = boot();
+    assert_eq!(system.last_latched_input(1), 0x00);
+
+    system.press_button(Button::A);
+    system.press_button(Button::RIGHT);
+    settle(&mut system);
+    assert_eq!(system.last_latched_input(1), 0x81);
+    assert_eq!(system.last_latched_input(2), 0x00);
+
+    // Held but not yet latched: the game hasn't seen it
+    system.press_button(Button::START);
+    assert_eq!(system.last_latched_input(1), 0x81);
+    settle(&mut system);
+    assert_eq!(system.last_latched_input(1), 0x89);
+    // The ROM stores the bits in the order it shifts them in, A at the top
+    assert_eq!(system.last_latched_input(1), system.read_memory(BUTTONS_ADDR).reverse_bits());
+    assert_eq!(system.last_latched_input(3), 0x00);
+}
+
+/// Records the context of every write to RAM
+struct ContextLog(Arc<Mutex<Vec<EmulatorContext>>>);
+
+impl MemoryObserver for ContextLog {
+    fn on_read(&mut self, _address: u32, _value: u8, _context: &EmulatorContext) {}
+
+    fn on_write(&mut self, address: u32, _old_value: u8, _new_value: u8, context: &EmulatorContext) {
+        if address < 0x0800 {
+            self.0.lock().unwrap().push(*context);
+        }
+    }
+}
+
+#[test]
+fn test_observers_see_latched_input_and_frame() {
+    let mut system = boot();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    system.cpu_mut().memory().attach_observer(Box::new(ContextLog(log.clone())));
+
+    system.press_button(Button::A);
+    system.run_frame().unwrap();
+    let frame = system.frame();
+    system.run_frame().unwrap();
+
+    // By the second frame the NMI has strobed, so the ROM's store of the
+    // buttons and everything after it carry the A bit
+    let log = log.lock().unwrap();
+    let last_frame: Vec<_> = log.iter().filter(|context| context.frame == frame).collect();
+    assert!(!last_frame.is_empty());
+    assert!(last_frame.iter().all(|context| context.last_input & Button::A.bits() != 0));
+    assert!(log.iter().all(|context| context.last_input_p2 == 0));
+    assert_eq!(log.last().unwrap().frame, frame);
+}
+
+#[test]
+fn test_released_buttons_draw_outlines() {
+    let mut system = boot();
+
+    // The top-left pixel of each tile is on the outline
+    let y = TILE_ROW * 8;
+    for i in 0..8 {
+        assert_ne!(system.framebuffer()[y * 256 + (TILE_COL + i) * 8], BLACK, "tile {}", i);
+    }
+}
+
+
+
+/// The button byte the ROM stored after each of the next `frames` frames
+fn stored_buttons(system: &mut NesSystem, frames: usize) -> Vec<u8> {
+    (0..frames)
+        .map(|_| {
+            system.run_frame().unwrap();
+            system.read_memory(BUTTONS_ADDR)
+        })
+        .collect()
+}
+
+#[test]
+fn test_input_script_drives_the_rom() {
+    // The ROM stores A in bit 7 down to Right in bit 0
+    let script = "press A 3; wait 2; hold RIGHT { press B 2 } wait 1; repeat 2 { press start+select; wait 1 }";
+    let mut system = boot();
+    system.attach_input_script(InputSchedule::parse(script).unwrap(), ScriptMode::Override);
+    assert!(system.input_script_running());
+    assert_eq!(
+        stored_buttons(&mut system, 13),
+        [0x80, 0x80, 0x80, 0x00, 0x00, 0x41, 0x41, 0x00, 0x30, 0x00, 0x30, 0x00, 0x00]
+    );
+    assert!(!system.input_script_running());
+
+    // Merged with a held button, which is all that's left when it ends
+    let mut system = boot();
+    system.press_button(Button::UP);
+    system.attach_input_script(InputSchedule::parse("press B 2").unwrap(), ScriptMode::Merge);
+    assert_eq!(stored_buttons(&mut system, 3), [0x48, 0x48, 0x08]);
+
+    // Overriding hides it until then, and detaching hands it straight back
+    system.attach_input_script(InputSchedule::parse("wait 2; press A 5").unwrap(), ScriptMode::Override);
+    assert_eq!(stored_buttons(&mut system, 3), [0x00, 0x00, 0x80]);
+    system.detach_input_script();
+    assert_eq!(stored_buttons(&mut system, 1), [0x08]);
+}
@@ -0,0 +1,121 @@
+//! Instruction hook: a known program seen through the PC coverage bitmap
+//! and the hot-loop counter from the `hot_loops` example
+
+#[path = "../examples/hot_loops.rs"]
+#[allow(dead_code)]
+mod hot_loops;
+mod common;
+
+use common::ram_cart;
+use emu_nes::{InstrEvent, NesSystem, PcCoverage};
+use hot_loops::HotLoops;
+use nes_asm::Assembler;
+use std::sync::mpsc;
+
+/// Instructions each test runs
+const STEPS: usize = 1600;
+
+/// A RAM cart running three passes of a 256-step inner loop from reset,
+/// then an idle loop:
+///
+/// ```text
+/// $8000  LDY #$03
+/// $8002  LDX #$00
+/// $8004  INX
+/// $8005  BNE $8004
+/// $8007  DEY
+/// $8008  BNE $8002
+/// $800A  JMP $800A
+/// ```
+fn system() -> NesSystem {
+    let mut asm = Assembler::new(0x8000, 13);
+    asm.ldy_imm(0x03)
+        .label("outer")
+        .ldx_imm(0x00)
+        .label("inner")
+        .inx()
+        .bne("inner")
+        .dey()
+        .bne("outer")
+        .label("idle")
+        .jmp("idle");
+    ram_cart(&asm.assemble().unwrap())
+}
+
+fn run(system: &mut NesSystem) {
+    for _ in 0..STEPS {
+        system.step().unwrap();
+    }
+}
+
+#[test]
+fn test_pc_coverage() {
+    let mut system = system();
+    let coverage = PcCoverage::new();
+    system.set_instruction_hook(Some(coverage.hook()));
+    run(&mut system);
+
+    let executed: Vec<u16> = (0..=0xFFFF).filter(|&pc| coverage.contains(pc)).collect();
+    assert_eq!(executed, [0x8000, 0x8002, 0x8004, 0x8005, 0x8007, 0x8008, 0x800A]);
+    assert_eq!(coverage.len(), 7);
+}
+
+#[test]
+fn test_hot_loops() {
+    let mut system = system();
+    let loops = HotLoops::new();
+    system.set_instruction_hook(Some(loops.hook()));
+    run(&mut system);
+
+    // 1 + 3 + 768 * 2 + 3 * 2 instructions before the idle loop
+    assert_eq!(
+        loops.top(10),
+        [(0x8004, 768), (0x8005, 768), (0x800A, 54), (0x8002, 3), (0x8007, 3), (0x8008, 3), (0x8000, 1)]
+    );
+    assert_eq!(loops.top(2), [(0x8004, 768), (0x8005, 768)]);
+}
+
+#[test]
+fn test_events_follow_the_program() {
+    let mut system = system();
+    let (sender, events) = mpsc::channel();
+    system.set_instruction_hook(Some(Box::new(move |event| sender.send(event).unwrap())));
+    run(&mut system);
+    let events: Vec<InstrEvent> = events.try_iter().collect();
+    assert_eq!(events.len(), STEPS);
+
+    let first = events[0];
+    assert_eq!((first.pc, first.opcode, first.mnemonic, first.operands()), (0x8000, 0xA0, "LDY", &[0x03][..]));
+    assert_eq!((first.y, first.next_pc, first.cycles), (3, 0x8002, 2));
+
+    // The inner BNE takes 3 cycles looping back and 2 falling through,
+    // when X has wrapped to 0
+    let branches: Vec<&InstrEvent> = events.iter().filter(|event| event.pc == 0x8005).collect();
+    assert!(branches[..255].iter().all(|event| event.cycles == 3 && event.next_pc == 0x8004));
+    assert_eq!((branches[255].cycles, branches[255].x, branches[255].next_pc), (2, 0, 0x8007));
+
+    // Each event leads to the next
+    for pair in events.windows(2) {
+        assert_eq!(pair[0].next_pc, pair[1].pc);
+    }
+    let last = events[STEPS - 1];
+    assert_eq!((last.mnemonic, last.operands(), last.y), ("JMP", &[0x0A, 0x80][..], 0));
+    assert_eq!(last.cycles, 3);
+}
+
+#[test]
+fn test_removing_the_hook() {
+    let mut system = system();
+    let coverage = PcCoverage::new();
+    assert!(system.set_instruction_hook(Some(coverage.hook())).is_none());
+    system.step().unwrap();
+    assert!(system.set_instruction_hook(None).is_some());
+    run(&mut system);
+    assert_eq!(coverage.len(), 1);
+
+    // Clones don't take it along
+    system.set_instruction_hook(Some(coverage.hook()));
+    let mut clone = system.clone();
+    run(&mut clone);
+    assert_eq!(coverage.len(), 1);
+}
@@ -0,0 +1,173 @@
+//! Homebrew lint, one small generated ROM per mistake
+
+use emu_nes::lint::FRAME_COUNTER_GRACE_FRAMES;
+use emu_nes::{LintKind, NesSystem};
+use nes_asm::{Assembler, InesBuilder};
+
+/// Frames each ROM runs for, well past the frame counter grace period
+const FRAMES: usize = 30;
+
+/// An NROM game that does everything by the book, except for `mistake`;
+/// the ROM, and the address of the code making the mistake
+///
+/// By the book: $4017 set at reset, two vblanks waited for, OAM copied in
+/// before sprites go on, and the NMI handler writes the palette and copies
+/// OAM at the start of vblank.
+fn game(mistake: Option<LintKind>) -> (Vec<u8>, Option<u16>) {
+    let mut asm = Assembler::new(0x8000, 0x8000);
+    asm.label("reset").sei().cld().ldx_imm(0x40);
+    if mistake != Some(LintKind::FrameCounterNotSet) {
+        asm.stx_abs(0x4017);
+    }
+    asm.ldx_imm(0xFF).txs().inx().stx_abs(0x2000).stx_abs(0x2001);
+    if mistake == Some(LintKind::WarmupWrite) {
+        asm.label("culprit").stx_abs(0x2005);
+    }
+    asm.label("vblank1")
+        .bit_abs(0x2002)
+        .bpl("vblank1")
+        .label("vblank2")
+        .bit_abs(0x2002)
+        .bpl("vblank2");
+    if mistake != Some(LintKind::StaleOam) {
+        asm.lda_imm(0x02).sta_abs(0x4014);
+    }
+    asm.lda_imm(0x80).sta_abs(0x2000).lda_imm(0x1E).label("sprites_on").sta_abs(0x2001);
+
+    asm.label("main");
+    match mistake {
+        Some(LintKind::VramDuringRendering) => {
+            asm.lda_imm(0x00).label("culprit").sta_abs(0x2007);
+        }
+        Some(LintKind::SpriteOverflowTest) => {
+            asm.label("culprit").lda_abs(0x2002).and_imm(0x20);
+        }
+        Some(LintKind::UnofficialOpcode) => {
+            // NOP, undocumented
+            asm.label("culprit").byte(0x1A);
+        }
+        _ => {}
+    }
+    asm.jmp("main");
+
+    asm.label("nmi");
+    if mistake == Some(LintKind::NmiOverBudget) {
+        // About 3800 cycles of game logic before the PPU updates
+        asm.ldy_imm(3)
+            .label("outer")
+            .ldx_imm(0)
+            .label("inner")
+            .dex()
+            .bne("inner")
+            .dey()
+            .bne("outer");
+    }
+    asm.lda_imm(0x3F)
+        .sta_abs(0x2006)
+        .lda_imm(0x00)
+        .sta_abs(0x2006)
+        .lda_imm(0x0F)
+        .label("palette")
+        .sta_abs(0x2007)
+        .lda_imm(0x02)
+        .sta_abs(0x4014)
+        .lda_imm(0x00)
+        .sta_abs(0x2005)
+        .sta_abs(0x2005)
+        .rti();
+    asm.vectors("nmi", "reset", "nmi");
+
+    let culprit = match mistake {
+        Some(LintKind::StaleOam) => asm.address_of("sprites_on"),
+        Some(LintKind::NmiOverBudget) => asm.address_of("palette"),
+        Some(LintKind::FrameCounterNotSet) => asm.address_of("reset"),
+        _ => asm.address_of("culprit"),
+    };
+    (InesBuilder::new(asm.assemble().unwrap()).build().unwrap(), culprit)
+}
+
+/// Run a game with the lint on, until it's done or the CPU stops
+fn linted(rom: &[u8]) -> NesSystem {
+    let mut system = NesSystem::from_bytes(rom).unwrap();
+    system.set_lint_enabled(true);
+    for _ in 0..FRAMES {
+        if system.run_frame().is_err() {
+            break;
+        }
+    }
+    system
+}
+
+#[test]
+fn test_clean_game_has_no_findings() {
+    let system = linted(&game(None).0);
+    assert!(system.lint_enabled());
+    let report = system.lint_report();
+    assert!(report.is_empty(), "{}", report);
+}
+
+#[test]
+fn test_each_mistake_is_found_where_it_is_made() {
+    for kind in LintKind::ALL {
+        let (rom, culprit) = game(Some(kind));
+        let culprit = culprit.unwrap();
+        let system = linted(&rom);
+        let findings = system.lint().unwrap().findings();
+        let found: Vec<_> = findings.iter().map(|finding| (finding.kind, finding.pc)).collect();
+        assert_eq!(found, vec![(kind, culprit)], "{:?}: {}", kind, system.lint_report());
+    }
+}
+
+#[test]
+fn test_mistakes_in_a_loop_are_counted_once() {
+    let system = linted(&game(Some(LintKind::VramDuringRendering)).0);
+    let finding = system.lint().unwrap().findings()[0];
+    assert!(finding.count > 1000, "{:?}", finding);
+    assert!(finding.first_frame <= 3, "{:?}", finding);
+
+    // Every frame's NMI runs over, but it's the one place in the code
+    let system = linted(&game(Some(LintKind::NmiOverBudget)).0);
+    let finding = system.lint().unwrap().findings()[0];
+    assert!(finding.count as usize >= FRAMES - 5, "{:?}", finding);
+}
+
+#[test]
+fn test_frame_counter_gets_its_grace_period() {
+    let (rom, _) = game(Some(LintKind::FrameCounterNotSet));
+    let mut system = NesSystem::from_bytes(&rom).unwrap();
+    system.set_lint_enabled(true);
+    system.run_frames(FRAME_COUNTER_GRACE_FRAMES as u64 - 2, false).unwrap();
+    assert!(system.lint_report().is_empty());
+    system.run_frames(3, false).unwrap();
+    assert_eq!(system.lint_report().len(), 1);
+}
+
+#[test]
+fn test_report_survives_reset_and_goes_with_the_setting() {
+    // Reset gives the game another grace period to miss
+    let mut system = linted(&game(Some(LintKind::FrameCounterNotSet)).0);
+    system.reset();
+    system.run_frames(FRAME_COUNTER_GRACE_FRAMES as u64 + 1, false).unwrap();
+    let report = system.lint_report();
+    assert_eq!(report.len(), 1, "{}", report);
+    assert_eq!(report.findings()[0].count, 2);
+    assert!(report.to_string().contains("write $40 to $4017 at reset"), "{}", report);
+
+    system.set_lint_enabled(false);
+    assert!(system.lint().is_none());
+    assert!(system.lint_report().is_empty());
+    system.reset();
+    system.run_frames(5, false).unwrap();
+    assert!(system.lint_report().is_empty());
+}
+
+#[test]
+fn test_quick_load_rewinds_findings() {
+    let mut system = linted(&game(Some(LintKind::VramDuringRendering)).0);
+    let state = system.quick_save();
+    let count = system.lint().unwrap().findings()[0].count;
+    system.run_frames(5, false).unwrap();
+    assert!(system.lint().unwrap().findings()[0].count > count);
+    system.quick_load(&state).unwrap();
+    assert_eq!(system.lint().unwrap().findings()[0].count, count);
+}
@@ -0,0 +1,95 @@
+//! Bulk export and import of memory regions
+
+use emu_core::EmulatorError;
+use emu_nes::test_util::test_output_dir;
+use emu_nes::{MemoryRegion, NesSystem};
+use nes_asm::{Assembler, InesBuilder};
+
+/// A game that counts frames in $10, with CHR-RAM and PRG-RAM
+fn system() -> NesSystem {
+    let mut asm = Assembler::new(0xC000, 0x4000);
+    asm.label("reset")
+        .lda_imm(0x80)
+        .sta_abs(0x2000)
+        .label("main")
+        .jmp("main")
+        .label("nmi")
+        .inc_zp(0x10)
+        .rti();
+    asm.vectors("nmi", "reset", "nmi");
+    NesSystem::from_bytes(&InesBuilder::new(asm.assemble().unwrap()).build().unwrap()).unwrap()
+}
+
+#[test]
+fn test_cpu_ram_round_trip() {
+    let mut system = system();
+    for _ in 0..5 {
+        system.run_frame().unwrap();
+    }
+    let mut ram = system.export_region(MemoryRegion::CpuRam);
+    assert_eq!(ram.len(), 0x800);
+    assert_eq!(ram[0x10], system.read_memory(0x10));
+
+    ram[0x10] = 0x80;
+    ram[0x7FF] = 0x5A;
+    system.import_region(MemoryRegion::CpuRam, &ram).unwrap();
+    assert_eq!(system.read_memory(0x10), 0x80);
+    // Mirrors see it too
+    assert_eq!(system.read_memory(0x1FFF), 0x5A);
+
+    // The game carries on from the imported counter
+    system.run_frame().unwrap();
+    assert_eq!(system.read_memory(0x10), 0x81);
+}
+
+#[test]
+fn test_wrong_length_is_rejected() {
+    let mut system = system();
+    let before = system.export_region(MemoryRegion::Oam);
+    let err = system.import_region(MemoryRegion::Oam, &[0xFF; 100]).unwrap_err();
+    match err {
+        EmulatorError::RegionSizeMismatch { region, expected, found } => {
+            assert_eq!(region, "oam");
+            assert_eq!(expected, 0x100);
+            assert_eq!(found, 100);
+        }
+        other => panic!("expected a size mismatch, got {:?}", other),
+    }
+    assert_eq!(system.export_region(MemoryRegion::Oam), before);
+}
+
+#[test]
+fn test_region_sizes() {
+    let mut system = system();
+    let sizes: Vec<usize> = MemoryRegion::ALL.iter().map(|&region| system.export_region(region).len()).collect();
+    assert_eq!(sizes, [0x800, 0x800, 0x20, 0x100, 0x2000, 0x2000]);
+
+    // Boards with CHR-ROM have no CHR-RAM
+    let mut prg = vec![0xEA; 0x4000];
+    prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+    let mut system = NesSystem::with_prg_rom(prg).unwrap();
+    assert!(system.export_region(MemoryRegion::ChrRam).is_empty());
+    assert!(system.import_region(MemoryRegion::ChrRam, &[0; 0x2000]).is_err());
+}
+
+#[test]
+fn test_palette_round_trips_through_a_file() {
+    let dir = test_output_dir().join("memory_regions");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("palette.bin");
+
+    let mut source = system();
+    for index in 0..0x20 {
+        source.write_palette(index, index + 0x10);
+    }
+    source.export_region_to(MemoryRegion::PpuPalette, &path).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap().len(), 0x20);
+
+    let mut target = system();
+    target.import_region_from(MemoryRegion::PpuPalette, &path).unwrap();
+    assert_eq!(
+        target.export_region(MemoryRegion::PpuPalette),
+        source.export_region(MemoryRegion::PpuPalette)
+    );
+    assert!(target.import_region_from(MemoryRegion::PpuPalette, dir.join("missing.bin")).is_err());
+}
@@ -0,0 +1,98 @@
+//! OAM decay with rendering off
+//!
+//! The ROM fills page 2 with 0-255 and turns rendering off. For its first
+//! DMA_FRAMES frames the NMI handler copies the page to OAM; after that it
+//! stops, the way a game that forgot its OAM DMA would.
+
+use emu_nes::ppu::OAM_DECAY_FRAMES;
+use emu_nes::{AccuracyFlags, NesSystem};
+
+/// Frames the NMI handler does OAM DMA for
+const DMA_FRAMES: u8 = 10;
+
+fn build_rom() -> Vec<u8> {
+    let mut prg = vec![0xEA; 0x4000];
+
+    // --- Reset ($8000) ---
+    let reset = [
+        0x78, // SEI
+        0xA2, 0xFF, 0x9A, // LDX #$FF; TXS
+        0xA9, 0x00, 0x85, 0x10, // LDA #0; STA $10
+        0xA2, 0x00, // LDX #0
+        0x8A, 0x9D, 0x00, 0x02, // fill: TXA; STA $0200,X
+        0xE8, 0xD0, 0xF9, // INX; BNE fill
+        0xA9, 0x00, 0x8D, 0x01, 0x20, // LDA #0; STA $2001 (rendering off)
+        0xA9, 0x80, 0x8D, 0x00, 0x20, // LDA #$80; STA $2000 (NMI on)
+        0x4C, 0x1B, 0x80, // idle: JMP idle
+    ];
+    prg[..reset.len()].copy_from_slice(&reset);
+    assert_eq!(reset.len(), 0x1E, "idle loop must be at $801B");
+
+    // --- NMI ($8100): DMA for the first DMA_FRAMES frames ---
+    prg[0x100..0x10E].copy_from_slice(&[
+        0xA5, 0x10, // LDA $10
+        0xC9, DMA_FRAMES, // CMP #DMA_FRAMES
+        0xB0, 0x07, // BCS done
+        0xA9, 0x02, 0x8D, 0x14, 0x40, // LDA #$02; STA $4014
+        0xE6, 0x10, // INC $10
+        0x40, // done: RTI
+    ]);
+    prg[0x3FFA..].copy_from_slice(&[0x00, 0x81, 0x00, 0x80, 0x0D, 0x81]);
+
+    let mut rom = b"NES\x1a\x01\x01\x00\x00".to_vec();
+    rom.resize(16, 0);
+    rom.extend_from_slice(&prg);
+    rom.extend_from_slice(&[0; 0x2000]);
+    rom
+}
+
+/// OAM as the DMA leaves it: 0-255, less the attribute bits that don't exist
+fn written_oam() -> Vec<u8> {
+    (0..=255u8).map(|i| if i & 0x03 == 2 { i & 0xE3 } else { i }).collect()
+}
+
+/// Run until the DMA has stopped, returning the system
+fn after_last_dma(flags: AccuracyFlags) -> NesSystem {
+    let mut system = NesSystem::from_bytes(&build_rom()).unwrap();
+    system.set_accuracy_flags(flags);
+    system.run_frames(DMA_FRAMES as u64 + 1, false).unwrap();
+    assert_eq!(system.ppu().oam().to_vec(), written_oam());
+    system
+}
+
+#[test]
+fn test_oam_decays_without_refresh() {
+    let mut system = after_last_dma(AccuracyFlags::OAM_DECAY);
+    let mut decayed_at = None;
+    for frame in 1..=OAM_DECAY_FRAMES as u32 + 1 {
+        system.run_frame().unwrap();
+        if decayed_at.is_none() && system.ppu().oam().to_vec() != written_oam() {
+            decayed_at = Some(frame);
+        }
+    }
+    let oam = system.ppu().oam().to_vec();
+    let changed = oam.iter().zip(written_oam()).filter(|(a, b)| *a != b).count();
+    assert!(changed > 200, "only {} bytes decayed", changed);
+    assert!(decayed_at.is_some_and(|frame| frame <= OAM_DECAY_FRAMES as u32), "decayed at {:?}", decayed_at);
+    assert!(system.ppu().frames_since_oam_write() >= OAM_DECAY_FRAMES as u32);
+
+    // The garbage is the same every run with the same seed
+    let mut again = after_last_dma(AccuracyFlags::OAM_DECAY);
+    again.run_frames(OAM_DECAY_FRAMES as u64 + 1, false).unwrap();
+    assert_eq!(again.ppu().oam().to_vec(), oam);
+
+    // And differs with another
+    let mut reseeded = after_last_dma(AccuracyFlags::OAM_DECAY);
+    reseeded.cpu_mut().memory().ppu_mut().set_oam_decay_seed(1);
+    reseeded.run_frames(OAM_DECAY_FRAMES as u64 + 1, false).unwrap();
+    assert_ne!(reseeded.ppu().oam().to_vec(), oam);
+}
+
+#[test]
+fn test_oam_stable_by_default() {
+    let mut system = after_last_dma(AccuracyFlags::empty());
+    system.run_frames(120, false).unwrap();
+    assert_eq!(system.ppu().oam().to_vec(), written_oam());
+    let since = system.ppu().frames_since_oam_write();
+    assert!((119..=121).contains(&since), "{} frames since the last write", since);
+}
@@ -0,0 +1,97 @@
+//! PAL cartridges: the region from the header sets the frame length, and an
+//! override runs a game as the other TV system
+
+#[path = "../examples/generate_input_test.rs"]
+#[allow(dead_code)]
+mod generator;
+
+use emu_core::Emulator;
+use emu_nes::apu_player::{CYCLES_PER_FRAME, PAL_CPU_CLOCK_HZ, PAL_CYCLES_PER_FRAME};
+use emu_nes::{NesSystem, Region};
+use generator::build_rom;
+
+/// Frames each test runs
+const FRAMES: u64 = 100;
+
+/// The input test ROM with the iNES 1.0 PAL flag set
+fn pal_rom() -> Vec<u8> {
+    let mut rom = build_rom();
+    rom[9] |= 0x01;
+    rom
+}
+
+/// Run `FRAMES` frames as a frontend does, returning the CPU cycles they took
+fn run_frames(system: &mut NesSystem) -> u64 {
+    (0..FRAMES).map(|_| Emulator::run_frame(system).unwrap() as u64).sum()
+}
+
+#[test]
+fn test_pal_rom_runs_pal_frames() {
+    let mut system = NesSystem::from_bytes(&pal_rom()).unwrap();
+    assert_eq!(system.region(), Region::Pal);
+    assert_eq!(system.cycles_per_frame(), PAL_CYCLES_PER_FRAME);
+    assert!((system.frame_rate() - PAL_CPU_CLOCK_HZ as f64 / 33247.0).abs() < 1e-9);
+    assert!((system.frame_rate() - 50.0).abs() < 0.01);
+
+    // Overruns are taken off the next frame, so the total is only ever
+    // the last frame's overrun past the PAL count
+    let cycles = run_frames(&mut system);
+    assert!((FRAMES * PAL_CYCLES_PER_FRAME..FRAMES * PAL_CYCLES_PER_FRAME + 10).contains(&cycles), "{} cycles", cycles);
+
+    // Which is a PPU frame apiece, at 3.2 dots a cycle
+    let stats = system.clock_stats();
+    assert_eq!(stats.ppu_drift(), 0);
+    assert_eq!(stats.apu_drift(), 0);
+    assert!(stats.frame_drift().abs() <= 1, "{:?}", stats);
+}
+
+#[test]
+fn test_pal_audio_follows_the_pal_clock() {
+    let mut system = NesSystem::from_bytes(&pal_rom()).unwrap();
+    let mut total = 0;
+    for frame in 0..FRAMES {
+        let mut samples = Vec::new();
+        system.run_frame_with_audio(44_100, &mut samples).unwrap();
+        // 44100 / 50.007 is 881.9, rounded one way or the other
+        assert!((881..=882).contains(&samples.len()), "frame {}: {}", frame, samples.len());
+        total += samples.len() as u64;
+    }
+
+    // Each frame's fraction of a sample is carried into the next, so the
+    // total is the cycles run at the PAL clock, rounded down once
+    let exact = system.apu().cycles() * 44_100 / PAL_CPU_CLOCK_HZ as u64;
+    assert_eq!(total, exact);
+}
+
+#[test]
+fn test_ntsc_rom_is_unchanged() {
+    let mut system = NesSystem::from_bytes(&build_rom()).unwrap();
+    assert_eq!(system.region(), Region::Ntsc);
+    let cycles = run_frames(&mut system);
+    assert!((FRAMES * CYCLES_PER_FRAME..FRAMES * CYCLES_PER_FRAME + 10).contains(&cycles), "{} cycles", cycles);
+    assert!(system.clock_stats().frame_drift().abs() <= 1);
+}
+
+#[test]
+fn test_region_override_power_cycles() {
+    let mut system = NesSystem::from_bytes(&pal_rom()).unwrap();
+    run_frames(&mut system);
+
+    // The same region again changes nothing
+    assert!(!system.set_region_override(Some(Region::Pal)));
+    assert_eq!(system.frame(), FRAMES);
+
+    assert!(system.set_region_override(Some(Region::Ntsc)));
+    assert_eq!(system.region(), Region::Ntsc);
+    assert_eq!(system.frame(), 0);
+    assert_eq!(system.apu().region(), Region::Ntsc);
+    assert_eq!(system.ppu().region(), Region::Ntsc);
+    let cycles = run_frames(&mut system);
+    assert!((FRAMES * CYCLES_PER_FRAME..FRAMES * CYCLES_PER_FRAME + 10).contains(&cycles), "{} cycles", cycles);
+    assert_eq!(system.clock_stats().ppu_drift(), 0);
+
+    // Back to what the header says
+    assert!(system.set_region_override(None));
+    assert_eq!(system.region(), Region::Pal);
+    assert_eq!(system.ppu().region(), Region::Pal);
+}
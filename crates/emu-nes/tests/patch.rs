@@ -0,0 +1,100 @@
+//! Loading ROMs through IPS patches, end to end
+//!
+//! The ROM stores $13 at $10 and idles. Its patch changes the immediate
+//! operand to $42.
+
+use emu_core::EmulatorError;
+use emu_nes::savestate::crc32;
+use emu_nes::{NesSystem, Patch};
+use nes_asm::{Assembler, InesBuilder};
+
+/// Where the marker is stored
+const MARKER: u16 = 0x0010;
+
+/// NROM image, and the file offset of the LDA operand the patch replaces
+fn build_rom() -> (Vec<u8>, usize) {
+    let mut asm = Assembler::new(0x8000, 0x4000);
+    asm.label("reset")
+        .label("load")
+        .lda_imm(0x13)
+        .sta_zp(MARKER as u8)
+        .label("idle")
+        .jmp("idle");
+    asm.vectors("idle", "reset", "idle");
+    let rom = InesBuilder::new(asm.assemble().unwrap()).build().unwrap();
+    // Header, then one past the opcode
+    let operand = 16 + (asm.address_of("load").unwrap() - 0x8000) as usize + 1;
+    (rom, operand)
+}
+
+/// IPS patch writing $42 over the operand
+fn build_patch() -> Vec<u8> {
+    let (_, operand) = build_rom();
+    let mut patch = b"PATCH".to_vec();
+    patch.extend_from_slice(&[(operand >> 16) as u8, (operand >> 8) as u8, operand as u8]);
+    patch.extend_from_slice(&[0x00, 0x01, 0x42]);
+    patch.extend_from_slice(b"EOF");
+    patch
+}
+
+fn marker(mut system: NesSystem) -> u8 {
+    system.run_frame().unwrap();
+    system.read_memory(MARKER)
+}
+
+/// A fresh directory holding `game.nes`
+fn rom_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("lumi_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("game.nes"), build_rom().0).unwrap();
+    dir
+}
+
+#[test]
+fn test_patch_applies_before_parsing() {
+    let patch = Patch {
+        name: "fix.ips".into(),
+        data: build_patch(),
+    };
+    let (rom, operand) = build_rom();
+    let mut system = NesSystem::from_bytes_with_patch(&rom, &patch).unwrap();
+    let info = system.cartridge_info().unwrap();
+    assert_eq!(info.patch.as_deref(), Some("fix.ips"));
+
+    // The CRC is the patched data's, so settings and savestates follow it
+    let mut patched = rom.clone();
+    patched[operand] = 0x42;
+    assert_eq!(info.crc32, crc32(&patched[16..]));
+    assert_eq!(marker(system), 0x42);
+
+    let mut plain = NesSystem::from_bytes(&rom).unwrap();
+    assert_eq!(plain.cartridge_info().unwrap().patch, None);
+    assert_eq!(marker(plain), 0x13);
+}
+
+#[test]
+fn test_patch_next_to_the_rom_is_found() {
+    let dir = rom_dir("sibling_patch");
+    let rom = dir.join("game.nes");
+    assert_eq!(marker(NesSystem::new(&rom).unwrap()), 0x13);
+
+    std::fs::write(dir.join("game.ips"), build_patch()).unwrap();
+    let mut system = NesSystem::new(&rom).unwrap();
+    assert_eq!(system.cartridge_info().unwrap().patch.as_deref(), Some("game.ips"));
+    assert_eq!(marker(system), 0x42);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_explicit_patch() {
+    let dir = rom_dir("explicit_patch");
+    std::fs::write(dir.join("hack.ips"), build_patch()).unwrap();
+    let system = NesSystem::new_quiet_with_patch(&dir.join("game.nes"), Some(&dir.join("hack.ips"))).unwrap();
+    assert_eq!(marker(system), 0x42);
+
+    // A patch that doesn't apply fails the load
+    std::fs::write(dir.join("bad.ips"), b"PATCH\x10\x00\x00\x00\x01\x42EOF").unwrap();
+    let error = NesSystem::new_quiet_with_patch(&dir.join("game.nes"), Some(&dir.join("bad.ips"))).unwrap_err();
+    assert!(matches!(error, EmulatorError::InvalidPatch(_)), "{}", error);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
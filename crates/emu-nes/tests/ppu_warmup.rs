@@ -0,0 +1,96 @@
+//! PPU warm-up after power-up and reset
+
+use emu_nes::ppu::PpuStatus;
+use emu_nes::{AccuracyFlags, NesSystem, SystemEvent};
+use nes_asm::{Assembler, InesBuilder};
+
+/// A game that turns on NMI and rendering, straight away or after the
+/// usual two vblank polls, then counts NMIs at $10
+fn build_rom(wait_for_warmup: bool) -> Vec<u8> {
+    let mut asm = Assembler::new(0x8000, 0x8000);
+    asm.label("reset").sei();
+    if wait_for_warmup {
+        asm.bit_abs(0x2002)
+            .label("vblank1")
+            .bit_abs(0x2002)
+            .bpl("vblank1")
+            .label("vblank2")
+            .bit_abs(0x2002)
+            .bpl("vblank2");
+    }
+    asm.lda_imm(0x80)
+        .sta_abs(0x2000)
+        .lda_imm(0x1E)
+        .sta_abs(0x2001)
+        .label("main")
+        .jmp("main")
+        .label("nmi")
+        .inc_zp(0x10)
+        .rti();
+    asm.vectors("nmi", "reset", "nmi");
+    InesBuilder::new(asm.assemble().unwrap()).chr(vec![0; 0x2000]).build().unwrap()
+}
+
+fn system(wait_for_warmup: bool, flags: AccuracyFlags) -> NesSystem {
+    let mut system = NesSystem::from_bytes(&build_rom(wait_for_warmup)).unwrap();
+    system.set_accuracy_flags(flags);
+    system
+}
+
+#[test]
+fn test_early_writes_are_ignored_under_the_flag() {
+    let mut system = system(false, AccuracyFlags::PPU_WARMUP);
+    system.run_frames(3, false).unwrap();
+    assert_eq!(system.ppu().ctrl.bits(), 0x00);
+    assert_eq!(system.ppu().mask.bits(), 0x00);
+    assert_eq!(system.peek_memory(0x0010), 0, "NMI was never enabled");
+    assert!(!system.ppu().warming_up());
+    assert_eq!(system.poll_events(), [SystemEvent::WarmupWritesIgnored { addr: 0x2000, count: 2 }]);
+}
+
+#[test]
+fn test_early_writes_land_without_the_flag() {
+    let mut system = system(false, AccuracyFlags::empty());
+    system.run_frames(3, false).unwrap();
+    assert_eq!(system.ppu().ctrl.bits(), 0x80);
+    assert_eq!(system.ppu().mask.bits(), 0x1E);
+    assert!(system.peek_memory(0x0010) >= 2);
+    assert!(system.poll_events().is_empty());
+}
+
+#[test]
+fn test_waiting_two_vblanks_is_enough() {
+    let mut system = system(true, AccuracyFlags::HARDWARE_STRICT);
+    // The first poll reads the power-up flag
+    assert!(system.ppu().status.contains(PpuStatus::VBLANK));
+    system.run_frames(4, false).unwrap();
+    assert_eq!(system.ppu().ctrl.bits(), 0x80);
+    assert!(system.peek_memory(0x0010) >= 1);
+    assert!(system.poll_events().is_empty());
+}
+
+#[test]
+fn test_reset_restarts_the_warmup() {
+    let mut system = system(false, AccuracyFlags::PPU_WARMUP);
+    system.run_frames(2, false).unwrap();
+    // Clear of the end of the pre-render line, where warm-up ends
+    system.run_cycles(1000).unwrap();
+    system.poll_events();
+
+    system.reset();
+    assert!(system.ppu().warming_up());
+    system.run_frames(2, false).unwrap();
+    assert_eq!(system.ppu().ctrl.bits(), 0x00);
+    assert_eq!(system.poll_events(), [SystemEvent::WarmupWritesIgnored { addr: 0x2000, count: 2 }]);
+}
+
+#[test]
+fn test_savestate_keeps_the_warmup() {
+    let mut system = system(false, AccuracyFlags::PPU_WARMUP);
+    system.step().unwrap();
+    let state = system.save_state();
+    system.run_frames(2, false).unwrap();
+    assert!(!system.ppu().warming_up());
+    system.load_state(&state).unwrap();
+    assert!(system.ppu().warming_up());
+}
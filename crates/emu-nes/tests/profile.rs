@@ -0,0 +1,68 @@
+//! Frame-time profiling on a real ROM
+
+#[path = "../examples/generate_input_test.rs"]
+#[allow(dead_code)]
+mod generator;
+
+use emu_core::{EmulatorContext, MemoryBus, MemoryObserver};
+use emu_nes::profile::PROFILE_FRAMES;
+use emu_nes::{NesSystem, Subsystem};
+use generator::build_rom;
+use std::time::Duration;
+
+/// An observer that does a little work on every access
+struct Counter(u64);
+
+impl MemoryObserver for Counter {
+    fn on_read(&mut self, address: u32, value: u8, _context: &EmulatorContext) {
+        self.0 = self.0.wrapping_add(address as u64 ^ value as u64);
+    }
+
+    fn on_write(&mut self, address: u32, _old_value: u8, new_value: u8, _context: &EmulatorContext) {
+        self.0 = self.0.wrapping_add(address as u64 ^ new_value as u64);
+    }
+}
+
+#[test]
+fn test_profile_parts_sum_to_the_frame() {
+    let mut system = NesSystem::from_bytes(&build_rom()).unwrap();
+    system.cpu_mut().memory().attach_observer(Box::new(Counter(0)));
+    assert_eq!(system.profile_report().frames, 0);
+
+    system.enable_profiling(true);
+    for _ in 0..10 {
+        system.run_frame().unwrap();
+    }
+    let report = system.profile_report();
+    assert_eq!(report.frames, 10);
+    assert!(report.frame.min <= report.frame.avg && report.frame.avg <= report.frame.max);
+    assert!(report.frame.min > Duration::ZERO);
+
+    // Every frame's parts add up to its total, so the averages do too
+    // (up to the nanosecond each average rounds off)
+    let parts: Duration = Subsystem::ALL.iter().map(|&s| report.stats(s).avg).sum();
+    let slack = Duration::from_nanos(Subsystem::ALL.len() as u64);
+    assert!(parts <= report.frame.avg + slack && report.frame.avg <= parts + slack);
+    let percent: f64 = Subsystem::ALL.iter().map(|&s| report.percentage(s)).sum();
+    assert!((percent - 100.0).abs() < 0.01, "{}", percent);
+
+    // Each was sampled doing real work
+    for subsystem in Subsystem::ALL {
+        assert!(report.stats(subsystem).avg > Duration::ZERO, "{:?}", subsystem);
+    }
+    assert!(report.to_string().contains("Frame times over 10 frames"));
+}
+
+#[test]
+fn test_profile_keeps_the_last_frames_and_drops_when_disabled() {
+    let mut system = NesSystem::from_bytes(&build_rom()).unwrap();
+    system.enable_profiling(true);
+    system.run_frames(PROFILE_FRAMES as u64 + 5, true).unwrap();
+    assert!(system.profiling());
+    assert_eq!(system.profile_report().frames, PROFILE_FRAMES);
+
+    system.enable_profiling(false);
+    system.run_frame().unwrap();
+    assert!(!system.profiling());
+    assert_eq!(system.profile_report().frames, 0);
+}
@@ -0,0 +1,364 @@
+//! The public API, pinned down so changes to it are deliberate
+//!
+//! `test_public_api_matches_snapshot` parses the crate's source, keeps the
+//! items reachable from outside with their signatures, and compares the
+//! result with `tests/public_api.txt`. Adding, removing or changing a
+//! public item fails it until the file is updated, which puts the change
+//! in front of a reviewer. Set `UPDATE_PUBLIC_API=1` to rewrite the file.
+//! The other tests use the prelude the way a frontend would, so a
+//! re-export that stops compiling is caught too.
+
+use emu_nes::prelude::*;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use syn::{Attribute, Fields, ImplItem, Item, Type, Visibility};
+
+/// A parsed source file and the directory its child modules live in
+struct Module {
+    path: String,
+    items: Vec<Item>,
+    dir: PathBuf,
+}
+
+fn parse(file: &Path) -> Vec<Item> {
+    let text = std::fs::read_to_string(file).unwrap_or_else(|e| panic!("{}: {}", file.display(), e));
+    syn::parse_file(&text).unwrap_or_else(|e| panic!("{}: {}", file.display(), e)).items
+}
+
+fn is_pub(vis: &Visibility) -> bool {
+    matches!(vis, Visibility::Public(_))
+}
+
+/// `#[cfg(test)]` exactly; feature-gated items are part of the API
+fn is_test_only(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|attr| {
+        attr.path().is_ident("cfg") && attr.parse_args::<syn::Ident>().is_ok_and(|ident| ident == "test")
+    })
+}
+
+/// The attributes that change what a user can do with an item
+fn api_attrs(attrs: &mut Vec<Attribute>) {
+    attrs.retain(|attr| ["derive", "cfg", "cfg_attr", "non_exhaustive"].iter().any(|name| attr.path().is_ident(name)));
+}
+
+/// Every module reachable through `pub mod` from the crate root
+fn public_modules(src: &Path) -> Vec<Module> {
+    let mut modules = Vec::new();
+    let mut pending = vec![Module { path: "emu_nes".into(), items: parse(&src.join("lib.rs")), dir: src.into() }];
+    while let Some(module) = pending.pop() {
+        for item in &module.items {
+            let Item::Mod(child) = item else { continue };
+            if !is_pub(&child.vis) || is_test_only(&child.attrs) {
+                continue;
+            }
+            let name = child.ident.to_string();
+            let path = format!("{}::{}", module.path, name);
+            let dir = module.dir.join(&name);
+            let items = match &child.content {
+                Some((_, items)) => items.clone(),
+                None if dir.join("mod.rs").exists() => parse(&dir.join("mod.rs")),
+                None => parse(&module.dir.join(format!("{}.rs", name))),
+            };
+            pending.push(Module { path, items, dir });
+        }
+        modules.push(module);
+    }
+    modules.sort_by(|a, b| a.path.cmp(&b.path));
+    modules
+}
+
+/// The name a type is implemented under, `NesSystem` for `NesSystem<M>`
+fn type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(path) => path.path.segments.last().map(|segment| segment.ident.to_string()),
+        _ => None,
+    }
+}
+
+/// The public items of `module`, bodies and private fields removed
+fn public_items(module: &Module, public_types: &BTreeSet<String>, crate_traits: &BTreeSet<String>) -> Vec<Item> {
+    let mut items = Vec::new();
+    for item in &module.items {
+        let mut item = item.clone();
+        let keep = match &mut item {
+            Item::Mod(_) | Item::Impl(_) | Item::Use(_) => false,
+            Item::Fn(f) => {
+                f.block.stmts.clear();
+                is_pub(&f.vis)
+            }
+            Item::Struct(s) => {
+                match &mut s.fields {
+                    Fields::Named(fields) => {
+                        fields.named = fields.named.clone().into_iter().filter(|field| is_pub(&field.vis)).collect();
+                    }
+                    Fields::Unnamed(fields) => {
+                        fields.unnamed = fields.unnamed.clone().into_iter().filter(|field| is_pub(&field.vis)).collect();
+                    }
+                    Fields::Unit => {}
+                }
+                is_pub(&s.vis)
+            }
+            Item::Trait(t) => {
+                for trait_item in &mut t.items {
+                    if let syn::TraitItem::Fn(f) = trait_item {
+                        f.default = None;
+                        f.semi_token = Some(Default::default());
+                    }
+                }
+                is_pub(&t.vis)
+            }
+            Item::Enum(e) => is_pub(&e.vis),
+            Item::Type(t) => is_pub(&t.vis),
+            Item::Const(c) => is_pub(&c.vis),
+            Item::Static(s) => is_pub(&s.vis),
+            _ => false,
+        };
+        if keep && !is_test_only(item_attrs(&item)) {
+            items.push(item);
+        }
+    }
+
+    // Re-exports, then impls of public types: inherent ones by their
+    // public functions, trait ones whole unless the trait is crate-private
+    for item in &module.items {
+        match item {
+            Item::Use(u) if is_pub(&u.vis) => items.push(item.clone()),
+            Item::Impl(imp) if !is_test_only(&imp.attrs) => {
+                if !type_name(&imp.self_ty).is_some_and(|name| public_types.contains(&name)) {
+                    continue;
+                }
+                let mut imp = imp.clone();
+                match &imp.trait_ {
+                    Some((_, path, _)) => {
+                        let name = path.segments.last().unwrap().ident.to_string();
+                        if crate_traits.contains(&name) && !public_types.contains(&name) {
+                            continue;
+                        }
+                        imp.items.retain(|item| matches!(item, ImplItem::Type(_) | ImplItem::Const(_)));
+                    }
+                    None => {
+                        imp.items.retain(|item| match item {
+                            ImplItem::Fn(f) => is_pub(&f.vis) && !is_test_only(&f.attrs),
+                            ImplItem::Const(c) => is_pub(&c.vis),
+                            _ => false,
+                        });
+                        if imp.items.is_empty() {
+                            continue;
+                        }
+                    }
+                }
+                for item in &mut imp.items {
+                    match item {
+                        ImplItem::Fn(f) => {
+                            f.block.stmts.clear();
+                            api_attrs(&mut f.attrs);
+                        }
+                        ImplItem::Const(c) => {
+                            api_attrs(&mut c.attrs);
+                            c.expr = syn::parse_quote!(_);
+                        }
+                        ImplItem::Type(t) => api_attrs(&mut t.attrs),
+                        _ => {}
+                    }
+                }
+                api_attrs(&mut imp.attrs);
+                items.push(Item::Impl(imp));
+            }
+            _ => {}
+        }
+    }
+
+    for item in &mut items {
+        strip_docs(item);
+    }
+    items
+}
+
+fn item_attrs(item: &Item) -> &[Attribute] {
+    match item {
+        Item::Fn(f) => &f.attrs,
+        Item::Struct(s) => &s.attrs,
+        Item::Trait(t) => &t.attrs,
+        Item::Enum(e) => &e.attrs,
+        Item::Type(t) => &t.attrs,
+        Item::Const(c) => &c.attrs,
+        Item::Static(s) => &s.attrs,
+        _ => &[],
+    }
+}
+
+/// Drop doc comments and other attributes that aren't API, down to
+/// fields and variants
+fn strip_docs(item: &mut Item) {
+    let fields = |fields: &mut Fields| {
+        for field in fields.iter_mut() {
+            api_attrs(&mut field.attrs);
+        }
+    };
+    match item {
+        Item::Fn(f) => api_attrs(&mut f.attrs),
+        Item::Struct(s) => {
+            api_attrs(&mut s.attrs);
+            fields(&mut s.fields);
+        }
+        Item::Enum(e) => {
+            api_attrs(&mut e.attrs);
+            for variant in &mut e.variants {
+                api_attrs(&mut variant.attrs);
+                fields(&mut variant.fields);
+            }
+        }
+        Item::Trait(t) => {
+            api_attrs(&mut t.attrs);
+            for trait_item in &mut t.items {
+                match trait_item {
+                    syn::TraitItem::Fn(f) => api_attrs(&mut f.attrs),
+                    syn::TraitItem::Type(t) => api_attrs(&mut t.attrs),
+                    syn::TraitItem::Const(c) => api_attrs(&mut c.attrs),
+                    _ => {}
+                }
+            }
+        }
+        Item::Type(t) => api_attrs(&mut t.attrs),
+        Item::Const(c) => {
+            api_attrs(&mut c.attrs);
+            // The value isn't part of the signature
+            *c.expr = syn::parse_quote!(_);
+        }
+        Item::Static(s) => {
+            api_attrs(&mut s.attrs);
+            *s.expr = syn::parse_quote!(_);
+        }
+        Item::Use(u) => api_attrs(&mut u.attrs),
+        _ => {}
+    }
+}
+
+/// The public API as Rust-like text, one section per module
+fn public_api(src: &Path) -> String {
+    let modules = public_modules(src);
+    let mut public_types = BTreeSet::new();
+    let mut crate_traits = BTreeSet::new();
+    for item in modules.iter().flat_map(|module| module.items.iter()) {
+        match item {
+            Item::Struct(s) if is_pub(&s.vis) => {
+                public_types.insert(s.ident.to_string());
+            }
+            Item::Enum(e) if is_pub(&e.vis) => {
+                public_types.insert(e.ident.to_string());
+            }
+            Item::Type(t) if is_pub(&t.vis) => {
+                public_types.insert(t.ident.to_string());
+            }
+            Item::Trait(t) => {
+                crate_traits.insert(t.ident.to_string());
+                if is_pub(&t.vis) {
+                    public_types.insert(t.ident.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    // Traits in private modules are crate-private however they're declared
+    for module in &modules {
+        for item in &module.items {
+            let Item::Mod(child) = item else { continue };
+            if is_pub(&child.vis) || child.content.is_some() {
+                continue;
+            }
+            let name = child.ident.to_string();
+            let file = [module.dir.join(format!("{}.rs", name)), module.dir.join(&name).join("mod.rs")]
+                .into_iter()
+                .find(|file| file.exists());
+            for item in file.map(|file| parse(&file)).unwrap_or_default() {
+                if let Item::Trait(t) = item {
+                    crate_traits.insert(t.ident.to_string());
+                }
+            }
+        }
+    }
+
+    let mut api = String::new();
+    for module in &modules {
+        let items = public_items(module, &public_types, &crate_traits);
+        if items.is_empty() {
+            continue;
+        }
+        let file = syn::File { shebang: None, attrs: Vec::new(), items };
+        api.push_str(&format!("// {}\n", module.path));
+        for line in prettyplease::unparse(&file).lines() {
+            // Bodies were emptied, so a function ends in `{}`
+            match line.strip_suffix(" {}") {
+                Some(signature) if signature.contains("fn ") => api.push_str(&format!("{};\n", signature)),
+                _ => api.push_str(&format!("{}\n", line)),
+            }
+        }
+        api.push('\n');
+    }
+    api
+}
+
+#[test]
+fn test_public_api_matches_snapshot() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let actual = public_api(&dir.join("src"));
+    let snapshot = dir.join("tests/public_api.txt");
+    if std::env::var_os("UPDATE_PUBLIC_API").is_some() {
+        std::fs::write(&snapshot, &actual).unwrap();
+    }
+
+    let expected = std::fs::read_to_string(&snapshot).unwrap();
+    if actual != expected {
+        let changed = actual
+            .lines()
+            .zip(expected.lines())
+            .position(|(a, e)| a != e)
+            .unwrap_or(actual.lines().count().min(expected.lines().count()));
+        panic!(
+            "The public API changed at line {} of tests/public_api.txt:\n  now:  {}\n  was:  {}\n\
+             If that's intended, rerun with UPDATE_PUBLIC_API=1 and review the diff",
+            changed + 1,
+            actual.lines().nth(changed).unwrap_or("<end>"),
+            expected.lines().nth(changed).unwrap_or("<end>"),
+        );
+    }
+}
+
+/// Loading, running and reading a frame back through the prelude alone
+#[test]
+fn test_prelude_drives_a_system() {
+    let mut rom = b"NES\x1a\x01\x01\x00\x00".to_vec();
+    rom.resize(16, 0);
+    let mut prg = vec![0xEA; 0x4000];
+    prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+    rom.extend(prg);
+    rom.extend(vec![0u8; 0x2000]);
+
+    let mut system = NesSystem::from_bytes(&rom).unwrap();
+    let info: CartridgeInfo = system.cartridge_info().unwrap();
+    assert_eq!(info.mirroring, Mirroring::Horizontal);
+    assert_eq!(info.region, Region::Ntsc);
+    assert_eq!(info.crc32, crc32(&rom[16..]));
+
+    system.press_button(Button::START);
+    system.run_frame().unwrap();
+    let rgb = framebuffer_to_rgb(system.framebuffer());
+    assert_eq!(rgb.len(), system.framebuffer().len() * 3);
+
+    let mut state = ControllerState::new();
+    state.press(Button::A);
+    assert!(state.is_pressed(Button::A));
+}
+
+/// Frontends matching on errors need a catch-all, so new variants don't
+/// break them
+#[test]
+fn test_errors_match_with_a_catch_all() {
+    let describe = |error: &EmulatorError| match error {
+        EmulatorError::RomTooShort { .. } => "short",
+        EmulatorError::InvalidPatch(_) => "patch",
+        _ => "other",
+    };
+    assert_eq!(describe(&NesSystem::from_bytes(b"NES\x1a\x01\x01\x00\x00\0\0\0\0\0\0\0\0").unwrap_err()), "short");
+    assert_eq!(describe(&EmulatorError::ServiceStopped), "other");
+}
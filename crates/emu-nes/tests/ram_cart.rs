@@ -0,0 +1,126 @@
+//! RAM cart: code and vectors written at runtime, and properties of the
+//! CPU running random code from it
+
+mod common;
+
+use common::ram_cart;
+use emu_nes::NesSystem;
+use nes_asm::Assembler;
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+/// Most instructions each random program runs for
+const STEPS: usize = 500;
+
+/// A program at `base` storing `value` to $0200, then idling at "idle"
+fn store_and_idle(base: u16, value: u8) -> Assembler {
+    let mut asm = Assembler::new(base, 0x10);
+    asm.lda_imm(value).sta_abs(0x0200).label("idle").jmp("idle");
+    asm
+}
+
+/// Step until `STEPS` instructions have run or the CPU jams, checking that
+/// every instruction takes time
+fn run(system: &mut NesSystem) -> std::result::Result<(), TestCaseError> {
+    for _ in 0..STEPS {
+        let before = system.cpu().cycles;
+        let Ok(cycles) = system.step() else {
+            break;
+        };
+        prop_assert!(cycles > 0);
+        prop_assert!(system.cpu().cycles > before, "cycles went from {} to {}", before, system.cpu().cycles);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_code_runs_and_can_be_swapped() {
+    let first = store_and_idle(0x8000, 0x42);
+    let mut system = ram_cart(&first.assemble().unwrap());
+    for _ in 0..4 {
+        system.step().unwrap();
+    }
+    assert_eq!(system.peek_memory(0x0200), 0x42);
+    assert_eq!(system.cpu().pc, first.address_of("idle").unwrap());
+
+    // A new program and a new reset vector, no new cartridge
+    let second = store_and_idle(0x9000, 0x17);
+    system.write_code(0x9000, &second.assemble().unwrap()).unwrap();
+    system.set_vectors(0x8000, 0x9000, 0x8000).unwrap();
+    system.reset();
+    for _ in 0..4 {
+        system.step().unwrap();
+    }
+    assert_eq!(system.peek_memory(0x0200), 0x17);
+    assert_eq!(system.cpu().pc, second.address_of("idle").unwrap());
+}
+
+#[test]
+fn test_cpu_writes_to_the_cart_stick() {
+    // The program writes an RTS for itself to call
+    let mut asm = Assembler::new(0x8000, 0x10);
+    asm.lda_imm(0x60).sta_abs(0xC000).jsr(0xC000).label("idle").jmp("idle");
+    let mut system = ram_cart(&asm.assemble().unwrap());
+    for _ in 0..5 {
+        system.step().unwrap();
+    }
+    assert_eq!(system.peek_memory(0xC000), 0x60);
+    assert_eq!(system.cpu().pc, asm.address_of("idle").unwrap());
+}
+
+#[test]
+fn test_savestates_carry_the_code() {
+    let mut asm = Assembler::new(0x8000, 3);
+    asm.label("idle").jmp("idle");
+    let code = asm.assemble().unwrap();
+    let mut system = ram_cart(&code);
+    let state = system.save_state();
+    system.write_code(0x8000, &[0xEA; 3]).unwrap();
+    system.load_state(&state).unwrap();
+    assert_eq!(system.peek_memory(0x8000), code[0]);
+}
+
+#[test]
+fn test_code_only_goes_on_a_ram_cart() {
+    let mut system = NesSystem::with_ram_cart();
+    assert!(system.write_code(0x7FFF, &[0xEA]).is_err());
+    // Bytes past the end are dropped
+    system.write_code(0xFFFF, &[0x01, 0x02]).unwrap();
+    assert_eq!(system.peek_memory(0xFFFF), 0x01);
+    assert_eq!(system.peek_memory(0x8000), 0x00);
+
+    let mut rom = NesSystem::with_prg_rom(vec![0xEA; 0x4000]).unwrap();
+    assert!(rom.write_code(0x8000, &[0x00]).is_err());
+    assert!(rom.set_vectors(0x8000, 0x8000, 0x8000).is_err());
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_random_code_never_stalls(code in vec(any::<u8>(), 1..512)) {
+        run(&mut ram_cart(&code))?;
+    }
+
+    #[test]
+    fn test_stack_stays_in_page_1(code in vec(any::<u8>(), 1..512)) {
+        // Whatever the random code left SP at, a push lands in page 1 and
+        // nowhere else, wrapping within it
+        let mut system = ram_cart(&code);
+        run(&mut system)?;
+        let sp = system.cpu().sp;
+        let [page_0, page_1, page_2] = [0x0000, 0x0100, 0x0200].map(|page| system.peek_memory(page | sp as u16));
+        system.write_code(0xF000, &[0x48]).unwrap();
+        let cpu = system.cpu_mut();
+        cpu.pc = 0xF000;
+        cpu.a = !page_1;
+        // PHA; if an interrupt is taken straight after, it pushes 3 more
+        if system.step().is_ok() {
+            prop_assert_eq!(system.peek_memory(0x0100 | sp as u16), !page_1);
+            prop_assert_eq!(system.peek_memory(sp as u16), page_0);
+            prop_assert_eq!(system.peek_memory(0x0200 | sp as u16), page_2);
+            let pushed = sp.wrapping_sub(system.cpu().sp);
+            prop_assert!(pushed == 1 || pushed == 4, "SP went from {:02X} to {:02X}", sp, system.cpu().sp);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Header correction from the ROM database, end to end
+//!
+//! The ROM is a 64KB GxROM (mapper 66) board whose header claims NROM.
+//! Its reset code switches to PRG bank 1 by writing $8000; the code in
+//! bank 1 stores $42 at $10. Under NROM the write does nothing and the
+//! code that follows in bank 0 stores $13 instead.
+
+use emu_nes::cartridge::Mirroring;
+use emu_nes::romdb::RomDb;
+use emu_nes::{Cartridge, NesSystem};
+
+/// Where the marker is stored
+const MARKER: u16 = 0x0010;
+
+/// Same code in both 32KB banks, apart from the marker it stores
+fn bank(marker: u8) -> Vec<u8> {
+    let mut prg = vec![0xEA; 0x8000];
+    let code = [
+        0x78, // SEI
+        0xA9, 0x10, 0x8D, 0x00, 0x80, // LDA #$10; STA $8000 (PRG bank 1)
+        0xA9, marker, 0x85, MARKER as u8, // LDA #marker; STA $10
+        0x4C, 0x0A, 0x80, // idle: JMP idle
+    ];
+    prg[..code.len()].copy_from_slice(&code);
+    prg[0x7FFA..].copy_from_slice(&[0x0A, 0x80, 0x00, 0x80, 0x0A, 0x80]);
+    prg
+}
+
+/// iNES image with an NROM header over GxROM data
+fn build_rom() -> Vec<u8> {
+    let mut rom = b"NES\x1a\x04\x01\x00\x00".to_vec();
+    rom.resize(16, 0);
+    rom.extend(bank(0x13));
+    rom.extend(bank(0x42));
+    rom.extend(vec![0u8; 0x2000]);
+    rom
+}
+
+fn marker(cartridge: Cartridge) -> u8 {
+    let mut system = NesSystem::from_cartridge(cartridge).unwrap();
+    system.run_frame().unwrap();
+    system.read_memory(MARKER)
+}
+
+#[test]
+fn test_database_entry_fixes_the_mapper() {
+    let rom = build_rom();
+    let crc32 = Cartridge::from_bytes(&rom).unwrap().crc32();
+    let db = RomDb::from_csv(&format!(
+        "crc32,mapper,mirroring,prg_ram_kb,region,name\n{:08X},66,vertical,0,ntsc,GxROM test\n",
+        crc32
+    ))
+    .unwrap();
+
+    let cartridge = Cartridge::from_bytes_with_db(&rom, &db).unwrap();
+    let info = cartridge.info();
+    assert!(info.header_corrected);
+    assert_eq!((info.crc32, info.mapper, info.mirroring, info.prg_ram_size), (crc32, 66, Mirroring::Vertical, 0));
+    assert_eq!(marker(cartridge), 0x42);
+
+    // The embedded database doesn't know this ROM, so its header stands
+    let cartridge = Cartridge::from_bytes(&rom).unwrap();
+    assert!(!cartridge.info().header_corrected);
+    assert_eq!(cartridge.info().mapper, 0);
+    assert_eq!(marker(cartridge), 0x13);
+}
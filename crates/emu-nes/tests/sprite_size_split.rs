@@ -0,0 +1,113 @@
+//! Switching PPUCTRL's sprite size partway down the screen
+//!
+//! The NMI handler selects 8x8 sprites, waits in a timed loop until about
+//! scanline 100 of the next frame, then selects 8x16. The same sprite is
+//! drawn above and below the split: tile 2 is solid colour 1 and tile 3,
+//! the bottom half of an 8x16 sprite using tile 2, is solid colour 2.
+//! Sprites above the split must be 8 lines tall and below it 16.
+
+use emu_nes::ppu::PpuCtrl;
+use emu_nes::NesSystem;
+
+/// Sprite Y positions, either side of the split
+const TOP_SPRITE_Y: usize = 40;
+const BOTTOM_SPRITE_Y: usize = 150;
+
+/// Sprite X position
+const SPRITE_X: usize = 100;
+
+/// Colours of sprite palette 0, entries 1 and 2
+const TOP_HALF_COLOUR: u8 = 0x16;
+const BOTTOM_HALF_COLOUR: u8 = 0x2A;
+
+/// Backdrop colour
+const BACKDROP: u8 = 0x0F;
+
+fn build_rom() -> Vec<u8> {
+    let mut prg = vec![0xEA; 0x4000];
+    let code = [
+        // --- Reset ($C000) ---
+        0x78, // SEI
+        0xA2, 0xFF, 0x9A, // LDX #$FF; TXS
+        0x2C, 0x02, 0x20, 0x10, 0xFB, // vblank1: BIT $2002; BPL vblank1
+        0x2C, 0x02, 0x20, 0x10, 0xFB, // vblank2: BIT $2002; BPL vblank2
+        // Sprite palette 0: backdrop, colour 1, colour 2
+        0xA9, 0x3F, 0x8D, 0x06, 0x20, // LDA #$3F; STA $2006
+        0xA9, 0x10, 0x8D, 0x06, 0x20, // LDA #$10; STA $2006
+        0xA9, BACKDROP, 0x8D, 0x07, 0x20, // LDA #backdrop; STA $2007
+        0xA9, TOP_HALF_COLOUR, 0x8D, 0x07, 0x20, // LDA #colour1; STA $2007
+        0xA9, BOTTOM_HALF_COLOUR, 0x8D, 0x07, 0x20, // LDA #colour2; STA $2007
+        // Sprites 0 and 1: tile 2 at the top and the bottom
+        0xA9, 0x00, 0x8D, 0x03, 0x20, // LDA #0; STA $2003
+        0xA2, 0x00, // LDX #0
+        0xBD, 0x00, 0xC1, 0x8D, 0x04, 0x20, // oam: LDA $C100,X; STA $2004
+        0xE8, 0xE0, 0x08, 0xD0, 0xF5, // INX; CPX #8; BNE oam
+        // Move the other 62 sprites off screen
+        0xA9, 0xFF, // LDA #$FF
+        0x8D, 0x04, 0x20, 0xE8, 0xD0, 0xFA, // hide: STA $2004; INX; BNE hide
+        0xA9, 0x10, 0x8D, 0x01, 0x20, // LDA #$10; STA $2001 (sprites on)
+        0xA9, 0x80, 0x8D, 0x00, 0x20, // LDA #$80; STA $2000 (NMI on, 8x8)
+        0x4C, 0x4B, 0xC0, // idle: JMP idle
+    ];
+    prg[..code.len()].copy_from_slice(&code);
+    assert_eq!(code.len(), 0x4E, "idle loop must be at $C04B");
+
+    // OAM for sprites 0 and 1
+    prg[0x100..0x108].copy_from_slice(&[
+        TOP_SPRITE_Y as u8, 0x02, 0x00, SPRITE_X as u8,
+        BOTTOM_SPRITE_Y as u8, 0x02, 0x00, SPRITE_X as u8,
+    ]);
+
+    // --- NMI ($C200) ---
+    // 11 * 1286 cycles from the start of vblank is about 124 scanlines,
+    // which lands near scanline 103 of the next frame
+    prg[0x200..0x215].copy_from_slice(&[
+        0xA9, 0x80, 0x8D, 0x00, 0x20, // LDA #$80; STA $2000 (8x8)
+        0xA2, 0x0B, // LDX #11
+        0xA0, 0x00, // outer: LDY #0
+        0x88, 0xD0, 0xFD, // inner: DEY; BNE inner
+        0xCA, 0xD0, 0xF8, // DEX; BNE outer
+        0xA9, 0xA0, 0x8D, 0x00, 0x20, // LDA #$A0; STA $2000 (8x16)
+        0x40, // RTI
+    ]);
+    prg[0x3FFA..].copy_from_slice(&[0x00, 0xC2, 0x00, 0xC0, 0x00, 0xC2]);
+
+    // CHR: tile 2 solid colour 1, tile 3 solid colour 2
+    let mut chr = vec![0u8; 0x2000];
+    chr[0x20..0x28].fill(0xFF);
+    chr[0x38..0x40].fill(0xFF);
+
+    let mut rom = b"NES\x1a\x01\x01\x00\x00".to_vec();
+    rom.resize(16, 0);
+    rom.extend_from_slice(&prg);
+    rom.extend_from_slice(&chr);
+    rom
+}
+
+#[test]
+fn test_sprite_size_follows_mid_frame_ppuctrl_writes() {
+    let mut system = NesSystem::from_bytes(&build_rom()).unwrap();
+    // The reset code's vblank waits poll $2002 and can lose a vblank to
+    // the read race, so leave a frame spare before the one checked
+    for _ in 0..5 {
+        system.run_frame().unwrap();
+    }
+
+    // The split happened between the two sprites
+    let tall = |system: &mut NesSystem, line: usize| {
+        system.ppu().scroll_latch(line).unwrap().ctrl.contains(PpuCtrl::SPRITE_SIZE)
+    };
+    let split = (0..240).find(|&line| tall(&mut system, line)).expect("8x16 was never selected");
+    assert!(split > TOP_SPRITE_Y + 16 && split < BOTTOM_SPRITE_Y, "split at scanline {}", split);
+    assert!((split..240).all(|line| tall(&mut system, line)));
+
+    let framebuffer = system.framebuffer().to_vec();
+    let column = |y: usize| framebuffer[y * 256 + SPRITE_X + 3];
+    for row in 0..16 {
+        let above = if row < 8 { TOP_HALF_COLOUR } else { BACKDROP };
+        assert_eq!(column(TOP_SPRITE_Y + row), above, "8x8 sprite above the split, row {}", row);
+        let below = if row < 8 { TOP_HALF_COLOUR } else { BOTTOM_HALF_COLOUR };
+        assert_eq!(column(BOTTOM_SPRITE_Y + row), below, "8x16 sprite below the split, row {}", row);
+    }
+    assert_eq!(column(BOTTOM_SPRITE_Y + 16), BACKDROP);
+}
@@ -0,0 +1,21 @@
+//! $2002 reads racing the start of vblank; the ROM and its timing
+//! argument live in [`emu_nes::compliance::ppu::vbl_nmi_timing_prg`]
+
+use emu_nes::compliance::ppu::{next_frame, vbl_nmi_timing, vbl_nmi_timing_prg};
+use emu_nes::NesSystem;
+
+#[test]
+fn test_vbl_nmi_race_counts() {
+    assert_eq!(vbl_nmi_timing(), Ok(()));
+}
+
+#[test]
+fn test_debugger_reads_do_not_clock_the_ppu() {
+    let mut system = NesSystem::with_prg_rom(vbl_nmi_timing_prg()).unwrap();
+    next_frame(&mut system).unwrap();
+
+    let dots = system.ppu().dots();
+    system.read_memory(0x2002);
+    system.read_memory(0x4015);
+    assert_eq!(system.ppu().dots(), dots);
+}
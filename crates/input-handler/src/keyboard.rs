@@ -0,0 +1,58 @@
+//! Keyboard bindings shared by the frontends
+//!
+//! Keys are named as the GUI's toolkit reports them: the character typed,
+//! or an arrow for the arrow keys. Frontends reading the keyboard some
+//! other way (a terminal, say) translate to these names first, so every
+//! frontend plays with the same keys.
+
+use emu_core::Emulator;
+
+/// Keyboard keys for each player 1 button, by button name
+///
+/// A binding's position is its bit in the button sets of [`set_buttons`].
+pub const KEY_BINDINGS: &[(&[&str], &str)] = &[
+    (&["↑", "w", "W"], "Up"),
+    (&["↓", "s", "S"], "Down"),
+    (&["←", "a", "A"], "Left"),
+    (&["→", "d", "D"], "Right"),
+    (&["z", "Z"], "A"),
+    (&["x", "X"], "B"),
+    (&["\n", "\r"], "Start"),
+    (&[" "], "Select"),
+];
+
+/// The bit of the button bound to `key`, if it's bound
+pub fn key_button(key: &str) -> Option<usize> {
+    KEY_BINDINGS.iter().position(|(keys, _)| keys.contains(&key))
+}
+
+/// Hold exactly the buttons in `buttons` (a bit per binding) on `core`'s
+/// controller `port`
+///
+/// Bound buttons the controller doesn't have are skipped.
+pub fn set_buttons(core: &mut dyn Emulator, port: usize, buttons: u8) {
+    let Some(device) = core.input_device(port) else {
+        return;
+    };
+    for (bit, &(_, name)) in KEY_BINDINGS.iter().enumerate() {
+        if let Some(index) = (0..device.button_count()).find(|&index| device.button_name(index) == name) {
+            device.set_button_pressed(index, buttons & (1 << bit) != 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_button_has_a_bit() {
+        assert!(KEY_BINDINGS.len() <= 8);
+        for (keys, name) in KEY_BINDINGS {
+            for key in *keys {
+                assert_eq!(KEY_BINDINGS[key_button(key).unwrap()].1, *name);
+            }
+        }
+        assert_eq!(key_button("q"), None);
+    }
+}
@@ -1,3 +1,7 @@
+//! Input handling shared by the frontends
+
+pub mod keyboard;
+
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
 }
@@ -0,0 +1,774 @@
+//! A small 6502 assembler for building test ROMs from Rust
+//!
+//! The ROM generators under `examples/` used to poke opcode bytes into a
+//! buffer by hand and count branch offsets on their fingers. [`Assembler`]
+//! does that bookkeeping: instructions are chained method calls, jumps and
+//! branches can name a label defined before or after them, and mistakes
+//! (a branch that can't reach, a label used but never placed, code running
+//! off the end of the bank) come back as an [`AsmError`] rather than a
+//! silently broken ROM. [`InesBuilder`] wraps the result in an iNES header.
+//!
+//! ```
+//! use nes_asm::{Assembler, InesBuilder};
+//!
+//! let mut asm = Assembler::new(0xC000, 0x4000);
+//! asm.org(0xC000)
+//!     .label("reset")
+//!     .ldx_imm(0)
+//!     .label("loop")
+//!     .inx()
+//!     .bne("loop")
+//!     .label("hang")
+//!     .jmp("hang")
+//!     .label("nmi")
+//!     .rti()
+//!     .vectors("nmi", "reset", "nmi");
+//! let prg = asm.assemble().unwrap();
+//! let rom = InesBuilder::new(prg).build().unwrap();
+//! assert_eq!(rom.len(), 16 + 0x4000);
+//! ```
+
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Result type for assembling
+pub type Result<T> = std::result::Result<T, AsmError>;
+
+/// Ways a program can fail to assemble
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    #[error("Undefined label: {0}")]
+    UndefinedLabel(String),
+
+    #[error("Label defined twice: {0}")]
+    DuplicateLabel(String),
+
+    #[error("Branch at 0x{from:04X} to {target} is out of range (offset {offset})")]
+    BranchOutOfRange {
+        from: u16,
+        target: String,
+        offset: i32,
+    },
+
+    #[error("Address 0x{0:04X} is outside the assembled window")]
+    OutOfWindow(u32),
+
+    #[error("Address 0x{0:04X} was written twice")]
+    Overlap(u16),
+
+    #[error("{what} is {len} bytes, not a multiple of {unit}")]
+    RomSize {
+        what: &'static str,
+        len: usize,
+        unit: usize,
+    },
+}
+
+/// An address operand: a fixed address, or a label resolved at assembly
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Address(u16),
+    Label(String),
+}
+
+impl From<u16> for Target {
+    fn from(addr: u16) -> Self {
+        Target::Address(addr)
+    }
+}
+
+impl From<&str> for Target {
+    fn from(label: &str) -> Self {
+        Target::Label(label.to_string())
+    }
+}
+
+impl From<String> for Target {
+    fn from(label: String) -> Self {
+        Target::Label(label)
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Address(addr) => write!(f, "${:04X}", addr),
+            Target::Label(label) => f.write_str(label),
+        }
+    }
+}
+
+/// How a pending operand gets filled in once labels are known
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    /// Little-endian 16-bit address
+    Word,
+    /// Signed offset from the end of a 2-byte branch
+    Relative,
+}
+
+/// An operand waiting on label resolution
+#[derive(Debug, Clone)]
+struct Fixup {
+    /// Where the operand goes
+    addr: u16,
+    /// Address of the instruction it belongs to, for error reports
+    from: u16,
+    kind: FixupKind,
+    target: Target,
+}
+
+/// 6502 assembler writing into a fixed window of the address space
+///
+/// The window is usually one PRG bank: `Assembler::new(0xC000, 0x4000)`
+/// for a 16KB NROM image. Bytes nobody writes are left as the fill byte.
+/// Instruction methods return `&mut Self` so a program reads as a chain;
+/// the first error along the way is kept and reported by
+/// [`assemble`](Self::assemble). Operands that name a label are filled in
+/// there too, once every label is placed, so forward references work.
+#[derive(Debug, Clone)]
+pub struct Assembler {
+    /// CPU address of the first byte of the window
+    base: u16,
+    /// The window's contents
+    image: Vec<u8>,
+    /// Which bytes of the window have been written
+    written: Vec<bool>,
+    /// Location counter (wide so running past $FFFF is an error, not a wrap)
+    pc: u32,
+    labels: HashMap<String, u16>,
+    fixups: Vec<Fixup>,
+    /// First error hit while emitting
+    error: Option<AsmError>,
+}
+
+impl Assembler {
+    /// Assembler for `size` bytes starting at CPU address `base`, filled
+    /// with $00 and with the location counter at `base`
+    pub fn new(base: u16, size: usize) -> Self {
+        Self {
+            base,
+            image: vec![0; size],
+            written: vec![false; size],
+            pc: base as u32,
+            labels: HashMap::new(),
+            fixups: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Fill unwritten bytes with `fill` instead of $00
+    pub fn with_fill(mut self, fill: u8) -> Self {
+        for (byte, &written) in self.image.iter_mut().zip(&self.written) {
+            if !written {
+                *byte = fill;
+            }
+        }
+        self
+    }
+
+    /// Move the location counter to `addr`
+    pub fn org(&mut self, addr: u16) -> &mut Self {
+        self.pc = addr as u32;
+        self
+    }
+
+    /// Current location counter
+    pub fn pc(&self) -> u16 {
+        self.pc as u16
+    }
+
+    /// Place `name` at the current location
+    pub fn label(&mut self, name: &str) -> &mut Self {
+        if self.labels.insert(name.to_string(), self.pc as u16).is_some() {
+            self.fail(AsmError::DuplicateLabel(name.to_string()));
+        }
+        self
+    }
+
+    /// Address a label was placed at, if it has been
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.labels.get(name).copied()
+    }
+
+    /// Emit one data byte
+    pub fn byte(&mut self, value: u8) -> &mut Self {
+        self.emit(value);
+        self
+    }
+
+    /// Emit data bytes
+    pub fn bytes(&mut self, values: &[u8]) -> &mut Self {
+        for &value in values {
+            self.emit(value);
+        }
+        self
+    }
+
+    /// Emit a little-endian address
+    pub fn word(&mut self, target: impl Into<Target>) -> &mut Self {
+        let from = self.pc as u16;
+        self.operand_word(from, target.into());
+        self
+    }
+
+    /// Write the NMI, reset and IRQ vectors into the last six bytes of the
+    /// window, leaving the location counter where it was
+    ///
+    /// For a window ending at $FFFF that's $FFFA-$FFFF itself; a 16KB bank
+    /// assembled at $8000 is mirrored there on NROM-128, so the vectors
+    /// land in the same place either way.
+    pub fn vectors(
+        &mut self,
+        nmi: impl Into<Target>,
+        reset: impl Into<Target>,
+        irq: impl Into<Target>,
+    ) -> &mut Self {
+        let pc = self.pc;
+        self.pc = self.base as u32 + self.image.len() as u32 - 6;
+        self.word(nmi).word(reset).word(irq);
+        self.pc = pc;
+        self
+    }
+
+    /// Resolve labels and return the window's bytes
+    pub fn assemble(&self) -> Result<Vec<u8>> {
+        if let Some(err) = &self.error {
+            return Err(err.clone());
+        }
+
+        let mut image = self.image.clone();
+        for fixup in &self.fixups {
+            let addr = match &fixup.target {
+                Target::Address(addr) => *addr,
+                Target::Label(label) => self
+                    .address_of(label)
+                    .ok_or_else(|| AsmError::UndefinedLabel(label.clone()))?,
+            };
+            let index = (fixup.addr - self.base) as usize;
+            match fixup.kind {
+                FixupKind::Word => {
+                    image[index..index + 2].copy_from_slice(&addr.to_le_bytes());
+                }
+                FixupKind::Relative => {
+                    // Offsets count from the instruction after the branch
+                    let offset = addr as i32 - (fixup.from as i32 + 2);
+                    if !(-128..=127).contains(&offset) {
+                        return Err(AsmError::BranchOutOfRange {
+                            from: fixup.from,
+                            target: fixup.target.to_string(),
+                            offset,
+                        });
+                    }
+                    image[index] = offset as i8 as u8;
+                }
+            }
+        }
+        Ok(image)
+    }
+
+    /// Keep the first error only: later ones tend to be fallout from it
+    fn fail(&mut self, err: AsmError) {
+        self.error.get_or_insert(err);
+    }
+
+    /// Write a byte at the location counter and advance it
+    fn emit(&mut self, value: u8) {
+        let pc = self.pc;
+        self.pc += 1;
+        let Some(index) = pc
+            .checked_sub(self.base as u32)
+            .map(|index| index as usize)
+            .filter(|&index| index < self.image.len())
+        else {
+            self.fail(AsmError::OutOfWindow(pc));
+            return;
+        };
+        if self.written[index] {
+            self.fail(AsmError::Overlap(pc as u16));
+        }
+        self.image[index] = value;
+        self.written[index] = true;
+    }
+
+    /// Reserve a 16-bit operand for `target`, for the instruction at `from`
+    fn operand_word(&mut self, from: u16, target: Target) {
+        let addr = self.pc as u16;
+        self.emit(0);
+        self.emit(0);
+        self.fixups.push(Fixup { addr, from, kind: FixupKind::Word, target });
+    }
+
+    fn op_implied(&mut self, opcode: u8) -> &mut Self {
+        self.emit(opcode);
+        self
+    }
+
+    fn op_byte(&mut self, opcode: u8, value: u8) -> &mut Self {
+        self.emit(opcode);
+        self.emit(value);
+        self
+    }
+
+    fn op_word(&mut self, opcode: u8, target: Target) -> &mut Self {
+        let from = self.pc as u16;
+        self.emit(opcode);
+        self.operand_word(from, target);
+        self
+    }
+
+    fn op_relative(&mut self, opcode: u8, target: Target) -> &mut Self {
+        let from = self.pc as u16;
+        self.emit(opcode);
+        let addr = self.pc as u16;
+        self.emit(0);
+        self.fixups.push(Fixup { addr, from, kind: FixupKind::Relative, target });
+        self
+    }
+}
+
+/// Instruction methods, one per opcode, grouped by operand type
+macro_rules! instructions {
+    (
+        implied { $($implied:ident = $implied_op:literal, $implied_doc:literal;)* }
+        byte { $($byte:ident = $byte_op:literal, $byte_doc:literal;)* }
+        word { $($word:ident = $word_op:literal, $word_doc:literal;)* }
+        relative { $($rel:ident = $rel_op:literal, $rel_doc:literal;)* }
+    ) => {
+        impl Assembler {
+            $(
+                #[doc = $implied_doc]
+                pub fn $implied(&mut self) -> &mut Self {
+                    self.op_implied($implied_op)
+                }
+            )*
+            $(
+                #[doc = $byte_doc]
+                pub fn $byte(&mut self, value: u8) -> &mut Self {
+                    self.op_byte($byte_op, value)
+                }
+            )*
+            $(
+                #[doc = $word_doc]
+                pub fn $word(&mut self, target: impl Into<Target>) -> &mut Self {
+                    self.op_word($word_op, target.into())
+                }
+            )*
+            $(
+                #[doc = $rel_doc]
+                pub fn $rel(&mut self, target: impl Into<Target>) -> &mut Self {
+                    self.op_relative($rel_op, target.into())
+                }
+            )*
+        }
+    };
+}
+
+instructions! {
+    implied {
+        asl_a = 0x0A, "`ASL A`";
+        brk = 0x00, "`BRK`";
+        clc = 0x18, "`CLC`";
+        cld = 0xD8, "`CLD`";
+        cli = 0x58, "`CLI`";
+        clv = 0xB8, "`CLV`";
+        dex = 0xCA, "`DEX`";
+        dey = 0x88, "`DEY`";
+        inx = 0xE8, "`INX`";
+        iny = 0xC8, "`INY`";
+        lsr_a = 0x4A, "`LSR A`";
+        nop = 0xEA, "`NOP`";
+        pha = 0x48, "`PHA`";
+        php = 0x08, "`PHP`";
+        pla = 0x68, "`PLA`";
+        plp = 0x28, "`PLP`";
+        rol_a = 0x2A, "`ROL A`";
+        ror_a = 0x6A, "`ROR A`";
+        rti = 0x40, "`RTI`";
+        rts = 0x60, "`RTS`";
+        sec = 0x38, "`SEC`";
+        sed = 0xF8, "`SED`";
+        sei = 0x78, "`SEI`";
+        tax = 0xAA, "`TAX`";
+        tay = 0xA8, "`TAY`";
+        tsx = 0xBA, "`TSX`";
+        txa = 0x8A, "`TXA`";
+        txs = 0x9A, "`TXS`";
+        tya = 0x98, "`TYA`";
+    }
+    byte {
+        adc_imm = 0x69, "`ADC #value`";
+        adc_zp = 0x65, "`ADC zp`";
+        adc_zp_x = 0x75, "`ADC zp,X`";
+        adc_ind_x = 0x61, "`ADC (zp,X)`";
+        adc_ind_y = 0x71, "`ADC (zp),Y`";
+        and_imm = 0x29, "`AND #value`";
+        and_zp = 0x25, "`AND zp`";
+        and_zp_x = 0x35, "`AND zp,X`";
+        and_ind_x = 0x21, "`AND (zp,X)`";
+        and_ind_y = 0x31, "`AND (zp),Y`";
+        asl_zp = 0x06, "`ASL zp`";
+        asl_zp_x = 0x16, "`ASL zp,X`";
+        bit_zp = 0x24, "`BIT zp`";
+        cmp_imm = 0xC9, "`CMP #value`";
+        cmp_zp = 0xC5, "`CMP zp`";
+        cmp_zp_x = 0xD5, "`CMP zp,X`";
+        cmp_ind_x = 0xC1, "`CMP (zp,X)`";
+        cmp_ind_y = 0xD1, "`CMP (zp),Y`";
+        cpx_imm = 0xE0, "`CPX #value`";
+        cpx_zp = 0xE4, "`CPX zp`";
+        cpy_imm = 0xC0, "`CPY #value`";
+        cpy_zp = 0xC4, "`CPY zp`";
+        dec_zp = 0xC6, "`DEC zp`";
+        dec_zp_x = 0xD6, "`DEC zp,X`";
+        eor_imm = 0x49, "`EOR #value`";
+        eor_zp = 0x45, "`EOR zp`";
+        eor_zp_x = 0x55, "`EOR zp,X`";
+        eor_ind_x = 0x41, "`EOR (zp,X)`";
+        eor_ind_y = 0x51, "`EOR (zp),Y`";
+        inc_zp = 0xE6, "`INC zp`";
+        inc_zp_x = 0xF6, "`INC zp,X`";
+        lda_imm = 0xA9, "`LDA #value`";
+        lda_zp = 0xA5, "`LDA zp`";
+        lda_zp_x = 0xB5, "`LDA zp,X`";
+        lda_ind_x = 0xA1, "`LDA (zp,X)`";
+        lda_ind_y = 0xB1, "`LDA (zp),Y`";
+        ldx_imm = 0xA2, "`LDX #value`";
+        ldx_zp = 0xA6, "`LDX zp`";
+        ldx_zp_y = 0xB6, "`LDX zp,Y`";
+        ldy_imm = 0xA0, "`LDY #value`";
+        ldy_zp = 0xA4, "`LDY zp`";
+        ldy_zp_x = 0xB4, "`LDY zp,X`";
+        lsr_zp = 0x46, "`LSR zp`";
+        lsr_zp_x = 0x56, "`LSR zp,X`";
+        ora_imm = 0x09, "`ORA #value`";
+        ora_zp = 0x05, "`ORA zp`";
+        ora_zp_x = 0x15, "`ORA zp,X`";
+        ora_ind_x = 0x01, "`ORA (zp,X)`";
+        ora_ind_y = 0x11, "`ORA (zp),Y`";
+        rol_zp = 0x26, "`ROL zp`";
+        rol_zp_x = 0x36, "`ROL zp,X`";
+        ror_zp = 0x66, "`ROR zp`";
+        ror_zp_x = 0x76, "`ROR zp,X`";
+        sbc_imm = 0xE9, "`SBC #value`";
+        sbc_zp = 0xE5, "`SBC zp`";
+        sbc_zp_x = 0xF5, "`SBC zp,X`";
+        sbc_ind_x = 0xE1, "`SBC (zp,X)`";
+        sbc_ind_y = 0xF1, "`SBC (zp),Y`";
+        sta_zp = 0x85, "`STA zp`";
+        sta_zp_x = 0x95, "`STA zp,X`";
+        sta_ind_x = 0x81, "`STA (zp,X)`";
+        sta_ind_y = 0x91, "`STA (zp),Y`";
+        stx_zp = 0x86, "`STX zp`";
+        stx_zp_y = 0x96, "`STX zp,Y`";
+        sty_zp = 0x84, "`STY zp`";
+        sty_zp_x = 0x94, "`STY zp,X`";
+    }
+    word {
+        adc_abs = 0x6D, "`ADC addr`";
+        adc_abs_x = 0x7D, "`ADC addr,X`";
+        adc_abs_y = 0x79, "`ADC addr,Y`";
+        and_abs = 0x2D, "`AND addr`";
+        and_abs_x = 0x3D, "`AND addr,X`";
+        and_abs_y = 0x39, "`AND addr,Y`";
+        asl_abs = 0x0E, "`ASL addr`";
+        asl_abs_x = 0x1E, "`ASL addr,X`";
+        bit_abs = 0x2C, "`BIT addr`";
+        cmp_abs = 0xCD, "`CMP addr`";
+        cmp_abs_x = 0xDD, "`CMP addr,X`";
+        cmp_abs_y = 0xD9, "`CMP addr,Y`";
+        cpx_abs = 0xEC, "`CPX addr`";
+        cpy_abs = 0xCC, "`CPY addr`";
+        dec_abs = 0xCE, "`DEC addr`";
+        dec_abs_x = 0xDE, "`DEC addr,X`";
+        eor_abs = 0x4D, "`EOR addr`";
+        eor_abs_x = 0x5D, "`EOR addr,X`";
+        eor_abs_y = 0x59, "`EOR addr,Y`";
+        inc_abs = 0xEE, "`INC addr`";
+        inc_abs_x = 0xFE, "`INC addr,X`";
+        jmp = 0x4C, "`JMP addr`";
+        jmp_ind = 0x6C, "`JMP (addr)`";
+        jsr = 0x20, "`JSR addr`";
+        lda_abs = 0xAD, "`LDA addr`";
+        lda_abs_x = 0xBD, "`LDA addr,X`";
+        lda_abs_y = 0xB9, "`LDA addr,Y`";
+        ldx_abs = 0xAE, "`LDX addr`";
+        ldx_abs_y = 0xBE, "`LDX addr,Y`";
+        ldy_abs = 0xAC, "`LDY addr`";
+        ldy_abs_x = 0xBC, "`LDY addr,X`";
+        lsr_abs = 0x4E, "`LSR addr`";
+        lsr_abs_x = 0x5E, "`LSR addr,X`";
+        ora_abs = 0x0D, "`ORA addr`";
+        ora_abs_x = 0x1D, "`ORA addr,X`";
+        ora_abs_y = 0x19, "`ORA addr,Y`";
+        rol_abs = 0x2E, "`ROL addr`";
+        rol_abs_x = 0x3E, "`ROL addr,X`";
+        ror_abs = 0x6E, "`ROR addr`";
+        ror_abs_x = 0x7E, "`ROR addr,X`";
+        sbc_abs = 0xED, "`SBC addr`";
+        sbc_abs_x = 0xFD, "`SBC addr,X`";
+        sbc_abs_y = 0xF9, "`SBC addr,Y`";
+        sta_abs = 0x8D, "`STA addr`";
+        sta_abs_x = 0x9D, "`STA addr,X`";
+        sta_abs_y = 0x99, "`STA addr,Y`";
+        stx_abs = 0x8E, "`STX addr`";
+        sty_abs = 0x8C, "`STY addr`";
+    }
+    relative {
+        bcc = 0x90, "`BCC target`";
+        bcs = 0xB0, "`BCS target`";
+        beq = 0xF0, "`BEQ target`";
+        bmi = 0x30, "`BMI target`";
+        bne = 0xD0, "`BNE target`";
+        bpl = 0x10, "`BPL target`";
+        bvc = 0x50, "`BVC target`";
+        bvs = 0x70, "`BVS target`";
+    }
+}
+
+/// iNES image builder: header, PRG-ROM and CHR-ROM
+#[derive(Debug, Clone)]
+pub struct InesBuilder {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    mapper: u8,
+    vertical_mirroring: bool,
+    battery: bool,
+}
+
+impl InesBuilder {
+    /// Mapper 0, horizontal mirroring, no CHR-ROM (so CHR-RAM)
+    pub fn new(prg: Vec<u8>) -> Self {
+        Self {
+            prg,
+            chr: Vec::new(),
+            mapper: 0,
+            vertical_mirroring: false,
+            battery: false,
+        }
+    }
+
+    /// CHR-ROM contents, in 8KB banks
+    pub fn chr(mut self, chr: Vec<u8>) -> Self {
+        self.chr = chr;
+        self
+    }
+
+    pub fn mapper(mut self, mapper: u8) -> Self {
+        self.mapper = mapper;
+        self
+    }
+
+    pub fn vertical_mirroring(mut self) -> Self {
+        self.vertical_mirroring = true;
+        self
+    }
+
+    /// Mark PRG-RAM as battery-backed
+    pub fn battery(mut self) -> Self {
+        self.battery = true;
+        self
+    }
+
+    /// The complete ROM file
+    pub fn build(self) -> Result<Vec<u8>> {
+        let banks = |what, data: &[u8], unit: usize| {
+            if data.len().is_multiple_of(unit) && data.len() / unit <= 0xFF {
+                Ok((data.len() / unit) as u8)
+            } else {
+                Err(AsmError::RomSize { what, len: data.len(), unit })
+            }
+        };
+        let prg_banks = banks("PRG-ROM", &self.prg, 0x4000)?;
+        let chr_banks = banks("CHR-ROM", &self.chr, 0x2000)?;
+        if prg_banks == 0 {
+            return Err(AsmError::RomSize { what: "PRG-ROM", len: 0, unit: 0x4000 });
+        }
+
+        let flags6 = (self.mapper & 0x0F) << 4
+            | (self.battery as u8) << 1
+            | self.vertical_mirroring as u8;
+        let mut rom = vec![b'N', b'E', b'S', 0x1A, prg_banks, chr_banks, flags6, self.mapper & 0xF0];
+        rom.resize(16, 0);
+        rom.extend_from_slice(&self.prg);
+        rom.extend_from_slice(&self.chr);
+        Ok(rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_straight_line_code() {
+        let mut asm = Assembler::new(0x8000, 8);
+        asm.lda_imm(0x42).sta_abs(0x2007).inx().rts();
+        assert_eq!(
+            asm.assemble().unwrap(),
+            [0xA9, 0x42, 0x8D, 0x07, 0x20, 0xE8, 0x60, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_backward_and_forward_labels() {
+        let mut asm = Assembler::new(0x8000, 16);
+        asm.label("top")
+            .dex()
+            .bne("top")
+            .jmp("end")
+            .nop()
+            .label("end")
+            .jsr("top");
+        let bytes = asm.assemble().unwrap();
+        // BNE back over itself and the DEX: -3
+        assert_eq!(&bytes[..3], [0xCA, 0xD0, 0xFD]);
+        // JMP forward past the NOP to $8007
+        assert_eq!(&bytes[3..6], [0x4C, 0x07, 0x80]);
+        assert_eq!(&bytes[7..10], [0x20, 0x00, 0x80]);
+        assert_eq!(asm.address_of("end"), Some(0x8007));
+    }
+
+    #[test]
+    fn test_forward_branch() {
+        let mut asm = Assembler::new(0x8000, 8);
+        asm.beq("skip").nop().nop().label("skip").rts();
+        assert_eq!(&asm.assemble().unwrap()[..5], [0xF0, 0x02, 0xEA, 0xEA, 0x60]);
+    }
+
+    #[test]
+    fn test_branch_range_limits() {
+        // 127 bytes forward is the furthest a branch reaches
+        let mut asm = Assembler::new(0x8000, 0x100);
+        asm.bne("far").bytes(&[0xEA; 127]).label("far");
+        assert_eq!(asm.assemble().unwrap()[1], 0x7F);
+
+        let mut asm = Assembler::new(0x8000, 0x100);
+        asm.bne("far").bytes(&[0xEA; 128]).label("far");
+        assert_eq!(
+            asm.assemble(),
+            Err(AsmError::BranchOutOfRange {
+                from: 0x8000,
+                target: "far".to_string(),
+                offset: 128,
+            })
+        );
+
+        // And 128 back, counted from the end of the branch
+        let mut asm = Assembler::new(0x8000, 0x100);
+        asm.label("back").bytes(&[0xEA; 126]).bvc("back");
+        assert_eq!(asm.assemble().unwrap()[127], 0x80);
+
+        let mut asm = Assembler::new(0x8000, 0x100);
+        asm.label("back").bytes(&[0xEA; 127]).bvc("back");
+        assert!(matches!(
+            asm.assemble(),
+            Err(AsmError::BranchOutOfRange { offset: -129, .. })
+        ));
+    }
+
+    #[test]
+    fn test_branch_to_address() {
+        let mut asm = Assembler::new(0x8000, 4);
+        asm.org(0x8002).bpl(0x8000u16);
+        assert_eq!(&asm.assemble().unwrap()[2..], [0x10, 0xFC]);
+    }
+
+    #[test]
+    fn test_label_errors() {
+        let mut asm = Assembler::new(0x8000, 4);
+        asm.jmp("nowhere");
+        assert_eq!(
+            asm.assemble(),
+            Err(AsmError::UndefinedLabel("nowhere".to_string()))
+        );
+
+        let mut asm = Assembler::new(0x8000, 4);
+        asm.label("twice").nop().label("twice");
+        assert_eq!(
+            asm.assemble(),
+            Err(AsmError::DuplicateLabel("twice".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_org_and_fill() {
+        let mut asm = Assembler::new(0xC000, 0x10).with_fill(0xEA);
+        asm.org(0xC008).label("sub").rts();
+        asm.org(0xC000).jsr("sub");
+        assert_eq!(asm.pc(), 0xC003);
+        let bytes = asm.assemble().unwrap();
+        assert_eq!(&bytes[..4], [0x20, 0x08, 0xC0, 0xEA]);
+        assert_eq!(bytes[8], 0x60);
+        assert_eq!(bytes[15], 0xEA);
+    }
+
+    #[test]
+    fn test_org_errors() {
+        // Below the window
+        let mut asm = Assembler::new(0xC000, 0x10);
+        asm.org(0xBFFF).nop();
+        assert_eq!(asm.assemble(), Err(AsmError::OutOfWindow(0xBFFF)));
+
+        // Running off the end
+        let mut asm = Assembler::new(0xC000, 0x10);
+        asm.org(0xC00E).lda_abs(0x2002);
+        assert_eq!(asm.assemble(), Err(AsmError::OutOfWindow(0xC010)));
+
+        // Past $FFFF doesn't wrap back to $0000
+        let mut asm = Assembler::new(0x0000, 0x10000);
+        asm.org(0xFFFF).lda_imm(0);
+        assert_eq!(asm.assemble(), Err(AsmError::OutOfWindow(0x10000)));
+
+        // Two orgs over the same bytes
+        let mut asm = Assembler::new(0xC000, 0x10);
+        asm.org(0xC000).lda_abs(0x2002);
+        asm.org(0xC002).nop();
+        assert_eq!(asm.assemble(), Err(AsmError::Overlap(0xC002)));
+    }
+
+    #[test]
+    fn test_vectors_sit_at_the_top_of_the_window() {
+        let mut asm = Assembler::new(0x8000, 0x4000);
+        asm.label("reset").jmp("reset").label("nmi").rti();
+        asm.vectors("nmi", "reset", 0x1234u16);
+        // The location counter is untouched
+        assert_eq!(asm.pc(), 0x8004);
+        let bytes = asm.assemble().unwrap();
+        assert_eq!(&bytes[0x3FFA..], [0x03, 0x80, 0x00, 0x80, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn test_data_directives() {
+        let mut asm = Assembler::new(0x8000, 6);
+        asm.byte(1).bytes(&[2, 3]).label("here").word("here").byte(4);
+        assert_eq!(asm.assemble().unwrap(), [1, 2, 3, 0x03, 0x80, 4]);
+    }
+
+    #[test]
+    fn test_ines_header() {
+        let rom = InesBuilder::new(vec![0; 0x8000])
+            .chr(vec![0; 0x2000])
+            .mapper(0x42)
+            .vertical_mirroring()
+            .battery()
+            .build()
+            .unwrap();
+        assert_eq!(&rom[..16], [b'N', b'E', b'S', 0x1A, 2, 1, 0x23, 0x40, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(rom.len(), 16 + 0x8000 + 0x2000);
+
+        // No CHR means CHR-RAM
+        let rom = InesBuilder::new(vec![0; 0x4000]).build().unwrap();
+        assert_eq!(rom[5], 0);
+    }
+
+    #[test]
+    fn test_ines_rejects_partial_banks() {
+        assert_eq!(
+            InesBuilder::new(vec![0; 0x1000]).build(),
+            Err(AsmError::RomSize { what: "PRG-ROM", len: 0x1000, unit: 0x4000 })
+        );
+        assert!(InesBuilder::new(Vec::new()).build().is_err());
+        assert!(InesBuilder::new(vec![0; 0x4000]).chr(vec![0; 100]).build().is_err());
+    }
+}
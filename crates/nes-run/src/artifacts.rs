@@ -0,0 +1,209 @@
+//! What a run leaves behind: screenshots, the instruction trace and
+//! event timeline, bank and memory region dumps
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use emu_nes::{framebuffer_to_rgb, BankMapping, BankState, EmuEvent, EmuEventKind, MemoryRegion, NesSystem};
+use serde_json::{json, Value};
+
+/// Screen width in pixels
+const WIDTH: usize = 256;
+
+/// Screen height in pixels
+const HEIGHT: usize = 240;
+
+/// Write a framebuffer of palette indices as a binary (P6) PPM image
+pub fn write_ppm<W: Write>(out: &mut W, framebuffer: &[u8]) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", WIDTH, HEIGHT)?;
+    out.write_all(&framebuffer_to_rgb(framebuffer))
+}
+
+/// One trace line for the instruction about to execute
+///
+/// Formatted like nestest.log without the disassembly: PC, opcode, the
+/// registers, the PPU's scanline and dot, then CPU cycles.
+pub fn trace_line(system: &mut NesSystem) -> String {
+    let opcode = system.read_memory(system.cpu().pc);
+    let (scanline, dot) = {
+        let ppu = system.ppu();
+        (ppu.scanline(), ppu.cycle())
+    };
+    let cpu = system.cpu();
+    format!(
+        "{:04X}  {:02X}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:3},{:3} CYC:{}",
+        cpu.pc,
+        opcode,
+        cpu.a,
+        cpu.x,
+        cpu.y,
+        cpu.status.bits(),
+        cpu.sp,
+        scanline,
+        dot,
+        cpu.cycles
+    )
+}
+
+/// One timeline event as a JSON object
+///
+/// Every object has `frame`, `scanline`, `cycle` and `kind`, plus the
+/// fields of its kind: `sources` (IRQ source bits), `addr` and `value`,
+/// `page`, `bank` or `count`.
+pub fn event_json(event: &EmuEvent) -> Value {
+    let (kind, fields) = match event.kind {
+        EmuEventKind::Nmi => ("nmi", json!({})),
+        EmuEventKind::Irq(sources) => ("irq", json!({ "sources": sources.bits() })),
+        EmuEventKind::PpuWrite { addr, value } => ("ppu_write", json!({ "addr": addr, "value": value })),
+        EmuEventKind::OamDma { page } => ("oam_dma", json!({ "page": page })),
+        EmuEventKind::ApuStatus(value) => ("apu_status", json!({ "value": value })),
+        EmuEventKind::PrgBank(bank) => ("prg_bank", json!({ "bank": bank })),
+        EmuEventKind::ChrBank(bank) => ("chr_bank", json!({ "bank": bank })),
+        EmuEventKind::Dropped(count) => ("dropped", json!({ "count": count })),
+    };
+    let mut object = json!({
+        "frame": event.frame,
+        "scanline": event.scanline,
+        "cycle": event.cycle,
+        "kind": kind,
+    });
+    if let (Value::Object(object), Value::Object(fields)) = (&mut object, fields) {
+        object.extend(fields);
+    }
+    object
+}
+
+/// Human-readable list of the mapped PRG and CHR banks
+pub fn format_banks(state: &BankState) -> String {
+    let window = |kind: &str, mapping: &BankMapping| {
+        let end = mapping.window_start + (mapping.size - 1);
+        match (mapping.bank_index, mapping.rom_offset) {
+            (Some(bank), Some(offset)) => format!(
+                "{} ${:04X}-${:04X}: bank {:3} (ROM ${:05X})\n",
+                kind, mapping.window_start, end, bank, offset
+            ),
+            _ => format!("{} ${:04X}-${:04X}: open bus\n", kind, mapping.window_start, end),
+        }
+    };
+    let mut text: String = state
+        .prg
+        .iter()
+        .map(|mapping| window("PRG", mapping))
+        .chain(state.chr.iter().map(|mapping| window("CHR", mapping)))
+        .collect();
+    text += &format!("Mirroring: {:?}\n", state.mirroring);
+    text += &format!("IRQ: {}", if state.irq_pending { "pending" } else { "idle" });
+    if let Some(counter) = state.irq_counter {
+        text += &format!(", counter {}", counter);
+    }
+    text.push('\n');
+    text
+}
+
+/// A memory region and the file it's dumped to or loaded from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionFile {
+    pub region: MemoryRegion,
+    pub path: PathBuf,
+}
+
+impl RegionFile {
+    /// Parse `REGION=FILE`, e.g. `"cpu-ram=ram.bin"`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (region, path) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected REGION=FILE, got '{}'", spec))?;
+        if path.is_empty() {
+            return Err(format!("no file given for {}", region));
+        }
+        Ok(Self {
+            region: region.parse()?,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ppm_layout() {
+        let mut framebuffer = vec![0x0F; WIDTH * HEIGHT];
+        framebuffer[0] = 0x30;
+        let mut out = Vec::new();
+        write_ppm(&mut out, &framebuffer).unwrap();
+
+        let header = b"P6\n256 240\n255\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(out.len(), header.len() + WIDTH * HEIGHT * 3);
+        let (r, g, b) = emu_nes::palette_to_rgb(0x30);
+        assert_eq!(out[header.len()..][..3], [r, g, b]);
+        assert_eq!(out[out.len() - 3..], [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_format_banks() {
+        let mut prg = vec![0xEA; 0x4000];
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut system = NesSystem::with_prg_rom(prg).unwrap();
+        let text = format_banks(&system.bank_state().unwrap());
+
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 4 + 8 + 2);
+        assert_eq!(lines[0], "PRG $8000-$9FFF: bank   0 (ROM $00000)");
+        // 16KB of PRG-ROM is mirrored into the upper half
+        assert_eq!(lines[3], "PRG $E000-$FFFF: bank   1 (ROM $02000)");
+        assert_eq!(lines[11], "CHR $1C00-$1FFF: bank   7 (ROM $01C00)");
+        assert_eq!(lines[12], "Mirroring: Horizontal");
+        assert_eq!(lines[13], "IRQ: idle");
+    }
+
+    #[test]
+    fn test_trace_line() {
+        let mut prg = vec![0xEA; 0x4000];
+        prg[..2].copy_from_slice(&[0xA9, 0x42]); // LDA #$42
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut system = NesSystem::with_prg_rom(prg).unwrap();
+
+        assert_eq!(
+            trace_line(&mut system),
+            "8000  A9  A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:0"
+        );
+        system.step().unwrap();
+        assert_eq!(
+            trace_line(&mut system),
+            "8002  EA  A:42 X:00 Y:00 P:24 SP:FD PPU:  0,  6 CYC:2"
+        );
+    }
+
+    #[test]
+    fn test_event_json() {
+        let event = EmuEvent {
+            frame: 3,
+            scanline: 241,
+            cycle: 5,
+            kind: EmuEventKind::PpuWrite { addr: 0x2005, value: 0x10 },
+        };
+        assert_eq!(
+            event_json(&event),
+            json!({ "frame": 3, "scanline": 241, "cycle": 5, "kind": "ppu_write", "addr": 0x2005, "value": 0x10 })
+        );
+        let nmi = EmuEvent { kind: EmuEventKind::Nmi, ..event };
+        assert_eq!(event_json(&nmi).to_string(), r#"{"cycle":5,"frame":3,"kind":"nmi","scanline":241}"#);
+    }
+
+    #[test]
+    fn test_region_file() {
+        assert_eq!(
+            RegionFile::parse("ppu-palette=dumps/palette.bin"),
+            Ok(RegionFile {
+                region: MemoryRegion::PpuPalette,
+                path: PathBuf::from("dumps/palette.bin"),
+            })
+        );
+        assert!(RegionFile::parse("cpu-ram").is_err());
+        assert!(RegionFile::parse("cpu-ram=").is_err());
+        assert!(RegionFile::parse("sram=save.bin").unwrap_err().contains("unknown memory region"));
+    }
+}
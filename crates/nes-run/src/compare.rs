@@ -0,0 +1,453 @@
+//! `nes-run compare`: run a directory of ROMs and diff the results
+//!
+//! Every ROM runs for the same number of frames and its last frame is
+//! hashed into a manifest. Run it once before a rendering change and once
+//! after with `--baseline`, and the summary lists which ROMs draw something
+//! different. With `--images` each frame is also saved as a reference frame
+//! (palette indices as grey levels), which is what `--dump-diffs` needs from
+//! the baseline run to draw side-by-side diff images.
+
+use anyhow::{Context, Result};
+use clap::Args;
+use emu_nes::savestate::crc32;
+use emu_nes::frame_compare::{load_reference_frame, save_diff_image, save_reference_frame};
+use emu_nes::NesSystem;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Run every ROM in a directory and compare with an earlier run
+#[derive(Args, Debug)]
+pub struct CompareArgs {
+    /// Directory of .nes files
+    #[arg(value_name = "DIR")]
+    dir: PathBuf,
+
+    /// Frames to run each ROM for
+    #[arg(short, long, default_value_t = 600)]
+    frames: u64,
+
+    /// Where to write this run's manifest
+    #[arg(short, long, value_name = "JSON", default_value = "manifest.json")]
+    output: PathBuf,
+
+    /// Also save each ROM's last frame here, for a later run's --dump-diffs
+    #[arg(long, value_name = "DIR")]
+    images: Option<PathBuf>,
+
+    /// Manifest of an earlier run to compare with
+    #[arg(long, value_name = "JSON")]
+    baseline: Option<PathBuf>,
+
+    /// Write diff images of changed ROMs here (needs the baseline's --images)
+    #[arg(long, value_name = "DIR", requires = "baseline")]
+    dump_diffs: Option<PathBuf>,
+
+    /// ROMs to run at once (default: one per CPU)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+}
+
+/// What a comparison run records
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Frames each ROM ran for
+    pub frames: u64,
+    /// One entry per ROM, sorted by name
+    pub roms: Vec<ManifestEntry>,
+}
+
+/// One ROM's result
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// File name of the ROM
+    pub rom: String,
+    /// CRC32 of the last frame's palette indices, in hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Saved last frame, relative to the manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame: Option<String>,
+    /// Why the ROM didn't finish, if it didn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ManifestEntry {
+    /// Whether two runs of this ROM ended the same way
+    fn same_result(&self, other: &ManifestEntry) -> bool {
+        self.hash == other.hash && self.error == other.error
+    }
+}
+
+/// How a ROM's result differs from the baseline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Unchanged,
+    Changed,
+    /// Not in the baseline
+    New,
+    /// In the baseline but not in this run
+    Removed,
+}
+
+impl Change {
+    fn label(self) -> &'static str {
+        match self {
+            Change::Unchanged => "unchanged",
+            Change::Changed => "CHANGED",
+            Change::New => "new",
+            Change::Removed => "removed",
+        }
+    }
+}
+
+/// One ROM's result, with the frame kept for diffing
+struct RomResult {
+    entry: ManifestEntry,
+    framebuffer: Option<Vec<u8>>,
+}
+
+/// `.nes` files in `dir`, sorted by name
+pub fn find_roms(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut roms = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        let is_rom = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("nes"));
+        if is_rom && path.is_file() {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    Ok(roms)
+}
+
+/// Run one ROM and hash its last frame
+fn run_rom(path: &Path, frames: u64) -> RomResult {
+    let rom = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let result = NesSystem::new_quiet(path).and_then(|mut system| {
+        system.run_frames(frames, true)?;
+        Ok(system.framebuffer().to_vec())
+    });
+    match result {
+        Ok(framebuffer) => RomResult {
+            entry: ManifestEntry {
+                rom,
+                hash: Some(format!("{:08x}", crc32(&framebuffer))),
+                frame: None,
+                error: None,
+            },
+            framebuffer: Some(framebuffer),
+        },
+        Err(err) => RomResult {
+            entry: ManifestEntry { rom, hash: None, frame: None, error: Some(err.to_string()) },
+            framebuffer: None,
+        },
+    }
+}
+
+/// Default worker threads: one per CPU
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Run `run` on every ROM on `jobs` worker threads, returning results in
+/// ROM order
+pub fn run_all<T: Send>(roms: &[PathBuf], jobs: usize, run: impl Fn(&Path) -> T + Sync) -> Vec<T> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(roms.len()));
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, roms.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = roms.get(index) else {
+                    break;
+                };
+                let result = run(path);
+                results.lock().unwrap().push((index, result));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Compare `current` with `baseline`, ROM by ROM in name order
+pub fn compare(baseline: &Manifest, current: &Manifest) -> Vec<(String, Change)> {
+    let mut changes = BTreeMap::new();
+    for entry in &current.roms {
+        let change = match baseline.roms.iter().find(|old| old.rom == entry.rom) {
+            Some(old) if old.same_result(entry) => Change::Unchanged,
+            Some(_) => Change::Changed,
+            None => Change::New,
+        };
+        changes.insert(entry.rom.clone(), change);
+    }
+    for old in &baseline.roms {
+        changes.entry(old.rom.clone()).or_insert(Change::Removed);
+    }
+    changes.into_iter().collect()
+}
+
+/// Table of every ROM's change, changed ones first, then a count of each kind
+pub fn format_summary(changes: &[(String, Change)]) -> String {
+    let order = [Change::Changed, Change::New, Change::Removed, Change::Unchanged];
+    let width = changes.iter().map(|(rom, _)| rom.len()).max().unwrap_or(0);
+    let mut text = String::new();
+    for kind in order {
+        for (rom, _) in changes.iter().filter(|(_, change)| *change == kind) {
+            text += &format!("{:<width$}  {}\n", rom, kind.label(), width = width);
+        }
+    }
+    let counts: Vec<String> = order
+        .iter()
+        .map(|&kind| {
+            let count = changes.iter().filter(|(_, change)| *change == kind).count();
+            format!("{} {}", count, kind.label().to_lowercase())
+        })
+        .collect();
+    text += &counts.join(", ");
+    text.push('\n');
+    text
+}
+
+/// File name a ROM's frame or diff image is saved under
+fn image_name(rom: &str, extension: &str) -> String {
+    format!("{}.{}", rom, extension)
+}
+
+/// Read a manifest written by an earlier run
+fn load_manifest(path: &Path) -> Result<Manifest> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("Bad manifest {}", path.display()))
+}
+
+/// Write diff images for the changed ROMs that have frames on both sides
+fn dump_diffs(
+    dir: &Path,
+    baseline_path: &Path,
+    baseline: &Manifest,
+    results: &[RomResult],
+    changes: &[(String, Change)],
+) -> Result<()> {
+    let baseline_dir = baseline_path.parent().unwrap_or(Path::new(""));
+    for (rom, _) in changes.iter().filter(|(_, change)| *change == Change::Changed) {
+        let old = baseline.roms.iter().find(|old| &old.rom == rom);
+        let new = results.iter().find(|result| &result.entry.rom == rom);
+        let (Some(frame), Some(actual)) = (
+            old.and_then(|old| old.frame.as_ref()),
+            new.and_then(|new| new.framebuffer.as_ref()),
+        ) else {
+            eprintln!("{}: no frame on both sides, skipping the diff image", rom);
+            continue;
+        };
+        let expected = load_reference_frame(&baseline_dir.join(frame))?;
+        let path = dir.join(image_name(rom, "ppm"));
+        save_diff_image(&expected, actual, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Run the comparison; returns whether any ROM's result changed
+pub fn run(args: &CompareArgs) -> Result<bool> {
+    let roms = find_roms(&args.dir)?;
+    let jobs = args.jobs.unwrap_or_else(default_jobs);
+    let mut results = run_all(&roms, jobs, |path| run_rom(path, args.frames));
+
+    if let Some(images) = &args.images {
+        let manifest_dir = args.output.parent().unwrap_or(Path::new(""));
+        for result in &mut results {
+            let Some(framebuffer) = &result.framebuffer else {
+                continue;
+            };
+            let name = image_name(&result.entry.rom, "pgm");
+            let path = images.join(&name);
+            save_reference_frame(&path, framebuffer)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            // Relative to the manifest when it can be, so the pair can move together
+            let recorded = path.strip_prefix(manifest_dir).unwrap_or(&path);
+            result.entry.frame = Some(recorded.to_string_lossy().into_owned());
+        }
+    }
+
+    let manifest = Manifest {
+        frames: args.frames,
+        roms: results.iter().map(|result| result.entry.clone()).collect(),
+    };
+    let json = serde_json::to_string_pretty(&manifest)?;
+    fs::write(&args.output, json + "\n")
+        .with_context(|| format!("Failed to write {}", args.output.display()))?;
+
+    let Some(baseline_path) = &args.baseline else {
+        println!("Ran {} ROMs for {} frames", manifest.roms.len(), args.frames);
+        return Ok(false);
+    };
+    let baseline = load_manifest(baseline_path)?;
+    if baseline.frames != manifest.frames {
+        eprintln!(
+            "Warning: baseline ran {} frames, this run {}",
+            baseline.frames, manifest.frames
+        );
+    }
+    let changes = compare(&baseline, &manifest);
+    print!("{}", format_summary(&changes));
+    if let Some(dir) = &args.dump_diffs {
+        dump_diffs(dir, baseline_path, &baseline, &results, &changes)?;
+    }
+    Ok(changes.iter().any(|(_, change)| *change == Change::Changed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    fn entry(rom: &str, hash: Option<&str>, error: Option<&str>) -> ManifestEntry {
+        ManifestEntry {
+            rom: rom.to_string(),
+            hash: hash.map(str::to_string),
+            frame: None,
+            error: error.map(str::to_string),
+        }
+    }
+
+    fn manifest(roms: Vec<ManifestEntry>) -> Manifest {
+        Manifest { frames: 60, roms }
+    }
+
+    /// NROM image that fills the screen with backdrop colour `colour`
+    fn backdrop_rom(colour: u8) -> Vec<u8> {
+        #[rustfmt::skip]
+        let program = [
+            0xA9, 0x3F, 0x8D, 0x06, 0x20, // LDA #$3F, STA $2006
+            0xA9, 0x00, 0x8D, 0x06, 0x20, // LDA #$00, STA $2006
+            0xA9, colour, 0x8D, 0x07, 0x20, // LDA #colour, STA $2007
+            0xA9, 0x00, 0x8D, 0x06, 0x20, // reset the VRAM address
+            0x8D, 0x06, 0x20,
+            0xA9, 0x08, 0x8D, 0x01, 0x20, // LDA #$08, STA $2001 (background on)
+            0x4C, 0x1C, 0x80,             // JMP $801C
+        ];
+        let mut prg = vec![0xEA; 0x4000];
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        rom.extend_from_slice(&prg);
+        rom.extend_from_slice(&[0; 0x2000]);
+        rom
+    }
+
+    #[test]
+    fn test_manifest_json_schema() {
+        let mut with_frame = entry("a.nes", Some("0badf00d"), None);
+        with_frame.frame = Some("frames/a.nes.pgm".into());
+        let manifest = manifest(vec![with_frame, entry("b.nes", None, Some("jammed"))]);
+
+        let json: serde_json::Value = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "frames": 60,
+                "roms": [
+                    { "rom": "a.nes", "hash": "0badf00d", "frame": "frames/a.nes.pgm" },
+                    { "rom": "b.nes", "error": "jammed" },
+                ],
+            })
+        );
+        assert_eq!(serde_json::from_value::<Manifest>(json).unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_compare_classifies_every_rom() {
+        let baseline = manifest(vec![
+            entry("same.nes", Some("11111111"), None),
+            entry("drawn.nes", Some("22222222"), None),
+            entry("jams.nes", Some("33333333"), None),
+            entry("gone.nes", Some("44444444"), None),
+        ]);
+        let mut current = manifest(vec![
+            entry("added.nes", Some("55555555"), None),
+            entry("drawn.nes", Some("2222222f"), None),
+            entry("jams.nes", None, Some("invalid opcode")),
+            entry("same.nes", Some("11111111"), None),
+        ]);
+        // Where the frame was saved doesn't matter
+        current.roms[3].frame = Some("same.nes.pgm".into());
+
+        assert_eq!(
+            compare(&baseline, &current),
+            vec![
+                ("added.nes".to_string(), Change::New),
+                ("drawn.nes".to_string(), Change::Changed),
+                ("gone.nes".to_string(), Change::Removed),
+                ("jams.nes".to_string(), Change::Changed),
+                ("same.nes".to_string(), Change::Unchanged),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_summary_lists_changes_first() {
+        let changes = vec![
+            ("a.nes".to_string(), Change::Unchanged),
+            ("bb.nes".to_string(), Change::Changed),
+            ("c.nes".to_string(), Change::New),
+        ];
+        assert_eq!(
+            format_summary(&changes),
+            "bb.nes  CHANGED\n\
+             c.nes   new\n\
+             a.nes   unchanged\n\
+             1 changed, 1 new, 0 removed, 1 unchanged\n"
+        );
+    }
+
+    #[test]
+    fn test_compare_end_to_end() {
+        let dir = scratch_dir("compare");
+        let roms = dir.join("roms");
+        fs::create_dir_all(&roms).unwrap();
+        fs::write(roms.join("blue.nes"), backdrop_rom(0x12)).unwrap();
+        fs::write(roms.join("red.nes"), backdrop_rom(0x16)).unwrap();
+        let args = |output: &str, baseline: Option<&str>| CompareArgs {
+            dir: roms.clone(),
+            frames: 5,
+            output: dir.join(output),
+            images: Some(dir.join(format!("{}-frames", output))),
+            baseline: baseline.map(|name| dir.join(name)),
+            dump_diffs: baseline.map(|_| dir.join("diffs")),
+            jobs: Some(2),
+        };
+
+        assert!(!run(&args("before.json", None)).unwrap());
+        let before = load_manifest(&dir.join("before.json")).unwrap();
+        assert_eq!(before.roms.len(), 2);
+        assert_eq!(before.roms[0].frame.as_deref(), Some("before.json-frames/blue.nes.pgm"));
+        assert_ne!(before.roms[0].hash, before.roms[1].hash);
+
+        // Same ROMs, same result
+        assert!(!run(&args("same.json", Some("before.json"))).unwrap());
+
+        // A palette tweak in one ROM is reported, with a diff image
+        fs::write(roms.join("red.nes"), backdrop_rom(0x06)).unwrap();
+        assert!(run(&args("after.json", Some("before.json"))).unwrap());
+        let after = load_manifest(&dir.join("after.json")).unwrap();
+        let changes = compare(&before, &after);
+        assert_eq!(changes[0], ("blue.nes".to_string(), Change::Unchanged));
+        assert_eq!(changes[1], ("red.nes".to_string(), Change::Changed));
+        assert!(dir.join("diffs/red.nes.ppm").is_file());
+        assert!(!dir.join("diffs/blue.nes.ppm").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,339 @@
+//! `nes-run corpus`: boot every ROM in a directory and say how far it got
+//!
+//! Each ROM runs for a number of frames in its own panic-catching wrapper,
+//! so one bad ROM (or emulator bug) can't take the others down. The result
+//! is a [`Classification`]: it didn't load, needs a mapper we don't have,
+//! jammed the CPU, panicked the emulator, or ran. For ROMs that ran, a few
+//! cheap signs of life say whether it's worth a look: whether the last
+//! frame shows anything but black, how many colours it has and how much
+//! code executed. The CSV has one row per ROM, for diffing between versions.
+
+use anyhow::{Context, Result};
+use clap::Args;
+use emu_core::EmulatorError;
+use emu_nes::palette::palette_to_rgb;
+use emu_nes::{NesSystem, PcCoverage};
+use std::any::Any;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use crate::compare::{default_jobs, find_roms, run_all};
+
+/// Boot every ROM in a directory and classify how each one ran
+#[derive(Args, Debug)]
+pub struct CorpusArgs {
+    /// Directory of .nes files
+    #[arg(value_name = "DIR")]
+    dir: PathBuf,
+
+    /// Frames to run each ROM for
+    #[arg(short, long, default_value_t = 600)]
+    frames: u64,
+
+    /// Where to write one line per ROM
+    #[arg(long, value_name = "CSV", default_value = "corpus.csv")]
+    csv: PathBuf,
+
+    /// ROMs to run at once (default: one per CPU)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+}
+
+/// How a ROM's run ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Classification {
+    /// The file couldn't be read or isn't a ROM we understand
+    LoadFailed(String),
+    /// The header asks for a mapper that isn't implemented
+    UnsupportedMapper(u8),
+    /// The CPU stopped at an instruction it can't execute
+    CpuJam(u16),
+    /// The emulator panicked
+    Panicked(String),
+    /// Every frame ran
+    RanOk {
+        /// The last frame shows something other than black
+        nonblack_frame: bool,
+        /// Colours in the last frame
+        distinct_colors: usize,
+        /// Addresses an instruction was executed from
+        executed_unique_pcs: usize,
+    },
+}
+
+impl Classification {
+    /// Summary order: the ones that need looking at first
+    fn rank(&self) -> u8 {
+        match self {
+            Classification::Panicked(_) => 0,
+            Classification::CpuJam(_) => 1,
+            Classification::UnsupportedMapper(_) => 2,
+            Classification::LoadFailed(_) => 3,
+            Classification::RanOk { .. } => 4,
+        }
+    }
+
+    /// Short name of the variant
+    pub fn label(&self) -> &'static str {
+        match self {
+            Classification::LoadFailed(_) => "load-failed",
+            Classification::UnsupportedMapper(_) => "unsupported-mapper",
+            Classification::CpuJam(_) => "cpu-jam",
+            Classification::Panicked(_) => "panicked",
+            Classification::RanOk { .. } => "ran-ok",
+        }
+    }
+
+    /// What went wrong, or the signs of life
+    pub fn detail(&self) -> String {
+        match self {
+            Classification::LoadFailed(reason) | Classification::Panicked(reason) => reason.clone(),
+            Classification::UnsupportedMapper(mapper) => format!("mapper {}", mapper),
+            Classification::CpuJam(pc) => format!("PC=${:04X}", pc),
+            Classification::RanOk { nonblack_frame, distinct_colors, executed_unique_pcs } => format!(
+                "{}, {} colours, {} PCs",
+                if *nonblack_frame { "drew something" } else { "all black" },
+                distinct_colors,
+                executed_unique_pcs
+            ),
+        }
+    }
+}
+
+/// (drew anything but black, distinct colours) of a frame of palette indices
+pub fn frame_colours(framebuffer: &[u8]) -> (bool, usize) {
+    let mut seen = [false; 64];
+    for &index in framebuffer {
+        seen[(index & 0x3F) as usize] = true;
+    }
+    let colours: Vec<(u8, u8, u8)> = (0..64u8).filter(|&i| seen[i as usize]).map(palette_to_rgb).collect();
+    let nonblack = colours.iter().any(|&rgb| rgb != (0, 0, 0));
+    let mut distinct = colours;
+    distinct.sort_unstable();
+    distinct.dedup();
+    (nonblack, distinct.len())
+}
+
+/// Load and run the ROM at `path`, without catching panics
+fn run_rom(path: &Path, frames: u64) -> Classification {
+    let mut system = match NesSystem::new_quiet(path) {
+        Ok(system) => system,
+        Err(EmulatorError::UnsupportedMapper(mapper)) => return Classification::UnsupportedMapper(mapper),
+        Err(err) => return Classification::LoadFailed(err.to_string()),
+    };
+    let pcs = PcCoverage::new();
+    system.set_instruction_hook(Some(pcs.hook()));
+    for _ in 0..frames {
+        if system.run_frame().is_err() {
+            return Classification::CpuJam(system.cpu().pc);
+        }
+    }
+    let (nonblack_frame, distinct_colors) = frame_colours(system.framebuffer());
+    Classification::RanOk { nonblack_frame, distinct_colors, executed_unique_pcs: pcs.len() }
+}
+
+/// The message a panic was raised with
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// Run `run`, turning a panic into [`Classification::Panicked`]
+///
+/// Everything the run touches has to be created inside `run`, so nothing
+/// half-updated by the panic is used afterwards.
+pub fn isolate(run: impl FnOnce() -> Classification) -> Classification {
+    panic::catch_unwind(AssertUnwindSafe(run))
+        .unwrap_or_else(|payload| Classification::Panicked(panic_message(payload.as_ref())))
+}
+
+/// Classify the ROM at `path` after `frames` frames
+pub fn classify(path: &Path, frames: u64) -> Classification {
+    isolate(|| run_rom(path, frames))
+}
+
+/// File name of `path`, for the table and the CSV
+fn rom_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Table of every ROM, failures first, then a count of each kind
+pub fn format_summary(results: &[(String, Classification)]) -> String {
+    let mut sorted: Vec<&(String, Classification)> = results.iter().collect();
+    sorted.sort_by(|(a_rom, a), (b_rom, b)| a.rank().cmp(&b.rank()).then(a_rom.cmp(b_rom)));
+    let width = results.iter().map(|(rom, _)| rom.len()).max().unwrap_or(0);
+    let mut text = String::new();
+    for (rom, classification) in &sorted {
+        text += &format!("{:<width$}  {:<18}  {}\n", rom, classification.label(), classification.detail(), width = width);
+    }
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for (_, classification) in &sorted {
+        match counts.last_mut() {
+            Some((label, count)) if *label == classification.label() => *count += 1,
+            _ => counts.push((classification.label(), 1)),
+        }
+    }
+    let counts: Vec<String> = counts.iter().map(|(label, count)| format!("{} {}", count, label)).collect();
+    text += &counts.join(", ");
+    text.push('\n');
+    text
+}
+
+/// Quote a CSV field if it needs it
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// CSV with a header and one row per ROM, in the order given
+pub fn format_csv(results: &[(String, Classification)]) -> String {
+    let mut csv = String::from("rom,classification,detail,nonblack_frame,distinct_colors,executed_unique_pcs\n");
+    for (rom, classification) in results {
+        let (detail, stats) = match classification {
+            Classification::RanOk { nonblack_frame, distinct_colors, executed_unique_pcs } => {
+                (String::new(), format!("{},{},{}", nonblack_frame, distinct_colors, executed_unique_pcs))
+            }
+            _ => (classification.detail(), ",,".to_string()),
+        };
+        csv += &format!("{},{},{},{}\n", csv_field(rom), classification.label(), csv_field(&detail), stats);
+    }
+    csv
+}
+
+/// Run the corpus and write the CSV
+pub fn run(args: &CorpusArgs) -> Result<()> {
+    let roms = find_roms(&args.dir)?;
+    let jobs = args.jobs.unwrap_or_else(default_jobs);
+    let results: Vec<(String, Classification)> =
+        run_all(&roms, jobs, |path| (rom_name(path), classify(path, args.frames)));
+    print!("{}", format_summary(&results));
+    fs::write(&args.csv, format_csv(&results))
+        .with_context(|| format!("Failed to write {}", args.csv.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation_rom as animation;
+    use crate::test_support::scratch_dir;
+
+    /// NROM image running `program` from $8000
+    fn nrom(program: &[u8], mapper: u8) -> Vec<u8> {
+        let mut prg = vec![0xEA; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, mapper << 4, mapper & 0xF0, 0, 0, 0, 0, 0, 0, 0, 0];
+        rom.extend_from_slice(&prg);
+        rom.extend_from_slice(&[0; 0x2000]);
+        rom
+    }
+
+    #[test]
+    fn test_every_classification() {
+        let dir = scratch_dir("corpus");
+        fs::write(dir.join("animation.nes"), animation::build_rom()).unwrap();
+        fs::write(dir.join("truncated.nes"), &animation::build_rom()[..100]).unwrap();
+        fs::write(dir.join("not-a-rom.nes"), b"hello").unwrap();
+        fs::write(dir.join("mapper200.nes"), nrom(&[], 200)).unwrap();
+        // LDA #1; JAM
+        fs::write(dir.join("jam.nes"), nrom(&[0xA9, 0x01, 0x02], 0)).unwrap();
+        // Black backdrop, rendering off, then spin
+        #[rustfmt::skip]
+        let idle = [
+            0xA9, 0x3F, 0x8D, 0x06, 0x20, // LDA #$3F, STA $2006
+            0xA9, 0x00, 0x8D, 0x06, 0x20, // LDA #$00, STA $2006
+            0xA9, 0x0F, 0x8D, 0x07, 0x20, // LDA #$0F, STA $2007
+            0xA9, 0x00, 0x8D, 0x06, 0x20, // reset the VRAM address
+            0x8D, 0x06, 0x20,
+            0x4C, 0x17, 0x80,             // JMP $8017
+        ];
+        fs::write(dir.join("idle.nes"), nrom(&idle, 0)).unwrap();
+
+        let classify = |name: &str| classify(&dir.join(name), 30);
+        match classify("animation.nes") {
+            Classification::RanOk { nonblack_frame, distinct_colors, executed_unique_pcs } => {
+                assert!(nonblack_frame);
+                assert!(distinct_colors > 1, "{} colours", distinct_colors);
+                assert!(executed_unique_pcs > 10, "{} PCs", executed_unique_pcs);
+            }
+            other => panic!("animation ROM: {:?}", other),
+        }
+        assert_eq!(
+            classify("idle.nes"),
+            Classification::RanOk { nonblack_frame: false, distinct_colors: 1, executed_unique_pcs: 10 }
+        );
+        assert!(matches!(classify("truncated.nes"), Classification::LoadFailed(_)));
+        assert!(matches!(classify("not-a-rom.nes"), Classification::LoadFailed(_)));
+        assert!(matches!(classify("missing.nes"), Classification::LoadFailed(_)));
+        assert_eq!(classify("mapper200.nes"), Classification::UnsupportedMapper(200));
+        assert_eq!(classify("jam.nes"), Classification::CpuJam(0x8003));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_panics_are_isolated() {
+        assert_eq!(
+            isolate(|| panic!("mapper bug")),
+            Classification::Panicked("mapper bug".to_string())
+        );
+        assert_eq!(
+            isolate(|| panic!("bank {}", 7)),
+            Classification::Panicked("bank 7".to_string())
+        );
+        assert_eq!(isolate(|| Classification::CpuJam(0x8000)), Classification::CpuJam(0x8000));
+    }
+
+    #[test]
+    fn test_frame_colours() {
+        assert_eq!(frame_colours(&[0x0F; 16]), (false, 1));
+        // $0D and $1D are both black
+        assert_eq!(frame_colours(&[0x0F, 0x0D, 0x1D]), (false, 1));
+        assert_eq!(frame_colours(&[0x0F, 0x16, 0x56]), (true, 2));
+    }
+
+    fn results() -> Vec<(String, Classification)> {
+        vec![
+            ("good.nes".to_string(), Classification::RanOk { nonblack_frame: true, distinct_colors: 4, executed_unique_pcs: 120 }),
+            ("bad, \"quoted\".nes".to_string(), Classification::LoadFailed("ROM too short".to_string())),
+            ("jam.nes".to_string(), Classification::CpuJam(0x8003)),
+            ("mmc5.nes".to_string(), Classification::UnsupportedMapper(5)),
+            ("boom.nes".to_string(), Classification::Panicked("index out of bounds".to_string())),
+        ]
+    }
+
+    #[test]
+    fn test_summary_lists_failures_first() {
+        assert_eq!(
+            format_summary(&results()),
+            "boom.nes           panicked            index out of bounds\n\
+             jam.nes            cpu-jam             PC=$8003\n\
+             mmc5.nes           unsupported-mapper  mapper 5\n\
+             bad, \"quoted\".nes  load-failed         ROM too short\n\
+             good.nes           ran-ok              drew something, 4 colours, 120 PCs\n\
+             1 panicked, 1 cpu-jam, 1 unsupported-mapper, 1 load-failed, 1 ran-ok\n"
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        assert_eq!(
+            format_csv(&results()),
+            "rom,classification,detail,nonblack_frame,distinct_colors,executed_unique_pcs\n\
+             good.nes,ran-ok,,true,4,120\n\
+             \"bad, \"\"quoted\"\".nes\",load-failed,ROM too short,,,\n\
+             jam.nes,cpu-jam,PC=$8003,,,\n\
+             mmc5.nes,unsupported-mapper,mapper 5,,,\n\
+             boom.nes,panicked,index out of bounds,,,\n"
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Headless NES runner for CI, scripts and bug reports
+
+mod artifacts;
+mod compare;
+mod corpus;
+
+/// A generated ROM that draws, for the corpus tests
+#[cfg(test)]
+#[path = "../../emu-nes/examples/generate_animation_test.rs"]
+#[allow(dead_code)]
+mod animation_rom;
+
+/// Helpers for the subcommands' tests
+#[cfg(test)]
+mod test_support {
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Fresh scratch directory under the system temp dir
+    pub fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nes-run-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+}
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use emu_nes::{InputSchedule, NesSystem, ScriptMode, SystemEvent};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use artifacts::RegionFile;
+
+/// Exit code when the CPU jams (clap already uses 2 for usage errors)
+const EXIT_JAM: u8 = 3;
+
+/// Exit code when `compare` finds a ROM whose result changed
+const EXIT_CHANGED: u8 = 4;
+
+/// Run a NES ROM without a window
+#[derive(Parser, Debug)]
+#[command(name = "nes-run")]
+#[command(about = "Run a NES ROM headlessly and save what it did", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(after_help = "Exit codes: 0 finished, 1 load or I/O failure, 3 CPU jam, 4 compare found changes")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run every ROM in a directory and compare the last frames with an earlier run
+    Compare(compare::CompareArgs),
+    /// Boot every ROM in a directory and classify how far each one got
+    Corpus(corpus::CorpusArgs),
+}
+
+/// Options for running a single ROM
+#[derive(clap::Args, Debug)]
+struct Args {
+    /// ROM to run
+    #[arg(value_name = "ROM", required = true)]
+    rom: Option<PathBuf>,
+
+    /// IPS or BPS patch to apply to the ROM (default: a .ips or .bps next
+    /// to it with the same name, if there is one)
+    #[arg(long, value_name = "FILE")]
+    patch: Option<PathBuf>,
+
+    /// Frames to run
+    #[arg(short, long, default_value_t = 600)]
+    frames: u64,
+
+    /// Save the last frame as a PPM image
+    #[arg(long, value_name = "PPM")]
+    screenshot: Option<PathBuf>,
+
+    /// Controller 1 input script, e.g. "wait 60; press START; hold RIGHT { press A 20 }"
+    /// (repeatable; scripts run side by side from the first frame)
+    #[arg(long, value_name = "SCRIPT", value_parser = InputSchedule::parse)]
+    input: Vec<InputSchedule>,
+
+    /// File holding an input script, # for comments
+    #[arg(long, value_name = "FILE")]
+    input_script: Option<PathBuf>,
+
+    /// Save the 2KB of internal RAM at the end of the run
+    #[arg(long, value_name = "FILE")]
+    dump_ram: Option<PathBuf>,
+
+    /// Save a memory region at the end of the run, e.g. "oam=oam.bin" (repeatable)
+    #[arg(long, value_name = "REGION=FILE", value_parser = RegionFile::parse)]
+    dump_region: Vec<RegionFile>,
+
+    /// Load a memory region before the first frame, e.g. "cpu-ram=ram.bin" (repeatable)
+    #[arg(long, value_name = "REGION=FILE", value_parser = RegionFile::parse)]
+    load_region: Vec<RegionFile>,
+
+    /// Battery save to load before the first frame (if it exists) and write
+    /// back at the end
+    #[arg(long, value_name = "FILE")]
+    battery: Option<PathBuf>,
+
+    /// Print which PRG and CHR banks are mapped at the end of the run
+    #[arg(long)]
+    dump_banks: bool,
+
+    /// Log every instruction (PC, opcode, registers, PPU position, cycles)
+    #[arg(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+
+    /// Log NMIs, IRQs, PPU register writes, OAM DMA, $4015 writes and bank
+    /// switches with their scanline and dot, one JSON object per line
+    #[arg(long, value_name = "FILE")]
+    events: Option<PathBuf>,
+
+    /// Also treat a tight loop that never waits for vblank as a jam
+    #[arg(long)]
+    exit_on_jam: bool,
+
+    /// Print how long the last frames took, split by CPU, PPU, APU and observers
+    #[arg(long)]
+    profile: bool,
+
+    /// Check for code that works in emulators but not on a console
+    /// (PPU writes during warm-up, VRAM writes while rendering, ...) and
+    /// print what was found
+    #[arg(long)]
+    lint: bool,
+}
+
+/// How the run ended
+enum Outcome {
+    /// All requested frames ran
+    Finished,
+    /// The CPU jammed (or looked stuck, with --exit-on-jam)
+    Jammed,
+}
+
+/// Build the input schedule from the script file and --input flags
+fn load_schedule(args: &Args) -> Result<InputSchedule> {
+    let mut schedule = InputSchedule::default();
+    if let Some(path) = &args.input_script {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read input script {}", path.display()))?;
+        let script = InputSchedule::parse(&text)
+            .with_context(|| format!("Bad input script {}", path.display()))?;
+        schedule.merge(&script);
+    }
+    for input in &args.input {
+        schedule.merge(input);
+    }
+    Ok(schedule)
+}
+
+/// Create `path` for writing, if given
+fn create_output(path: Option<&PathBuf>) -> Result<Option<BufWriter<File>>> {
+    path.map(|path| {
+        File::create(path)
+            .map(BufWriter::new)
+            .with_context(|| format!("Failed to create {}", path.display()))
+    })
+    .transpose()
+}
+
+/// Run the frames, writing the trace and event log as they go
+fn run_frames(args: &Args, system: &mut NesSystem) -> Result<Outcome> {
+    let mut trace = create_output(args.trace.as_ref())?;
+    let mut events = create_output(args.events.as_ref())?;
+    system.set_event_log_enabled(events.is_some());
+
+    for frame in 0..args.frames {
+        let result = match trace.as_mut() {
+            Some(out) => {
+                let mut written = Ok(());
+                let result = system.run_frame_with(|system| {
+                    if written.is_ok() {
+                        written = writeln!(out, "{}", artifacts::trace_line(system));
+                    }
+                });
+                written.context("Failed to write trace")?;
+                result
+            }
+            None => system.run_frame(),
+        };
+        if let Some(out) = events.as_mut() {
+            for event in system.take_frame_events() {
+                writeln!(out, "{}", artifacts::event_json(&event)).context("Failed to write events")?;
+            }
+        }
+        if let Err(err) = result {
+            eprintln!("CPU jammed in frame {}: {} (PC=${:04X})", frame, err, system.cpu().pc);
+            return Ok(Outcome::Jammed);
+        }
+
+        for event in system.poll_events() {
+            match event {
+                SystemEvent::PossibleHang { pc, loop_len } => {
+                    eprintln!(
+                        "Frame {}: possible hang in a {}-instruction loop at ${:04X}",
+                        frame, loop_len, pc
+                    );
+                    if args.exit_on_jam {
+                        return Ok(Outcome::Jammed);
+                    }
+                }
+                // No breakpoints or accuracy flags are set here
+                SystemEvent::BreakpointHit { .. } | SystemEvent::WarmupWritesIgnored { .. } => {}
+            }
+        }
+    }
+
+    if let Some(mut out) = trace {
+        out.flush().context("Failed to write trace")?;
+    }
+    if let Some(mut out) = events {
+        out.flush().context("Failed to write events")?;
+    }
+    Ok(Outcome::Finished)
+}
+
+fn run(args: &Args) -> Result<Outcome> {
+    let rom = args.rom.as_ref().context("No ROM given")?;
+    let schedule = load_schedule(args)?;
+    let mut system = NesSystem::new_quiet_with_patch(rom, args.patch.as_deref())
+        .with_context(|| format!("Failed to load {}", rom.display()))?;
+    // Pixels are only needed for a screenshot
+    system.set_render_enabled(args.screenshot.is_some());
+    system.enable_profiling(args.profile);
+    system.set_lint_enabled(args.lint);
+    for load in &args.load_region {
+        system
+            .import_region_from(load.region, &load.path)
+            .with_context(|| format!("Failed to load {} from {}", load.region, load.path.display()))?;
+    }
+    if let Some(path) = args.battery.as_ref().filter(|path| path.exists()) {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        system
+            .load_battery_save(&data)
+            .with_context(|| format!("Failed to load {}", path.display()))?;
+    }
+
+    system.attach_input_script(schedule, ScriptMode::Override);
+    let outcome = run_frames(args, &mut system)?;
+    println!(
+        "Ran {} frames ({} CPU cycles)",
+        system.frame(),
+        system.cpu().cycles
+    );
+    if args.profile {
+        print!("{}", system.profile_report());
+    }
+    if args.lint {
+        print!("{}", system.lint_report());
+    }
+
+    if args.dump_banks {
+        if let Some(state) = system.bank_state() {
+            print!("{}", artifacts::format_banks(&state));
+        }
+    }
+
+    if let Some(path) = &args.screenshot {
+        let mut out = BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+        );
+        artifacts::write_ppm(&mut out, system.framebuffer())
+            .and_then(|_| out.flush())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    if let Some(path) = &args.dump_ram {
+        fs::write(path, system.cpu_mut().memory().ram())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    for dump in &args.dump_region {
+        system
+            .export_region_to(dump.region, &dump.path)
+            .with_context(|| format!("Failed to write {}", dump.path.display()))?;
+    }
+    if let (Some(path), Some(data)) = (&args.battery, system.battery_save()) {
+        fs::write(path, data).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+
+    Ok(outcome)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match &cli.command {
+        Some(Command::Compare(args)) => compare::run(args).map(|changed| {
+            if changed {
+                ExitCode::from(EXIT_CHANGED)
+            } else {
+                ExitCode::SUCCESS
+            }
+        }),
+        Some(Command::Corpus(args)) => corpus::run(args).map(|_| ExitCode::SUCCESS),
+        None => run(&cli.run).map(|outcome| match outcome {
+            Outcome::Finished => ExitCode::SUCCESS,
+            Outcome::Jammed => ExitCode::from(EXIT_JAM),
+        }),
+    };
+    result.unwrap_or_else(|err| {
+        eprintln!("Error: {:#}", err);
+        ExitCode::FAILURE
+    })
+}
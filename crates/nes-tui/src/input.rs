@@ -0,0 +1,131 @@
+//! Keys to controller buttons and frontend commands
+//!
+//! Keys are named as in [`input_handler::keyboard`], so the terminal plays
+//! with the same bindings as the window: WASD or the arrows, Z and X for A
+//! and B, Enter for Start and Space for Select.
+//!
+//! Terminals report presses but not releases. A press holds its button
+//! for [`HOLD_FRAMES`] frames, and the terminal's key repeat renews it
+//! while the key stays down, so a held key plays as held once repeat
+//! starts.
+
+use input_handler::keyboard;
+
+/// Frames a button stays held after its key's last press or repeat
+pub const HOLD_FRAMES: u8 = 8;
+
+/// Something a key does other than press a button
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Stop or carry on running frames
+    Pause,
+    Reset,
+    /// Save the frame on screen as a PPM image
+    Screenshot,
+    Quit,
+}
+
+/// The command bound to `key`, if any
+pub fn command(key: &str) -> Option<Command> {
+    match key {
+        "p" | "P" => Some(Command::Pause),
+        "r" | "R" => Some(Command::Reset),
+        "F12" => Some(Command::Screenshot),
+        "q" | "Q" | "Esc" => Some(Command::Quit),
+        _ => None,
+    }
+}
+
+/// Buttons held from the terminal, each counting down to its release
+#[derive(Debug, Clone, Default)]
+pub struct HeldButtons {
+    /// Frames left, one per [`keyboard::KEY_BINDINGS`] entry
+    frames_left: [u8; 8],
+}
+
+impl HeldButtons {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hold the button bound to `key`; false if it isn't bound
+    pub fn press(&mut self, key: &str) -> bool {
+        let Some(bit) = keyboard::key_button(key) else {
+            return false;
+        };
+        self.frames_left[bit] = HOLD_FRAMES;
+        true
+    }
+
+    /// The buttons held for the next frame, one bit per binding (as
+    /// [`keyboard::set_buttons`] takes them), counting that frame off
+    pub fn next_frame(&mut self) -> u8 {
+        let mut buttons = 0;
+        for (bit, frames_left) in self.frames_left.iter_mut().enumerate() {
+            if *frames_left > 0 {
+                buttons |= 1 << bit;
+                *frames_left -= 1;
+            }
+        }
+        buttons
+    }
+
+    /// Let go of everything, e.g. on reset
+    pub fn release_all(&mut self) {
+        self.frames_left = [0; 8];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_map_through_the_shared_bindings() {
+        let mut held = HeldButtons::new();
+        assert!(held.press("w"));
+        assert!(held.press("\r"));
+        assert!(!held.press("p"));
+        let bits = held.next_frame();
+        assert_eq!(bits, 1 << keyboard::key_button("↑").unwrap() | 1 << keyboard::key_button("\n").unwrap());
+
+        // Arrows and letters land on the same button
+        let mut arrow = HeldButtons::new();
+        arrow.press("←");
+        let mut letter = HeldButtons::new();
+        letter.press("A");
+        assert_eq!(arrow.next_frame(), letter.next_frame());
+    }
+
+    #[test]
+    fn test_press_holds_until_it_runs_out() {
+        let mut held = HeldButtons::new();
+        held.press("z");
+        for _ in 0..HOLD_FRAMES - 1 {
+            assert_ne!(held.next_frame(), 0);
+        }
+
+        // Key repeat renews it before it lapses
+        held.press("z");
+        for _ in 0..HOLD_FRAMES {
+            assert_ne!(held.next_frame(), 0);
+        }
+        assert_eq!(held.next_frame(), 0);
+
+        held.press("x");
+        held.release_all();
+        assert_eq!(held.next_frame(), 0);
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(command("P"), Some(Command::Pause));
+        assert_eq!(command("r"), Some(Command::Reset));
+        assert_eq!(command("F12"), Some(Command::Screenshot));
+        assert_eq!(command("Esc"), Some(Command::Quit));
+        // Button keys aren't commands
+        for key in ["z", "w", "\r", " "] {
+            assert_eq!(command(key), None, "{:?}", key);
+        }
+    }
+}
@@ -0,0 +1,14 @@
+//! Terminal frontend for quick checks over SSH and in CI logs
+//!
+//! [`render`] turns frames into text with ANSI colours, and [`input`] turns
+//! the keys a terminal reports into buttons and commands. Both are plain
+//! functions over strings, so they're tested without a terminal. The
+//! `nes-tui` binary (behind the `terminal` feature) puts them together with
+//! crossterm:
+//!
+//! ```text
+//! cargo run --release -p nes-tui --features terminal -- game.nes
+//! ```
+
+pub mod input;
+pub mod render;
@@ -0,0 +1,150 @@
+//! Play a NES ROM in the terminal
+//!
+//! Raw mode and the alternate screen come from crossterm; everything drawn
+//! is built by [`nes_tui::render`]. There's no sound.
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, terminal};
+use emu_core::Emulator;
+use emu_nes::{framebuffer_to_rgb, NesSystem};
+use input_handler::keyboard;
+use nes_tui::input::{self, Command, HeldButtons};
+use nes_tui::render::{self, ColorMode};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Run a NES ROM in the terminal
+#[derive(Parser, Debug)]
+#[command(name = "nes-tui")]
+#[command(about = "Play a NES ROM as half-block characters in the terminal", long_about = None)]
+#[command(after_help = "Keys: WASD or arrows, Z = A, X = B, Enter = Start, Space = Select; P pause, R reset, F12 screenshot, Q or Esc quit")]
+struct Args {
+    /// ROM to run
+    rom: PathBuf,
+
+    /// IPS or BPS patch to apply to the ROM (default: a .ips or .bps next
+    /// to it with the same name, if there is one)
+    #[arg(long, value_name = "FILE")]
+    patch: Option<PathBuf>,
+
+    /// Use the 256-colour palette, for terminals without true colour
+    #[arg(long)]
+    colors_256: bool,
+}
+
+/// Name of a key as [`input_handler::keyboard`] knows it
+fn key_name(code: KeyCode) -> Option<String> {
+    let name = match code {
+        KeyCode::Char(c) => return Some(c.to_string()),
+        KeyCode::F(number) => return Some(format!("F{}", number)),
+        KeyCode::Up => "↑",
+        KeyCode::Down => "↓",
+        KeyCode::Left => "←",
+        KeyCode::Right => "→",
+        KeyCode::Enter => "\r",
+        KeyCode::Esc => "Esc",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+/// Save the frame on screen next to where nes-tui was started
+fn screenshot(system: &mut NesSystem) -> Result<PathBuf> {
+    let path = PathBuf::from(format!("nes-tui-{}.ppm", system.frame()));
+    let mut data = b"P6\n256 240\n255\n".to_vec();
+    data.extend(framebuffer_to_rgb(system.framebuffer()));
+    std::fs::write(&path, data).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Run frames and draw them until the player quits
+fn run(system: &mut NesSystem, mode: ColorMode) -> Result<()> {
+    let frame_time = Duration::from_secs_f64(1.0 / system.frame_rate());
+    let mut stdout = io::stdout();
+    let mut held = HeldButtons::new();
+    let mut out = String::new();
+    let mut paused = false;
+    let mut message = String::new();
+    let (mut fps, mut frames_counted, mut fps_timer) = (0.0, 0, Instant::now());
+
+    loop {
+        let frame_start = Instant::now();
+        while event::poll(Duration::ZERO)? {
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind == KeyEventKind::Release {
+                continue;
+            }
+            // Raw mode swallows Ctrl+C, so it quits like Q
+            if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+                return Ok(());
+            }
+            let Some(name) = key_name(key.code) else {
+                continue;
+            };
+            match input::command(&name) {
+                Some(Command::Quit) => return Ok(()),
+                Some(Command::Pause) => paused = !paused,
+                Some(Command::Reset) => {
+                    system.reset();
+                    held.release_all();
+                }
+                Some(Command::Screenshot) => {
+                    message = match screenshot(system) {
+                        Ok(path) => format!("Saved {}", path.display()),
+                        Err(e) => e.to_string(),
+                    };
+                }
+                None => {
+                    held.press(&name);
+                }
+            }
+        }
+
+        if !paused {
+            keyboard::set_buttons(system, 0, held.next_frame());
+            system.run_frame()?;
+            frames_counted += 1;
+        }
+        if fps_timer.elapsed() >= Duration::from_secs(1) {
+            fps = frames_counted as f32 / fps_timer.elapsed().as_secs_f32();
+            frames_counted = 0;
+            fps_timer = Instant::now();
+        }
+
+        out.clear();
+        render::render_frame(&mut out, &system.framebuffer_rgba(), system.screen_size(), mode);
+        render::status_line(&mut out, fps, system.frame(), paused);
+        if !message.is_empty() {
+            out.push_str("\r\n");
+            out.push_str(&message);
+            out.push_str("\x1b[K");
+        }
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()?;
+
+        if let Some(rest) = frame_time.checked_sub(frame_start.elapsed()) {
+            thread::sleep(rest);
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut system = NesSystem::new_quiet_with_patch(&args.rom, args.patch.as_deref())
+        .with_context(|| format!("Failed to load {}", args.rom.display()))?;
+    let mode = if args.colors_256 { ColorMode::Ansi256 } else { ColorMode::TrueColor };
+
+    terminal::enable_raw_mode()?;
+    execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+    let result = run(&mut system, mode);
+    // Give the terminal back even when the game failed
+    execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    result
+}
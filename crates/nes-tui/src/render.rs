@@ -0,0 +1,189 @@
+//! Frames as coloured half-block characters
+//!
+//! The frame is averaged down by [`SCALE`] each way, 256x240 to 128x120,
+//! and each character cell shows two of those pixels stacked: [`UPPER_HALF`]
+//! in the top one's colour over a background in the bottom one's. Terminal
+//! cells are about twice as tall as they're wide, so the picture keeps its
+//! shape in 128 columns and 60 rows.
+//!
+//! Colours go out as 24-bit SGR sequences, or quantized to the xterm
+//! 256-colour palette for terminals without true colour. A colour is only
+//! sent when it differs from the cell before, which keeps a mostly flat
+//! screen to a few kilobytes.
+
+use std::fmt::Write;
+
+/// How many frame pixels each way make one terminal pixel
+pub const SCALE: usize = 2;
+
+/// The character drawn in every cell
+pub const UPPER_HALF: char = '▀';
+
+/// Moves the cursor to the top-left corner, so a frame overwrites the last
+pub const HOME: &str = "\x1b[H";
+
+/// Resets colours
+pub const RESET: &str = "\x1b[0m";
+
+/// Levels of each channel in the 6x6x6 colour cube (palette entries 16-231)
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// How colours are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// 24-bit `38;2;r;g;b` sequences
+    #[default]
+    TrueColor,
+    /// The nearest of the xterm 256 colours
+    Ansi256,
+}
+
+/// Average an RGBA picture of `(width, height)` down by [`SCALE`] each way,
+/// returning its RGB pixels and their size
+///
+/// A leftover row or column past a multiple of [`SCALE`] is dropped.
+pub fn downscale(rgba: &[u8], (width, height): (usize, usize)) -> (Vec<[u8; 3]>, (usize, usize)) {
+    let size = (width / SCALE, height / SCALE);
+    let mut pixels = Vec::with_capacity(size.0 * size.1);
+    for y in 0..size.1 {
+        for x in 0..size.0 {
+            let mut sum = [0u32; 3];
+            for dy in 0..SCALE {
+                for dx in 0..SCALE {
+                    let offset = ((y * SCALE + dy) * width + x * SCALE + dx) * 4;
+                    for (total, &channel) in sum.iter_mut().zip(&rgba[offset..offset + 3]) {
+                        *total += u32::from(channel);
+                    }
+                }
+            }
+            let count = (SCALE * SCALE) as u32;
+            pixels.push(sum.map(|total| ((total + count / 2) / count) as u8));
+        }
+    }
+    (pixels, size)
+}
+
+/// The xterm palette index nearest to `rgb`, from the colour cube or the
+/// grey ramp (232-255)
+pub fn ansi256(rgb: [u8; 3]) -> u8 {
+    let level = |value: u8| match value {
+        0..=47 => 0,
+        48..=114 => 1,
+        _ => (value as usize - 35) / 40,
+    };
+    let cube = rgb.map(level);
+    let cube_rgb = cube.map(|index| CUBE_LEVELS[index]);
+
+    let average = rgb.iter().map(|&channel| u32::from(channel)).sum::<u32>() / 3;
+    let grey = (average.saturating_sub(3) / 10).min(23) as u8;
+    let grey_level = 8 + 10 * grey;
+
+    let distance = |other: [u8; 3]| -> u32 {
+        rgb.iter().zip(other).map(|(&a, b)| (i32::from(a) - i32::from(b)).pow(2) as u32).sum()
+    };
+    if distance([grey_level; 3]) < distance(cube_rgb) {
+        232 + grey
+    } else {
+        16 + 36 * cube[0] as u8 + 6 * cube[1] as u8 + cube[2] as u8
+    }
+}
+
+/// Append the SGR sequence setting the foreground (`layer` 38) or
+/// background (48) to `rgb`
+fn push_color(out: &mut String, layer: u8, rgb: [u8; 3], mode: ColorMode) {
+    match mode {
+        ColorMode::TrueColor => write!(out, "\x1b[{};2;{};{};{}m", layer, rgb[0], rgb[1], rgb[2]),
+        ColorMode::Ansi256 => write!(out, "\x1b[{};5;{}m", layer, ansi256(rgb)),
+    }
+    .unwrap();
+}
+
+/// Append an RGBA picture of `size` as rows of half-blocks, starting from
+/// [`HOME`]; every row ends with [`RESET`] and `\r\n` (raw mode doesn't
+/// return the carriage by itself)
+pub fn render_frame(out: &mut String, rgba: &[u8], size: (usize, usize), mode: ColorMode) {
+    let (pixels, (width, height)) = downscale(rgba, size);
+    out.push_str(HOME);
+    for row in pixels.chunks_exact(width * 2).take(height / 2) {
+        let (top, bottom) = row.split_at(width);
+        let mut colors = None;
+        for (&upper, &lower) in top.iter().zip(bottom) {
+            let (last_upper, last_lower) = colors.unzip();
+            if last_upper != Some(upper) {
+                push_color(out, 38, upper, mode);
+            }
+            if last_lower != Some(lower) {
+                push_color(out, 48, lower, mode);
+            }
+            colors = Some((upper, lower));
+            out.push(UPPER_HALF);
+        }
+        out.push_str(RESET);
+        out.push_str("\r\n");
+    }
+}
+
+/// Append the status line: speed, frame count, and whether it's paused,
+/// clearing whatever was left on the line
+pub fn status_line(out: &mut String, fps: f32, frame: u64, paused: bool) {
+    write!(out, "{}{:5.1} FPS  frame {}", RESET, fps, frame).unwrap();
+    if paused {
+        out.push_str("  PAUSED");
+    }
+    out.push_str("  [P]ause [R]eset [F12] screenshot [Q]uit\x1b[K");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `(width, height)` picture of one colour
+    fn flat(rgb: [u8; 3], (width, height): (usize, usize)) -> Vec<u8> {
+        [rgb[0], rgb[1], rgb[2], 0xFF].repeat(width * height)
+    }
+
+    #[test]
+    fn test_downscale_averages_blocks() {
+        // Top-left block: two white pixels, two black
+        let mut rgba = flat([0, 0, 0], (4, 2));
+        rgba[..4].copy_from_slice(&[255, 255, 255, 255]);
+        rgba[16..20].copy_from_slice(&[255, 255, 255, 255]);
+        let (pixels, size) = downscale(&rgba, (4, 2));
+        assert_eq!(size, (2, 1));
+        assert_eq!(pixels, vec![[128, 128, 128], [0, 0, 0]]);
+
+        // An odd row is dropped
+        assert_eq!(downscale(&flat([9, 9, 9], (2, 3)), (2, 3)).1, (1, 1));
+    }
+
+    #[test]
+    fn test_ansi256_picks_cube_or_grey() {
+        assert_eq!(ansi256([0, 0, 0]), 16);
+        assert_eq!(ansi256([255, 255, 255]), 231);
+        assert_eq!(ansi256([255, 0, 0]), 196);
+        assert_eq!(ansi256([0, 95, 255]), 27);
+        assert_eq!(ansi256([128, 128, 128]), 244);
+    }
+
+    #[test]
+    fn test_cells_stack_two_pixels_and_skip_repeated_colours() {
+        // Red over blue, four frame pixels per terminal pixel
+        let mut rgba = flat([255, 0, 0], (4, 2));
+        rgba.extend(flat([0, 0, 255], (4, 2)));
+        let mut out = String::new();
+        render_frame(&mut out, &rgba, (4, 4), ColorMode::TrueColor);
+        assert_eq!(out, "\x1b[H\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m▀▀\x1b[0m\r\n");
+
+        out.clear();
+        render_frame(&mut out, &rgba, (4, 4), ColorMode::Ansi256);
+        assert_eq!(out, "\x1b[H\x1b[38;5;196m\x1b[48;5;21m▀▀\x1b[0m\r\n");
+    }
+
+    #[test]
+    fn test_status_line() {
+        let mut out = String::new();
+        status_line(&mut out, 59.94, 1200, true);
+        assert!(out.starts_with("\x1b[0m 59.9 FPS  frame 1200  PAUSED"), "{:?}", out);
+        assert!(out.ends_with("\x1b[K"));
+    }
+}
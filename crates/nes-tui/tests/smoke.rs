@@ -0,0 +1,51 @@
+//! A generated ROM run through the renderer, no terminal needed
+
+#[path = "../../emu-nes/examples/generate_animation_test.rs"]
+#[allow(dead_code)]
+mod animation_rom;
+
+use emu_core::Emulator;
+use emu_nes::NesSystem;
+use input_handler::keyboard;
+use nes_tui::input::HeldButtons;
+use nes_tui::render::{self, ColorMode, HOME, RESET, UPPER_HALF};
+
+#[test]
+fn test_sixty_frames_render_as_half_blocks() {
+    let mut system = NesSystem::from_bytes(&animation_rom::build_rom()).unwrap();
+    let mut held = HeldButtons::new();
+    held.press("\r");
+    let mut out = String::new();
+    for _ in 0..60 {
+        keyboard::set_buttons(&mut system, 0, held.next_frame());
+        system.run_frame().unwrap();
+        out.clear();
+        render::render_frame(&mut out, &system.framebuffer_rgba(), system.screen_size(), ColorMode::TrueColor);
+        render::status_line(&mut out, 60.0, system.frame(), false);
+    }
+
+    // 60 rows of 128 cells, then the status line
+    let body = out.strip_prefix(HOME).unwrap();
+    let lines: Vec<&str> = body.split("\r\n").collect();
+    assert_eq!(lines.len(), 61);
+    for line in &lines[..60] {
+        assert_eq!(line.chars().filter(|&c| c == UPPER_HALF).count(), 128);
+        assert!(line.starts_with("\x1b[38;2;"), "{:?}", &line[..20]);
+        assert!(line.ends_with(RESET));
+    }
+    assert!(lines[60].contains("FPS  frame 60"), "{:?}", lines[60]);
+
+    // Every escape is a colour: foreground or background, three channels
+    let sequences: Vec<&str> = body.split('\x1b').skip(1).map(|rest| &rest[..rest.find(|c: char| c.is_ascii_alphabetic()).unwrap() + 1]).collect();
+    let colors: Vec<&&str> = sequences.iter().filter(|sequence| sequence.ends_with('m') && sequence.len() > 4).collect();
+    assert!(!colors.is_empty());
+    for color in colors {
+        let fields: Vec<&str> = color[1..color.len() - 1].split(';').collect();
+        assert!(matches!(fields[..2], ["38", "2"] | ["48", "2"]), "{:?}", color);
+        assert_eq!(fields.len(), 5, "{:?}", color);
+    }
+
+    // The animation draws more than one colour
+    let distinct: std::collections::HashSet<&&str> = sequences.iter().collect();
+    assert!(distinct.len() > 3, "{:?}", distinct);
+}
@@ -1,6 +1,10 @@
+mod output;
+mod player;
+
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use midly::{Smf, Timing, TrackEventKind, MidiMessage};
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
@@ -13,87 +17,245 @@ struct Args {
     #[arg(value_name = "MIDI_FILE")]
     input: PathBuf,
 
-    /// Output NES ROM file (default: input name with .nes extension)
+    /// Output file (default: input name with the format's extension)
     #[arg(short, long, value_name = "OUTPUT")]
     output: Option<PathBuf>,
 
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = Format::Nes)]
+    format: Format,
+
+    /// Pitch bend range in semitones (full wheel deflection)
+    #[arg(long, value_name = "SEMITONES", default_value_t = 2)]
+    bend_range: u8,
+
+    /// Pulse duty cycles for GM programs (0-127), e.g. "56=25,80=50"
+    ///
+    /// Duties are percentages: 12.5, 25, 50 or 75. Programs not listed use
+    /// the built-in defaults.
+    #[arg(long, value_name = "PROGRAM=DUTY,...", value_parser = parse_program_map)]
+    program_map: Option<ProgramMap>,
+
     /// Verbose output
     #[arg(short, long)]
     verbose: bool,
 }
 
-/// A musical note event
-#[derive(Debug, Clone)]
+/// What to wrap the player and music in
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    /// NES ROM that plays on power-up
+    Nes,
+    /// NES Sound Format, for music players and hardware
+    Nsf,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Nes => "nes",
+            Format::Nsf => "nsf",
+        }
+    }
+}
+
+/// Music data format version, the first byte of the header
+const FORMAT_VERSION: u8 = 2;
+
+/// Bytes per encoded event
+const EVENT_SIZE: usize = 11;
+
+/// Event type byte: note on/off
+const EVENT_NOTE: u8 = 0;
+
+/// Event type byte: pitch bend
+const EVENT_BEND: u8 = 1;
+
+/// Event type byte: vibrato
+const EVENT_VIBRATO: u8 = 2;
+
+/// Pulse duty cycle used when nothing else applies (50%)
+const DEFAULT_DUTY: u8 = 2;
+
+/// Vibrato rate in frames per cycle (about 5 Hz)
+const VIBRATO_RATE: u8 = 12;
+
+/// MIDI controller number of the modulation wheel
+const CC_MOD_WHEEL: u8 = 1;
+
+/// Duty cycle (0 = 12.5%, 1 = 25%, 2 = 50%, 3 = 75%) per GM program
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ProgramMap(HashMap<u8, u8>);
+
+impl ProgramMap {
+    /// Duty cycle for `program`, falling back to the built-in mapping
+    fn duty(&self, program: u8) -> u8 {
+        if let Some(&duty) = self.0.get(&program) {
+            return duty;
+        }
+        match program {
+            // Brass: thin and buzzy
+            56..=63 => 1,
+            // Reeds and pipes: hollow and nasal
+            64..=79 => 0,
+            // Square lead and everything else: plain square wave
+            _ => DEFAULT_DUTY,
+        }
+    }
+}
+
+/// Parse a `--program-map` value
+fn parse_program_map(spec: &str) -> std::result::Result<ProgramMap, String> {
+    let mut map = HashMap::new();
+    for entry in spec.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (program, duty) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("'{}' should be PROGRAM=DUTY", entry))?;
+        let program: u8 = program
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&program| program < 128)
+            .ok_or_else(|| format!("'{}' is not a program number (0-127)", program))?;
+        let duty = match duty.trim().trim_end_matches('%') {
+            "12" | "12.5" => 0,
+            "25" => 1,
+            "50" => 2,
+            "75" => 3,
+            other => return Err(format!("'{}' is not a duty cycle (12.5, 25, 50 or 75)", other)),
+        };
+        map.insert(program, duty);
+    }
+    Ok(ProgramMap(map))
+}
+
+/// What a music event does
+#[derive(Debug, Clone, PartialEq)]
+enum EventKind {
+    /// Start a note (velocity 1-127) or stop it (velocity 0)
+    Note { note: u8, velocity: u8, duty: u8 },
+    /// Offset the playing note's timer period (negative = higher pitch)
+    Bend { offset: i16 },
+    /// Wobble the period by up to `depth` either way, one cycle every `rate` frames
+    Vibrato { depth: u8, rate: u8 },
+}
+
+/// A music event
+#[derive(Debug, Clone, PartialEq)]
 struct NoteEvent {
     /// Time in ticks
     time: u32,
-    /// MIDI note number (0-127)
-    note: u8,
-    /// Velocity (0-127, 0 = note off)
-    velocity: u8,
     /// Channel assignment (0-3 for Pulse1, Pulse2, Triangle, Noise)
     channel: u8,
+    /// What happens
+    kind: EventKind,
 }
 
 /// Music data for embedding in ROM
+#[derive(Debug, PartialEq)]
 struct MusicData {
     /// Tempo in microseconds per quarter note
     tempo: u32,
     /// Ticks per quarter note
     ticks_per_quarter: u16,
-    /// List of note events (sorted by time)
+    /// List of events (sorted by time)
     events: Vec<NoteEvent>,
 }
 
 impl MusicData {
-    /// Convert MIDI note number to NES APU timer period
+    /// Convert a (possibly fractional) MIDI pitch to NES APU timer period
     /// Formula: period = CPU_CLOCK / (16 * frequency) - 1
     /// CPU_CLOCK = 1789773 Hz (NTSC)
-    fn midi_note_to_apu_period(note: u8) -> u16 {
+    fn pitch_to_apu_period(pitch: f64) -> u16 {
         // MIDI note 69 = A4 = 440 Hz
         // frequency = 440 * 2^((note - 69) / 12)
         const CPU_CLOCK: f64 = 1789773.0;
-        let note_f64 = note as f64;
-        let frequency = 440.0 * 2.0_f64.powf((note_f64 - 69.0) / 12.0);
+        let frequency = 440.0 * 2.0_f64.powf((pitch - 69.0) / 12.0);
         let period = (CPU_CLOCK / (16.0 * frequency)) - 1.0;
-        
+
         // Clamp to valid range (0-2047 for 11-bit period)
-        period.max(0.0).min(2047.0) as u16
+        period.clamp(0.0, 2047.0) as u16
     }
-    
+
+    /// Convert MIDI note number to NES APU timer period
+    fn midi_note_to_apu_period(note: u8) -> u16 {
+        Self::pitch_to_apu_period(note as f64)
+    }
+
+    /// Period offset for pitch bend `bend` (-8192 to 8191) on `note`, with
+    /// full deflection bending by `range` semitones
+    ///
+    /// The bent pitch is re-quantized to an 11-bit period, so the offset is
+    /// exact rather than a linear approximation.
+    fn bend_offset(note: u8, bend: i16, range: u8) -> i16 {
+        let semitones = bend as f64 / 8192.0 * range as f64;
+        let bent = Self::pitch_to_apu_period(note as f64 + semitones);
+        bent as i16 - Self::midi_note_to_apu_period(note) as i16
+    }
+
+    /// Vibrato depth in period units for a mod wheel position (0-127); 0 is off
+    fn mod_wheel_depth(value: u8) -> u8 {
+        value >> 3
+    }
+
     /// Encode music data as bytes for ROM
     fn encode(&self) -> Vec<u8> {
-        let mut data = Vec::new();
-        
-        // Header: tempo (4 bytes), ticks_per_quarter (2 bytes), event_count (2 bytes)
+        let mut data = Vec::with_capacity(9 + self.events.len() * EVENT_SIZE);
+
+        // Header: version (1 byte), tempo (4 bytes), ticks_per_quarter (2 bytes), event_count (2 bytes)
+        data.push(FORMAT_VERSION);
         data.extend_from_slice(&self.tempo.to_le_bytes());
         data.extend_from_slice(&self.ticks_per_quarter.to_le_bytes());
         data.extend_from_slice(&(self.events.len() as u16).to_le_bytes());
-        
-        // Events: each event is 8 bytes
-        // [time: 4 bytes][note: 1 byte][velocity: 1 byte][channel: 1 byte][period: 2 bytes]
+
+        // Events: each event is 11 bytes, so the player can step through them at a fixed stride
+        // [time: 4 bytes][type: 1 byte][channel: 1 byte][payload: 5 bytes]
         for event in &self.events {
-            let period = Self::midi_note_to_apu_period(event.note);
             data.extend_from_slice(&event.time.to_le_bytes());
-            data.push(event.note);
-            data.push(event.velocity);
+            let payload = match event.kind {
+                EventKind::Note { note, velocity, duty } => {
+                    data.push(EVENT_NOTE);
+                    let period = Self::midi_note_to_apu_period(note).to_le_bytes();
+                    [note, velocity, duty, period[0], period[1]]
+                }
+                EventKind::Bend { offset } => {
+                    data.push(EVENT_BEND);
+                    let offset = offset.to_le_bytes();
+                    [offset[0], offset[1], 0, 0, 0]
+                }
+                EventKind::Vibrato { depth, rate } => {
+                    data.push(EVENT_VIBRATO);
+                    [depth, rate, 0, 0, 0]
+                }
+            };
             data.push(event.channel);
-            data.extend_from_slice(&period.to_le_bytes());
+            data.extend_from_slice(&payload);
         }
-        
+
         data
     }
 }
 
-fn parse_midi(path: &PathBuf, verbose: bool) -> Result<MusicData> {
+/// A channel message with its absolute time, before NES channel assignment
+struct TimedMessage {
+    time: u32,
+    channel: u8,
+    message: MidiMessage,
+}
+
+fn parse_midi(path: &PathBuf, bend_range: u8, program_map: &ProgramMap, verbose: bool) -> Result<MusicData> {
     let data = fs::read(path)?;
-    let smf = Smf::parse(&data)?;
-    
+    parse_midi_bytes(&data, bend_range, program_map, verbose)
+}
+
+fn parse_midi_bytes(data: &[u8], bend_range: u8, program_map: &ProgramMap, verbose: bool) -> Result<MusicData> {
+    let smf = Smf::parse(data)?;
+
     if verbose {
         println!("MIDI format: {:?}", smf.header.format);
         println!("Number of tracks: {}", smf.tracks.len());
     }
-    
+
     // Get timing information
     let ticks_per_quarter = match smf.header.timing {
         Timing::Metrical(tpq) => tpq.as_int(),
@@ -101,111 +263,50 @@ fn parse_midi(path: &PathBuf, verbose: bool) -> Result<MusicData> {
             anyhow::bail!("Timecode-based MIDI files are not supported");
         }
     };
-    
+
     if verbose {
         println!("Ticks per quarter note: {}", ticks_per_quarter);
     }
-    
+
     // Default tempo: 120 BPM = 500000 microseconds per quarter note
     let mut tempo = 500000u32;
-    let mut events = Vec::new();
-    
-    // Track current time and active notes per channel
-    let mut current_time = 0u32;
-    let mut channel_notes: [Option<u8>; 4] = [None; 4]; // Track which note is playing on each NES channel
-    
-    // Simple channel allocation: distribute MIDI channels to NES channels
-    // Pulse1: MIDI channels 0-3
-    // Pulse2: MIDI channels 4-7
-    // Triangle: MIDI channels 8-11
-    // Noise: MIDI channel 9 (standard percussion channel)
-    
-    // Process all tracks
-    for (track_idx, track) in smf.tracks.iter().enumerate() {
-        current_time = 0;
-        
+    let mut messages = Vec::new();
+
+    // Collect channel messages from all tracks, so bends and controllers
+    // can be applied in time order below
+    for track in &smf.tracks {
+        let mut current_time = 0u32;
+
         for event in track {
             current_time += event.delta.as_int();
-            
+
             match event.kind {
-                TrackEventKind::Meta(meta) => {
-                    if let midly::MetaMessage::Tempo(new_tempo) = meta {
-                        tempo = new_tempo.as_int();
-                        if verbose {
-                            println!("Tempo change: {} μs/quarter note", tempo);
-                        }
+                TrackEventKind::Meta(midly::MetaMessage::Tempo(new_tempo)) => {
+                    tempo = new_tempo.as_int();
+                    if verbose {
+                        println!("Tempo change: {} μs/quarter note", tempo);
                     }
                 }
                 TrackEventKind::Midi { channel, message } => {
-                    let midi_channel = channel.as_int();
-                    
-                    // Map MIDI channel to NES channel
-                    let nes_channel = if midi_channel == 9 {
-                        3 // Percussion -> Noise channel
-                    } else if midi_channel < 4 {
-                        0 // Pulse 1
-                    } else if midi_channel < 8 {
-                        1 // Pulse 2
-                    } else {
-                        2 // Triangle
-                    };
-                    
-                    match message {
-                        MidiMessage::NoteOn { key, vel } => {
-                            let note = key.as_int();
-                            let velocity = vel.as_int();
-                            
-                            if velocity > 0 {
-                                // Note on
-                                events.push(NoteEvent {
-                                    time: current_time,
-                                    note,
-                                    velocity,
-                                    channel: nes_channel,
-                                });
-                                channel_notes[nes_channel as usize] = Some(note);
-                            } else {
-                                // Note off (velocity = 0)
-                                if channel_notes[nes_channel as usize].is_some() {
-                                    events.push(NoteEvent {
-                                        time: current_time,
-                                        note,
-                                        velocity: 0,
-                                        channel: nes_channel,
-                                    });
-                                    channel_notes[nes_channel as usize] = None;
-                                }
-                            }
-                        }
-                        MidiMessage::NoteOff { key, .. } => {
-                            let note = key.as_int();
-                            
-                            if channel_notes[nes_channel as usize].is_some() {
-                                events.push(NoteEvent {
-                                    time: current_time,
-                                    note,
-                                    velocity: 0,
-                                    channel: nes_channel,
-                                });
-                                channel_notes[nes_channel as usize] = None;
-                            }
-                        }
-                        _ => {}
-                    }
+                    messages.push(TimedMessage {
+                        time: current_time,
+                        channel: channel.as_int(),
+                        message,
+                    });
                 }
                 _ => {}
             }
         }
     }
-    
-    // Sort events by time
-    events.sort_by_key(|e| e.time);
-    
+    messages.sort_by_key(|m| m.time);
+
+    let events = convert_messages(&messages, bend_range, program_map);
+
     if verbose {
         println!("Total events: {}", events.len());
         println!("Duration: {} ticks", events.last().map(|e| e.time).unwrap_or(0));
     }
-    
+
     Ok(MusicData {
         tempo,
         ticks_per_quarter,
@@ -213,154 +314,103 @@ fn parse_midi(path: &PathBuf, verbose: bool) -> Result<MusicData> {
     })
 }
 
-fn generate_rom(music: &MusicData, output: &PathBuf, verbose: bool) -> Result<()> {
-    if verbose {
-        println!("Generating NES ROM...");
-    }
-    
-    let music_data = music.encode();
-    
-    if verbose {
-        println!("Music data size: {} bytes", music_data.len());
-    }
-    
-    // Build ROM structure
-    let mut rom = Vec::new();
-    
-    // iNES Header
-    rom.extend_from_slice(b"NES\x1a");
-    rom.push(2);  // 2 PRG ROM banks (32KB) - need space for music data
-    rom.push(0);  // 0 CHR ROM banks
-    rom.push(0);  // Mapper 0, horizontal mirroring
-    rom.extend_from_slice(&[0; 9]);
-    
-    // PRG ROM (32KB)
-    let mut prg = vec![0u8; 32768];
-    
-    // Music player code starts at $8000
-    let code_start = 0x8000;
-    let mut pc = code_start;
-    
-    // Music data will be placed at $C000 (second PRG bank)
-    let music_data_addr = 0xC000u16;
-    
-    // === RESET Handler ===
-    let reset_addr = 0x8000;
-    pc = reset_addr;
-    
-    // Wait for PPU warmup (simplified)
-    for _ in 0..2 {
-        let wait_loop = pc;
-        prg[(pc - code_start) as usize] = 0x2C; // BIT $2002
-        prg[(pc - code_start + 1) as usize] = 0x02;
-        prg[(pc - code_start + 2) as usize] = 0x20;
-        pc += 3;
-        prg[(pc - code_start) as usize] = 0x10; // BPL wait_loop
-        prg[(pc - code_start + 1) as usize] = ((wait_loop as i32 - (pc as i32 + 2)) as i8) as u8;
-        pc += 2;
-    }
-    
-    // Initialize APU
-    // Enable all channels
-    prg[(pc - code_start) as usize] = 0xA9; // LDA #$0F
-    prg[(pc - code_start + 1) as usize] = 0x0F;
-    pc += 2;
-    prg[(pc - code_start) as usize] = 0x8D; // STA $4015
-    prg[(pc - code_start + 1) as usize] = 0x15;
-    prg[(pc - code_start + 2) as usize] = 0x40;
-    pc += 3;
-    
-    // Set up pulse channels with 50% duty, constant volume
-    for channel_base in [0x4000u16, 0x4004u16] {
-        prg[(pc - code_start) as usize] = 0xA9; // LDA #$BF (50% duty, length halt, const vol=15)
-        prg[(pc - code_start + 1) as usize] = 0xBF;
-        pc += 2;
-        prg[(pc - code_start) as usize] = 0x8D; // STA $400x
-        prg[(pc - code_start + 1) as usize] = (channel_base & 0xFF) as u8;
-        prg[(pc - code_start + 2) as usize] = ((channel_base >> 8) & 0xFF) as u8;
-        pc += 3;
-    }
-    
-    // Initialize triangle channel
-    prg[(pc - code_start) as usize] = 0xA9; // LDA #$FF (length halt, linear counter load)
-    prg[(pc - code_start + 1) as usize] = 0xFF;
-    pc += 2;
-    prg[(pc - code_start) as usize] = 0x8D; // STA $4008
-    prg[(pc - code_start + 1) as usize] = 0x08;
-    prg[(pc - code_start + 2) as usize] = 0x40;
-    pc += 3;
-    
-    // Initialize noise channel
-    prg[(pc - code_start) as usize] = 0xA9; // LDA #$3F (length halt, const vol=15)
-    prg[(pc - code_start + 1) as usize] = 0x3F;
-    pc += 2;
-    prg[(pc - code_start) as usize] = 0x8D; // STA $400C
-    prg[(pc - code_start + 1) as usize] = 0x0C;
-    prg[(pc - code_start + 2) as usize] = 0x40;
-    pc += 3;
-    
-    // Initialize music playback variables
-    // $00-$01: Current event index (16-bit)
-    // $02-$05: Current time counter (32-bit)
-    prg[(pc - code_start) as usize] = 0xA9; // LDA #$00
-    prg[(pc - code_start + 1) as usize] = 0x00;
-    pc += 2;
-    for addr in 0x00..=0x05 {
-        prg[(pc - code_start) as usize] = 0x85; // STA $addr
-        prg[(pc - code_start + 1) as usize] = addr;
-        pc += 2;
-    }
-    
-    // Enable NMI
-    prg[(pc - code_start) as usize] = 0xA9; // LDA #$80
-    prg[(pc - code_start + 1) as usize] = 0x80;
-    pc += 2;
-    prg[(pc - code_start) as usize] = 0x8D; // STA $2000
-    prg[(pc - code_start + 1) as usize] = 0x00;
-    prg[(pc - code_start + 2) as usize] = 0x20;
-    pc += 3;
-    
-    // Main loop - infinite loop
-    let main_loop = pc;
-    prg[(pc - code_start) as usize] = 0x4C; // JMP main_loop
-    prg[(pc - code_start + 1) as usize] = (main_loop & 0xFF) as u8;
-    prg[(pc - code_start + 2) as usize] = ((main_loop >> 8) & 0xFF) as u8;
-    pc += 3;
-    
-    // === NMI Handler (called 60 times per second) ===
-    let nmi_addr = pc;
-    
-    // For now, simple handler that just returns
-    // TODO: Implement music playback logic that reads music data and updates APU
-    prg[(pc - code_start) as usize] = 0x40; // RTI
-    pc += 1;
-    
-    // Place music data at $C000 (second bank, offset 0x4000 in PRG)
-    let music_data_offset = 0x4000;
-    if music_data_offset + music_data.len() > prg.len() {
-        anyhow::bail!("Music data too large to fit in ROM");
-    }
-    prg[music_data_offset..music_data_offset + music_data.len()].copy_from_slice(&music_data);
-    
-    // Set interrupt vectors at $FFFA-$FFFF
-    let vector_offset = 0xFFFA - code_start;
-    prg[vector_offset as usize] = (nmi_addr & 0xFF) as u8;      // NMI low
-    prg[vector_offset as usize + 1] = ((nmi_addr >> 8) & 0xFF) as u8; // NMI high
-    prg[vector_offset as usize + 2] = (reset_addr & 0xFF) as u8;    // RESET low
-    prg[vector_offset as usize + 3] = ((reset_addr >> 8) & 0xFF) as u8; // RESET high
-    prg[vector_offset as usize + 4] = 0x00; // IRQ low
-    prg[vector_offset as usize + 5] = 0x00; // IRQ high
-    
-    rom.extend_from_slice(&prg);
-    
-    // Write ROM to file
-    fs::write(output, rom)?;
-    
-    if verbose {
-        println!("ROM written to: {}", output.display());
+/// Turn time-ordered MIDI channel messages into music events
+fn convert_messages(messages: &[TimedMessage], bend_range: u8, program_map: &ProgramMap) -> Vec<NoteEvent> {
+    let mut events = Vec::new();
+
+    // Track active notes per channel
+    let mut channel_notes: [Option<u8>; 4] = [None; 4]; // Track which note is playing on each NES channel
+    // Current pitch bend per NES channel (-8192 to 8191)
+    let mut channel_bends = [0i16; 4];
+    // Last vibrato depth sent per NES channel
+    let mut channel_vibrato = [0u8; 4];
+    // Current GM program per MIDI channel
+    let mut programs = [0u8; 16];
+
+    // Simple channel allocation: distribute MIDI channels to NES channels
+    // Pulse1: MIDI channels 0-3
+    // Pulse2: MIDI channels 4-7
+    // Triangle: MIDI channels 8-11
+    // Noise: MIDI channel 9 (standard percussion channel)
+    for &TimedMessage { time, channel: midi_channel, message } in messages {
+        // Map MIDI channel to NES channel
+        let nes_channel = if midi_channel == 9 {
+            3 // Percussion -> Noise channel
+        } else if midi_channel < 4 {
+            0 // Pulse 1
+        } else if midi_channel < 8 {
+            1 // Pulse 2
+        } else {
+            2 // Triangle
+        };
+        let is_pulse = nes_channel < 2;
+
+        match message {
+            MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                let note = key.as_int();
+                events.push(NoteEvent {
+                    time,
+                    channel: nes_channel,
+                    kind: EventKind::Note {
+                        note,
+                        velocity: vel.as_int(),
+                        duty: if is_pulse { program_map.duty(programs[midi_channel as usize]) } else { DEFAULT_DUTY },
+                    },
+                });
+                channel_notes[nes_channel as usize] = Some(note);
+
+                // The bend offset depends on the note, so carry a held bend over to it
+                let bend = channel_bends[nes_channel as usize];
+                if is_pulse && bend != 0 {
+                    events.push(NoteEvent {
+                        time,
+                        channel: nes_channel,
+                        kind: EventKind::Bend { offset: MusicData::bend_offset(note, bend, bend_range) },
+                    });
+                }
+            }
+            // Note on with velocity 0 is a note off
+            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. }
+                if channel_notes[nes_channel as usize].is_some() =>
+            {
+                events.push(NoteEvent {
+                    time,
+                    channel: nes_channel,
+                    kind: EventKind::Note { note: key.as_int(), velocity: 0, duty: 0 },
+                });
+                channel_notes[nes_channel as usize] = None;
+            }
+            MidiMessage::PitchBend { bend } if is_pulse => {
+                let bend = bend.as_int();
+                channel_bends[nes_channel as usize] = bend;
+                if let Some(note) = channel_notes[nes_channel as usize] {
+                    events.push(NoteEvent {
+                        time,
+                        channel: nes_channel,
+                        kind: EventKind::Bend { offset: MusicData::bend_offset(note, bend, bend_range) },
+                    });
+                }
+            }
+            MidiMessage::Controller { controller, value } if controller.as_int() == CC_MOD_WHEEL && nes_channel != 3 => {
+                let depth = MusicData::mod_wheel_depth(value.as_int());
+                // The mod wheel sends a stream of values; only changes the player can hear matter
+                if depth != channel_vibrato[nes_channel as usize] {
+                    channel_vibrato[nes_channel as usize] = depth;
+                    events.push(NoteEvent {
+                        time,
+                        channel: nes_channel,
+                        kind: EventKind::Vibrato { depth, rate: VIBRATO_RATE },
+                    });
+                }
+            }
+            MidiMessage::ProgramChange { program } => {
+                programs[midi_channel as usize] = program.as_int();
+            }
+            _ => {}
+        }
     }
-    
-    Ok(())
+
+    events
 }
 
 fn main() -> Result<()> {
@@ -369,7 +419,7 @@ fn main() -> Result<()> {
     // Determine output filename
     let output = args.output.unwrap_or_else(|| {
         let mut path = args.input.clone();
-        path.set_extension("nes");
+        path.set_extension(args.format.extension());
         path
     });
     
@@ -380,16 +430,28 @@ fn main() -> Result<()> {
     }
     
     // Parse MIDI file
-    let music = parse_midi(&args.input, args.verbose)
+    let program_map = args.program_map.unwrap_or_default();
+    let music = parse_midi(&args.input, args.bend_range, &program_map, args.verbose)
         .context("Failed to parse MIDI file")?;
     
     if args.verbose {
         println!();
     }
     
-    // Generate ROM
-    generate_rom(&music, &output, args.verbose)
-        .context("Failed to generate ROM")?;
+    if args.verbose {
+        println!("Music data size: {} bytes", music.encode().len());
+    }
+    let bytes = match args.format {
+        Format::Nes => output::nes_rom(&music).context("Failed to generate ROM")?,
+        Format::Nsf => {
+            let title = args.input.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+            output::nsf(&music, &title).context("Failed to generate NSF")?
+        }
+    };
+    fs::write(&output, bytes)?;
+    if args.verbose {
+        println!("Written to: {}", output.display());
+    }
     
     println!("✓ Successfully converted {} to {}", 
              args.input.display(), 
@@ -397,3 +459,121 @@ fn main() -> Result<()> {
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse `encode`'s output back into music data
+    fn decode(data: &[u8]) -> MusicData {
+        assert_eq!(data[0], FORMAT_VERSION);
+        let count = u16::from_le_bytes([data[7], data[8]]) as usize;
+        let events = data[9..]
+            .chunks(EVENT_SIZE)
+            .map(|event| {
+                let payload = &event[6..];
+                let kind = match event[4] {
+                    EVENT_NOTE => {
+                        let period = u16::from_le_bytes([payload[3], payload[4]]);
+                        assert_eq!(period, MusicData::midi_note_to_apu_period(payload[0]));
+                        EventKind::Note { note: payload[0], velocity: payload[1], duty: payload[2] }
+                    }
+                    EVENT_BEND => EventKind::Bend { offset: i16::from_le_bytes([payload[0], payload[1]]) },
+                    EVENT_VIBRATO => EventKind::Vibrato { depth: payload[0], rate: payload[1] },
+                    other => panic!("unknown event type {}", other),
+                };
+                NoteEvent {
+                    time: u32::from_le_bytes(event[..4].try_into().unwrap()),
+                    channel: event[5],
+                    kind,
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), count);
+        MusicData {
+            tempo: u32::from_le_bytes(data[1..5].try_into().unwrap()),
+            ticks_per_quarter: u16::from_le_bytes([data[5], data[6]]),
+            events,
+        }
+    }
+
+    #[test]
+    fn test_bend_range_edges() {
+        // Full downward bend lands exactly on the note `range` semitones below
+        assert_eq!(
+            MusicData::bend_offset(69, -8192, 2),
+            (MusicData::midi_note_to_apu_period(67) - MusicData::midi_note_to_apu_period(69)) as i16
+        );
+        assert_eq!(
+            MusicData::bend_offset(60, -8192, 12),
+            (MusicData::midi_note_to_apu_period(48) - MusicData::midi_note_to_apu_period(60)) as i16
+        );
+        // Full upward bend stops one step short of the range
+        let up = MusicData::bend_offset(69, 8191, 2);
+        let target = MusicData::midi_note_to_apu_period(71) as i16 - MusicData::midi_note_to_apu_period(69) as i16;
+        assert!(up < 0 && (up - target).abs() <= 1, "{} vs {}", up, target);
+        // Centred wheel does nothing
+        assert_eq!(MusicData::bend_offset(69, 0, 2), 0);
+    }
+
+    #[test]
+    fn test_bend_clamps_to_11_bit_period() {
+        // Low notes are already at the longest period, so bending down can't go further
+        assert_eq!(MusicData::midi_note_to_apu_period(20), 2047);
+        assert_eq!(MusicData::bend_offset(20, -8192, 12), 0);
+        // Far above the top of the MIDI range the period bottoms out at 0
+        assert_eq!(MusicData::pitch_to_apu_period(160.0), 0);
+        assert_eq!(MusicData::pitch_to_apu_period(170.0), 0);
+    }
+
+    #[test]
+    fn test_encoding_round_trip() {
+        let music = MusicData {
+            tempo: 500000,
+            ticks_per_quarter: 480,
+            events: vec![
+                NoteEvent { time: 0, channel: 0, kind: EventKind::Note { note: 69, velocity: 100, duty: 1 } },
+                NoteEvent { time: 120, channel: 0, kind: EventKind::Bend { offset: -42 } },
+                NoteEvent { time: 240, channel: 2, kind: EventKind::Vibrato { depth: 7, rate: VIBRATO_RATE } },
+                NoteEvent { time: 70000, channel: 0, kind: EventKind::Note { note: 69, velocity: 0, duty: 0 } },
+            ],
+        };
+        let data = music.encode();
+        assert_eq!(data.len(), 9 + 4 * EVENT_SIZE);
+        assert_eq!(decode(&data), music);
+    }
+
+    #[test]
+    fn test_bends_follow_notes_and_programs_set_duty() {
+        use midly::num::u7;
+        use midly::PitchBend;
+
+        let message = |time, channel: u8, message| TimedMessage { time, channel, message };
+        let note_on = |key: u8| MidiMessage::NoteOn { key: u7::new(key), vel: u7::new(100) };
+        let messages = [
+            message(0, 0, MidiMessage::ProgramChange { program: u7::new(56) }),
+            message(0, 0, MidiMessage::PitchBend { bend: PitchBend::from_int(-8192) }),
+            message(10, 0, note_on(69)),
+            message(20, 0, MidiMessage::Controller { controller: u7::new(1), value: u7::new(64) }),
+            message(21, 0, MidiMessage::Controller { controller: u7::new(1), value: u7::new(65) }),
+        ];
+        let map = parse_program_map("80=12.5").unwrap();
+        let events = convert_messages(&messages, 2, &map);
+
+        let kinds: Vec<_> = events.iter().map(|e| e.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                // Brass gets the built-in 25% duty
+                EventKind::Note { note: 69, velocity: 100, duty: 1 },
+                // The bend held before the note is applied to it
+                EventKind::Bend { offset: MusicData::bend_offset(69, -8192, 2) },
+                // 65 maps to the same depth as 64, so only one vibrato event
+                EventKind::Vibrato { depth: 8, rate: VIBRATO_RATE },
+            ]
+        );
+        assert_eq!(map.duty(80), 0);
+        assert!(parse_program_map("200=50").is_err());
+        assert!(parse_program_map("80=33").is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! .nes and .nsf wrappers around the shared player
+
+use crate::player::{self, Asm, FRAME_MICROS};
+use crate::MusicData;
+use anyhow::{bail, Result};
+
+/// Where the player and music are loaded, in both formats
+const LOAD_ADDR: u16 = 0x8000;
+
+/// Where the .nes output keeps its music data
+const NES_MUSIC_ADDR: u16 = 0xC000;
+
+/// Size of the NSF header
+pub const NSF_HEADER_SIZE: usize = 0x80;
+
+/// Length of one PAL frame in microseconds, for the NSF header
+const PAL_FRAME_MICROS: u16 = 19997;
+
+/// Build an NROM cartridge that plays the music from its NMI handler
+///
+/// ```text
+/// $8000-$BFFF: Code (reset and NMI handlers, player)
+/// $C000-$FFF9: Music data
+/// $FFFA-$FFFF: Vectors
+/// ```
+pub fn nes_rom(music: &MusicData) -> Result<Vec<u8>> {
+    let mut asm = Asm::new(LOAD_ADDR);
+
+    asm.label("reset");
+    asm.emit(&[0x78, 0xD8, 0xA2, 0xFF, 0x9A]); // SEI, CLD, LDX #$FF, TXS
+    // Wait two vblanks for the PPU to warm up
+    for _ in 0..2 {
+        asm.emit(&[0x2C, 0x02, 0x20, 0x10, 0xFB]); // BIT $2002, BPL -5
+    }
+    asm.abs(0x20, "init", 0); // JSR init
+    asm.emit(&[0xA9, 0x80, 0x8D, 0x00, 0x20]); // LDA #$80, STA $2000 (NMI on)
+    asm.label("idle");
+    asm.abs(0x4C, "idle", 0); // JMP idle
+
+    asm.label("nmi");
+    asm.emit(&[0x48, 0x8A, 0x48, 0x98, 0x48]); // PHA, TXA, PHA, TYA, PHA
+    asm.abs(0x20, "play", 0); // JSR play
+    asm.emit(&[0x68, 0xA8, 0x68, 0xAA, 0x68]); // PLA, TAY, PLA, TAX, PLA
+    asm.label("irq");
+    asm.emit(&[0x40]); // RTI
+
+    player::emit(&mut asm, music);
+
+    asm.org(NES_MUSIC_ADDR)?.label("music").emit(&music.encode());
+    if asm.org(0xFFFA).is_err() {
+        bail!("Music data too large to fit in ROM");
+    }
+    asm.word("nmi").word("reset").word("irq");
+    let prg = asm.finish()?;
+
+    let mut rom = Vec::with_capacity(16 + prg.len());
+    rom.extend_from_slice(b"NES\x1a");
+    rom.push(2); // 2 PRG ROM banks (32KB)
+    rom.push(0); // 0 CHR ROM banks
+    rom.push(0); // Mapper 0, horizontal mirroring
+    rom.extend_from_slice(&[0; 9]);
+    rom.extend_from_slice(&prg);
+    Ok(rom)
+}
+
+/// Copy `text` into a 32-byte, NUL-terminated NSF string field
+fn nsf_string(field: &mut [u8], text: &str) {
+    for (dst, byte) in field[..31].iter_mut().zip(text.bytes().filter(u8::is_ascii)) {
+        *dst = byte;
+    }
+}
+
+/// Build a single-song NSF with `title` as the song name
+///
+/// The player and music are loaded at $8000 with no bank switching, so
+/// they must fit in 32KB.
+pub fn nsf(music: &MusicData, title: &str) -> Result<Vec<u8>> {
+    let mut asm = Asm::new(LOAD_ADDR);
+    player::emit(&mut asm, music);
+    asm.label("music").emit(&music.encode());
+    let init = asm.address("init").unwrap();
+    let play = asm.address("play").unwrap();
+    let data = asm.finish()?;
+    if data.len() > 0x8000 {
+        bail!("Music data too large to fit in an NSF without bank switching");
+    }
+
+    let mut header = [0u8; NSF_HEADER_SIZE];
+    header[..5].copy_from_slice(b"NESM\x1a");
+    header[0x05] = 1; // Version
+    header[0x06] = 1; // Songs
+    header[0x07] = 1; // Starting song
+    header[0x08..0x0A].copy_from_slice(&LOAD_ADDR.to_le_bytes());
+    header[0x0A..0x0C].copy_from_slice(&init.to_le_bytes());
+    header[0x0C..0x0E].copy_from_slice(&play.to_le_bytes());
+    nsf_string(&mut header[0x0E..0x2E], title);
+    nsf_string(&mut header[0x2E..0x4E], "<?>");
+    nsf_string(&mut header[0x4E..0x6E], "<?>");
+    header[0x6E..0x70].copy_from_slice(&(FRAME_MICROS as u16).to_le_bytes());
+    header[0x78..0x7A].copy_from_slice(&PAL_FRAME_MICROS.to_le_bytes());
+    // $70-$77 bank switching (none), $7A NTSC, $7B no expansion audio
+
+    let mut nsf = Vec::with_capacity(NSF_HEADER_SIZE + data.len());
+    nsf.extend_from_slice(&header);
+    nsf.extend_from_slice(&data);
+    Ok(nsf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::ticks_per_frame;
+    use crate::{parse_midi_bytes, ProgramMap};
+    use emu_core::Cpu;
+    use emu_nes::cpu::{Cpu6502, CpuMemory};
+    use emu_nes::NesSystem;
+
+    /// Ticks per quarter note in `small_midi`
+    const TPQ: u32 = 96;
+
+    /// A C4 then E4 on pulse 1 over a C3 triangle bass, then a snare
+    fn small_midi() -> Vec<u8> {
+        let mut track = vec![
+            0x00, 0x90, 60, 100, // C4 on, channel 0
+            0x00, 0x98, 48, 100, // C3 on, channel 8
+            0x60, 0x80, 60, 0, // C4 off one quarter later
+            0x00, 0x90, 64, 100, // E4 on
+            0x60, 0x99, 38, 100, // Snare on the percussion channel
+        ];
+        track.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+        let mut smf = b"MThd\0\0\0\x06\0\0\0\x01".to_vec();
+        smf.extend_from_slice(&(TPQ as u16).to_be_bytes());
+        smf.extend_from_slice(b"MTrk");
+        smf.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        smf.extend_from_slice(&track);
+        smf
+    }
+
+    fn small_music() -> MusicData {
+        parse_midi_bytes(&small_midi(), 2, &ProgramMap::default(), false).unwrap()
+    }
+
+    /// Flat 64KB with the NSF loaded, logging APU register writes by frame
+    struct NsfBus {
+        ram: Vec<u8>,
+        frame: usize,
+        writes: Vec<(usize, u16, u8)>,
+    }
+
+    impl CpuMemory for NsfBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.ram[addr as usize]
+        }
+
+        fn write(&mut self, addr: u16, value: u8) {
+            if (0x4000..=0x4017).contains(&addr) {
+                self.writes.push((self.frame, addr, value));
+            }
+            self.ram[addr as usize] = value;
+        }
+    }
+
+    /// Call the routine at `addr` as an NSF player would and run it to its RTS
+    fn call(cpu: &mut Cpu6502<NsfBus>, addr: u16) {
+        const CALLER: u16 = 0x3F00;
+        let [lo, hi] = addr.to_le_bytes();
+        let caller = [0x20, lo, hi, 0x4C, 0x03, 0x3F]; // JSR addr, JMP *
+        cpu.memory().ram[CALLER as usize..CALLER as usize + 6].copy_from_slice(&caller);
+        cpu.pc = CALLER;
+        for _ in 0..100_000 {
+            if cpu.pc == CALLER + 3 {
+                return;
+            }
+            cpu.step().unwrap();
+        }
+        panic!("routine at ${:04X} did not return", addr);
+    }
+
+    #[test]
+    fn test_nsf_header_points_at_player() {
+        let nsf = nsf(&small_music(), "small song").unwrap();
+        assert_eq!(&nsf[..5], b"NESM\x1a");
+        assert_eq!(&nsf[5..8], &[1, 1, 1]);
+        let word = |offset: usize| u16::from_le_bytes([nsf[offset], nsf[offset + 1]]);
+        assert_eq!(word(0x08), LOAD_ADDR);
+        assert_eq!(&nsf[0x0E..0x19], b"small song\0");
+        assert_eq!(word(0x6E), FRAME_MICROS as u16);
+        assert_eq!(nsf[0x7A], 0);
+        assert!(nsf[0x70..0x78].iter().all(|&bank| bank == 0));
+
+        let code = |addr: u16| &nsf[NSF_HEADER_SIZE + (addr - LOAD_ADDR) as usize..];
+        // init starts by enabling the channels, play by checking for events left
+        assert_eq!(&code(word(0x0A))[..5], &[0xA9, 0x0F, 0x8D, 0x15, 0x40]);
+        assert_eq!(&code(word(0x0C))[..2], &[0xA5, 0x02]);
+        // No PPU setup anywhere: nothing stores to $2000-$2007
+        assert!(!nsf.windows(3).any(|op| op[0] == 0x8D && op[2] == 0x20 && op[1] < 8));
+    }
+
+    #[test]
+    fn test_nsf_plays_midi() {
+        let music = small_music();
+        let nsf = nsf(&music, "small").unwrap();
+        let mut bus = NsfBus { ram: vec![0; 0x10000], frame: 0, writes: Vec::new() };
+        bus.ram[LOAD_ADDR as usize..LOAD_ADDR as usize + nsf.len() - NSF_HEADER_SIZE]
+            .copy_from_slice(&nsf[NSF_HEADER_SIZE..]);
+        let init = u16::from_le_bytes([nsf[0x0A], nsf[0x0B]]);
+        let play = u16::from_le_bytes([nsf[0x0C], nsf[0x0D]]);
+
+        let mut cpu = Cpu6502::new(bus);
+        cpu.a = 0; // First song
+        cpu.x = 0; // NTSC
+        call(&mut cpu, init);
+        // init only sets the APU up, silently
+        let setup = std::mem::take(&mut cpu.memory().writes);
+        let setup: Vec<_> = setup.iter().map(|w| (w.1, w.2)).collect();
+        assert_eq!(
+            setup,
+            vec![(0x4015, 0x0F), (0x4017, 0x40), (0x4000, 0x30), (0x4004, 0x30), (0x400C, 0x30), (0x4008, 0x80)]
+        );
+        for frame in 0..120 {
+            cpu.memory().frame = frame;
+            call(&mut cpu, play);
+        }
+
+        let writes = &cpu.memory().writes;
+        let at = |frame: usize| -> Vec<(u16, u8)> {
+            writes.iter().filter(|w| w.0 == frame).map(|w| (w.1, w.2)).collect()
+        };
+        // First frame a tick reaches `ticks`
+        let step = ticks_per_frame(&music) as u64;
+        let frame_of = |ticks: u32| ((ticks as u64) << 16).div_ceil(step) as usize;
+        let period = |note| MusicData::midi_note_to_apu_period(note).to_le_bytes();
+
+        let c4 = period(60);
+        let c3 = period(48);
+        assert_eq!(
+            at(0),
+            vec![(0x4000, 0xBF), (0x4002, c4[0]), (0x4003, c4[1]), (0x4008, 0xFF), (0x400A, c3[0]), (0x400B, c3[1])]
+        );
+        let e4 = period(64);
+        let quarter = frame_of(TPQ);
+        assert_eq!(at(quarter), vec![(0x4000, 0x30), (0x4000, 0xBF), (0x4002, e4[0]), (0x4003, e4[1])]);
+        // Nothing happens between events
+        assert!((1..quarter).all(|frame| at(frame).is_empty()));
+        let snare = frame_of(2 * TPQ);
+        assert_eq!(at(snare), vec![(0x400E, (38 & 0x0F) ^ 0x0F), (0x400C, 0x3F), (0x400F, 0x08)]);
+        assert!(snare < 120 && writes.iter().all(|w| w.0 <= snare));
+    }
+
+    #[test]
+    fn test_nes_rom_plays_from_nmi() {
+        let rom = nes_rom(&small_music()).unwrap();
+        let mut system = NesSystem::from_bytes(&rom).unwrap();
+        system.run_frames(10, false).unwrap();
+        // Pulse 1 and triangle are sounding
+        assert_eq!(system.apu().read_register(0x4015) & 0x05, 0x05);
+    }
+}
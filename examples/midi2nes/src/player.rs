@@ -0,0 +1,315 @@
+//! 6502 playback engine shared by the .nes and .nsf outputs
+//!
+//! The player is two routines that follow NSF conventions: `init` sets up
+//! the APU and points at the first event, and `play` is called once per
+//! frame to issue every event that has come due. The .nes output calls
+//! `play` from its NMI handler; an NSF player calls it on its own timer.
+//! Both expect the encoded music data at the `music` label.
+
+use crate::{MusicData, EVENT_SIZE};
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+
+/// Length of one NTSC frame in microseconds (60.0988 Hz)
+pub const FRAME_MICROS: u32 = 16639;
+
+/// Zero page: pointer to the next event (2 bytes)
+const PTR: u8 = 0x00;
+/// Zero page: events left to play (2 bytes)
+const REMAINING: u8 = 0x02;
+/// Zero page: song position in ticks (4 bytes)
+const TIME: u8 = 0x04;
+/// Zero page: fraction of a tick (2 bytes)
+const FRAC: u8 = 0x08;
+
+/// Offset of the first event in the music data, past the header
+const HEADER_SIZE: u16 = 9;
+
+/// Song ticks that pass per frame, in 16.16 fixed point
+pub fn ticks_per_frame(music: &MusicData) -> u32 {
+    let step = music.ticks_per_quarter as u64 * FRAME_MICROS as u64 * 65536 / music.tempo.max(1) as u64;
+    step.min(u32::MAX as u64) as u32
+}
+
+/// How a label reference is patched in
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    /// Little-endian address
+    Word,
+    /// Low byte of the address
+    Low,
+    /// High byte of the address
+    High,
+    /// Signed offset from the end of a branch instruction
+    Branch,
+}
+
+/// A label reference waiting for the label's address
+struct Fixup {
+    offset: usize,
+    label: &'static str,
+    addend: u16,
+    kind: FixupKind,
+}
+
+/// Minimal 6502 assembler: raw bytes plus labels with forward references
+pub struct Asm {
+    origin: u16,
+    bytes: Vec<u8>,
+    labels: HashMap<&'static str, u16>,
+    fixups: Vec<Fixup>,
+}
+
+impl Asm {
+    /// Start assembling at `origin`
+    pub fn new(origin: u16) -> Self {
+        Self { origin, bytes: Vec::new(), labels: HashMap::new(), fixups: Vec::new() }
+    }
+
+    /// Address of the next byte
+    pub fn pc(&self) -> u16 {
+        self.origin.wrapping_add(self.bytes.len() as u16)
+    }
+
+    /// Define `name` at the current address
+    pub fn label(&mut self, name: &'static str) -> &mut Self {
+        let pc = self.pc();
+        let previous = self.labels.insert(name, pc);
+        assert!(previous.is_none(), "label {} defined twice", name);
+        self
+    }
+
+    /// Address of a defined label
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.labels.get(name).copied()
+    }
+
+    /// Append raw bytes
+    pub fn emit(&mut self, bytes: &[u8]) -> &mut Self {
+        self.bytes.extend_from_slice(bytes);
+        self
+    }
+
+    fn fixup(&mut self, label: &'static str, addend: u16, kind: FixupKind) {
+        let offset = self.bytes.len();
+        self.fixups.push(Fixup { offset, label, addend, kind });
+        let size = if matches!(kind, FixupKind::Word) { 2 } else { 1 };
+        self.bytes.resize(offset + size, 0);
+    }
+
+    /// Instruction with an absolute operand: `opcode label+addend`
+    pub fn abs(&mut self, opcode: u8, label: &'static str, addend: u16) -> &mut Self {
+        self.bytes.push(opcode);
+        self.fixup(label, addend, FixupKind::Word);
+        self
+    }
+
+    /// `LDA #<(label+addend)`
+    pub fn lda_low(&mut self, label: &'static str, addend: u16) -> &mut Self {
+        self.bytes.push(0xA9);
+        self.fixup(label, addend, FixupKind::Low);
+        self
+    }
+
+    /// `LDA #>(label+addend)`
+    pub fn lda_high(&mut self, label: &'static str, addend: u16) -> &mut Self {
+        self.bytes.push(0xA9);
+        self.fixup(label, addend, FixupKind::High);
+        self
+    }
+
+    /// Relative branch to `label`
+    pub fn branch(&mut self, opcode: u8, label: &'static str) -> &mut Self {
+        self.bytes.push(opcode);
+        self.fixup(label, 0, FixupKind::Branch);
+        self
+    }
+
+    /// A 16-bit address table entry
+    pub fn word(&mut self, label: &'static str) -> &mut Self {
+        self.fixup(label, 0, FixupKind::Word);
+        self
+    }
+
+    /// Pad with zeros up to `addr`
+    pub fn org(&mut self, addr: u16) -> Result<&mut Self> {
+        if addr < self.pc() {
+            bail!("${:04X} is already taken (next free byte is ${:04X})", addr, self.pc());
+        }
+        self.bytes.resize((addr - self.origin) as usize, 0);
+        Ok(self)
+    }
+
+    /// Resolve every label reference and return the assembled bytes
+    pub fn finish(mut self) -> Result<Vec<u8>> {
+        for fixup in &self.fixups {
+            let target = self
+                .labels
+                .get(fixup.label)
+                .with_context(|| format!("undefined label {}", fixup.label))?
+                .wrapping_add(fixup.addend);
+            match fixup.kind {
+                FixupKind::Word => {
+                    self.bytes[fixup.offset..fixup.offset + 2].copy_from_slice(&target.to_le_bytes())
+                }
+                FixupKind::Low => self.bytes[fixup.offset] = target as u8,
+                FixupKind::High => self.bytes[fixup.offset] = (target >> 8) as u8,
+                FixupKind::Branch => {
+                    let next = self.origin as i32 + fixup.offset as i32 + 1;
+                    let distance = target as i32 - next;
+                    if !(-128..=127).contains(&distance) {
+                        bail!("branch to {} is out of range ({} bytes)", fixup.label, distance);
+                    }
+                    self.bytes[fixup.offset] = distance as i8 as u8;
+                }
+            }
+        }
+        Ok(self.bytes)
+    }
+}
+
+/// Emit the `init` and `play` routines
+///
+/// `init` may be called with anything in A and X (an NSF player passes the
+/// song number and region), and leaves the APU silent until the first note.
+/// `play` handles note events; bends and vibrato are stepped over.
+pub fn emit(asm: &mut Asm, music: &MusicData) {
+    let step = ticks_per_frame(music).to_le_bytes();
+
+    // === init ===
+    asm.label("init");
+    asm.emit(&[
+        0xA9, 0x0F, 0x8D, 0x15, 0x40, // LDA #$0F, STA $4015 (all channels on)
+        0xA9, 0x40, 0x8D, 0x17, 0x40, // LDA #$40, STA $4017 (no frame IRQ)
+        0xA9, 0x30, // LDA #$30 (length halt, constant volume 0)
+        0x8D, 0x00, 0x40, // STA $4000
+        0x8D, 0x04, 0x40, // STA $4004
+        0x8D, 0x0C, 0x40, // STA $400C
+        0xA9, 0x80, 0x8D, 0x08, 0x40, // LDA #$80, STA $4008 (triangle linear counter 0)
+    ]);
+    // Point at the first event and load the event count from the header
+    asm.lda_low("music", HEADER_SIZE).emit(&[0x85, PTR]);
+    asm.lda_high("music", HEADER_SIZE).emit(&[0x85, PTR + 1]);
+    asm.abs(0xAD, "music", 7).emit(&[0x85, REMAINING]); // LDA music+7, STA
+    asm.abs(0xAD, "music", 8).emit(&[0x85, REMAINING + 1]); // LDA music+8, STA
+    asm.emit(&[0xA9, 0x00]); // LDA #0
+    for addr in [TIME, TIME + 1, TIME + 2, TIME + 3, FRAC, FRAC + 1] {
+        asm.emit(&[0x85, addr]); // STA addr
+    }
+    asm.emit(&[0x60]); // RTS
+
+    // === play ===
+    // Issue every event whose time has been reached, then advance the clock
+    asm.label("play");
+    asm.label("next_event");
+    asm.emit(&[0xA5, REMAINING, 0x05, REMAINING + 1]); // LDA REMAINING, ORA REMAINING+1
+    asm.branch(0xF0, "advance"); // BEQ advance (song over)
+    // Compare the event's 32-bit time with the song position, high byte first
+    asm.emit(&[0xA0, 0x03]); // LDY #3
+    asm.label("compare");
+    asm.emit(&[0xB1, PTR, 0xD9, TIME, 0x00]); // LDA (PTR),Y, CMP TIME,Y
+    asm.branch(0x90, "due"); // BCC due (event is earlier)
+    asm.branch(0xD0, "advance"); // BNE advance (event is later)
+    asm.emit(&[0x88]); // DEY
+    asm.branch(0x10, "compare"); // BPL compare
+    asm.label("due");
+    asm.abs(0x20, "event", 0); // JSR event
+    asm.emit(&[
+        0x18, 0xA5, PTR, 0x69, EVENT_SIZE as u8, 0x85, PTR, // CLC, LDA PTR, ADC #EVENT_SIZE, STA PTR
+        0x90, 0x02, 0xE6, PTR + 1, // BCC +2, INC PTR+1
+        0xA5, REMAINING, 0xD0, 0x02, 0xC6, REMAINING + 1, // LDA REMAINING, BNE +2, DEC REMAINING+1
+        0xC6, REMAINING, // DEC REMAINING
+    ]);
+    asm.abs(0x4C, "next_event", 0); // JMP next_event
+    asm.label("advance");
+    asm.emit(&[0x18]); // CLC
+    let adds = [(FRAC, step[0]), (FRAC + 1, step[1]), (TIME, step[2]), (TIME + 1, step[3]), (TIME + 2, 0), (TIME + 3, 0)];
+    for (addr, value) in adds {
+        asm.emit(&[0xA5, addr, 0x69, value, 0x85, addr]); // LDA addr, ADC #value, STA addr
+    }
+    asm.emit(&[0x60]); // RTS
+
+    // === event: issue the event at PTR ===
+    asm.label("event");
+    asm.emit(&[0xA0, 0x04, 0xB1, PTR]); // LDY #4, LDA (PTR),Y (type)
+    asm.branch(0xD0, "event_done"); // BNE event_done (not a note)
+    asm.emit(&[0xA0, 0x05, 0xB1, PTR, 0x0A, 0x0A, 0xAA]); // LDY #5, LDA (PTR),Y, ASL, ASL, TAX
+    asm.emit(&[0xA0, 0x07, 0xB1, PTR]); // LDY #7, LDA (PTR),Y (velocity)
+    asm.branch(0xF0, "note_off"); // BEQ note_off
+    asm.emit(&[0xE0, 0x08]); // CPX #8
+    asm.branch(0xF0, "triangle_on"); // BEQ triangle_on
+    asm.branch(0xB0, "noise_on"); // BCS noise_on
+    // Pulse: duty into bits 6-7, length halt, constant volume 15
+    asm.emit(&[0xA0, 0x08, 0xB1, PTR]); // LDY #8, LDA (PTR),Y (duty)
+    asm.emit(&[0x0A; 6]); // ASL x6
+    asm.emit(&[0x09, 0x3F, 0x9D, 0x00, 0x40]); // ORA #$3F, STA $4000,X
+    asm.emit(&[0xA0, 0x09, 0xB1, PTR, 0x9D, 0x02, 0x40]); // LDY #9, LDA (PTR),Y, STA $4002,X
+    asm.emit(&[0xC8, 0xB1, PTR, 0x9D, 0x03, 0x40]); // INY, LDA (PTR),Y, STA $4003,X
+    asm.label("event_done");
+    asm.emit(&[0x60]); // RTS
+
+    asm.label("triangle_on");
+    asm.emit(&[
+        0xA9, 0xFF, 0x8D, 0x08, 0x40, // LDA #$FF, STA $4008
+        0xA0, 0x09, 0xB1, PTR, 0x8D, 0x0A, 0x40, // LDY #9, LDA (PTR),Y, STA $400A
+        0xC8, 0xB1, PTR, 0x8D, 0x0B, 0x40, // INY, LDA (PTR),Y, STA $400B
+        0x60, // RTS
+    ]);
+
+    // Noise: the low nibble of the note picks the period, higher notes shorter
+    asm.label("noise_on");
+    asm.emit(&[
+        0xA0, 0x06, 0xB1, PTR, // LDY #6, LDA (PTR),Y (note)
+        0x29, 0x0F, 0x49, 0x0F, 0x8D, 0x0E, 0x40, // AND #$0F, EOR #$0F, STA $400E
+        0xA9, 0x3F, 0x8D, 0x0C, 0x40, // LDA #$3F, STA $400C
+        0xA9, 0x08, 0x8D, 0x0F, 0x40, // LDA #$08, STA $400F
+        0x60, // RTS
+    ]);
+
+    // Pulse and noise go quiet through their volume, the triangle through its linear counter
+    asm.label("note_off");
+    asm.emit(&[0xE0, 0x08]); // CPX #8
+    asm.branch(0xF0, "triangle_off"); // BEQ triangle_off
+    asm.emit(&[0xA9, 0x30, 0x9D, 0x00, 0x40, 0x60]); // LDA #$30, STA $4000,X, RTS
+    asm.label("triangle_off");
+    asm.emit(&[0xA9, 0x80, 0x8D, 0x08, 0x40, 0x60]); // LDA #$80, STA $4008, RTS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asm_resolves_forward_references() {
+        let mut asm = Asm::new(0x8000);
+        asm.branch(0xD0, "skip"); // $8000
+        asm.abs(0x4C, "data", 1); // $8002
+        asm.label("skip"); // $8005
+        asm.lda_low("data", 0).lda_high("data", 0);
+        asm.org(0x8100).unwrap().label("data").word("skip");
+        assert!(asm.org(0x8000).is_err());
+        let bytes = asm.finish().unwrap();
+        assert_eq!(&bytes[..9], &[0xD0, 0x03, 0x4C, 0x01, 0x81, 0xA9, 0x00, 0xA9, 0x81]);
+        assert_eq!(&bytes[0x100..], &[0x05, 0x80]);
+    }
+
+    #[test]
+    fn test_asm_rejects_bad_references() {
+        let mut asm = Asm::new(0x8000);
+        asm.abs(0x20, "nowhere", 0);
+        assert!(asm.finish().is_err());
+
+        let mut asm = Asm::new(0x8000);
+        asm.branch(0xF0, "far").emit(&[0xEA; 200]).label("far");
+        assert!(asm.finish().is_err());
+    }
+
+    #[test]
+    fn test_ticks_per_frame() {
+        let music = MusicData { tempo: 500000, ticks_per_quarter: 480, events: Vec::new() };
+        // 480 ticks per half second is 15.97 ticks per frame
+        let step = ticks_per_frame(&music);
+        assert_eq!(step >> 16, 15);
+        assert!((step as f64 / 65536.0 - 15.973).abs() < 0.001);
+    }
+}
@@ -1,458 +1,1173 @@
-use std::sync::{Arc, Mutex};
-use std::collections::VecDeque;
-use std::thread;
+use std::sync::{mpsc, Arc};
+use std::io;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 use std::rc::Rc;
 use std::cell::RefCell;
 use emu_nes::system::NesSystem;
-use emu_core::Button;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Stream, StreamConfig, SampleRate};
-use tracing::trace;
+use emu_nes::{AccuracyFlags, EmulatorService, InputSchedule, MemoryRegion, Region, ScriptMode, StereoConfig};
+use emu_core::{Emulator, EmulatorError};
+use crate::controls::{LoopSettings, PadInput, RunControl, RunState};
+use crate::cores::{self, Core, CoreHandle};
+use crate::debugger::{self, DebuggerView, DisasmRow, RegisterView};
+use crate::emulation::{EmulationLoop, Screen};
+use crate::frame_loop;
+use crate::menu::{self, MenuAction, MenuContext};
+use crate::pacing::{self, FrameQueue};
+use crate::ppu_viewer;
+use crate::rom_watch::{self, RomWatch};
+use crate::run_ahead;
+use crate::session::{self, Session, SessionError};
+use crate::settings::{AccuracyProfile, Config, FrameBlendMode, GameOverrides, PacingMode, ScaleMode, Settings, TvSystem};
+use crate::slots::{self, SlotFile};
+use crate::status::{StatusModel, StatusSender, StatusUpdate};
 
 slint::include_modules!();
 
-/// Audio sample rate (Hz)
-const SAMPLE_RATE: u32 = 44100;
+/// NES frame width in pixels
+const SCREEN_WIDTH: f32 = 256.0;
 
-/// Samples per frame at 60 FPS: 44100 / 60 = 735
-const SAMPLES_PER_FRAME: usize = 735;
+/// NES frame height in pixels
+const SCREEN_HEIGHT: f32 = 240.0;
 
-/// Audio buffer size (how many samples to buffer)
-const AUDIO_BUFFER_SIZE: usize = 4096;
+/// Lines hidden at the top and at the bottom when cropping overscan
+const OVERSCAN_LINES: f32 = 8.0;
 
-/// Audio system for playing NES audio
-struct AudioSystem {
-    _stream: Stream,
-    sample_buffer: Arc<Mutex<VecDeque<f32>>>,
+/// How often the UI thread refreshes the status bar
+const STATUS_REFRESH: Duration = Duration::from_millis(100);
+
+/// How often the UI thread checks for a new frame in display-paced mode
+const PRESENT_POLL: Duration = Duration::from_millis(2);
+
+/// Round the scale down to a whole number when that shrinks it by less than this fraction
+const INTEGER_SNAP: f32 = 0.05;
+
+/// Size and position of the screen image within an area of `area_width` x
+/// `area_height` logical pixels
+///
+/// Except in stretch mode the image keeps its aspect ratio and is centred,
+/// leaving black bars. Scales just above a whole number are snapped down to
+/// it so pixels stay evenly sized.
+pub fn screen_rect(area_width: f32, area_height: f32, mode: ScaleMode, crop_overscan: bool) -> ScreenRect {
+    let area_width = area_width.max(0.0);
+    let area_height = area_height.max(0.0);
+    let pixel_aspect = match mode {
+        ScaleMode::Square => 1.0,
+        ScaleMode::Ntsc => 8.0 / 7.0,
+        ScaleMode::Stretch => {
+            return ScreenRect {
+                x: 0.0,
+                y: 0.0,
+                width: area_width,
+                height: area_height,
+            };
+        }
+    };
+    let source_height = if crop_overscan {
+        SCREEN_HEIGHT - 2.0 * OVERSCAN_LINES
+    } else {
+        SCREEN_HEIGHT
+    };
+    
+    let mut scale = (area_width / (SCREEN_WIDTH * pixel_aspect)).min(area_height / source_height);
+    if scale >= 1.0 && scale.fract() < scale * INTEGER_SNAP {
+        scale = scale.floor();
+    }
+    let width = (SCREEN_WIDTH * pixel_aspect * scale).round().min(area_width);
+    let height = (source_height * scale).round().min(area_height);
+    ScreenRect {
+        x: ((area_width - width) / 2.0).floor(),
+        y: ((area_height - height) / 2.0).floor(),
+        width,
+        height,
+    }
 }
 
-impl AudioSystem {
-    fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let host = cpal::default_host();
-        let device = host.default_output_device()
-            .ok_or("No audio output device available")?;
-        
-        let config = StreamConfig {
-            channels: 1, // Mono
-            sample_rate: SampleRate(SAMPLE_RATE),
-            buffer_size: cpal::BufferSize::Default,
-        };
-        
-        println!("Audio config: {:?}", config);
-        
-        // Shared buffer for audio samples
-        let sample_buffer = Arc::new(Mutex::new(VecDeque::with_capacity(AUDIO_BUFFER_SIZE)));
-        let buffer_clone = sample_buffer.clone();
-        
-        let stream = device.build_output_stream(
-            &config,
-            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                let mut buffer = buffer_clone.lock().unwrap();
-                let mut last_sample = -1.0; // APU silence level
-                
-                // Fill output buffer
-                for sample in data.iter_mut() {
-                    if let Some(s) = buffer.pop_front() {
-                        *sample = s;
-                        last_sample = s;
-                    } else {
-                        // Buffer underrun - repeat last sample to avoid clicking
-                        *sample = last_sample;
-                    }
-                }
-                
-                // Debug: warn if buffer is running low
-                if buffer.len() < 100 {
-                    // eprintln!("Audio buffer low: {} samples", buffer.len());
-                }
-            },
-            move |err| {
-                eprintln!("Audio stream error: {}", err);
-            },
-            None,
-        )?;
-        
-        // Pre-fill buffer with a small amount of silence to prevent initial underrun
-        // Just enough to cover the first audio callback (~1-2ms)
-        {
-            let mut buffer = sample_buffer.lock().unwrap();
-            for _ in 0..256 {
-                buffer.push_back(-1.0);
+/// Map a scale mode to the scale mode combo box index
+fn scale_mode_to_index(mode: ScaleMode) -> i32 {
+    match mode {
+        ScaleMode::Square => 0,
+        ScaleMode::Ntsc => 1,
+        ScaleMode::Stretch => 2,
+    }
+}
+
+/// Map the scale mode combo box index to a scale mode
+fn index_to_scale_mode(index: i32) -> ScaleMode {
+    match index {
+        0 => ScaleMode::Square,
+        2 => ScaleMode::Stretch,
+        _ => ScaleMode::Ntsc,
+    }
+}
+
+/// Map a frame blend mode to the frame blend index
+fn frame_blend_to_index(mode: FrameBlendMode) -> i32 {
+    match mode {
+        FrameBlendMode::Off => 0,
+        FrameBlendMode::Blend => 1,
+        FrameBlendMode::Phosphor => 2,
+    }
+}
+
+/// Map the frame blend index to a frame blend mode
+fn index_to_frame_blend(index: i32) -> FrameBlendMode {
+    match index {
+        1 => FrameBlendMode::Blend,
+        2 => FrameBlendMode::Phosphor,
+        _ => FrameBlendMode::Off,
+    }
+}
+
+/// Map a pacing mode to the pacing combo box index
+fn pacing_mode_to_index(mode: PacingMode) -> i32 {
+    match mode {
+        PacingMode::Emulation => 0,
+        PacingMode::Display => 1,
+    }
+}
+
+/// Map the pacing combo box index to a pacing mode
+fn index_to_pacing_mode(index: i32) -> PacingMode {
+    match index {
+        1 => PacingMode::Display,
+        _ => PacingMode::Emulation,
+    }
+}
+
+/// Map an accuracy profile to the accuracy combo box index
+fn accuracy_profile_to_index(profile: AccuracyProfile) -> i32 {
+    match profile {
+        AccuracyProfile::Compatible => 0,
+        AccuracyProfile::HardwareStrict => 1,
+    }
+}
+
+/// Map the accuracy combo box index to an accuracy profile
+fn index_to_accuracy_profile(index: i32) -> AccuracyProfile {
+    match index {
+        1 => AccuracyProfile::HardwareStrict,
+        _ => AccuracyProfile::Compatible,
+    }
+}
+
+/// Core flags for an accuracy profile
+fn accuracy_flags(profile: AccuracyProfile) -> AccuracyFlags {
+    match profile {
+        AccuracyProfile::Compatible => AccuracyFlags::empty(),
+        AccuracyProfile::HardwareStrict => AccuracyFlags::HARDWARE_STRICT,
+    }
+}
+
+/// Savestate slot operation requested from the UI
+enum StateCommand {
+    Save { slot: u8, path: PathBuf },
+    Load { slot: u8, path: PathBuf },
+}
+
+/// Something the debugger does to the system, such as a step
+type DebuggerCommand = Box<dyn FnOnce(&mut NesSystem) -> emu_core::Result<()> + Send>;
+
+/// The main window, as the frame loop sees it
+struct WindowScreen {
+    window: slint::Weak<MainWindow>,
+    status: StatusSender,
+}
+
+impl Screen for WindowScreen {
+    fn show_frame(&self, rgba: Vec<u8>, size: (usize, usize)) {
+        let window_weak = self.window.clone();
+        let status = self.status.clone();
+        slint::invoke_from_event_loop(move || {
+            if let Some(window) = window_weak.upgrade() {
+                window.set_screen_image(frame_loop::screen_image(&rgba, size));
+                status.send(StatusUpdate::FramePresented { at: Instant::now() }).ok();
             }
-        }
-        
-        stream.play()?;
-        
-        Ok(Self {
-            _stream: stream,
-            sample_buffer,
-        })
+        }).ok();
     }
-    
-    /// Send audio samples to the playback buffer
-    fn send_samples(&self, samples: &[f32]) {
-        let mut buffer = self.sample_buffer.lock().unwrap();
-        
-        // Add samples if buffer has space
-        for &sample in samples {
-            if buffer.len() < AUDIO_BUFFER_SIZE {
-                buffer.push_back(sample);
-            } else {
-                // Buffer full - drop samples to avoid unbounded growth
-                break;
+
+    fn warn(&self, text: String) {
+        let window_weak = self.window.clone();
+        slint::invoke_from_event_loop(move || {
+            if let Some(window) = window_weak.upgrade() {
+                window.set_warning_text(text.into());
             }
-        }
+        }).ok();
     }
-    
-    /// Fade out audio buffer to prevent pop
-    /// Gradually fades current samples to silence (-1.0)
-    fn fade_out(&self) {
-        let mut buffer = self.sample_buffer.lock().unwrap();
-        let fade_samples = 441; // ~10ms fade at 44.1kHz
-        
-        // Clear existing buffer and add fade-out samples
-        let current_level = buffer.back().copied().unwrap_or(-1.0);
-        buffer.clear();
-        
-        for i in 0..fade_samples {
-            let t = i as f32 / fade_samples as f32;
-            let sample = current_level * (1.0 - t) + (-1.0) * t;
-            buffer.push_back(sample);
-        }
+
+    fn stopped(&self, black_screen: Option<(Vec<u8>, (usize, usize))>) {
+        let window_weak = self.window.clone();
+        slint::invoke_from_event_loop(move || {
+            if let Some(window) = window_weak.upgrade() {
+                window.set_emulator_running(false);
+                if let Some((rgba_data, screen_size)) = black_screen {
+                    window.set_screen_image(frame_loop::screen_image(&rgba_data, screen_size));
+                }
+            }
+        }).ok();
     }
 }
 
 pub struct EmulatorApp {
     window: MainWindow,
     #[allow(dead_code)]
-    emulator: Arc<Mutex<Option<NesSystem>>>,
+    emulator: CoreHandle,
+    /// Drains status updates into the status bar; stops when dropped
+    _status_timer: slint::Timer,
+    /// Polls the loaded ROM for auto-reload; stops when dropped
+    _reload_timer: slint::Timer,
+    /// Shows display-paced frames; stops when dropped
+    _present_timer: slint::Timer,
 }
 
 impl EmulatorApp {
     pub fn new() -> Result<Self, slint::PlatformError> {
         let window = MainWindow::new()?;
-        let emulator = Arc::new(Mutex::new(None));
+        // The service stops once the last handle to it is dropped
+        let (emulator, _) = EmulatorService::spawn(None::<Core>);
+        let (status, status_timer) = Self::setup_status_bar(&window);
+        let frames = Arc::new(FrameQueue::new(pacing::QUEUE_DEPTH));
+        let present_timer = Self::setup_presenter(&window, frames.clone(), status.clone());
 
         // Setup callbacks
-        Self::setup_callbacks(&window, emulator.clone());
+        let reload_timer = Self::setup_callbacks(&window, emulator.clone(), frames, status);
 
-        Ok(Self { window, emulator })
+        Ok(Self {
+            window,
+            emulator,
+            _status_timer: status_timer,
+            _reload_timer: reload_timer,
+            _present_timer: present_timer,
+        })
+    }
+    
+    /// Start the timer that shows the newest display-paced frame
+    ///
+    /// Emulation-paced frames don't go through the queue, so this finds
+    /// nothing to do in that mode.
+    fn setup_presenter(window: &MainWindow, frames: Arc<FrameQueue>, status: StatusSender) -> slint::Timer {
+        let window_weak = window.as_weak();
+        let timer = slint::Timer::default();
+        timer.start(slint::TimerMode::Repeated, PRESENT_POLL, move || {
+            let Some((rgba_data, screen_size)) = frames.take_latest() else {
+                return;
+            };
+            if let Some(window) = window_weak.upgrade() {
+                window.set_screen_image(frame_loop::screen_image(&rgba_data, screen_size));
+                status.send(StatusUpdate::FramePresented { at: Instant::now() }).ok();
+            }
+        });
+        timer
+    }
+    
+    /// Create the status channel and the timer that shows what arrives on it
+    fn setup_status_bar(window: &MainWindow) -> (StatusSender, slint::Timer) {
+        let (sender, receiver) = mpsc::channel::<StatusUpdate>();
+        let mut model = StatusModel::new(Instant::now());
+        let window_weak = window.as_weak();
+        let timer = slint::Timer::default();
+        timer.start(slint::TimerMode::Repeated, STATUS_REFRESH, move || {
+            let now = Instant::now();
+            for update in receiver.try_iter() {
+                model.apply(update, now);
+            }
+            model.tick(now);
+            if let Some(window) = window_weak.upgrade() {
+                window.set_status_text(model.message_text().into());
+                window.set_stats_text(model.stats_text().into());
+                window.set_rom_text(model.rom_text().into());
+                window.set_lint_text(model.lint_text().into());
+            }
+        });
+        (sender, timer)
+    }
+    
+    /// Status bar name for a ROM: its file name, its mapper if known, and
+    /// whether it runs as PAL
+    fn rom_loaded(path: &Path, system: &mut Core) -> StatusUpdate {
+        let pal = cores::nes(system).is_some_and(|system| system.region() == Region::Pal);
+        let info = cores::nes(system).and_then(|system| system.cartridge_info());
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        StatusUpdate::RomLoaded {
+            name: match info.as_ref().and_then(|info| info.patch.as_ref()) {
+                Some(patch) => format!("{} + {}", name, patch),
+                None => name,
+            },
+            mapper: info.as_ref().map(|info| info.mapper),
+            header_corrected: info.is_some_and(|info| info.header_corrected),
+            pal,
+        }
     }
 
-    fn setup_callbacks(window: &MainWindow, emulator: Arc<Mutex<Option<NesSystem>>>) {
-        // Shared flag to control whether emulation thread is running
-        let running = Arc::new(Mutex::new(false));
-        // Load ROM callback
-        let emulator_clone = emulator.clone();
+    /// Wire up the UI, returning the timer that drives ROM auto-reload
+    fn setup_callbacks(
+        window: &MainWindow,
+        emulator: CoreHandle,
+        frames: Arc<FrameQueue>,
+        status: StatusSender,
+    ) -> slint::Timer {
+        // Whether the frame loop runs, and whether Start resumes or resets
+        let control = Arc::new(RunControl::new());
+        // Player 1's buttons, held from the keyboard
+        let input = Arc::new(PadInput::new());
+        // Overlays, pacing, run-ahead and speed, read every frame
+        let loop_settings = Arc::new(LoopSettings::new());
+        
+        let loop_settings_clone = loop_settings.clone();
+        window.on_sprite_overlay_toggled(move |enabled| {
+            loop_settings_clone.set_sprite_overlay(enabled);
+        });
+        
+        // Global settings and per-game overrides
+        let config = Rc::new(RefCell::new(Config::default()));
+        if let Some(dir) = session::config_dir() {
+            match Config::load(&dir) {
+                Ok(loaded) => *config.borrow_mut() = loaded,
+                Err(e) => {
+                    eprintln!("Failed to read settings: {}", e);
+                    window.set_warning_text(format!("Couldn't read settings, using defaults: {}", e).into());
+                }
+            }
+        }
+        window.set_sprite_overlay(config.borrow().global.sprite_overlay);
+        loop_settings.set_sprite_overlay(config.borrow().global.sprite_overlay);
+        window.set_input_display(config.borrow().global.input_display);
+        loop_settings.set_input_display(config.borrow().global.input_display);
+        Self::apply_display_settings(window, &config.borrow().global);
+        loop_settings.set_pacing(config.borrow().global.pacing);
+        loop_settings.set_run_ahead(config.borrow().global.run_ahead.min(run_ahead::MAX_FRAMES));
+        loop_settings.set_frame_blend(config.borrow().global.frame_blend, config.borrow().global.frame_blend_strength);
+        window.set_auto_reload_rom(config.borrow().global.auto_reload_rom);
+        window.set_keep_state_on_reload(config.borrow().global.keep_state_on_reload);
+        window.set_stereo_separation(config.borrow().global.stereo_separation);
+        window.set_accuracy_profile(accuracy_profile_to_index(config.borrow().global.accuracy));
+        Self::show_recent_roms(window, &config.borrow().recent_roms);
+        let speeds: Vec<i32> = menu::SPEEDS.iter().map(|&speed| speed as i32).collect();
+        window.set_speeds(Rc::new(slint::VecModel::from(speeds)).into());
+        window.set_speed(menu::NORMAL_SPEED as i32);
+        
+        let loop_settings_clone = loop_settings.clone();
+        window.on_speed_changed(move |percent| {
+            loop_settings_clone.set_speed(percent.max(1) as u32);
+        });
+        
+        window.on_screen_rect(|width, height, mode, crop_overscan| {
+            screen_rect(width, height, index_to_scale_mode(mode), crop_overscan)
+        });
+        
+        // Display settings are global, so changing them updates settings.toml
+        let config_clone = config.clone();
         let window_weak = window.as_weak();
+        let loop_settings_clone = loop_settings.clone();
+        let status_clone = status.clone();
+        window.on_display_settings_changed(move || {
+            let Some(window) = window_weak.upgrade() else {
+                return;
+            };
+            let mut config = config_clone.borrow_mut();
+            config.global.scale_mode = index_to_scale_mode(window.get_scale_mode());
+            config.global.crop_overscan = window.get_crop_overscan();
+            config.global.frame_blend = index_to_frame_blend(window.get_frame_blend());
+            loop_settings_clone.set_frame_blend(config.global.frame_blend, config.global.frame_blend_strength);
+            config.global.pacing = index_to_pacing_mode(window.get_pacing_mode());
+            loop_settings_clone.set_pacing(config.global.pacing);
+            config.global.run_ahead = window.get_run_ahead() as u8;
+            loop_settings_clone.set_run_ahead(config.global.run_ahead);
+            if let Some(dir) = session::config_dir() {
+                if let Err(e) = config.save(&dir) {
+                    status_clone.send(StatusUpdate::error(format!("Couldn't save settings: {}", e))).ok();
+                }
+            }
+        });
+        
+        // The input display is a global setting
+        let loop_settings_clone = loop_settings.clone();
+        let config_clone = config.clone();
+        let status_clone = status.clone();
+        window.on_input_display_toggled(move |enabled| {
+            loop_settings_clone.set_input_display(enabled);
+            let mut config = config_clone.borrow_mut();
+            config.global.input_display = enabled;
+            if let Some(dir) = session::config_dir() {
+                if let Err(e) = config.save(&dir) {
+                    status_clone.send(StatusUpdate::error(format!("Couldn't save settings: {}", e))).ok();
+                }
+            }
+        });
+        
+        // Auto-reload settings are global too
+        let config_clone = config.clone();
+        let window_weak = window.as_weak();
+        let status_clone = status.clone();
+        window.on_reload_settings_changed(move || {
+            let Some(window) = window_weak.upgrade() else {
+                return;
+            };
+            let mut config = config_clone.borrow_mut();
+            config.global.auto_reload_rom = window.get_auto_reload_rom();
+            config.global.keep_state_on_reload = window.get_keep_state_on_reload();
+            if let Some(dir) = session::config_dir() {
+                if let Err(e) = config.save(&dir) {
+                    status_clone.send(StatusUpdate::error(format!("Couldn't save settings: {}", e))).ok();
+                }
+            }
+        });
+        
+        // The accuracy profile is global; power-up quirks like the PPU
+        // warm-up show from the next reset
+        let config_clone = config.clone();
+        let window_weak = window.as_weak();
+        let emulator_clone = emulator.clone();
+        let status_clone = status.clone();
+        window.on_accuracy_changed(move || {
+            let Some(window) = window_weak.upgrade() else {
+                return;
+            };
+            let mut config = config_clone.borrow_mut();
+            config.global.accuracy = index_to_accuracy_profile(window.get_accuracy_profile());
+            let flags = accuracy_flags(config.global.accuracy);
+            emulator_clone.call(move |core| {
+                if let Some(system) = core.as_mut().and_then(cores::nes) {
+                    system.set_accuracy_flags(flags);
+                }
+            });
+            if let Some(dir) = session::config_dir() {
+                if let Err(e) = config.save(&dir) {
+                    status_clone.send(StatusUpdate::error(format!("Couldn't save settings: {}", e))).ok();
+                }
+            }
+        });
+        
+        // Stereo separation is global; dragging the slider is heard straight
+        // away, and letting go saves it
+        let emulator_clone = emulator.clone();
+        window.on_stereo_separation_changed(move |separation| {
+            emulator_clone.call(move |core| {
+                if let Some(system) = core.as_mut().and_then(cores::nes) {
+                    system.set_stereo_config(StereoConfig::with_separation(separation));
+                }
+            });
+        });
+        let config_clone = config.clone();
+        let status_clone = status.clone();
+        window.on_stereo_separation_released(move |separation| {
+            let mut config = config_clone.borrow_mut();
+            config.global.stereo_separation = separation;
+            if let Some(dir) = session::config_dir() {
+                if let Err(e) = config.save(&dir) {
+                    status_clone.send(StatusUpdate::error(format!("Couldn't save settings: {}", e))).ok();
+                }
+            }
+        });
+        
+        // Offer to resume the last session if it still restores cleanly
+        let resumable = Rc::new(RefCell::new(None::<(Session, Core)>));
+        if let Some(dir) = session::config_dir() {
+            match Session::load(&dir).and_then(|session| match session {
+                Some(session) => session.restore().map(|system| Some((session, Box::new(system) as Core))),
+                None => Ok(None),
+            }) {
+                Ok(Some(restored)) => {
+                    *resumable.borrow_mut() = Some(restored);
+                    window.set_resume_available(true);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("Couldn't resume last session: {}", e);
+                    window.set_warning_text(format!("Couldn't resume last session: {}", e).into());
+                }
+            }
+        }
+        
+        // Load a ROM, from the file dialog or the recent ROMs
+        let load_rom_path = {
+            let emulator_clone = emulator.clone();
+            let window_weak = window.as_weak();
+            let control_clone = control.clone();
+            let resumable_clone = resumable.clone();
+            let loop_settings_clone = loop_settings.clone();
+            let config_clone = config.clone();
+            let status_clone = status.clone();
+            Rc::new(move |path: PathBuf, patch: Option<PathBuf>| {
+                let mut system = match cores::load(&path, patch.as_deref()) {
+                    Ok(system) => system,
+                    Err(e) => {
+                        status_clone.send(StatusUpdate::error(format!("ROM load failed: {}", e))).ok();
+                        return;
+                    }
+                };
+                println!("ROM loaded successfully!");
+                
+                // Replaces any previous game's overrides
+                let settings = match Self::rom_crc32(&mut system) {
+                    Some(crc) => config_clone.borrow().for_game(crc),
+                    None => config_clone.borrow().global.clone(),
+                };
+                if let Some(window) = window_weak.upgrade() {
+                    Self::apply_settings(&window, &loop_settings_clone, &mut system, &settings);
+                }
+                
+                status_clone.send(Self::rom_loaded(&path, &mut system)).ok();
+                // Savestate commands already sent still go to the previous ROM
+                let loop_settings = loop_settings_clone.clone();
+                emulator_clone.call(move |core| {
+                    *core = Some(system);
+                    loop_settings.restart_picture();
+                });
+                control_clone.set_resumable(false);
+                *resumable_clone.borrow_mut() = None;
+                
+                let mut config = config_clone.borrow_mut();
+                config.add_recent_rom(&path);
+                if let Some(dir) = session::config_dir() {
+                    if let Err(e) = config.save(&dir) {
+                        status_clone.send(StatusUpdate::error(format!("Couldn't save settings: {}", e))).ok();
+                    }
+                }
+                if let Some(window) = window_weak.upgrade() {
+                    window.set_paused(false);
+                    window.set_resume_available(false);
+                    Self::show_recent_roms(&window, &config.recent_roms);
+                    let path_str = path.to_string_lossy().into_owned();
+                    window.set_rom_path(path_str.into());
+                    println!("ROM path set in UI");
+                }
+            })
+        };
+        
+        // Load ROM callback
+        let load_rom_clone = load_rom_path.clone();
+        let status_clone = status.clone();
         window.on_load_rom(move || {
             println!("Load ROM button clicked");
             
-            match native_dialog::FileDialog::new()
-                .add_filter("NES ROM", &["nes"])
-                .show_open_single_file()
-            {
+            let dialog = cores::CORES
+                .iter()
+                .fold(native_dialog::FileDialog::new(), |dialog, core| dialog.add_filter(core.name, core.extensions));
+            match dialog.show_open_single_file() {
                 Ok(Some(path)) => {
                     println!("Selected file: {:?}", path);
-                    
-                    let mut emu_lock = emulator_clone.lock().unwrap();
-                    match NesSystem::new(&path) {
-                        Ok(system) => {
-                            println!("ROM loaded successfully!");
-                            *emu_lock = Some(system);
-                            if let Some(window) = window_weak.upgrade() {
-                                let path_str = path.to_string_lossy().into_owned();
-                                window.set_rom_path(path_str.into());
-                                println!("ROM path set in UI");
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("Failed to load ROM: {:?}", e);
-                        }
-                    }
+                    load_rom_clone(path, None);
                 }
                 Ok(None) => {
                     println!("File dialog cancelled");
                 }
                 Err(e) => {
-                    eprintln!("File dialog error: {:?}", e);
+                    status_clone.send(StatusUpdate::error(format!("Couldn't open the file dialog: {}", e))).ok();
+                }
+            }
+        });
+        
+        // File > Load ROM with Patch: the ROM, then the patch for it
+        let load_rom_clone = load_rom_path.clone();
+        let status_clone = status.clone();
+        window.on_load_rom_with_patch(move || {
+            let rom_dialog = cores::CORES
+                .iter()
+                .fold(native_dialog::FileDialog::new(), |dialog, core| dialog.add_filter(core.name, core.extensions));
+            let picked = rom_dialog.show_open_single_file().and_then(|rom| match rom {
+                Some(rom) => native_dialog::FileDialog::new()
+                    .add_filter("ROM patch", &emu_nes::patch::EXTENSIONS)
+                    .set_location(rom.parent().unwrap_or(Path::new(".")))
+                    .show_open_single_file()
+                    .map(|patch| patch.map(|patch| (rom, patch))),
+                None => Ok(None),
+            });
+            match picked {
+                Ok(Some((rom, patch))) => load_rom_clone(rom, Some(patch)),
+                Ok(None) => {}
+                Err(e) => {
+                    status_clone.send(StatusUpdate::error(format!("Couldn't open the file dialog: {}", e))).ok();
                 }
             }
         });
+        
+        // File > Recent ROMs
+        let config_clone = config.clone();
+        window.on_open_recent(move |index| {
+            let path = usize::try_from(index).ok().and_then(|index| config_clone.borrow().recent_roms.get(index).cloned());
+            if let Some(path) = path {
+                load_rom_path(path, None);
+            }
+        });
 
         // Start emulation callback
-        let emulator_clone = emulator.clone();
+        let emulation = EmulationLoop {
+            emulator: emulator.clone(),
+            control: control.clone(),
+            input: input.clone(),
+            settings: loop_settings.clone(),
+            frames,
+            status: status.clone(),
+            audio: true,
+        };
         let window_weak = window.as_weak();
-        let running_clone = running.clone();
+        let status_clone = status.clone();
         window.on_start_emulation(move || {
             println!("Start emulation clicked");
             
-            // Check if ROM is loaded and reset it
-            {
-                let mut emu_lock = emulator_clone.lock().unwrap();
-                if let Some(ref mut system) = *emu_lock {
+            // Check if ROM is loaded and reset it (unless unpausing a resumed session)
+            let resume = emulation.control.state() == RunState::Paused;
+            let loaded = emulation.emulator.call(move |core| {
+                let Some(system) = core else {
+                    return false;
+                };
+                if resume {
+                    println!("Continuing resumed session");
+                } else {
                     system.reset();
                     println!("Emulator reset - starting from beginning");
-                } else {
-                    println!("No ROM loaded, cannot start");
-                    return;
                 }
+                true
+            });
+            if !loaded.wait().unwrap_or(false) {
+                status_clone.send(StatusUpdate::info("Load a ROM first")).ok();
+                return;
             }
 
-            // Check if already running
-            {
-                let mut running_lock = running_clone.lock().unwrap();
-                if *running_lock {
-                    println!("Emulation already running");
-                    return;
-                }
-                *running_lock = true;
+            let screen = WindowScreen {
+                window: window_weak.clone(),
+                status: status_clone.clone(),
+            };
+            if emulation.clone().spawn(screen).is_none() {
+                println!("Emulation already running");
+                return;
             }
 
-            println!("Starting emulation thread...");
-
             // Set running state
             if let Some(window) = window_weak.upgrade() {
                 window.set_emulator_running(true);
+                window.set_paused(false);
+                window.set_warning_text("".into());
             }
+        });
 
-            let emulator_thread = emulator_clone.clone();
-            let window_weak_clone = window_weak.clone();
-            let running_thread = running_clone.clone();
-
-            thread::spawn(move || {
-                println!("Emulation thread started");
-                
-                // Initialize audio in emulation thread (cpal Stream is not Send)
-                let audio = match AudioSystem::new() {
-                    Ok(audio_system) => {
-                        println!("✓ Audio system initialized");
-                        Some(audio_system)
-                    }
+        // Live CHR reloading (developer feature)
+        #[cfg(feature = "chr-watch")]
+        {
+            let emulator_clone = emulator.clone();
+            let window_weak = window.as_weak();
+            let watcher = Rc::new(RefCell::new(None::<crate::chr_watch::ChrWatcher>));
+            let status_clone = status.clone();
+            window.set_chr_watch_available(true);
+            window.on_watch_chr(move || {
+                let path = match native_dialog::FileDialog::new()
+                    .add_filter("CHR data", &["chr"])
+                    .show_open_single_file()
+                {
+                    Ok(Some(path)) => path,
+                    Ok(None) => return,
                     Err(e) => {
-                        eprintln!("⚠ Failed to initialize audio: {}", e);
-                        eprintln!("  Continuing without audio...");
-                        None
+                        status_clone.send(StatusUpdate::error(format!("Couldn't open the file dialog: {}", e))).ok();
+                        return;
                     }
                 };
                 
-                let target_fps = 60.0;
-                let frame_duration = Duration::from_secs_f64(1.0 / target_fps);
-                let mut frame_count = 0;
-                let mut fps_timer = Instant::now();
-                
-                // Audio sampling: collect samples throughout frame execution
-                let mut audio_buffer = Vec::with_capacity(SAMPLES_PER_FRAME);
-
-                loop {
-                    // Check if we should continue running
-                    {
-                        let running_lock = running_thread.lock().unwrap();
-                        if !*running_lock {
-                            println!("Emulation stopped by user");
-                            // Fade out audio to prevent pop
-                            if let Some(ref audio_system) = audio {
-                                audio_system.fade_out();
-                            }
-                            break;
-                        }
-                    }
-                    
-                    let frame_start = Instant::now();
-
-                    // Run one frame, collect audio samples, and get framebuffer
-                    let (should_continue, rgba_data) = {
-                        let mut emu_lock = emulator_thread.lock().unwrap();
-                        if let Some(ref mut system) = *emu_lock {
-                            audio_buffer.clear();
-                            
-                            // Run for one frame (29780 CPU cycles ≈ 1/60th second)
-                            // We need 735 audio samples, so run in 735 chunks
-                            const CYCLES_PER_FRAME: u64 = 29780;
-                            let cycles_per_sample = CYCLES_PER_FRAME / SAMPLES_PER_FRAME as u64; // ~40 cycles
-                            
-                            for i in 0..SAMPLES_PER_FRAME {
-                                // Run cycles for this sample period
-                                let cycles_to_run = if i == SAMPLES_PER_FRAME - 1 {
-                                    // Last sample - run remaining cycles
-                                    CYCLES_PER_FRAME - (cycles_per_sample * i as u64)
-                                } else {
-                                    cycles_per_sample
-                                };
-                                
-                                if let Err(e) = system.run_cycles(cycles_to_run) {
-                                    eprintln!("Emulation error: {:?}", e);
-                                    return;
-                                }
-                                
-                                // Sample audio after running cycles
-                                audio_buffer.push(system.audio_sample());
-                            }
-
-                            // Convert framebuffer to image
-                            let framebuffer = system.framebuffer();
-                            let rgba_data = Self::framebuffer_to_rgba(framebuffer);
-                            
-                            (true, rgba_data)
-                        } else {
-                            println!("Emulator stopped");
-                            return;
-                        }
-                    };
-
-                    if !should_continue {
-                        break;
-                    }
-                    
-                    // Send audio samples to audio thread
-                    if let Some(ref audio_system) = audio {
-                        audio_system.send_samples(&audio_buffer);
-                    }
-
-                    // Update display on UI thread
-                    let window_weak_update = window_weak_clone.clone();
-                    slint::invoke_from_event_loop(move || {
-                        if let Some(window) = window_weak_update.upgrade() {
-                            let buffer = slint::SharedPixelBuffer::clone_from_slice(
-                                &rgba_data,
-                                256,
-                                240,
-                            );
-                            let image = slint::Image::from_rgba8(buffer);
-                            window.set_screen_image(image);
-                        }
-                    }).ok();
-
-                    // FPS calculation
-                    frame_count += 1;
-                    
-                    // Debug: log frame count every 60 frames
-                    if frame_count % 60 == 0 {
-                        trace!("Frame {}", frame_count);
-                    }
-                    
-                    if fps_timer.elapsed() >= Duration::from_secs(1) {
-                        let fps = frame_count;
-                        let window_weak_fps = window_weak_clone.clone();
-                        slint::invoke_from_event_loop(move || {
-                            if let Some(window) = window_weak_fps.upgrade() {
-                                window.set_fps_text(format!("FPS: {}", fps).into());
-                            }
-                        }).ok();
-                        frame_count = 0;
-                        fps_timer = Instant::now();
-                    }
-
-                    // Frame timing
-                    let elapsed = frame_start.elapsed();
-                    if elapsed < frame_duration {
-                        thread::sleep(frame_duration - elapsed);
-                    }
+                // Dropping the old watcher stops it
+                let new_watcher = crate::chr_watch::ChrWatcher::spawn(path, emulator_clone.clone());
+                if let Some(window) = window_weak.upgrade() {
+                    window.set_chr_watch_path(new_watcher.path().to_string_lossy().into_owned().into());
                 }
+                *watcher.borrow_mut() = Some(new_watcher);
+            });
+        }
 
-                println!("Emulation thread ended");
-                
-                // Clear screen and running state when stopped
-                slint::invoke_from_event_loop(move || {
-                    if let Some(window) = window_weak_clone.upgrade() {
-                        window.set_emulator_running(false);
-                        window.set_fps_text("FPS: 0".into());
-                        
-                        // Create a black screen
-                        let black_screen = vec![0u8; 256 * 240 * 4];
-                        let buffer = slint::SharedPixelBuffer::clone_from_slice(
-                            &black_screen,
-                            256,
-                            240,
-                        );
-                        let image = slint::Image::from_rgba8(buffer);
-                        window.set_screen_image(image);
-                    }
-                }).ok();
+        // Reload the ROM when it's rebuilt on disk
+        let emulator_clone = emulator.clone();
+        let window_weak = window.as_weak();
+        let control_clone = control.clone();
+        let loop_settings_clone = loop_settings.clone();
+        let config_clone = config.clone();
+        let status_clone = status.clone();
+        let mut watch = None::<RomWatch>;
+        let reload_timer = slint::Timer::default();
+        reload_timer.start(slint::TimerMode::Repeated, rom_watch::POLL_INTERVAL, move || {
+            let Some(window) = window_weak.upgrade() else {
+                return;
+            };
+            let rom_path = window.get_rom_path();
+            if !window.get_auto_reload_rom() || rom_path.is_empty() {
+                watch = None;
+                return;
+            }
+            let rom_path = Path::new(rom_path.as_str());
+            // A newly loaded ROM starts a fresh watch from what's on disk now
+            let watch = match watch {
+                Some(ref mut watch) if watch.path() == rom_path => watch,
+                _ => watch.insert(RomWatch::new(rom_path.to_path_buf())),
+            };
+            let data = match watch.poll() {
+                None => return,
+                Some(Ok(data)) => data,
+                Some(Err(e)) => {
+                    status_clone.send(StatusUpdate::error(format!("Couldn't read the rebuilt ROM: {}", e))).ok();
+                    return;
+                }
+            };
+            
+            // A bad build leaves the running game alone
+            let patched = cores::patch_for(rom_path, None)
+                .and_then(|patch| cores::from_bytes(rom_path, &data, patch.as_ref()));
+            let mut system = match patched {
+                Ok(system) => system,
+                Err(e) => {
+                    status_clone.send(StatusUpdate::error(format!("Reload failed, still running the old ROM: {}", e))).ok();
+                    return;
+                }
+            };
+            let config = config_clone.borrow();
+            let settings = match Self::rom_crc32(&mut system) {
+                Some(crc) => config.for_game(crc),
+                None => config.global.clone(),
+            };
+            Self::apply_settings(&window, &loop_settings_clone, &mut system, &settings);
+            
+            // The swap lands between frames, so a running game carries
+            // straight on with the new cartridge
+            let loaded = Self::rom_loaded(rom_path, &mut system);
+            let keep_state = window.get_keep_state_on_reload();
+            let loop_settings = loop_settings_clone.clone();
+            let transplant = emulator_clone.call(move |core| {
+                let transplant = match (core.as_mut().and_then(cores::nes), cores::nes(&mut system)) {
+                    (Some(old), Some(new)) if keep_state => Some(new.transplant_state(&old.save_state())),
+                    _ => None,
+                };
+                *core = Some(system);
+                loop_settings.restart_picture();
+                transplant
             });
+            let kept_state = match transplant.wait() {
+                Ok(Some(Ok(()))) => true,
+                Ok(Some(Err(e))) => {
+                    status_clone.send(StatusUpdate::warning(format!("Couldn't keep the state, power-cycling: {}", e))).ok();
+                    false
+                }
+                _ => false,
+            };
+            status_clone.send(loaded).ok();
+            if !kept_state {
+                control_clone.set_resumable(false);
+                window.set_paused(false);
+            }
+            let name = rom_path.file_name().unwrap_or_default().to_string_lossy();
+            status_clone.send(StatusUpdate::info(if kept_state {
+                format!("Reloaded {}, state kept", name)
+            } else {
+                format!("Reloaded {}", name)
+            })).ok();
         });
 
         // Stop emulator callback
         let emulator_clone = emulator.clone();
         let window_weak = window.as_weak();
-        let running_clone = running.clone();
+        let control_clone = control.clone();
+        let config_clone = config.clone();
+        let status_clone = status.clone();
         window.on_stop_emulation(move || {
             println!("Stop emulation clicked");
             
-            // Set running flag to false to stop the emulation thread
-            {
-                let mut running_lock = running_clone.lock().unwrap();
-                *running_lock = false;
-            }
+            // The emulation thread ends before its next frame
+            control_clone.stop();
             
-            // Reset the emulator state (so next Start begins fresh)
-            {
-                let mut emu_lock = emulator_clone.lock().unwrap();
-                if let Some(ref mut system) = *emu_lock {
-                    system.reset();
-                    println!("Emulator reset to initial state");
+            // Keep the game's progress in the auto slot, after anything
+            // already sent, then reset it (so next Start begins fresh)
+            let auto_slot = window_weak.upgrade().filter(|window| !window.get_rom_path().is_empty()).map(|window| {
+                slots::slot_path(
+                    Path::new(window.get_rom_path().as_str()),
+                    config_clone.borrow().global.state_dir.as_deref(),
+                    slots::AUTO_SLOT,
+                )
+            });
+            let status = status_clone.clone();
+            let black_screen = emulator_clone.call(move |core| {
+                let system = core.as_mut()?;
+                if let Some(path) = auto_slot {
+                    let outcome = Self::run_state_command(system, StateCommand::Save {
+                        slot: slots::AUTO_SLOT,
+                        path,
+                    });
+                    status.send(Self::state_status_update(outcome)).ok();
                 }
+                system.reset();
+                println!("Emulator reset to initial state");
+                Some((frame_loop::black_screen(&**system), system.screen_size()))
+            });
+            
+            // Clear the screen
+            if let (Some(window), Ok(Some((black_screen, screen_size)))) = (window_weak.upgrade(), black_screen.wait()) {
+                window.set_screen_image(frame_loop::screen_image(&black_screen, screen_size));
             }
             
-            // Clear the screen and update UI state
+            // Update UI state
             if let Some(window) = window_weak.upgrade() {
                 window.set_emulator_running(false);
-                
-                // Create a black screen
-                let black_screen = vec![0u8; 256 * 240 * 4]; // All black pixels (RGBA)
-                let buffer = slint::SharedPixelBuffer::clone_from_slice(
-                    &black_screen,
-                    256,
-                    240,
-                );
-                let image = slint::Image::from_rgba8(buffer);
-                window.set_screen_image(image);
-                window.set_fps_text("FPS: 0".into());
+                window.set_paused(false);
             }
+            status_clone.send(StatusUpdate::Stopped).ok();
             println!("Emulation stopped and reset (ROM still loaded)");
         });
+        
+        // Pause: stop the emulation thread without resetting, so Start resumes
+        let window_weak = window.as_weak();
+        let control_clone = control.clone();
+        let status_clone = status.clone();
+        window.on_pause_emulation(move || {
+            // The thread leaves the picture up as it stops
+            if !control_clone.pause() {
+                return;
+            }
+            if let Some(window) = window_weak.upgrade() {
+                window.set_paused(true);
+            }
+            status_clone.send(StatusUpdate::info("Paused")).ok();
+        });
+        
+        // Soft reset and power cycle, carried on from wherever the game is
+        for power_cycle in [false, true] {
+            let emulator_clone = emulator.clone();
+            let loop_settings_clone = loop_settings.clone();
+            let status_clone = status.clone();
+            let handler = move || {
+                let loop_settings = loop_settings_clone.clone();
+                emulator_clone.call(move |core| {
+                    let Some(system) = core.as_mut() else {
+                        return;
+                    };
+                    match cores::nes(system) {
+                        Some(system) if power_cycle => system.power_cycle(),
+                        _ => system.reset(),
+                    }
+                    loop_settings.restart_picture();
+                });
+                status_clone.send(StatusUpdate::info(if power_cycle { "Power cycled" } else { "Reset" })).ok();
+            };
+            if power_cycle {
+                window.on_power_cycle(handler);
+            } else {
+                window.on_soft_reset(handler);
+            }
+        }
+        
+        // Save the picture on screen, without any overlays
+        let emulator_clone = emulator.clone();
+        let status_clone = status.clone();
+        window.on_take_screenshot(move || {
+            let picture = emulator_clone.call(|core| core.as_mut().map(|system| (system.framebuffer_rgba(), system.screen_size())));
+            let Ok(Some((rgba_data, screen_size))) = picture.wait() else {
+                return;
+            };
+            let path = match native_dialog::FileDialog::new()
+                .set_filename("screenshot.ppm")
+                .add_filter("PPM image", &["ppm"])
+                .show_save_single_file()
+            {
+                Ok(Some(path)) => path,
+                Ok(None) => return,
+                Err(e) => {
+                    status_clone.send(StatusUpdate::error(format!("Couldn't open the file dialog: {}", e))).ok();
+                    return;
+                }
+            };
+            let update = match std::fs::write(&path, frame_loop::ppm(&rgba_data, screen_size)) {
+                Ok(()) => StatusUpdate::info(format!("Saved screenshot to {}", path.display())),
+                Err(e) => StatusUpdate::error(format!("Couldn't save the screenshot: {}", e)),
+            };
+            status_clone.send(update).ok();
+        });
 
-        // Keyboard press handler
+        // Resume last session callback
         let emulator_clone = emulator.clone();
-        window.on_key_pressed(move |key| {
-            let mut emu_lock = emulator_clone.lock().unwrap();
-            if let Some(ref mut system) = *emu_lock {
-                let controller = system.controller1().state();
+        let window_weak = window.as_weak();
+        let control_clone = control.clone();
+        let loop_settings_clone = loop_settings.clone();
+        let status_clone = status.clone();
+        window.on_resume_session(move || {
+            let Some((session, mut system)) = resumable.borrow_mut().take() else {
+                return;
+            };
+            println!("Resuming session: {:?}", session.rom_path);
+            
+            // Start paused on the restored frame; audio starts with the emulation thread on unpause
+            let image = frame_loop::screen_image(&system.framebuffer_rgba(), system.screen_size());
+            if let Some(window) = window_weak.upgrade() {
+                Self::apply_settings(&window, &loop_settings_clone, &mut system, &session.settings);
+            }
+            status_clone.send(Self::rom_loaded(&session.rom_path, &mut system)).ok();
+            emulator_clone.call(move |core| *core = Some(system));
+            control_clone.set_resumable(true);
+            
+            if let Some(window) = window_weak.upgrade() {
+                window.set_rom_path(session.rom_path.to_string_lossy().into_owned().into());
+                window.set_paused(true);
+                window.set_resume_available(false);
+                window.set_screen_image(image);
+            }
+        });
+        
+        // Save the session when the window closes or File > Exit is picked
+        let emulator_clone = emulator.clone();
+        let window_weak = window.as_weak();
+        let control_clone = control.clone();
+        let config_clone = config.clone();
+        let save_session = Rc::new(move || {
+            control_clone.stop();
+            
+            let Some(window) = window_weak.upgrade() else {
+                return;
+            };
+            let rom_path = window.get_rom_path();
+            let Some(dir) = session::config_dir().filter(|_| !rom_path.is_empty()) else {
+                return;
+            };
+            
+            // Sessions hold NES savestates
+            let mut settings = Settings {
+                sprite_overlay: window.get_sprite_overlay(),
+                input_display: window.get_input_display(),
+                hang_detection: false,
+                scale_mode: index_to_scale_mode(window.get_scale_mode()),
+                crop_overscan: window.get_crop_overscan(),
+                frame_blend: index_to_frame_blend(window.get_frame_blend()),
+                frame_blend_strength: config_clone.borrow().global.frame_blend_strength,
+                pacing: index_to_pacing_mode(window.get_pacing_mode()),
+                run_ahead: window.get_run_ahead() as u8,
+                state_dir: config_clone.borrow().global.state_dir.clone(),
+                auto_reload_rom: window.get_auto_reload_rom(),
+                keep_state_on_reload: window.get_keep_state_on_reload(),
+                stereo_separation: window.get_stereo_separation(),
+                accuracy: index_to_accuracy_profile(window.get_accuracy_profile()),
+                tv_system: None,
+            };
+            let rom_path = PathBuf::from(rom_path.as_str());
+            let session = emulator_clone.call(move |core| {
+                let system = core.as_mut().and_then(cores::nes)?;
+                settings.hang_detection = system.hang_detection();
+                // The savestate only loads into a console of the same region
+                settings.tv_system = Some(if system.region() == Region::Pal { TvSystem::Pal } else { TvSystem::Ntsc });
+                Some(Session::capture(system, rom_path, settings))
+            });
+            if let Ok(Some(session)) = session.wait() {
+                match session.save(&dir) {
+                    Ok(()) => println!("Session saved to {:?}", dir),
+                    Err(e) => eprintln!("Failed to save session: {}", e),
+                }
+            }
+        });
+        let save_session_clone = save_session.clone();
+        window.window().on_close_requested(move || {
+            save_session_clone();
+            slint::CloseRequestResponse::HideWindow
+        });
+        window.on_exit(move || {
+            save_session();
+            slint::quit_event_loop().ok();
+        });
+
+        // Quick save (F5) and load (F7) to the selected slot
+        for load in [false, true] {
+            let emulator_clone = emulator.clone();
+            let window_weak = window.as_weak();
+            let control_clone = control.clone();
+            let loop_settings_clone = loop_settings.clone();
+            let config_clone = config.clone();
+            let status_clone = status.clone();
+            let handler = move || {
+                let Some(window) = window_weak.upgrade() else {
+                    return;
+                };
+                let rom_path = window.get_rom_path();
+                if rom_path.is_empty() {
+                    return;
+                }
+                let slot = window.get_state_slot() as u8;
+                let path = slots::slot_path(Path::new(rom_path.as_str()), config_clone.borrow().global.state_dir.as_deref(), slot);
+                let command = if load {
+                    StateCommand::Load { slot, path }
+                } else {
+                    StateCommand::Save { slot, path }
+                };
                 
-                match key.as_str() {
-                    "↑" | "w" | "W" => controller.press(Button::UP),
-                    "↓" | "s" | "S" => controller.press(Button::DOWN),
-                    "←" | "a" | "A" => controller.press(Button::LEFT),
-                    "→" | "d" | "D" => controller.press(Button::RIGHT),
-                    "z" | "Z" => controller.press(Button::A),
-                    "x" | "X" => controller.press(Button::B),
-                    "\n" | "\r" => controller.press(Button::START),
-                    " " => controller.press(Button::SELECT),
-                    _ => {}
+                // Runs at the service's next frame boundary
+                let status = status_clone.clone();
+                let loop_settings = loop_settings_clone.clone();
+                let loaded = emulator_clone.call(move |core| {
+                    let system = core.as_mut()?;
+                    let outcome = Self::run_state_command(system, command);
+                    let succeeded = outcome.is_ok();
+                    if succeeded && load {
+                        loop_settings.restart_picture();
+                    }
+                    status.send(Self::state_status_update(outcome)).ok();
+                    (succeeded && load).then(|| (system.framebuffer_rgba(), system.screen_size()))
+                });
+                
+                // A running game just carries on from the loaded state
+                if control_clone.state() == RunState::Running {
+                    return;
+                }
+                if let Ok(Some((rgba_data, screen_size))) = loaded.wait() {
+                    // Show the loaded frame and continue from it on Start
+                    window.set_screen_image(frame_loop::screen_image(&rgba_data, screen_size));
+                    control_clone.set_resumable(true);
+                    window.set_paused(true);
                 }
+            };
+            if load {
+                window.on_quick_load(handler);
+            } else {
+                window.on_quick_save(handler);
+            }
+        }
+        
+        // Input macros, played on top of the keyboard
+        let emulator_clone = emulator.clone();
+        let status_clone = status.clone();
+        window.on_run_macro(move || {
+            let path = match native_dialog::FileDialog::new()
+                .add_filter("Input macro", &["macro"])
+                .show_open_single_file()
+            {
+                Ok(Some(path)) => path,
+                Ok(None) => return,
+                Err(e) => {
+                    status_clone.send(StatusUpdate::error(format!("Couldn't open the file dialog: {}", e))).ok();
+                    return;
+                }
+            };
+            let schedule = match std::fs::read_to_string(&path).map_err(EmulatorError::from).and_then(|text| InputSchedule::parse(&text)) {
+                Ok(schedule) => schedule,
+                Err(e) => {
+                    status_clone.send(StatusUpdate::error(format!("Couldn't load {}: {}", path.display(), e))).ok();
+                    return;
+                }
+            };
+            let frames = schedule.len();
+            let attached = emulator_clone.call(move |core| {
+                core.as_mut()
+                    .and_then(cores::nes)
+                    .map(|system| system.attach_input_script(schedule, ScriptMode::Merge))
+                    .is_some()
+            });
+            if !attached.wait().unwrap_or(false) {
+                return;
             }
+            status_clone.send(StatusUpdate::info(format!("Playing {} ({} frames)", path.display(), frames))).ok();
         });
 
-        // Keyboard release handler
+        // Game settings dialog
         let emulator_clone = emulator.clone();
-        window.on_key_released(move |key| {
-            let mut emu_lock = emulator_clone.lock().unwrap();
-            if let Some(ref mut system) = *emu_lock {
-                let controller = system.controller1().state();
+        let window_weak = window.as_weak();
+        let loop_settings_clone = loop_settings.clone();
+        let status_clone = status.clone();
+        window.on_open_game_settings(move || {
+            let header = emulator_clone.call(|core| {
+                let system = core.as_mut()?;
+                let crc = Self::rom_crc32(system)?;
+                Some((crc, cores::nes(system)?.cartridge_info()?.region))
+            });
+            let Ok(Some((crc, header_region))) = header.wait() else {
+                return;
+            };
+            let Some(window) = window_weak.upgrade() else {
+                return;
+            };
+            
+            let dialog = GameSettingsDialog::new().unwrap();
+            let overrides = config.borrow().per_game.get(&crate::settings::game_key(crc)).cloned().unwrap_or_default();
+            let rom_path = window.get_rom_path();
+            let name = std::path::Path::new(rom_path.as_str())
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            dialog.set_game_name(format!("{} ({})", name, crate::settings::game_key(crc)).into());
+            dialog.set_global_sprite_overlay(config.borrow().global.sprite_overlay);
+            dialog.set_global_hang_detection(config.borrow().global.hang_detection);
+            dialog.set_sprite_overlay_choice(Self::override_to_choice(overrides.sprite_overlay));
+            dialog.set_hang_detection_choice(Self::override_to_choice(overrides.hang_detection));
+            dialog.set_header_tv_system(if header_region == Region::Pal { "PAL" } else { "NTSC" }.into());
+            dialog.set_tv_system_choice(Self::tv_system_to_choice(overrides.tv_system));
+            
+            let dialog_weak = dialog.as_weak();
+            dialog.on_cancel(move || {
+                if let Some(dialog) = dialog_weak.upgrade() {
+                    dialog.hide().ok();
+                }
+            });
+            
+            let dialog_weak = dialog.as_weak();
+            let config = config.clone();
+            let emulator_clone = emulator_clone.clone();
+            let window_weak = window_weak.clone();
+            let loop_settings_clone = loop_settings_clone.clone();
+            let status_clone = status_clone.clone();
+            dialog.on_save(move || {
+                let Some(dialog) = dialog_weak.upgrade() else {
+                    return;
+                };
+                let overrides = GameOverrides {
+                    sprite_overlay: Self::choice_to_override(dialog.get_sprite_overlay_choice()),
+                    hang_detection: Self::choice_to_override(dialog.get_hang_detection_choice()),
+                    tv_system: Self::choice_to_tv_system(dialog.get_tv_system_choice()),
+                };
+                config.borrow_mut().set_overrides(crc, overrides);
+                if let Some(dir) = session::config_dir() {
+                    if let Err(e) = config.borrow().save(&dir) {
+                        status_clone.send(StatusUpdate::error(format!("Couldn't save settings: {}", e))).ok();
+                    }
+                }
                 
-                match key.as_str() {
-                    "↑" | "w" | "W" => controller.release(Button::UP),
-                    "↓" | "s" | "S" => controller.release(Button::DOWN),
-                    "←" | "a" | "A" => controller.release(Button::LEFT),
-                    "→" | "d" | "D" => controller.release(Button::RIGHT),
-                    "z" | "Z" => controller.release(Button::A),
-                    "x" | "X" => controller.release(Button::B),
-                    "\n" | "\r" => controller.release(Button::START),
-                    " " => controller.release(Button::SELECT),
-                    _ => {}
+                // Apply straight away if the same ROM is still loaded
+                let settings = config.borrow().for_game(crc);
+                let game_settings = settings.clone();
+                let rom_path = window_weak.upgrade().map(|window| PathBuf::from(window.get_rom_path().as_str()));
+                let applied = emulator_clone.call(move |core| {
+                    let system = core.as_mut()?;
+                    if Self::rom_crc32(system) != Some(crc) {
+                        return None;
+                    }
+                    let region = cores::nes(system).map(|system| system.region());
+                    Self::configure_system(system, &game_settings);
+                    let power_cycled = cores::nes(system).map(|system| system.region()) != region;
+                    let loaded = rom_path.map(|path| Self::rom_loaded(&path, system));
+                    Some((power_cycled, loaded))
+                });
+                if let (Some(window), Ok(Some((power_cycled, loaded)))) = (window_weak.upgrade(), applied.wait()) {
+                    Self::show_settings(&window, &loop_settings_clone, &settings);
+                    if power_cycled {
+                        status_clone.send(StatusUpdate::info("Power cycled with the new TV system")).ok();
+                    }
+                    if let Some(loaded) = loaded {
+                        status_clone.send(loaded).ok();
+                    }
                 }
+                dialog.hide().ok();
+            });
+            
+            dialog.show().unwrap();
+        });
+
+        // Keyboard press handler; the frame loop picks the buttons up
+        // before its next frame
+        let input_clone = input.clone();
+        let window_weak = window.as_weak();
+        window.on_key_pressed(move |key| {
+            // Number keys pick the savestate slot
+            if let Ok(slot @ 1..=9) = key.parse::<i32>() {
+                if let Some(window) = window_weak.upgrade() {
+                    window.set_state_slot(slot);
+                }
+                return;
             }
+            
+            input_clone.set_key(&key, true);
+        });
+
+        // Keyboard release handler
+        window.on_key_released(move |key| {
+            input.set_key(&key, false);
         });
         
         // Memory viewer callback
@@ -467,14 +1182,18 @@ impl EmulatorApp {
             let current_region = Rc::new(RefCell::new(0i32));
             
             // Initial population
-            {
-                let mut emu_lock = emulator_clone.lock().unwrap();
-                if let Some(ref mut system) = *emu_lock {
-                    let memory_text = Self::format_memory_region(system, 0);
+            let contents = emulator_clone.call(|core| {
+                let system = core.as_mut().ok_or("No ROM loaded")?;
+                let system = cores::nes(system).ok_or("Not available for this system")?;
+                Ok::<_, &str>((Self::format_memory_region(system, 0), system.profiling()))
+            });
+            match contents.wait() {
+                Ok(Ok((memory_text, profiling))) => {
                     viewer.set_memory_text(memory_text.into());
-                } else {
-                    viewer.set_memory_text("No ROM loaded".into());
+                    viewer.set_profiling(profiling);
                 }
+                Ok(Err(reason)) => viewer.set_memory_text(reason.into()),
+                Err(e) => viewer.set_memory_text(e.to_string().into()),
             }
             
             // Handle region changes
@@ -483,15 +1202,24 @@ impl EmulatorApp {
             let current_region_clone = current_region.clone();
             viewer.on_region_changed(move |index| {
                 *current_region_clone.borrow_mut() = index;
-                let mut emu_lock = emulator_region.lock().unwrap();
-                if let Some(ref mut system) = *emu_lock {
-                    let memory_text = Self::format_memory_region(system, index);
-                    if let Some(v) = viewer_weak_region.upgrade() {
-                        v.set_memory_text(memory_text.into());
-                    }
+                let memory_text = emulator_region.call(move |core| {
+                    core.as_mut().and_then(cores::nes).map(|system| Self::format_memory_region(system, index))
+                });
+                if let (Ok(Some(memory_text)), Some(v)) = (memory_text.wait(), viewer_weak_region.upgrade()) {
+                    v.set_memory_text(memory_text.into());
                 }
             });
             
+            // Profiling only costs anything while the viewer asks for it
+            let emulator_profile = emulator_clone.clone();
+            viewer.on_profiling_toggled(move |enabled| {
+                emulator_profile.call(move |core| {
+                    if let Some(system) = core.as_mut().and_then(cores::nes) {
+                        system.enable_profiling(enabled);
+                    }
+                });
+            });
+            
             let viewer_weak = viewer.as_weak();
             let emulator_viewer = emulator_clone.clone();
             
@@ -501,19 +1229,30 @@ impl EmulatorApp {
             
             timer.borrow().start(slint::TimerMode::Repeated, std::time::Duration::from_millis(100), move || {
                 if viewer_weak.upgrade().is_none() {
-                    // Viewer closed, stop timer
+                    // Viewer closed, stop timer and the profiling it showed
                     if let Some(t) = timer_weak.upgrade() {
                         t.borrow().stop();
                     }
+                    emulator_viewer.call(|core| {
+                        if let Some(system) = core.as_mut().and_then(cores::nes) {
+                            system.enable_profiling(false);
+                        }
+                    });
                     return;
                 }
                 
-                let mut emu_lock = emulator_viewer.lock().unwrap();
-                if let Some(ref mut system) = *emu_lock {
-                    let region = *current_region.borrow();
-                    let memory_text = Self::format_memory_region(system, region);
-                    if let Some(v) = viewer_weak.upgrade() {
-                        v.set_memory_text(memory_text.into());
+                let region = *current_region.borrow();
+                let contents = emulator_viewer.call(move |core| {
+                    let system = core.as_mut().and_then(cores::nes)?;
+                    let profile = system.profiling().then(|| system.profile_report().to_string());
+                    Some((Self::format_memory_region(system, region), profile))
+                });
+                if let (Ok(Some((memory_text, profile))), Some(v)) = (contents.wait(), viewer_weak.upgrade()) {
+                    v.set_memory_text(memory_text.into());
+                    // A newly loaded ROM starts with profiling off
+                    v.set_profiling(profile.is_some());
+                    if let Some(profile) = profile {
+                        v.set_profile_text(profile.into());
                     }
                 }
             });
@@ -524,6 +1263,543 @@ impl EmulatorApp {
             // Keep timer alive by forgetting the Rc
             std::mem::forget(timer);
         });
+        
+        // Debugger callback
+        let emulator_clone = emulator.clone();
+        let status_clone = status.clone();
+        window.on_open_debugger(move || {
+            let view = DebuggerWindow::new().unwrap();
+            // The rows on screen, to map clicks back to addresses
+            let rows = Rc::new(RefCell::new(Vec::new()));
+            // The registers last shown; while paused the view only changes with them
+            let shown = Rc::new(RefCell::new(None::<RegisterView>));
+            
+            emulator_clone.call(|core| {
+                if let Some(system) = core.as_mut().and_then(cores::nes) {
+                    system.set_event_log_enabled(true);
+                }
+            });
+            
+            let refresh = {
+                let view_weak = view.as_weak();
+                let emulator = emulator_clone.clone();
+                let rows = rows.clone();
+                let shown = shown.clone();
+                Rc::new(move || {
+                    let Some(view) = view_weak.upgrade() else {
+                        return;
+                    };
+                    let page = view.get_memory_page() as u8;
+                    let captured = emulator.call(move |core| {
+                        core.as_mut().and_then(cores::nes).map(|system| DebuggerView::capture(system, page))
+                    });
+                    if let Ok(Some(captured)) = captured.wait() {
+                        *shown.borrow_mut() = Some(captured.registers);
+                        Self::refresh_debugger(&view, captured, &mut rows.borrow_mut());
+                    }
+                })
+            };
+            refresh();
+            
+            // Run a debugger command on the system, then show where it left off
+            let command = {
+                let emulator = emulator_clone.clone();
+                let status = status_clone.clone();
+                let refresh = refresh.clone();
+                Rc::new(move |command: DebuggerCommand| {
+                    let status = status.clone();
+                    emulator.call(move |core| {
+                        if let Some(system) = core.as_mut().and_then(cores::nes) {
+                            if let Err(e) = command(system) {
+                                status.send(StatusUpdate::error(format!("CPU stopped: {}", e))).ok();
+                            }
+                        }
+                    });
+                    refresh();
+                })
+            };
+            
+            let command_clone = command.clone();
+            let rows_clone = rows.clone();
+            view.on_row_clicked(move |index| {
+                let Some(addr) = debugger::row_address(&rows_clone.borrow(), index) else {
+                    return;
+                };
+                command_clone(Box::new(move |system: &mut NesSystem| {
+                    system.toggle_breakpoint(addr);
+                    Ok(())
+                }));
+            });
+            let command_clone = command.clone();
+            view.on_run(move || command_clone(Box::new(|system: &mut NesSystem| {
+                system.set_paused(false);
+                Ok(())
+            })));
+            let command_clone = command.clone();
+            view.on_pause(move || command_clone(Box::new(|system: &mut NesSystem| {
+                system.set_paused(true);
+                Ok(())
+            })));
+            let command_clone = command.clone();
+            view.on_step_into(move || command_clone(Box::new(|system: &mut NesSystem| system.step().map(drop))));
+            let command_clone = command.clone();
+            view.on_step_over(move || command_clone(Box::new(NesSystem::step_over)));
+            let command_clone = command.clone();
+            view.on_step_out(move || command_clone(Box::new(NesSystem::step_out)));
+            let refresh_clone = refresh.clone();
+            view.on_memory_page_changed(move |_| refresh_clone());
+            
+            let names: Vec<slint::SharedString> = MemoryRegion::ALL.iter().map(|region| region.name().into()).collect();
+            view.set_regions(Rc::new(slint::VecModel::from(names)).into());
+            let emulator = emulator_clone.clone();
+            let status = status_clone.clone();
+            view.on_export_region(move |index| {
+                let Some(&region) = usize::try_from(index).ok().and_then(|index| MemoryRegion::ALL.get(index)) else {
+                    return;
+                };
+                let filename = format!("{}.bin", region);
+                let path = match native_dialog::FileDialog::new()
+                    .set_filename(&filename)
+                    .add_filter("Binary data", &["bin"])
+                    .show_save_single_file()
+                {
+                    Ok(Some(path)) => path,
+                    Ok(None) => return,
+                    Err(e) => {
+                        status.send(StatusUpdate::error(format!("Couldn't open the file dialog: {}", e))).ok();
+                        return;
+                    }
+                };
+                let target = path.clone();
+                let result = emulator.call(move |core| {
+                    core.as_mut().and_then(cores::nes).map(|system| system.export_region_to(region, &target))
+                });
+                let result = match result.wait() {
+                    Ok(Some(result)) => result,
+                    Ok(None) => return,
+                    Err(e) => Err(e),
+                };
+                let update = match result {
+                    Ok(()) => StatusUpdate::info(format!("Saved {} to {}", region, path.display())),
+                    Err(e) => StatusUpdate::error(format!("Couldn't save {}: {}", region, e)),
+                };
+                status.send(update).ok();
+            });
+            
+            // Follow execution at 10Hz while running
+            let view_weak = view.as_weak();
+            let emulator_debugger = emulator_clone.clone();
+            let timer = Rc::new(RefCell::new(slint::Timer::default()));
+            let timer_weak = Rc::downgrade(&timer);
+            timer.borrow().start(slint::TimerMode::Repeated, Duration::from_millis(100), move || {
+                let open = view_weak.upgrade().is_some();
+                let shown = *shown.borrow();
+                let changed = emulator_debugger.call(move |core| {
+                    let system = core.as_mut().and_then(cores::nes)?;
+                    if !open {
+                        // Closing the debugger lets the game run freely again
+                        system.set_event_log_enabled(false);
+                        system.clear_breakpoints();
+                        system.set_paused(false);
+                        return None;
+                    }
+                    Some(!system.is_paused() || shown != Some(RegisterView::capture(system)))
+                });
+                if !open {
+                    if let Some(t) = timer_weak.upgrade() {
+                        t.borrow().stop();
+                    }
+                    return;
+                }
+                if matches!(changed.wait(), Ok(Some(true))) {
+                    refresh();
+                }
+            });
+            
+            view.show().unwrap();
+            std::mem::forget(timer);
+        });
+        
+        // PPU viewer, following the pattern tables at 10Hz
+        let emulator_clone = emulator.clone();
+        window.on_open_ppu_viewer(move || {
+            let viewer = PpuViewer::new().unwrap();
+            
+            // Redraw the viewer, or report that it has been closed
+            let refresh = {
+                let viewer_weak = viewer.as_weak();
+                let emulator = emulator_clone.clone();
+                move || {
+                    let Some(viewer) = viewer_weak.upgrade() else {
+                        return false;
+                    };
+                    let palette = viewer.get_palette() as u8;
+                    let pictures = emulator.call(move |core| {
+                        core.as_mut().and_then(cores::nes).map(|system| ppu_viewer::capture(system.ppu(), palette))
+                    });
+                    if let Ok(Some((pattern_tables, palettes))) = pictures.wait() {
+                        viewer.set_pattern_tables(frame_loop::screen_image(
+                            &pattern_tables,
+                            (ppu_viewer::PATTERN_WIDTH, ppu_viewer::PATTERN_HEIGHT),
+                        ));
+                        viewer.set_palettes(frame_loop::screen_image(
+                            &palettes,
+                            (ppu_viewer::PALETTE_WIDTH, ppu_viewer::PALETTE_HEIGHT),
+                        ));
+                    }
+                    true
+                }
+            };
+            refresh();
+            
+            let timer = Rc::new(RefCell::new(slint::Timer::default()));
+            let timer_weak = Rc::downgrade(&timer);
+            timer.borrow().start(slint::TimerMode::Repeated, Duration::from_millis(100), move || {
+                if !refresh() {
+                    if let Some(t) = timer_weak.upgrade() {
+                        t.borrow().stop();
+                    }
+                }
+            });
+            
+            viewer.show().unwrap();
+            std::mem::forget(timer);
+        });
+        
+        // Homebrew lint: its switch and findings, following them once a second.
+        // Closing the panel leaves the lint running, for the status bar badge
+        let emulator_clone = emulator.clone();
+        window.on_open_lint_panel(move || {
+            let panel = LintPanel::new().unwrap();
+            
+            let emulator_toggle = emulator_clone.clone();
+            panel.on_linting_toggled(move |enabled| {
+                emulator_toggle.call(move |core| {
+                    if let Some(system) = core.as_mut().and_then(cores::nes) {
+                        system.set_lint_enabled(enabled);
+                    }
+                });
+            });
+            
+            // Refresh the panel, or report that it has been closed
+            let refresh = {
+                let panel_weak = panel.as_weak();
+                let emulator = emulator_clone.clone();
+                move || {
+                    let Some(panel) = panel_weak.upgrade() else {
+                        return false;
+                    };
+                    let findings = emulator.call(|core| {
+                        let system = core.as_mut().and_then(cores::nes)?;
+                        let lint = system.lint()?;
+                        Some(lint.findings().iter().map(|finding| finding.to_string().into()).collect::<Vec<slint::SharedString>>())
+                    });
+                    // A newly loaded ROM starts with the lint off
+                    let findings = findings.wait().ok().flatten();
+                    panel.set_linting(findings.is_some());
+                    panel.set_findings(Rc::new(slint::VecModel::from(findings.unwrap_or_default())).into());
+                    true
+                }
+            };
+            refresh();
+            
+            let timer = Rc::new(RefCell::new(slint::Timer::default()));
+            let timer_weak = Rc::downgrade(&timer);
+            timer.borrow().start(slint::TimerMode::Repeated, Duration::from_secs(1), move || {
+                if !refresh() {
+                    if let Some(t) = timer_weak.upgrade() {
+                        t.borrow().stop();
+                    }
+                }
+            });
+            
+            panel.show().unwrap();
+            std::mem::forget(timer);
+        });
+        
+        // Help > About
+        window.on_open_about(|| {
+            let dialog = AboutDialog::new().unwrap();
+            dialog.set_build_info(menu::build_info().into());
+            let dialog_weak = dialog.as_weak();
+            dialog.on_dismiss(move || {
+                if let Some(dialog) = dialog_weak.upgrade() {
+                    dialog.hide().ok();
+                }
+            });
+            dialog.show().unwrap();
+        });
+        
+        // Menu bar: which items can be used, and where each one goes
+        window.on_menu_state(|rom_loaded, running, paused, resume_available, recent_roms, auto_reload, chr_watch| {
+            Self::menu_state(&MenuContext {
+                rom_loaded,
+                running,
+                paused,
+                resume_available,
+                recent_roms: recent_roms.max(0) as usize,
+                auto_reload,
+                chr_watch,
+            })
+        });
+        let window_weak = window.as_weak();
+        window.on_menu_activated(move |id| {
+            let Some(window) = window_weak.upgrade() else {
+                return;
+            };
+            match MenuAction::parse(&id) {
+                Some(action) => Self::route_menu_action(&window, action),
+                None => eprintln!("Unknown menu item {:?}", id),
+            }
+        });
+        
+        reload_timer
+    }
+    
+    /// The menu bar's state in `context`
+    fn menu_state(context: &MenuContext) -> MenuState {
+        let enabled = |action: MenuAction| action.enabled(context);
+        MenuState {
+            start_label: context.start_label().into(),
+            start: enabled(MenuAction::StartPauseResume),
+            stop: enabled(MenuAction::Stop),
+            resume_session: enabled(MenuAction::ResumeSession),
+            recent: enabled(MenuAction::OpenRecent(0)),
+            screenshot: enabled(MenuAction::Screenshot),
+            soft_reset: enabled(MenuAction::SoftReset),
+            power_cycle: enabled(MenuAction::PowerCycle),
+            save_state: enabled(MenuAction::SaveState(1)),
+            load_state: enabled(MenuAction::LoadState(1)),
+            keep_state: enabled(MenuAction::KeepStateOnReload),
+            watch_chr: enabled(MenuAction::WatchChr),
+            game_settings: enabled(MenuAction::GameSettings),
+            run_macro: enabled(MenuAction::RunMacro),
+            ppu_viewer: enabled(MenuAction::PpuViewer),
+            debugger: enabled(MenuAction::Debugger),
+            memory_viewer: enabled(MenuAction::MemoryViewer),
+            lint: enabled(MenuAction::Lint),
+        }
+    }
+    
+    /// Carry out a menu action through the callback that does the same
+    /// from the rest of the UI, after updating any setting it changes
+    fn route_menu_action(window: &MainWindow, action: MenuAction) {
+        match action {
+            MenuAction::LoadRom => window.invoke_load_rom(),
+            MenuAction::LoadRomWithPatch => window.invoke_load_rom_with_patch(),
+            MenuAction::OpenRecent(index) => window.invoke_open_recent(index as i32),
+            MenuAction::Screenshot => window.invoke_take_screenshot(),
+            MenuAction::Exit => window.invoke_exit(),
+            MenuAction::StartPauseResume if window.get_emulator_running() => window.invoke_pause_emulation(),
+            MenuAction::StartPauseResume => window.invoke_start_emulation(),
+            MenuAction::Stop => window.invoke_stop_emulation(),
+            MenuAction::ResumeSession => window.invoke_resume_session(),
+            MenuAction::SoftReset => window.invoke_soft_reset(),
+            MenuAction::PowerCycle => window.invoke_power_cycle(),
+            MenuAction::SaveState(slot) => {
+                window.set_state_slot(slot.into());
+                window.invoke_quick_save();
+            }
+            MenuAction::LoadState(slot) => {
+                window.set_state_slot(slot.into());
+                window.invoke_quick_load();
+            }
+            MenuAction::Speed(percent) => {
+                window.set_speed(percent as i32);
+                window.invoke_speed_changed(percent as i32);
+            }
+            MenuAction::Pacing(mode) => {
+                window.set_pacing_mode(pacing_mode_to_index(mode));
+                window.invoke_display_settings_changed();
+            }
+            MenuAction::RunAhead(frames) => {
+                window.set_run_ahead(frames.into());
+                window.invoke_display_settings_changed();
+            }
+            MenuAction::Accuracy(profile) => {
+                window.set_accuracy_profile(accuracy_profile_to_index(profile));
+                window.invoke_accuracy_changed();
+            }
+            MenuAction::AutoReload => {
+                window.set_auto_reload_rom(!window.get_auto_reload_rom());
+                window.invoke_reload_settings_changed();
+            }
+            MenuAction::KeepStateOnReload => {
+                window.set_keep_state_on_reload(!window.get_keep_state_on_reload());
+                window.invoke_reload_settings_changed();
+            }
+            MenuAction::GameSettings => window.invoke_open_game_settings(),
+            MenuAction::RunMacro => window.invoke_run_macro(),
+            MenuAction::WatchChr => window.invoke_watch_chr(),
+            MenuAction::PpuViewer => window.invoke_open_ppu_viewer(),
+            MenuAction::Debugger => window.invoke_open_debugger(),
+            MenuAction::MemoryViewer => window.invoke_open_memory_viewer(),
+            MenuAction::Lint => window.invoke_open_lint_panel(),
+            MenuAction::InputDisplay => {
+                let enabled = !window.get_input_display();
+                window.set_input_display(enabled);
+                window.invoke_input_display_toggled(enabled);
+            }
+            MenuAction::SpriteOverlay => {
+                let enabled = !window.get_sprite_overlay();
+                window.set_sprite_overlay(enabled);
+                window.invoke_sprite_overlay_toggled(enabled);
+            }
+            MenuAction::Scale(mode) => {
+                window.set_scale_mode(scale_mode_to_index(mode));
+                window.invoke_display_settings_changed();
+            }
+            MenuAction::CropOverscan => {
+                window.set_crop_overscan(!window.get_crop_overscan());
+                window.invoke_display_settings_changed();
+            }
+            MenuAction::FrameBlend(mode) => {
+                window.set_frame_blend(frame_blend_to_index(mode));
+                window.invoke_display_settings_changed();
+            }
+            MenuAction::About => window.invoke_open_about(),
+        }
+    }
+    
+    /// List the recent ROMs in File > Recent ROMs
+    fn show_recent_roms(window: &MainWindow, recent_roms: &[PathBuf]) {
+        let titles: Vec<slint::SharedString> = recent_roms.iter().map(|path| path.display().to_string().into()).collect();
+        window.set_recent_roms(Rc::new(slint::VecModel::from(titles)).into());
+    }
+    
+    /// Show a capture of the system's state in the debugger
+    fn refresh_debugger(view: &DebuggerWindow, captured: DebuggerView, rows: &mut Vec<DisasmRow>) {
+        *rows = captured.rows;
+        let entries: Vec<DisasmEntry> = rows
+            .iter()
+            .map(|row| DisasmEntry {
+                label: row.label().into(),
+                current: row.current,
+                breakpoint: row.breakpoint,
+            })
+            .collect();
+        view.set_rows(Rc::new(slint::VecModel::from(entries)).into());
+        view.set_registers(captured.registers.text().into());
+        let memory: Vec<slint::SharedString> = captured.memory.into_iter().map(Into::into).collect();
+        view.set_memory_rows(Rc::new(slint::VecModel::from(memory)).into());
+        view.set_paused(captured.paused);
+        // Paused, there's nothing new; keep showing the last frame that ran
+        if let Some(events) = captured.events {
+            let events: Vec<slint::SharedString> = events.into_iter().map(Into::into).collect();
+            view.set_events(Rc::new(slint::VecModel::from(events)).into());
+        }
+    }
+    
+    /// Apply resolved settings to the UI and the system
+    fn apply_settings(window: &MainWindow, loop_settings: &LoopSettings, system: &mut Core, settings: &Settings) {
+        Self::show_settings(window, loop_settings, settings);
+        Self::configure_system(system, settings);
+    }
+    
+    /// Apply resolved settings to the UI
+    fn show_settings(window: &MainWindow, loop_settings: &LoopSettings, settings: &Settings) {
+        loop_settings.set_sprite_overlay(settings.sprite_overlay);
+        loop_settings.set_frame_blend(settings.frame_blend, settings.frame_blend_strength);
+        window.set_sprite_overlay(settings.sprite_overlay);
+        window.set_stereo_separation(settings.stereo_separation);
+        Self::apply_display_settings(window, settings);
+    }
+    
+    /// Apply resolved settings to the system
+    fn configure_system(system: &mut Core, settings: &Settings) {
+        if let Some(system) = cores::nes(system) {
+            system.set_hang_detection(settings.hang_detection);
+            system.set_stereo_config(StereoConfig::with_separation(settings.stereo_separation));
+            system.set_accuracy_flags(accuracy_flags(settings.accuracy));
+            // Power cycles, but only when the region actually changes
+            system.set_region_override(settings.tv_system.map(TvSystem::region));
+        }
+    }
+    
+    /// CRC32 of the loaded game, which keys its settings overrides
+    fn rom_crc32(system: &mut Core) -> Option<u32> {
+        cores::nes(system).and_then(|system| system.rom_crc32())
+    }
+    
+    /// Apply the screen scaling, pacing and run-ahead settings to the UI
+    fn apply_display_settings(window: &MainWindow, settings: &Settings) {
+        window.set_scale_mode(scale_mode_to_index(settings.scale_mode));
+        window.set_crop_overscan(settings.crop_overscan);
+        window.set_frame_blend(frame_blend_to_index(settings.frame_blend));
+        window.set_pacing_mode(pacing_mode_to_index(settings.pacing));
+        window.set_run_ahead(settings.run_ahead.min(run_ahead::MAX_FRAMES) as i32);
+    }
+    
+    /// Map an override to the game settings dialog's choice index
+    fn override_to_choice(value: Option<bool>) -> i32 {
+        match value {
+            None => 0,
+            Some(true) => 1,
+            Some(false) => 2,
+        }
+    }
+    
+    /// Map the game settings dialog's choice index to an override
+    fn choice_to_override(choice: i32) -> Option<bool> {
+        match choice {
+            1 => Some(true),
+            2 => Some(false),
+            _ => None,
+        }
+    }
+    
+    /// Map a TV system override to the game settings dialog's choice index
+    fn tv_system_to_choice(value: Option<TvSystem>) -> i32 {
+        match value {
+            None => 0,
+            Some(TvSystem::Ntsc) => 1,
+            Some(TvSystem::Pal) => 2,
+        }
+    }
+    
+    /// Map the game settings dialog's TV system choice to an override
+    fn choice_to_tv_system(choice: i32) -> Option<TvSystem> {
+        match choice {
+            1 => Some(TvSystem::Ntsc),
+            2 => Some(TvSystem::Pal),
+            _ => None,
+        }
+    }
+    
+    /// Run a savestate command, describing the outcome for the status bar
+    fn run_state_command(system: &mut Core, command: StateCommand) -> Result<String, String> {
+        let Some(system) = cores::nes(system) else {
+            return Err("Savestates aren't supported for this system".into());
+        };
+        match command {
+            StateCommand::Save { slot, path } => {
+                let rgba_data = system.framebuffer_rgba();
+                match SlotFile::capture(system, &rgba_data).save(&path) {
+                    Ok(()) => Ok(format!("Saved slot {}", slot)),
+                    Err(e) => {
+                        eprintln!("Failed to save {:?}: {}", path, e);
+                        Err(format!("Couldn't save slot {}: {}", slot, e))
+                    }
+                }
+            }
+            StateCommand::Load { slot, path } => match SlotFile::load(&path).and_then(|file| file.restore(system)) {
+                Ok(()) => Ok(format!("Loaded slot {}", slot)),
+                Err(SessionError::Io(e)) if e.kind() == io::ErrorKind::NotFound => Err(format!("Slot {} is empty", slot)),
+                Err(SessionError::CrcMismatch { .. }) => Err(format!("Slot {} was saved from a different ROM", slot)),
+                Err(e) => {
+                    eprintln!("Failed to load {:?}: {}", path, e);
+                    Err(format!("Couldn't load slot {}: {}", slot, e))
+                }
+            },
+        }
+    }
+    
+    /// Status bar message for a savestate command's outcome
+    fn state_status_update(outcome: Result<String, String>) -> StatusUpdate {
+        match outcome {
+            Ok(text) => StatusUpdate::info(text),
+            Err(text) => StatusUpdate::error(text),
+        }
     }
     
     /// Get memory region bounds from index
@@ -569,7 +1845,7 @@ impl EmulatorApp {
             for i in 0..16 {
                 if addr + i < end {
                     let byte = system.read_memory(addr + i);
-                    let ch = if byte >= 32 && byte < 127 {
+                    let ch = if (32..127).contains(&byte) {
                         byte as char
                     } else {
                         '.'
@@ -584,38 +1860,6 @@ impl EmulatorApp {
         output
     }
 
-    fn framebuffer_to_rgba(framebuffer: &[u8]) -> Vec<u8> {
-        // NES palette (NTSC colors)
-        const NES_PALETTE: [(u8, u8, u8); 64] = [
-            (84, 84, 84), (0, 30, 116), (8, 16, 144), (48, 0, 136),
-            (68, 0, 100), (92, 0, 48), (84, 4, 0), (60, 24, 0),
-            (32, 42, 0), (8, 58, 0), (0, 64, 0), (0, 60, 0),
-            (0, 50, 60), (0, 0, 0), (0, 0, 0), (0, 0, 0),
-            (152, 150, 152), (8, 76, 196), (48, 50, 236), (92, 30, 228),
-            (136, 20, 176), (160, 20, 100), (152, 34, 32), (120, 60, 0),
-            (84, 90, 0), (40, 114, 0), (8, 124, 0), (0, 118, 40),
-            (0, 102, 120), (0, 0, 0), (0, 0, 0), (0, 0, 0),
-            (236, 238, 236), (76, 154, 236), (120, 124, 236), (176, 98, 236),
-            (228, 84, 236), (236, 88, 180), (236, 106, 100), (212, 136, 32),
-            (160, 170, 0), (116, 196, 0), (76, 208, 32), (56, 204, 108),
-            (56, 180, 204), (60, 60, 60), (0, 0, 0), (0, 0, 0),
-            (236, 238, 236), (168, 204, 236), (188, 188, 236), (212, 178, 236),
-            (236, 174, 236), (236, 174, 212), (236, 180, 176), (228, 196, 144),
-            (204, 210, 120), (180, 222, 120), (168, 226, 144), (152, 226, 180),
-            (160, 214, 228), (160, 162, 160), (0, 0, 0), (0, 0, 0),
-        ];
-
-        let mut rgba = Vec::with_capacity(256 * 240 * 4);
-        for &color_idx in framebuffer {
-            let (r, g, b) = NES_PALETTE[(color_idx & 0x3F) as usize];
-            rgba.push(r);
-            rgba.push(g);
-            rgba.push(b);
-            rgba.push(255); // Alpha
-        }
-        rgba
-    }
-
     pub fn run(&self) -> Result<(), slint::PlatformError> {
         self.window.run()
     }
@@ -626,3 +1870,65 @@ impl Default for EmulatorApp {
         Self::new().expect("Failed to create EmulatorApp")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> ScreenRect {
+        ScreenRect { x, y, width, height }
+    }
+
+    #[test]
+    fn test_square_pixels_letterbox() {
+        // Exactly 2x, bars left and right
+        assert_eq!(screen_rect(800.0, 480.0, ScaleMode::Square, false), rect(144.0, 0.0, 512.0, 480.0));
+        // Exactly 3x, bars top and bottom
+        assert_eq!(screen_rect(768.0, 1000.0, ScaleMode::Square, false), rect(0.0, 140.0, 768.0, 720.0));
+    }
+
+    #[test]
+    fn test_near_integer_scales_snap_down() {
+        // 2.04x snaps to 2x
+        assert_eq!(screen_rect(1000.0, 490.0, ScaleMode::Square, false), rect(244.0, 5.0, 512.0, 480.0));
+        // 2.5x is too far from a whole number to snap
+        assert_eq!(screen_rect(1000.0, 600.0, ScaleMode::Square, false), rect(180.0, 0.0, 640.0, 600.0));
+        // Below 1x there's nothing to snap to
+        assert_eq!(screen_rect(256.0, 200.0, ScaleMode::Square, false).height, 200.0);
+    }
+
+    #[test]
+    fn test_ntsc_aspect_and_overscan_crop() {
+        // 8:7 pixels: 256x240 at 1x is about 293x240
+        assert_eq!(screen_rect(1000.0, 240.0, ScaleMode::Ntsc, false), rect(353.0, 0.0, 293.0, 240.0));
+        // Cropping leaves 224 lines, so 448 high is exactly 2x
+        assert_eq!(screen_rect(1000.0, 448.0, ScaleMode::Ntsc, true), rect(207.0, 0.0, 585.0, 448.0));
+    }
+
+    #[test]
+    fn test_stretch_fills_area() {
+        assert_eq!(screen_rect(1000.0, 300.0, ScaleMode::Stretch, true), rect(0.0, 0.0, 1000.0, 300.0));
+        assert_eq!(screen_rect(-5.0, 10.0, ScaleMode::Square, false), rect(0.0, 5.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_scale_mode_index_round_trip() {
+        for mode in [ScaleMode::Square, ScaleMode::Ntsc, ScaleMode::Stretch] {
+            assert_eq!(index_to_scale_mode(scale_mode_to_index(mode)), mode);
+        }
+    }
+
+    #[test]
+    fn test_frame_blend_index_round_trip() {
+        for mode in [FrameBlendMode::Off, FrameBlendMode::Blend, FrameBlendMode::Phosphor] {
+            assert_eq!(index_to_frame_blend(frame_blend_to_index(mode)), mode);
+        }
+    }
+
+    #[test]
+    fn test_pacing_mode_index_round_trip() {
+        for mode in [PacingMode::Emulation, PacingMode::Display] {
+            assert_eq!(index_to_pacing_mode(pacing_mode_to_index(mode)), mode);
+        }
+    }
+}
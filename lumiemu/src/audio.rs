@@ -0,0 +1,152 @@
+//! Sound output: a playback buffer the emulation thread fills and cpal drains
+//!
+//! Samples are queued as interleaved stereo; when the buffer runs dry the
+//! last sample frame is repeated rather than dropping to silence, which
+//! would click.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Stream, StreamConfig, SampleRate};
+
+/// Audio sample rate (Hz)
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Audio buffer size (how many sample frames to buffer)
+pub const AUDIO_BUFFER_SIZE: usize = 4096;
+
+/// Output channels; samples are interleaved left, right
+pub const AUDIO_CHANNELS: usize = 2;
+
+/// Audio system for playing NES audio
+pub struct AudioSystem {
+    _stream: Stream,
+    /// Interleaved samples, [`AUDIO_CHANNELS`] per frame
+    sample_buffer: Arc<Mutex<VecDeque<f32>>>,
+    /// Output callbacks that ran out of samples
+    underruns: Arc<AtomicU64>,
+}
+
+/// Playback buffer health, for the status bar
+#[derive(Debug, Clone, Copy)]
+pub struct AudioStats {
+    /// Fraction of the playback buffer holding sample frames, 0.0 to 1.0
+    pub fill: f32,
+    /// Output callbacks so far that ran out of samples
+    pub underruns: u64,
+}
+
+impl AudioSystem {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let host = cpal::default_host();
+        let device = host.default_output_device()
+            .ok_or("No audio output device available")?;
+        
+        let config = StreamConfig {
+            channels: AUDIO_CHANNELS as u16,
+            sample_rate: SampleRate(SAMPLE_RATE),
+            buffer_size: cpal::BufferSize::Default,
+        };
+        
+        println!("Audio config: {:?}", config);
+        
+        // Shared buffer for audio samples
+        let sample_buffer = Arc::new(Mutex::new(VecDeque::with_capacity(AUDIO_BUFFER_SIZE * AUDIO_CHANNELS)));
+        let buffer_clone = sample_buffer.clone();
+        let underruns = Arc::new(AtomicU64::new(0));
+        let underruns_clone = underruns.clone();
+        
+        let stream = device.build_output_stream(
+            &config,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                let mut buffer = buffer_clone.lock().unwrap();
+                let mut last_frame = [-1.0; AUDIO_CHANNELS]; // APU silence level
+                let mut ran_dry = false;
+                
+                // Fill output buffer a whole frame at a time so channels stay paired
+                for frame in data.chunks_mut(AUDIO_CHANNELS) {
+                    if buffer.len() >= AUDIO_CHANNELS {
+                        for (channel, sample) in frame.iter_mut().enumerate() {
+                            last_frame[channel] = buffer.pop_front().unwrap();
+                            *sample = last_frame[channel];
+                        }
+                    } else {
+                        // Buffer underrun - repeat last frame to avoid clicking
+                        frame.copy_from_slice(&last_frame[..frame.len()]);
+                        ran_dry = true;
+                    }
+                }
+                
+                if ran_dry {
+                    underruns_clone.fetch_add(1, Ordering::Relaxed);
+                }
+            },
+            move |err| {
+                eprintln!("Audio stream error: {}", err);
+            },
+            None,
+        )?;
+        
+        // Pre-fill buffer with a small amount of silence to prevent initial underrun
+        // Just enough to cover the first audio callback (~1-2ms)
+        {
+            let mut buffer = sample_buffer.lock().unwrap();
+            buffer.extend([-1.0; 256 * AUDIO_CHANNELS]);
+        }
+        
+        stream.play()?;
+        
+        Ok(Self {
+            _stream: stream,
+            sample_buffer,
+            underruns,
+        })
+    }
+    
+    /// Buffer fill and underrun count
+    pub fn audio_stats(&self) -> AudioStats {
+        let buffered = self.sample_buffer.lock().unwrap().len() / AUDIO_CHANNELS;
+        AudioStats {
+            fill: buffered as f32 / AUDIO_BUFFER_SIZE as f32,
+            underruns: self.underruns.load(Ordering::Relaxed),
+        }
+    }
+    
+    /// Send (left, right) sample frames to the playback buffer
+    pub fn send_samples(&self, samples: &[(f32, f32)]) {
+        let mut buffer = self.sample_buffer.lock().unwrap();
+        
+        // Add samples if buffer has space
+        for &(left, right) in samples {
+            if buffer.len() < AUDIO_BUFFER_SIZE * AUDIO_CHANNELS {
+                buffer.extend([left, right]);
+            } else {
+                // Buffer full - drop samples to avoid unbounded growth
+                break;
+            }
+        }
+    }
+    
+    /// Fade out audio buffer to prevent pop
+    /// Gradually fades current samples to silence (-1.0)
+    pub fn fade_out(&self) {
+        let mut buffer = self.sample_buffer.lock().unwrap();
+        let fade_samples = 441; // ~10ms fade at 44.1kHz
+        
+        // Clear existing buffer and add fade-out frames from the last one queued
+        let mut current_frame = [-1.0; AUDIO_CHANNELS];
+        if buffer.len() >= AUDIO_CHANNELS {
+            let last = buffer.len() - AUDIO_CHANNELS;
+            for (channel, level) in current_frame.iter_mut().enumerate() {
+                *level = buffer[last + channel];
+            }
+        }
+        buffer.clear();
+        
+        for i in 0..fade_samples {
+            let t = i as f32 / fade_samples as f32;
+            buffer.extend(current_frame.map(|level| level * (1.0 - t) - t));
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Live CHR reloading for homebrew development
+//!
+//! Watches a raw .chr file (as saved by YY-CHR and friends) and pokes it
+//! into pattern table memory whenever it changes on disk, so tile edits
+//! show up without rebuilding the ROM. Polls the modification time rather
+//! than pulling in a platform file-notification dependency.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use crate::cores::{self, CoreHandle};
+use tracing::{debug, warn};
+
+/// How often the file's modification time is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Background thread that re-pokes a CHR file on change; stops when dropped
+pub struct ChrWatcher {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ChrWatcher {
+    /// Start watching `path`, loading it once immediately
+    pub fn spawn(path: PathBuf, emulator: CoreHandle) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_thread = stop.clone();
+        let path_thread = path.clone();
+
+        let handle = thread::spawn(move || {
+            let mut last_modified: Option<SystemTime> = None;
+            while !stop_thread.load(Ordering::Relaxed) {
+                let modified = std::fs::metadata(&path_thread).and_then(|m| m.modified()).ok();
+                if modified.is_some() && modified != last_modified {
+                    last_modified = modified;
+                    match std::fs::read(&path_thread) {
+                        Ok(data) => {
+                            let path = path_thread.clone();
+                            emulator.call(move |core| {
+                                if let Some(system) = core.as_mut().and_then(cores::nes) {
+                                    system.write_chr(0, &data);
+                                    debug!("Reloaded {} bytes of CHR from {:?}", data.len(), path);
+                                }
+                            });
+                        }
+                        Err(e) => warn!("Failed to read {:?}: {}", path_thread, e),
+                    }
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+
+        Self {
+            path,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// File being watched
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+impl Drop for ChrWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
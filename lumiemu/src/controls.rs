@@ -0,0 +1,388 @@
+//! State the UI thread shares with the emulation thread, without locks
+//!
+//! The system itself lives on the emulator service thread and is only
+//! reached through commands. What's left to share is small: whether the
+//! frame loop should keep going ([`RunControl`]), the buttons held on the
+//! keyboard ([`PadInput`]) and the settings the loop reads every frame
+//! ([`LoopSettings`]). Each is a handful of atomics, so a key handler or
+//! the Stop button never waits for a frame to finish.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
+use emu_core::Emulator;
+use crate::frame_loop;
+use crate::menu;
+use crate::settings::{FrameBlendMode, PacingMode};
+use crate::video_filter;
+
+/// What the frame loop is doing, as far as the UI is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunState {
+    /// Not running; Start resets the game
+    Stopped,
+    Running,
+    /// Not running; Start carries on from the frame on screen
+    Paused,
+}
+
+impl RunState {
+    fn from_bits(bits: u64) -> Self {
+        match bits & 0b11 {
+            1 => RunState::Running,
+            2 => RunState::Paused,
+            _ => RunState::Stopped,
+        }
+    }
+
+    fn bits(self) -> u64 {
+        match self {
+            RunState::Stopped => 0,
+            RunState::Running => 1,
+            RunState::Paused => 2,
+        }
+    }
+}
+
+/// Run state shared with the frame loop
+///
+/// Every start hands out a new generation, kept in the same atomic as the
+/// state. A loop keeps going only while its own generation is running, so
+/// one stopped and restarted before it noticed ends instead of running
+/// alongside the new one.
+#[derive(Debug, Default)]
+pub struct RunControl {
+    /// Generation in the upper bits, [`RunState`] in the low two
+    word: AtomicU64,
+}
+
+impl RunControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn state(&self) -> RunState {
+        RunState::from_bits(self.word.load(Ordering::Acquire))
+    }
+
+    /// Swap `state` for the result of `f`, keeping the generation;
+    /// returns whether it changed
+    fn transition(&self, f: impl Fn(RunState) -> Option<RunState>) -> bool {
+        self.word
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |word| {
+                f(RunState::from_bits(word)).map(|state| (word & !0b11) | state.bits())
+            })
+            .is_ok()
+    }
+
+    /// Start running, returning the generation the new loop runs as, or
+    /// None if a loop is already running
+    pub fn start(&self) -> Option<u64> {
+        self.word
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |word| {
+                (RunState::from_bits(word) != RunState::Running)
+                    .then(|| ((word >> 2) + 1) << 2 | RunState::Running.bits())
+            })
+            .ok()
+            .map(|word| (word >> 2) + 1)
+    }
+
+    /// Pause a running loop; false if it wasn't running
+    pub fn pause(&self) -> bool {
+        self.transition(|state| (state == RunState::Running).then_some(RunState::Paused))
+    }
+
+    /// Stop, so the next Start resets
+    pub fn stop(&self) {
+        self.transition(|_| Some(RunState::Stopped));
+    }
+
+    /// While not running, set whether Start carries on from the frame on
+    /// screen (a restored session or a loaded savestate) or resets
+    pub fn set_resumable(&self, resumable: bool) {
+        self.transition(|state| match (state, resumable) {
+            (RunState::Stopped, true) => Some(RunState::Paused),
+            (RunState::Paused, false) => Some(RunState::Stopped),
+            _ => None,
+        });
+    }
+
+    /// Whether the loop started as `generation` should run another frame
+    pub fn keeps_running(&self, generation: u64) -> bool {
+        let word = self.word.load(Ordering::Acquire);
+        word >> 2 == generation && RunState::from_bits(word) == RunState::Running
+    }
+
+    /// Whether `generation` is still the latest start, so its loop is the
+    /// one to report how it stopped
+    pub fn is_current(&self, generation: u64) -> bool {
+        self.word.load(Ordering::Acquire) >> 2 == generation
+    }
+
+    /// Stop the loop started as `generation` from within, e.g. on an
+    /// emulation error, unless it's already been stopped or replaced
+    pub fn stop_run(&self, generation: u64) {
+        self.word
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |word| {
+                (word >> 2 == generation && RunState::from_bits(word) == RunState::Running)
+                    .then_some(word & !0b11)
+            })
+            .ok();
+    }
+}
+
+/// Buttons held on one controller, one bit per [`frame_loop`] binding
+///
+/// Key handlers set bits as keys go up and down; the frame loop hands the
+/// whole set to the core before each frame.
+#[derive(Debug, Default)]
+pub struct PadInput {
+    buttons: AtomicU8,
+}
+
+impl PadInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Press or release the button bound to `key`; false if it isn't bound
+    pub fn set_key(&self, key: &str, pressed: bool) -> bool {
+        let Some(bit) = frame_loop::key_button(key) else {
+            return false;
+        };
+        if pressed {
+            self.buttons.fetch_or(1 << bit, Ordering::Relaxed);
+        } else {
+            self.buttons.fetch_and(!(1 << bit), Ordering::Relaxed);
+        }
+        true
+    }
+
+    /// The buttons held, one bit per binding
+    pub fn buttons(&self) -> u8 {
+        self.buttons.load(Ordering::Relaxed)
+    }
+
+    /// Hold exactly these buttons on `core`'s controller `port`
+    pub fn apply(&self, core: &mut dyn Emulator, port: usize) {
+        frame_loop::set_buttons(core, port, self.buttons());
+    }
+}
+
+/// Settings the frame loop reads every frame
+#[derive(Debug)]
+pub struct LoopSettings {
+    sprite_overlay: AtomicBool,
+    input_display: AtomicBool,
+    pacing: AtomicU8,
+    run_ahead: AtomicU8,
+    /// Emulation speed in percent
+    speed: AtomicU32,
+    frame_blend: AtomicU8,
+    /// Bits of the `f32` strength
+    frame_blend_strength: AtomicU32,
+    /// Bumped whenever the picture starts over
+    picture_epoch: AtomicU64,
+}
+
+impl Default for LoopSettings {
+    fn default() -> Self {
+        Self {
+            sprite_overlay: AtomicBool::new(false),
+            input_display: AtomicBool::new(false),
+            pacing: AtomicU8::new(Self::pacing_bits(PacingMode::default())),
+            run_ahead: AtomicU8::new(0),
+            speed: AtomicU32::new(menu::NORMAL_SPEED),
+            frame_blend: AtomicU8::new(Self::frame_blend_bits(FrameBlendMode::default())),
+            frame_blend_strength: AtomicU32::new(video_filter::DEFAULT_STRENGTH.to_bits()),
+            picture_epoch: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LoopSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn pacing_bits(mode: PacingMode) -> u8 {
+        match mode {
+            PacingMode::Emulation => 0,
+            PacingMode::Display => 1,
+        }
+    }
+
+    fn frame_blend_bits(mode: FrameBlendMode) -> u8 {
+        match mode {
+            FrameBlendMode::Off => 0,
+            FrameBlendMode::Blend => 1,
+            FrameBlendMode::Phosphor => 2,
+        }
+    }
+
+    pub fn sprite_overlay(&self) -> bool {
+        self.sprite_overlay.load(Ordering::Relaxed)
+    }
+
+    pub fn set_sprite_overlay(&self, enabled: bool) {
+        self.sprite_overlay.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn input_display(&self) -> bool {
+        self.input_display.load(Ordering::Relaxed)
+    }
+
+    pub fn set_input_display(&self, enabled: bool) {
+        self.input_display.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn pacing(&self) -> PacingMode {
+        match self.pacing.load(Ordering::Relaxed) {
+            1 => PacingMode::Display,
+            _ => PacingMode::Emulation,
+        }
+    }
+
+    pub fn set_pacing(&self, mode: PacingMode) {
+        self.pacing.store(Self::pacing_bits(mode), Ordering::Relaxed);
+    }
+
+    /// Frames of run-ahead asked for
+    pub fn run_ahead(&self) -> u8 {
+        self.run_ahead.load(Ordering::Relaxed)
+    }
+
+    pub fn set_run_ahead(&self, frames: u8) {
+        self.run_ahead.store(frames, Ordering::Relaxed);
+    }
+
+    /// Emulation speed in percent
+    pub fn speed(&self) -> u32 {
+        self.speed.load(Ordering::Relaxed)
+    }
+
+    pub fn set_speed(&self, percent: u32) {
+        self.speed.store(percent, Ordering::Relaxed);
+    }
+
+    /// Frame blending mode and strength
+    pub fn frame_blend(&self) -> (FrameBlendMode, f32) {
+        let mode = match self.frame_blend.load(Ordering::Relaxed) {
+            1 => FrameBlendMode::Blend,
+            2 => FrameBlendMode::Phosphor,
+            _ => FrameBlendMode::Off,
+        };
+        (mode, f32::from_bits(self.frame_blend_strength.load(Ordering::Relaxed)))
+    }
+
+    pub fn set_frame_blend(&self, mode: FrameBlendMode, strength: f32) {
+        self.frame_blend.store(Self::frame_blend_bits(mode), Ordering::Relaxed);
+        self.frame_blend_strength.store(strength.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Changes whenever the picture starts over, so filters holding
+    /// earlier frames drop them
+    pub fn picture_epoch(&self) -> u64 {
+        self.picture_epoch.load(Ordering::Relaxed)
+    }
+
+    /// Mark the picture as starting over: a new ROM, a reset or power
+    /// cycle, or a loaded savestate
+    pub fn restart_picture(&self) {
+        self.picture_epoch.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_start_pause_stop() {
+        let control = RunControl::new();
+        assert_eq!(control.state(), RunState::Stopped);
+
+        let first = control.start().unwrap();
+        assert_eq!(control.state(), RunState::Running);
+        assert!(control.keeps_running(first));
+        assert_eq!(control.start(), None);
+
+        assert!(control.pause());
+        assert!(!control.pause());
+        assert_eq!(control.state(), RunState::Paused);
+        assert!(!control.keeps_running(first));
+
+        let second = control.start().unwrap();
+        assert_ne!(first, second);
+        control.stop();
+        assert_eq!(control.state(), RunState::Stopped);
+        assert!(control.is_current(second));
+    }
+
+    #[test]
+    fn test_restarted_loop_replaces_the_old_one() {
+        let control = RunControl::new();
+        let old = control.start().unwrap();
+        control.stop();
+        let new = control.start().unwrap();
+
+        // The old loop ends without stopping the new one
+        assert!(!control.keeps_running(old));
+        assert!(!control.is_current(old));
+        control.stop_run(old);
+        assert!(control.keeps_running(new));
+
+        control.stop_run(new);
+        assert_eq!(control.state(), RunState::Stopped);
+    }
+
+    #[test]
+    fn test_resumable_only_while_not_running() {
+        let control = RunControl::new();
+        control.set_resumable(true);
+        assert_eq!(control.state(), RunState::Paused);
+        control.set_resumable(false);
+        assert_eq!(control.state(), RunState::Stopped);
+
+        control.start();
+        control.set_resumable(true);
+        control.set_resumable(false);
+        assert_eq!(control.state(), RunState::Running);
+    }
+
+    #[test]
+    fn test_pad_input_tracks_bound_keys() {
+        let pad = PadInput::new();
+        assert!(pad.set_key("z", true));
+        assert!(pad.set_key("↑", true));
+        assert!(!pad.set_key("q", true));
+        let held = pad.buttons();
+        assert_eq!(held.count_ones(), 2);
+
+        assert!(pad.set_key("Z", false));
+        assert_eq!(pad.buttons().count_ones(), 1);
+        assert_eq!(pad.buttons() & !held, 0);
+    }
+
+    #[test]
+    fn test_loop_settings_round_trip() {
+        let settings = LoopSettings::new();
+        assert_eq!(settings.pacing(), PacingMode::default());
+        assert_eq!(settings.speed(), menu::NORMAL_SPEED);
+
+        settings.set_pacing(PacingMode::Display);
+        settings.set_run_ahead(2);
+        settings.set_speed(50);
+        settings.set_sprite_overlay(true);
+        assert_eq!(settings.pacing(), PacingMode::Display);
+        assert_eq!(settings.run_ahead(), 2);
+        assert_eq!(settings.speed(), 50);
+        assert!(settings.sprite_overlay());
+        assert!(!settings.input_display());
+
+        assert_eq!(settings.frame_blend(), (FrameBlendMode::Off, video_filter::DEFAULT_STRENGTH));
+        settings.set_frame_blend(FrameBlendMode::Phosphor, 0.8);
+        assert_eq!(settings.frame_blend(), (FrameBlendMode::Phosphor, 0.8));
+        let epoch = settings.picture_epoch();
+        settings.restart_picture();
+        assert_ne!(settings.picture_epoch(), epoch);
+    }
+}
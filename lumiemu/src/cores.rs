@@ -0,0 +1,126 @@
+//! The emulation cores the frontend can run, picked by file extension
+//!
+//! The frame loop only talks to [`Emulator`]; features that exist for one
+//! console only (the NES debug overlays, savestates, the memory viewer)
+//! reach the concrete system through [`nes`].
+
+use std::path::Path;
+use emu_core::{Emulator, EmulatorError};
+use emu_nes::{EmulatorHandle, NesSystem, Patch};
+
+/// A loaded core, owned by whichever thread is running it
+pub type Core = Box<dyn Emulator + Send>;
+
+/// The emulator service, holding whichever core is loaded
+///
+/// Everything the frontend does to the running game goes through here, so
+/// it lands between frames.
+pub type CoreHandle = EmulatorHandle<Option<Core>>;
+
+/// One entry in the registry
+pub struct CoreInfo {
+    /// Console name, for file dialog filters
+    pub name: &'static str,
+    /// File extensions it loads, lower case without the dot
+    pub extensions: &'static [&'static str],
+    /// Build the core from a ROM image and the patch to apply to it
+    pub from_bytes: fn(&[u8], Option<&Patch>) -> emu_core::Result<Core>,
+}
+
+/// Every core the frontend knows about
+pub const CORES: &[CoreInfo] = &[CoreInfo {
+    name: "NES ROM",
+    extensions: &["nes"],
+    from_bytes: |data, patch| {
+        Ok(Box::new(match patch {
+            Some(patch) => NesSystem::from_bytes_with_patch(data, patch)?,
+            None => NesSystem::from_bytes(data)?,
+        }))
+    },
+}];
+
+/// The core that loads files like `path`
+pub fn core_for(path: &Path) -> Option<&'static CoreInfo> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    CORES.iter().find(|core| core.extensions.contains(&extension.as_str()))
+}
+
+/// Build a core for the ROM image `data`, read from `path`, patched with
+/// `patch` if there is one
+pub fn from_bytes(path: &Path, data: &[u8], patch: Option<&Patch>) -> emu_core::Result<Core> {
+    let core = core_for(path).ok_or_else(|| {
+        EmulatorError::RomLoadError(format!("No emulator for {}", path.display()))
+    })?;
+    (core.from_bytes)(data, patch)
+}
+
+/// The patch to apply to the ROM at `rom_path`: the file at `patch`, or
+/// the `.ips` or `.bps` next to the ROM if that's None
+pub fn patch_for(rom_path: &Path, patch: Option<&Path>) -> emu_core::Result<Option<Patch>> {
+    patch
+        .map(Path::to_path_buf)
+        .or_else(|| Patch::find_for(rom_path))
+        .map(|path| Patch::load(&path))
+        .transpose()
+}
+
+/// Load the ROM at `path` with the core for its extension, applying the
+/// patch [`patch_for`] picks
+pub fn load(path: &Path, patch: Option<&Path>) -> emu_core::Result<Core> {
+    let data = std::fs::read(path)
+        .map_err(|e| EmulatorError::RomLoadError(format!("Failed to open ROM: {}", e)))?;
+    from_bytes(path, &data, patch_for(path, patch)?.as_ref())
+}
+
+/// The NES behind a core, if it is one
+pub fn nes(core: &mut Core) -> Option<&mut NesSystem> {
+    core.as_any_mut().downcast_mut()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_core_picked_by_extension() {
+        assert_eq!(core_for(Path::new("games/Mario.NES")).map(|core| core.name), Some("NES ROM"));
+        assert!(core_for(Path::new("notes.txt")).is_none());
+        assert!(core_for(Path::new("no_extension")).is_none());
+
+        let error = from_bytes(Path::new("game.gb"), &[], None).err().unwrap();
+        assert!(error.to_string().contains("No emulator for game.gb"));
+    }
+
+    fn nrom() -> Vec<u8> {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        rom.extend_from_slice(&[0xEA; 0x4000]);
+        rom.extend_from_slice(&[0; 0x2000]);
+        rom
+    }
+
+    #[test]
+    fn test_nes_reached_through_the_core() {
+        let mut core = from_bytes(Path::new("game.nes"), &nrom(), None).unwrap();
+        assert!(nes(&mut core).is_some_and(|system| system.rom_crc32().is_some()));
+    }
+
+    #[test]
+    fn test_patch_next_to_the_rom() {
+        let dir = std::env::temp_dir().join(format!("lumiemu_cores_patch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("game.nes");
+        std::fs::write(&rom, nrom()).unwrap();
+        assert!(patch_for(&rom, None).unwrap().is_none());
+
+        std::fs::write(dir.join("game.ips"), b"PATCH\x00\x00\x10\x00\x01\x42EOF").unwrap();
+        let mut core = load(&rom, None).unwrap();
+        let info = nes(&mut core).and_then(|system| system.cartridge_info()).unwrap();
+        assert_eq!(info.patch.as_deref(), Some("game.ips"));
+
+        // An explicit patch wins over the one next to the ROM
+        std::fs::write(dir.join("other.ips"), b"PATCH\x00\x00\x11\x00\x01\x42EOF").unwrap();
+        let patch = patch_for(&rom, Some(&dir.join("other.ips"))).unwrap().unwrap();
+        assert_eq!(patch.name, "other.ips");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,353 @@
+//! What the debugger window shows, built from a paused or running NES
+//!
+//! Everything here is plain data so it can be tested without a window;
+//! app.rs copies it into the Slint models.
+
+use emu_core::Emulator;
+use emu_nes::{DisasmLine, EmuEvent, NesSystem};
+
+/// Instructions listed above the one at PC
+pub const ROWS_BEFORE: usize = 12;
+
+/// Instructions listed in all
+pub const ROWS: usize = 64;
+
+/// Bytes shown per line of the memory pane
+const MEMORY_ROW_BYTES: u16 = 16;
+
+/// One line of the disassembly list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisasmRow {
+    pub addr: u16,
+    /// Instruction bytes in hex, e.g. `AD 02 20`
+    pub bytes: String,
+    /// Assembly text, e.g. `LDA $2002`
+    pub text: String,
+    /// This is the next instruction to run
+    pub current: bool,
+    pub breakpoint: bool,
+}
+
+impl DisasmRow {
+    fn new(system: &NesSystem, line: DisasmLine) -> Self {
+        let bytes = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ");
+        Self {
+            addr: line.addr,
+            bytes,
+            text: line.text,
+            current: line.addr == system.cpu().pc,
+            breakpoint: system.has_breakpoint(line.addr),
+        }
+    }
+
+    /// The row as one line of text: address, bytes, then assembly
+    pub fn label(&self) -> String {
+        format!("${:04X}  {:<8}  {}", self.addr, self.bytes, self.text)
+    }
+}
+
+/// Disassembly around `center`, with up to [`ROWS_BEFORE`] rows before it
+///
+/// 6502 code can't be read backwards, so the rows before `center` come
+/// from the furthest-back start address whose instructions line up with
+/// it exactly. Anything that isn't code (tables, padding) may disassemble
+/// as nonsense, as in any disassembler.
+pub fn disasm_rows(system: &mut NesSystem, center: u16) -> Vec<DisasmRow> {
+    let lead_in = lead_in(system, center);
+    let after = system.disassemble(center, ROWS - lead_in.len());
+    lead_in.into_iter().chain(after).map(|line| DisasmRow::new(system, line)).collect()
+}
+
+/// The [`ROWS_BEFORE`] instructions leading up to `center`, or fewer if
+/// none line up
+fn lead_in(system: &mut NesSystem, center: u16) -> Vec<DisasmLine> {
+    // The longest instructions are 3 bytes
+    for back in (1..=ROWS_BEFORE as u16 * 3).rev() {
+        let mut addr = center.wrapping_sub(back);
+        let mut lines = Vec::new();
+        while addr != center {
+            let line = system.disassemble(addr, 1).remove(0);
+            if line.bytes.len() as u16 > center.wrapping_sub(addr) {
+                break;
+            }
+            addr = line.next_addr();
+            lines.push(line);
+        }
+        if addr == center && lines.len() >= ROWS_BEFORE {
+            return lines.split_off(lines.len() - ROWS_BEFORE);
+        }
+    }
+    Vec::new()
+}
+
+/// Address of the row the user clicked, if it's a row
+pub fn row_address(rows: &[DisasmRow], index: i32) -> Option<u16> {
+    usize::try_from(index).ok().and_then(|index| rows.get(index)).map(|row| row.addr)
+}
+
+/// The register panel's contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterView {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub pc: u16,
+    pub p: u8,
+    pub cycles: u64,
+    pub scanline: u16,
+    pub dot: u16,
+}
+
+impl RegisterView {
+    pub fn capture(system: &mut NesSystem) -> Self {
+        let (scanline, dot) = {
+            let ppu = system.ppu();
+            (ppu.scanline(), ppu.cycle())
+        };
+        let cpu = system.cpu();
+        Self {
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            sp: cpu.sp,
+            pc: cpu.pc,
+            p: cpu.status.bits(),
+            cycles: cpu.cycles,
+            scanline,
+            dot,
+        }
+    }
+
+    /// The status flags as `NV-BDIZC`, upper case when set
+    pub fn flags(&self) -> String {
+        "NV-BDIZC"
+            .chars()
+            .enumerate()
+            .map(|(i, flag)| if self.p & (0x80 >> i) != 0 { flag } else { flag.to_ascii_lowercase() })
+            .collect()
+    }
+
+    /// The panel's text, one register group per line
+    pub fn text(&self) -> String {
+        format!(
+            "A: ${:02X}  X: ${:02X}  Y: ${:02X}\nSP: ${:02X}  PC: ${:04X}\nP: ${:02X}  {}\nCycle {}\nScanline {}, dot {}",
+            self.a,
+            self.x,
+            self.y,
+            self.sp,
+            self.pc,
+            self.p,
+            self.flags(),
+            self.cycles,
+            self.scanline,
+            self.dot
+        )
+    }
+}
+
+/// A 256-byte page of memory as hex, 16 bytes per line
+pub fn memory_rows(system: &mut NesSystem, page: u8) -> Vec<String> {
+    let base = (page as u16) << 8;
+    (0..0x100 / MEMORY_ROW_BYTES)
+        .map(|row| {
+            let start = base + row * MEMORY_ROW_BYTES;
+            let bytes: Vec<String> = (start..start + MEMORY_ROW_BYTES)
+                .map(|addr| format!("{:02X}", system.peek_memory(addr)))
+                .collect();
+            format!("${:04X}: {}", start, bytes.join(" "))
+        })
+        .collect()
+}
+
+/// Everything the window shows, captured between frames
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebuggerView {
+    pub registers: RegisterView,
+    pub rows: Vec<DisasmRow>,
+    pub memory: Vec<String>,
+    pub paused: bool,
+    /// The timeline's rows, if a frame ran since the last capture
+    pub events: Option<Vec<String>>,
+}
+
+impl DebuggerView {
+    /// Capture the view centred on PC, with memory page `page`
+    ///
+    /// Takes the system's frame events, so a second capture with nothing
+    /// run in between has none.
+    pub fn capture(system: &mut NesSystem, page: u8) -> Self {
+        let registers = RegisterView::capture(system);
+        Self {
+            registers,
+            rows: disasm_rows(system, registers.pc),
+            memory: memory_rows(system, page),
+            paused: system.is_paused(),
+            events: event_rows(&system.take_frame_events()),
+        }
+    }
+}
+
+/// The event timeline's rows: the newest frame's events, if there are any
+///
+/// The window refreshes slower than frames run, so `events` may span
+/// several frames; only the last is shown.
+pub fn event_rows(events: &[EmuEvent]) -> Option<Vec<String>> {
+    let last = events.last()?.frame;
+    Some(events.iter().filter(|event| event.frame == last).map(ToString::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emu_nes::{EmuEventKind, SystemEvent};
+    use nes_asm::{Assembler, InesBuilder};
+
+    /// A main loop that calls a subroutine, with data in front of it
+    fn system() -> NesSystem {
+        let mut asm = Assembler::new(0xC000, 0x4000);
+        asm.bytes(&[0xFF; 7]) // Not code
+            .label("reset")
+            .ldx_imm(0xFF)
+            .txs()
+            .label("main")
+            .jsr("increment")
+            .jmp("main")
+            .label("increment")
+            .inc_zp(0x10)
+            .lda_zp(0x10)
+            .sta_abs(0x0200)
+            .rts();
+        asm.vectors("reset", "reset", "reset");
+        NesSystem::from_bytes(&InesBuilder::new(asm.assemble().unwrap()).build().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_rows_centre_on_pc() {
+        let mut system = system();
+        // Through the reset code and into the subroutine at $C010
+        for _ in 0..3 {
+            system.step().unwrap();
+        }
+        assert_eq!(system.cpu().pc, 0xC010);
+        system.set_breakpoint(0xC014, true);
+
+        let rows = disasm_rows(&mut system, 0xC010);
+        assert_eq!(rows.len(), ROWS);
+        let current: Vec<&DisasmRow> = rows.iter().filter(|row| row.current).collect();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].text, "INC $10");
+        assert_eq!(current[0].bytes, "E6 10");
+
+        // Everything before PC lines up with the instructions there
+        let pc_row = rows.iter().position(|row| row.current).unwrap();
+        assert_eq!(pc_row, ROWS_BEFORE);
+        assert_eq!(rows[pc_row - 1].text, "JMP $C00A");
+        assert_eq!(rows[pc_row - 2].text, "JSR $C010");
+        assert!(rows.windows(2).all(|pair| pair[0].addr.wrapping_add(pair[0].bytes.split(' ').count() as u16) == pair[1].addr));
+
+        let breakpoint: Vec<u16> = rows.iter().filter(|row| row.breakpoint).map(|row| row.addr).collect();
+        assert_eq!(breakpoint, [0xC014]);
+        assert_eq!(rows[pc_row + 1].label(), "$C012  A5 10     LDA $10");
+    }
+
+    #[test]
+    fn test_clicks_map_to_addresses() {
+        let mut system = system();
+        let rows = disasm_rows(&mut system, 0xC007);
+        let index = rows.iter().position(|row| row.addr == 0xC007).unwrap();
+        assert_eq!(row_address(&rows, index as i32), Some(0xC007));
+        assert_eq!(row_address(&rows, -1), None);
+        assert_eq!(row_address(&rows, ROWS as i32), None);
+
+        // A click toggles that row's breakpoint
+        let addr = row_address(&rows, index as i32 + 1).unwrap();
+        assert!(system.toggle_breakpoint(addr));
+        assert!(disasm_rows(&mut system, 0xC007).iter().any(|row| row.addr == addr && row.breakpoint));
+        assert!(!system.toggle_breakpoint(addr));
+    }
+
+    #[test]
+    fn test_step_commands() {
+        let mut system = system();
+        while system.cpu().pc != 0xC00A {
+            system.step().unwrap();
+        }
+        // Over the JSR: the subroutine ran and we're at the JMP after it
+        system.step_over().unwrap();
+        assert_eq!(RegisterView::capture(&mut system).pc, 0xC00D);
+        assert_eq!(system.peek_memory(0x10), 1);
+        // Over anything else is a single step
+        system.step_over().unwrap();
+        assert_eq!(system.cpu().pc, 0xC00A);
+
+        // Into the subroutine and back out
+        system.step().unwrap();
+        system.step().unwrap();
+        assert_eq!(system.cpu().pc, 0xC012);
+        system.step_out().unwrap();
+        assert_eq!(system.cpu().pc, 0xC00D);
+        assert_eq!(system.peek_memory(0x10), 2);
+
+        // A breakpoint inside the subroutine stops a step over
+        system.step().unwrap();
+        system.set_breakpoint(0xC014, true);
+        system.step_over().unwrap();
+        assert_eq!(system.cpu().pc, 0xC014);
+        assert!(system.is_paused());
+        assert_eq!(system.poll_events(), [SystemEvent::BreakpointHit { pc: 0xC014 }]);
+    }
+
+    #[test]
+    fn test_event_rows_show_the_last_frame() {
+        assert_eq!(event_rows(&[]), None);
+        let event = |frame, scanline, kind| EmuEvent { frame, scanline, cycle: 1, kind };
+        let events = [
+            event(6, 241, EmuEventKind::Nmi),
+            event(7, 241, EmuEventKind::Nmi),
+            event(7, 250, EmuEventKind::PpuWrite { addr: 0x2005, value: 0x08 }),
+        ];
+        assert_eq!(event_rows(&events).unwrap(), ["241,  1  NMI", "250,  1  $2005 = $08"]);
+    }
+
+    #[test]
+    fn test_register_panel() {
+        let mut system = system();
+        system.step().unwrap();
+        let view = RegisterView::capture(&mut system);
+        assert_eq!(view.x, 0xFF);
+        assert_eq!(view.pc, 0xC009);
+        // I and the unused bit set, and N from loading $FF
+        assert_eq!(view.flags(), "Nv-bdIzc");
+        assert!(view.text().starts_with("A: $00  X: $FF  Y: $00\nSP: $FD  PC: $C009\n"), "{}", view.text());
+    }
+
+    #[test]
+    fn test_memory_pane() {
+        let mut system = system();
+        for _ in 0..20 {
+            system.step().unwrap();
+        }
+        let rows = memory_rows(&mut system, 0x02);
+        assert_eq!(rows.len(), 16);
+        assert!(rows[0].starts_with("$0200: 0"), "{}", rows[0]);
+        assert_ne!(&rows[0][7..9], "00");
+        assert_eq!(rows[15].len(), "$02F0: ".len() + 16 * 3 - 1);
+        // Registers aren't read, so nothing changes by looking
+        assert!(memory_rows(&mut system, 0x20)[0].starts_with("$2000: 20 20"));
+    }
+
+    #[test]
+    fn test_whole_view() {
+        let mut system = system();
+        system.run_frame().unwrap();
+        system.set_paused(true);
+        let view = DebuggerView::capture(&mut system, 0x02);
+        assert_eq!(view.rows[ROWS_BEFORE].addr, view.registers.pc);
+        assert!(view.rows[ROWS_BEFORE].current);
+        assert_eq!(view.memory, memory_rows(&mut system, 0x02));
+        assert!(view.paused);
+        // Nothing logged without the event log on
+        assert_eq!(view.events, None);
+    }
+}
@@ -0,0 +1,465 @@
+//! The frame loop: the thread that keeps asking the service for frames
+//!
+//! It runs a frame through the [`CoreHandle`], plays its sound, shows its
+//! picture and sleeps until the next one is due, for as long as its
+//! [`RunControl`] generation keeps running. Everything else that touches
+//! the system goes through the service too, so it lands between the
+//! loop's frames; the loop and the UI share only the atomics in
+//! [`crate::controls`].
+//!
+//! What the loop shows goes through [`Screen`], so it runs the same against
+//! the window or a stand-in.
+
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use emu_core::EmulatorError;
+use emu_nes::system::SystemEvent;
+use emu_nes::LintSink;
+use tracing::trace;
+use crate::audio::{AudioSystem, AUDIO_BUFFER_SIZE, SAMPLE_RATE};
+use crate::controls::{LoopSettings, PadInput, RunControl, RunState};
+use crate::cores::{self, CoreHandle};
+use crate::frame_loop;
+use crate::menu;
+use crate::overlay::{self, PpuSnapshot};
+use crate::pacing::{self, FrameQueue};
+use crate::run_ahead;
+use crate::settings::PacingMode;
+use crate::status::{StatusSender, StatusUpdate};
+use crate::video_filter::{Filter, FrameBlend};
+
+/// Where the loop's pictures and warnings go
+pub trait Screen: Send + 'static {
+    /// Show an emulation-paced frame of `(width, height)` RGBA pixels now
+    fn show_frame(&self, rgba: Vec<u8>, size: (usize, usize));
+
+    /// Warn about something the game is doing, without stopping it
+    fn warn(&self, text: String);
+
+    /// The loop has ended; `black_screen` replaces the picture, unless the
+    /// game was paused and its last frame stays up
+    fn stopped(&self, black_screen: Option<(Vec<u8>, (usize, usize))>);
+}
+
+/// What the emulation thread gets back from the service for each frame
+struct FrameOutput {
+    rgba_data: Vec<u8>,
+    screen_size: (usize, usize),
+    frame_duration: Duration,
+    /// The picture to leave up once emulation stops
+    black_screen: Vec<u8>,
+    /// The audio buffer, handed back to be played and reused
+    samples: Vec<(f32, f32)>,
+    /// The blend filter, handed back with the frame it last saw
+    frame_blend: FrameBlend,
+    events: Vec<SystemEvent>,
+    frame: u64,
+    /// Homebrew lint findings, while the lint is on
+    lint_findings: Option<usize>,
+    /// Emulation can't go on
+    error: Option<EmulatorError>,
+}
+
+/// Everything a frame loop needs, shared with the UI
+#[derive(Clone)]
+pub struct EmulationLoop {
+    pub emulator: CoreHandle,
+    pub control: Arc<RunControl>,
+    /// Player 1, from the keyboard
+    pub input: Arc<PadInput>,
+    pub settings: Arc<LoopSettings>,
+    /// Display-paced frames, for the UI to show at its own pace
+    pub frames: Arc<FrameQueue>,
+    pub status: StatusSender,
+    /// Open the audio device; off for headless runs
+    pub audio: bool,
+}
+
+impl EmulationLoop {
+    /// Start running on a new thread, or return None if a loop is running
+    pub fn spawn(self, screen: impl Screen) -> Option<JoinHandle<()>> {
+        let generation = self.control.start()?;
+        println!("Starting emulation thread...");
+        Some(thread::spawn(move || self.run(generation, screen)))
+    }
+
+    fn run(self, generation: u64, screen: impl Screen) {
+        println!("Emulation thread started");
+        let Self { emulator, control, input, settings, frames, status, audio } = self;
+
+        // Initialize audio in emulation thread (cpal Stream is not Send)
+        let audio = audio.then(AudioSystem::new).and_then(|audio| match audio {
+            Ok(audio_system) => {
+                println!("✓ Audio system initialized");
+                Some(audio_system)
+            }
+            Err(e) => {
+                status.send(StatusUpdate::warning(format!("No audio, continuing without it: {}", e))).ok();
+                None
+            }
+        });
+
+        let mut frame_count = 0;
+        let mut fps_timer = Instant::now();
+        let mut underruns_seen = audio.as_ref().map_or(0, |audio| audio.audio_stats().underruns);
+
+        // Audio sampling: collect samples throughout frame execution
+        let mut audio_buffer = Vec::new();
+        // The black picture shown once the thread stops
+        let mut black_screen = (Vec::new(), (0, 0));
+        // Run-ahead is dropped when it can't keep up, until the setting changes
+        let mut run_ahead_watchdog = run_ahead::Watchdog::new();
+        let mut run_ahead_dropped = None;
+        // Kept between frames, as it mixes each with the one before
+        let mut frame_blend = FrameBlend::default();
+
+        // Stopped, paused, or replaced by a newer start
+        while control.keeps_running(generation) {
+            let frame_start = Instant::now();
+            let pacing_mode = settings.pacing();
+            let run_ahead_setting = settings.run_ahead();
+            let speed = settings.speed();
+            if run_ahead_dropped.is_some_and(|frames| frames != run_ahead_setting) {
+                run_ahead_dropped = None;
+                run_ahead_watchdog.reset();
+            }
+            let frames_ahead = if run_ahead_dropped.is_some() { 0 } else { run_ahead_setting };
+
+            // Run one frame, collect audio samples, and get framebuffer
+            let sprite_overlay = settings.sprite_overlay();
+            let input_display = settings.input_display();
+            let input = input.clone();
+            let mut samples = std::mem::take(&mut audio_buffer);
+            let (blend_mode, blend_strength) = settings.frame_blend();
+            frame_blend.configure(blend_mode, blend_strength, settings.picture_epoch());
+            let mut filter = std::mem::take(&mut frame_blend);
+            let output = emulator.call(move |core| {
+                let system = core.as_mut()?;
+                input.apply(&mut **system, 0);
+                let screen_size = system.screen_size();
+                let frame_duration = frame_loop::frame_duration(&**system);
+                let black_screen = frame_loop::black_screen(&**system);
+
+                let (mut rgba_data, error) = match run_ahead::run_frame(&mut **system, frames_ahead, SAMPLE_RATE, &mut samples) {
+                    Ok(rgba_data) => (rgba_data, None),
+                    Err(e) => (system.framebuffer_rgba(), Some(e)),
+                };
+                // At native resolution and under the overlays; the UI scales it after
+                filter.apply(&mut rgba_data, screen_size);
+
+                // The debug overlays and hang warnings only exist for the NES
+                let (events, frame, lint_findings) = match cores::nes(system) {
+                    Some(system) => {
+                        if sprite_overlay {
+                            let banks = system.bank_state();
+                            let ppu = system.ppu();
+                            let scroll = ppu.scroll_latch(0).unwrap_or_default();
+                            overlay::draw_overlay(&mut rgba_data, &PpuSnapshot {
+                                oam: *ppu.oam(),
+                                ctrl: ppu.ctrl.bits(),
+                                mask: ppu.mask.bits(),
+                                scroll_x: scroll.scroll_x(),
+                                scroll_y: scroll.scroll_y(),
+                            }, banks.as_ref());
+                        }
+
+                        // Only controller 1 is driven from the keyboard
+                        if input_display {
+                            overlay::draw_input_display(&mut rgba_data, &[system.last_latched_input(1)]);
+                        }
+                        (system.poll_events(), system.frame(), system.lint().map(LintSink::len))
+                    }
+                    None => (Vec::new(), 0, None),
+                };
+
+                Some(FrameOutput {
+                    rgba_data,
+                    screen_size,
+                    frame_duration,
+                    black_screen,
+                    samples,
+                    frame_blend: filter,
+                    events,
+                    frame,
+                    lint_findings,
+                    error,
+                })
+            });
+            let Ok(Some(output)) = output.wait() else {
+                println!("Emulator stopped");
+                return;
+            };
+            let FrameOutput { rgba_data, screen_size, frame_duration, black_screen: black, samples, frame_blend: filter, events, frame, lint_findings, error } = output;
+            audio_buffer = samples;
+            frame_blend = filter;
+            black_screen = (black, screen_size);
+            if let Some(e) = error {
+                status.send(StatusUpdate::error(format!("Emulation stopped: {}", e))).ok();
+                control.stop_run(generation);
+            }
+
+            // Run-ahead costs a frame of emulation per frame ahead,
+            // so it only stays on while there's time to spare
+            if frames_ahead > 0 && run_ahead_watchdog.frame_took(frame_start.elapsed(), frame_duration) {
+                run_ahead_dropped = Some(frames_ahead);
+                status.send(StatusUpdate::warning("Run-ahead turned off: this machine can't keep up")).ok();
+            }
+
+            // Send audio samples to audio thread; away from full
+            // speed there's no keeping it in step, so it goes quiet
+            if let Some(audio_system) = audio.as_ref().filter(|_| speed == menu::NORMAL_SPEED) {
+                audio_system.send_samples(&audio_buffer);
+            }
+
+            // Surface hang warnings without interrupting emulation, and breakpoint stops
+            for event in events {
+                match event {
+                    SystemEvent::PossibleHang { pc, loop_len } => {
+                        trace!("Possible hang at ${:04X} ({} instruction loop)", pc, loop_len);
+                        screen.warn(format!(
+                            "Game appears stuck at ${:04X} — possibly an unsupported feature",
+                            pc
+                        ));
+                    }
+                    SystemEvent::BreakpointHit { pc } => {
+                        status.send(StatusUpdate::info(format!("Stopped at breakpoint ${:04X}", pc))).ok();
+                    }
+                    SystemEvent::WarmupWritesIgnored { addr, count } => {
+                        // A homebrew bug a real console would show, so say how to fix it
+                        screen.warn(format!(
+                            "{} PPU write(s) before warm-up ignored, first to ${:04X} — wait for two vblanks after reset",
+                            count, addr
+                        ));
+                    }
+                }
+            }
+
+            // Update display now, or leave the frame for the UI's next redraw
+            match pacing_mode {
+                PacingMode::Emulation => screen.show_frame(rgba_data, screen_size),
+                PacingMode::Display => {
+                    frames.push((rgba_data, screen_size));
+                }
+            }
+
+            // FPS calculation
+            frame_count += 1;
+
+            // Debug: log frame count every 60 frames
+            if frame_count % 60 == 0 {
+                trace!("Frame {}", frame_count);
+            }
+
+            if fps_timer.elapsed() >= Duration::from_secs(1) {
+                let audio_stats = audio.as_ref().map(AudioSystem::audio_stats);
+                if let Some(stats) = audio_stats.filter(|stats| stats.underruns > underruns_seen) {
+                    if speed == menu::NORMAL_SPEED {
+                        status.send(StatusUpdate::warning("Audio underrun")).ok();
+                    }
+                    underruns_seen = stats.underruns;
+                }
+                status.send(StatusUpdate::Emulation {
+                    fps: frame_count as f32 / fps_timer.elapsed().as_secs_f32(),
+                    frame,
+                    audio_fill: audio_stats.map(|stats| stats.fill),
+                }).ok();
+                status.send(StatusUpdate::Lint { findings: lint_findings }).ok();
+                frame_count = 0;
+                fps_timer = Instant::now();
+            }
+
+            // Frame timing: display-paced emulation keeps the audio
+            // buffer topped up instead, when there is audio to follow
+            match (pacing_mode, audio.as_ref()) {
+                (PacingMode::Display, Some(audio_system)) if speed == menu::NORMAL_SPEED => {
+                    let fill = audio_system.audio_stats().fill;
+                    thread::sleep(pacing::audio_master_delay(fill, AUDIO_BUFFER_SIZE, SAMPLE_RATE, frame_duration));
+                }
+                _ => {
+                    let frame_duration = pacing::frame_duration_at(frame_duration, speed);
+                    let elapsed = frame_start.elapsed();
+                    if elapsed < frame_duration {
+                        thread::sleep(frame_duration - elapsed);
+                    }
+                }
+            }
+        }
+
+        // Fade out audio to prevent pop
+        if let Some(ref audio_system) = audio {
+            audio_system.fade_out();
+        }
+        // A newer loop has taken over the screen
+        if !control.is_current(generation) {
+            println!("Emulation thread replaced");
+            return;
+        }
+        println!("Emulation thread ended");
+        // A frame still queued would cover the black screen
+        frames.clear();
+        status.send(StatusUpdate::Stopped).ok();
+        // Clear the screen when stopped; pausing leaves the last frame up
+        let paused = control.state() == RunState::Paused;
+        screen.stopped((!paused).then_some(black_screen));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use emu_nes::{EmulatorService, NesSystem};
+    use crate::cores::Core;
+
+    /// Counts what the loop shows instead of drawing it
+    #[derive(Clone, Default)]
+    struct CountingScreen {
+        frames: Arc<AtomicUsize>,
+        stops: Arc<AtomicUsize>,
+    }
+
+    impl Screen for CountingScreen {
+        fn show_frame(&self, _rgba: Vec<u8>, _size: (usize, usize)) {
+            self.frames.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn warn(&self, _text: String) {}
+
+        fn stopped(&self, _black_screen: Option<(Vec<u8>, (usize, usize))>) {
+            self.stops.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// A loop with no audio around an NROM game that spins on NOPs
+    fn headless_loop() -> (EmulationLoop, mpsc::Receiver<StatusUpdate>) {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        rom.extend_from_slice(&[0xEA; 0x4000]);
+        rom.extend_from_slice(&[0; 0x2000]);
+        let core: Core = Box::new(NesSystem::from_bytes(&rom).unwrap());
+        let (emulator, _) = EmulatorService::spawn(Some(core));
+        let (status, receiver) = mpsc::channel();
+        let emulation = EmulationLoop {
+            emulator,
+            control: Arc::new(RunControl::new()),
+            input: Arc::new(PadInput::new()),
+            settings: Arc::new(LoopSettings::new()),
+            frames: Arc::new(FrameQueue::new(pacing::QUEUE_DEPTH)),
+            status,
+            audio: false,
+        };
+        (emulation, receiver)
+    }
+
+    /// Join `thread`, failing if it takes longer than `limit`
+    fn join_within(thread: JoinHandle<()>, limit: Duration) {
+        let (done, finished) = mpsc::channel();
+        thread::spawn(move || done.send(thread.join().is_ok()).ok());
+        assert_eq!(finished.recv_timeout(limit), Ok(true), "loop didn't stop within {:?}", limit);
+    }
+
+    #[test]
+    fn test_pause_keeps_the_picture_and_stop_clears_it() {
+        let (emulation, _status) = headless_loop();
+        let control = emulation.control.clone();
+        let screen = CountingScreen::default();
+        let thread = emulation.clone().spawn(screen.clone()).unwrap();
+        assert!(emulation.clone().spawn(screen.clone()).is_none());
+        while screen.frames.load(Ordering::Relaxed) < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(control.pause());
+        join_within(thread, Duration::from_secs(2));
+        assert_eq!(control.state(), RunState::Paused);
+        assert_eq!(screen.stops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_keys_reach_the_system_while_running() {
+        let (emulation, _status) = headless_loop();
+        let thread = emulation.clone().spawn(CountingScreen::default()).unwrap();
+        emulation.input.set_key("z", true);
+
+        // Applied before the next frame, without waiting on a lock
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            let held = emulation.emulator.call(|core| {
+                let system = core.as_mut().and_then(cores::nes).unwrap();
+                system.controller1().state().buttons
+            });
+            if !held.wait().unwrap().is_empty() {
+                break;
+            }
+            assert!(Instant::now() < deadline, "key press never reached the system");
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        emulation.control.stop();
+        join_within(thread, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_stress_input_and_start_stop() {
+        let (emulation, _status) = headless_loop();
+        let screen = CountingScreen::default();
+        let until = Instant::now() + Duration::from_secs(2);
+
+        // Keys going up and down as fast as they can
+        let input = emulation.input.clone();
+        let typist = thread::spawn(move || {
+            let mut presses = 0u64;
+            while Instant::now() < until {
+                for key in ["z", "x", "↑", "\r"] {
+                    input.set_key(key, presses.is_multiple_of(2));
+                }
+                presses += 1;
+            }
+            presses
+        });
+
+        // Start, pause and stop from another thread, never waiting for a frame
+        let control = emulation.control.clone();
+        let starter = {
+            let emulation = emulation.clone();
+            let screen = screen.clone();
+            thread::spawn(move || {
+                let mut threads = Vec::new();
+                let mut round = 0u32;
+                while Instant::now() < until {
+                    threads.extend(emulation.clone().spawn(screen.clone()));
+                    thread::sleep(Duration::from_millis(u64::from(round % 7)));
+                    if round.is_multiple_of(3) {
+                        control.pause();
+                    } else {
+                        control.stop();
+                    }
+                    round += 1;
+                }
+                threads
+            })
+        };
+
+        // Meanwhile commands keep getting through between frames
+        let mut slowest = Duration::ZERO;
+        while Instant::now() < until {
+            let sent = Instant::now();
+            emulation.emulator.call(|core| core.is_some()).wait().unwrap();
+            slowest = slowest.max(sent.elapsed());
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert!(typist.join().unwrap() > 0);
+        let threads = starter.join().unwrap();
+        assert!(threads.len() > 10, "only {} starts", threads.len());
+        for thread in threads {
+            join_within(thread, Duration::from_secs(2));
+        }
+        // Far more than a frame, as on one CPU every thread here takes its
+        // turn; a command stuck behind a lock or a growing queue waits longer
+        assert!(slowest < Duration::from_secs(2), "a command waited {:?}", slowest);
+        assert_ne!(emulation.control.state(), RunState::Running);
+        assert!(screen.frames.load(Ordering::Relaxed) > 0);
+    }
+}
@@ -0,0 +1,195 @@
+//! Console-independent pieces of the emulation loop
+//!
+//! Everything here goes through [`Emulator`], so the loop's timing, picture
+//! and keyboard handling work the same for any core in [`crate::cores`].
+
+use std::time::Duration;
+use emu_core::{Emulator, Result};
+
+// The keyboard bindings are shared with the other frontends
+pub use input_handler::keyboard::{key_button, set_buttons};
+
+/// Real time one frame of `core` takes
+pub fn frame_duration(core: &dyn Emulator) -> Duration {
+    Duration::from_secs_f64(1.0 / core.frame_rate())
+}
+
+/// Run one frame, replacing `audio` with its samples, and return the picture as RGBA
+pub fn run_frame(core: &mut dyn Emulator, sample_rate: u32, audio: &mut Vec<(f32, f32)>) -> Result<Vec<u8>> {
+    audio.clear();
+    core.run_frame_with_audio(sample_rate, audio)?;
+    Ok(core.framebuffer_rgba())
+}
+
+/// An all-black RGBA picture the size of `core`'s screen
+pub fn black_screen(core: &dyn Emulator) -> Vec<u8> {
+    let (width, height) = core.screen_size();
+    let mut rgba = vec![0; width * height * 4];
+    for pixel in rgba.chunks_exact_mut(4) {
+        pixel[3] = 0xFF;
+    }
+    rgba
+}
+
+/// Wrap an RGBA picture of `(width, height)` pixels for display
+pub fn screen_image(rgba: &[u8], (width, height): (usize, usize)) -> slint::Image {
+    let buffer = slint::SharedPixelBuffer::clone_from_slice(rgba, width as u32, height as u32);
+    slint::Image::from_rgba8(buffer)
+}
+
+/// Encode an RGBA picture of `(width, height)` pixels as a binary (P6) PPM
+/// image, dropping the alpha channel
+pub fn ppm(rgba: &[u8], (width, height): (usize, usize)) -> Vec<u8> {
+    let mut data = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    data.extend(rgba.chunks_exact(4).flat_map(|pixel| &pixel[..3]));
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emu_core::InputDevice;
+    use emu_nes::apu_player::PAL_CPU_CLOCK_HZ;
+    use emu_nes::{NesSystem, Region};
+    use nes_asm::InesBuilder;
+    use std::any::Any;
+
+    /// A two-button pad
+    #[derive(Default)]
+    struct Pad {
+        pressed: [bool; 2],
+    }
+
+    impl InputDevice for Pad {
+        fn button_count(&self) -> usize {
+            2
+        }
+
+        fn button_name(&self, index: usize) -> &'static str {
+            ["A", "Start"][index]
+        }
+
+        fn is_button_pressed(&self, index: usize) -> bool {
+            self.pressed[index]
+        }
+
+        fn set_button_pressed(&mut self, index: usize, pressed: bool) {
+            self.pressed[index] = pressed;
+        }
+    }
+
+    /// A 4x2 console at 50 frames per second whose picture is the frame count
+    #[derive(Default)]
+    struct TestCore {
+        frames: u8,
+        pad: Pad,
+    }
+
+    impl Emulator for TestCore {
+        fn reset(&mut self) {
+            self.frames = 0;
+        }
+
+        fn run_frame(&mut self) -> Result<usize> {
+            self.frames += 1;
+            Ok(100)
+        }
+
+        fn run_frame_with_audio(&mut self, sample_rate: u32, audio: &mut Vec<(f32, f32)>) -> Result<usize> {
+            let level = self.frames as f32;
+            audio.extend((0..sample_rate / 50).map(|_| (level, -level)));
+            self.run_frame()
+        }
+
+        fn is_paused(&self) -> bool {
+            false
+        }
+
+        fn set_paused(&mut self, _paused: bool) {}
+
+        fn screen_size(&self) -> (usize, usize) {
+            (4, 2)
+        }
+
+        fn frame_rate(&self) -> f64 {
+            50.0
+        }
+
+        fn framebuffer_rgba(&mut self) -> Vec<u8> {
+            vec![self.frames; 4 * 2 * 4]
+        }
+
+        fn input_device(&mut self, port: usize) -> Option<&mut dyn InputDevice> {
+            match port {
+                0 => Some(&mut self.pad),
+                _ => None,
+            }
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn test_frame_timing_follows_the_core() {
+        assert_eq!(frame_duration(&TestCore::default()), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_frame_timing_of_a_pal_nes() {
+        let rom = InesBuilder::new(vec![0xEA; 0x4000]).build().unwrap();
+        let mut system = NesSystem::from_bytes(&rom).unwrap();
+        system.set_region_override(Some(Region::Pal));
+        // 33247 cycles of the PAL clock, to the nanosecond
+        let nanos = 33_247 * 1_000_000_000 / PAL_CPU_CLOCK_HZ as u64;
+        assert_eq!(nanos, 19_996_908);
+        assert_eq!(frame_duration(&system), Duration::from_nanos(nanos));
+    }
+
+    #[test]
+    fn test_run_frame_replaces_audio_and_returns_the_picture() {
+        let mut core = TestCore::default();
+        let mut audio = vec![(9.0, 9.0)];
+        let rgba = run_frame(&mut core, 1000, &mut audio).unwrap();
+        assert_eq!(audio, vec![(0.0, -0.0); 20]);
+        assert_eq!(rgba, vec![1; 32]);
+
+        run_frame(&mut core, 1000, &mut audio).unwrap();
+        assert_eq!(audio.len(), 20);
+        assert_eq!(audio[0], (1.0, -1.0));
+    }
+
+    #[test]
+    fn test_black_screen_matches_the_core() {
+        let black = black_screen(&TestCore::default());
+        assert_eq!(black.len(), 4 * 2 * 4);
+        assert!(black.chunks(4).all(|pixel| pixel == [0, 0, 0, 0xFF]));
+    }
+
+    #[test]
+    fn test_ppm_drops_alpha() {
+        let rgba = [1, 2, 3, 0xFF, 4, 5, 6, 0x80];
+        assert_eq!(ppm(&rgba, (2, 1)), b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06");
+    }
+
+    #[test]
+    fn test_keys_reach_buttons_by_name() {
+        let a = key_button("z").unwrap();
+        let start = key_button("\r").unwrap();
+        assert_eq!(key_button("Z"), Some(a));
+        assert_eq!(key_button("q"), None);
+
+        let mut core = TestCore::default();
+        set_buttons(&mut core, 0, 1 << a | 1 << start);
+        assert_eq!(core.pad.pressed, [true, true]);
+        set_buttons(&mut core, 0, 1 << start);
+        assert_eq!(core.pad.pressed, [false, true]);
+
+        // This pad has no Up, and there's no second pad
+        let up = key_button("w").unwrap();
+        set_buttons(&mut core, 0, 1 << up);
+        assert_eq!(core.pad.pressed, [false, false]);
+        set_buttons(&mut core, 1, 0xFF);
+    }
+}
@@ -1,4 +1,23 @@
 mod app;
+mod audio;
+#[cfg(feature = "chr-watch")]
+mod chr_watch;
+mod controls;
+mod cores;
+mod debugger;
+mod emulation;
+mod frame_loop;
+mod menu;
+mod overlay;
+mod pacing;
+mod ppu_viewer;
+mod rom_watch;
+mod run_ahead;
+mod session;
+mod settings;
+mod slots;
+mod status;
+mod video_filter;
 
 use app::EmulatorApp;
 
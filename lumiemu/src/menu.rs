@@ -0,0 +1,357 @@
+//! Menu bar routing and state
+//!
+//! Every item in the main window's menu bar reports itself through one
+//! `menu-activated` callback with an id such as `"load-rom"` or
+//! `"save-state/3"`. [`MenuAction::parse`] turns that back into an action
+//! for the app to route to the callback that carries it out, and
+//! [`MenuAction::enabled`] decides from a [`MenuContext`] which items can
+//! be used right now, so the `.slint` file only lays the menus out.
+
+use crate::settings::{AccuracyProfile, FrameBlendMode, PacingMode, ScaleMode};
+
+/// Emulation speeds in the Speed menu, in percent
+pub const SPEEDS: [u32; 5] = [25, 50, 100, 200, 400];
+
+/// Full speed, the only one audio plays at
+pub const NORMAL_SPEED: u32 = 100;
+
+/// Savestate slots in the Save State and Load State menus
+pub const STATE_SLOTS: u8 = 9;
+
+/// Something a menu item does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuAction {
+    // File
+    LoadRom,
+    /// Pick a ROM, then an IPS or BPS patch to apply to it
+    LoadRomWithPatch,
+    /// Load an entry of the recent ROMs list, newest first
+    OpenRecent(usize),
+    Screenshot,
+    Exit,
+
+    // Emulation
+    /// Start, pause or resume, whichever [`MenuContext::start_label`] says
+    StartPauseResume,
+    /// Stop, keeping progress in the auto slot
+    Stop,
+    ResumeSession,
+    SoftReset,
+    PowerCycle,
+    SaveState(u8),
+    LoadState(u8),
+    /// Run at this percentage of full speed
+    Speed(u32),
+    Pacing(PacingMode),
+    RunAhead(u8),
+    Accuracy(AccuracyProfile),
+    AutoReload,
+    KeepStateOnReload,
+    GameSettings,
+    RunMacro,
+    WatchChr,
+
+    // View
+    PpuViewer,
+    Debugger,
+    MemoryViewer,
+    /// The homebrew lint's findings, and its switch
+    Lint,
+    InputDisplay,
+    SpriteOverlay,
+    Scale(ScaleMode),
+    CropOverscan,
+    FrameBlend(FrameBlendMode),
+
+    // Help
+    About,
+}
+
+impl MenuAction {
+    /// The action for a menu item id, if it is one
+    pub fn parse(id: &str) -> Option<Self> {
+        let (name, arg) = match id.split_once('/') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (id, None),
+        };
+        let number = |arg: Option<&str>| arg.and_then(|arg| arg.parse::<u32>().ok());
+        let slot = |arg| number(arg).filter(|slot| (1..=STATE_SLOTS as u32).contains(slot)).map(|slot| slot as u8);
+
+        let action = match (name, arg) {
+            ("load-rom", None) => MenuAction::LoadRom,
+            ("load-rom-patch", None) => MenuAction::LoadRomWithPatch,
+            ("recent", arg) => MenuAction::OpenRecent(number(arg)? as usize),
+            ("screenshot", None) => MenuAction::Screenshot,
+            ("exit", None) => MenuAction::Exit,
+            ("start", None) => MenuAction::StartPauseResume,
+            ("stop", None) => MenuAction::Stop,
+            ("resume-session", None) => MenuAction::ResumeSession,
+            ("soft-reset", None) => MenuAction::SoftReset,
+            ("power-cycle", None) => MenuAction::PowerCycle,
+            ("save-state", arg) => MenuAction::SaveState(slot(arg)?),
+            ("load-state", arg) => MenuAction::LoadState(slot(arg)?),
+            ("speed", arg) => MenuAction::Speed(number(arg).filter(|speed| SPEEDS.contains(speed))?),
+            ("pacing", Some("emulation")) => MenuAction::Pacing(PacingMode::Emulation),
+            ("pacing", Some("display")) => MenuAction::Pacing(PacingMode::Display),
+            ("run-ahead", arg) => MenuAction::RunAhead(
+                number(arg).filter(|&frames| frames <= crate::run_ahead::MAX_FRAMES as u32)? as u8,
+            ),
+            ("accuracy", Some("compatible")) => MenuAction::Accuracy(AccuracyProfile::Compatible),
+            ("accuracy", Some("hardware-strict")) => MenuAction::Accuracy(AccuracyProfile::HardwareStrict),
+            ("auto-reload", None) => MenuAction::AutoReload,
+            ("keep-state", None) => MenuAction::KeepStateOnReload,
+            ("game-settings", None) => MenuAction::GameSettings,
+            ("run-macro", None) => MenuAction::RunMacro,
+            ("watch-chr", None) => MenuAction::WatchChr,
+            ("ppu-viewer", None) => MenuAction::PpuViewer,
+            ("debugger", None) => MenuAction::Debugger,
+            ("memory-viewer", None) => MenuAction::MemoryViewer,
+            ("lint", None) => MenuAction::Lint,
+            ("input-display", None) => MenuAction::InputDisplay,
+            ("sprite-overlay", None) => MenuAction::SpriteOverlay,
+            ("scale", Some("square")) => MenuAction::Scale(ScaleMode::Square),
+            ("scale", Some("ntsc")) => MenuAction::Scale(ScaleMode::Ntsc),
+            ("scale", Some("stretch")) => MenuAction::Scale(ScaleMode::Stretch),
+            ("crop-overscan", None) => MenuAction::CropOverscan,
+            ("frame-blend", Some("off")) => MenuAction::FrameBlend(FrameBlendMode::Off),
+            ("frame-blend", Some("blend")) => MenuAction::FrameBlend(FrameBlendMode::Blend),
+            ("frame-blend", Some("phosphor")) => MenuAction::FrameBlend(FrameBlendMode::Phosphor),
+            ("about", None) => MenuAction::About,
+            _ => return None,
+        };
+        Some(action)
+    }
+
+    /// Whether the item can be used in `context`
+    ///
+    /// Anything that acts on the game needs one loaded; settings can be
+    /// changed at any time.
+    pub fn enabled(self, context: &MenuContext) -> bool {
+        match self {
+            MenuAction::OpenRecent(index) => index < context.recent_roms,
+            MenuAction::Stop => context.rom_loaded && (context.running || context.paused),
+            MenuAction::ResumeSession => context.resume_available && !context.running,
+            MenuAction::WatchChr => context.rom_loaded && context.chr_watch,
+            MenuAction::KeepStateOnReload => context.auto_reload,
+            MenuAction::StartPauseResume
+            | MenuAction::Screenshot
+            | MenuAction::SoftReset
+            | MenuAction::PowerCycle
+            | MenuAction::SaveState(_)
+            | MenuAction::LoadState(_)
+            | MenuAction::GameSettings
+            | MenuAction::RunMacro
+            | MenuAction::PpuViewer
+            | MenuAction::Debugger
+            | MenuAction::MemoryViewer
+            | MenuAction::Lint => context.rom_loaded,
+            MenuAction::LoadRom
+            | MenuAction::LoadRomWithPatch
+            | MenuAction::Exit
+            | MenuAction::Speed(_)
+            | MenuAction::Pacing(_)
+            | MenuAction::RunAhead(_)
+            | MenuAction::Accuracy(_)
+            | MenuAction::AutoReload
+            | MenuAction::InputDisplay
+            | MenuAction::SpriteOverlay
+            | MenuAction::Scale(_)
+            | MenuAction::CropOverscan
+            | MenuAction::FrameBlend(_)
+            | MenuAction::About => true,
+        }
+    }
+}
+
+/// The app state the menus depend on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MenuContext {
+    pub rom_loaded: bool,
+    /// The emulation thread is running
+    pub running: bool,
+    /// Stopped without a reset, so Start carries on from the same frame
+    pub paused: bool,
+    /// The last session can be resumed
+    pub resume_available: bool,
+    /// Entries in the recent ROMs list
+    pub recent_roms: usize,
+    /// The ROM is reloaded when it changes on disk
+    pub auto_reload: bool,
+    /// Built with the `chr-watch` feature
+    pub chr_watch: bool,
+}
+
+impl MenuContext {
+    /// Title of the [`MenuAction::StartPauseResume`] item
+    pub fn start_label(&self) -> &'static str {
+        if self.running {
+            "Pause"
+        } else if self.paused {
+            "Resume"
+        } else {
+            "Start"
+        }
+    }
+}
+
+/// Version and build details for Help > About
+pub fn build_info() -> String {
+    let mut features = Vec::new();
+    if cfg!(feature = "chr-watch") {
+        features.push("chr-watch");
+    }
+    format!(
+        "LumiEmu {}\n{} build for {}-{}\nFeatures: {}",
+        env!("CARGO_PKG_VERSION"),
+        if cfg!(debug_assertions) { "Debug" } else { "Release" },
+        std::env::consts::ARCH,
+        std::env::consts::OS,
+        if features.is_empty() { "none".to_string() } else { features.join(", ") },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every id the menu bar sends, and whether the `for` loop it's in
+    /// appends a number to it
+    fn slint_ids() -> Vec<(&'static str, bool)> {
+        let slint = include_str!("../ui/emulator.slint");
+        slint
+            .split("menu-activated(\"")
+            .skip(1)
+            .map(|rest| {
+                let (id, after) = rest.split_once('"').unwrap();
+                (id, after.trim_start().starts_with('+'))
+            })
+            .collect()
+    }
+
+    fn loaded() -> MenuContext {
+        MenuContext {
+            rom_loaded: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_every_menu_item_routes_somewhere() {
+        let ids = slint_ids();
+        assert!(ids.len() > 20, "{:?}", ids);
+        for (id, numbered) in ids {
+            let routed = if numbered {
+                (0..=SPEEDS[SPEEDS.len() - 1]).any(|number| MenuAction::parse(&format!("{}{}", id, number)).is_some())
+            } else {
+                MenuAction::parse(id).is_some()
+            };
+            assert!(routed, "{} isn't routed", id);
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(MenuAction::parse("load-rom"), Some(MenuAction::LoadRom));
+        assert_eq!(MenuAction::parse("load-rom-patch"), Some(MenuAction::LoadRomWithPatch));
+        assert_eq!(MenuAction::parse("recent/2"), Some(MenuAction::OpenRecent(2)));
+        assert_eq!(MenuAction::parse("save-state/9"), Some(MenuAction::SaveState(9)));
+        assert_eq!(MenuAction::parse("load-state/1"), Some(MenuAction::LoadState(1)));
+        assert_eq!(MenuAction::parse("speed/200"), Some(MenuAction::Speed(200)));
+        assert_eq!(MenuAction::parse("scale/stretch"), Some(MenuAction::Scale(ScaleMode::Stretch)));
+        assert_eq!(MenuAction::parse("lint"), Some(MenuAction::Lint));
+        assert_eq!(MenuAction::parse("frame-blend/phosphor"), Some(MenuAction::FrameBlend(FrameBlendMode::Phosphor)));
+        assert_eq!(MenuAction::parse("pacing/display"), Some(MenuAction::Pacing(PacingMode::Display)));
+        assert_eq!(MenuAction::parse("run-ahead/0"), Some(MenuAction::RunAhead(0)));
+        assert_eq!(
+            MenuAction::parse("accuracy/hardware-strict"),
+            Some(MenuAction::Accuracy(AccuracyProfile::HardwareStrict))
+        );
+
+        // Out of range, malformed, or unknown
+        for id in ["save-state/0", "load-state/10", "speed/150", "run-ahead/9", "recent", "recent/x", "scale/big", "frame-blend", "about/1", "", "open"] {
+            assert_eq!(MenuAction::parse(id), None, "{}", id);
+        }
+    }
+
+    #[test]
+    fn test_nothing_loaded() {
+        let context = MenuContext::default();
+        for action in [
+            MenuAction::StartPauseResume,
+            MenuAction::Stop,
+            MenuAction::Screenshot,
+            MenuAction::SoftReset,
+            MenuAction::PowerCycle,
+            MenuAction::SaveState(1),
+            MenuAction::LoadState(1),
+            MenuAction::Debugger,
+            MenuAction::PpuViewer,
+            MenuAction::MemoryViewer,
+            MenuAction::Lint,
+            MenuAction::GameSettings,
+            MenuAction::RunMacro,
+            MenuAction::OpenRecent(0),
+            MenuAction::ResumeSession,
+        ] {
+            assert!(!action.enabled(&context), "{:?}", action);
+        }
+        for action in [
+            MenuAction::LoadRom,
+            MenuAction::LoadRomWithPatch,
+            MenuAction::Exit,
+            MenuAction::About,
+            MenuAction::Speed(NORMAL_SPEED),
+            MenuAction::Scale(ScaleMode::Ntsc),
+            MenuAction::InputDisplay,
+        ] {
+            assert!(action.enabled(&context), "{:?}", action);
+        }
+        assert_eq!(context.start_label(), "Start");
+    }
+
+    #[test]
+    fn test_loaded_running_and_paused() {
+        // Loaded, not started: nothing to stop yet
+        let context = loaded();
+        assert!(MenuAction::StartPauseResume.enabled(&context));
+        assert!(MenuAction::SaveState(3).enabled(&context));
+        assert!(MenuAction::Debugger.enabled(&context));
+        assert!(!MenuAction::Stop.enabled(&context));
+
+        let running = MenuContext { running: true, ..loaded() };
+        assert!(MenuAction::Stop.enabled(&running));
+        assert_eq!(running.start_label(), "Pause");
+
+        let paused = MenuContext { paused: true, ..loaded() };
+        assert!(MenuAction::Stop.enabled(&paused));
+        assert_eq!(paused.start_label(), "Resume");
+    }
+
+    #[test]
+    fn test_resume_recent_and_toggles() {
+        let context = MenuContext {
+            resume_available: true,
+            recent_roms: 2,
+            ..Default::default()
+        };
+        assert!(MenuAction::ResumeSession.enabled(&context));
+        assert!(!MenuAction::ResumeSession.enabled(&MenuContext { running: true, ..context }));
+        assert!(MenuAction::OpenRecent(1).enabled(&context));
+        assert!(!MenuAction::OpenRecent(2).enabled(&context));
+
+        // Keeping state only means something with auto-reload on
+        assert!(!MenuAction::KeepStateOnReload.enabled(&context));
+        assert!(MenuAction::KeepStateOnReload.enabled(&MenuContext { auto_reload: true, ..context }));
+
+        // Watching CHR needs the feature and a ROM to patch
+        assert!(!MenuAction::WatchChr.enabled(&MenuContext { chr_watch: true, ..context }));
+        assert!(MenuAction::WatchChr.enabled(&MenuContext { chr_watch: true, ..loaded() }));
+        assert!(!MenuAction::WatchChr.enabled(&loaded()));
+    }
+
+    #[test]
+    fn test_build_info_names_the_version() {
+        let info = build_info();
+        assert!(info.starts_with(&format!("LumiEmu {}", env!("CARGO_PKG_VERSION"))), "{}", info);
+    }
+}
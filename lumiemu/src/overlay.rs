@@ -0,0 +1,351 @@
+//! Debug overlay and input display drawn over the game screen
+//!
+//! Everything here draws straight into the 256x240 RGBA buffer before it is
+//! uploaded to the UI, clipping anything that falls off the screen.
+
+use emu_nes::BankState;
+
+const WIDTH: i32 = 256;
+const HEIGHT: i32 = 240;
+
+/// RGBA color
+pub type Color = [u8; 4];
+
+/// Outline color for sprite 0 (used for sprite 0 hit, so it stands out)
+pub const SPRITE_ZERO_COLOR: Color = [255, 64, 64, 255];
+/// Outline color for all other sprites
+pub const SPRITE_COLOR: Color = [64, 255, 64, 255];
+/// Text color for the register readout
+pub const TEXT_COLOR: Color = [255, 255, 255, 255];
+/// Background behind the register readout
+pub const TEXT_BACKGROUND: Color = [0, 0, 0, 255];
+/// Controller body in the input display
+pub const PAD_BODY: Color = [48, 48, 48, 255];
+/// Controller outline in the input display
+pub const PAD_OUTLINE: Color = [160, 160, 160, 255];
+/// A button that isn't pressed
+pub const PAD_RELEASED: Color = [96, 96, 96, 255];
+/// A button that is pressed
+pub const PAD_PRESSED: Color = [255, 64, 64, 255];
+
+/// Size of one controller in the input display
+pub const PAD_WIDTH: i32 = 34;
+pub const PAD_HEIGHT: i32 = 13;
+
+/// Each button's rectangle within the pad (x, y, w, h), in latch bit order:
+/// A, B, Select, Start, Up, Down, Left, Right
+const PAD_BUTTONS: [(i32, i32, i32, i32); 8] = [
+    (28, 5, 4, 4),
+    (23, 5, 4, 4),
+    (13, 7, 4, 2),
+    (18, 7, 4, 2),
+    (5, 2, 3, 3),
+    (5, 8, 3, 3),
+    (2, 5, 3, 3),
+    (8, 5, 3, 3),
+];
+
+/// Snapshot of the PPU state the overlay needs
+pub struct PpuSnapshot {
+    pub oam: [u8; 256],
+    pub ctrl: u8,
+    pub mask: u8,
+    pub scroll_x: u16,
+    pub scroll_y: u16,
+}
+
+/// Set one pixel, ignoring coordinates outside the screen
+pub fn put_pixel(rgba: &mut [u8], x: i32, y: i32, color: Color) {
+    if !(0..WIDTH).contains(&x) || !(0..HEIGHT).contains(&y) {
+        return;
+    }
+    let offset = ((y * WIDTH + x) * 4) as usize;
+    if let Some(pixel) = rgba.get_mut(offset..offset + 4) {
+        pixel.copy_from_slice(&color);
+    }
+}
+
+/// Fill a rectangle, clipped to the screen
+pub fn fill_rect(rgba: &mut [u8], x: i32, y: i32, w: i32, h: i32, color: Color) {
+    for py in y.max(0)..(y + h).min(HEIGHT) {
+        for px in x.max(0)..(x + w).min(WIDTH) {
+            put_pixel(rgba, px, py, color);
+        }
+    }
+}
+
+/// Draw a 1-pixel rectangle outline, clipped to the screen
+pub fn draw_rect(rgba: &mut [u8], x: i32, y: i32, w: i32, h: i32, color: Color) {
+    if w <= 0 || h <= 0 {
+        return;
+    }
+    fill_rect(rgba, x, y, w, 1, color);
+    fill_rect(rgba, x, y + h - 1, w, 1, color);
+    fill_rect(rgba, x, y, 1, h, color);
+    fill_rect(rgba, x + w - 1, y, 1, h, color);
+}
+
+/// 3x5 glyph for a character (one row per byte, bit 2 = leftmost column)
+fn glyph(c: char) -> Option<[u8; 5]> {
+    Some(match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        ' ' => [0; 5],
+        _ => return None,
+    })
+}
+
+/// Draw text in the 3x5 font (4 pixels per character); unknown characters are skipped
+pub fn draw_text(rgba: &mut [u8], x: i32, y: i32, text: &str, color: Color) {
+    for (i, c) in text.chars().enumerate() {
+        let Some(rows) = glyph(c) else { continue };
+        let cx = x + i as i32 * 4;
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) != 0 {
+                    put_pixel(rgba, cx + col, y + row as i32, color);
+                }
+            }
+        }
+    }
+}
+
+/// Outline every visible sprite and label it with its OAM index
+pub fn draw_sprites(rgba: &mut [u8], oam: &[u8; 256], tall_sprites: bool) {
+    let height = if tall_sprites { 16 } else { 8 };
+
+    // Draw back to front so lower indices (higher priority) end up on top
+    for index in (0..64).rev() {
+        let entry = &oam[index * 4..index * 4 + 4];
+        // Y values of $EF and up hide the sprite
+        if entry[0] >= 0xEF {
+            continue;
+        }
+        let y = entry[0] as i32 + 1;
+        let x = entry[3] as i32;
+        let color = if index == 0 { SPRITE_ZERO_COLOR } else { SPRITE_COLOR };
+        draw_rect(rgba, x, y, 8, height, color);
+        draw_text(rgba, x + 1, y - 6, &index.to_string(), color);
+    }
+}
+
+/// One line of bank numbers, in hex, or `--` for an open-bus window
+fn bank_line(label: &str, mappings: &[emu_nes::BankMapping]) -> String {
+    let banks: Vec<String> = mappings
+        .iter()
+        .map(|mapping| match mapping.bank_index {
+            Some(bank) => format!("{:02X}", bank),
+            None => "  ".to_string(),
+        })
+        .collect();
+    format!("{}:{}", label, banks.join(" "))
+}
+
+/// Draw the scroll and PPUCTRL/PPUMASK readout in the top-left corner
+///
+/// With `banks`, two more lines list the PRG bank in each 8KB window and
+/// the CHR bank in each 1KB window.
+pub fn draw_readout(rgba: &mut [u8], ppu: &PpuSnapshot, banks: Option<&BankState>) {
+    let mut lines = vec![
+        format!("SX:{:03} SY:{:03}", ppu.scroll_x, ppu.scroll_y),
+        format!("C:{:02X} M:{:02X}", ppu.ctrl, ppu.mask),
+    ];
+    if let Some(banks) = banks {
+        lines.push(bank_line("PRG", &banks.prg));
+        lines.push(bank_line("CHR", &banks.chr));
+    }
+    let width = lines.iter().map(|line| line.len()).max().unwrap_or(0) as i32 * 4 + 3;
+    fill_rect(rgba, 0, 0, width, lines.len() as i32 * 6 + 3, TEXT_BACKGROUND);
+    for (row, line) in lines.iter().enumerate() {
+        draw_text(rgba, 2, 2 + row as i32 * 6, line, TEXT_COLOR);
+    }
+}
+
+/// Draw one controller with its top-left corner at (x, y), lighting the
+/// buttons set in `buttons` (a latched controller byte)
+pub fn draw_pad(rgba: &mut [u8], x: i32, y: i32, buttons: u8) {
+    fill_rect(rgba, x, y, PAD_WIDTH, PAD_HEIGHT, PAD_BODY);
+    draw_rect(rgba, x, y, PAD_WIDTH, PAD_HEIGHT, PAD_OUTLINE);
+    // The middle of the D-pad, so it reads as a cross
+    fill_rect(rgba, x + 5, y + 5, 3, 3, PAD_RELEASED);
+    for (bit, &(bx, by, w, h)) in PAD_BUTTONS.iter().enumerate() {
+        let color = if buttons & (1 << bit) != 0 { PAD_PRESSED } else { PAD_RELEASED };
+        fill_rect(rgba, x + bx, y + by, w, h, color);
+    }
+}
+
+/// Draw a controller per player in the bottom-right corner, player 1 lowest
+///
+/// It sits above the bottom 8 lines so cropping the overscan doesn't hide it.
+pub fn draw_input_display(rgba: &mut [u8], players: &[u8]) {
+    let x = WIDTH - PAD_WIDTH - 4;
+    for (index, &buttons) in players.iter().enumerate() {
+        let y = HEIGHT - 8 - 2 - PAD_HEIGHT - index as i32 * (PAD_HEIGHT + 2);
+        draw_pad(rgba, x, y, buttons);
+    }
+}
+
+/// Draw the full overlay: sprite boxes plus the register and bank readout
+pub fn draw_overlay(rgba: &mut [u8], ppu: &PpuSnapshot, banks: Option<&BankState>) {
+    // PPUCTRL bit 5 selects 8x16 sprites
+    draw_sprites(rgba, &ppu.oam, ppu.ctrl & 0x20 != 0);
+    draw_readout(rgba, ppu, banks);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blank() -> Vec<u8> {
+        vec![0; (WIDTH * HEIGHT * 4) as usize]
+    }
+
+    fn pixel(rgba: &[u8], x: i32, y: i32) -> Color {
+        let offset = ((y * WIDTH + x) * 4) as usize;
+        rgba[offset..offset + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_rect_clipped_at_edges() {
+        let mut rgba = blank();
+        // Hangs off the top-left and bottom-right corners
+        draw_rect(&mut rgba, -4, -4, 8, 8, SPRITE_COLOR);
+        draw_rect(&mut rgba, 250, 235, 16, 16, SPRITE_COLOR);
+
+        assert_eq!(pixel(&rgba, 3, 0), SPRITE_COLOR);
+        assert_eq!(pixel(&rgba, 0, 3), SPRITE_COLOR);
+        assert_eq!(pixel(&rgba, 2, 2), [0; 4]);
+        assert_eq!(pixel(&rgba, 250, 239), SPRITE_COLOR);
+        assert_eq!(pixel(&rgba, 255, 235), SPRITE_COLOR);
+        assert_eq!(rgba.len(), (WIDTH * HEIGHT * 4) as usize);
+    }
+
+    #[test]
+    fn test_text_clipped_at_edges() {
+        let mut rgba = blank();
+        draw_text(&mut rgba, 254, 237, "88", TEXT_COLOR);
+        draw_text(&mut rgba, -2, -3, "8", TEXT_COLOR);
+
+        // Only the visible corner of each glyph is drawn
+        assert_eq!(pixel(&rgba, 254, 237), TEXT_COLOR);
+        assert_eq!(pixel(&rgba, 0, 0), TEXT_COLOR);
+    }
+
+    #[test]
+    fn test_digit_glyph() {
+        let mut rgba = blank();
+        draw_text(&mut rgba, 10, 10, "1", TEXT_COLOR);
+
+        assert_eq!(pixel(&rgba, 11, 10), TEXT_COLOR);
+        assert_eq!(pixel(&rgba, 10, 10), [0; 4]);
+        assert_eq!(pixel(&rgba, 12, 14), TEXT_COLOR);
+    }
+
+    #[test]
+    fn test_bank_lines() {
+        let mut prg = vec![0xEA; 0x8000];
+        prg[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut system = emu_nes::NesSystem::with_prg_rom(prg).unwrap();
+        let banks = system.bank_state().unwrap();
+        assert_eq!(bank_line("PRG", &banks.prg), "PRG:00 01 02 03");
+        assert_eq!(bank_line("CHR", &banks.chr), "CHR:00 01 02 03 04 05 06 07");
+
+        // The bank lines make the readout box taller
+        let ppu = PpuSnapshot { oam: [0xFF; 256], ctrl: 0, mask: 0, scroll_x: 0, scroll_y: 0 };
+        let mut without = blank();
+        draw_readout(&mut without, &ppu, None);
+        let mut with = blank();
+        draw_readout(&mut with, &ppu, Some(&banks));
+        assert_eq!(pixel(&without, 1, 20), [0; 4]);
+        assert_eq!(pixel(&with, 1, 20), TEXT_BACKGROUND);
+    }
+
+    #[test]
+    fn test_sprite_zero_color_and_hidden_sprites() {
+        let mut rgba = blank();
+        let mut oam = [0xFF; 256];
+        oam[0..4].copy_from_slice(&[19, 0, 0, 40]); // Sprite 0 at (40, 20)
+        oam[4..8].copy_from_slice(&[99, 0, 0, 80]); // Sprite 1 at (80, 100)
+        draw_sprites(&mut rgba, &oam, true);
+
+        assert_eq!(pixel(&rgba, 40, 20), SPRITE_ZERO_COLOR);
+        assert_eq!(pixel(&rgba, 47, 35), SPRITE_ZERO_COLOR);
+        assert_eq!(pixel(&rgba, 80, 100), SPRITE_COLOR);
+        // Sprites with Y=$FF are hidden, so (255, 0)-ish stays clear
+        assert_eq!(pixel(&rgba, 255, 239), [0; 4]);
+    }
+
+    #[test]
+    fn test_pad_lights_latched_buttons() {
+        let mut rgba = blank();
+        // A and Right held
+        draw_pad(&mut rgba, 10, 20, 0x81);
+
+        assert_eq!(pixel(&rgba, 10, 20), PAD_OUTLINE);
+        assert_eq!(pixel(&rgba, 10 + 33, 20 + 12), PAD_OUTLINE);
+        assert_eq!(pixel(&rgba, 10 + 1, 20 + 1), PAD_BODY);
+        // A and B, left to right on the face
+        assert_eq!(pixel(&rgba, 10 + 29, 20 + 6), PAD_PRESSED);
+        assert_eq!(pixel(&rgba, 10 + 24, 20 + 6), PAD_RELEASED);
+        // Select and Start
+        assert_eq!(pixel(&rgba, 10 + 14, 20 + 7), PAD_RELEASED);
+        assert_eq!(pixel(&rgba, 10 + 19, 20 + 8), PAD_RELEASED);
+        // The D-pad: up, down, left, right and its middle
+        assert_eq!(pixel(&rgba, 10 + 6, 20 + 3), PAD_RELEASED);
+        assert_eq!(pixel(&rgba, 10 + 6, 20 + 9), PAD_RELEASED);
+        assert_eq!(pixel(&rgba, 10 + 3, 20 + 6), PAD_RELEASED);
+        assert_eq!(pixel(&rgba, 10 + 9, 20 + 6), PAD_PRESSED);
+        assert_eq!(pixel(&rgba, 10 + 6, 20 + 6), PAD_RELEASED);
+        // Nothing outside the pad
+        assert_eq!(pixel(&rgba, 10 + 34, 20), [0; 4]);
+        assert_eq!(pixel(&rgba, 10, 20 + 13), [0; 4]);
+
+        // Every button lit: exactly the 8 button rectangles change colour
+        let mut all = blank();
+        draw_pad(&mut all, 10, 20, 0xFF);
+        let pressed = all.chunks(4).filter(|&p| p == PAD_PRESSED).count();
+        let expected: i32 = PAD_BUTTONS.iter().map(|&(_, _, w, h)| w * h).sum();
+        assert_eq!(pressed as i32, expected);
+    }
+
+    #[test]
+    fn test_input_display_stacks_pads_above_overscan() {
+        let mut rgba = blank();
+        draw_input_display(&mut rgba, &[0x01, 0x02]);
+
+        // Player 1's bottom edge clears the 8 overscan lines
+        let x = WIDTH - PAD_WIDTH - 4;
+        assert_eq!(pixel(&rgba, x, HEIGHT - 11), PAD_OUTLINE);
+        assert!((HEIGHT - 8..HEIGHT).all(|y| pixel(&rgba, x, y) == [0; 4]));
+        // A lit on player 1, B on player 2 above it
+        let p1 = HEIGHT - 10 - PAD_HEIGHT;
+        let p2 = p1 - PAD_HEIGHT - 2;
+        assert_eq!(pixel(&rgba, x + 29, p1 + 6), PAD_PRESSED);
+        assert_eq!(pixel(&rgba, x + 24, p1 + 6), PAD_RELEASED);
+        assert_eq!(pixel(&rgba, x + 24, p2 + 6), PAD_PRESSED);
+        assert_eq!(pixel(&rgba, x + 29, p2 + 6), PAD_RELEASED);
+    }
+}
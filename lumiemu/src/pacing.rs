@@ -0,0 +1,188 @@
+//! Display-paced presentation
+//!
+//! In the default emulation-paced mode the emulation thread sleeps out each
+//! frame on its own clock and hands every picture to the UI as it goes. The
+//! monitor refreshes on a different clock, so now and then a frame is shown
+//! twice or not at all, which reads as judder.
+//!
+//! In display-paced mode the emulation thread instead follows the audio
+//! device ("audio-master sync"): it runs whenever the playback buffer drops
+//! below [`AUDIO_TARGET_FILL`], and puts each finished picture in a
+//! [`FrameQueue`]. The UI takes the newest one whenever it redraws and
+//! drops any it never got to. Without audio the thread falls back to the
+//! frame clock.
+//!
+//! The queue has its own lock, held only to push or take a frame; nobody
+//! holds it while locking the emulator or calling `invoke_from_event_loop`,
+//! so the two threads can't deadlock on it.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Frames the emulation thread may run ahead of the display
+pub const QUEUE_DEPTH: usize = 2;
+
+/// Playback buffer fill the emulation thread keeps topped up to, 0.0 to 1.0
+pub const AUDIO_TARGET_FILL: f32 = 0.25;
+
+/// A finished picture: RGBA pixels and their size
+pub type Frame = (Vec<u8>, (usize, usize));
+
+/// Finished frames waiting for the display
+///
+/// Pushing onto a full queue drops the oldest frame, so the emulation
+/// thread never waits for the UI.
+#[derive(Debug)]
+pub struct FrameQueue<T = Frame> {
+    frames: Mutex<VecDeque<T>>,
+    capacity: usize,
+}
+
+impl<T> FrameQueue<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Add a finished frame, returning the one it pushed out, if any
+    pub fn push(&self, frame: T) -> Option<T> {
+        let mut frames = self.frames.lock().unwrap();
+        let dropped = if frames.len() == self.capacity {
+            frames.pop_front()
+        } else {
+            None
+        };
+        frames.push_back(frame);
+        dropped
+    }
+
+    /// The newest frame, discarding any older ones still waiting
+    pub fn take_latest(&self) -> Option<T> {
+        let mut frames = self.frames.lock().unwrap();
+        let latest = frames.pop_back();
+        frames.clear();
+        latest
+    }
+
+    /// Drop every waiting frame, e.g. once emulation stops
+    pub fn clear(&self) {
+        self.frames.lock().unwrap().clear();
+    }
+
+    /// Frames waiting
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.frames.lock().unwrap().len()
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// How long to wait before running the next frame in display-paced mode
+///
+/// That's however long the audio device takes to play the playback buffer
+/// down from `fill` to [`AUDIO_TARGET_FILL`] (zero if it's already below),
+/// capped at two frames so a stalled audio device can't stop emulation.
+pub fn audio_master_delay(fill: f32, buffer_frames: usize, sample_rate: u32, frame_duration: Duration) -> Duration {
+    let excess = (fill - AUDIO_TARGET_FILL).max(0.0) * buffer_frames as f32;
+    Duration::from_secs_f32(excess / sample_rate as f32).min(frame_duration * 2)
+}
+
+/// How long a frame lasts at `speed` percent of full speed
+pub fn frame_duration_at(frame_duration: Duration, speed: u32) -> Duration {
+    frame_duration * 100 / speed.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_latest_skips_older_frames() {
+        let queue = FrameQueue::new(2);
+        assert_eq!(queue.take_latest(), None::<u32>);
+
+        queue.push(1);
+        queue.push(2);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.take_latest(), Some(2));
+        assert!(queue.is_empty());
+        assert_eq!(queue.take_latest(), None);
+    }
+
+    #[test]
+    fn test_full_queue_drops_the_oldest() {
+        let queue = FrameQueue::new(2);
+        assert_eq!(queue.push(1), None);
+        assert_eq!(queue.push(2), None);
+        assert_eq!(queue.push(3), Some(1));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.take_latest(), Some(3));
+
+        // A zero-size queue still holds the newest frame
+        let queue = FrameQueue::new(0);
+        queue.push(1);
+        assert_eq!(queue.push(2), Some(1));
+        assert_eq!(queue.take_latest(), Some(2));
+    }
+
+    #[test]
+    fn test_clear() {
+        let queue = FrameQueue::new(QUEUE_DEPTH);
+        queue.push(1);
+        queue.clear();
+        assert_eq!(queue.take_latest(), None);
+    }
+
+    #[test]
+    fn test_producer_and_consumer_threads() {
+        let queue = std::sync::Arc::new(FrameQueue::new(QUEUE_DEPTH));
+        let producer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || {
+                for frame in 0..1000u32 {
+                    queue.push(frame);
+                }
+            })
+        };
+        // Whatever the consumer sees only moves forward
+        let mut last = None;
+        while !producer.is_finished() || !queue.is_empty() {
+            if let Some(frame) = queue.take_latest() {
+                assert!(last < Some(frame));
+                last = Some(frame);
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(last, Some(999));
+    }
+
+    #[test]
+    fn test_audio_master_delay() {
+        let frame = Duration::from_millis(16);
+        let millis = |fill| audio_master_delay(fill, 4000, 40_000, frame).as_secs_f32() * 1000.0;
+        // With a 4000-frame buffer at 40kHz, each 0.1 over the target is 10ms
+        assert!((millis(0.35) - 10.0).abs() < 0.01);
+        assert!((millis(0.5) - 25.0).abs() < 0.01);
+        // Never more than two frames
+        assert_eq!(audio_master_delay(1.0, 4000, 40_000, frame), frame * 2);
+        // At or below the target: run straight away
+        assert_eq!(audio_master_delay(AUDIO_TARGET_FILL, 4000, 40_000, frame), Duration::ZERO);
+        assert_eq!(audio_master_delay(0.0, 4000, 40_000, frame), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_frame_duration_at_speed() {
+        let frame = Duration::from_millis(20);
+        assert_eq!(frame_duration_at(frame, 100), frame);
+        assert_eq!(frame_duration_at(frame, 200), Duration::from_millis(10));
+        assert_eq!(frame_duration_at(frame, 25), Duration::from_millis(80));
+        assert_eq!(frame_duration_at(frame, 0), frame * 100);
+    }
+}
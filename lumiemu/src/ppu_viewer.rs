@@ -0,0 +1,129 @@
+//! Pattern tables and palette RAM for the PPU viewer
+//!
+//! Both pattern tables are drawn side by side as one 256x128 RGBA picture,
+//! 16x16 tiles each, using one of the eight palettes in palette RAM. The
+//! palettes themselves are drawn as a 16x2 picture, one pixel per entry:
+//! background palettes on the top row, sprite palettes below.
+
+use emu_nes::palette::palette_to_rgb;
+use emu_nes::Ppu;
+
+/// Width of the pattern table picture, both tables side by side
+pub const PATTERN_WIDTH: usize = 256;
+/// Height of the pattern table picture
+pub const PATTERN_HEIGHT: usize = 128;
+
+/// Width of the palette picture, one pixel per entry
+pub const PALETTE_WIDTH: usize = 16;
+/// Height of the palette picture: background row, then sprite row
+pub const PALETTE_HEIGHT: usize = 2;
+
+/// Palettes to pick from: four background, then four sprite
+pub const PALETTES: u8 = 8;
+
+/// RGBA picture of both pattern tables, shaded with `colors`
+///
+/// `read_chr` reads pattern table memory from $0000-$1FFF; `colors` are
+/// the RGB colors for pixel values 0 to 3.
+pub fn pattern_tables(mut read_chr: impl FnMut(u16) -> u8, colors: [(u8, u8, u8); 4]) -> Vec<u8> {
+    let mut rgba = vec![0xFF; PATTERN_WIDTH * PATTERN_HEIGHT * 4];
+    for tile in 0..512u16 {
+        // Table 1 sits to the right of table 0
+        let tile_x = (tile / 256) as usize * 128 + (tile % 16) as usize * 8;
+        let tile_y = (tile % 256 / 16) as usize * 8;
+        for row in 0..8 {
+            let low = read_chr(tile * 16 + row);
+            let high = read_chr(tile * 16 + row + 8);
+            for column in 0..8 {
+                let bit = 7 - column;
+                let value = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+                let (r, g, b) = colors[value as usize];
+                let offset = ((tile_y + row as usize) * PATTERN_WIDTH + tile_x + column) * 4;
+                rgba[offset..offset + 3].copy_from_slice(&[r, g, b]);
+            }
+        }
+    }
+    rgba
+}
+
+/// RGBA picture of palette RAM, one pixel per entry
+pub fn palette_swatches(mut read_palette: impl FnMut(u16) -> u8) -> Vec<u8> {
+    (0..32u16)
+        .flat_map(|entry| {
+            let (r, g, b) = palette_to_rgb(read_palette(entry));
+            [r, g, b, 0xFF]
+        })
+        .collect()
+}
+
+/// The four colors of `palette` (0-3 background, 4-7 sprite)
+///
+/// Entry 0 of every palette shows the shared backdrop color, as it does
+/// on screen.
+pub fn palette_colors(mut read_palette: impl FnMut(u16) -> u8, palette: u8) -> [(u8, u8, u8); 4] {
+    let base = (palette % PALETTES) as u16 * 4;
+    [0, 1, 2, 3].map(|entry| palette_to_rgb(read_palette(if entry == 0 { 0 } else { base + entry })))
+}
+
+/// Pattern table and palette pictures of `ppu`'s current state
+pub fn capture(ppu: &Ppu, palette: u8) -> (Vec<u8>, Vec<u8>) {
+    let colors = palette_colors(|addr| ppu.read_palette_direct(addr), palette);
+    (
+        pattern_tables(|addr| ppu.read_chr_direct(addr), colors),
+        palette_swatches(|addr| ppu.read_palette_direct(addr)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLORS: [(u8, u8, u8); 4] = [(0, 0, 0), (1, 1, 1), (2, 2, 2), (3, 3, 3)];
+
+    fn pixel(rgba: &[u8], x: usize, y: usize) -> u8 {
+        rgba[(y * PATTERN_WIDTH + x) * 4]
+    }
+
+    #[test]
+    fn test_tile_decoding() {
+        // Tile 0 row 0: low plane %10000001, high plane %10000010
+        let rgba = pattern_tables(|addr| match addr {
+            0x0000 => 0x81,
+            0x0008 => 0x82,
+            _ => 0,
+        }, COLORS);
+        assert_eq!(rgba.len(), PATTERN_WIDTH * PATTERN_HEIGHT * 4);
+        let row: Vec<u8> = (0..8).map(|x| pixel(&rgba, x, 0)).collect();
+        assert_eq!(row, [3, 0, 0, 0, 0, 0, 2, 1]);
+        assert_eq!(pixel(&rgba, 0, 1), 0);
+        assert_eq!(rgba[3], 0xFF);
+    }
+
+    #[test]
+    fn test_tile_placement() {
+        // The last row of tile $1F (row 1, column 15) and the first of $100
+        let rgba = pattern_tables(|addr| match addr {
+            0x01F7 | 0x1000 => 0xFF,
+            _ => 0,
+        }, COLORS);
+        assert_eq!(pixel(&rgba, 120, 15), 1);
+        assert_eq!(pixel(&rgba, 127, 15), 1);
+        assert_eq!(pixel(&rgba, 128, 0), 1);
+        assert_eq!(pixel(&rgba, 128, 1), 0);
+        assert_eq!(pixel(&rgba, 0, 0), 0);
+    }
+
+    #[test]
+    fn test_palettes() {
+        let palette_ram: Vec<u8> = (0..32).collect();
+        let swatches = palette_swatches(|addr| palette_ram[addr as usize]);
+        assert_eq!(swatches.len(), PALETTE_WIDTH * PALETTE_HEIGHT * 4);
+        let (r, g, b) = palette_to_rgb(17);
+        assert_eq!(swatches[17 * 4..18 * 4], [r, g, b, 0xFF]);
+
+        // Sprite palette 1 is entries $15-$17, behind the backdrop
+        let colors = palette_colors(|addr| palette_ram[addr as usize], 5);
+        assert_eq!(colors, [0, 0x15, 0x16, 0x17].map(palette_to_rgb));
+        assert_eq!(palette_colors(|addr| palette_ram[addr as usize], 13), colors);
+    }
+}
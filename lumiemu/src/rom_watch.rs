@@ -0,0 +1,157 @@
+//! Auto-reload for a ROM that's rebuilt on disk
+//!
+//! Homebrew build scripts rewrite the .nes file constantly. The UI polls
+//! the loaded ROM's size and modification time, and only reports a change
+//! once it has held still for two polls in a row, so a file that's still
+//! being written isn't picked up half-done. Like the CHR watcher this polls
+//! rather than pulling in a platform file-notification dependency.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often the ROM file is checked
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What a poll can see of a file without reading it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub len: u64,
+    pub modified: SystemTime,
+}
+
+impl FileStamp {
+    /// Stamp the file at `path`
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(Self {
+            len: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+}
+
+/// Turns a stream of polled stamps into settled changes
+#[derive(Debug, Clone)]
+pub struct ChangeDebouncer {
+    /// Stamp of the version last reported (or loaded)
+    current: Option<FileStamp>,
+    /// A different stamp seen on the previous poll, waiting to settle
+    pending: Option<FileStamp>,
+}
+
+impl ChangeDebouncer {
+    /// Start from the stamp of the version already loaded
+    pub fn new(current: Option<FileStamp>) -> Self {
+        Self { current, pending: None }
+    }
+
+    /// Feed one poll's stamp, or `None` if the file couldn't be stamped
+    ///
+    /// Returns true when a new version has been the same for two polls.
+    pub fn poll(&mut self, stamp: Option<FileStamp>) -> bool {
+        // A missing file is usually mid-replace; wait for it to come back
+        let Some(stamp) = stamp.filter(|&stamp| Some(stamp) != self.current) else {
+            self.pending = None;
+            return false;
+        };
+        if self.pending == Some(stamp) {
+            self.current = Some(stamp);
+            self.pending = None;
+            true
+        } else {
+            self.pending = Some(stamp);
+            false
+        }
+    }
+}
+
+/// Watches one ROM file for rebuilt versions
+#[derive(Debug, Clone)]
+pub struct RomWatch {
+    path: PathBuf,
+    debouncer: ChangeDebouncer,
+}
+
+impl RomWatch {
+    /// Watch `path`, treating what's on disk now as already loaded
+    pub fn new(path: PathBuf) -> Self {
+        let debouncer = ChangeDebouncer::new(FileStamp::read(&path).ok());
+        Self { path, debouncer }
+    }
+
+    /// File being watched
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check the file, returning its contents once a new version has settled
+    pub fn poll(&mut self) -> Option<io::Result<Vec<u8>>> {
+        if self.debouncer.poll(FileStamp::read(&self.path).ok()) {
+            Some(fs::read(&self.path))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(len: u64, secs: u64) -> Option<FileStamp> {
+        Some(FileStamp {
+            len,
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+        })
+    }
+
+    #[test]
+    fn test_change_must_hold_for_two_polls() {
+        let mut debouncer = ChangeDebouncer::new(stamp(100, 1));
+        assert!(!debouncer.poll(stamp(100, 1)));
+
+        // Still growing: each poll sees something new
+        assert!(!debouncer.poll(stamp(40, 2)));
+        assert!(!debouncer.poll(stamp(80, 2)));
+        assert!(!debouncer.poll(stamp(120, 3)));
+        assert!(debouncer.poll(stamp(120, 3)));
+        // Reported once, not on every poll after
+        assert!(!debouncer.poll(stamp(120, 3)));
+
+        // Deleted and recreated: the gap resets the wait
+        assert!(!debouncer.poll(stamp(10, 4)));
+        assert!(!debouncer.poll(None));
+        assert!(!debouncer.poll(stamp(10, 4)));
+        assert!(debouncer.poll(stamp(10, 4)));
+
+        // Going back to the loaded version isn't a change
+        assert!(!debouncer.poll(stamp(120, 3)));
+        assert!(!debouncer.poll(stamp(10, 4)));
+    }
+
+    #[test]
+    fn test_rom_watch_reads_settled_file() {
+        let dir = std::env::temp_dir().join(format!("lumiemu-rom-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.nes");
+        fs::write(&path, [1u8; 16]).unwrap();
+
+        let mut watch = RomWatch::new(path.clone());
+        assert_eq!(watch.path(), path);
+        assert!(watch.poll().is_none());
+        assert!(watch.poll().is_none());
+
+        // Half written, then finished; sizes differ even if the mtime doesn't
+        fs::write(&path, [2u8; 8]).unwrap();
+        assert!(watch.poll().is_none());
+        fs::write(&path, [2u8; 32]).unwrap();
+        assert!(watch.poll().is_none());
+        assert_eq!(watch.poll().unwrap().unwrap(), vec![2u8; 32]);
+        assert!(watch.poll().is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+//! Run-ahead input latency reduction
+//!
+//! Most games act on input a frame or more after they read it. Run-ahead
+//! hides that: each real frame is followed by up to [`MAX_FRAMES`]
+//! speculative ones with the same input. Their last picture is shown, and
+//! then the machine goes back to the end of the real frame with
+//! [`NesSystem::quick_load`]. Only the real frame's audio is played.
+//!
+//! That costs one extra frame of emulation per frame of run-ahead, so the
+//! emulation thread watches how long frames take and turns run-ahead off
+//! when there isn't enough headroom (see [`Watchdog`]).
+
+use std::time::Duration;
+use emu_core::{Emulator, Result};
+use emu_nes::NesSystem;
+use crate::frame_loop;
+
+/// Most frames the picture can be run ahead by
+pub const MAX_FRAMES: u8 = 2;
+
+/// Share of the frame time emulation may take with run-ahead on
+const FRAME_BUDGET: f32 = 0.8;
+
+/// How many more over-budget frames than on-budget ones it takes to give up
+const SLOW_FRAME_LIMIT: u32 = 60;
+
+/// Run one frame, replacing `audio` with its samples, and return the
+/// picture from `frames` frames later as RGBA
+///
+/// Only the NES has quick states, so other cores (and a paused NES) run
+/// the frame as [`frame_loop::run_frame`] does.
+pub fn run_frame(core: &mut dyn Emulator, frames: u8, sample_rate: u32, audio: &mut Vec<(f32, f32)>) -> Result<Vec<u8>> {
+    match core.as_any_mut().downcast_mut::<NesSystem>() {
+        Some(system) if frames > 0 && !system.is_paused() => run_nes_frame(system, frames, sample_rate, audio),
+        _ => frame_loop::run_frame(core, sample_rate, audio),
+    }
+}
+
+fn run_nes_frame(system: &mut NesSystem, frames: u8, sample_rate: u32, audio: &mut Vec<(f32, f32)>) -> Result<Vec<u8>> {
+    // A picture is drawn over two frames (see `NesSystem::run_frames`), so
+    // only the last two of the sequence need to render
+    let rendering = system.render_enabled();
+    system.set_render_enabled(rendering && frames < 2);
+    audio.clear();
+    let real = system.run_frame_with_audio(sample_rate, audio);
+    let base = system.quick_save();
+
+    let ahead = real.map(drop).and_then(|()| {
+        (1..=frames).try_for_each(|frame| {
+            system.set_render_enabled(rendering && frames - frame < 2);
+            system.run_frame()
+        })
+    });
+    system.set_render_enabled(rendering);
+    let rgba = system.framebuffer_rgba();
+    system.quick_load(&base)?;
+    ahead.map(|()| rgba)
+}
+
+/// Decides when run-ahead can't keep up
+///
+/// Feed it the time each frame took to emulate. A frame over
+/// [`FRAME_BUDGET`] of the frame time counts against run-ahead and one
+/// under it counts for, so a brief stall is forgiven but a sustained
+/// shortfall isn't.
+#[derive(Debug, Default)]
+pub struct Watchdog {
+    slow_frames: u32,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a frame that took `elapsed` out of `frame_duration`
+    ///
+    /// Returns true once run-ahead should be turned off, after which the
+    /// count starts over.
+    pub fn frame_took(&mut self, elapsed: Duration, frame_duration: Duration) -> bool {
+        if elapsed.as_secs_f32() > frame_duration.as_secs_f32() * FRAME_BUDGET {
+            self.slow_frames += 1;
+        } else {
+            self.slow_frames = self.slow_frames.saturating_sub(1);
+        }
+        if self.slow_frames >= SLOW_FRAME_LIMIT {
+            self.slow_frames = 0;
+            return true;
+        }
+        false
+    }
+
+    /// Forget earlier frames, e.g. when run-ahead is turned back on
+    pub fn reset(&mut self) {
+        self.slow_frames = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emu_core::Button;
+    use nes_asm::{Assembler, InesBuilder};
+
+    /// Backdrop colours for A released and held
+    const RELEASED: u8 = 0x0F;
+    const HELD: u8 = 0x16;
+
+    /// A game that reads controller 1 in its NMI handler and shows the A
+    /// button as the backdrop colour from the next NMI, so it's drawn two
+    /// frames after the one that read it
+    fn input_rom() -> Vec<u8> {
+        let mut asm = Assembler::new(0xC000, 0x4000);
+        asm.label("reset")
+            .sei()
+            .label("vblank1")
+            .bit_abs(0x2002)
+            .bpl("vblank1")
+            .label("vblank2")
+            .bit_abs(0x2002)
+            .bpl("vblank2")
+            // Background on, with CHR-RAM blank so every pixel is the backdrop
+            .lda_imm(0x0A)
+            .sta_abs(0x2001)
+            .lda_imm(0x80)
+            .sta_abs(0x2000)
+            .label("main")
+            .jmp("main")
+            // Backdrop colour from the last reading, then point the PPU
+            // back at the nametable
+            .label("nmi")
+            .lda_zp(0x00)
+            .ldx_imm(0x3F)
+            .stx_abs(0x2006)
+            .ldx_imm(0x00)
+            .stx_abs(0x2006)
+            .sta_abs(0x2007)
+            .stx_abs(0x2006)
+            .stx_abs(0x2006)
+            .lda_imm(0x01)
+            .sta_abs(0x4016)
+            .lda_imm(0x00)
+            .sta_abs(0x4016)
+            .lda_abs(0x4016)
+            .and_imm(0x01)
+            .beq("released")
+            .lda_imm(HELD)
+            .bne("store")
+            .label("released")
+            .lda_imm(RELEASED)
+            .label("store")
+            .sta_zp(0x00)
+            .rti();
+        asm.vectors("nmi", "reset", "nmi");
+        InesBuilder::new(asm.assemble().unwrap()).build().unwrap()
+    }
+
+    /// Calls to `run_frame` after pressing A until the picture shows it
+    fn frames_until_shown(frames: u8) -> usize {
+        let mut system = NesSystem::from_bytes(&input_rom()).unwrap();
+        let mut audio = Vec::new();
+        for _ in 0..10 {
+            run_frame(&mut system, frames, 44_100, &mut audio).unwrap();
+        }
+        let (r, g, b) = emu_nes::palette_to_rgb(HELD);
+        let shows_held = |rgba: &[u8]| rgba[..4] == [r, g, b, 0xFF];
+        assert!(!shows_held(&system.framebuffer_rgba()));
+
+        system.press_button(Button::A);
+        (1..=10)
+            .find(|_| shows_held(&run_frame(&mut system, frames, 44_100, &mut audio).unwrap()))
+            .expect("the A button shows up")
+    }
+
+    #[test]
+    fn test_run_ahead_shows_input_sooner() {
+        let base = frames_until_shown(0);
+        assert_eq!(frames_until_shown(1), base - 1);
+        assert_eq!(frames_until_shown(2), base - 2);
+    }
+
+    #[test]
+    fn test_run_ahead_keeps_the_real_timeline() {
+        let mut plain = NesSystem::from_bytes(&input_rom()).unwrap();
+        let mut ahead = NesSystem::from_bytes(&input_rom()).unwrap();
+        let (mut plain_audio, mut ahead_audio) = (Vec::new(), Vec::new());
+        for frame in 0..20 {
+            if frame == 10 {
+                plain.press_button(Button::A);
+                ahead.press_button(Button::A);
+            }
+            run_frame(&mut plain, 0, 44_100, &mut plain_audio).unwrap();
+            run_frame(&mut ahead, 2, 44_100, &mut ahead_audio).unwrap();
+            assert_eq!(ahead_audio, plain_audio);
+            assert_eq!(ahead.frame(), plain.frame());
+        }
+        // Everything but the picture, which is from later on
+        let machine = |system: &mut NesSystem| {
+            let ram: Vec<u8> = (0..0x800).map(|addr| system.read_memory(addr)).collect();
+            (ram, system.cpu().pc, system.cpu().cycles, system.clock_stats())
+        };
+        assert_eq!(machine(&mut ahead), machine(&mut plain));
+    }
+
+    #[test]
+    fn test_watchdog_gives_up_on_sustained_slow_frames() {
+        let frame = Duration::from_millis(16);
+        let slow = Duration::from_millis(15);
+        let fast = Duration::from_millis(5);
+        let mut watchdog = Watchdog::new();
+
+        // Alternating slow and fast frames never trip it
+        for _ in 0..1000 {
+            assert!(!watchdog.frame_took(slow, frame));
+            assert!(!watchdog.frame_took(fast, frame));
+        }
+
+        let tripped = (1..=1000).find(|_| watchdog.frame_took(slow, frame));
+        assert_eq!(tripped, Some(SLOW_FRAME_LIMIT as usize));
+
+        watchdog.frame_took(slow, frame);
+        watchdog.reset();
+        assert!(!watchdog.frame_took(slow, frame));
+    }
+}
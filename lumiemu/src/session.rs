@@ -0,0 +1,293 @@
+//! Saving and resuming the GUI session across runs
+//!
+//! On exit the frontend writes the loaded ROM's path and CRC32, the current
+//! settings and a savestate to the config directory. On the next start the
+//! session is only offered for resuming if the ROM is still there, still has
+//! the same CRC32, and the savestate loads cleanly.
+//!
+//! The session is two files: `session.json` for the metadata and
+//! `session.state` for the raw savestate.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use emu_core::EmulatorError;
+use emu_nes::NesSystem;
+use serde::{Deserialize, Serialize};
+
+use crate::settings::{Settings, TvSystem};
+
+const METADATA_FILE: &str = "session.json";
+const STATE_FILE: &str = "session.state";
+
+/// Per-user config directory for lumiemu, if one can be determined
+pub fn config_dir() -> Option<PathBuf> {
+    let base = if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME") {
+        PathBuf::from(dir)
+    } else if cfg!(windows) {
+        PathBuf::from(std::env::var_os("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(std::env::var_os("HOME")?).join("Library/Application Support")
+    } else {
+        PathBuf::from(std::env::var_os("HOME")?).join(".config")
+    };
+    Some(base.join("lumiemu"))
+}
+
+/// Why a saved session can't be resumed
+#[derive(Debug)]
+pub enum SessionError {
+    /// Reading or writing the session files failed
+    Io(io::Error),
+    /// The session metadata couldn't be parsed
+    Corrupt(String),
+    /// The ROM is no longer at the saved path
+    RomMissing(PathBuf),
+    /// The ROM at the saved path has changed since the session was saved
+    CrcMismatch { expected: u32, found: u32 },
+    /// The ROM or savestate failed to load
+    Emulator(EmulatorError),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Corrupt(e) => write!(f, "session file is corrupt ({})", e),
+            Self::RomMissing(path) => write!(f, "ROM not found at {}", path.display()),
+            Self::CrcMismatch { expected, found } => {
+                write!(f, "ROM has changed (CRC32 {:08X}, expected {:08X})", found, expected)
+            }
+            Self::Emulator(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+impl From<io::Error> for SessionError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<EmulatorError> for SessionError {
+    fn from(e: EmulatorError) -> Self {
+        Self::Emulator(e)
+    }
+}
+
+/// Everything in `session.json`
+#[derive(Serialize, Deserialize)]
+struct Metadata {
+    rom_path: PathBuf,
+    rom_crc32: u32,
+    #[serde(default)]
+    settings: Settings,
+}
+
+/// A saved GUI session
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    /// ROM that was loaded
+    pub rom_path: PathBuf,
+    /// CRC32 of the ROM's PRG and CHR data when the session was saved
+    pub rom_crc32: u32,
+    /// Frontend settings at exit
+    pub settings: Settings,
+    /// Savestate from [`NesSystem::save_state`]
+    pub state: Vec<u8>,
+}
+
+impl Session {
+    /// Capture the session for a running system
+    pub fn capture(system: &mut NesSystem, rom_path: PathBuf, settings: Settings) -> Self {
+        Self {
+            rom_path,
+            rom_crc32: system.rom_crc32().unwrap_or(0),
+            settings,
+            state: system.save_state(),
+        }
+    }
+
+    /// Write the session files into `dir`, creating it if needed
+    pub fn save(&self, dir: &Path) -> Result<(), SessionError> {
+        fs::create_dir_all(dir)?;
+        let metadata = Metadata {
+            rom_path: self.rom_path.clone(),
+            rom_crc32: self.rom_crc32,
+            settings: self.settings.clone(),
+        };
+        let json = serde_json::to_string_pretty(&metadata).map_err(|e| SessionError::Corrupt(e.to_string()))?;
+
+        // Write the state first: a session only counts once its metadata exists
+        fs::write(dir.join(STATE_FILE), &self.state)?;
+        fs::write(dir.join(METADATA_FILE), json)?;
+        Ok(())
+    }
+
+    /// Read the session saved in `dir`, or `None` if there isn't one
+    pub fn load(dir: &Path) -> Result<Option<Self>, SessionError> {
+        let json = match fs::read_to_string(dir.join(METADATA_FILE)) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let metadata: Metadata = serde_json::from_str(&json).map_err(|e| SessionError::Corrupt(e.to_string()))?;
+        let state = fs::read(dir.join(STATE_FILE))?;
+        Ok(Some(Self {
+            rom_path: metadata.rom_path,
+            rom_crc32: metadata.rom_crc32,
+            settings: metadata.settings,
+            state,
+        }))
+    }
+
+    /// Load the ROM and apply the savestate, running as the TV system the
+    /// settings name if they do
+    ///
+    /// Fails if the ROM is gone, has a different CRC32, or the savestate
+    /// doesn't load.
+    pub fn restore(&self) -> Result<NesSystem, SessionError> {
+        if !self.rom_path.is_file() {
+            return Err(SessionError::RomMissing(self.rom_path.clone()));
+        }
+        let mut system = NesSystem::new(&self.rom_path)?;
+        let found = system.rom_crc32().unwrap_or(0);
+        if found != self.rom_crc32 {
+            return Err(SessionError::CrcMismatch {
+                expected: self.rom_crc32,
+                found,
+            });
+        }
+        system.set_region_override(self.settings.tv_system.map(TvSystem::region));
+        system.load_state(&self.state)?;
+        Ok(system)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh scratch directory under the system temp dir
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lumiemu-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write an NROM image whose program spins on `JMP $8000`
+    fn write_rom(dir: &Path, fill: u8) -> PathBuf {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![fill; 0x4000];
+        prg[..3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        prg[0x3FFC..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80]);
+        rom.extend_from_slice(&prg);
+        rom.extend_from_slice(&[0; 0x2000]);
+        let path = dir.join("game.nes");
+        fs::write(&path, rom).unwrap();
+        path
+    }
+
+    fn saved_session(dir: &Path) -> Session {
+        let rom_path = write_rom(dir, 0xEA);
+        let mut system = NesSystem::new_quiet(&rom_path).unwrap();
+        for _ in 0..5 {
+            system.run_frame().unwrap();
+        }
+        let settings = Settings {
+            sprite_overlay: true,
+            ..Default::default()
+        };
+        let session = Session::capture(&mut system, rom_path, settings);
+        session.save(dir).unwrap();
+        session
+    }
+
+    /// Load the session in `dir` and expect restoring it to fail
+    fn restore_error(dir: &Path) -> SessionError {
+        match Session::load(dir).unwrap().unwrap().restore() {
+            Ok(_) => panic!("session restored"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let dir = scratch_dir("round-trip");
+        let session = saved_session(&dir);
+
+        let loaded = Session::load(&dir).unwrap().unwrap();
+        assert_eq!(loaded, session);
+
+        let mut system = loaded.restore().unwrap();
+        assert_eq!(system.frame(), 5);
+        assert_eq!(system.save_state(), session.state);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_session_keeps_a_forced_tv_system() {
+        let dir = scratch_dir("tv-system");
+        let rom_path = write_rom(&dir, 0xEA);
+        let mut system = NesSystem::new_quiet(&rom_path).unwrap();
+        system.set_region_override(Some(emu_nes::Region::Pal));
+        for _ in 0..5 {
+            system.run_frame().unwrap();
+        }
+        let settings = Settings {
+            tv_system: Some(TvSystem::Pal),
+            ..Default::default()
+        };
+        Session::capture(&mut system, rom_path, settings).save(&dir).unwrap();
+
+        let mut restored = Session::load(&dir).unwrap().unwrap().restore().unwrap();
+        assert_eq!(restored.region(), emu_nes::Region::Pal);
+        assert_eq!(restored.save_state(), system.save_state());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_no_session_is_not_an_error() {
+        let dir = scratch_dir("empty");
+        assert!(Session::load(&dir).unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_changed_rom_is_rejected() {
+        let dir = scratch_dir("crc");
+        let session = saved_session(&dir);
+        write_rom(&dir, 0x00);
+
+        let err = restore_error(&dir);
+        assert!(
+            matches!(err, SessionError::CrcMismatch { expected, .. } if expected == session.rom_crc32),
+            "{:?}",
+            err
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_missing_rom_and_corrupt_files_are_rejected() {
+        let dir = scratch_dir("broken");
+        let session = saved_session(&dir);
+
+        fs::write(dir.join(STATE_FILE), &session.state[..64]).unwrap();
+        let err = restore_error(&dir);
+        assert!(matches!(err, SessionError::Emulator(EmulatorError::InvalidSaveState(_))), "{:?}", err);
+
+        fs::remove_file(&session.rom_path).unwrap();
+        let err = restore_error(&dir);
+        assert!(matches!(err, SessionError::RomMissing(_)), "{:?}", err);
+
+        fs::write(dir.join(METADATA_FILE), "{ not json").unwrap();
+        assert!(matches!(Session::load(&dir), Err(SessionError::Corrupt(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,382 @@
+//! User-facing frontend settings
+//!
+//! Settings resolve in three layers: built-in defaults, then the global
+//! settings from `settings.toml`, then any overrides for the loaded game
+//! (keyed by the CRC32 of its PRG/CHR data, as 8 uppercase hex digits).
+//!
+//! ```toml
+//! recent_roms = ["/home/me/roms/game.nes"]
+//!
+//! [global]
+//! sprite_overlay = false
+//! scale_mode = "ntsc"
+//! frame_blend = "blend"
+//! pacing = "display"
+//! run_ahead = 1
+//! accuracy = "hardware_strict"
+//! state_dir = "/home/me/nes-states"
+//!
+//! [per_game.1A2B3C4D]
+//! hang_detection = false
+//! tv_system = "pal"
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use emu_nes::Region;
+use serde::{Deserialize, Serialize};
+
+const SETTINGS_FILE: &str = "settings.toml";
+
+/// ROMs kept in File > Recent ROMs
+pub const MAX_RECENT_ROMS: usize = 8;
+
+/// How the screen is scaled to fit the window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScaleMode {
+    /// 1:1 pixel aspect ratio
+    Square,
+    /// 8:7 pixel aspect ratio, as on an NTSC television
+    #[default]
+    Ntsc,
+    /// Fill the whole screen area, ignoring aspect ratio
+    Stretch,
+}
+
+/// How each frame is mixed with the previous one (see
+/// [`crate::video_filter::FrameBlend`])
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameBlendMode {
+    /// Every frame as the core drew it
+    #[default]
+    Off,
+    /// Average with the previous frame, hiding sprite flicker
+    Blend,
+    /// Let lit pixels fade out over a few frames, like a CRT
+    Phosphor,
+}
+
+/// What sets the pace frames are shown at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PacingMode {
+    /// The emulation thread's own frame clock; every frame is shown
+    #[default]
+    Emulation,
+    /// The display's redraws, showing the newest frame; emulation follows
+    /// the audio device (see [`crate::pacing`])
+    Display,
+}
+
+/// Which hardware quirks the core emulates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccuracyProfile {
+    /// None of the opt-in quirks, so games that get away with relying on
+    /// emulator leniency keep working
+    #[default]
+    Compatible,
+    /// Every quirk the core knows, for homebrew that has to run on a real
+    /// console
+    HardwareStrict,
+}
+
+/// TV system a game runs as, overriding its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TvSystem {
+    /// 60 frames a second
+    Ntsc,
+    /// 50 frames a second, on a slower CPU
+    Pal,
+}
+
+impl TvSystem {
+    /// The core's region for this TV system
+    pub fn region(self) -> Region {
+        match self {
+            Self::Ntsc => Region::Ntsc,
+            Self::Pal => Region::Pal,
+        }
+    }
+}
+
+/// Frontend settings that persist across runs
+///
+/// Unknown or missing fields fall back to their defaults, so settings files
+/// written by older or newer versions still load.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Draw the sprite/register debug overlay
+    pub sprite_overlay: bool,
+    /// Show the buttons the game latched in a corner of the screen
+    pub input_display: bool,
+    /// Warn when the game looks stuck in a tight loop
+    pub hang_detection: bool,
+    /// Screen scaling mode
+    pub scale_mode: ScaleMode,
+    /// Hide the top and bottom 8 lines, which most TVs didn't show
+    pub crop_overscan: bool,
+    /// Mixing of each frame with the one before
+    pub frame_blend: FrameBlendMode,
+    /// Weight of the previous frame when blending, or how much of it is
+    /// left for phosphor, 0.0 to 1.0
+    pub frame_blend_strength: f32,
+    /// Frame pacing mode
+    pub pacing: PacingMode,
+    /// Frames to run ahead to hide the game's input lag, 0 (off) to
+    /// [`crate::run_ahead::MAX_FRAMES`]
+    pub run_ahead: u8,
+    /// Where savestate slots go; next to the ROM if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_dir: Option<PathBuf>,
+    /// Reload the ROM when its file changes on disk
+    pub auto_reload_rom: bool,
+    /// Carry the running state over to a reloaded ROM instead of power-cycling
+    pub keep_state_on_reload: bool,
+    /// How far the pulse channels are panned apart, 0.0 (mono) to 1.0
+    pub stereo_separation: f32,
+    /// Hardware quirks to emulate
+    pub accuracy: AccuracyProfile,
+    /// TV system to run as instead of the header's; the game settings
+    /// dialog sets it per game
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tv_system: Option<TvSystem>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            sprite_overlay: false,
+            input_display: false,
+            hang_detection: true,
+            scale_mode: ScaleMode::default(),
+            crop_overscan: false,
+            frame_blend: FrameBlendMode::default(),
+            frame_blend_strength: crate::video_filter::DEFAULT_STRENGTH,
+            pacing: PacingMode::default(),
+            run_ahead: 0,
+            state_dir: None,
+            auto_reload_rom: false,
+            keep_state_on_reload: false,
+            stereo_separation: 0.0,
+            accuracy: AccuracyProfile::default(),
+            tv_system: None,
+        }
+    }
+}
+
+/// Settings for one game; `None` keeps the global value
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sprite_overlay: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hang_detection: Option<bool>,
+    /// For ROMs whose header has the wrong TV system
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tv_system: Option<TvSystem>,
+}
+
+impl GameOverrides {
+    /// Check whether nothing is overridden
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Apply a game's overrides (if any) on top of the global settings
+pub fn resolve(global: &Settings, game: Option<&GameOverrides>) -> Settings {
+    let Some(game) = game else {
+        return global.clone();
+    };
+    Settings {
+        sprite_overlay: game.sprite_overlay.unwrap_or(global.sprite_overlay),
+        hang_detection: game.hang_detection.unwrap_or(global.hang_detection),
+        tv_system: game.tv_system.or(global.tv_system),
+        ..global.clone()
+    }
+}
+
+/// Key for a ROM in [`Config::per_game`]
+pub fn game_key(crc32: u32) -> String {
+    format!("{:08X}", crc32)
+}
+
+/// Contents of `settings.toml`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Recently loaded ROMs, newest first; before the tables, as TOML needs
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub recent_roms: Vec<PathBuf>,
+    /// Settings for every game
+    pub global: Settings,
+    /// Overrides keyed by [`game_key`]
+    pub per_game: BTreeMap<String, GameOverrides>,
+}
+
+impl Config {
+    /// Read `settings.toml` from `dir`, or the defaults if it doesn't exist
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        match fs::read_to_string(dir.join(SETTINGS_FILE)) {
+            Ok(text) => toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write `settings.toml` into `dir`, creating it if needed
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let text = toml::to_string_pretty(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join(SETTINGS_FILE), text)
+    }
+
+    /// Effective settings for the ROM with this CRC32
+    pub fn for_game(&self, crc32: u32) -> Settings {
+        resolve(&self.global, self.per_game.get(&game_key(crc32)))
+    }
+
+    /// Replace a game's overrides, dropping the entry when nothing is overridden
+    pub fn set_overrides(&mut self, crc32: u32, overrides: GameOverrides) {
+        if overrides.is_empty() {
+            self.per_game.remove(&game_key(crc32));
+        } else {
+            self.per_game.insert(game_key(crc32), overrides);
+        }
+    }
+
+    /// Put `path` at the top of the recent ROMs, dropping the oldest past
+    /// [`MAX_RECENT_ROMS`]
+    pub fn add_recent_rom(&mut self, path: &Path) {
+        self.recent_roms.retain(|recent| recent != path);
+        self.recent_roms.insert(0, path.to_path_buf());
+        self.recent_roms.truncate(MAX_RECENT_ROMS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_override() {
+        let global = Settings {
+            sprite_overlay: true,
+            input_display: true,
+            hang_detection: true,
+            scale_mode: ScaleMode::Square,
+            crop_overscan: true,
+            frame_blend: FrameBlendMode::Phosphor,
+            frame_blend_strength: 0.75,
+            pacing: PacingMode::Display,
+            run_ahead: 1,
+            state_dir: Some(PathBuf::from("/states")),
+            auto_reload_rom: true,
+            keep_state_on_reload: false,
+            stereo_separation: 0.5,
+            accuracy: AccuracyProfile::HardwareStrict,
+            tv_system: None,
+        };
+        let game = GameOverrides {
+            hang_detection: Some(false),
+            ..Default::default()
+        };
+
+        let settings = resolve(&global, Some(&game));
+        assert!(settings.sprite_overlay);
+        assert!(!settings.hang_detection);
+        assert_eq!(settings.scale_mode, ScaleMode::Square);
+        assert_eq!(settings.frame_blend, FrameBlendMode::Phosphor);
+        assert_eq!(settings.pacing, PacingMode::Display);
+        assert_eq!(settings.run_ahead, 1);
+        assert_eq!(settings.state_dir, global.state_dir);
+        assert!(settings.auto_reload_rom);
+        assert_eq!(settings.stereo_separation, 0.5);
+        assert_eq!(settings.accuracy, AccuracyProfile::HardwareStrict);
+        assert_eq!(settings.tv_system, None);
+        assert_eq!(resolve(&global, None), global);
+        assert_eq!(resolve(&global, Some(&GameOverrides::default())), global);
+    }
+
+    #[test]
+    fn test_missing_keys_use_defaults() {
+        let config: Config = toml::from_str("[global]\nsprite_overlay = true\n").unwrap();
+        assert!(config.global.sprite_overlay);
+        assert!(config.global.hang_detection);
+        assert_eq!(config.global.scale_mode, ScaleMode::Ntsc);
+
+        let config: Config = toml::from_str("[global]\nscale_mode = \"stretch\"\n").unwrap();
+        assert_eq!(config.global.scale_mode, ScaleMode::Stretch);
+        assert_eq!(config.global.pacing, PacingMode::Emulation);
+        assert_eq!(config.global.run_ahead, 0);
+        assert_eq!(config.global.state_dir, None);
+        assert!(!config.global.auto_reload_rom);
+        assert_eq!(config.global.stereo_separation, 0.0);
+        assert_eq!(config.global.accuracy, AccuracyProfile::Compatible);
+        assert_eq!(config.global.frame_blend, FrameBlendMode::Off);
+        assert!(config.per_game.is_empty());
+
+        let config: Config = toml::from_str("[global]\nframe_blend = \"phosphor\"\n").unwrap();
+        assert_eq!(config.global.frame_blend, FrameBlendMode::Phosphor);
+        assert_eq!(config.global.frame_blend_strength, crate::video_filter::DEFAULT_STRENGTH);
+
+        assert_eq!(toml::from_str::<Config>("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let mut config = Config::default();
+        config.set_overrides(
+            0x1A2B_3C4D,
+            GameOverrides {
+                hang_detection: Some(false),
+                tv_system: Some(TvSystem::Pal),
+                ..Default::default()
+            },
+        );
+        config.set_overrides(0xDEAD_BEEF, GameOverrides::default());
+
+        let text = toml::to_string_pretty(&config).unwrap();
+        assert!(text.contains("[per_game.1A2B3C4D]"), "{}", text);
+        assert!(!text.contains("DEADBEEF"), "{}", text);
+        assert!(!text.contains("sprite_overlay = true"), "{}", text);
+        assert!(text.contains("tv_system = \"pal\""), "{}", text);
+
+        let parsed: Config = toml::from_str(&text).unwrap();
+        assert_eq!(parsed, config);
+        assert!(!parsed.for_game(0x1A2B_3C4D).hang_detection);
+        assert_eq!(parsed.for_game(0x1A2B_3C4D).tv_system, Some(TvSystem::Pal));
+        assert!(parsed.for_game(0x0000_0001).hang_detection);
+        assert_eq!(parsed.for_game(0x0000_0001).tv_system, None);
+
+    }
+
+    #[test]
+    fn test_recent_roms() {
+        let mut config = Config::default();
+        for index in 0..MAX_RECENT_ROMS + 2 {
+            config.add_recent_rom(Path::new(&format!("/roms/{}.nes", index)));
+        }
+        assert_eq!(config.recent_roms.len(), MAX_RECENT_ROMS);
+        assert_eq!(config.recent_roms[0], Path::new("/roms/9.nes"));
+
+        // Loading one again moves it to the top instead of listing it twice
+        config.add_recent_rom(Path::new("/roms/5.nes"));
+        assert_eq!(config.recent_roms.len(), MAX_RECENT_ROMS);
+        assert_eq!(config.recent_roms[0], Path::new("/roms/5.nes"));
+        assert_eq!(config.recent_roms.iter().filter(|path| path.ends_with("5.nes")).count(), 1);
+
+        let text = toml::to_string_pretty(&config).unwrap();
+        assert_eq!(toml::from_str::<Config>(&text).unwrap(), config);
+        assert!(!toml::to_string_pretty(&Config::default()).unwrap().contains("recent_roms"));
+    }
+}
@@ -0,0 +1,237 @@
+//! Numbered savestate slots
+//!
+//! Slot N of `game.nes` is saved as `game.stateN`, next to the ROM or in
+//! the configured state directory. Slots 1-9 are picked with the number
+//! keys; slot 0 is written automatically when emulation stops.
+//!
+//! Layout: `"LSLT"`, format version (u16), ROM CRC32 (u32), save time in
+//! seconds since the Unix epoch (u64), a 64x60 RGB thumbnail, then the
+//! savestate from [`NesSystem::save_state`]. Integers are little-endian.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use emu_nes::NesSystem;
+
+use crate::session::SessionError;
+
+/// Slot written when emulation stops
+pub const AUTO_SLOT: u8 = 0;
+
+/// Thumbnail width in pixels (a quarter of the screen)
+pub const THUMBNAIL_WIDTH: usize = 64;
+
+/// Thumbnail height in pixels (a quarter of the screen)
+pub const THUMBNAIL_HEIGHT: usize = 60;
+
+/// Bytes in an RGB thumbnail
+const THUMBNAIL_LEN: usize = THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 3;
+
+const MAGIC: &[u8; 4] = b"LSLT";
+const VERSION: u16 = 1;
+const HEADER_LEN: usize = 4 + 2 + 4 + 8 + THUMBNAIL_LEN;
+
+/// Where slot `slot` of the ROM at `rom_path` is stored
+pub fn slot_path(rom_path: &Path, state_dir: Option<&Path>, slot: u8) -> PathBuf {
+    let stem = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = format!("{}.state{}", stem, slot);
+    match state_dir {
+        Some(dir) => dir.join(name),
+        None => rom_path.with_file_name(name),
+    }
+}
+
+/// Shrink a 256x240 RGBA frame to a 64x60 RGB thumbnail
+///
+/// Each thumbnail pixel is the average of a 4x4 block of the frame.
+pub fn thumbnail(rgba: &[u8]) -> Vec<u8> {
+    const SCALE: usize = 4;
+    const FRAME_WIDTH: usize = THUMBNAIL_WIDTH * SCALE;
+    debug_assert_eq!(rgba.len(), FRAME_WIDTH * THUMBNAIL_HEIGHT * SCALE * 4);
+
+    let mut thumb = Vec::with_capacity(THUMBNAIL_LEN);
+    for ty in 0..THUMBNAIL_HEIGHT {
+        for tx in 0..THUMBNAIL_WIDTH {
+            let mut sum = [0u32; 3];
+            for y in ty * SCALE..(ty + 1) * SCALE {
+                for x in tx * SCALE..(tx + 1) * SCALE {
+                    let i = (y * FRAME_WIDTH + x) * 4;
+                    for (channel, total) in sum.iter_mut().enumerate() {
+                        *total += rgba[i + channel] as u32;
+                    }
+                }
+            }
+            thumb.extend(sum.map(|total| (total / (SCALE * SCALE) as u32) as u8));
+        }
+    }
+    thumb
+}
+
+/// Contents of a slot file
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlotFile {
+    /// CRC32 of the ROM's PRG and CHR data
+    pub rom_crc32: u32,
+    /// When the slot was saved, in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// 64x60 RGB preview of the screen
+    pub thumbnail: Vec<u8>,
+    /// Savestate from [`NesSystem::save_state`]
+    pub state: Vec<u8>,
+}
+
+impl SlotFile {
+    /// Capture the system's state, with `rgba` (the current frame) as the thumbnail
+    pub fn capture(system: &mut NesSystem, rgba: &[u8]) -> Self {
+        Self {
+            rom_crc32: system.rom_crc32().unwrap_or(0),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or(0),
+            thumbnail: thumbnail(rgba),
+            state: system.save_state(),
+        }
+    }
+
+    /// Serialize to the slot file layout
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_LEN + self.state.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.extend_from_slice(&self.rom_crc32.to_le_bytes());
+        data.extend_from_slice(&self.timestamp.to_le_bytes());
+        data.extend_from_slice(&self.thumbnail);
+        data.extend_from_slice(&self.state);
+        data
+    }
+
+    /// Parse the slot file layout
+    pub fn from_bytes(data: &[u8]) -> Result<Self, SessionError> {
+        if data.len() < HEADER_LEN || &data[..4] != MAGIC {
+            return Err(SessionError::Corrupt("not a slot file".into()));
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version != VERSION {
+            return Err(SessionError::Corrupt(format!(
+                "slot format version {} (expected {})",
+                version, VERSION
+            )));
+        }
+        Ok(Self {
+            rom_crc32: u32::from_le_bytes(data[6..10].try_into().unwrap()),
+            timestamp: u64::from_le_bytes(data[10..18].try_into().unwrap()),
+            thumbnail: data[18..HEADER_LEN].to_vec(),
+            state: data[HEADER_LEN..].to_vec(),
+        })
+    }
+
+    /// Write the slot to `path`, creating its directory if needed
+    pub fn save(&self, path: &Path) -> Result<(), SessionError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Read the slot at `path`
+    pub fn load(path: &Path) -> Result<Self, SessionError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Load the savestate into `system`, refusing a slot saved from another ROM
+    pub fn restore(&self, system: &mut NesSystem) -> Result<(), SessionError> {
+        let found = system.rom_crc32().unwrap_or(0);
+        if found != self.rom_crc32 {
+            return Err(SessionError::CrcMismatch {
+                expected: self.rom_crc32,
+                found,
+            });
+        }
+        system.load_state(&self.state)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// NROM image whose program spins on `JMP $8000`
+    fn rom(fill: u8) -> Vec<u8> {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![fill; 0x4000];
+        prg[..3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        prg[0x3FFC..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80]);
+        rom.extend_from_slice(&prg);
+        rom.extend_from_slice(&[0; 0x2000]);
+        rom
+    }
+
+    #[test]
+    fn test_thumbnail_averages_blocks() {
+        let mut rgba = vec![0u8; 256 * 240 * 4];
+        // Top-left 4x4 block: left half white, right half black
+        for y in 0..4 {
+            for x in 0..2 {
+                rgba[(y * 256 + x) * 4..][..4].copy_from_slice(&[255, 255, 255, 255]);
+            }
+        }
+        // Bottom-right block solid red
+        for y in 236..240 {
+            for x in 252..256 {
+                rgba[(y * 256 + x) * 4..][..4].copy_from_slice(&[200, 0, 0, 255]);
+            }
+        }
+
+        let thumb = thumbnail(&rgba);
+        assert_eq!(thumb.len(), THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 3);
+        assert_eq!(thumb[..3], [127, 127, 127]);
+        assert_eq!(thumb[3..6], [0, 0, 0]);
+        assert_eq!(thumb[thumb.len() - 3..], [200, 0, 0]);
+    }
+
+    #[test]
+    fn test_slot_path() {
+        let rom_path = Path::new("/games/nes/mario.nes");
+        assert_eq!(slot_path(rom_path, None, 3), Path::new("/games/nes/mario.state3"));
+        assert_eq!(
+            slot_path(rom_path, Some(Path::new("/states")), AUTO_SLOT),
+            Path::new("/states/mario.state0")
+        );
+    }
+
+    #[test]
+    fn test_slot_round_trip() {
+        let mut system = NesSystem::from_bytes(&rom(0xEA)).unwrap();
+        for _ in 0..3 {
+            system.run_frame().unwrap();
+        }
+        let slot = SlotFile::capture(&mut system, &vec![0; 256 * 240 * 4]);
+        assert!(slot.timestamp > 0);
+
+        let parsed = SlotFile::from_bytes(&slot.to_bytes()).unwrap();
+        assert_eq!(parsed, slot);
+
+        let mut fresh = NesSystem::from_bytes(&rom(0xEA)).unwrap();
+        parsed.restore(&mut fresh).unwrap();
+        assert_eq!(fresh.frame(), 3);
+    }
+
+    #[test]
+    fn test_bad_slots_are_rejected() {
+        let mut system = NesSystem::from_bytes(&rom(0xEA)).unwrap();
+        let slot = SlotFile::capture(&mut system, &vec![0; 256 * 240 * 4]);
+
+        let mut other = NesSystem::from_bytes(&rom(0x00)).unwrap();
+        let err = slot.restore(&mut other).unwrap_err();
+        assert!(matches!(err, SessionError::CrcMismatch { .. }), "{:?}", err);
+
+        let mut data = slot.to_bytes();
+        assert!(matches!(SlotFile::from_bytes(&data[..100]), Err(SessionError::Corrupt(_))));
+        data[4] = 99;
+        assert!(matches!(SlotFile::from_bytes(&data), Err(SessionError::Corrupt(_))));
+    }
+}
@@ -0,0 +1,463 @@
+//! Status bar model
+//!
+//! The UI callbacks and the emulation thread send [`StatusUpdate`]s down a
+//! channel; the UI thread drains it into a [`StatusModel`] a few times a
+//! second and shows the three text lines it produces. Keeping the message
+//! queue here, away from Slint, means its priority, timeout and coalescing
+//! rules can be tested without a window.
+
+use std::time::{Duration, Instant};
+
+/// Sending half of the status channel, cloned into every callback and thread
+pub type StatusSender = std::sync::mpsc::Sender<StatusUpdate>;
+
+/// How important a status message is; more important messages are shown first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Priority {
+    /// How long a message of this priority stays up
+    pub fn timeout(self) -> Duration {
+        match self {
+            Priority::Info => Duration::from_secs(3),
+            Priority::Warning => Duration::from_secs(5),
+            Priority::Error => Duration::from_secs(8),
+        }
+    }
+}
+
+/// Something the status bar should know about
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatusUpdate {
+    /// A transient message, e.g. "Saved slot 3"
+    Message { text: String, priority: Priority },
+    /// Once a second from the emulation thread
+    Emulation {
+        /// Frames emulated over the last second
+        fps: f32,
+        /// Current frame number
+        frame: u64,
+        /// Audio buffer fill, 0.0 to 1.0, if audio is playing
+        audio_fill: Option<f32>,
+    },
+    /// Once a second from the emulation thread, with the emulation figures
+    Lint {
+        /// Homebrew lint findings so far, if the lint is on
+        findings: Option<usize>,
+    },
+    /// The UI put a new frame on screen
+    FramePresented {
+        /// When, for measuring jitter: updates are applied in batches
+        at: Instant,
+    },
+    /// A ROM was loaded or a session resumed
+    RomLoaded {
+        name: String,
+        mapper: Option<u8>,
+        /// The ROM database overrode the iNES header
+        header_corrected: bool,
+        /// Running as a PAL console, at 50 frames a second
+        pal: bool,
+    },
+    /// The emulation thread stopped
+    Stopped,
+}
+
+impl StatusUpdate {
+    pub fn info(text: impl Into<String>) -> Self {
+        Self::Message { text: text.into(), priority: Priority::Info }
+    }
+
+    pub fn warning(text: impl Into<String>) -> Self {
+        Self::Message { text: text.into(), priority: Priority::Warning }
+    }
+
+    pub fn error(text: impl Into<String>) -> Self {
+        Self::Message { text: text.into(), priority: Priority::Error }
+    }
+}
+
+/// Most messages kept at once; the oldest least important one is dropped first
+const MAX_MESSAGES: usize = 8;
+
+/// Display FPS is measured over windows of this length
+const DISPLAY_FPS_WINDOW: Duration = Duration::from_secs(1);
+
+/// A message waiting to be shown or being shown
+#[derive(Debug, Clone)]
+struct QueuedMessage {
+    text: String,
+    priority: Priority,
+    /// Times the same text was posted while this one was up
+    count: u32,
+    posted: Instant,
+    expires: Instant,
+}
+
+/// Emulation figures from the last [`StatusUpdate::Emulation`]
+#[derive(Debug, Clone, Copy, Default)]
+struct EmulationStats {
+    fps: f32,
+    frame: u64,
+    audio_fill: Option<f32>,
+}
+
+/// Everything the status bar shows
+#[derive(Debug)]
+pub struct StatusModel {
+    messages: Vec<QueuedMessage>,
+    /// `None` while stopped
+    emulation: Option<EmulationStats>,
+    display_fps: f32,
+    presented: u32,
+    presented_since: Instant,
+    /// Standard deviation of the time between presented frames (ms) over
+    /// the last window
+    jitter_ms: f32,
+    /// When the last frame was presented
+    last_presented: Option<Instant>,
+    /// Count, sum and sum of squares of the intervals (ms) in the current window
+    intervals: u32,
+    interval_sum: f64,
+    interval_sum_sq: f64,
+    rom: Option<(String, Option<u8>, bool, bool)>,
+    /// Lint findings, `None` while the lint is off or emulation stopped
+    lint: Option<usize>,
+}
+
+impl StatusModel {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            messages: Vec::new(),
+            emulation: None,
+            display_fps: 0.0,
+            presented: 0,
+            presented_since: now,
+            jitter_ms: 0.0,
+            last_presented: None,
+            intervals: 0,
+            interval_sum: 0.0,
+            interval_sum_sq: 0.0,
+            rom: None,
+            lint: None,
+        }
+    }
+
+    /// Apply one update
+    pub fn apply(&mut self, update: StatusUpdate, now: Instant) {
+        match update {
+            StatusUpdate::Message { text, priority } => self.post(text, priority, now),
+            StatusUpdate::Emulation { fps, frame, audio_fill } => {
+                self.emulation = Some(EmulationStats { fps, frame, audio_fill });
+            }
+            StatusUpdate::Lint { findings } => self.lint = findings,
+            StatusUpdate::FramePresented { at } => self.frame_presented(at),
+            StatusUpdate::RomLoaded { name, mapper, header_corrected, pal } => {
+                self.rom = Some((name, mapper, header_corrected, pal));
+            }
+            StatusUpdate::Stopped => {
+                self.emulation = None;
+                self.lint = None;
+                self.display_fps = 0.0;
+                self.jitter_ms = 0.0;
+                self.last_presented = None;
+                self.restart_window(now);
+            }
+        }
+    }
+
+    /// Count a presented frame and the time since the one before it
+    fn frame_presented(&mut self, at: Instant) {
+        if let Some(last) = self.last_presented {
+            let interval = at.saturating_duration_since(last).as_secs_f64() * 1000.0;
+            self.intervals += 1;
+            self.interval_sum += interval;
+            self.interval_sum_sq += interval * interval;
+        }
+        self.last_presented = Some(at);
+        self.presented += 1;
+    }
+
+    fn restart_window(&mut self, now: Instant) {
+        self.presented = 0;
+        self.presented_since = now;
+        self.intervals = 0;
+        self.interval_sum = 0.0;
+        self.interval_sum_sq = 0.0;
+    }
+
+    /// Queue a message, folding it into an identical one that is still up
+    fn post(&mut self, text: String, priority: Priority, now: Instant) {
+        self.expire(now);
+        let expires = now + priority.timeout();
+        if let Some(existing) = self.messages.iter_mut().find(|message| message.text == text) {
+            existing.count += 1;
+            existing.priority = existing.priority.max(priority);
+            existing.posted = now;
+            existing.expires = existing.expires.max(expires);
+            return;
+        }
+        if self.messages.len() == MAX_MESSAGES {
+            let dropped = (0..self.messages.len())
+                .min_by_key(|&i| (self.messages[i].priority, self.messages[i].posted))
+                .unwrap();
+            self.messages.remove(dropped);
+        }
+        self.messages.push(QueuedMessage { text, priority, count: 1, posted: now, expires });
+    }
+
+    /// Drop expired messages and roll the display FPS window
+    pub fn tick(&mut self, now: Instant) {
+        self.expire(now);
+        let elapsed = now.saturating_duration_since(self.presented_since);
+        if elapsed >= DISPLAY_FPS_WINDOW {
+            self.display_fps = self.presented as f32 / elapsed.as_secs_f32();
+            self.jitter_ms = if self.intervals > 0 {
+                let count = self.intervals as f64;
+                let mean = self.interval_sum / count;
+                (self.interval_sum_sq / count - mean * mean).max(0.0).sqrt() as f32
+            } else {
+                0.0
+            };
+            self.restart_window(now);
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.messages.retain(|message| message.expires > now);
+    }
+
+    /// The message to show: the most important one still up, newest first
+    pub fn message_text(&self) -> String {
+        let Some(message) = self
+            .messages
+            .iter()
+            .max_by_key(|message| (message.priority, message.posted))
+        else {
+            return String::new();
+        };
+        let prefix = match message.priority {
+            Priority::Info => "",
+            Priority::Warning => "⚠ ",
+            Priority::Error => "✖ ",
+        };
+        if message.count > 1 {
+            format!("{}{} (×{})", prefix, message.text, message.count)
+        } else {
+            format!("{}{}", prefix, message.text)
+        }
+    }
+
+    /// Emulated and displayed FPS, presentation jitter, audio buffer fill and frame number
+    pub fn stats_text(&self) -> String {
+        let Some(stats) = self.emulation else {
+            return "Stopped".to_string();
+        };
+        let audio = match stats.audio_fill {
+            Some(fill) => format!("audio {:.0}%", fill.clamp(0.0, 1.0) * 100.0),
+            None => "no audio".to_string(),
+        };
+        format!(
+            "{:.0} fps ({:.0} shown, ±{:.1} ms) | {} | frame {}",
+            stats.fps, self.display_fps, self.jitter_ms, audio, stats.frame
+        )
+    }
+
+    /// The lint badge: how many findings there are, or nothing while the
+    /// lint is off
+    pub fn lint_text(&self) -> String {
+        match self.lint {
+            None => String::new(),
+            Some(0) => "Lint: 0".to_string(),
+            Some(findings) => format!("⚠ Lint: {}", findings),
+        }
+    }
+    
+    /// Loaded ROM, its mapper, and PAL if it runs as one
+    pub fn rom_text(&self) -> String {
+        let Some((name, mapper, header_corrected, pal)) = &self.rom else {
+            return "No ROM loaded".to_string();
+        };
+        let mut notes = Vec::new();
+        if let Some(mapper) = mapper {
+            notes.push(format!("mapper {}", mapper));
+        }
+        if *pal {
+            notes.push("PAL".to_string());
+        }
+        if *header_corrected {
+            notes.push("header fixed by ROM database".to_string());
+        }
+        if notes.is_empty() {
+            name.clone()
+        } else {
+            format!("{} ({})", name, notes.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(start: Instant, secs: f32) -> Instant {
+        start + Duration::from_secs_f32(secs)
+    }
+
+    #[test]
+    fn test_messages_time_out() {
+        let start = Instant::now();
+        let mut model = StatusModel::new(start);
+        model.apply(StatusUpdate::info("Saved slot 3"), start);
+        assert_eq!(model.message_text(), "Saved slot 3");
+
+        model.tick(secs(start, 2.9));
+        assert_eq!(model.message_text(), "Saved slot 3");
+        model.tick(secs(start, 3.0));
+        assert_eq!(model.message_text(), "");
+    }
+
+    #[test]
+    fn test_higher_priority_wins_until_it_expires() {
+        let start = Instant::now();
+        let mut model = StatusModel::new(start);
+        model.apply(StatusUpdate::error("ROM load failed: bad magic"), start);
+        model.apply(StatusUpdate::info("Saved slot 1"), secs(start, 1.0));
+        assert_eq!(model.message_text(), "✖ ROM load failed: bad magic");
+
+        // Among equals the newest is shown
+        model.apply(StatusUpdate::error("Couldn't save slot 2"), secs(start, 1.5));
+        assert_eq!(model.message_text(), "✖ Couldn't save slot 2");
+
+        // Once both errors are gone the info message is long expired too
+        model.tick(secs(start, 9.5));
+        assert_eq!(model.message_text(), "");
+
+        model.apply(StatusUpdate::warning("Audio underrun"), secs(start, 10.0));
+        model.apply(StatusUpdate::info("Loaded slot 1"), secs(start, 10.5));
+        assert_eq!(model.message_text(), "⚠ Audio underrun");
+        model.tick(secs(start, 15.0));
+        assert_eq!(model.message_text(), "");
+    }
+
+    #[test]
+    fn test_repeated_messages_coalesce() {
+        let start = Instant::now();
+        let mut model = StatusModel::new(start);
+        for i in 0..3 {
+            model.apply(StatusUpdate::warning("Audio underrun"), secs(start, i as f32 * 4.0));
+        }
+        assert_eq!(model.message_text(), "⚠ Audio underrun (×3)");
+
+        // Each repeat extends the timeout from when it was posted
+        model.tick(secs(start, 12.9));
+        assert_eq!(model.message_text(), "⚠ Audio underrun (×3)");
+        model.tick(secs(start, 13.0));
+        assert_eq!(model.message_text(), "");
+
+        // After expiring, the count starts over
+        model.apply(StatusUpdate::warning("Audio underrun"), secs(start, 14.0));
+        assert_eq!(model.message_text(), "⚠ Audio underrun");
+    }
+
+    #[test]
+    fn test_queue_drops_least_important_first() {
+        let start = Instant::now();
+        let mut model = StatusModel::new(start);
+        model.apply(StatusUpdate::error("disk full"), start);
+        for i in 0..MAX_MESSAGES {
+            model.apply(StatusUpdate::info(format!("info {}", i)), secs(start, 0.1));
+        }
+        assert_eq!(model.messages.len(), MAX_MESSAGES);
+        assert!(model.messages.iter().any(|message| message.text == "disk full"));
+        assert!(!model.messages.iter().any(|message| message.text == "info 0"));
+    }
+
+    #[test]
+    fn test_stats_and_rom_text() {
+        let start = Instant::now();
+        let mut model = StatusModel::new(start);
+        assert_eq!(model.stats_text(), "Stopped");
+        assert_eq!(model.rom_text(), "No ROM loaded");
+
+        let loaded = |header_corrected, pal| StatusUpdate::RomLoaded {
+            name: "game.nes".into(),
+            mapper: Some(66),
+            header_corrected,
+            pal,
+        };
+        model.apply(loaded(true, false), start);
+        assert_eq!(model.rom_text(), "game.nes (mapper 66, header fixed by ROM database)");
+        model.apply(loaded(true, true), start);
+        assert_eq!(model.rom_text(), "game.nes (mapper 66, PAL, header fixed by ROM database)");
+        model.apply(loaded(false, true), start);
+        assert_eq!(model.rom_text(), "game.nes (mapper 66, PAL)");
+        model.apply(loaded(false, false), start);
+        assert_eq!(model.rom_text(), "game.nes (mapper 66)");
+        model.apply(StatusUpdate::RomLoaded { name: "demo.bin".into(), mapper: None, header_corrected: false, pal: false }, start);
+        assert_eq!(model.rom_text(), "demo.bin");
+
+        model.apply(StatusUpdate::Emulation { fps: 60.0, frame: 1234, audio_fill: Some(0.456) }, start);
+        for _ in 0..58 {
+            model.apply(StatusUpdate::FramePresented { at: start }, start);
+        }
+        model.tick(secs(start, 0.5));
+        assert_eq!(model.stats_text(), "60 fps (0 shown, ±0.0 ms) | audio 46% | frame 1234");
+        model.tick(secs(start, 1.0));
+        assert_eq!(model.stats_text(), "60 fps (58 shown, ±0.0 ms) | audio 46% | frame 1234");
+
+        model.apply(StatusUpdate::Emulation { fps: 59.0, frame: 1300, audio_fill: None }, start);
+        assert_eq!(model.stats_text(), "59 fps (58 shown, ±0.0 ms) | no audio | frame 1300");
+
+        model.apply(StatusUpdate::Stopped, secs(start, 2.0));
+        assert_eq!(model.stats_text(), "Stopped");
+    }
+
+    #[test]
+    fn test_lint_badge() {
+        let start = Instant::now();
+        let mut model = StatusModel::new(start);
+        assert_eq!(model.lint_text(), "");
+        model.apply(StatusUpdate::Lint { findings: Some(0) }, start);
+        assert_eq!(model.lint_text(), "Lint: 0");
+        model.apply(StatusUpdate::Lint { findings: Some(3) }, start);
+        assert_eq!(model.lint_text(), "⚠ Lint: 3");
+        model.apply(StatusUpdate::Lint { findings: None }, start);
+        assert_eq!(model.lint_text(), "");
+
+        model.apply(StatusUpdate::Lint { findings: Some(3) }, start);
+        model.apply(StatusUpdate::Stopped, start);
+        assert_eq!(model.lint_text(), "");
+    }
+
+    #[test]
+    fn test_presentation_jitter() {
+        let start = Instant::now();
+        let mut model = StatusModel::new(start);
+        model.apply(StatusUpdate::Emulation { fps: 60.0, frame: 0, audio_fill: None }, start);
+
+        // Evenly spaced frames: no jitter, however they're batched
+        for i in 0..60 {
+            model.apply(StatusUpdate::FramePresented { at: secs(start, i as f32 / 60.0) }, secs(start, 0.9));
+        }
+        model.tick(secs(start, 1.0));
+        assert_eq!(model.stats_text(), "60 fps (60 shown, ±0.0 ms) | no audio | frame 0");
+
+        // Alternating 12ms and 20ms gaps are 4ms either side of the mean
+        let mut at = secs(start, 59.0 / 60.0);
+        for i in 0..60 {
+            at += Duration::from_millis(if i % 2 == 0 { 12 } else { 20 });
+            model.apply(StatusUpdate::FramePresented { at }, at);
+        }
+        model.tick(secs(start, 2.0));
+        assert!((model.jitter_ms - 4.0).abs() < 0.01, "{}", model.jitter_ms);
+
+        model.apply(StatusUpdate::Stopped, secs(start, 2.5));
+        assert_eq!(model.jitter_ms, 0.0);
+        assert_eq!(model.last_presented, None);
+    }
+}
@@ -0,0 +1,173 @@
+//! Filters run over each frame before it is shown
+//!
+//! Filters work on the core's RGBA picture at its native resolution, before
+//! the debug overlays are drawn and before the UI scales it to the window
+//! (see [`crate::settings::ScaleMode`]). Blending first means neighbouring
+//! frames line up pixel for pixel, and the overlays stay sharp on top.
+//!
+//! The one filter so far is [`FrameBlend`]. Many games flicker sprites on
+//! alternate frames to get round the eight-per-line limit, which a CRT's
+//! glow hid but a modern display shows as a harsh 30Hz strobe; mixing in
+//! the previous frame smooths it out.
+
+use crate::settings::FrameBlendMode;
+
+/// Default weight of the previous frame, or its decay for phosphor
+pub const DEFAULT_STRENGTH: f32 = 0.5;
+
+/// Something that rewrites a frame in place, and may remember earlier ones
+pub trait Filter: Send {
+    /// Filter `rgba`, a `(width, height)` picture
+    fn apply(&mut self, rgba: &mut [u8], size: (usize, usize));
+
+    /// Forget earlier frames, so a new picture doesn't start with the old
+    /// one bleeding through
+    fn reset(&mut self);
+}
+
+/// Mixes each frame with the one before it
+///
+/// In [`FrameBlendMode::Blend`] each channel is `current * (1 - strength) +
+/// previous * strength`, with `previous` the frame as the core drew it.
+/// In [`FrameBlendMode::Phosphor`] it's `max(current, previous * strength)`
+/// with `previous` the last frame shown, so a lit pixel fades over several
+/// frames like a CRT's phosphor. Alpha is left alone.
+#[derive(Debug, Clone, Default)]
+pub struct FrameBlend {
+    mode: FrameBlendMode,
+    strength: f32,
+    /// What the next frame is mixed with, empty until there is one
+    previous: Vec<u8>,
+    size: (usize, usize),
+    /// [`crate::controls::LoopSettings::picture_epoch`] the history is from
+    epoch: u64,
+}
+
+impl FrameBlend {
+    /// Pick up the settings for the next frame; the history is dropped
+    /// when the mode changes or `epoch` moves on to a new picture
+    pub fn configure(&mut self, mode: FrameBlendMode, strength: f32, epoch: u64) {
+        if mode != self.mode || epoch != self.epoch {
+            self.reset();
+        }
+        self.mode = mode;
+        self.strength = strength.clamp(0.0, 1.0);
+        self.epoch = epoch;
+    }
+}
+
+impl Filter for FrameBlend {
+    fn apply(&mut self, rgba: &mut [u8], size: (usize, usize)) {
+        if self.mode == FrameBlendMode::Off {
+            return;
+        }
+        // The first frame, or one of a new size, has nothing to mix with
+        if size != self.size || self.previous.len() != rgba.len() {
+            self.size = size;
+            self.previous.clear();
+            self.previous.extend_from_slice(rgba);
+            return;
+        }
+
+        let strength = self.strength;
+        for (index, (current, previous)) in rgba.iter_mut().zip(self.previous.iter_mut()).enumerate() {
+            if index % 4 == 3 {
+                continue;
+            }
+            let (now, before) = (f32::from(*current), f32::from(*previous));
+            match self.mode {
+                FrameBlendMode::Blend => {
+                    *previous = *current;
+                    *current = (now * (1.0 - strength) + before * strength).round() as u8;
+                }
+                FrameBlendMode::Phosphor => {
+                    *current = now.max((before * strength).round()) as u8;
+                    *previous = *current;
+                }
+                FrameBlendMode::Off => unreachable!(),
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.previous.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: (usize, usize) = (4, 2);
+
+    fn frame(color: [u8; 4]) -> Vec<u8> {
+        color.repeat(SIZE.0 * SIZE.1)
+    }
+
+    fn filtered(filter: &mut impl Filter, color: [u8; 4]) -> Vec<u8> {
+        let mut rgba = frame(color);
+        filter.apply(&mut rgba, SIZE);
+        rgba
+    }
+
+    fn configured(mode: FrameBlendMode, strength: f32) -> FrameBlend {
+        let mut blend = FrameBlend::default();
+        blend.configure(mode, strength, 0);
+        blend
+    }
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+    #[test]
+    fn test_blend_halves_a_flickering_sprite() {
+        let mut blend = configured(FrameBlendMode::Blend, DEFAULT_STRENGTH);
+        assert_eq!(filtered(&mut blend, RED), frame(RED));
+        assert_eq!(filtered(&mut blend, BLACK), frame([128, 0, 0, 255]));
+        // Mixed with the black frame as drawn, not as shown
+        assert_eq!(filtered(&mut blend, RED), frame([128, 0, 0, 255]));
+
+        let mut weighted = configured(FrameBlendMode::Blend, 0.25);
+        filtered(&mut weighted, RED);
+        assert_eq!(filtered(&mut weighted, BLACK), frame([64, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_phosphor_fades_over_frames() {
+        let mut phosphor = configured(FrameBlendMode::Phosphor, DEFAULT_STRENGTH);
+        filtered(&mut phosphor, RED);
+        assert_eq!(filtered(&mut phosphor, BLACK), frame([128, 0, 0, 255]));
+        assert_eq!(filtered(&mut phosphor, BLACK), frame([64, 0, 0, 255]));
+        // Anything brighter than the afterglow shows as drawn
+        assert_eq!(filtered(&mut phosphor, RED), frame(RED));
+    }
+
+    #[test]
+    fn test_off_passes_frames_through() {
+        let mut off = FrameBlend::default();
+        filtered(&mut off, RED);
+        assert_eq!(filtered(&mut off, BLACK), frame(BLACK));
+    }
+
+    #[test]
+    fn test_new_picture_forgets_the_last_one() {
+        let mut blend = configured(FrameBlendMode::Blend, DEFAULT_STRENGTH);
+        filtered(&mut blend, RED);
+
+        // A ROM load or power cycle moves the epoch on
+        blend.configure(FrameBlendMode::Blend, DEFAULT_STRENGTH, 1);
+        assert_eq!(filtered(&mut blend, BLACK), frame(BLACK));
+
+        // As does switching modes, or a frame of another size
+        filtered(&mut blend, RED);
+        blend.configure(FrameBlendMode::Phosphor, DEFAULT_STRENGTH, 1);
+        assert_eq!(filtered(&mut blend, BLACK), frame(BLACK));
+        let mut small = [0u8; 4];
+        blend.apply(&mut small, (1, 1));
+        assert_eq!(small, [0; 4]);
+
+        filtered(&mut blend, RED);
+        blend.reset();
+        assert_eq!(filtered(&mut blend, BLACK), frame(BLACK));
+    }
+}